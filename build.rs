@@ -0,0 +1,9 @@
+//! Compiles the gRPC protobuf definitions in proto/ (see grpc). Uses protox
+//! instead of protoc, to avoid depending on a system protobuf compiler.
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    println!("cargo:rerun-if-changed=proto");
+    let descriptors = protox::compile(["toydb.proto"], ["proto"])?;
+    tonic_build::configure().compile_fds(descriptors)?;
+    Ok(())
+}
@@ -0,0 +1,9 @@
+//! Fuzzes the SQL lexer and parser with arbitrary input. Run with:
+//!
+//! cargo +nightly fuzz run parser
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| toydb::sql::parser::fuzz(data));
@@ -0,0 +1,497 @@
+use std::collections::VecDeque;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use rand::Rng;
+use rustls::pki_types::ServerName;
+use tokio::io::{AsyncRead, AsyncReadExt as _, AsyncWrite, AsyncWriteExt as _};
+use tokio::io::{ReadHalf, WriteHalf};
+use tokio::net::{TcpStream, ToSocketAddrs};
+use tokio::sync::Mutex;
+
+use crate::client::{RetryPolicy, handshake_response};
+use crate::encoding::{Value as _, bincode};
+use crate::error::{Error, Result};
+use crate::raft;
+use crate::server::{Capabilities, PROTOCOL_VERSION, Request, Response, Status};
+use crate::sql::engine::{Change, StatementResult};
+use crate::sql::execution::Returning;
+use crate::sql::types::{Label, Row, Table};
+use crate::storage::mvcc;
+use crate::storage::{BitCask, Engine as _};
+use crate::tls;
+use crate::{errdata, errinput};
+
+/// An async toyDB client, for use with Tokio. It has the same API as the
+/// blocking Client, but does non-blocking network I/O, so it can be used from
+/// async tasks without a thread per connection. SELECT rows can also be
+/// streamed via query() instead of buffering them in memory.
+///
+/// Futures returned by the client aren't cancellation-safe: if one is dropped
+/// before it completes (e.g. via tokio::time::timeout), the connection is left
+/// in an unknown state and the client must be discarded. Use cancel_handle()
+/// to cancel a statement instead.
+pub struct AsyncClient {
+    /// Inbound response stream.
+    reader: ReadHalf<Box<dyn Connection>>,
+    /// Buffered inbound bytes that haven't been decoded yet.
+    buffer: Vec<u8>,
+    /// Outbound request stream. Shared with cancel handles.
+    writer: Arc<Mutex<WriteHalf<Box<dyn Connection>>>>,
+    /// If true, the server is still streaming SELECT rows from a query()
+    /// whose Rows were dropped before reaching the end. They're discarded
+    /// before the next request.
+    streaming: bool,
+    /// The current transaction, if any.
+    txn: Option<mvcc::TransactionState>,
+    /// The retry policy for with_retry().
+    retry_policy: RetryPolicy,
+    /// The negotiated protocol version.
+    protocol_version: u32,
+    /// The negotiated protocol capabilities.
+    capabilities: Capabilities,
+}
+
+/// An async network connection, either plain TCP or TLS.
+trait Connection: AsyncRead + AsyncWrite + Send + Unpin {}
+
+impl<T: AsyncRead + AsyncWrite + Send + Unpin> Connection for T {}
+
+impl AsyncClient {
+    /// Connects to a toyDB server, creating a new client.
+    pub async fn connect(addr: impl ToSocketAddrs) -> Result<Self> {
+        let socket = TcpStream::connect(addr).await?;
+        socket.set_nodelay(true)?;
+        Self::new(Box::new(socket)).await
+    }
+
+    /// Connects to a toyDB server using TLS, verifying the server certificate
+    /// against the CA certificates in the given PEM file. The certificate must
+    /// be valid for the host.
+    pub async fn connect_tls(host: &str, port: u16, ca: impl AsRef<Path>) -> Result<Self> {
+        let socket = TcpStream::connect((host, port)).await?;
+        socket.set_nodelay(true)?;
+        let connector = tokio_rustls::TlsConnector::from(tls::client_config(ca.as_ref())?);
+        let name = ServerName::try_from(host.to_string())
+            .or_else(|_| errinput!("invalid TLS server name {host}"))?;
+        Self::new(Box::new(connector.connect(name, socket).await?)).await
+    }
+
+    /// Creates a new client using the given connection, and negotiates the
+    /// protocol version.
+    async fn new(connection: Box<dyn Connection>) -> Result<Self> {
+        let (reader, writer) = tokio::io::split(connection);
+        let mut client = Self {
+            reader,
+            buffer: Vec::new(),
+            writer: Arc::new(Mutex::new(writer)),
+            streaming: false,
+            txn: None,
+            retry_policy: RetryPolicy::default(),
+            protocol_version: PROTOCOL_VERSION,
+            capabilities: Capabilities::NONE,
+        };
+        let request =
+            Request::Handshake { version: PROTOCOL_VERSION, capabilities: Capabilities::ALL };
+        (client.protocol_version, client.capabilities) =
+            handshake_response(client.request(request).await)?;
+        Ok(client)
+    }
+
+    /// Returns the negotiated protocol version.
+    pub fn protocol_version(&self) -> u32 {
+        self.protocol_version
+    }
+
+    /// Returns the negotiated protocol capabilities, i.e. the optional
+    /// features supported by both the client and server.
+    pub fn capabilities(&self) -> Capabilities {
+        self.capabilities
+    }
+
+    /// Sends a request to the server, returning the response.
+    async fn request(&mut self, request: Request) -> Result<Response> {
+        self.discard_rows().await?;
+        send(&self.writer, request).await?;
+        self.receive().await
+    }
+
+    /// Receives a response from the server.
+    async fn receive(&mut self) -> Result<Response> {
+        loop {
+            if let Some((response, n)) = bincode::try_deserialize::<Result<Response>>(&self.buffer)?
+            {
+                self.buffer.drain(..n);
+                return response;
+            }
+            self.buffer.reserve(8192);
+            if self.reader.read_buf(&mut self.buffer).await? == 0 {
+                return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+            }
+        }
+    }
+
+    /// Receives the next frame of streamed SELECT rows. An empty frame ends
+    /// the rows.
+    async fn receive_frame(&mut self) -> Result<Vec<Row>> {
+        let result = self.receive().await;
+        // The stream ends with an empty frame, or an error.
+        self.streaming = matches!(&result, Ok(Response::Rows(rows)) if !rows.is_empty());
+        match result? {
+            Response::Rows(rows) => Ok(rows),
+            response => errdata!("unexpected response {response:?}"),
+        }
+    }
+
+    /// Discards any remaining rows streamed by a previous query().
+    async fn discard_rows(&mut self) -> Result<()> {
+        while self.streaming {
+            self.receive_frame().await?;
+        }
+        Ok(())
+    }
+
+    /// Returns a handle that can cancel the client's in-flight statement from a
+    /// different task, e.g. on a timeout.
+    pub fn cancel_handle(&self) -> AsyncCancelHandle {
+        AsyncCancelHandle { writer: self.writer.clone() }
+    }
+
+    /// Executes a SQL statement. SELECT rows are buffered in the result, use
+    /// query() to stream them instead.
+    pub async fn execute(&mut self, statement: &str) -> Result<StatementResult> {
+        let mut result = self.execute_start(statement).await?;
+        if let StatementResult::Select { rows, .. } = &mut result {
+            loop {
+                let frame = self.receive_frame().await?;
+                if frame.is_empty() {
+                    break;
+                }
+                rows.extend(frame);
+            }
+        }
+        Ok(result)
+    }
+
+    /// Executes a SQL query, streaming the result rows as they're received from
+    /// the server in frames. INSERT, UPDATE, and DELETE return their RETURNING
+    /// rows, if any, which are sent with the result. Other statements are
+    /// executed and return no rows. Dropping the rows before the end discards
+    /// the remaining rows.
+    pub async fn query(&mut self, statement: &str) -> Result<Rows<'_>> {
+        let (columns, frame, done) = match self.execute_start(statement).await? {
+            StatementResult::Select { columns, .. } => (columns, Vec::new(), false),
+            StatementResult::Delete { returning: Some(Returning { columns, rows }), .. }
+            | StatementResult::Insert { returning: Some(Returning { columns, rows }), .. }
+            | StatementResult::Update { returning: Some(Returning { columns, rows }), .. } => {
+                (columns, rows, true)
+            }
+            _ => (Vec::new(), Vec::new(), true),
+        };
+        Ok(Rows { client: self, columns, frame: frame.into_iter(), done })
+    }
+
+    /// Executes a SQL statement, returning its result. SELECT results have no
+    /// rows; they're streamed separately in frames.
+    async fn execute_start(&mut self, statement: &str) -> Result<StatementResult> {
+        let result = match self.request(Request::Execute(statement.to_string())).await {
+            Ok(Response::Execute(result)) => result,
+            Ok(response) => return errdata!("unexpected response {response:?}"),
+            // The server rolls back the transaction on deadlocks.
+            Err(Error::Deadlock) => {
+                self.txn = None;
+                return Err(Error::Deadlock);
+            }
+            Err(error) => return Err(error),
+        };
+        self.streaming = matches!(result, StatementResult::Select { .. });
+        // Update the transaction state.
+        match &result {
+            StatementResult::Begin(state) => self.txn = Some(state.clone()),
+            StatementResult::Commit { .. } => self.txn = None,
+            StatementResult::Rollback { .. } => self.txn = None,
+            _ => {}
+        }
+        Ok(result)
+    }
+
+    /// Authenticates as the given user. If the server has authentication
+    /// enabled, this must be done before any other requests.
+    pub async fn authenticate(&mut self, user: &str, password: &str) -> Result<()> {
+        let request =
+            Request::Authenticate { user: user.to_string(), password: password.to_string() };
+        match self.request(request).await? {
+            Response::Authenticate => Ok(()),
+            response => errdata!("unexpected response: {response:?}"),
+        }
+    }
+
+    /// Fetches a table schema.
+    pub async fn get_table(&mut self, table: &str) -> Result<Table> {
+        match self.request(Request::GetTable(table.to_string())).await? {
+            Response::GetTable(table) => Ok(table),
+            response => errdata!("unexpected response: {response:?}"),
+        }
+    }
+
+    /// Lists database tables.
+    pub async fn list_tables(&mut self) -> Result<Vec<String>> {
+        match self.request(Request::ListTables).await? {
+            Response::ListTables(tables) => Ok(tables),
+            response => errdata!("unexpected response: {response:?}"),
+        }
+    }
+
+    /// Returns server status.
+    pub async fn status(&mut self) -> Result<Status> {
+        match self.request(Request::Status).await? {
+            Response::Status(status) => Ok(status),
+            response => errdata!("unexpected response: {response:?}"),
+        }
+    }
+
+    /// Garbage collects old MVCC versions, retaining the server's configured
+    /// number of recent versions for time-travel queries.
+    pub async fn gc(&mut self) -> Result<mvcc::GarbageCollection> {
+        match self.request(Request::GC).await? {
+            Response::GC(gc) => Ok(gc),
+            response => errdata!("unexpected response: {response:?}"),
+        }
+    }
+
+    /// Fetches the row changes committed to a table with a changefeed by up to
+    /// limit commits after the given commit sequence number, in commit order.
+    pub async fn changes(&mut self, table: &str, after: u64, limit: usize) -> Result<Vec<Change>> {
+        match self.request(Request::Changes { table: table.to_string(), after, limit }).await? {
+            Response::Changes(changes) => Ok(changes),
+            response => errdata!("unexpected response: {response:?}"),
+        }
+    }
+
+    /// Takes a full backup of the SQL database and writes it to a new BitCask
+    /// file at the given path, returning the Raft log index it was taken at.
+    /// See Client::backup(). The file is written on a blocking thread.
+    pub async fn backup(&mut self, path: impl AsRef<Path>) -> Result<raft::Index> {
+        let path = path.as_ref().to_path_buf();
+        if path.exists() {
+            return errinput!("backup file {} already exists", path.display());
+        }
+        let backup = match self.request(Request::Backup).await? {
+            Response::Backup(backup) => backup,
+            response => return errdata!("unexpected response: {response:?}"),
+        };
+        tokio::task::spawn_blocking(move || {
+            let mut engine = BitCask::new(path)?;
+            for (key, value) in backup.data {
+                engine.set(&key, value)?;
+            }
+            engine.flush()
+        })
+        .await
+        .or_else(|err| errdata!("backup failed: {err}"))??;
+        Ok(backup.applied_index)
+    }
+
+    /// Takes an incremental backup of the SQL database since a previous
+    /// backup at the given Raft log index, and writes it to a new file at the
+    /// given path. See Client::backup_incremental(). The file is written on a
+    /// blocking thread.
+    pub async fn backup_incremental(
+        &mut self,
+        path: impl AsRef<Path>,
+        since: raft::Index,
+    ) -> Result<raft::Index> {
+        let path = path.as_ref().to_path_buf();
+        if path.exists() {
+            return errinput!("backup file {} already exists", path.display());
+        }
+        let backup = match self.request(Request::BackupIncremental { since }).await? {
+            Response::BackupIncremental(backup) => backup,
+            response => return errdata!("unexpected response: {response:?}"),
+        };
+        let index = backup.index();
+        tokio::task::spawn_blocking(move || backup.write(&mut BitCask::new(path)?))
+            .await
+            .or_else(|err| errdata!("backup failed: {err}"))??;
+        Ok(index)
+    }
+
+    /// Subscribes to a table's changefeed, returning a stream of the row
+    /// changes committed after the given commit sequence number, in commit
+    /// order. See Client::changefeed().
+    pub fn changefeed(
+        &mut self,
+        table: &str,
+        after: u64,
+        poll_interval: Duration,
+    ) -> AsyncChangefeed<'_> {
+        AsyncChangefeed {
+            client: self,
+            table: table.to_string(),
+            after,
+            poll_interval,
+            buffer: VecDeque::new(),
+        }
+    }
+
+    /// Returns the transaction state.
+    pub fn txn(&self) -> Option<&mvcc::TransactionState> {
+        self.txn.as_ref()
+    }
+
+    /// Sets the retry policy used by with_retry() and with_transaction().
+    pub fn set_retry_policy(&mut self, retry_policy: RetryPolicy) {
+        self.retry_policy = retry_policy;
+    }
+
+    /// Runs the given closure, automatically retrying retryable errors with
+    /// randomized exponential backoff until the retry policy's budget is
+    /// exhausted. See Client::with_retry().
+    pub async fn with_retry<T>(
+        &mut self,
+        f: impl AsyncFn(&mut AsyncClient) -> Result<T>,
+    ) -> Result<T> {
+        let policy = self.retry_policy.clone();
+        let start = Instant::now();
+        let mut retries: u32 = 0;
+        loop {
+            match f(self).await {
+                Ok(result) => return Ok(result),
+                Err(error) if error.is_retryable() && retries < policy.max_retries => {
+                    self.rollback_txn().await?;
+                    let max_wait = policy
+                        .min_wait
+                        .saturating_mul(2_u32.saturating_pow(retries))
+                        .clamp(policy.min_wait, policy.max_wait.max(policy.min_wait));
+                    let wait = rand::thread_rng().gen_range(policy.min_wait..=max_wait);
+                    if policy.max_duration.is_some_and(|max| start.elapsed() + wait > max) {
+                        return Err(error);
+                    }
+                    tokio::time::sleep(wait).await;
+                    retries += 1;
+                }
+                Err(error) => {
+                    self.rollback_txn().await.ok(); // ignore rollback error
+                    return Err(error);
+                }
+            }
+        }
+    }
+
+    /// Runs the given closure in a read-write transaction and commits it,
+    /// retrying the entire transaction on retryable errors like with_retry().
+    /// See Client::with_transaction().
+    pub async fn with_transaction<T>(
+        &mut self,
+        f: impl AsyncFn(&mut AsyncClient) -> Result<T>,
+    ) -> Result<T> {
+        // Move f into the closure, otherwise the future isn't Send due to a
+        // compiler limitation with borrows in async closures.
+        self.with_retry(async move |client| {
+            client.execute("BEGIN").await?;
+            let result = f(client).await?;
+            client.execute("COMMIT").await?;
+            Ok(result)
+        })
+        .await
+    }
+
+    /// Rolls back the current transaction, if any. The server may already
+    /// have rolled it back, e.g. if COMMIT failed.
+    async fn rollback_txn(&mut self) -> Result<()> {
+        if self.txn.is_none() {
+            return Ok(());
+        }
+        match self.execute("ROLLBACK").await {
+            Ok(_) => Ok(()),
+            Err(Error::InvalidInput(_)) => {
+                self.txn = None;
+                Ok(())
+            }
+            Err(error) => Err(error),
+        }
+    }
+}
+
+/// Streamed SELECT result rows, obtained via AsyncClient::query().
+pub struct Rows<'a> {
+    client: &'a mut AsyncClient,
+    columns: Vec<Label>,
+    /// The current frame of rows.
+    frame: std::vec::IntoIter<Row>,
+    /// If true, all frames have been received.
+    done: bool,
+}
+
+impl Rows<'_> {
+    /// Returns the result column labels.
+    pub fn columns(&self) -> &[Label] {
+        &self.columns
+    }
+
+    /// Returns the next row, or None at the end of the rows.
+    pub async fn next(&mut self) -> Result<Option<Row>> {
+        loop {
+            if let Some(row) = self.frame.next() {
+                return Ok(Some(row));
+            }
+            if self.done {
+                return Ok(None);
+            }
+            let frame = self.client.receive_frame().await.inspect_err(|_| self.done = true)?;
+            self.done = frame.is_empty();
+            self.frame = frame.into_iter();
+        }
+    }
+}
+
+/// A changefeed subscription, obtained via AsyncClient::changefeed().
+pub struct AsyncChangefeed<'a> {
+    client: &'a mut AsyncClient,
+    table: String,
+    /// The commit sequence number to fetch changes after.
+    after: u64,
+    poll_interval: Duration,
+    /// Fetched changes that haven't been yielded yet.
+    buffer: VecDeque<Change>,
+}
+
+impl AsyncChangefeed<'_> {
+    /// The maximum number of commits to fetch per request.
+    const BATCH_SIZE: usize = 100;
+
+    /// Returns the next change, waiting for one to be committed if necessary.
+    pub async fn next(&mut self) -> Result<Change> {
+        while self.buffer.is_empty() {
+            let changes = self.client.changes(&self.table, self.after, Self::BATCH_SIZE).await?;
+            match changes.last() {
+                Some(last) => self.after = last.seq,
+                None => tokio::time::sleep(self.poll_interval).await,
+            }
+            self.buffer.extend(changes);
+        }
+        Ok(self.buffer.pop_front().expect("no buffered change"))
+    }
+}
+
+/// Cancels the in-flight statement of an async client, if any. Obtained via
+/// AsyncClient::cancel_handle(). See CancelHandle.
+#[derive(Clone)]
+pub struct AsyncCancelHandle {
+    writer: Arc<Mutex<WriteHalf<Box<dyn Connection>>>>,
+}
+
+impl AsyncCancelHandle {
+    /// Cancels the in-flight statement, if any.
+    pub async fn cancel(&self) -> Result<()> {
+        send(&self.writer, Request::Cancel).await
+    }
+}
+
+/// Sends a request to the server via the given shared request stream.
+async fn send(writer: &Mutex<WriteHalf<Box<dyn Connection>>>, request: Request) -> Result<()> {
+    let mut writer = writer.lock().await;
+    writer.write_all(&request.encode()).await?;
+    Ok(writer.flush().await?)
+}
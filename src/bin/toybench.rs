@@ -0,0 +1,945 @@
+//! Runs standardized toyDB benchmarks, reporting throughput and latency
+//! percentiles so that performance can be compared between releases. By
+//! default, it assumes a running 5-node cluster as launched via
+//! cluster/run.sh, but this can be modified via -H. For example, a YCSB
+//! workload A run with a Zipfian key distribution can be run as:
+//!
+//! cargo run --release --bin toybench -- ycsb --workload a --distribution zipfian
+//!
+//! The available benchmarks are:
+//!
+//! * ycsb: YCSB-style key/value workloads A-F on a single table.
+//! * tpcc: a simplified TPC-C order processing workload.
+//! * storage: concurrent reads and writes against the in-memory storage
+//!   engine, without a cluster, comparing a global mutex with the engine's
+//!   own read/write lock.
+//!
+//! Unlike the workload tool, the benchmarks are fixed and well-known, and the
+//! generated work is deterministic for a given seed. See --help for arguments.
+
+#![warn(clippy::all)]
+
+use std::io::Write as _;
+use std::time::{Duration, Instant};
+
+use clap::Parser;
+use hdrhistogram::Histogram;
+use itertools::Itertools as _;
+use rand::distributions::Distribution as _;
+use rand::rngs::StdRng;
+use rand::{Rng as _, SeedableRng as _};
+
+use toydb::errinput;
+use toydb::error::Result;
+use toydb::sql::types::{Row, Rows};
+use toydb::storage::{self, Engine as _};
+use toydb::{Client, StatementResult};
+
+fn main() {
+    let Command { runner, subcommand } = Command::parse();
+    let result = match subcommand {
+        Subcommand::Ycsb(ycsb) => runner.run(ycsb),
+        Subcommand::Tpcc(tpcc) => runner.run(tpcc),
+        Subcommand::Storage(storage) => storage.run(&runner),
+    };
+    if let Err(error) = result {
+        eprintln!("Error: {error}");
+        std::process::exit(1);
+    }
+}
+
+/// Handles command-line parsing.
+#[derive(clap::Parser)]
+#[command(about = "Runs toyDB benchmarks.", version, propagate_version = true)]
+struct Command {
+    #[command(flatten)]
+    runner: Runner,
+
+    #[command(subcommand)]
+    subcommand: Subcommand,
+}
+
+#[derive(clap::Subcommand)]
+enum Subcommand {
+    Ycsb(Ycsb),
+    Tpcc(Tpcc),
+    Storage(Storage),
+}
+
+/// Runs a benchmark.
+#[derive(clap::Args)]
+struct Runner {
+    /// Hosts to connect to (optionally with port number).
+    #[arg(
+        short = 'H',
+        long,
+        value_delimiter = ',',
+        default_value = "localhost:9601,localhost:9602,localhost:9603,localhost:9604,localhost:9605"
+    )]
+    hosts: Vec<String>,
+
+    /// Number of concurrent workers to spawn.
+    #[arg(short, long, default_value = "16")]
+    concurrency: usize,
+
+    /// Number of operations (transactions) to execute.
+    #[arg(short = 'n', long, default_value = "10000")]
+    count: usize,
+
+    /// Seed to use for random number generation.
+    #[arg(short, long, default_value = "16791084677885396490")]
+    seed: u64,
+
+    /// Skip loading the initial dataset, reusing the one from a previous run.
+    #[arg(long)]
+    skip_load: bool,
+}
+
+impl Runner {
+    /// Runs the specified benchmark.
+    fn run<B: Benchmark>(self, benchmark: B) -> Result<()> {
+        // Use separate RNGs for loading and generating work, such that the
+        // generated work is the same when loading is skipped.
+        let mut load_rng = StdRng::seed_from_u64(self.seed);
+        let rng = StdRng::seed_from_u64(self.seed.wrapping_add(1));
+        let mut client = Client::connect(&self.hosts[0])?;
+
+        // Set up a histogram recording latencies as nanoseconds. The buckets
+        // range from 0.001ms to 10s.
+        let mut hist = Histogram::<u32>::new_with_bounds(1_000, 10_000_000_000, 3)?.into_sync();
+
+        // Load the dataset.
+        print!("Loading initial dataset... ");
+        std::io::stdout().flush()?;
+        let start = Instant::now();
+        match self.skip_load {
+            false => benchmark.load(&mut client, &mut load_rng)?,
+            true => print!("skipped "),
+        }
+        println!("done ({:.3}s)", start.elapsed().as_secs_f64());
+
+        // Spawn workers, round robin across hosts.
+        let start = std::thread::scope(|s| -> Result<Instant> {
+            print!("Spawning {} workers... ", self.concurrency);
+            std::io::stdout().flush()?;
+            let start = Instant::now();
+
+            let (work_tx, work_rx) = crossbeam::channel::bounded(self.concurrency);
+            let (done_tx, done_rx) = crossbeam::channel::bounded::<()>(0);
+
+            for addr in self.hosts.iter().cycle().take(self.concurrency) {
+                let mut client = Client::connect(addr)?;
+                let mut recorder = hist.recorder();
+                let work_rx = work_rx.clone();
+                let done_tx = done_tx.clone();
+                s.spawn(move || -> Result<()> {
+                    while let Ok(item) = work_rx.recv() {
+                        let start = Instant::now();
+                        B::execute(&mut client, &item)?;
+                        recorder.record(start.elapsed().as_nanos() as u64)?;
+                    }
+                    drop(done_tx); // disconnects done_rx once all workers exit
+                    Ok(())
+                });
+            }
+            drop(done_tx); // drop local copy
+
+            println!("done ({:.3}s)", start.elapsed().as_secs_f64());
+
+            // Spawn work generator.
+            {
+                println!("Running benchmark {benchmark}...");
+                let generator = benchmark.generate(rng).take(self.count);
+                s.spawn(move || -> Result<()> {
+                    for item in generator {
+                        work_tx.send(item)?;
+                    }
+                    Ok(())
+                });
+            }
+
+            // Periodically print stats until all workers are done.
+            let start = Instant::now();
+            let ticker = crossbeam::channel::tick(Duration::from_secs(1));
+
+            println!();
+            println!("Time   Progress      Ops      Rate       p50       p90       p99      pMax");
+
+            while let Err(crossbeam::channel::TryRecvError::Empty) = done_rx.try_recv() {
+                crossbeam::select! {
+                    recv(ticker) -> _ => {},
+                    recv(done_rx) -> _ => {},
+                }
+
+                let duration = start.elapsed().as_secs_f64();
+                hist.refresh_timeout(Duration::from_secs(1));
+
+                println!(
+                    "{:<8} {:>5.1}%  {:>7}  {:>6.0}/s  {:>6.1}ms  {:>6.1}ms  {:>6.1}ms  {:>6.1}ms",
+                    format!("{:.1}s", duration),
+                    hist.len() as f64 / self.count as f64 * 100.0,
+                    hist.len(),
+                    hist.len() as f64 / duration,
+                    millis(hist.value_at_quantile(0.5)),
+                    millis(hist.value_at_quantile(0.9)),
+                    millis(hist.value_at_quantile(0.99)),
+                    millis(hist.max()),
+                );
+            }
+            Ok(start)
+        })?;
+
+        // Print a final summary.
+        let duration = start.elapsed().as_secs_f64();
+        hist.refresh();
+        println!();
+        println!("Operations:  {}", hist.len());
+        println!("Duration:    {duration:.3}s");
+        println!("Throughput:  {:.1} ops/s", hist.len() as f64 / duration);
+        println!(
+            "Latency:     mean={:.1}ms p50={:.1}ms p90={:.1}ms p99={:.1}ms p99.9={:.1}ms max={:.1}ms",
+            hist.mean() / 1_000_000.0,
+            millis(hist.value_at_quantile(0.5)),
+            millis(hist.value_at_quantile(0.9)),
+            millis(hist.value_at_quantile(0.99)),
+            millis(hist.value_at_quantile(0.999)),
+            millis(hist.max()),
+        );
+
+        // Verify the final dataset.
+        println!();
+        print!("Verifying dataset... ");
+        std::io::stdout().flush()?;
+        let start = Instant::now();
+        benchmark.verify(&mut client)?;
+        println!("done ({:.3}s)", start.elapsed().as_secs_f64());
+
+        Ok(())
+    }
+}
+
+/// Converts a nanosecond histogram value to milliseconds.
+fn millis(nanos: u64) -> f64 {
+    Duration::from_nanos(nanos).as_secs_f64() * 1000.0
+}
+
+/// A benchmark.
+trait Benchmark: std::fmt::Display {
+    /// A work item, i.e. a single operation.
+    type Item: Send;
+
+    /// Loads the initial dataset, replacing any existing tables.
+    fn load(&self, client: &mut Client, rng: &mut StdRng) -> Result<()>;
+
+    /// Generates work items as an iterator.
+    fn generate(&self, rng: StdRng) -> impl Iterator<Item = Self::Item> + Send + 'static;
+
+    /// Executes a single work item, retrying transient errors.
+    fn execute(client: &mut Client, item: &Self::Item) -> Result<()>;
+
+    /// Verifies the dataset invariants after the benchmark has completed.
+    fn verify(&self, client: &mut Client) -> Result<()>;
+}
+
+/// A key distribution, used to pick keys for operations.
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+enum Distribution {
+    /// All keys are equally likely.
+    Uniform,
+    /// A few keys are much more popular than others, scattered across the
+    /// keyspace.
+    Zipfian,
+    /// The most recently inserted keys are the most popular.
+    Latest,
+}
+
+/// Picks keys in 1..=n according to a key distribution, where n is the
+/// current number of keys (which may grow as keys are inserted).
+struct KeyChooser {
+    distribution: Distribution,
+    zipfian: Zipfian,
+}
+
+impl KeyChooser {
+    /// Creates a new key chooser for an initial number of keys.
+    fn new(distribution: Distribution, n: u64) -> Self {
+        Self { distribution, zipfian: Zipfian::new(n) }
+    }
+
+    /// Picks a key in 1..=n.
+    fn next(&self, rng: &mut StdRng, n: u64) -> u64 {
+        match self.distribution {
+            Distribution::Uniform => rng.gen_range(1..=n),
+            // Scramble the Zipfian rank via a hash, such that popular keys
+            // aren't clustered at the start of the keyspace. This is what YCSB
+            // does too.
+            Distribution::Zipfian => fnv1a(self.zipfian.sample(rng)) % n + 1,
+            Distribution::Latest => n.saturating_sub(self.zipfian.sample(rng)).max(1),
+        }
+    }
+}
+
+/// Samples a Zipfian distribution of ranks in 0..n with the YCSB default
+/// constant of 0.99, using the algorithm from "Quickly Generating
+/// Billion-Record Synthetic Databases" by Gray et al. The distribution is
+/// computed for the initial key count, and not extended when keys are
+/// inserted.
+struct Zipfian {
+    n: u64,
+    theta: f64,
+    alpha: f64,
+    zetan: f64,
+    eta: f64,
+}
+
+impl Zipfian {
+    const THETA: f64 = 0.99;
+
+    fn new(n: u64) -> Self {
+        let theta = Self::THETA;
+        let zeta = |n: u64| (1..=n).map(|i| 1.0 / (i as f64).powf(theta)).sum::<f64>();
+        let zetan = zeta(n);
+        let eta = (1.0 - (2.0 / n as f64).powf(1.0 - theta)) / (1.0 - zeta(2) / zetan);
+        Self { n, theta, alpha: 1.0 / (1.0 - theta), zetan, eta }
+    }
+
+    fn sample(&self, rng: &mut StdRng) -> u64 {
+        let u: f64 = rng.r#gen();
+        let uz = u * self.zetan;
+        if uz < 1.0 {
+            return 0;
+        }
+        if uz < 1.0 + 0.5_f64.powf(self.theta) {
+            return 1;
+        }
+        let rank = self.n as f64 * (self.eta * u - self.eta + 1.0).powf(self.alpha);
+        (rank as u64).min(self.n - 1)
+    }
+}
+
+/// Hashes a u64 using 64-bit FNV-1a.
+fn fnv1a(value: u64) -> u64 {
+    value
+        .to_le_bytes()
+        .iter()
+        .fold(0xcbf29ce484222325, |hash, byte| (hash ^ *byte as u64).wrapping_mul(0x100000001b3))
+}
+
+/// Generates a random alphanumeric string of the given length.
+fn random_string(rng: &mut StdRng, length: usize) -> String {
+    rand::distributions::Alphanumeric.sample_iter(rng).take(length).map(char::from).collect()
+}
+
+/// A YCSB-style key/value workload, using a usertable with an integer primary
+/// key and a string value. The core YCSB workloads are:
+///
+/// * A: update heavy, 50% reads and 50% updates.
+/// * B: read mostly, 95% reads and 5% updates.
+/// * C: read only, 100% reads.
+/// * D: read latest, 95% reads and 5% inserts.
+/// * E: short ranges, 95% scans and 5% inserts.
+/// * F: read-modify-write, 50% reads and 50% read-modify-writes.
+///
+/// YCSB uses the latest distribution for workload D, and Zipfian for the
+/// others, but any distribution can be used with any workload here.
+#[derive(clap::Args, Clone)]
+#[command(about = "A YCSB-style key/value workload")]
+struct Ycsb {
+    /// The YCSB core workload to run.
+    #[arg(short, long, default_value = "a")]
+    workload: YcsbWorkload,
+
+    /// The key distribution.
+    #[arg(short, long, default_value = "zipfian")]
+    distribution: Distribution,
+
+    /// Initial number of records.
+    #[arg(short, long, default_value = "10000")]
+    records: u64,
+
+    /// Record value size (excluding primary key).
+    #[arg(long, default_value = "100")]
+    size: usize,
+
+    /// Maximum number of records to return from a scan.
+    #[arg(long, default_value = "100")]
+    max_scan: usize,
+}
+
+/// A YCSB core workload.
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+enum YcsbWorkload {
+    A,
+    B,
+    C,
+    D,
+    E,
+    F,
+}
+
+impl YcsbWorkload {
+    /// Returns the proportions of reads, updates, inserts, scans, and
+    /// read-modify-writes, in percent. The generator relies on this order.
+    fn mix(&self) -> [u32; 5] {
+        match self {
+            Self::A => [50, 50, 0, 0, 0],
+            Self::B => [95, 5, 0, 0, 0],
+            Self::C => [100, 0, 0, 0, 0],
+            Self::D => [95, 0, 5, 0, 0],
+            Self::E => [0, 0, 5, 95, 0],
+            Self::F => [50, 0, 0, 0, 50],
+        }
+    }
+}
+
+/// A YCSB operation.
+enum YcsbOperation {
+    /// Reads a record.
+    Read(u64),
+    /// Updates a record.
+    Update(u64, String),
+    /// Inserts a new record.
+    Insert(u64, String),
+    /// Scans up to the given number of records, starting at the given key.
+    Scan(u64, usize),
+    /// Reads and then updates a record in a transaction.
+    ReadModifyWrite(u64, String),
+}
+
+impl std::fmt::Display for Ycsb {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "ycsb (workload={:?} distribution={:?} records={} size={})",
+            self.workload, self.distribution, self.records, self.size
+        )
+    }
+}
+
+impl Benchmark for Ycsb {
+    type Item = YcsbOperation;
+
+    fn load(&self, client: &mut Client, rng: &mut StdRng) -> Result<()> {
+        client.execute("BEGIN")?;
+        client.execute("DROP TABLE IF EXISTS usertable")?;
+        client.execute("CREATE TABLE usertable (id INT PRIMARY KEY, value STRING NOT NULL)")?;
+        let rows = (1..=self.records).map(|id| (id, random_string(rng, self.size))).collect_vec();
+        for chunk in rows.chunks(100) {
+            client.execute(&format!(
+                "INSERT INTO usertable (id, value) VALUES {}",
+                chunk.iter().map(|(id, value)| format!("({id}, '{value}')")).join(", ")
+            ))?;
+        }
+        client.execute("COMMIT")?;
+        Ok(())
+    }
+
+    fn generate(&self, mut rng: StdRng) -> impl Iterator<Item = Self::Item> + 'static {
+        let chooser = KeyChooser::new(self.distribution, self.records);
+        let mix =
+            rand::distributions::WeightedIndex::new(self.workload.mix()).expect("invalid mix");
+        let (size, max_scan) = (self.size, self.max_scan);
+        let mut last_key = self.records;
+        std::iter::from_fn(move || {
+            let kind = mix.sample(&mut rng);
+            Some(match kind {
+                0 => YcsbOperation::Read(chooser.next(&mut rng, last_key)),
+                1 => YcsbOperation::Update(
+                    chooser.next(&mut rng, last_key),
+                    random_string(&mut rng, size),
+                ),
+                2 => {
+                    last_key += 1;
+                    YcsbOperation::Insert(last_key, random_string(&mut rng, size))
+                }
+                3 => YcsbOperation::Scan(
+                    chooser.next(&mut rng, last_key),
+                    rng.gen_range(1..=max_scan),
+                ),
+                4 => YcsbOperation::ReadModifyWrite(
+                    chooser.next(&mut rng, last_key),
+                    random_string(&mut rng, size),
+                ),
+                _ => unreachable!("invalid operation {kind}"),
+            })
+        })
+    }
+
+    fn execute(client: &mut Client, item: &Self::Item) -> Result<()> {
+        // Concurrent inserts may not have been applied yet when a later key is
+        // read or updated, so missing keys are tolerated.
+        match item {
+            YcsbOperation::Read(id) => client.with_retry(|client| {
+                let rows: Rows = client
+                    .execute(&format!("SELECT * FROM usertable WHERE id = {id}"))?
+                    .try_into()?;
+                assert!(rows.count() <= 1, "Unexpected row count");
+                Ok(())
+            }),
+            YcsbOperation::Update(id, value) => client.with_retry(|client| {
+                client
+                    .execute(&format!("UPDATE usertable SET value = '{value}' WHERE id = {id}"))?;
+                Ok(())
+            }),
+            YcsbOperation::Insert(id, value) => client.with_retry(|client| {
+                // An insert may be retried after it was applied, e.g. on a
+                // leader change, so use an upsert.
+                client.execute(&format!("DELETE FROM usertable WHERE id = {id}"))?;
+                client.execute(&format!("INSERT INTO usertable VALUES ({id}, '{value}')"))?;
+                Ok(())
+            }),
+            YcsbOperation::Scan(id, limit) => client.with_retry(|client| {
+                let rows: Rows = client
+                    .execute(&format!(
+                        "SELECT * FROM usertable WHERE id >= {id} ORDER BY id LIMIT {limit}"
+                    ))?
+                    .try_into()?;
+                assert!(rows.count() <= *limit, "Unexpected row count");
+                Ok(())
+            }),
+            YcsbOperation::ReadModifyWrite(id, value) => client.with_transaction(|client| {
+                let rows: Rows = client
+                    .execute(&format!("SELECT * FROM usertable WHERE id = {id}"))?
+                    .try_into()?;
+                assert!(rows.count() <= 1, "Unexpected row count");
+                client
+                    .execute(&format!("UPDATE usertable SET value = '{value}' WHERE id = {id}"))?;
+                Ok(())
+            }),
+        }
+    }
+
+    fn verify(&self, client: &mut Client) -> Result<()> {
+        // Inserts use sequential keys, so there must be no gaps.
+        let row: Row = client.execute("SELECT COUNT(*), MAX(id) FROM usertable")?.try_into()?;
+        let mut row = row.into_iter();
+        let count: i64 = row.next().unwrap().try_into()?;
+        let max: i64 = row.next().unwrap().try_into()?;
+        assert!(count >= self.records as i64, "Unexpected row count");
+        assert_eq!(count, max, "Unexpected gap in keys");
+        Ok(())
+    }
+}
+
+/// A simplified TPC-C order processing workload. Each warehouse has 10
+/// districts, each with a number of customers, and each warehouse stocks all
+/// items. It runs a mix of three TPC-C transactions:
+///
+/// * New-order (45%): places an order for a customer with 1-10 order lines,
+///   taking the next order ID from the district and updating item stock.
+/// * Payment (43%): makes a payment for a customer, updating the warehouse
+///   and district year-to-date totals and the customer balance.
+/// * Order-status (12%): reads a customer's balance and latest order lines,
+///   in a read-only transaction.
+///
+/// The order-status proportion also covers TPC-C's delivery and stock-level
+/// transactions, which are omitted. Unlike TPC-C, there are no think times,
+/// and the data set is much smaller.
+#[derive(clap::Args, Clone)]
+#[command(about = "A simplified TPC-C order processing workload")]
+struct Tpcc {
+    /// Number of warehouses.
+    #[arg(short, long, default_value = "1")]
+    warehouses: u64,
+
+    /// Number of customers per district.
+    #[arg(long, default_value = "30")]
+    customers: u64,
+
+    /// Number of items.
+    #[arg(short, long, default_value = "100")]
+    items: u64,
+
+    /// The key distribution for customers and items.
+    #[arg(short, long, default_value = "uniform")]
+    distribution: Distribution,
+}
+
+impl Tpcc {
+    /// The number of districts per warehouse.
+    const DISTRICTS: u64 = 10;
+
+    /// The maximum number of order lines in an order.
+    const MAX_ORDER_LINES: u64 = 10;
+
+    /// The order ID space per district. Order IDs are given by the district ID
+    /// and the district's next order ID.
+    const ORDER_IDS: u64 = 1_000_000_000;
+
+    /// The district IDs, across all warehouses.
+    fn districts(&self) -> u64 {
+        self.warehouses * Self::DISTRICTS
+    }
+}
+
+/// A TPC-C transaction. Customers, districts, and stock are identified by
+/// global IDs across all warehouses.
+enum TpccTransaction {
+    /// Places an order for a customer, with (item, stock, quantity) lines.
+    NewOrder { district: u64, customer: u64, lines: Vec<(u64, u64, u64)> },
+    /// Makes a payment for a customer.
+    Payment { warehouse: u64, district: u64, customer: u64, amount: u64 },
+    /// Reads a customer's status.
+    OrderStatus { customer: u64 },
+}
+
+impl std::fmt::Display for Tpcc {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "tpcc (warehouses={} customers={} items={} distribution={:?})",
+            self.warehouses, self.customers, self.items, self.distribution
+        )
+    }
+}
+
+impl Benchmark for Tpcc {
+    type Item = TpccTransaction;
+
+    fn load(&self, client: &mut Client, rng: &mut StdRng) -> Result<()> {
+        client.execute("BEGIN")?;
+        for table in ["order_line", "orders", "stock", "item", "customer", "district", "warehouse"]
+        {
+            client.execute(&format!("DROP TABLE IF EXISTS {table}"))?;
+        }
+        client.execute(
+            "CREATE TABLE warehouse (
+                id INTEGER PRIMARY KEY,
+                name STRING NOT NULL,
+                ytd INTEGER NOT NULL
+            )",
+        )?;
+        client.execute(
+            "CREATE TABLE district (
+                id INTEGER PRIMARY KEY,
+                warehouse_id INTEGER NOT NULL INDEX REFERENCES warehouse,
+                name STRING NOT NULL,
+                ytd INTEGER NOT NULL,
+                next_order_id INTEGER NOT NULL
+            )",
+        )?;
+        client.execute(
+            "CREATE TABLE customer (
+                id INTEGER PRIMARY KEY,
+                district_id INTEGER NOT NULL INDEX REFERENCES district,
+                name STRING NOT NULL,
+                balance INTEGER NOT NULL
+            )",
+        )?;
+        client.execute(
+            "CREATE TABLE item (
+                id INTEGER PRIMARY KEY,
+                name STRING NOT NULL,
+                price INTEGER NOT NULL
+            )",
+        )?;
+        client.execute(
+            "CREATE TABLE stock (
+                id INTEGER PRIMARY KEY,
+                warehouse_id INTEGER NOT NULL INDEX REFERENCES warehouse,
+                item_id INTEGER NOT NULL REFERENCES item,
+                quantity INTEGER NOT NULL
+            )",
+        )?;
+        client.execute(
+            "CREATE TABLE orders (
+                id INTEGER PRIMARY KEY,
+                district_id INTEGER NOT NULL REFERENCES district,
+                customer_id INTEGER NOT NULL INDEX REFERENCES customer,
+                line_count INTEGER NOT NULL
+            )",
+        )?;
+        client.execute(
+            "CREATE TABLE order_line (
+                id INTEGER PRIMARY KEY,
+                order_id INTEGER NOT NULL INDEX REFERENCES orders,
+                item_id INTEGER NOT NULL REFERENCES item,
+                quantity INTEGER NOT NULL,
+                amount INTEGER NOT NULL
+            )",
+        )?;
+
+        let insert = |client: &mut Client, table: &str, rows: Vec<String>| -> Result<()> {
+            for chunk in rows.chunks(100) {
+                client.execute(&format!("INSERT INTO {table} VALUES {}", chunk.join(", ")))?;
+            }
+            Ok(())
+        };
+        let warehouses = (1..=self.warehouses).map(|w| format!("({w}, 'warehouse {w}', 0)"));
+        insert(client, "warehouse", warehouses.collect())?;
+        let districts = (1..=self.districts())
+            .map(|d| format!("({d}, {}, 'district {d}', 0, 1)", (d - 1) / Self::DISTRICTS + 1));
+        insert(client, "district", districts.collect())?;
+        let customers = (1..=self.districts() * self.customers).map(|c| {
+            let d = (c - 1) / self.customers + 1;
+            format!("({c}, {d}, '{}', 0)", random_string(rng, 16))
+        });
+        insert(client, "customer", customers.collect())?;
+        let items = (1..=self.items)
+            .map(|i| format!("({i}, '{}', {})", random_string(rng, 24), rng.gen_range(1..=100)));
+        insert(client, "item", items.collect())?;
+        let stock = (1..=self.warehouses)
+            .flat_map(|w| (1..=self.items).map(move |i| (w, i, (w - 1) * self.items + i)));
+        let stock = stock.map(|(w, i, s)| format!("({s}, {w}, {i}, {})", rng.gen_range(10..=100)));
+        insert(client, "stock", stock.collect())?;
+        client.execute("COMMIT")?;
+        Ok(())
+    }
+
+    fn generate(&self, mut rng: StdRng) -> impl Iterator<Item = Self::Item> + 'static {
+        let customers = KeyChooser::new(self.distribution, self.customers);
+        let items = KeyChooser::new(self.distribution, self.items);
+        let mix = rand::distributions::WeightedIndex::new([45, 43, 12]).expect("invalid mix");
+        let tpcc = self.clone();
+        std::iter::from_fn(move || {
+            let warehouse = rng.gen_range(1..=tpcc.warehouses);
+            let district = (warehouse - 1) * Tpcc::DISTRICTS + rng.gen_range(1..=Tpcc::DISTRICTS);
+            let customer =
+                (district - 1) * tpcc.customers + customers.next(&mut rng, tpcc.customers);
+            Some(match mix.sample(&mut rng) {
+                0 => {
+                    let mut lines = Vec::new();
+                    for _ in 0..rng.gen_range(1..=Tpcc::MAX_ORDER_LINES) {
+                        let item = items.next(&mut rng, tpcc.items);
+                        let stock = (warehouse - 1) * tpcc.items + item;
+                        lines.push((item, stock, rng.gen_range(1..=10)));
+                    }
+                    TpccTransaction::NewOrder { district, customer, lines }
+                }
+                1 => {
+                    let amount = rng.gen_range(1..=5000);
+                    TpccTransaction::Payment { warehouse, district, customer, amount }
+                }
+                2 => TpccTransaction::OrderStatus { customer },
+                kind => unreachable!("invalid transaction {kind}"),
+            })
+        })
+    }
+
+    fn execute(client: &mut Client, item: &Self::Item) -> Result<()> {
+        match item {
+            TpccTransaction::NewOrder { district, customer, lines } => {
+                client.with_transaction(|client| {
+                    let next_order_id: i64 = client
+                        .execute(&format!("SELECT next_order_id FROM district WHERE id = {district}"))?
+                        .try_into()?;
+                    client.execute(&format!(
+                        "UPDATE district SET next_order_id = next_order_id + 1 WHERE id = {district}"
+                    ))?;
+                    let order_id = district * Tpcc::ORDER_IDS + next_order_id as u64;
+                    client.execute(&format!(
+                        "INSERT INTO orders VALUES ({order_id}, {district}, {customer}, {})",
+                        lines.len()
+                    ))?;
+                    let mut order_lines = Vec::new();
+                    for (n, (item, stock, quantity)) in lines.iter().enumerate() {
+                        let price: i64 =
+                            client.execute(&format!("SELECT price FROM item WHERE id = {item}"))?.try_into()?;
+                        // Like TPC-C, restock by 91 when the stock runs low.
+                        let available: i64 = client
+                            .execute(&format!("SELECT quantity FROM stock WHERE id = {stock}"))?
+                            .try_into()?;
+                        let mut remaining = available - *quantity as i64;
+                        if remaining < 10 {
+                            remaining += 91;
+                        }
+                        client.execute(&format!(
+                            "UPDATE stock SET quantity = {remaining} WHERE id = {stock}"
+                        ))?;
+                        let id = order_id * Tpcc::MAX_ORDER_LINES + n as u64;
+                        let amount = price * *quantity as i64;
+                        order_lines.push(format!("({id}, {order_id}, {item}, {quantity}, {amount})"));
+                    }
+                    match client.execute(&format!("INSERT INTO order_line VALUES {}", order_lines.join(", ")))? {
+                        StatementResult::Insert { count, .. } => assert_eq!(count as usize, lines.len(), "Unexpected row count"),
+                        result => panic!("Unexpected result {result:?}"),
+                    }
+                    Ok(())
+                })
+            }
+
+            TpccTransaction::Payment { warehouse, district, customer, amount } => client
+                .with_transaction(|client| {
+                    client.execute(&format!(
+                        "UPDATE warehouse SET ytd = ytd + {amount} WHERE id = {warehouse}"
+                    ))?;
+                    client.execute(&format!(
+                        "UPDATE district SET ytd = ytd + {amount} WHERE id = {district}"
+                    ))?;
+                    client.execute(&format!(
+                        "UPDATE customer SET balance = balance - {amount} WHERE id = {customer}"
+                    ))?;
+                    Ok(())
+                }),
+
+            TpccTransaction::OrderStatus { customer } => client.with_retry(|client| {
+                client.execute("BEGIN READ ONLY")?;
+                client.execute(&format!("SELECT balance FROM customer WHERE id = {customer}"))?;
+                let rows: Rows = client
+                    .execute(&format!(
+                        "SELECT id, line_count FROM orders WHERE customer_id = {customer}
+                        ORDER BY id DESC LIMIT 1"
+                    ))?
+                    .try_into()?;
+                if let Some(row) = rows.into_iter().next().transpose()? {
+                    let mut row = row.into_iter();
+                    let order_id: i64 = row.next().unwrap().try_into()?;
+                    let line_count: i64 = row.next().unwrap().try_into()?;
+                    let lines: Rows = client
+                        .execute(&format!("SELECT * FROM order_line WHERE order_id = {order_id}"))?
+                        .try_into()?;
+                    assert_eq!(lines.count() as i64, line_count, "Unexpected order line count");
+                }
+                client.execute("COMMIT")?;
+                Ok(())
+            }),
+        }
+    }
+
+    fn verify(&self, client: &mut Client) -> Result<()> {
+        // Payments update the warehouse, district, and customer atomically.
+        let warehouse_ytd: i64 = client.execute("SELECT SUM(ytd) FROM warehouse")?.try_into()?;
+        let district_ytd: i64 = client.execute("SELECT SUM(ytd) FROM district")?.try_into()?;
+        let balance: i64 = client.execute("SELECT SUM(balance) FROM customer")?.try_into()?;
+        assert_eq!(warehouse_ytd, district_ytd, "Warehouse and district totals differ");
+        assert_eq!(warehouse_ytd, -balance, "Warehouse total and customer balances differ");
+
+        // New orders take the district's next order ID and write all lines.
+        let next_order_ids: i64 =
+            client.execute("SELECT SUM(next_order_id - 1) FROM district")?.try_into()?;
+        let orders: i64 = client.execute("SELECT COUNT(*) FROM orders")?.try_into()?;
+        assert_eq!(orders, next_order_ids, "Unexpected order count");
+        if orders > 0 {
+            let line_count: i64 =
+                client.execute("SELECT SUM(line_count) FROM orders")?.try_into()?;
+            let order_lines: i64 = client.execute("SELECT COUNT(*) FROM order_line")?.try_into()?;
+            assert_eq!(order_lines, line_count, "Unexpected order line count");
+        }
+        Ok(())
+    }
+}
+
+/// A storage engine benchmark, which runs concurrent point reads, writes, and
+/// short scans directly against the in-memory storage engine, without a
+/// cluster. It runs the workload twice: once with all workers sharing the
+/// engine behind a mutex (like the MVCC layer does), and once with each worker
+/// using its own clone of the engine, where readers don't block each other.
+/// Uses the runner's concurrency, count, and seed.
+#[derive(clap::Args, Clone)]
+#[command(about = "Concurrent in-memory storage engine operations")]
+struct Storage {
+    /// Initial number of keys.
+    #[arg(short, long, default_value = "100000")]
+    keys: u64,
+
+    /// Value size.
+    #[arg(long, default_value = "100")]
+    size: usize,
+
+    /// Percentage of operations that are writes.
+    #[arg(long, default_value = "10")]
+    writes: u32,
+
+    /// Percentage of operations that are scans of up to 10 keys.
+    #[arg(long, default_value = "5")]
+    scans: u32,
+}
+
+impl Storage {
+    /// Runs the benchmark, with a global mutex and with engine clones.
+    fn run(&self, runner: &Runner) -> Result<()> {
+        if self.writes + self.scans > 100 {
+            return errinput!("writes and scans can't exceed 100%");
+        }
+        let mut memory = storage::Memory::new();
+        let value = vec![0; self.size];
+        for key in 0..self.keys {
+            memory.set(&key.to_be_bytes(), value.clone())?;
+        }
+
+        println!(
+            "Running storage benchmark ({} workers, {} operations)",
+            runner.concurrency, runner.count
+        );
+        println!();
+        println!("Locking   Duration   Throughput");
+        let locked = std::sync::Mutex::new(memory.clone());
+        let global = self.run_workers(runner, || {
+            let locked = &locked;
+            move |op: &StorageOperation| op.execute(&mut *locked.lock()?)
+        })?;
+        println!("global    {:>7.3}s   {:>8.0}/s", global, runner.count as f64 / global);
+        let shared = self.run_workers(runner, || {
+            let mut memory = memory.clone();
+            move |op: &StorageOperation| op.execute(&mut memory)
+        })?;
+        println!("shared    {:>7.3}s   {:>8.0}/s", shared, runner.count as f64 / shared);
+        println!();
+        println!("Speedup:  {:.2}x", global / shared);
+        Ok(())
+    }
+
+    /// Runs the operations across concurrent workers, using an executor
+    /// created for each worker, and returns the duration in seconds.
+    fn run_workers<F, X>(&self, runner: &Runner, executor: F) -> Result<f64>
+    where
+        F: Fn() -> X,
+        X: FnMut(&StorageOperation) -> Result<()> + Send,
+    {
+        let workers = runner.concurrency.max(1);
+        let mut rng = StdRng::seed_from_u64(runner.seed);
+        let operations = (0..runner.count).map(|_| self.generate(&mut rng)).collect_vec();
+        let chunks = operations.chunks(runner.count.div_ceil(workers).max(1)).collect_vec();
+        let start = Instant::now();
+        std::thread::scope(|s| {
+            let handles = chunks
+                .iter()
+                .map(|ops| {
+                    let mut execute = executor();
+                    s.spawn(move || ops.iter().try_for_each(&mut execute))
+                })
+                .collect_vec();
+            handles.into_iter().try_for_each(|h| h.join().expect("worker panicked"))
+        })?;
+        Ok(start.elapsed().as_secs_f64())
+    }
+
+    /// Generates a random operation.
+    fn generate(&self, rng: &mut StdRng) -> StorageOperation {
+        let key = rng.gen_range(0..self.keys).to_be_bytes().to_vec();
+        let roll = rng.gen_range(0..100);
+        if roll < self.writes {
+            StorageOperation::Write(key, vec![0; self.size])
+        } else if roll < self.writes + self.scans {
+            StorageOperation::Scan(key, 10)
+        } else {
+            StorageOperation::Read(key)
+        }
+    }
+}
+
+/// A storage benchmark operation.
+enum StorageOperation {
+    /// Reads a key.
+    Read(Vec<u8>),
+    /// Writes a key.
+    Write(Vec<u8>, Vec<u8>),
+    /// Scans up to the given number of keys, starting at the given key.
+    Scan(Vec<u8>, usize),
+}
+
+impl StorageOperation {
+    /// Executes the operation against the engine.
+    fn execute(&self, engine: &mut storage::Memory) -> Result<()> {
+        match self {
+            Self::Read(key) => _ = engine.get(key)?,
+            Self::Write(key, value) => engine.set(key, value.clone())?,
+            Self::Scan(key, limit) => {
+                for item in engine.scan(key.clone()..).take(*limit) {
+                    item?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,631 @@
+//! Loads the toyDB server configuration. Each setting is resolved from the
+//! following sources, in order of precedence:
+//!
+//! 1. Command-line flags, e.g. --listen-sql localhost:9601.
+//! 2. TOYDB_ environment variables, e.g. TOYDB_LISTEN_SQL=localhost:9601.
+//! 3. The config file, by default config/toydb.yaml.
+//! 4. The built-in defaults.
+//!
+//! The file path can itself be given via --config or TOYDB_CONFIG. Maps, i.e.
+//! peers and peers_sql, are given as comma-separated ID=address pairs in flags
+//! and environment variables, e.g. TOYDB_PEERS=2=host2:9701,3=host3:9701.
+//! Lists, i.e. learners and audit_tables, are given as comma-separated values.
+//! When the config file is reloaded on SIGHUP, flags and environment variables
+//! still take precedence over it.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use serde::Deserialize;
+
+use toydb::Server;
+use toydb::errinput;
+use toydb::error::Result;
+use toydb::server::Settings;
+use toydb::{logging, raft};
+
+/// The toyDB server configuration, resolved via Flags::load.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// The node ID. Must be unique in the cluster.
+    pub id: raft::NodeID,
+    /// The other nodes in the cluster, and their Raft TCP addresses.
+    pub peers: HashMap<raft::NodeID, String>,
+    /// The IDs of non-voting read replicas, possibly including this node.
+    /// Must be the same on all nodes.
+    pub learners: Vec<raft::NodeID>,
+    /// The Raft listen address.
+    pub listen_raft: String,
+    /// The SQL listen address.
+    pub listen_sql: String,
+    /// The SQL address advertised to clients. Empty uses listen_sql.
+    pub advertise_sql: String,
+    /// The other nodes' SQL client addresses, which clients are redirected to
+    /// when connecting to the cluster.
+    pub peers_sql: HashMap<raft::NodeID, String>,
+    /// The Postgres wire protocol listen address. Empty disables it.
+    pub listen_pg: String,
+    /// The HTTP/JSON query listen address. Empty disables it.
+    pub listen_http: String,
+    /// The number of entries the node can lag behind the leader's commit
+    /// index while reporting ready via HTTP /readyz.
+    pub ready_max_lag: u64,
+    /// The gRPC listen address. Empty disables it.
+    pub listen_grpc: String,
+    /// The SQL Unix socket path. Empty disables it.
+    pub listen_unix: String,
+    /// The Unix socket's file permissions, in octal.
+    pub unix_socket_mode: String,
+    /// The node's TLS certificate chain PEM file. Empty disables TLS.
+    pub tls_cert: String,
+    /// The node's TLS private key PEM file.
+    pub tls_key: String,
+    /// The CA certificate PEM file used to verify peer certificates.
+    pub tls_ca: String,
+    /// If true, Raft peers must authenticate with a certificate signed by the
+    /// CA (mutual TLS).
+    pub tls_peer_auth: bool,
+    /// The maximum number of SQL client connections. 0 disables it.
+    pub max_connections: usize,
+    /// The maximum number of SQL client connections per IP. 0 disables it.
+    pub max_connections_per_ip: usize,
+    /// The maximum number of statements per second per session. 0 disables it.
+    pub session_qps: u32,
+    /// The admin superuser's password. Empty disables authentication.
+    pub admin_password: String,
+    /// The log level, optionally with per-module levels (see logging::Filter).
+    pub log_level: String,
+    /// The log format: text or json.
+    pub log_format: String,
+    /// The OTLP/HTTP trace collector address. Empty disables tracing.
+    pub trace_endpoint: String,
+    /// The fraction of statements to trace, between 0 and 1.
+    pub trace_sample_rate: f64,
+    /// The path to this node's data directory. The Raft log is stored in
+    /// "raft", and the SQL state machine in "sql".
+    pub data_dir: String,
+    /// The Raft storage engine: bitcask, segmented, or memory.
+    pub storage_raft: String,
+    /// The SQL storage engine: bitcask or memory.
+    pub storage_sql: String,
+    /// If false, don't fsync Raft log writes to disk. Disabling this
+    /// will yield much better write performance, but may lose data on
+    /// host crashes which compromises Raft safety guarantees.
+    pub fsync: bool,
+    /// How long the Raft leader batches client writes before proposing them
+    /// together (group commit), in milliseconds. 0 disables it.
+    pub group_commit_window: u64,
+    /// The maximum size of a group commit batch in bytes. A full batch is
+    /// proposed without waiting for the rest of the window.
+    pub group_commit_bytes: usize,
+    /// The garbage fraction threshold at which to trigger compaction.
+    pub compact_threshold: f64,
+    /// The minimum bytes of garbage before triggering compaction.
+    pub compact_min_bytes: u64,
+    /// The size in bytes at which the segmented Raft log starts a new segment.
+    pub raft_segment_size: u64,
+    /// The number of segmented Raft log segments to retain before the
+    /// truncation point.
+    pub raft_retain_segments: usize,
+    /// The memory limit for each SQL statement in bytes. 0 disables it.
+    pub query_memory_limit: usize,
+    /// The query result cache size in bytes. 0 disables it.
+    pub result_cache_size: usize,
+    /// The query plan cache size in plans. 0 disables it.
+    pub plan_cache_size: usize,
+    /// The minimum duration of statements to log in the slow query log, in
+    /// milliseconds. 0 disables it.
+    pub slow_query_threshold: u64,
+    /// The slow query log file path, relative to data_dir.
+    pub slow_query_log: String,
+    /// The slow query log size in bytes before it's rotated. 0 disables
+    /// rotation.
+    pub slow_query_log_size: u64,
+    /// The audit log file path, relative to data_dir. Empty disables it.
+    pub audit_log: String,
+    /// The tables to audit DML statements for in the audit log.
+    pub audit_tables: Vec<String>,
+    /// The maximum size of a streamed SELECT row frame in bytes.
+    pub frame_size: usize,
+    /// The number of recent MVCC versions retained by garbage collection.
+    pub gc_retention: u64,
+    /// The interval between garbage collection runs in seconds. 0 disables it.
+    pub gc_interval: u64,
+    /// The interval between replica consistency checks in seconds. 0 disables
+    /// it.
+    pub consistency_check_interval: u64,
+    /// The interval between purges of expired rows from tables with a TTL in
+    /// seconds. 0 disables it.
+    pub ttl_purge_interval: u64,
+    /// How long a transaction can go without a heartbeat before it's
+    /// considered abandoned and rolled back, in seconds. 0 disables it.
+    pub intent_timeout: u64,
+    /// How long an explicit transaction can run in seconds. 0 disables it.
+    pub transaction_timeout: u64,
+    /// How long an explicit transaction can be idle in seconds. 0 disables it.
+    pub idle_transaction_timeout: u64,
+    /// How long a statement can execute in milliseconds. 0 disables it.
+    pub statement_timeout: u64,
+    /// How long in-flight statements and transactions can run on shutdown in
+    /// seconds.
+    pub shutdown_grace: u64,
+    /// If true, enable fault injection via the hidden Request::InjectFaults
+    /// admin request, for chaos testing. Not for production use.
+    pub fault_injection: bool,
+}
+
+/// Command-line flags for the config file and each setting, which can also be
+/// given as TOYDB_ environment variables. Unset flags use the config file.
+#[derive(Clone, Debug, clap::Args)]
+pub struct Flags {
+    /// The configuration file path.
+    #[arg(short = 'c', long = "config", env = "TOYDB_CONFIG", global = true)]
+    #[arg(default_value = "config/toydb.yaml")]
+    pub file: String,
+    /// The node ID.
+    #[arg(long, env = "TOYDB_ID", global = true)]
+    id: Option<raft::NodeID>,
+    /// The other nodes' Raft addresses, as ID=host:port,...
+    #[arg(long, env = "TOYDB_PEERS", global = true, value_parser = parse_addrs)]
+    peers: Option<HashMap<raft::NodeID, String>>,
+    /// The IDs of non-voting read replicas, as ID,...
+    #[arg(long, env = "TOYDB_LEARNERS", global = true, value_delimiter = ',')]
+    learners: Option<Vec<raft::NodeID>>,
+    /// The Raft listen address.
+    #[arg(long, env = "TOYDB_LISTEN_RAFT", global = true)]
+    listen_raft: Option<String>,
+    /// The SQL listen address.
+    #[arg(long, env = "TOYDB_LISTEN_SQL", global = true)]
+    listen_sql: Option<String>,
+    /// The SQL address advertised to clients.
+    #[arg(long, env = "TOYDB_ADVERTISE_SQL", global = true)]
+    advertise_sql: Option<String>,
+    /// The other nodes' SQL addresses, as ID=host:port,...
+    #[arg(long, env = "TOYDB_PEERS_SQL", global = true, value_parser = parse_addrs)]
+    peers_sql: Option<HashMap<raft::NodeID, String>>,
+    /// The Postgres wire protocol listen address.
+    #[arg(long, env = "TOYDB_LISTEN_PG", global = true)]
+    listen_pg: Option<String>,
+    /// The HTTP/JSON query listen address.
+    #[arg(long, env = "TOYDB_LISTEN_HTTP", global = true)]
+    listen_http: Option<String>,
+    /// The number of entries the node can lag the leader while ready.
+    #[arg(long, env = "TOYDB_READY_MAX_LAG", global = true)]
+    ready_max_lag: Option<u64>,
+    /// The gRPC listen address.
+    #[arg(long, env = "TOYDB_LISTEN_GRPC", global = true)]
+    listen_grpc: Option<String>,
+    /// The SQL Unix socket path.
+    #[arg(long, env = "TOYDB_LISTEN_UNIX", global = true)]
+    listen_unix: Option<String>,
+    /// The Unix socket's file permissions, in octal.
+    #[arg(long, env = "TOYDB_UNIX_SOCKET_MODE", global = true)]
+    unix_socket_mode: Option<String>,
+    /// The TLS certificate chain PEM file.
+    #[arg(long, env = "TOYDB_TLS_CERT", global = true)]
+    tls_cert: Option<String>,
+    /// The TLS private key PEM file.
+    #[arg(long, env = "TOYDB_TLS_KEY", global = true)]
+    tls_key: Option<String>,
+    /// The TLS CA certificate PEM file.
+    #[arg(long, env = "TOYDB_TLS_CA", global = true)]
+    tls_ca: Option<String>,
+    /// Whether Raft peers must use mutual TLS.
+    #[arg(long, env = "TOYDB_TLS_PEER_AUTH", global = true)]
+    tls_peer_auth: Option<bool>,
+    /// The maximum number of SQL client connections.
+    #[arg(long, env = "TOYDB_MAX_CONNECTIONS", global = true)]
+    max_connections: Option<usize>,
+    /// The maximum number of SQL client connections per IP.
+    #[arg(long, env = "TOYDB_MAX_CONNECTIONS_PER_IP", global = true)]
+    max_connections_per_ip: Option<usize>,
+    /// The maximum number of statements per second per session.
+    #[arg(long, env = "TOYDB_SESSION_QPS", global = true)]
+    session_qps: Option<u32>,
+    /// The admin superuser's password.
+    #[arg(long, env = "TOYDB_ADMIN_PASSWORD", global = true, hide_env_values = true)]
+    admin_password: Option<String>,
+    /// The log level, optionally with per-module levels.
+    #[arg(long, env = "TOYDB_LOG_LEVEL", global = true)]
+    log_level: Option<String>,
+    /// The log format.
+    #[arg(long, env = "TOYDB_LOG_FORMAT", global = true)]
+    log_format: Option<String>,
+    /// The OTLP/HTTP trace collector address.
+    #[arg(long, env = "TOYDB_TRACE_ENDPOINT", global = true)]
+    trace_endpoint: Option<String>,
+    /// The fraction of statements to trace.
+    #[arg(long, env = "TOYDB_TRACE_SAMPLE_RATE", global = true)]
+    trace_sample_rate: Option<f64>,
+    /// The data directory.
+    #[arg(long, env = "TOYDB_DATA_DIR", global = true)]
+    data_dir: Option<String>,
+    /// The Raft storage engine.
+    #[arg(long, env = "TOYDB_STORAGE_RAFT", global = true)]
+    storage_raft: Option<String>,
+    /// The SQL storage engine.
+    #[arg(long, env = "TOYDB_STORAGE_SQL", global = true)]
+    storage_sql: Option<String>,
+    /// Whether to fsync Raft log writes.
+    #[arg(long, env = "TOYDB_FSYNC", global = true)]
+    fsync: Option<bool>,
+    /// The group commit window in milliseconds.
+    #[arg(long, env = "TOYDB_GROUP_COMMIT_WINDOW", global = true)]
+    group_commit_window: Option<u64>,
+    /// The maximum group commit batch size in bytes.
+    #[arg(long, env = "TOYDB_GROUP_COMMIT_BYTES", global = true)]
+    group_commit_bytes: Option<usize>,
+    /// The garbage fraction threshold for compaction.
+    #[arg(long, env = "TOYDB_COMPACT_THRESHOLD", global = true)]
+    compact_threshold: Option<f64>,
+    /// The minimum garbage bytes for compaction.
+    #[arg(long, env = "TOYDB_COMPACT_MIN_BYTES", global = true)]
+    compact_min_bytes: Option<u64>,
+    /// The segmented Raft log segment size in bytes.
+    #[arg(long, env = "TOYDB_RAFT_SEGMENT_SIZE", global = true)]
+    raft_segment_size: Option<u64>,
+    /// The number of segmented Raft log segments to retain.
+    #[arg(long, env = "TOYDB_RAFT_RETAIN_SEGMENTS", global = true)]
+    raft_retain_segments: Option<usize>,
+    /// The memory limit for each SQL statement in bytes.
+    #[arg(long, env = "TOYDB_QUERY_MEMORY_LIMIT", global = true)]
+    query_memory_limit: Option<usize>,
+    /// The query result cache size in bytes.
+    #[arg(long, env = "TOYDB_RESULT_CACHE_SIZE", global = true)]
+    result_cache_size: Option<usize>,
+    /// The query plan cache size in plans.
+    #[arg(long, env = "TOYDB_PLAN_CACHE_SIZE", global = true)]
+    plan_cache_size: Option<usize>,
+    /// The slow query log threshold in milliseconds.
+    #[arg(long, env = "TOYDB_SLOW_QUERY_THRESHOLD", global = true)]
+    slow_query_threshold: Option<u64>,
+    /// The slow query log file path.
+    #[arg(long, env = "TOYDB_SLOW_QUERY_LOG", global = true)]
+    slow_query_log: Option<String>,
+    /// The slow query log size in bytes before rotation.
+    #[arg(long, env = "TOYDB_SLOW_QUERY_LOG_SIZE", global = true)]
+    slow_query_log_size: Option<u64>,
+    /// The audit log file path.
+    #[arg(long, env = "TOYDB_AUDIT_LOG", global = true)]
+    audit_log: Option<String>,
+    /// The tables to audit DML for, as table,...
+    #[arg(long, env = "TOYDB_AUDIT_TABLES", global = true, value_delimiter = ',')]
+    audit_tables: Option<Vec<String>>,
+    /// The maximum size of a streamed row frame in bytes.
+    #[arg(long, env = "TOYDB_FRAME_SIZE", global = true)]
+    frame_size: Option<usize>,
+    /// The number of recent MVCC versions to retain.
+    #[arg(long, env = "TOYDB_GC_RETENTION", global = true)]
+    gc_retention: Option<u64>,
+    /// The interval between garbage collection runs in seconds.
+    #[arg(long, env = "TOYDB_GC_INTERVAL", global = true)]
+    gc_interval: Option<u64>,
+    /// The interval between replica consistency checks in seconds.
+    #[arg(long, env = "TOYDB_CONSISTENCY_CHECK_INTERVAL", global = true)]
+    consistency_check_interval: Option<u64>,
+    /// The interval between purges of expired TTL rows in seconds.
+    #[arg(long, env = "TOYDB_TTL_PURGE_INTERVAL", global = true)]
+    ttl_purge_interval: Option<u64>,
+    /// How long a transaction can go without a heartbeat in seconds.
+    #[arg(long, env = "TOYDB_INTENT_TIMEOUT", global = true)]
+    intent_timeout: Option<u64>,
+    /// How long an explicit transaction can run in seconds.
+    #[arg(long, env = "TOYDB_TRANSACTION_TIMEOUT", global = true)]
+    transaction_timeout: Option<u64>,
+    /// How long an explicit transaction can be idle in seconds.
+    #[arg(long, env = "TOYDB_IDLE_TRANSACTION_TIMEOUT", global = true)]
+    idle_transaction_timeout: Option<u64>,
+    /// How long a statement can execute in milliseconds.
+    #[arg(long, env = "TOYDB_STATEMENT_TIMEOUT", global = true)]
+    statement_timeout: Option<u64>,
+    /// The shutdown grace period in seconds.
+    #[arg(long, env = "TOYDB_SHUTDOWN_GRACE", global = true)]
+    shutdown_grace: Option<u64>,
+    /// Whether to enable fault injection, for chaos testing.
+    #[arg(long, env = "TOYDB_FAULT_INJECTION", global = true, hide = true)]
+    fault_injection: Option<bool>,
+}
+
+impl Flags {
+    /// Loads the configuration from the config file, overridden by flags and
+    /// environment variables. Errors on unknown TOYDB_ environment variables,
+    /// like the config file does for unknown keys.
+    pub fn load(&self) -> Result<Config> {
+        let command = <Self as clap::Args>::augment_args(clap::Command::new("toydb"));
+        let known: Vec<_> = command.get_arguments().filter_map(|arg| arg.get_env()).collect();
+        for (name, _) in std::env::vars_os() {
+            if name.to_string_lossy().starts_with("TOYDB_") && !known.contains(&name.as_os_str()) {
+                return errinput!("unknown environment variable {}", name.to_string_lossy());
+            }
+        }
+
+        let mut config: Config = Config::defaults()?
+            .add_source(::config::File::with_name(&self.file))
+            .build()?
+            .try_deserialize()?;
+
+        // Flags replace the file's value, including all entries of maps.
+        macro_rules! apply {
+            ($($key:ident),*) => {
+                $(if let Some(value) = &self.$key {
+                    config.$key = value.clone();
+                })*
+            };
+        }
+        apply!(
+            id,
+            peers,
+            learners,
+            listen_raft,
+            listen_sql,
+            advertise_sql,
+            peers_sql,
+            listen_pg,
+            listen_http,
+            ready_max_lag,
+            listen_grpc,
+            listen_unix,
+            unix_socket_mode,
+            tls_cert,
+            tls_key,
+            tls_ca,
+            tls_peer_auth,
+            max_connections,
+            max_connections_per_ip,
+            session_qps,
+            admin_password,
+            log_level,
+            log_format,
+            trace_endpoint,
+            trace_sample_rate,
+            data_dir,
+            storage_raft,
+            storage_sql,
+            fsync,
+            group_commit_window,
+            group_commit_bytes,
+            compact_threshold,
+            compact_min_bytes,
+            raft_segment_size,
+            raft_retain_segments,
+            query_memory_limit,
+            result_cache_size,
+            plan_cache_size,
+            slow_query_threshold,
+            slow_query_log,
+            slow_query_log_size,
+            audit_log,
+            audit_tables,
+            frame_size,
+            gc_retention,
+            gc_interval,
+            consistency_check_interval,
+            ttl_purge_interval,
+            intent_timeout,
+            transaction_timeout,
+            idle_transaction_timeout,
+            statement_timeout,
+            shutdown_grace,
+            fault_injection
+        );
+        Ok(config)
+    }
+}
+
+/// Parses a comma-separated list of ID=address pairs. An empty string is an
+/// empty map.
+fn parse_addrs(s: &str) -> Result<HashMap<raft::NodeID, String>> {
+    let mut addrs = HashMap::new();
+    for pair in s.split(',').map(str::trim).filter(|pair| !pair.is_empty()) {
+        let Some((id, addr)) = pair.split_once('=') else {
+            return errinput!("invalid address {pair:?}, expected ID=host:port");
+        };
+        let id = id.trim().parse().or_else(|_| errinput!("invalid node ID {id:?}"))?;
+        addrs.insert(id, addr.trim().to_string());
+    }
+    Ok(addrs)
+}
+
+impl Config {
+    /// Returns a config builder with the built-in defaults.
+    fn defaults() -> Result<::config::ConfigBuilder<::config::builder::DefaultState>> {
+        Ok(::config::Config::builder()
+            .set_default("id", "1")?
+            .set_default("learners", Vec::<String>::new())?
+            .set_default("listen_sql", "localhost:9601")?
+            .set_default("listen_raft", "localhost:9701")?
+            .set_default("advertise_sql", "")?
+            .set_default("peers_sql", HashMap::<String, String>::new())?
+            .set_default("listen_pg", "")?
+            .set_default("listen_http", "")?
+            .set_default("ready_max_lag", Server::DEFAULT_READY_MAX_LAG)?
+            .set_default("listen_grpc", "")?
+            .set_default("listen_unix", "")?
+            .set_default("unix_socket_mode", format!("{:o}", Server::DEFAULT_UNIX_SOCKET_MODE))?
+            .set_default("tls_cert", "")?
+            .set_default("tls_key", "")?
+            .set_default("tls_ca", "")?
+            .set_default("tls_peer_auth", false)?
+            .set_default("max_connections", 0)?
+            .set_default("max_connections_per_ip", 0)?
+            .set_default("session_qps", 0)?
+            .set_default("admin_password", "")?
+            .set_default("log_level", "info")?
+            .set_default("log_format", "text")?
+            .set_default("trace_endpoint", "")?
+            .set_default("trace_sample_rate", 1.0)?
+            .set_default("data_dir", "data")?
+            .set_default("storage_raft", "bitcask")?
+            .set_default("storage_sql", "bitcask")?
+            .set_default("fsync", true)?
+            .set_default("group_commit_window", 0)?
+            .set_default("group_commit_bytes", 1_000_000)?
+            .set_default("compact_threshold", 0.2)?
+            .set_default("compact_min_bytes", 1_000_000)?
+            .set_default("raft_segment_size", 64_000_000)?
+            .set_default("raft_retain_segments", 2)?
+            .set_default("query_memory_limit", 1_000_000_000)?
+            .set_default("result_cache_size", 0)?
+            .set_default("plan_cache_size", 0)?
+            .set_default("slow_query_threshold", 0)?
+            .set_default("slow_query_log", "slow.log")?
+            .set_default("slow_query_log_size", 10_000_000)?
+            .set_default("audit_log", "")?
+            .set_default("audit_tables", Vec::<String>::new())?
+            .set_default("frame_size", Server::DEFAULT_FRAME_SIZE as u64)?
+            .set_default("gc_retention", Server::DEFAULT_GC_RETENTION)?
+            .set_default("gc_interval", 60)?
+            .set_default("consistency_check_interval", 0)?
+            .set_default("ttl_purge_interval", 60)?
+            .set_default("intent_timeout", 60)?
+            .set_default("transaction_timeout", 0)?
+            .set_default("idle_transaction_timeout", 0)?
+            .set_default("statement_timeout", 0)?
+            .set_default("shutdown_grace", Server::DEFAULT_SHUTDOWN_GRACE.as_secs())?
+            .set_default("fault_injection", false)?)
+    }
+
+    /// Validates the configuration, returning an error describing the first
+    /// invalid setting.
+    pub fn validate(&self) -> Result<()> {
+        if self.peers.contains_key(&self.id) {
+            return errinput!("peers can't contain the node's own ID {}", self.id);
+        }
+        for (id, addr) in &self.peers {
+            check_addr(&format!("peer {id}"), addr)?;
+        }
+        for (id, addr) in &self.peers_sql {
+            if !self.peers.contains_key(id) && *id != self.id {
+                return errinput!("peers_sql contains unknown node {id}, which isn't in peers");
+            }
+            check_addr(&format!("peers_sql {id}"), addr)?;
+        }
+        for id in &self.learners {
+            if !self.peers.contains_key(id) && *id != self.id {
+                return errinput!("learners contains unknown node {id}, which isn't in peers");
+            }
+        }
+        if self.peers.keys().chain([&self.id]).all(|id| self.learners.contains(id)) {
+            return errinput!("learners can't contain all nodes, at least one must vote");
+        }
+        check_addr("listen_sql", &self.listen_sql)?;
+        check_addr("listen_raft", &self.listen_raft)?;
+        for (key, addr) in [
+            ("advertise_sql", &self.advertise_sql),
+            ("listen_pg", &self.listen_pg),
+            ("listen_http", &self.listen_http),
+            ("listen_grpc", &self.listen_grpc),
+            ("trace_endpoint", &self.trace_endpoint),
+        ] {
+            if !addr.is_empty() {
+                check_addr(key, addr)?;
+            }
+        }
+        match u32::from_str_radix(&self.unix_socket_mode, 8) {
+            Ok(mode) if mode <= 0o777 => {}
+            _ => {
+                return errinput!(
+                    "invalid unix_socket_mode {:?}, expected octal permissions like 600",
+                    self.unix_socket_mode
+                );
+            }
+        }
+        match (self.tls_cert.as_str(), self.tls_key.as_str(), self.tls_ca.as_str()) {
+            ("", "", "") if self.tls_peer_auth => {
+                return errinput!("tls_peer_auth requires tls_cert");
+            }
+            ("", "", "") => {}
+            ("", _, _) => return errinput!("tls_key and tls_ca require tls_cert"),
+            (_, "", _) => return errinput!("tls_key must be given with tls_cert"),
+            (_, _, "") => return errinput!("tls_ca must be given with tls_cert"),
+            _ => {}
+        }
+        if self.log_level.parse::<logging::Filter>().is_err() {
+            return errinput!(
+                "invalid log_level {:?}, expected DEBUG, INFO, WARN, or ERROR, optionally \
+                 followed by MODULE=LEVEL pairs",
+                self.log_level
+            );
+        }
+        if self.log_format.parse::<logging::Format>().is_err() {
+            return errinput!("invalid log_format {:?}, expected text or json", self.log_format);
+        }
+        if !(0.0..=1.0).contains(&self.trace_sample_rate) {
+            return errinput!("trace_sample_rate must be between 0 and 1");
+        }
+        if !matches!(self.storage_raft.as_str(), "bitcask" | "segmented" | "memory" | "") {
+            return errinput!(
+                "invalid storage_raft engine {:?}, expected bitcask, segmented, or memory",
+                self.storage_raft
+            );
+        }
+        if !matches!(self.storage_sql.as_str(), "bitcask" | "memory" | "") {
+            return errinput!(
+                "invalid storage_sql engine {:?}, expected bitcask or memory",
+                self.storage_sql
+            );
+        }
+        if self.data_dir.is_empty() {
+            return errinput!("data_dir can't be empty");
+        }
+        if self.group_commit_window > 0 && self.group_commit_bytes == 0 {
+            return errinput!("group_commit_bytes must be greater than 0 with group_commit_window");
+        }
+        if !(0.0..=1.0).contains(&self.compact_threshold) {
+            return errinput!("compact_threshold must be between 0 and 1");
+        }
+        if self.raft_segment_size == 0 {
+            return errinput!("raft_segment_size must be greater than 0");
+        }
+        if self.slow_query_threshold > 0 && self.slow_query_log.is_empty() {
+            return errinput!("slow_query_log can't be empty when slow_query_threshold is set");
+        }
+        if !self.audit_tables.is_empty() && self.audit_log.is_empty() {
+            return errinput!("audit_tables requires audit_log");
+        }
+        if self.frame_size == 0 {
+            return errinput!("frame_size must be greater than 0");
+        }
+        Ok(())
+    }
+
+    /// Returns the server settings that can be reloaded while serving.
+    pub fn settings(&self) -> Settings {
+        let secs = |secs: u64| Some(secs).filter(|s| *s > 0).map(Duration::from_secs);
+        Settings {
+            memory_limit: Some(self.query_memory_limit).filter(|limit| *limit > 0),
+            transaction_timeout: secs(self.transaction_timeout),
+            idle_timeout: secs(self.idle_transaction_timeout),
+            statement_timeout: Some(self.statement_timeout)
+                .filter(|t| *t > 0)
+                .map(Duration::from_millis),
+            max_connections: Some(self.max_connections).filter(|max| *max > 0),
+            max_connections_per_ip: Some(self.max_connections_per_ip).filter(|max| *max > 0),
+            session_qps: Some(self.session_qps).filter(|qps| *qps > 0),
+        }
+    }
+
+    /// Returns true if this config differs from the other in settings that
+    /// can't be reloaded, i.e. other than the log level and server settings.
+    pub fn requires_restart(&self, other: &Self) -> bool {
+        let mut other = other.clone();
+        other.log_level.clone_from(&self.log_level);
+        other.query_memory_limit = self.query_memory_limit;
+        other.transaction_timeout = self.transaction_timeout;
+        other.idle_transaction_timeout = self.idle_transaction_timeout;
+        other.statement_timeout = self.statement_timeout;
+        other.max_connections = self.max_connections;
+        other.max_connections_per_ip = self.max_connections_per_ip;
+        other.session_qps = self.session_qps;
+        *self != other
+    }
+}
+
+/// Checks that a config address has the form host:port.
+fn check_addr(key: &str, addr: &str) -> Result<()> {
+    match addr.rsplit_once(':') {
+        Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok() => Ok(()),
+        _ => errinput!("invalid {key} address {addr:?}, expected host:port"),
+    }
+}
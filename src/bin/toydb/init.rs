@@ -0,0 +1,168 @@
+//! The init subcommand, which sets up a new local or test cluster. For each
+//! node, it writes a config file and an empty data directory under a node
+//! directory toydbN, with listen addresses and peer lists for all nodes. With
+//! TLS, it also generates a CA certificate and key (ca.pem, ca.key) in the
+//! cluster directory, and a certificate and key for each node (node.pem,
+//! node.key) in its node directory, signed by the CA.
+//!
+//! Node N listens on the base SQL and Raft ports + N - 1. All paths in the
+//! config files are absolute, so the nodes can be started from any directory.
+
+use std::io::Write as _;
+use std::os::unix::fs::OpenOptionsExt as _;
+use std::path::{Path, PathBuf};
+
+use itertools::Itertools as _;
+use toydb::errinput;
+use toydb::error::Result;
+use toydb::raft::NodeID;
+
+/// Initializes a new cluster.
+#[derive(clap::Args)]
+pub struct Init {
+    /// The number of nodes.
+    #[arg(long, default_value_t = 3)]
+    nodes: NodeID,
+    /// The cluster directory, which will contain a directory for each node.
+    #[arg(long, default_value = "cluster")]
+    dir: String,
+    /// The host name that nodes listen on and reach each other at.
+    #[arg(long, default_value = "localhost")]
+    host: String,
+    /// The SQL port of node 1. Node N uses this port + N - 1.
+    #[arg(long, default_value_t = 9601)]
+    sql_port: u16,
+    /// The Raft port of node 1. Node N uses this port + N - 1.
+    #[arg(long, default_value_t = 9701)]
+    raft_port: u16,
+    /// Generate certificates, and enable TLS with mutual peer authentication.
+    #[arg(long)]
+    tls: bool,
+}
+
+impl Init {
+    /// Runs the init command. Refuses to overwrite existing node directories.
+    pub fn run(&self) -> Result<()> {
+        if self.nodes == 0 {
+            return errinput!("cluster must have at least 1 node");
+        }
+        let last = self.nodes as u16 - 1;
+        if self.sql_port.checked_add(last).is_none() || self.raft_port.checked_add(last).is_none() {
+            return errinput!("ports for {} nodes are out of range", self.nodes);
+        }
+        let dir = Path::new(&self.dir);
+        for id in 1..=self.nodes {
+            let node_dir = dir.join(format!("toydb{id}"));
+            if node_dir.exists() {
+                return errinput!("node directory {} already exists", node_dir.display());
+            }
+        }
+        if self.tls && dir.join("ca.pem").exists() {
+            return errinput!("CA certificate {} already exists", dir.join("ca.pem").display());
+        }
+        std::fs::create_dir_all(dir)?;
+        let dir = dir.canonicalize()?;
+
+        let certs = match self.tls {
+            true => Some(Certs::generate(&dir)?),
+            false => None,
+        };
+        for id in 1..=self.nodes {
+            let node_dir = dir.join(format!("toydb{id}"));
+            std::fs::create_dir_all(node_dir.join("data"))?;
+            if let Some(certs) = &certs {
+                certs.generate_node(&self.host, &node_dir)?;
+            }
+            let config = node_dir.join("toydb.yaml");
+            std::fs::write(&config, self.build_config(id, &dir, &node_dir))?;
+            println!(
+                "Node {id}: SQL {host}:{sql}, Raft {host}:{raft}, config {config}",
+                host = self.host,
+                sql = self.sql_port(id),
+                raft = self.raft_port(id),
+                config = config.display(),
+            );
+        }
+        println!("Initialized {}-node cluster in {}", self.nodes, dir.display());
+        println!("Start each node with: toydb -c CONFIG");
+        Ok(())
+    }
+
+    /// Builds the config file for the given node.
+    fn build_config(&self, id: NodeID, dir: &Path, node_dir: &Path) -> String {
+        let host = &self.host;
+        let mut lines = vec![
+            format!("# toyDB node {id} of {}, generated by toydb init.", self.nodes),
+            format!("id: {id}"),
+            format!("data_dir: {}", node_dir.join("data").display()),
+            format!("listen_sql: {host}:{}", self.sql_port(id)),
+            format!("listen_raft: {host}:{}", self.raft_port(id)),
+        ];
+        let peers = (1..=self.nodes).filter(|peer| *peer != id).collect_vec();
+        if peers.is_empty() {
+            lines.extend(["peers: {}".to_string(), "peers_sql: {}".to_string()]);
+        } else {
+            lines.push("peers:".to_string());
+            lines.extend(peers.iter().map(|p| format!("  '{p}': {host}:{}", self.raft_port(*p))));
+            lines.push("peers_sql:".to_string());
+            lines.extend(peers.iter().map(|p| format!("  '{p}': {host}:{}", self.sql_port(*p))));
+        }
+        if self.tls {
+            lines.push(format!("tls_cert: {}", node_dir.join("node.pem").display()));
+            lines.push(format!("tls_key: {}", node_dir.join("node.key").display()));
+            lines.push(format!("tls_ca: {}", dir.join("ca.pem").display()));
+            lines.push("tls_peer_auth: true".to_string());
+        }
+        lines.into_iter().map(|line| line + "\n").collect()
+    }
+
+    /// Returns the SQL port of the given node.
+    fn sql_port(&self, id: NodeID) -> u16 {
+        self.sql_port + id as u16 - 1
+    }
+
+    /// Returns the Raft port of the given node.
+    fn raft_port(&self, id: NodeID) -> u16 {
+        self.raft_port + id as u16 - 1
+    }
+}
+
+/// A generated CA, which signs the node certificates.
+struct Certs {
+    ca: rcgen::Certificate,
+    ca_key: rcgen::KeyPair,
+}
+
+impl Certs {
+    /// Generates a CA certificate and key, and writes them to ca.pem and
+    /// ca.key in the given directory. The key can be used to sign
+    /// certificates for nodes added later.
+    fn generate(dir: &Path) -> Result<Self> {
+        let ca_key = rcgen::KeyPair::generate()?;
+        let mut params = rcgen::CertificateParams::new(Vec::new())?;
+        params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+        params.distinguished_name.push(rcgen::DnType::CommonName, "toyDB CA");
+        let ca = params.self_signed(&ca_key)?;
+        std::fs::write(dir.join("ca.pem"), ca.pem())?;
+        Self::write_key(dir.join("ca.key"), &ca_key)?;
+        Ok(Self { ca, ca_key })
+    }
+
+    /// Generates a node certificate for the given host name, signed by the CA,
+    /// and writes it to node.pem and node.key in the given node directory.
+    fn generate_node(&self, host: &str, node_dir: &Path) -> Result<()> {
+        let key = rcgen::KeyPair::generate()?;
+        let params = rcgen::CertificateParams::new(vec![host.to_string()])?;
+        let cert = params.signed_by(&key, &self.ca, &self.ca_key)?;
+        std::fs::write(node_dir.join("node.pem"), cert.pem())?;
+        Self::write_key(node_dir.join("node.key"), &key)
+    }
+
+    /// Writes a private key, readable only by the owner.
+    fn write_key(path: PathBuf, key: &rcgen::KeyPair) -> Result<()> {
+        let mut file =
+            std::fs::OpenOptions::new().write(true).create_new(true).mode(0o600).open(path)?;
+        file.write_all(key.serialize_pem().as_bytes())?;
+        Ok(())
+    }
+}
@@ -0,0 +1,668 @@
+//! The toyDB server. Takes configuration from a config file (default
+//! config/toydb.yaml), TOYDB_ environment variables, or command-line flags,
+//! see the config module for the precedence order. Listens
+//! for SQL clients (default port 9601) and Raft connections from other toyDB
+//! peers (default port 9701). The Raft log and SQL database are stored at
+//! data/raft and data/sql by default.
+//!
+//! Use the toysql command-line client to connect to the server.
+//!
+//! The configuration is validated on startup. On SIGHUP, the config file is
+//! reloaded, updating the log level, statement memory limit, timeouts, and
+//! connection and rate limits for new sessions. Other changes require a
+//! restart. The check subcommand validates the configuration and exits.
+//!
+//! On SIGTERM or SIGINT, the server shuts down gracefully: it stops accepting
+//! connections, drains in-flight statements and transactions, and transfers
+//! Raft leadership to another node (see Server::serve).
+//!
+//! The restore subcommand restores a backup taken with toysql's !backup
+//! command to a point in time, by replaying any incremental backups and the
+//! node's Raft log on top of it.
+//!
+//! The status subcommand connects to the running node given by the
+//! configuration, and displays the status of all cluster nodes.
+//!
+//! The decommission subcommand removes a node from the cluster, to scale it
+//! down. When run on the removed node itself, it also retires its data
+//! directory by writing a RETIRED marker file, and the server then refuses to
+//! start with it.
+//!
+//! The scrub subcommand checks the consistency of the running node's stored
+//! data, and optionally repairs it on all nodes.
+//!
+//! The check-consistency subcommand checks that all cluster nodes have the
+//! same state machine contents, by comparing checksums.
+//!
+//! The init subcommand sets up a new local or test cluster, writing config
+//! files, data directories, and optionally TLS certificates for each node.
+//! Unlike the other subcommands, it doesn't read the configuration.
+
+#![warn(clippy::all)]
+
+mod config;
+mod init;
+
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use clap::Parser as _;
+use itertools::Itertools as _;
+use log::{error, info, warn};
+use toydb::error::{Error, Result};
+use toydb::fault::FaultInjector;
+use toydb::logging;
+use toydb::raft::{self, State as _};
+use toydb::server::{SettingsHandle, ShutdownHandle};
+use toydb::sql;
+use toydb::sql::types::User;
+use toydb::storage::{self, Engine as _};
+use toydb::trace;
+use toydb::{Client, Server};
+use toydb::{errdata, errinput};
+
+use crate::config::{Config, Flags};
+
+/// The marker file written to a decommissioned node's data directory.
+const RETIRED_FILE: &str = "RETIRED";
+
+/// How long to wait for a decommissioned node's leadership transfer, removal,
+/// and replication.
+const DECOMMISSION_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How often to poll the cluster status while decommissioning a node.
+const DECOMMISSION_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+fn main() {
+    if let Err(error) = Command::parse().run() {
+        eprintln!("Error: {error}");
+        std::process::exit(1);
+    }
+}
+
+/// The toyDB server command.
+#[derive(clap::Parser)]
+#[command(about = "Starts a toyDB server.", version, propagate_version = true)]
+struct Command {
+    #[command(flatten)]
+    flags: Flags,
+    #[command(subcommand)]
+    subcommand: Option<Subcommand>,
+}
+
+/// A toyDB server subcommand.
+#[derive(clap::Subcommand)]
+enum Subcommand {
+    /// Restores a backup to a point in time, by replaying any incremental
+    /// backups and the node's Raft log on top of it. The restored SQL database
+    /// is written to a new data directory, which can seed a new cluster by
+    /// copying it to every node. The node must be stopped, and belong to the
+    /// cluster the backup was taken from. The node's Raft log can be omitted
+    /// if incremental backups are given.
+    Restore {
+        /// The backup file, taken with toysql's !backup command.
+        backup: String,
+        /// Incremental backup files to layer on top of the backup, in the
+        /// order they were taken with toysql's !backup FILE INDEX command.
+        #[arg(long)]
+        incremental: Vec<String>,
+        /// Restore the database as of this MVCC version, i.e. the state seen
+        /// by AS OF SYSTEM TIME at it. Defaults to the latest committed state.
+        #[arg(long)]
+        as_of: Option<u64>,
+        /// The data directory to write the restored database to.
+        #[arg(long)]
+        to: String,
+    },
+    /// Validates the configuration and exits.
+    Check,
+    /// Displays the status of all cluster nodes, as collected by the running
+    /// node: their health, Raft role and term, applied index, and storage
+    /// sizes. Connects to the node's SQL address, authenticating as admin if
+    /// authentication is enabled.
+    Status,
+    /// Removes a node from the cluster. Transfers Raft leadership away from
+    /// it if it's the leader, removes it from the Raft configuration, and
+    /// waits for the removal to replicate to all remaining nodes. Connects to
+    /// another node's SQL address, authenticating as admin if authentication
+    /// is enabled. If the node is the local node, its data directory is then
+    /// retired. The removed node should be stopped afterwards.
+    Decommission {
+        /// The ID of the node to remove.
+        node: raft::NodeID,
+    },
+    /// Checks the consistency of the stored data: MVCC metadata and intents,
+    /// the catalog, table rows, and secondary indexes. Without repair, only the
+    /// running node's data is checked. Repairs run on all nodes via the Raft
+    /// log. Reports any inconsistencies, and exits with a non-zero status if
+    /// unrepaired ones are found. Connects to the node's SQL address,
+    /// authenticating as admin if authentication is enabled.
+    Scrub {
+        /// Repair inconsistencies where possible, on all nodes.
+        #[arg(long)]
+        repair: bool,
+    },
+    /// Checks that all cluster nodes have the same state machine contents,
+    /// by having them checksum it at the same Raft applied index and
+    /// comparing the checksums with the leader's. Exits with a non-zero
+    /// status if any node diverges or can't be checked. Connects to the node's
+    /// SQL address, authenticating as admin if authentication is enabled.
+    CheckConsistency,
+    /// Initializes a new local or test cluster in a directory, with a
+    /// directory for each node containing its config file, data directory,
+    /// and (with --tls) certificate. Nodes listen on consecutive ports from
+    /// the given base ports, and have all other nodes as peers.
+    Init(init::Init),
+}
+
+impl Command {
+    /// Runs the command.
+    fn run(self) -> Result<()> {
+        if let Some(Subcommand::Init(init)) = &self.subcommand {
+            return init.run();
+        }
+        let cfg = self.flags.load()?;
+        cfg.validate()?;
+        match self.subcommand {
+            None => Self::serve(cfg, self.flags),
+            Some(Subcommand::Check) => {
+                println!("Configuration {} is valid", self.flags.file);
+                Ok(())
+            }
+            Some(Subcommand::Restore { backup, incremental, as_of, to }) => {
+                Self::restore(cfg, &backup, &incremental, as_of, &to)
+            }
+            Some(Subcommand::Status) => Self::status(cfg),
+            Some(Subcommand::Decommission { node }) => Self::decommission(cfg, node),
+            Some(Subcommand::Scrub { repair }) => Self::scrub(cfg, repair),
+            Some(Subcommand::CheckConsistency) => Self::check_consistency(cfg),
+            Some(Subcommand::Init(_)) => unreachable!("handled above"),
+        }
+    }
+
+    /// Displays the cluster status, as collected by the local node.
+    fn status(cfg: Config) -> Result<()> {
+        let addr = match cfg.advertise_sql.as_str() {
+            "" => cfg.listen_sql.as_str(),
+            addr => addr,
+        };
+        let mut client = Self::connect(&cfg, addr)?;
+        print!("{}", client.cluster_status()?);
+        Ok(())
+    }
+
+    /// Scrubs the local node's stored data, displaying the result.
+    fn scrub(cfg: Config, repair: bool) -> Result<()> {
+        let addr = match cfg.advertise_sql.as_str() {
+            "" => cfg.listen_sql.as_str(),
+            addr => addr,
+        };
+        let mut client = Self::connect(&cfg, addr)?;
+        let scrub = client.scrub(repair)?;
+        print!("{scrub}");
+        if !scrub.issues.is_empty() && !scrub.repaired {
+            return errdata!("found {} inconsistencies", scrub.issues.len());
+        }
+        Ok(())
+    }
+
+    /// Checks replica consistency via the local node, displaying the result.
+    fn check_consistency(cfg: Config) -> Result<()> {
+        let addr = match cfg.advertise_sql.as_str() {
+            "" => cfg.listen_sql.as_str(),
+            addr => addr,
+        };
+        let mut client = Self::connect(&cfg, addr)?;
+        let check = client.check_consistency()?;
+        print!("{check}");
+        if !check.is_consistent() {
+            return errdata!("consistency check failed");
+        }
+        Ok(())
+    }
+
+    /// Connects to the given SQL address, using TLS if enabled, and
+    /// authenticates as admin if authentication is enabled.
+    fn connect(cfg: &Config, addr: &str) -> Result<Client> {
+        let mut client = match cfg.tls_cert.as_str() {
+            "" => Client::connect(addr)?,
+            _ => {
+                let Some((host, port)) = addr.rsplit_once(':') else {
+                    return errinput!("invalid SQL address {addr}");
+                };
+                let host = host.trim_start_matches('[').trim_end_matches(']');
+                Client::connect_tls(host, port.parse()?, &cfg.tls_ca)?
+            }
+        };
+        if !cfg.admin_password.is_empty() {
+            client.authenticate(User::ADMIN, &cfg.admin_password)?;
+        }
+        Ok(client)
+    }
+
+    /// Decommissions the given node: transfers leadership away from it,
+    /// removes it from the cluster, and waits for the removal to replicate to
+    /// all remaining nodes. If it's the local node, its data directory is
+    /// retired. It's safe to rerun if interrupted.
+    ///
+    /// Requests are sent via another node, since the cluster ignores the
+    /// removed node. They're forwarded to the Raft leader, and retried on
+    /// Error::Abort or Error::NotLeader, e.g. during leader elections.
+    fn decommission(cfg: Config, node: raft::NodeID) -> Result<()> {
+        if node != cfg.id && !cfg.peers.contains_key(&node) {
+            return errinput!("unknown node {node}");
+        }
+        let local_sql = match cfg.advertise_sql.as_str() {
+            "" => cfg.listen_sql.clone(),
+            addr => addr.to_string(),
+        };
+        let addrs = cfg
+            .peers_sql
+            .iter()
+            .map(|(id, addr)| (*id, addr.clone()))
+            .chain(std::iter::once((cfg.id, local_sql)))
+            .filter(|(id, _)| *id != node)
+            .sorted()
+            .collect_vec();
+        let mut client = None;
+        for (id, addr) in &addrs {
+            match Self::connect(&cfg, addr) {
+                Ok(c) => {
+                    client = Some(c);
+                    break;
+                }
+                Err(err) => warn!("Failed connecting to node {id} at {addr}: {err}"),
+            }
+        }
+        let Some(mut client) = client else {
+            return errinput!("no other cluster node is reachable");
+        };
+
+        // Transfer leadership away from the node and remove it. If it's no
+        // longer a member, a previous run removed it, at or before the current
+        // commit index.
+        let deadline = Instant::now() + DECOMMISSION_TIMEOUT;
+        let index = loop {
+            if Instant::now() >= deadline {
+                return errinput!("timed out removing node {node}");
+            }
+            let status = match client.status() {
+                Ok(status) => status,
+                Err(Error::Abort | Error::NotLeader(_)) => {
+                    std::thread::sleep(DECOMMISSION_POLL_INTERVAL);
+                    continue;
+                }
+                Err(err) => return Err(err),
+            };
+            if !status.raft.match_index.contains_key(&node) {
+                println!("Node {node} is not a cluster member");
+                break status.raft.commit_index;
+            }
+            if status.raft.leader == node {
+                match client.transfer_leadership() {
+                    Ok(to) => println!("Transferred leadership from node {node} to node {to}"),
+                    Err(Error::Abort) => {} // no follower caught up yet
+                    Err(err) => return Err(err),
+                }
+            } else {
+                match client.remove_node(node) {
+                    Ok(index) => {
+                        println!("Removed node {node} from the cluster at Raft index {index}");
+                        break index;
+                    }
+                    Err(Error::Abort | Error::NotLeader(_)) => {} // leader change, or catching up
+                    Err(err) => return Err(err),
+                }
+            }
+            std::thread::sleep(DECOMMISSION_POLL_INTERVAL);
+        };
+
+        // Wait for the removal to replicate to all remaining nodes, so they
+        // keep ignoring the node even if they become leader.
+        loop {
+            match client.status() {
+                Ok(status) if status.raft.match_index.values().all(|i| *i >= index) => break,
+                Ok(_) | Err(Error::Abort | Error::NotLeader(_)) => {}
+                Err(err) => return Err(err),
+            }
+            if Instant::now() >= deadline {
+                return errinput!("timed out replicating removal of node {node}");
+            }
+            std::thread::sleep(DECOMMISSION_POLL_INTERVAL);
+        }
+        println!("Removal replicated to all remaining nodes");
+
+        // Retire the data directory, if it's ours.
+        if node != cfg.id {
+            println!(
+                "Stop node {node}, and run toydb decommission {node} on it to retire its data"
+            );
+            return Ok(());
+        }
+        let path = Path::new(&cfg.data_dir).join(RETIRED_FILE);
+        std::fs::create_dir_all(&cfg.data_dir)?;
+        std::fs::write(&path, format!("node {node} removed at Raft index {index}\n"))?;
+        println!(
+            "Retired data directory {}. Stop the node to complete the decommission",
+            cfg.data_dir
+        );
+        Ok(())
+    }
+
+    /// Restores a backup to a point in time, writing it to a new data
+    /// directory. Incremental backups are replayed in order, followed by the
+    /// node's Raft log if it exists. If the log has been truncated past the
+    /// backup, incremental backups must cover the truncated entries. See
+    /// State::restore for details.
+    fn restore(
+        cfg: Config,
+        backup: &str,
+        incremental: &[String],
+        as_of: Option<u64>,
+        to: &str,
+    ) -> Result<()> {
+        if !matches!(cfg.storage_raft.as_str(), "bitcask" | "" | "segmented") {
+            return errinput!("can't restore from {} Raft log", cfg.storage_raft);
+        }
+        let (backup, raft_path) = (Path::new(backup), Path::new(&cfg.data_dir).join("raft"));
+        for path in std::iter::once(backup).chain(incremental.iter().map(Path::new)) {
+            if !path.exists() {
+                return errinput!("backup file {} does not exist", path.display());
+            }
+        }
+        if !raft_path.exists() && incremental.is_empty() {
+            return errinput!("Raft log {} does not exist", raft_path.display());
+        }
+        let sql_path = Path::new(to).join("sql");
+        if sql_path.exists() {
+            return errinput!("SQL database {} already exists", sql_path.display());
+        }
+
+        // Copy the backup into a temporary file in the new data directory, and
+        // replay the incremental backups and log. Only move it into place once
+        // the restore succeeds.
+        let mut logs = Vec::new();
+        for path in incremental {
+            logs.push(raft::Log::new(Box::new(storage::BitCask::new(path.into())?))?);
+        }
+        if raft_path.exists() {
+            let engine: Box<dyn storage::Engine> = match cfg.storage_raft.as_str() {
+                "segmented" => Box::new(storage::Segmented::new(
+                    raft_path,
+                    cfg.raft_segment_size,
+                    cfg.raft_retain_segments,
+                )?),
+                _ => Box::new(storage::BitCask::new(raft_path)?),
+            };
+            logs.push(raft::Log::new(engine)?);
+        }
+        let mut source = storage::BitCask::new(backup.to_path_buf())?;
+        std::fs::create_dir_all(to)?;
+        let tmp_path = sql_path.with_extension("restore");
+        let mut engine = storage::BitCask::new(tmp_path.clone())?;
+        let mut scan = source.scan(..);
+        while let Some((key, value)) = scan.next().transpose()? {
+            engine.set(&key, value)?;
+        }
+        let mut state = sql::engine::Raft::new_state(engine)?;
+        let from = state.get_applied_index();
+        let result = state.restore(&mut logs, as_of);
+        drop(state); // flushes the file
+        let index = match result {
+            Ok(index) => index,
+            Err(error) => {
+                std::fs::remove_file(tmp_path)?;
+                return Err(error);
+            }
+        };
+        std::fs::rename(tmp_path, sql_path)?;
+        println!("Restored backup at Raft index {from} to index {index} in {to}");
+        Ok(())
+    }
+
+    /// Runs the toyDB server.
+    fn serve(cfg: Config, flags: Flags) -> Result<()> {
+        // Refuse to start with a decommissioned node's data directory.
+        if Path::new(&cfg.data_dir).join(RETIRED_FILE).exists() {
+            return errinput!("node {} was decommissioned, its data directory is retired", cfg.id);
+        }
+
+        // Initialize logging. The filter can be changed on reload.
+        logging::init(cfg.log_format.parse()?, cfg.log_level.parse()?)?;
+        logging::set_node(cfg.id);
+
+        // Initialize trace export, if enabled.
+        if !cfg.trace_endpoint.is_empty() {
+            trace::init(&cfg.trace_endpoint, cfg.trace_sample_rate, cfg.id)?;
+        }
+
+        // Enable fault injection into the storage engines and Raft transport,
+        // if requested. This is only used for chaos testing.
+        let faults = cfg.fault_injection.then(FaultInjector::new);
+        if faults.is_some() {
+            warn!("Fault injection is enabled, this node is not safe for production use");
+        }
+
+        // Initialize the Raft log storage engine.
+        let datadir = Path::new(&cfg.data_dir);
+        let mut raft_log = match cfg.storage_raft.as_str() {
+            "bitcask" | "" => {
+                let engine = storage::BitCask::new_maybe_compact(
+                    datadir.join("raft"),
+                    cfg.compact_threshold,
+                    cfg.compact_min_bytes,
+                )?;
+                raft::Log::new(Self::faulty(engine, faults.as_ref()))?
+            }
+            "segmented" => {
+                let engine = storage::Segmented::new(
+                    datadir.join("raft"),
+                    cfg.raft_segment_size,
+                    cfg.raft_retain_segments,
+                )?;
+                raft::Log::new(Self::faulty(engine, faults.as_ref()))?
+            }
+            "memory" => raft::Log::new(Self::faulty(storage::Memory::new(), faults.as_ref()))?,
+            name => return errinput!("invalid Raft storage engine {name}"),
+        };
+        raft_log.enable_fsync(cfg.fsync);
+
+        // Initialize the SQL storage engine.
+        let (raft_state, lock_waits) = match cfg.storage_sql.as_str() {
+            "bitcask" | "" => {
+                let engine = storage::BitCask::new_maybe_compact(
+                    datadir.join("sql"),
+                    cfg.compact_threshold,
+                    cfg.compact_min_bytes,
+                )?;
+                Self::sql_state(engine, faults.as_ref())?
+            }
+            "memory" => Self::sql_state(storage::Memory::new(), faults.as_ref())?,
+            name => return errinput!("invalid SQL storage engine {name}"),
+        };
+
+        // Load the TLS configuration, if enabled.
+        let tls = match (cfg.tls_cert.as_str(), cfg.tls_key.as_str(), cfg.tls_ca.as_str()) {
+            ("", _, _) => None,
+            (cert, key, ca) => Some(toydb::tls::Config::load(
+                Path::new(cert),
+                Path::new(key),
+                Path::new(ca),
+                cfg.tls_peer_auth,
+            )?),
+        };
+
+        // Start the server.
+        let mut sql_addrs = cfg.peers_sql.clone();
+        let advertise_sql = match cfg.advertise_sql.as_str() {
+            "" => cfg.listen_sql.clone(),
+            addr => addr.to_string(),
+        };
+        sql_addrs.insert(cfg.id, advertise_sql);
+        let mut raft_opts = raft::Options {
+            learners: cfg.learners.iter().copied().collect(),
+            truncate_log: cfg.storage_raft == "segmented",
+            ..raft::Options::default()
+        };
+        if cfg.group_commit_window > 0 {
+            raft_opts.group_commit_bytes = cfg.group_commit_bytes;
+        }
+        let mut server = Server::new(cfg.id, cfg.peers.clone(), raft_log, raft_state, raft_opts)?;
+        server.set_group_commit_window(
+            Some(cfg.group_commit_window).filter(|w| *w > 0).map(Duration::from_millis),
+        );
+        server.set_sql_addrs(sql_addrs);
+        let nonempty = |s: &String| Some(s.clone()).filter(|s| !s.is_empty());
+        server.set_listen_pg(nonempty(&cfg.listen_pg));
+        server.set_listen_http(nonempty(&cfg.listen_http));
+        server.set_ready_max_lag(cfg.ready_max_lag);
+        server.set_data_dir(Some(datadir.to_path_buf()));
+        server.set_listen_grpc(nonempty(&cfg.listen_grpc));
+        server.set_listen_unix(nonempty(&cfg.listen_unix).map(Into::into));
+        let unix_socket_mode = u32::from_str_radix(&cfg.unix_socket_mode, 8)?;
+        server.set_unix_socket_mode(unix_socket_mode);
+        server.set_tls(tls);
+        server.set_admin_password(nonempty(&cfg.admin_password));
+        server.set_file_dir(Some(datadir.to_path_buf()));
+        server.set_result_cache_size(Some(cfg.result_cache_size).filter(|size| *size > 0));
+        server.set_plan_cache_size(Some(cfg.plan_cache_size).filter(|size| *size > 0));
+        if cfg.slow_query_threshold > 0 {
+            server.set_slow_query_log(Some(sql::engine::SlowQueryLog::open(
+                datadir.join(&cfg.slow_query_log),
+                Duration::from_millis(cfg.slow_query_threshold),
+                cfg.slow_query_log_size,
+            )?));
+        }
+        if !cfg.audit_log.is_empty() {
+            server.set_audit_log(Some(sql::engine::AuditLog::open(
+                datadir.join(&cfg.audit_log),
+                cfg.audit_tables.clone(),
+            )?));
+        }
+        server.set_frame_size(cfg.frame_size);
+        server.set_gc_retention(cfg.gc_retention);
+        server.set_gc_interval(Some(cfg.gc_interval).filter(|i| *i > 0).map(Duration::from_secs));
+        server.set_consistency_check_interval(
+            Some(cfg.consistency_check_interval).filter(|i| *i > 0).map(Duration::from_secs),
+        );
+        server.set_ttl_purge_interval(
+            Some(cfg.ttl_purge_interval).filter(|i| *i > 0).map(Duration::from_secs),
+        );
+        server.set_lock_waits(Some(lock_waits));
+        server.set_intent_timeout(
+            Some(cfg.intent_timeout).filter(|i| *i > 0).map(Duration::from_secs),
+        );
+        server.set_shutdown_grace(Duration::from_secs(cfg.shutdown_grace));
+        server.set_fault_injector(faults);
+        let settings = server.settings_handle();
+        settings.set(cfg.settings());
+        let (listen_raft, listen_sql) = (cfg.listen_raft.clone(), cfg.listen_sql.clone());
+        let reloader = Reloader { flags, config: cfg, settings };
+        handle_signals(server.shutdown_handle(), reloader);
+        server.serve(listen_raft, listen_sql)
+    }
+
+    /// Boxes a Raft log storage engine, injecting faults if given.
+    fn faulty<E: storage::Engine + 'static>(
+        engine: E,
+        faults: Option<&FaultInjector>,
+    ) -> Box<dyn storage::Engine> {
+        match faults {
+            Some(faults) => Box::new(storage::Faulty::new(engine, faults.clone())),
+            None => Box::new(engine),
+        }
+    }
+
+    /// Creates the SQL state machine with a storage engine, injecting faults
+    /// if given. Also returns its lock waiter wakeups.
+    fn sql_state<E: storage::Engine + 'static>(
+        engine: E,
+        faults: Option<&FaultInjector>,
+    ) -> Result<(Box<dyn raft::State>, Arc<storage::mvcc::LockWaits>)> {
+        Ok(match faults {
+            Some(faults) => {
+                let state =
+                    sql::engine::Raft::new_state(storage::Faulty::new(engine, faults.clone()))?;
+                let lock_waits = state.lock_waits();
+                (Box::new(state), lock_waits)
+            }
+            None => {
+                let state = sql::engine::Raft::new_state(engine)?;
+                let lock_waits = state.lock_waits();
+                (Box::new(state), lock_waits)
+            }
+        })
+    }
+}
+
+/// Reloads the config file, applying changes to the log level and server
+/// settings. Flags and environment variables still override the file.
+struct Reloader {
+    /// The command-line flags and environment variables.
+    flags: Flags,
+    /// The current config.
+    config: Config,
+    /// Changes the server settings.
+    settings: SettingsHandle,
+}
+
+impl Reloader {
+    /// Reloads the config file. If it's invalid, the current config is kept.
+    fn reload(&mut self) -> Result<()> {
+        let config = self.flags.load()?;
+        config.validate()?;
+        logging::set_filter(config.log_level.parse()?)?;
+        self.settings.set(config.settings());
+        if config.requires_restart(&self.config) {
+            warn!(
+                "Only the log level, limits, and timeouts can be reloaded, restart to apply other changes"
+            );
+        }
+        info!("Reloaded config file {}", self.flags.file);
+        self.config = config;
+        Ok(())
+    }
+}
+
+/// Set by the SIGTERM and SIGINT handler.
+static TERMINATED: AtomicBool = AtomicBool::new(false);
+
+/// Set by the SIGHUP handler, and cleared when reloading.
+static RELOAD: AtomicBool = AtomicBool::new(false);
+
+/// How often to check for signals.
+const SIGNAL_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Shuts down the server gracefully on SIGTERM or SIGINT (Ctrl-C), and reloads
+/// the config file on SIGHUP.
+///
+/// The signal handlers can only do async-signal-safe operations, so they set
+/// flags that a separate thread polls and acts on.
+fn handle_signals(shutdown: ShutdownHandle, mut reloader: Reloader) {
+    extern "C" fn handle_terminate(_: libc::c_int) {
+        TERMINATED.store(true, Ordering::Relaxed);
+    }
+    extern "C" fn handle_reload(_: libc::c_int) {
+        RELOAD.store(true, Ordering::Relaxed);
+    }
+
+    // SAFETY: the handlers only store to atomics, which is signal-safe.
+    unsafe {
+        libc::signal(libc::SIGTERM, handle_terminate as libc::sighandler_t);
+        libc::signal(libc::SIGINT, handle_terminate as libc::sighandler_t);
+        libc::signal(libc::SIGHUP, handle_reload as libc::sighandler_t);
+    }
+    std::thread::spawn(move || {
+        while !TERMINATED.load(Ordering::Relaxed) {
+            if RELOAD.swap(false, Ordering::Relaxed) {
+                if let Err(error) = reloader.reload() {
+                    error!("Failed to reload config file {}: {error}", reloader.flags.file);
+                }
+            }
+            std::thread::sleep(SIGNAL_POLL_INTERVAL);
+        }
+        shutdown.shutdown();
+    });
+}
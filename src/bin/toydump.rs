@@ -1,6 +1,7 @@
 //! toydump is a debug tool that prints a toyDB BitCask database in
 //! human-readable form. It can print both the SQL database and the Raft log
-//! (via --raft). It only outputs live BitCask data, not garbage entries.
+//! (via --raft). It only outputs live BitCask data, not garbage entries. A
+//! directory is dumped as a segmented Raft log.
 
 #![warn(clippy::all)]
 
@@ -8,7 +9,7 @@ use clap::Parser as _;
 
 use toydb::encoding::format::{self, Formatter as _};
 use toydb::error::Result;
-use toydb::storage::{BitCask, Engine as _};
+use toydb::storage::{BitCask, Engine, Segmented};
 
 fn main() {
     if let Err(error) = Command::parse().run() {
@@ -20,7 +21,8 @@ fn main() {
 #[derive(clap::Parser)]
 #[command(about = "Prints toyDB file contents.", version, propagate_version = true)]
 struct Command {
-    /// The BitCask file to dump (SQL database unless --raft).
+    /// The BitCask file or segmented log directory to dump (SQL database
+    /// unless --raft).
     file: String,
     /// The file is a Raft log, not SQL database.
     #[arg(long)]
@@ -33,8 +35,12 @@ struct Command {
 impl Command {
     /// Runs the command.
     fn run(self) -> Result<()> {
-        let mut engine = BitCask::new(self.file.into())?;
-        let mut scan = engine.scan(..);
+        let path = std::path::PathBuf::from(self.file);
+        let mut engine: Box<dyn Engine> = match path.is_dir() {
+            true => Box::new(Segmented::new(path, u64::MAX, 0)?),
+            false => Box::new(BitCask::new(path)?),
+        };
+        let mut scan = engine.scan_dyn((std::ops::Bound::Unbounded, std::ops::Bound::Unbounded));
         while let Some((key, value)) = scan.next().transpose()? {
             let mut string = match self.raft {
                 true => format::Raft::<format::SQLCommand>::key_value(&key, &value),
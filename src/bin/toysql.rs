@@ -1,28 +1,61 @@
 //! toySQL is a command-line client for toyDB. It connects to a toyDB node
 //! (default localhost:9601) and executes SQL statements against it via an
 //! interactive shell interface. Command history is stored in .toysql.history.
+//!
+//! Statements can span multiple lines, and are executed once terminated by a
+//! semicolon. Tab completes SQL keywords and the table and column names in the
+//! catalog. Psql-style \ meta commands are also supported, e.g. \d to list
+//! tables.
+//!
+//! Query results are output as an aligned table, CSV, or JSON lines, selected
+//! via --format or \format, and can be written to a file via --output or \o.
+//! Together with a statement argument, this allows exporting query results in
+//! shell pipelines, e.g. toysql --format csv --headers "SELECT * FROM t".
+//!
+//! COPY ... FROM STDIN statements import CSV or JSON data from toySQL's stdin,
+//! e.g. toysql "COPY t FROM STDIN HEADER" < t.csv, while \copy imports a
+//! local file.
+//!
+//! !snapshot pins a consistent snapshot across statements by beginning a
+//! read-only transaction, optionally AS OF SYSTEM TIME a past version, e.g. for
+//! reports made up of several queries. The prompt shows the pinned version.
+//!
+//! With --migrate, toySQL applies the pending schema migrations in a directory
+//! of VERSION_NAME.sql files and exits, see Client::migrate().
 
 #![warn(clippy::all)]
 
-use std::path::PathBuf;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufWriter, Write as _};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
 
 use clap::Parser as _;
 use itertools::Itertools as _;
+use rustyline::completion::{Completer, Pair};
 use rustyline::error::ReadlineError;
 use rustyline::history::DefaultHistory;
 use rustyline::validate::{ValidationContext, ValidationResult, Validator};
-use rustyline::{Editor, Modifiers};
-use rustyline_derive::{Completer, Helper, Highlighter, Hinter};
+use rustyline::{CompletionType, Editor, Modifiers};
+use rustyline_derive::{Helper, Highlighter, Hinter};
 
 use toydb::Client;
-use toydb::errinput;
+use toydb::client::{CancelHandle, Migration};
+use toydb::encoding::{csv, json};
 use toydb::error::Result;
 use toydb::sql::engine::StatementResult;
-use toydb::sql::parser::{Lexer, Token};
+use toydb::sql::execution::Returning;
+use toydb::sql::parser::ast::{ImportSource, Statement};
+use toydb::sql::parser::{Keyword, Lexer, Parser, Token};
+use toydb::sql::types::{Label, Row, format_ident};
+use toydb::{errdata, errinput};
 
 fn main() {
     if let Err(error) = Command::parse().run() {
         eprintln!("Error: {error}");
+        std::process::exit(1);
     }
 }
 
@@ -39,12 +72,55 @@ struct Command {
     /// Port number to connect to.
     #[arg(short = 'p', long, default_value = "9601")]
     port: u16,
+    /// Connect via the Unix socket at this path, instead of host and port.
+    #[arg(short = 'S', long, conflicts_with_all = ["host", "port", "tls_ca"])]
+    socket: Option<PathBuf>,
+    /// Connect using TLS, verifying the server certificate against the CA
+    /// certificates in this PEM file.
+    #[arg(long)]
+    tls_ca: Option<PathBuf>,
+    /// User to authenticate as, if the server requires authentication.
+    #[arg(short = 'U', long)]
+    user: Option<String>,
+    /// Password to authenticate with.
+    #[arg(long, requires = "user")]
+    password: Option<String>,
+    /// The query result output format.
+    #[arg(short = 'f', long, value_enum, default_value_t = Format::Table)]
+    format: Format,
+    /// Display column headers.
+    #[arg(long)]
+    headers: bool,
+    /// Write query results to this file instead of stdout.
+    #[arg(short = 'o', long)]
+    output: Option<PathBuf>,
+    /// Apply the pending schema migrations in this directory, then exit.
+    /// Migrations are .sql files named VERSION_NAME.sql, e.g.
+    /// 001_create_users.sql, applied in version order.
+    #[arg(long, conflicts_with = "statement")]
+    migrate: Option<PathBuf>,
 }
 
 impl Command {
     /// Runs the command.
     fn run(self) -> Result<()> {
-        let mut shell = Shell::new(&self.host, self.port)?;
+        let client = match (&self.socket, &self.tls_ca) {
+            (Some(socket), _) => Client::connect_unix(socket)?,
+            (None, Some(ca)) => Client::connect_tls(&self.host, self.port, ca)?,
+            (None, None) => Client::connect((self.host.as_str(), self.port))?,
+        };
+        let mut shell = Shell::new(client)?;
+        shell.format = self.format;
+        shell.show_headers = self.headers;
+        if let Some(output) = &self.output {
+            shell.set_output(Some(output))?;
+        }
+        if let Some(user) = &self.user {
+            shell.client.authenticate(user, self.password.as_deref().unwrap_or_default())?;
+        }
+        if let Some(dir) = &self.migrate {
+            return shell.migrate(dir);
+        }
         match self.statement {
             Some(statement) => shell.execute(&statement),
             None => shell.run(),
@@ -57,33 +133,69 @@ struct Shell {
     /// The toyDB client.
     client: Client,
     /// The Rustyline command editor.
-    editor: Editor<InputValidator, DefaultHistory>,
+    editor: Editor<InputHelper, DefaultHistory>,
     /// The path to the history file, if any.
     history_path: Option<PathBuf>,
     /// If true, SELECT column headers will be displayed.
     show_headers: bool,
+    /// The query result output format.
+    format: Format,
+    /// The file to write query results to, if any. Otherwise, stdout is used.
+    output: Option<(PathBuf, BufWriter<File>)>,
+    /// If true, the shell should exit (i.e. \q was given).
+    quit: bool,
 }
 
 impl Shell {
-    /// Creates a new shell connected to the given server.
-    fn new(host: &str, port: u16) -> Result<Self> {
-        let client = Client::connect((host, port))?;
+    /// Creates a new shell using the given client.
+    fn new(client: Client) -> Result<Self> {
         // Set up Rustyline. Make sure multiline pastes are handled normally.
-        let mut editor = Editor::new()?;
-        editor.set_helper(Some(InputValidator));
+        // Consecutive duplicates and lines starting with a space aren't added
+        // to the history.
+        let config = rustyline::Config::builder()
+            .completion_type(CompletionType::List)
+            .history_ignore_dups(true)?
+            .history_ignore_space(true)
+            .max_history_size(MAX_HISTORY)?
+            .build();
+        let mut editor = Editor::with_config(config)?;
+        editor.set_helper(Some(InputHelper::default()));
         editor.bind_sequence(
             rustyline::KeyEvent(rustyline::KeyCode::BracketedPasteStart, Modifiers::NONE),
             rustyline::Cmd::Noop,
         );
         let history_path =
             std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".toysql.history"));
-        Ok(Self { client, editor, history_path, show_headers: false })
+        Ok(Self {
+            client,
+            editor,
+            history_path,
+            show_headers: false,
+            format: Format::Table,
+            output: None,
+            quit: false,
+        })
+    }
+
+    /// Applies the pending schema migrations in the given directory.
+    fn migrate(&mut self, dir: &Path) -> Result<()> {
+        let migrations = Migration::read_dir(dir)?;
+        let applied = self.client.migrate(&migrations)?;
+        for migration in migrations.iter().filter(|m| applied.contains(&m.version)) {
+            println!("Applied migration {} {}", migration.version, migration.name);
+        }
+        if applied.is_empty() {
+            println!("No pending migrations");
+        }
+        Ok(())
     }
 
-    /// Executes a SQL statement or ! command.
+    /// Executes a SQL statement, ! command, or \ meta command.
     fn execute(&mut self, input: &str) -> Result<()> {
         if input.starts_with('!') {
             self.execute_command(input)
+        } else if input.starts_with('\\') {
+            self.execute_meta(input)
         } else if !input.is_empty() {
             self.execute_sql(input)
         } else {
@@ -100,6 +212,28 @@ impl Shell {
         let args = input.collect_vec();
 
         match (command, args.as_slice()) {
+            // Takes a full backup of the database.
+            ("!backup", [file]) => {
+                let index = self.client.backup(file)?;
+                println!("Backed up to {file} at Raft index {index}")
+            }
+            // Takes an incremental backup since a previous backup's index.
+            ("!backup", [file, since]) => {
+                let since = since.parse().or_else(|_| errinput!("invalid Raft index {since}"))?;
+                let index = self.client.backup_incremental(file, since)?;
+                println!("Backed up incrementally to {file} from Raft index {since} to {index}")
+            }
+            ("!backup", _) => {
+                return errinput!("!backup takes a file path and optional Raft index");
+            }
+
+            // Garbage collects old MVCC versions.
+            ("!gc", []) => {
+                let gc = self.client.gc()?;
+                println!("Garbage collected {} versions, horizon at {}", gc.versions, gc.horizon)
+            }
+            ("!gc", _) => return errinput!("!gc takes no arguments"),
+
             // Toggles column headers.
             ("!headers", []) => {
                 self.show_headers = !self.show_headers;
@@ -114,17 +248,67 @@ impl Shell {
             ("!help", []) => println!(
                 r#"
 Enter a SQL statement terminated by a semicolon (;) to execute it, or Ctrl-D to
-exit. The following commands are also available:
+exit. Ctrl-C cancels the executing statement. The following commands are also
+available:
 
+    !backup FILE [INDEX]
+                       Back up the database to a file, incrementally since
+                       a previous backup at the Raft log index if given
+    !gc                Garbage collect old MVCC versions
     !headers           Toggles column headers
     !help              This help message
-    !status            Display server status
-    !table NAME        Display a table schema
-    !tables            List tables
+    !snapshot [VERSION]
+                       Pin a snapshot at the latest or given version for
+                       subsequent statements, until COMMIT or !snapshot off
+    !status            Display server and cluster status
+
+    \d, \dt            List tables
+    \copy TABLE FROM 'FILE' [FORMAT CSV|JSON] [HEADER]
+                       Import a local file into a table, like COPY
+    \d TABLE           Display a table's schema
+    \format [FORMAT]   Display or set the output format: table, csv, or json
+    \o [FILE]          Write query results to a file, or stdout if omitted
+    \?                 This help message
+    \q                 Exit the shell
+
+Schemas can be inspected with SHOW TABLES, SHOW CREATE TABLE, and DESCRIBE.
+Tab completes SQL keywords and table and column names.
 "#
             ),
             ("!help", _) => return errinput!("!help takes no arguments"),
 
+            // Pins a consistent snapshot for subsequent statements, by
+            // beginning a read-only transaction at the latest or given
+            // version, or releases it.
+            ("!snapshot", ["off"]) => match self.client.txn() {
+                Some(txn) if txn.read_only => {
+                    let version = txn.version;
+                    self.client.execute("ROLLBACK")?;
+                    println!("Released snapshot at version {version}")
+                }
+                _ => return errinput!("no snapshot pinned"),
+            },
+            ("!snapshot", [] | [_]) => {
+                if let Some(txn) = self.client.txn() {
+                    return errinput!("already in transaction {}", txn.version);
+                }
+                let statement = match args.first() {
+                    Some(version) => {
+                        let version: u64 =
+                            version.parse().or_else(|_| errinput!("invalid version {version}"))?;
+                        format!("BEGIN READ ONLY AS OF SYSTEM TIME {version}")
+                    }
+                    None => "BEGIN READ ONLY".to_string(),
+                };
+                match self.client.execute(&statement)? {
+                    StatementResult::Begin(state) => {
+                        println!("Pinned snapshot at version {}", state.version)
+                    }
+                    result => return errdata!("unexpected result {result:?}"),
+                }
+            }
+            ("!snapshot", _) => return errinput!("!snapshot takes a version or off"),
+
             // Displays server status.
             ("!status", []) => {
                 let status = self.client.status()?;
@@ -134,7 +318,7 @@ Server:       n{server} with Raft leader n{leader} in term {term} for {nodes} no
 Raft log:     {committed} committed, {applied} applied, {raft_size} MB, {raft_garbage}% garbage ({raft_storage} engine)
 Replication:  {raft_match}
 SQL storage:  {sql_keys} keys, {sql_size} MB logical, {nodes}x {sql_disk_size} MB disk, {sql_garbage}% garbage ({sql_storage} engine)
-Transactions: {active_txns} active, {versions} total
+Transactions: {active_txns} active, {versions} total, GC horizon at {gc_horizon}
 "#,
                     server = status.server,
                     leader = status.raft.leader,
@@ -157,47 +341,275 @@ Transactions: {active_txns} active, {versions} total
                     sql_storage = status.mvcc.storage.name,
                     active_txns = status.mvcc.active_txns,
                     versions = status.mvcc.versions,
-                )
+                    gc_horizon = status.mvcc.gc_horizon,
+                );
+                // Cluster status requires superuser privileges, and a server
+                // that supports it.
+                match self.client.cluster_status() {
+                    Ok(cluster) => println!("{cluster}"),
+                    Err(error) => println!("Cluster status unavailable: {error}\n"),
+                }
             }
             ("!status", _) => return errinput!("!status takes no arguments"),
 
-            ("!table", [name]) => println!("{}", self.client.get_table(name)?),
-            ("!table", _) => return errinput!("!table takes 1 argument"),
+            (command, _) => return errinput!("unknown command {command}"),
+        }
+        Ok(())
+    }
+
+    /// Executes a psql-style \ meta command (e.g. \d).
+    fn execute_meta(&mut self, input: &str) -> Result<()> {
+        // \copy takes a COPY statement, so it can't be split into arguments.
+        if let Some(copy) = input.strip_prefix("\\copy") {
+            if copy.is_empty() || copy.starts_with(char::is_whitespace) {
+                return self.execute_copy(copy);
+            }
+        }
+        let mut input = input.trim_end_matches(';').split_ascii_whitespace();
+        let Some(command) = input.next() else {
+            return errinput!("expected command");
+        };
+        let args = input.collect_vec();
+
+        match (command, args.as_slice()) {
+            // Lists tables.
+            ("\\d" | "\\dt", []) => {
+                self.refresh_completions();
+                for table in self.client.list_tables()? {
+                    println!("{table}");
+                }
+            }
 
-            ("!tables", []) => self.client.list_tables()?.iter().for_each(|t| println!("{t}")),
-            ("!tables", _) => return errinput!("!tables takes no arguments"),
+            // Displays a table's schema.
+            ("\\d", [table]) => println!("{}", self.client.get_table(table)?),
+            ("\\d" | "\\dt", _) => return errinput!("{command} takes at most one table name"),
 
-            (command, _) => return errinput!("unknown command {command}"),
+            // Displays or sets the output format.
+            ("\\format", []) => println!("Output format is {}", self.format),
+            ("\\format", [format]) => {
+                self.format = clap::ValueEnum::from_str(format, true).or_else(|_| {
+                    errinput!("invalid format {format}, expected table, csv, or json")
+                })?;
+                println!("Output format is {}", self.format)
+            }
+            ("\\format", _) => return errinput!("\\format takes at most one format"),
+
+            // Redirects query results to a file, or back to stdout.
+            ("\\o", []) => {
+                self.set_output(None)?;
+                println!("Writing query results to stdout")
+            }
+            ("\\o", [file]) => {
+                self.set_output(Some(file.as_ref()))?;
+                println!("Writing query results to {file}")
+            }
+            ("\\o", _) => return errinput!("\\o takes at most one file path"),
+
+            // Displays help.
+            ("\\?", []) => self.execute_command("!help")?,
+            ("\\?", _) => return errinput!("\\? takes no arguments"),
+
+            // Exits the shell.
+            ("\\q", []) => self.quit = true,
+            ("\\q", _) => return errinput!("\\q takes no arguments"),
+
+            (command, _) => return errinput!("unknown command {command}, try \\?"),
         }
         Ok(())
     }
 
-    /// Executes a SQL statement and displays the results.
+    /// Imports a local file via COPY FROM STDIN, given the rest of a \copy
+    /// command, e.g. \copy t (a, b) FROM 'file.csv' HEADER.
+    fn execute_copy(&mut self, copy: &str) -> Result<()> {
+        let copy = format!("COPY {}", copy.trim().trim_end_matches(';'));
+        let (table, columns, path, format, header) = match Parser::new(&copy).parse()? {
+            Statement::Import {
+                table,
+                columns,
+                source: ImportSource::File { path, format, header },
+            } => (table, columns, path, format, header),
+            _ => return errinput!("expected \\copy TABLE [(COLUMNS)] FROM 'FILE'"),
+        };
+        let mut statement = format!("COPY {}", format_ident(&table));
+        if let Some(columns) = columns {
+            statement += &format!(" ({})", columns.iter().map(|c| format_ident(c)).join(", "));
+        }
+        statement += &format!(" FROM STDIN FORMAT {format}");
+        if header {
+            statement += " HEADER";
+        }
+        let file = File::open(&path).or_else(|err| errinput!("can't open file '{path}': {err}"))?;
+        let cancel = self.client.cancel_handle();
+        let result = with_interrupt(cancel, || self.client.copy_from(&statement, file))?;
+        self.print_result(result)
+    }
+
+    /// Sets the file to write query results to, or stdout if None. The file
+    /// is truncated if it exists.
+    fn set_output(&mut self, path: Option<&std::path::Path>) -> Result<()> {
+        if let Some((_, mut file)) = self.output.take() {
+            file.flush()?;
+        }
+        if let Some(path) = path {
+            let file = BufWriter::new(File::create(path)?);
+            self.output = Some((path.to_path_buf(), file));
+        }
+        Ok(())
+    }
+
+    /// Writes query results to the output file or stdout.
+    fn write_output(&mut self, output: &str) -> Result<()> {
+        match &mut self.output {
+            Some((_, file)) => {
+                file.write_all(output.as_bytes())?;
+                file.flush()?;
+            }
+            None => print!("{output}"),
+        }
+        Ok(())
+    }
+
+    /// Formats SELECT result rows in the output format.
+    fn format_rows(&self, columns: &[Label], rows: Vec<Row>) -> Result<String> {
+        let mut output = String::new();
+        match self.format {
+            // Pad each column to its widest value, separated by |.
+            Format::Table => {
+                let mut lines = Vec::new();
+                if self.show_headers {
+                    lines.push(columns.iter().map(|c| c.as_header().to_string()).collect_vec());
+                }
+                for row in rows {
+                    lines.push(row.iter().map(|v| v.to_string()).collect_vec());
+                }
+                let mut widths = vec![0; columns.len()];
+                for line in &lines {
+                    for (width, value) in widths.iter_mut().zip(line) {
+                        *width = (*width).max(value.chars().count());
+                    }
+                }
+                for (i, line) in lines.iter().enumerate() {
+                    let line = line.iter().zip(&widths).map(|(v, w)| format!("{v:w$}")).join(" | ");
+                    output.push_str(line.trim_end());
+                    output.push('\n');
+                    if i == 0 && self.show_headers {
+                        output.push_str(&widths.iter().map(|w| "-".repeat(*w)).join("-+-"));
+                        output.push('\n');
+                    }
+                }
+            }
+            Format::Csv => {
+                if self.show_headers {
+                    output.push_str(&csv::encode_header(columns));
+                    output.push('\n');
+                }
+                for row in rows {
+                    output.push_str(&csv::encode_row(&row));
+                    output.push('\n');
+                }
+            }
+            Format::Json => {
+                for row in rows {
+                    output.push_str(&json::encode_row(columns, row)?);
+                    output.push('\n');
+                }
+            }
+        }
+        Ok(output)
+    }
+
+    /// Refreshes the table and column names used for tab completion. Errors
+    /// are ignored, e.g. if the user can't list tables.
+    fn refresh_completions(&mut self) {
+        let mut tables = BTreeMap::new();
+        for name in self.client.list_tables().unwrap_or_default() {
+            let Ok(table) = self.client.get_table(&name) else { continue };
+            tables.insert(name, table.columns.into_iter().map(|c| c.name).collect());
+        }
+        if let Some(helper) = self.editor.helper_mut() {
+            helper.tables = tables;
+        }
+    }
+
+    /// Executes a SQL statement and displays the results. COPY FROM STDIN
+    /// statements import data from stdin.
     fn execute_sql(&mut self, statement: &str) -> Result<()> {
+        let copy = matches!(
+            Parser::new(statement).parse(),
+            Ok(Statement::Import { source: ImportSource::Stdin { .. }, .. })
+        );
+        let cancel = self.client.cancel_handle();
+        let result = with_interrupt(cancel, || match copy {
+            true => self.client.copy_from(statement, std::io::stdin().lock()),
+            false => self.client.execute(statement),
+        })?;
+        self.print_result(result)
+    }
+
+    /// Displays a statement result.
+    fn print_result(&mut self, mut result: StatementResult) -> Result<()> {
         use StatementResult::*;
-        match self.client.execute(statement)? {
+        // RETURNING rows are displayed before the affected row count.
+        if let Delete { returning, .. } | Insert { returning, .. } | Update { returning, .. } =
+            &mut result
+        {
+            if let Some(Returning { columns, rows }) = returning.take() {
+                let output = self.format_rows(&columns, rows)?;
+                self.write_output(&output)?
+            }
+        }
+        match result {
             Begin(state) => match state.read_only {
                 true => println!("Began read-only transaction at version {}", state.version),
                 false => println!("Began transaction {}", state.version),
             },
             Commit { version } => println!("Committed transaction {version}"),
             Rollback { version } => println!("Rolled back transaction {version}"),
-            Insert { count } => println!("Inserted {count} rows"),
-            Delete { count } => println!("Deleted {count} rows"),
-            Update { count } => println!("Updated {count} rows"),
-            CreateTable { name } => println!("Created table {name}"),
-            DropTable { name, existed } => match existed {
-                true => println!("Dropped table {name}"),
-                false => println!("Table {name} does not exist"),
+            Savepoint { name } => println!("Created savepoint {name}"),
+            RollbackToSavepoint { name } => println!("Rolled back to savepoint {name}"),
+            ReleaseSavepoint { name } => println!("Released savepoint {name}"),
+            AbortTransaction { version } => println!("Aborted transaction {version}"),
+            SetTransaction { isolation } => println!("Set transaction isolation level {isolation}"),
+            Set { name, value } => println!("Set {name} to {value}"),
+            Insert { count, .. } => println!("Inserted {count} rows"),
+            Import { count } => println!("Imported {count} rows"),
+            Export { count } => println!("Exported {count} rows"),
+            Delete { count, .. } => println!("Deleted {count} rows"),
+            Update { count, .. } => println!("Updated {count} rows"),
+            CreateTable { name } => {
+                println!("Created table {name}");
+                self.refresh_completions();
+            }
+            DropTable { name, existed } => {
+                match existed {
+                    true => println!("Dropped table {name}"),
+                    false => println!("Table {name} does not exist"),
+                }
+                self.refresh_completions();
+            }
+            CreateIndex { table, column } => println!("Created index on {table}.{column}"),
+            CreateTrigger { table, name } => println!("Created trigger {name} on {table}"),
+            DropTrigger { table, name, existed } => match existed {
+                true => println!("Dropped trigger {name} on {table}"),
+                false => println!("Trigger {name} does not exist on {table}"),
+            },
+            CreateChangefeed { table } => println!("Created changefeed for {table}"),
+            DropChangefeed { table } => println!("Dropped changefeed for {table}"),
+            DropPartition { table, partition, count } => {
+                println!("Dropped partition {partition} of {table} ({count} rows)")
+            }
+            TruncateTable { name, count } => println!("Truncated table {name} ({count} rows)"),
+            CreateUser { name } => println!("Created user {name}"),
+            DropUser { name, existed } => match existed {
+                true => println!("Dropped user {name}"),
+                false => println!("User {name} does not exist"),
             },
-            Explain(plan) => println!("{plan}"),
+            Grant { user } => println!("Granted privileges to {user}"),
+            Revoke { user } => println!("Revoked privileges from {user}"),
+            Explain(plan) => self.write_output(&format!("{plan}\n"))?,
             Select { columns, rows } => {
-                if self.show_headers {
-                    println!("{}", columns.iter().map(|c| c.as_header()).join(", "));
-                }
-                for row in rows {
-                    println!("{}", row.iter().join(", "));
-                }
+                let output = self.format_rows(&columns, rows)?;
+                self.write_output(&output)?
             }
         }
         Ok(())
@@ -227,9 +639,10 @@ Transactions: {active_txns} active, {versions} total
         // Print welcome message.
         let server = self.client.status()?.server;
         println!("Connected to toyDB node n{server}. Enter !help for instructions.");
+        self.refresh_completions();
 
         // Prompt for commands and execute them.
-        loop {
+        while !self.quit {
             let input = match self.prompt() {
                 Ok(input) => input.trim().to_string(),
                 Err(ReadlineError::Interrupted) => continue,
@@ -250,18 +663,153 @@ Transactions: {active_txns} active, {versions} total
     }
 }
 
-/// A Rustyline helper for multiline editing. After a new line is entered, it
-/// determines whether the input makes up a complete SQL statement that should
-/// be submitted to the server (i.e. it's terminated by ;), or wait for further
-/// input.
-#[derive(Completer, Helper, Highlighter, Hinter)]
-struct InputValidator;
+/// Set by the SIGINT handler, and cleared when the statement is cancelled.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// How often to check for SIGINT while executing a statement.
+const INTERRUPT_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Calls the given closure, cancelling the client's in-flight statement on
+/// SIGINT (Ctrl-C) instead of exiting. Rustyline handles Ctrl-C itself while
+/// reading input, so this is only needed during execution.
+///
+/// The signal handler can only do async-signal-safe operations, so it sets a
+/// flag that a separate thread polls and sends the cancel request.
+fn with_interrupt<T>(cancel: CancelHandle, f: impl FnOnce() -> T) -> T {
+    extern "C" fn handle_sigint(_: libc::c_int) {
+        INTERRUPTED.store(true, Ordering::Relaxed);
+    }
+
+    let done = AtomicBool::new(false);
+    INTERRUPTED.store(false, Ordering::Relaxed);
+    // SAFETY: the handler only stores to an atomic, which is signal-safe.
+    let previous = unsafe { libc::signal(libc::SIGINT, handle_sigint as libc::sighandler_t) };
+    let result = std::thread::scope(|s| {
+        s.spawn(|| {
+            while !done.load(Ordering::Relaxed) {
+                if INTERRUPTED.swap(false, Ordering::Relaxed) {
+                    if let Err(error) = cancel.cancel() {
+                        eprintln!("Error: {error}");
+                    }
+                }
+                std::thread::sleep(INTERRUPT_POLL_INTERVAL);
+            }
+        });
+        let result = f();
+        done.store(true, Ordering::Relaxed);
+        result
+    });
+    // SAFETY: restores the previous handler returned by libc::signal().
+    unsafe { libc::signal(libc::SIGINT, previous) };
+    result
+}
+
+/// A query result output format.
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum Format {
+    /// An aligned table.
+    Table,
+    /// CSV, with a header record if headers are enabled.
+    Csv,
+    /// JSON lines, with one object per row.
+    Json,
+}
+
+impl std::fmt::Display for Format {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Table => "table",
+            Self::Csv => "csv",
+            Self::Json => "json",
+        })
+    }
+}
+
+/// A Rustyline helper for multiline editing and tab completion.
+///
+/// After a new line is entered, it determines whether the input makes up a
+/// complete SQL statement that should be submitted to the server (i.e. it's
+/// terminated by ;), or wait for further input.
+///
+/// On tab, it completes the word before the cursor with matching SQL keywords,
+/// table names, and column names, or the columns of a given table for
+/// table.column. Meta commands are completed at the start of the line.
+#[derive(Default, Helper, Highlighter, Hinter)]
+struct InputHelper {
+    /// Table names and their column names, from the catalog.
+    tables: BTreeMap<String, Vec<String>>,
+}
+
+/// Meta commands, for tab completion.
+const META_COMMANDS: &[&str] = &["\\?", "\\copy", "\\d", "\\dt", "\\format", "\\o", "\\q"];
+
+/// The maximum number of history entries to keep.
+const MAX_HISTORY: usize = 10_000;
+
+impl Completer for InputHelper {
+    type Candidate = Pair;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _: &rustyline::Context,
+    ) -> rustyline::Result<(usize, Vec<Pair>)> {
+        // Find the start of the identifier or meta command before the cursor.
+        let is_word = |c: char| c.is_alphanumeric() || c == '_' || c == '.' || c == '\\';
+        let start = line[..pos]
+            .char_indices()
+            .rev()
+            .find(|(_, c)| !is_word(*c))
+            .map(|(i, c)| i + c.len_utf8())
+            .unwrap_or(0);
+        let word = &line[start..pos];
+        let matches = |name: &str| {
+            name.len() >= word.len()
+                && name.is_char_boundary(word.len())
+                && name[..word.len()].eq_ignore_ascii_case(word)
+        };
+        let pair = |name: String| Pair { display: name.clone(), replacement: name };
+
+        // Meta commands.
+        if word.starts_with('\\') {
+            let commands = match start {
+                0 => META_COMMANDS.iter().filter(|c| matches(c)).map(|c| pair(c.to_string())),
+                _ => return Ok((start, Vec::new())),
+            };
+            return Ok((start, commands.collect()));
+        }
+        if word.is_empty() {
+            return Ok((start, Vec::new()));
+        }
+
+        // Columns of a given table, as table.column.
+        if let Some((table, _)) = word.split_once('.') {
+            let columns = self.tables.get(table).into_iter().flatten();
+            let candidates = columns.map(|c| format!("{table}.{c}")).filter(|c| matches(c));
+            return Ok((start, candidates.map(pair).collect()));
+        }
+
+        // Keywords, table names, and column names. Keywords are completed in
+        // lowercase if the word is lowercase.
+        let lowercase = word.chars().all(|c| !c.is_uppercase());
+        let keywords = Keyword::ALL.iter().map(|k| match lowercase {
+            true => k.to_string().to_lowercase(),
+            false => k.to_string(),
+        });
+        let tables = self.tables.keys().cloned();
+        let columns = self.tables.values().flatten().cloned();
+        let candidates =
+            keywords.chain(tables).chain(columns).filter(|c| matches(c)).sorted().dedup();
+        Ok((start, candidates.map(pair).collect()))
+    }
+}
 
-impl Validator for InputValidator {
+impl Validator for InputHelper {
     fn validate(&self, ctx: &mut ValidationContext) -> rustyline::Result<ValidationResult> {
         let input = ctx.input();
-        // Empty lines and ! commands are ready.
-        if input.is_empty() || input.starts_with('!') || input == ";" {
+        // Empty lines, ! commands, and \ meta commands are ready.
+        if input.is_empty() || input.starts_with('!') || input.starts_with('\\') || input == ";" {
             return Ok(ValidationResult::Valid(None));
         }
         // For SQL statements, just look for any semicolon or lexer error, and
@@ -4,17 +4,24 @@
 //!
 //! cargo run --release --bin workload -- read
 //!
+//! Each workload verifies a set of invariants once it completes (e.g. that
+//! the bank workload's balances sum to the initial total), optionally while
+//! injecting Raft faults into the cluster. It can thus also be used as a
+//! consistency smoke test in CI: with --json it prints a machine-readable
+//! report, and it exits with a non-zero status if any check fails.
+//!
 //! See --help for a list of available workloads and arguments.
 
 #![warn(clippy::all)]
 
 use std::cmp::min;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::io::Write as _;
 use std::time::{Duration, Instant};
 
 use clap::Parser;
 use hdrhistogram::Histogram;
+use hdrhistogram::sync::SyncHistogram;
 use itertools::Itertools as _;
 use petname::Generator as _;
 use rand::SeedableRng as _;
@@ -22,18 +29,30 @@ use rand::distributions::Distribution as _;
 use rand::rngs::StdRng;
 
 use toydb::error::Result;
+use toydb::fault::Faults;
 use toydb::sql::types::{Row, Rows};
 use toydb::{Client, StatementResult};
 
 fn main() {
     let Command { runner, subcommand } = Command::parse();
+    let json = runner.json;
     let result = match subcommand {
         Subcommand::Read(read) => runner.run(read),
         Subcommand::Write(write) => runner.run(write),
         Subcommand::Bank(bank) => runner.run(bank),
     };
-    if let Err(error) = result {
-        eprintln!("Error: {error}")
+    match result {
+        Ok(true) => {}
+        Ok(false) => std::process::exit(1),
+        Err(error) => {
+            match json {
+                true => {
+                    println!("{}", serde_json::json!({"passed": false, "error": error.to_string()}))
+                }
+                false => eprintln!("Error: {error}"),
+            }
+            std::process::exit(1);
+        }
     }
 }
 
@@ -78,29 +97,145 @@ struct Runner {
     /// Seed to use for random number generation.
     #[arg(short, long, default_value = "16791084677885396490")]
     seed: u64,
+
+    /// Probability of dropping Raft messages while running the workload.
+    /// Requires fault injection to be enabled on the nodes.
+    #[arg(long, default_value = "0")]
+    fault_drop: f64,
+
+    /// Probability of duplicating Raft messages while running the workload.
+    /// Requires fault injection to be enabled on the nodes.
+    #[arg(long, default_value = "0")]
+    fault_duplicate: f64,
+
+    /// Milliseconds to delay Raft messages by while running the workload.
+    /// Requires fault injection to be enabled on the nodes.
+    #[arg(long, default_value = "0")]
+    fault_delay: u64,
+
+    /// Also check that all nodes have consistent state machines after the
+    /// workload completes.
+    #[arg(long)]
+    check_consistency: bool,
+
+    /// Print a JSON report to stdout, and progress to stderr.
+    #[arg(long)]
+    json: bool,
 }
 
 impl Runner {
-    /// Runs the specified workload.
-    fn run<W: Workload>(self, workload: W) -> Result<()> {
+    /// Runs the specified workload, returning true if all checks passed.
+    fn run<W: Workload>(self, workload: W) -> Result<bool> {
         let mut rng = StdRng::seed_from_u64(self.seed);
         let mut client = Client::connect(&self.hosts[0])?;
 
+        // With --json, stdout is reserved for the report.
+        let mut out: Box<dyn std::io::Write> = match self.json {
+            true => Box::new(std::io::stderr()),
+            false => Box::new(std::io::stdout()),
+        };
+
         // Set up a histogram recording txn latencies as nanoseconds. The
         // buckets range from 0.001s to 10s.
         let mut hist = Histogram::<u32>::new_with_bounds(1_000, 10_000_000_000, 3)?.into_sync();
 
         // Prepare the dataset.
-        print!("Preparing initial dataset... ");
-        std::io::stdout().flush()?;
+        write!(out, "Preparing initial dataset... ")?;
+        out.flush()?;
         let start = Instant::now();
         workload.prepare(&mut client, &mut rng)?;
-        println!("done ({:.3}s)", start.elapsed().as_secs_f64());
+        writeln!(out, "done ({:.3}s)", start.elapsed().as_secs_f64())?;
+
+        // Inject faults into all nodes while the workload runs.
+        let faults = Faults {
+            drop: self.fault_drop,
+            duplicate: self.fault_duplicate,
+            delay: Duration::from_millis(self.fault_delay),
+            ..Faults::default()
+        };
+        let faulty = faults != Faults::default();
+        if faulty {
+            write!(out, "Injecting faults... ")?;
+            out.flush()?;
+            self.inject_faults(&faults)?;
+            writeln!(out, "done")?;
+        }
 
+        // Run the workload. Always clear injected faults afterwards, even if
+        // the workload fails, to avoid leaving the cluster degraded.
+        let start = Instant::now();
+        let result = self.execute(&workload, rng, &mut hist, &mut out);
+        let duration = start.elapsed();
+        if faulty {
+            self.inject_faults(&Faults::default())?;
+        }
+        result?;
+
+        // Verify the final dataset.
+        writeln!(out)?;
+        write!(out, "Verifying dataset... ")?;
+        out.flush()?;
+        let start = Instant::now();
+        let mut checks = workload.verify(&mut client, self.count)?;
+        if self.check_consistency {
+            let check = client.check_consistency()?;
+            checks.push(Check::new(
+                "replica_consistency",
+                check.is_consistent(),
+                format!("{:?}", check.nodes),
+            ));
+        }
+        writeln!(out, "done ({:.3}s)", start.elapsed().as_secs_f64())?;
+
+        writeln!(out)?;
+        for check in &checks {
+            match check.passed {
+                true => writeln!(out, "{:<24} ok", check.name)?,
+                false => writeln!(out, "{:<24} FAILED: {}", check.name, check.detail)?,
+            }
+        }
+        let passed = checks.iter().all(|check| check.passed);
+
+        if self.json {
+            let millis =
+                |q: f64| Duration::from_nanos(hist.value_at_quantile(q)).as_secs_f64() * 1000.0;
+            let report = serde_json::json!({
+                "workload": workload.to_string(),
+                "txns": hist.len(),
+                "duration": duration.as_secs_f64(),
+                "rate": hist.len() as f64 / duration.as_secs_f64(),
+                "latency_ms": {
+                    "p50": millis(0.5),
+                    "p90": millis(0.9),
+                    "p99": millis(0.99),
+                    "max": Duration::from_nanos(hist.max()).as_secs_f64() * 1000.0,
+                },
+                "checks": checks.iter().map(|check| serde_json::json!({
+                    "name": check.name,
+                    "passed": check.passed,
+                    "detail": check.detail,
+                })).collect_vec(),
+                "passed": passed,
+            });
+            println!("{report}");
+        }
+
+        Ok(passed)
+    }
+
+    /// Executes the workload's transactions across concurrent workers, and
+    /// prints periodic progress stats until they complete.
+    fn execute<W: Workload>(
+        &self,
+        workload: &W,
+        rng: StdRng,
+        hist: &mut SyncHistogram<u32>,
+        out: &mut Box<dyn std::io::Write>,
+    ) -> Result<()> {
         // Spawn workers, round robin across hosts.
         std::thread::scope(|s| -> Result<()> {
-            print!("Spawning {} workers... ", self.concurrency);
-            std::io::stdout().flush()?;
+            write!(out, "Spawning {} workers... ", self.concurrency)?;
+            out.flush()?;
             let start = Instant::now();
 
             let (work_tx, work_rx) = crossbeam::channel::bounded(self.concurrency);
@@ -114,7 +249,7 @@ impl Runner {
                 s.spawn(move || -> Result<()> {
                     while let Ok(item) = work_rx.recv() {
                         let start = Instant::now();
-                        client.with_retry(|client| W::execute(client, &item))?;
+                        client.with_retry(|client| workload.execute(client, &item))?;
                         recorder.record(start.elapsed().as_nanos() as u64)?;
                     }
                     drop(done_tx); // disconnects done_rx once all workers exit
@@ -123,11 +258,11 @@ impl Runner {
             }
             drop(done_tx); // drop local copy
 
-            println!("done ({:.3}s)", start.elapsed().as_secs_f64());
+            writeln!(out, "done ({:.3}s)", start.elapsed().as_secs_f64())?;
 
             // Spawn work generator.
             {
-                println!("Running workload {}...", workload);
+                writeln!(out, "Running workload {}...", workload)?;
                 let generator = workload.generate(rng).take(self.count);
                 s.spawn(move || -> Result<()> {
                     for item in generator {
@@ -141,8 +276,11 @@ impl Runner {
             let start = Instant::now();
             let ticker = crossbeam::channel::tick(Duration::from_secs(1));
 
-            println!();
-            println!("Time   Progress     Txns      Rate       p50       p90       p99      pMax");
+            writeln!(out)?;
+            writeln!(
+                out,
+                "Time   Progress     Txns      Rate       p50       p90       p99      pMax"
+            )?;
 
             while let Err(crossbeam::channel::TryRecvError::Empty) = done_rx.try_recv() {
                 crossbeam::select! {
@@ -153,7 +291,8 @@ impl Runner {
                 let duration = start.elapsed().as_secs_f64();
                 hist.refresh_timeout(Duration::from_secs(1));
 
-                println!(
+                writeln!(
+                    out,
                     "{:<8} {:>5.1}%  {:>7}  {:>6.0}/s  {:>6.1}ms  {:>6.1}ms  {:>6.1}ms  {:>6.1}ms",
                     format!("{:.1}s", duration),
                     hist.len() as f64 / self.count as f64 * 100.0,
@@ -163,25 +302,45 @@ impl Runner {
                     Duration::from_nanos(hist.value_at_quantile(0.9)).as_secs_f64() * 1000.0,
                     Duration::from_nanos(hist.value_at_quantile(0.99)).as_secs_f64() * 1000.0,
                     Duration::from_nanos(hist.max()).as_secs_f64() * 1000.0,
-                );
+                )?;
             }
             Ok(())
-        })?;
-
-        // Verify the final dataset.
-        println!();
-        print!("Verifying dataset... ");
-        std::io::stdout().flush()?;
-        let start = Instant::now();
-        workload.verify(&mut client, self.count)?;
-        println!("done ({:.3}s)", start.elapsed().as_secs_f64());
+        })
+    }
 
+    /// Injects the given faults into all nodes, replacing any current faults.
+    fn inject_faults(&self, faults: &Faults) -> Result<()> {
+        for addr in &self.hosts {
+            Client::connect(addr)?.inject_faults(faults.clone())?;
+        }
         Ok(())
     }
 }
 
+/// The result of a workload invariant check.
+struct Check {
+    /// The check name.
+    name: &'static str,
+    /// Whether the check passed.
+    passed: bool,
+    /// Details about the check result, e.g. the expected and actual values.
+    detail: String,
+}
+
+impl Check {
+    /// Creates a new check result.
+    fn new(name: &'static str, passed: bool, detail: String) -> Self {
+        Self { name, passed, detail }
+    }
+
+    /// Creates a check result that passes if the values are equal.
+    fn equal<T: PartialEq + std::fmt::Display>(name: &'static str, expect: T, actual: T) -> Self {
+        Self::new(name, expect == actual, format!("expected {expect}, got {actual}"))
+    }
+}
+
 /// A workload.
-trait Workload: std::fmt::Display {
+trait Workload: std::fmt::Display + Sync {
     /// A work item.
     type Item: Send;
 
@@ -193,12 +352,11 @@ trait Workload: std::fmt::Display {
 
     /// Executes a single work item. This will automatically be retried on
     /// certain errors, and must use a transaction where appropriate.
-    fn execute(client: &mut Client, item: &Self::Item) -> Result<()>;
+    fn execute(&self, client: &mut Client, item: &Self::Item) -> Result<()>;
 
-    /// Verifies the dataset after the workload has completed.
-    fn verify(&self, _client: &mut Client, _txns: usize) -> Result<()> {
-        Ok(())
-    }
+    /// Verifies the dataset's invariants after the workload has completed,
+    /// given the number of executed transactions.
+    fn verify(&self, client: &mut Client, txns: usize) -> Result<Vec<Check>>;
 }
 
 /// A read-only workload. Creates an id,value table and populates it with the
@@ -258,7 +416,7 @@ impl Workload for Read {
         }
     }
 
-    fn execute(client: &mut Client, item: &Self::Item) -> Result<()> {
+    fn execute(&self, client: &mut Client, item: &Self::Item) -> Result<()> {
         let batch_size = item.len();
         let query = format!(
             r#"SELECT * FROM "read" WHERE {}"#,
@@ -269,10 +427,9 @@ impl Workload for Read {
         Ok(())
     }
 
-    fn verify(&self, client: &mut Client, _: usize) -> Result<()> {
+    fn verify(&self, client: &mut Client, _: usize) -> Result<Vec<Check>> {
         let count: i64 = client.execute(r#"SELECT COUNT(*) FROM "read""#)?.try_into()?;
-        assert_eq!(count, self.rows as i64, "Unexpected row count");
-        Ok(())
+        Ok(vec![Check::equal("row_count", self.rows as i64, count)])
     }
 }
 
@@ -335,13 +492,13 @@ impl Workload for Write {
         WriteGenerator { next_id: 1, size: self.size, batch: self.batch, rng }
     }
 
-    fn execute(client: &mut Client, item: &Self::Item) -> Result<()> {
+    fn execute(&self, client: &mut Client, item: &Self::Item) -> Result<()> {
         let batch_size = item.len();
         let query = format!(
             r#"INSERT INTO "write" (id, value) VALUES {}"#,
             item.iter().map(|(id, value)| format!("({}, '{}')", id, value)).join(", ")
         );
-        if let StatementResult::Insert { count } = client.execute(&query)? {
+        if let StatementResult::Insert { count, .. } = client.execute(&query)? {
             assert_eq!(count as usize, batch_size, "Unexpected row count");
         } else {
             panic!("Unexpected result")
@@ -349,10 +506,9 @@ impl Workload for Write {
         Ok(())
     }
 
-    fn verify(&self, client: &mut Client, txns: usize) -> Result<()> {
+    fn verify(&self, client: &mut Client, txns: usize) -> Result<Vec<Check>> {
         let count: i64 = client.execute(r#"SELECT COUNT(*) FROM "write""#)?.try_into()?;
-        assert_eq!(count as usize, txns * self.batch, "Unexpected row count");
-        Ok(())
+        Ok(vec![Check::equal("row_count", (txns * self.batch) as i64, count)])
     }
 }
 
@@ -386,6 +542,11 @@ impl Iterator for WriteGenerator {
 /// and transfers a random amount without overdrawing the account. This
 /// somewhat convoluted scheme is used to make the workload slightly less
 /// trivial, including joins, ordering, and secondary indexes.
+///
+/// The schema can be varied via --schema (see BankSchema). Once completed, it
+/// verifies that the balances sum to the initial total and aren't negative,
+/// that foreign keys are intact, and with the ledger schema that the recorded
+/// transfers account for every balance.
 #[derive(clap::Args, Clone)]
 #[command(about = "A bank workload, making transfers between customer accounts")]
 struct Bank {
@@ -404,41 +565,98 @@ struct Bank {
     /// Max amount to transfer.
     #[arg(short, long, default_value = "50")]
     max_transfer: u64,
+
+    /// The schema to use.
+    #[arg(long, value_enum, default_value = "normalized")]
+    schema: BankSchema,
+}
+
+/// A bank workload schema.
+#[derive(Clone, Copy, PartialEq, clap::ValueEnum)]
+enum BankSchema {
+    /// Accounts only, with customer IDs but no customer table.
+    Flat,
+    /// Customers and accounts, with a foreign key from accounts to customers.
+    Normalized,
+    /// Like normalized, but also records each transfer in a transfer table,
+    /// with foreign keys to the accounts.
+    Ledger,
+}
+
+impl std::fmt::Display for BankSchema {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Flat => write!(f, "flat"),
+            Self::Normalized => write!(f, "normalized"),
+            Self::Ledger => write!(f, "ledger"),
+        }
+    }
 }
 
 impl std::fmt::Display for Bank {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "bank (customers={} accounts={})", self.customers, self.accounts)
+        write!(
+            f,
+            "bank (customers={} accounts={} schema={})",
+            self.customers, self.accounts, self.schema
+        )
+    }
+}
+
+impl Bank {
+    /// Returns a query for the given customer's account with the highest
+    /// (DESC) or lowest (ASC) balance, as an id,balance row.
+    fn account_query(&self, customer: u64, order: &str) -> String {
+        match self.schema {
+            BankSchema::Flat => format!(
+                "SELECT id, balance
+                        FROM account
+                        WHERE customer_id = {customer}
+                        ORDER BY balance {order}
+                        LIMIT 1"
+            ),
+            BankSchema::Normalized | BankSchema::Ledger => format!(
+                "SELECT a.id, a.balance
+                        FROM account a JOIN customer c ON a.customer_id = c.id
+                        WHERE c.id = {customer}
+                        ORDER BY a.balance {order}
+                        LIMIT 1"
+            ),
+        }
     }
 }
 
 impl Workload for Bank {
-    type Item = (u64, u64, u64); // from,to,amount
+    type Item = (u64, u64, u64, u64); // id,from,to,amount
 
     fn prepare(&self, client: &mut Client, rng: &mut StdRng) -> Result<()> {
         let petnames = petname::Petnames::default();
         client.execute("BEGIN")?;
+        client.execute("DROP TABLE IF EXISTS transfer")?;
         client.execute("DROP TABLE IF EXISTS account")?;
         client.execute("DROP TABLE IF EXISTS customer")?;
-        client.execute(
-            "CREATE TABLE customer (
-                    id INTEGER PRIMARY KEY,
-                    name STRING NOT NULL
-                )",
-        )?;
-        client.execute(
+        if self.schema != BankSchema::Flat {
+            client.execute(
+                "CREATE TABLE customer (
+                        id INTEGER PRIMARY KEY,
+                        name STRING NOT NULL
+                    )",
+            )?;
+            client.execute(&format!(
+                "INSERT INTO customer VALUES {}",
+                (1..=self.customers)
+                    .zip(petnames.iter(rng, 3, " "))
+                    .map(|(id, name)| format!("({}, '{}')", id, name))
+                    .join(", ")
+            ))?;
+        }
+        client.execute(&format!(
             "CREATE TABLE account (
                     id INTEGER PRIMARY KEY,
-                    customer_id INTEGER NOT NULL INDEX REFERENCES customer,
+                    customer_id INTEGER NOT NULL INDEX {},
                     balance INTEGER NOT NULL
                 )",
-        )?;
-        client.execute(&format!(
-            "INSERT INTO customer VALUES {}",
-            (1..=self.customers)
-                .zip(petnames.iter(rng, 3, " "))
-                .map(|(id, name)| format!("({}, '{}')", id, name))
-                .join(", ")
+            if self.schema == BankSchema::Flat { "" } else { "REFERENCES customer" }
         ))?;
         client.execute(&format!(
             "INSERT INTO account VALUES {}",
@@ -447,6 +665,16 @@ impl Workload for Bank {
                 .map(|(c, a)| (format!("({}, {}, {})", a, c, self.balance)))
                 .join(", ")
         ))?;
+        if self.schema == BankSchema::Ledger {
+            client.execute(
+                "CREATE TABLE transfer (
+                        id INTEGER PRIMARY KEY,
+                        from_account INTEGER NOT NULL INDEX REFERENCES account,
+                        to_account INTEGER NOT NULL INDEX REFERENCES account,
+                        amount INTEGER NOT NULL
+                    )",
+            )?;
+        }
         client.execute("COMMIT")?;
         Ok(())
     }
@@ -455,44 +683,41 @@ impl Workload for Bank {
         let customers = self.customers;
         let max_transfer = self.max_transfer;
         // Generate random u64s, then pick random from,to,amount as the
-        // remainder of the max customer and amount.
+        // remainder of the max customer and amount. Number the transfers
+        // sequentially, for use as ledger IDs.
         rand::distributions::Uniform::new_inclusive(0, u64::MAX)
             .sample_iter(rng)
             .tuples()
             .map(move |(a, b, c)| (a % customers + 1, b % customers + 1, c % max_transfer + 1))
             .filter(|(from, to, _)| from != to)
+            .zip(1..)
+            .map(|((from, to, amount), id)| (id, from, to, amount))
     }
 
-    fn execute(client: &mut Client, item: &Self::Item) -> Result<()> {
-        let &(from, to, mut amount) = item;
+    fn execute(&self, client: &mut Client, item: &Self::Item) -> Result<()> {
+        let &(id, from, to, mut amount) = item;
 
         client.execute("BEGIN")?;
 
-        let row: Row = client
-            .execute(&format!(
-                "SELECT a.id, a.balance
-                        FROM account a JOIN customer c ON a.customer_id = c.id
-                        WHERE c.id = {}
-                        ORDER BY a.balance DESC
-                        LIMIT 1",
-                from
-            ))?
-            .try_into()?;
+        // If a previous attempt failed during COMMIT, it may have committed
+        // anyway. Don't record the transfer twice in that case.
+        if self.schema == BankSchema::Ledger {
+            let count: i64 = client
+                .execute(&format!("SELECT COUNT(*) FROM transfer WHERE id = {id}"))?
+                .try_into()?;
+            if count > 0 {
+                client.execute("COMMIT")?;
+                return Ok(());
+            }
+        }
+
+        let row: Row = client.execute(&self.account_query(from, "DESC"))?.try_into()?;
         let mut row = row.into_iter();
         let from_account: i64 = row.next().unwrap().try_into()?;
         let from_balance: i64 = row.next().unwrap().try_into()?;
         amount = min(amount, from_balance as u64);
 
-        let to_account: i64 = client
-            .execute(&format!(
-                "SELECT a.id, a.balance
-                        FROM account a JOIN customer c ON a.customer_id = c.id
-                        WHERE c.id = {}
-                        ORDER BY a.balance ASC
-                        LIMIT 1",
-                to
-            ))?
-            .try_into()?;
+        let to_account: i64 = client.execute(&self.account_query(to, "ASC"))?.try_into()?;
 
         client.execute(&format!(
             "UPDATE account SET balance = balance - {} WHERE id = {}",
@@ -502,18 +727,85 @@ impl Workload for Bank {
             "UPDATE account SET balance = balance + {} WHERE id = {}",
             amount, to_account,
         ))?;
+        if self.schema == BankSchema::Ledger {
+            client.execute(&format!(
+                "INSERT INTO transfer VALUES ({}, {}, {}, {})",
+                id, from_account, to_account, amount
+            ))?;
+        }
 
         client.execute("COMMIT")?;
 
         Ok(())
     }
 
-    fn verify(&self, client: &mut Client, _: usize) -> Result<()> {
+    fn verify(&self, client: &mut Client, txns: usize) -> Result<Vec<Check>> {
+        let mut checks = Vec::new();
+
+        let count: i64 = client.execute("SELECT COUNT(*) FROM account")?.try_into()?;
+        checks.push(Check::equal("account_count", (self.customers * self.accounts) as i64, count));
         let balance: i64 = client.execute("SELECT SUM(balance) FROM account")?.try_into()?;
-        assert_eq!(balance as u64, self.customers * self.accounts * self.balance);
+        let total = self.customers * self.accounts * self.balance;
+        checks.push(Check::equal("balance_total", total as i64, balance));
         let negative: i64 =
             client.execute("SELECT COUNT(*) FROM account WHERE balance < 0")?.try_into()?;
-        assert_eq!(negative, 0);
-        Ok(())
+        checks.push(Check::equal("negative_balances", 0, negative));
+
+        if self.schema == BankSchema::Flat {
+            return Ok(checks);
+        }
+
+        // Count rows with intact foreign keys via inner joins, and compare
+        // them with the total row counts.
+        let joined: i64 = client
+            .execute("SELECT COUNT(*) FROM account a JOIN customer c ON a.customer_id = c.id")?
+            .try_into()?;
+        checks.push(Check::equal("account_customer_fk", count, joined));
+
+        if self.schema != BankSchema::Ledger {
+            return Ok(checks);
+        }
+
+        let count: i64 = client.execute("SELECT COUNT(*) FROM transfer")?.try_into()?;
+        checks.push(Check::equal("transfer_count", txns as i64, count));
+        let joined: i64 = client
+            .execute(
+                "SELECT COUNT(*) FROM transfer t
+                        JOIN account f ON t.from_account = f.id
+                        JOIN account a ON t.to_account = a.id",
+            )?
+            .try_into()?;
+        checks.push(Check::equal("transfer_account_fk", count, joined));
+
+        // Each account's balance must be its initial balance plus the net
+        // amount transferred to it.
+        let mut expect: HashMap<i64, i64> = HashMap::new();
+        let rows: Rows =
+            client.execute("SELECT from_account, to_account, amount FROM transfer")?.try_into()?;
+        for row in rows {
+            let mut row = row?.into_iter();
+            let from: i64 = row.next().unwrap().try_into()?;
+            let to: i64 = row.next().unwrap().try_into()?;
+            let amount: i64 = row.next().unwrap().try_into()?;
+            *expect.entry(from).or_insert(self.balance as i64) -= amount;
+            *expect.entry(to).or_insert(self.balance as i64) += amount;
+        }
+        let mut mismatched = Vec::new();
+        let rows: Rows = client.execute("SELECT id, balance FROM account")?.try_into()?;
+        for row in rows {
+            let mut row = row?.into_iter();
+            let id: i64 = row.next().unwrap().try_into()?;
+            let balance: i64 = row.next().unwrap().try_into()?;
+            if balance != expect.get(&id).copied().unwrap_or(self.balance as i64) {
+                mismatched.push(id);
+            }
+        }
+        checks.push(Check::new(
+            "transfer_balances",
+            mismatched.is_empty(),
+            format!("expected no mismatched accounts, got {mismatched:?}"),
+        ));
+
+        Ok(checks)
     }
 }
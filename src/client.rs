@@ -1,60 +1,453 @@
-use std::cmp::min;
-use std::io::Write as _;
-use std::time::Duration;
+use std::collections::VecDeque;
+use std::io::{Read, Write as _};
+use std::ops::{Deref, DerefMut};
+use std::path::Path;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
 
+use itertools::Itertools as _;
 use rand::Rng;
 
+use crate::clock::Timestamp;
 use crate::encoding::Value as _;
-use crate::errdata;
 use crate::error::{Error, Result};
-use crate::server::{Request, Response, Status};
-use crate::sql::engine::StatementResult;
-use crate::sql::types::Table;
+use crate::fault::Faults;
+use crate::raft;
+use crate::server::{
+    Capabilities, ClusterStatus, ConsistencyCheck, MIN_PROTOCOL_VERSION, NodeStatus,
+    PROTOCOL_VERSION, Request, Response, Status, Topology,
+};
+use crate::sql::engine::{Change, Checksum, Scrub, StatementResult};
+use crate::sql::execution::Returning;
+use crate::sql::parser::split_statements;
+use crate::sql::types::{Label, Row, Table};
 use crate::storage::mvcc;
+use crate::storage::{BitCask, Engine as _};
+use crate::tls::{self, Stream};
+use crate::{errdata, errinput};
 
 /// A toyDB client. Connects to a server via TCP and submits SQL statements and
 /// other requests.
 pub struct Client {
     /// Inbound response stream.
-    reader: std::io::BufReader<std::net::TcpStream>,
-    /// Outbound request stream.
-    writer: std::io::BufWriter<std::net::TcpStream>,
+    reader: std::io::BufReader<Stream>,
+    /// Outbound request stream. Shared with cancel handles.
+    writer: Arc<Mutex<std::io::BufWriter<Stream>>>,
     /// The current transaction, if any.
     txn: Option<mvcc::TransactionState>,
+    /// The retry policy for with_retry().
+    retry_policy: RetryPolicy,
+    /// If true, a network or protocol error occurred and the connection
+    /// can't be used anymore. Pools discard broken clients.
+    broken: bool,
+    /// If true, the server is still streaming SELECT rows from a query()
+    /// whose Rows were dropped before reaching the end. They're discarded
+    /// before the next request.
+    streaming: bool,
+    /// Cluster state for leader redirects, if connected via connect_cluster().
+    cluster: Option<Cluster>,
+    /// The negotiated protocol version.
+    protocol_version: u32,
+    /// The negotiated protocol capabilities.
+    capabilities: Capabilities,
+    /// The latest hybrid logical clock timestamp observed by the client.
+    timestamp: Timestamp,
 }
 
 impl Client {
     /// Connects to a toyDB server, creating a new client.
     pub fn connect(addr: impl std::net::ToSocketAddrs) -> Result<Self> {
         let socket = std::net::TcpStream::connect(addr)?;
-        let reader = std::io::BufReader::new(socket.try_clone()?);
-        let writer = std::io::BufWriter::new(socket);
-        Ok(Self { reader, writer, txn: None })
+        Self::new(Stream::Tcp(socket))
     }
 
-    /// Sends a request to the server, returning the response.
+    /// Connects to a local toyDB server via a Unix socket at the given path.
+    pub fn connect_unix(path: impl AsRef<Path>) -> Result<Self> {
+        let socket = std::os::unix::net::UnixStream::connect(path)?;
+        Self::new(Stream::Unix(socket))
+    }
+
+    /// Connects to a toyDB server using TLS, verifying the server certificate
+    /// against the CA certificates in the given PEM file. The certificate must
+    /// be valid for the host.
+    pub fn connect_tls(host: &str, port: u16, ca: impl AsRef<Path>) -> Result<Self> {
+        let socket = std::net::TcpStream::connect((host, port))?;
+        let config = tls::client_config(ca.as_ref())?;
+        Self::new(Stream::connect(socket, Some(&config), host)?)
+    }
+
+    /// Connects to a toyDB cluster via any of the given node addresses, and
+    /// redirects to the Raft leader. Requests to other nodes are forwarded to
+    /// the leader, so this saves a network hop, and avoids errors when the
+    /// node loses contact with the leader.
+    ///
+    /// The client caches the cluster topology (see Request::Topology), and
+    /// refreshes it every TOPOLOGY_TTL, when a request fails with Error::Abort
+    /// or Error::NotLeader (e.g. due to a leader change), or when the
+    /// connection breaks. If the
+    /// leader has changed, the client reconnects to it before the next request,
+    /// outside of transactions. If the connection broke, it reconnects to the
+    /// leader or any other reachable node, and re-authenticates. The failed
+    /// request itself isn't retried, since it may have been applied, but
+    /// transactions can be retried via with_transaction(). Session variables
+    /// set via SET are reset when reconnecting.
+    pub fn connect_cluster(addrs: &[impl AsRef<str>]) -> Result<Self> {
+        Self::connect_cluster_with(addrs, None)
+    }
+
+    /// Like connect_cluster(), but uses TLS, verifying server certificates
+    /// against the CA certificates in the given PEM file. They must be valid
+    /// for the hosts in the node addresses.
+    pub fn connect_cluster_tls(addrs: &[impl AsRef<str>], ca: impl AsRef<Path>) -> Result<Self> {
+        Self::connect_cluster_with(addrs, Some(tls::client_config(ca.as_ref())?))
+    }
+
+    fn connect_cluster_with(
+        addrs: &[impl AsRef<str>],
+        tls: Option<Arc<rustls::ClientConfig>>,
+    ) -> Result<Self> {
+        let seeds = addrs.iter().map(|addr| addr.as_ref().to_string()).collect_vec();
+        let cluster = Cluster { seeds, tls, topology: None, credentials: None, stale: true };
+        let mut client = Self::new(cluster.connect_any()?)?;
+        client.cluster = Some(cluster);
+        client.redirect()?;
+        Ok(client)
+    }
+
+    /// Connects to a cluster node at the given address, with a connection
+    /// timeout, using TLS if given. Used by servers to query their peers.
+    pub(crate) fn connect_node(
+        addr: &str,
+        tls: Option<&Arc<rustls::ClientConfig>>,
+    ) -> Result<Self> {
+        Self::new(connect_addr(addr, tls)?)
+    }
+
+    /// Creates a new client using the given stream, and negotiates the
+    /// protocol version.
+    fn new(stream: Stream) -> Result<Self> {
+        let reader = std::io::BufReader::new(stream.try_clone()?);
+        let writer = Arc::new(Mutex::new(std::io::BufWriter::new(stream)));
+        let retry_policy = RetryPolicy::default();
+        let mut client = Self {
+            reader,
+            writer,
+            txn: None,
+            retry_policy,
+            broken: false,
+            streaming: false,
+            cluster: None,
+            protocol_version: PROTOCOL_VERSION,
+            capabilities: Capabilities::NONE,
+            timestamp: Timestamp::default(),
+        };
+        client.handshake()?;
+        Ok(client)
+    }
+
+    /// Negotiates the protocol version and capabilities with the server.
+    fn handshake(&mut self) -> Result<()> {
+        let request =
+            Request::Handshake { version: PROTOCOL_VERSION, capabilities: Capabilities::ALL };
+        (self.protocol_version, self.capabilities) = handshake_response(self.roundtrip(request))?;
+        Ok(())
+    }
+
+    /// Returns the negotiated protocol version.
+    pub fn protocol_version(&self) -> u32 {
+        self.protocol_version
+    }
+
+    /// Returns the negotiated protocol capabilities, i.e. the optional
+    /// features supported by both the client and server.
+    pub fn capabilities(&self) -> Capabilities {
+        self.capabilities
+    }
+
+    /// Sends a request to the server, returning the response. If connected
+    /// via connect_cluster(), redirects to the leader first if necessary.
     fn request(&mut self, request: Request) -> Result<Response> {
-        request.encode_into(&mut self.writer)?;
-        self.writer.flush()?;
-        Result::decode_from(&mut self.reader)?
+        self.redirect()?;
+        let response = self.roundtrip(request);
+        if let Some(cluster) = self.cluster.as_mut() {
+            cluster.stale |=
+                self.broken || matches!(response, Err(Error::Abort | Error::NotLeader(_)));
+        }
+        response
+    }
+
+    /// Sends a request to the server and receives the response.
+    fn roundtrip(&mut self, request: Request) -> Result<Response> {
+        self.discard_rows()?;
+        send(&self.writer, request).inspect_err(|_| self.broken = true)?;
+        self.receive()
+    }
+
+    /// Reconnects to the cluster if the connection is broken, and refreshes
+    /// the cached topology if it's stale or expired, redirecting to the leader
+    /// if it's a different node. Does nothing if not connected via
+    /// connect_cluster(), or within a transaction with a working connection.
+    fn redirect(&mut self) -> Result<()> {
+        let Some(cluster) = self.cluster.as_ref() else {
+            return Ok(());
+        };
+        // The server may have closed the idle connection, e.g. when shutting
+        // down. If so, reconnect instead of failing the request.
+        if !self.broken
+            && self.txn.is_none()
+            && !self.streaming
+            && self.reader.buffer().is_empty()
+            && self.reader.get_ref().peer_closed()
+        {
+            self.broken = true;
+        }
+        if self.broken {
+            // The server rolled back any transaction when the connection broke.
+            let stream = cluster.connect_any()?;
+            self.reconnect(stream)?;
+            self.txn = None;
+        } else if self.txn.is_some() || !cluster.is_expired() {
+            return Ok(());
+        }
+        // Servers without topology support can't redirect.
+        if !self.capabilities.contains(Capabilities::TOPOLOGY) {
+            return Ok(());
+        }
+
+        // Refresh the topology. If this fails, e.g. because there is currently
+        // no leader, keep using the current connection and retry later.
+        let topology = match self.roundtrip(Request::Topology) {
+            Ok(Response::Topology(topology)) => topology,
+            Ok(response) => return errdata!("unexpected response: {response:?}"),
+            Err(_) if !self.broken => return Ok(()),
+            Err(error) => return Err(error),
+        };
+        let cluster = self.cluster.as_mut().expect("no cluster");
+        let (server, leader) = (topology.server, topology.leader);
+        cluster.topology = Some((topology, Instant::now()));
+        cluster.stale = false;
+
+        // Redirect to the leader, if we know its address.
+        if server != leader {
+            if let Ok(stream) = cluster.connect_leader() {
+                self.reconnect(stream)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Replaces the client's connection with the given stream, and
+    /// re-authenticates if the client was authenticated.
+    fn reconnect(&mut self, stream: Stream) -> Result<()> {
+        self.reader = std::io::BufReader::new(stream.try_clone()?);
+        *self.writer.lock()? = std::io::BufWriter::new(stream);
+        self.broken = false;
+        self.streaming = false;
+        self.handshake()?;
+        let credentials = self.cluster.as_ref().and_then(|cluster| cluster.credentials.clone());
+        if let Some((user, password)) = credentials {
+            let request = Request::Authenticate { user, password };
+            match self.roundtrip(request)? {
+                Response::Authenticate => {}
+                response => return errdata!("unexpected response: {response:?}"),
+            }
+        }
+        Ok(())
+    }
+
+    /// Receives a response from the server. The outer result is a network or
+    /// decoding error, which leaves the connection broken, while the inner
+    /// result is the server's response.
+    fn receive(&mut self) -> Result<Response> {
+        Result::decode_from(&mut self.reader).inspect_err(|_| self.broken = true)?
+    }
+
+    /// Receives the next frame of streamed SELECT rows. An empty frame ends
+    /// the rows.
+    fn receive_frame(&mut self) -> Result<Vec<Row>> {
+        let result = self.receive();
+        // The stream ends with an empty frame, or an error.
+        self.streaming = matches!(&result, Ok(Response::Rows(rows)) if !rows.is_empty());
+        match result? {
+            Response::Rows(rows) => Ok(rows),
+            response => errdata!("unexpected response {response:?}"),
+        }
+    }
+
+    /// Discards any remaining rows streamed by a previous query().
+    fn discard_rows(&mut self) -> Result<()> {
+        while self.streaming {
+            self.receive_frame()?;
+        }
+        Ok(())
     }
 
-    /// Executes a SQL statement.
+    /// Returns a handle that can cancel the client's in-flight statement from a
+    /// different thread, e.g. on Ctrl-C.
+    pub fn cancel_handle(&self) -> CancelHandle {
+        CancelHandle { writer: self.writer.clone() }
+    }
+
+    /// Executes a SQL statement. SELECT rows are buffered in the result, use
+    /// query() to stream them instead.
     pub fn execute(&mut self, statement: &str) -> Result<StatementResult> {
-        let result = match self.request(Request::Execute(statement.to_string()))? {
-            Response::Execute(result) => result,
+        let mut result = self.execute_start(statement)?;
+        if let StatementResult::Select { rows, .. } = &mut result {
+            loop {
+                let frame = self.receive_frame()?;
+                if frame.is_empty() {
+                    break;
+                }
+                rows.extend(frame);
+            }
+        }
+        Ok(result)
+    }
+
+    /// Executes a SQL query, returning an iterator over the result rows. The
+    /// server streams the rows in frames, which are fetched as the iterator is
+    /// consumed, so large results aren't buffered in memory. INSERT, UPDATE,
+    /// and DELETE return their RETURNING rows, if any, which are sent with the
+    /// result. Other statements are executed and return no rows. Dropping the
+    /// iterator before the end discards the remaining rows.
+    pub fn query(&mut self, statement: &str) -> Result<Rows<'_>> {
+        let (columns, frame, done) = match self.execute_start(statement)? {
+            StatementResult::Select { columns, .. } => (columns, Vec::new(), false),
+            StatementResult::Delete { returning: Some(Returning { columns, rows }), .. }
+            | StatementResult::Insert { returning: Some(Returning { columns, rows }), .. }
+            | StatementResult::Update { returning: Some(Returning { columns, rows }), .. } => {
+                (columns, rows, true)
+            }
+            _ => (Vec::new(), Vec::new(), true),
+        };
+        Ok(Rows { client: self, columns, frame: frame.into_iter(), done })
+    }
+
+    /// Executes multiple SQL statements in a single round trip, returning
+    /// their results in order. Execution stops at the first error, which is
+    /// returned as the last result, and the remaining statements aren't
+    /// executed. SELECT result rows are buffered.
+    ///
+    /// If the server doesn't support batches, the statements are executed
+    /// one at a time instead.
+    pub fn execute_batch(&mut self, statements: &[&str]) -> Result<Vec<Result<StatementResult>>> {
+        if !self.capabilities.contains(Capabilities::BATCH) {
+            let mut results = Vec::with_capacity(statements.len());
+            for statement in statements {
+                let result = self.execute(statement);
+                let failed = result.is_err();
+                results.push(result);
+                if failed {
+                    break;
+                }
+            }
+            return Ok(results);
+        }
+        let statements = statements.iter().map(|statement| statement.to_string()).collect();
+        let results = match self.request(Request::ExecuteBatch(statements))? {
+            Response::ExecuteBatch(results) => results,
             response => return errdata!("unexpected response {response:?}"),
         };
-        // Update the transaction state.
-        match &result {
-            StatementResult::Begin(state) => self.txn = Some(state.clone()),
-            StatementResult::Commit { .. } => self.txn = None,
-            StatementResult::Rollback { .. } => self.txn = None,
-            _ => {}
+        for result in &results {
+            self.update_txn(result);
+        }
+        Ok(results)
+    }
+
+    /// Executes an INSERT, UPDATE, or DELETE statement at most once for the
+    /// given client-supplied idempotency token, which should be unique for
+    /// each logical write (e.g. a random UUID). If a statement has already
+    /// committed with the token, its original result is returned instead of
+    /// executing it again. Unlike execute(), it's therefore safe to retry
+    /// after ambiguous failures such as Error::Abort, e.g. via with_retry().
+    /// It can't be used in an explicit transaction.
+    pub fn execute_idempotent(&mut self, statement: &str, token: &str) -> Result<StatementResult> {
+        if !self.capabilities.contains(Capabilities::IDEMPOTENCY) {
+            return errinput!("server doesn't support idempotency tokens");
+        }
+        let request = Request::ExecuteIdempotent {
+            statement: statement.to_string(),
+            token: token.to_string(),
+        };
+        match self.request(request)? {
+            Response::Execute(result) => Ok(result),
+            response => errdata!("unexpected response {response:?}"),
+        }
+    }
+
+    /// Executes a COPY FROM STDIN statement (or IMPORT FROM STDIN), importing
+    /// the CSV or JSON data read from the given reader. The data is streamed to
+    /// the server in chunks, and imported once complete.
+    pub fn copy_from(&mut self, statement: &str, mut reader: impl Read) -> Result<StatementResult> {
+        if !self.capabilities.contains(Capabilities::COPY) {
+            return errinput!("server doesn't support COPY");
+        }
+        self.redirect()?;
+        self.discard_rows()?;
+        let mut chunk = vec![0; COPY_CHUNK_SIZE];
+        loop {
+            let n = match reader.read(&mut chunk) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(error) if error.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(error) => return Err(error.into()),
+            };
+            let request = Request::CopyData(chunk[..n].to_vec());
+            send(&self.writer, request).inspect_err(|_| self.broken = true)?;
+        }
+        let response = self.roundtrip(Request::Copy(statement.to_string()));
+        if let Some(cluster) = self.cluster.as_mut() {
+            cluster.stale |=
+                self.broken || matches!(response, Err(Error::Abort | Error::NotLeader(_)));
+        }
+        match response? {
+            Response::Execute(result) => Ok(result),
+            response => errdata!("unexpected response {response:?}"),
         }
+    }
+
+    /// Executes a SQL statement, returning its result. SELECT results have no
+    /// rows; they're streamed separately in frames.
+    fn execute_start(&mut self, statement: &str) -> Result<StatementResult> {
+        let result = match self.request(Request::Execute(statement.to_string())) {
+            Ok(Response::Execute(result)) => Ok(result),
+            Ok(response) => return errdata!("unexpected response {response:?}"),
+            Err(error) => Err(error),
+        };
+        self.update_txn(&result);
+        let result = result?;
+        self.streaming = matches!(result, StatementResult::Select { .. });
         Ok(result)
     }
 
+    /// Updates the transaction state with a statement result.
+    fn update_txn(&mut self, result: &Result<StatementResult>) {
+        match result {
+            Ok(StatementResult::Begin(state)) => self.txn = Some(state.clone()),
+            Ok(StatementResult::Commit { .. }) => self.txn = None,
+            Ok(StatementResult::Rollback { .. }) => self.txn = None,
+            // The server rolls back the transaction on deadlocks.
+            Err(Error::Deadlock) => self.txn = None,
+            _ => {}
+        }
+    }
+
+    /// Authenticates as the given user. If the server has authentication
+    /// enabled, this must be done before any other requests.
+    pub fn authenticate(&mut self, user: &str, password: &str) -> Result<()> {
+        let request =
+            Request::Authenticate { user: user.to_string(), password: password.to_string() };
+        match self.request(request)? {
+            Response::Authenticate => {}
+            response => return errdata!("unexpected response: {response:?}"),
+        }
+        // Remember the credentials, to re-authenticate after reconnecting.
+        if let Some(cluster) = self.cluster.as_mut() {
+            cluster.credentials = Some((user.to_string(), password.to_string()));
+        }
+        Ok(())
+    }
+
     /// Fetches a table schema.
     pub fn get_table(&mut self, table: &str) -> Result<Table> {
         match self.request(Request::GetTable(table.to_string()))? {
@@ -79,42 +472,882 @@ impl Client {
         }
     }
 
+    /// Returns the status of the server's own node, without contacting the
+    /// leader. Requires superuser privileges.
+    pub fn node_status(&mut self) -> Result<NodeStatus> {
+        if !self.capabilities.contains(Capabilities::CLUSTER_STATUS) {
+            return errinput!("server doesn't support node status");
+        }
+        match self.request(Request::NodeStatus)? {
+            Response::NodeStatus(status) => Ok(status),
+            response => errdata!("unexpected response: {response:?}"),
+        }
+    }
+
+    /// Returns the status of all cluster nodes, as collected by the server.
+    /// Works without a Raft leader. Requires superuser privileges.
+    pub fn cluster_status(&mut self) -> Result<ClusterStatus> {
+        if !self.capabilities.contains(Capabilities::CLUSTER_STATUS) {
+            return errinput!("server doesn't support cluster status");
+        }
+        match self.request(Request::ClusterStatus)? {
+            Response::ClusterStatus(status) => Ok(status),
+            response => errdata!("unexpected response: {response:?}"),
+        }
+    }
+
+    /// Transfers Raft leadership from the current leader to an up-to-date
+    /// follower, returning its ID. Errors with Error::Abort if no follower has
+    /// caught up yet. Requires superuser privileges.
+    pub fn transfer_leadership(&mut self) -> Result<raft::NodeID> {
+        if !self.capabilities.contains(Capabilities::DECOMMISSION) {
+            return errinput!("server doesn't support leadership transfers");
+        }
+        match self.request(Request::TransferLeadership)? {
+            Response::TransferLeadership(id) => Ok(id),
+            response => errdata!("unexpected response: {response:?}"),
+        }
+    }
+
+    /// Removes a follower from the Raft cluster, returning the log index of the
+    /// committed removal. Requires superuser privileges. The server must not be
+    /// the removed node, since it's then ignored by the cluster.
+    pub fn remove_node(&mut self, id: raft::NodeID) -> Result<raft::Index> {
+        if !self.capabilities.contains(Capabilities::DECOMMISSION) {
+            return errinput!("server doesn't support node removal");
+        }
+        match self.request(Request::RemoveNode(id))? {
+            Response::RemoveNode(index) => Ok(index),
+            response => errdata!("unexpected response: {response:?}"),
+        }
+    }
+
+    /// Checks the consistency of the stored data, and repairs it if requested.
+    /// Requires superuser privileges.
+    pub fn scrub(&mut self, repair: bool) -> Result<Scrub> {
+        if !self.capabilities.contains(Capabilities::SCRUB) {
+            return errinput!("server doesn't support scrubbing");
+        }
+        match self.request(Request::Scrub { repair })? {
+            Response::Scrub(scrub) => Ok(scrub),
+            response => errdata!("unexpected response: {response:?}"),
+        }
+    }
+
+    /// Checks that all cluster nodes have consistent state machines, by
+    /// comparing checksums computed at the same applied index. Requires
+    /// superuser privileges.
+    pub fn check_consistency(&mut self) -> Result<ConsistencyCheck> {
+        if !self.capabilities.contains(Capabilities::CONSISTENCY_CHECK) {
+            return errinput!("server doesn't support consistency checks");
+        }
+        match self.request(Request::CheckConsistency)? {
+            Response::CheckConsistency(check) => Ok(check),
+            response => errdata!("unexpected response: {response:?}"),
+        }
+    }
+
+    /// Returns the server's checksums computed at the given applied index by
+    /// a consistency check, or None if it hasn't applied it yet. Requires
+    /// superuser privileges.
+    pub fn checksum(&mut self, index: raft::Index) -> Result<Option<Checksum>> {
+        if !self.capabilities.contains(Capabilities::CONSISTENCY_CHECK) {
+            return errinput!("server doesn't support consistency checks");
+        }
+        match self.request(Request::Checksum(index))? {
+            Response::Checksum(checksum) => Ok(checksum),
+            response => errdata!("unexpected response: {response:?}"),
+        }
+    }
+
+    /// Sets the faults injected into the server, returning the previous
+    /// faults. For chaos testing, and only available if the server has fault
+    /// injection enabled. Requires superuser privileges.
+    #[doc(hidden)]
+    pub fn inject_faults(&mut self, faults: Faults) -> Result<Faults> {
+        match self.request(Request::InjectFaults(faults))? {
+            Response::InjectFaults(previous) => Ok(previous),
+            response => errdata!("unexpected response: {response:?}"),
+        }
+    }
+
+    /// Synchronizes hybrid logical clocks with the server, returning the
+    /// server's current timestamp. The server's clock is advanced past the
+    /// latest timestamp observed by this client, so the returned timestamp is
+    /// causally after everything the client has seen from other servers. It
+    /// can be used e.g. for AS OF SYSTEM TIME queries.
+    pub fn clock(&mut self) -> Result<Timestamp> {
+        if !self.capabilities.contains(Capabilities::CLOCK) {
+            return errinput!("server doesn't support clock synchronization");
+        }
+        match self.request(Request::Clock(self.timestamp))? {
+            Response::Clock(timestamp) => {
+                self.observe_timestamp(timestamp);
+                Ok(timestamp)
+            }
+            response => errdata!("unexpected response: {response:?}"),
+        }
+    }
+
+    /// Records a timestamp observed elsewhere, e.g. by a different client,
+    /// such that the next clock() call returns a later timestamp.
+    pub fn observe_timestamp(&mut self, timestamp: Timestamp) {
+        self.timestamp = self.timestamp.max(timestamp);
+    }
+
+    /// Fetches the cluster topology from the server. If connected via
+    /// connect_cluster(), this also updates the cached topology.
+    pub fn topology(&mut self) -> Result<Topology> {
+        let topology = match self.request(Request::Topology)? {
+            Response::Topology(topology) => topology,
+            response => return errdata!("unexpected response: {response:?}"),
+        };
+        if let Some(cluster) = self.cluster.as_mut() {
+            cluster.topology = Some((topology.clone(), Instant::now()));
+        }
+        Ok(topology)
+    }
+
+    /// Garbage collects old MVCC versions, retaining the server's configured
+    /// number of recent versions for time-travel queries.
+    pub fn gc(&mut self) -> Result<mvcc::GarbageCollection> {
+        match self.request(Request::GC)? {
+            Response::GC(gc) => Ok(gc),
+            response => errdata!("unexpected response: {response:?}"),
+        }
+    }
+
+    /// Fetches the row changes committed to a table with a changefeed by up to
+    /// limit commits after the given commit sequence number, in commit order.
+    pub fn changes(&mut self, table: &str, after: u64, limit: usize) -> Result<Vec<Change>> {
+        match self.request(Request::Changes { table: table.to_string(), after, limit })? {
+            Response::Changes(changes) => Ok(changes),
+            response => errdata!("unexpected response: {response:?}"),
+        }
+    }
+
+    /// Takes a full backup of the SQL database and writes it to a new BitCask
+    /// file at the given path, returning the Raft log index it was taken at.
+    /// It can be restored to this or any later point in time with `toydb
+    /// restore`, which replays the Raft log on top of it.
+    pub fn backup(&mut self, path: impl AsRef<Path>) -> Result<raft::Index> {
+        let path = path.as_ref();
+        if path.exists() {
+            return errinput!("backup file {} already exists", path.display());
+        }
+        let backup = match self.request(Request::Backup)? {
+            Response::Backup(backup) => backup,
+            response => return errdata!("unexpected response: {response:?}"),
+        };
+        let mut engine = BitCask::new(path.to_path_buf())?;
+        for (key, value) in backup.data {
+            engine.set(&key, value)?;
+        }
+        engine.flush()?;
+        Ok(backup.applied_index)
+    }
+
+    /// Takes an incremental backup of the SQL database, containing the changes
+    /// after a previous full or incremental backup taken at the given Raft log
+    /// index, and writes it to a new file at the given path. Returns the Raft
+    /// log index it was taken at, which later incremental backups can build
+    /// on. It can be restored with `toydb restore --incremental`, which
+    /// replays it on top of the previous backups.
+    pub fn backup_incremental(
+        &mut self,
+        path: impl AsRef<Path>,
+        since: raft::Index,
+    ) -> Result<raft::Index> {
+        let path = path.as_ref();
+        if path.exists() {
+            return errinput!("backup file {} already exists", path.display());
+        }
+        let backup = match self.request(Request::BackupIncremental { since })? {
+            Response::BackupIncremental(backup) => backup,
+            response => return errdata!("unexpected response: {response:?}"),
+        };
+        let index = backup.index();
+        backup.write(&mut BitCask::new(path.to_path_buf())?)?;
+        Ok(index)
+    }
+
+    /// Subscribes to a table's changefeed, returning an iterator over the row
+    /// changes committed after the given commit sequence number, in commit
+    /// order. When caught up, it polls the server at the given interval, so it
+    /// only ends if the client is dropped. A commit's changes are yielded
+    /// consecutively with the same sequence number; to resume the changefeed
+    /// later, pass the sequence number of the last fully processed commit.
+    pub fn changefeed(
+        &mut self,
+        table: &str,
+        after: u64,
+        poll_interval: Duration,
+    ) -> Changefeed<'_> {
+        Changefeed {
+            client: self,
+            table: table.to_string(),
+            after,
+            poll_interval,
+            buffer: VecDeque::new(),
+        }
+    }
+
     /// Returns the transaction state.
     pub fn txn(&self) -> Option<&mvcc::TransactionState> {
         self.txn.as_ref()
     }
 
-    /// Runs the given closure, automatically retrying serialization and abort
-    /// errors. If a transaction is open following an error, it is automatically
-    /// rolled back. It is the caller's responsibility to use a transaction in
-    /// the closure where appropriate (i.e. when it is not idempotent).
+    /// Sets the retry policy used by with_retry() and with_transaction().
+    pub fn set_retry_policy(&mut self, retry_policy: RetryPolicy) {
+        self.retry_policy = retry_policy;
+    }
+
+    /// Runs the given closure, automatically retrying retryable errors (see
+    /// Error::is_retryable) with randomized exponential backoff until the
+    /// retry policy's budget is exhausted. If a transaction is open following
+    /// an error, it is automatically rolled back. It is the caller's
+    /// responsibility to use a transaction in the closure where appropriate
+    /// (i.e. when it is not idempotent), see with_transaction().
     pub fn with_retry<T>(&mut self, f: impl Fn(&mut Client) -> Result<T>) -> Result<T> {
-        const MAX_RETRIES: u32 = 10;
-        const MIN_WAIT: u64 = 10;
-        const MAX_WAIT: u64 = 2_000;
+        let policy = self.retry_policy.clone();
+        let start = Instant::now();
         let mut retries: u32 = 0;
         loop {
             match f(self) {
                 Ok(result) => return Ok(result),
-                Err(Error::Serialization | Error::Abort) if retries < MAX_RETRIES => {
-                    if self.txn().is_some() {
-                        self.execute("ROLLBACK")?;
-                    }
-                    // Use exponential backoff starting at MIN_WAIT doubling up
-                    // to MAX_WAIT, but randomize the wait time in this interval
+                Err(error) if error.is_retryable() && retries < policy.max_retries => {
+                    self.rollback_txn()?;
+                    // Use exponential backoff starting at min_wait doubling up
+                    // to max_wait, but randomize the wait time in this interval
                     // to reduce the chance of collisions.
-                    let mut wait = min(MIN_WAIT * 2_u64.pow(retries), MAX_WAIT);
-                    wait = rand::thread_rng().gen_range(MIN_WAIT..=wait);
-                    std::thread::sleep(Duration::from_millis(wait));
+                    let max_wait = policy
+                        .min_wait
+                        .saturating_mul(2_u32.saturating_pow(retries))
+                        .clamp(policy.min_wait, policy.max_wait.max(policy.min_wait));
+                    let wait = rand::thread_rng().gen_range(policy.min_wait..=max_wait);
+                    if policy.max_duration.is_some_and(|max| start.elapsed() + wait > max) {
+                        return Err(error);
+                    }
+                    std::thread::sleep(wait);
                     retries += 1;
                 }
                 Err(error) => {
-                    if self.txn().is_some() {
-                        self.execute("ROLLBACK").ok(); // ignore rollback error
-                    }
+                    self.rollback_txn().ok(); // ignore rollback error
                     return Err(error);
                 }
             }
         }
     }
+
+    /// Runs the given closure in a read-write transaction and commits it,
+    /// retrying the entire transaction on retryable errors like with_retry().
+    /// The closure must not begin or end the transaction itself.
+    ///
+    /// If COMMIT fails with Error::Abort (e.g. due to a leader change), the
+    /// transaction may or may not have committed, and is retried anyway. The
+    /// closure should be idempotent if this matters.
+    pub fn with_transaction<T>(&mut self, f: impl Fn(&mut Client) -> Result<T>) -> Result<T> {
+        self.with_retry(|client| {
+            client.execute("BEGIN")?;
+            let result = f(client)?;
+            client.execute("COMMIT")?;
+            Ok(result)
+        })
+    }
+
+    /// Applies the given schema migrations that haven't been applied yet, in
+    /// version order, returning the versions that were applied. Applied
+    /// versions are recorded in the schema_migrations table, which is created
+    /// if it doesn't exist.
+    ///
+    /// Each migration is applied in its own transaction, together with its
+    /// record in schema_migrations, so it's either applied completely or not
+    /// at all. Clients can migrate concurrently: conflicting transactions are
+    /// retried like with_transaction(), and skip migrations that were applied
+    /// in the meanwhile. If a migration fails, the following ones aren't
+    /// applied, and a later call resumes from it. It can't be used in an
+    /// explicit transaction.
+    pub fn migrate(&mut self, migrations: &[Migration]) -> Result<Vec<u64>> {
+        if self.txn.is_some() {
+            return errinput!("can't migrate in a transaction");
+        }
+        let migrations = migrations.iter().sorted_by_key(|m| m.version).collect_vec();
+        if let Some((m, _)) =
+            migrations.iter().tuple_windows().find(|(a, b)| a.version == b.version)
+        {
+            return errinput!("duplicate migration version {}", m.version);
+        }
+        self.with_retry(|client| client.create_migrations_table())?;
+
+        let mut applied = Vec::new();
+        for migration in migrations {
+            let select = format!(
+                "SELECT version FROM {MIGRATIONS_TABLE} WHERE version = {}",
+                migration.version
+            );
+            let insert = format!(
+                "INSERT INTO {MIGRATIONS_TABLE} VALUES ({}, '{}')",
+                migration.version,
+                migration.name.replace('\'', "''")
+            );
+            let apply = self.with_transaction(|client| {
+                if let StatementResult::Select { rows, .. } = client.execute(&select)? {
+                    if !rows.is_empty() {
+                        return Ok(false);
+                    }
+                }
+                for statement in &migration.statements {
+                    client.execute(statement)?;
+                }
+                client.execute(&insert)?;
+                Ok(true)
+            });
+            match apply {
+                Ok(true) => applied.push(migration.version),
+                Ok(false) => {}
+                Err(Error::InvalidInput(message)) => {
+                    return errinput!("migration {} failed: {message}", migration.version);
+                }
+                Err(error) => return Err(error),
+            }
+        }
+        Ok(applied)
+    }
+
+    /// Creates the schema_migrations table, unless it already exists.
+    fn create_migrations_table(&mut self) -> Result<()> {
+        if self.list_tables()?.iter().any(|table| table == MIGRATIONS_TABLE) {
+            return Ok(());
+        }
+        let create = format!(
+            "CREATE TABLE {MIGRATIONS_TABLE} (version INTEGER PRIMARY KEY, name STRING NOT NULL)"
+        );
+        match self.execute(&create) {
+            Ok(_) => Ok(()),
+            // A concurrent client may have created it.
+            Err(Error::InvalidInput(_))
+                if self.list_tables()?.iter().any(|table| table == MIGRATIONS_TABLE) =>
+            {
+                Ok(())
+            }
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Rolls back the current transaction, if any. The server may already
+    /// have rolled it back, e.g. if COMMIT failed.
+    fn rollback_txn(&mut self) -> Result<()> {
+        if self.txn.is_none() {
+            return Ok(());
+        }
+        match self.execute("ROLLBACK") {
+            Ok(_) => Ok(()),
+            Err(Error::InvalidInput(_)) => {
+                self.txn = None;
+                Ok(())
+            }
+            Err(error) => Err(error),
+        }
+    }
+}
+
+/// Streamed SELECT result rows, obtained via Client::query(). Iterates over
+/// the rows, fetching frames of rows from the server as needed.
+pub struct Rows<'a> {
+    client: &'a mut Client,
+    columns: Vec<Label>,
+    /// The current frame of rows.
+    frame: std::vec::IntoIter<Row>,
+    /// If true, all frames have been received.
+    done: bool,
+}
+
+impl Rows<'_> {
+    /// Returns the result column labels.
+    pub fn columns(&self) -> &[Label] {
+        &self.columns
+    }
+}
+
+impl Iterator for Rows<'_> {
+    type Item = Result<Row>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(row) = self.frame.next() {
+                return Some(Ok(row));
+            }
+            if self.done {
+                return None;
+            }
+            match self.client.receive_frame() {
+                Ok(frame) if frame.is_empty() => self.done = true,
+                Ok(frame) => self.frame = frame.into_iter(),
+                Err(error) => {
+                    self.done = true;
+                    return Some(Err(error));
+                }
+            }
+        }
+    }
+}
+
+/// A changefeed subscription, obtained via Client::changefeed(). Iterates
+/// over committed row changes, polling the server for new changes.
+pub struct Changefeed<'a> {
+    client: &'a mut Client,
+    table: String,
+    /// The commit sequence number to fetch changes after.
+    after: u64,
+    poll_interval: Duration,
+    /// Fetched changes that haven't been yielded yet.
+    buffer: VecDeque<Change>,
+}
+
+impl Changefeed<'_> {
+    /// The maximum number of commits to fetch per request.
+    const BATCH_SIZE: usize = 100;
+}
+
+impl Iterator for Changefeed<'_> {
+    type Item = Result<Change>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.buffer.is_empty() {
+            let changes = match self.client.changes(&self.table, self.after, Self::BATCH_SIZE) {
+                Ok(changes) => changes,
+                Err(error) => return Some(Err(error)),
+            };
+            match changes.last() {
+                Some(last) => self.after = last.seq,
+                None => std::thread::sleep(self.poll_interval),
+            }
+            self.buffer.extend(changes);
+        }
+        self.buffer.pop_front().map(Ok)
+    }
+}
+
+/// A retry policy for Client::with_retry(). The budget is exhausted when
+/// either the number of retries or the total time spent reaches its limit.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RetryPolicy {
+    /// The maximum number of retries.
+    pub max_retries: u32,
+    /// The maximum total duration, including backoff waits, or None for no
+    /// limit. A retry is not attempted if its wait would exceed it.
+    pub max_duration: Option<Duration>,
+    /// The minimum backoff wait, and the initial maximum.
+    pub min_wait: Duration,
+    /// The maximum backoff wait. The maximum doubles for each retry up to this.
+    pub max_wait: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 10,
+            max_duration: None,
+            min_wait: Duration::from_millis(10),
+            max_wait: Duration::from_secs(2),
+        }
+    }
+}
+
+/// The table that Client::migrate() records applied migration versions in.
+pub const MIGRATIONS_TABLE: &str = "schema_migrations";
+
+/// A schema migration, applied via Client::migrate().
+#[derive(Clone, Debug, PartialEq)]
+pub struct Migration {
+    /// The migration version. Migrations are applied in version order, and
+    /// each version is only applied once.
+    pub version: u64,
+    /// A descriptive name, recorded along with the version.
+    pub name: String,
+    /// The SQL statements to apply, typically DDL.
+    pub statements: Vec<String>,
+}
+
+impl Migration {
+    /// Creates a migration from a script of semicolon-separated statements.
+    pub fn new(version: u64, name: impl Into<String>, script: &str) -> Result<Self> {
+        let statements = split_statements(script)?;
+        Ok(Self { version, name: name.into(), statements })
+    }
+
+    /// Reads migrations from the .sql files in a directory. Each file
+    /// contains a script of semicolon-separated statements, and is named
+    /// VERSION_NAME.sql, e.g. 001_create_users.sql. Other files are ignored.
+    pub fn read_dir(dir: impl AsRef<Path>) -> Result<Vec<Self>> {
+        let mut migrations = Vec::new();
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            if !path.is_file() || path.extension().is_none_or(|ext| ext != "sql") {
+                continue;
+            }
+            let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+            let (version, name) = stem.split_once('_').unwrap_or((&stem, ""));
+            let Ok(version) = version.parse() else {
+                return errinput!(
+                    "invalid migration file name {stem}.sql, expected VERSION_NAME.sql"
+                );
+            };
+            let script = std::fs::read_to_string(&path)?;
+            migrations.push(Self::new(version, name, &script)?);
+        }
+        migrations.sort_by_key(|migration| migration.version);
+        Ok(migrations)
+    }
+}
+
+/// Cluster state for a client connected via Client::connect_cluster().
+struct Cluster {
+    /// The node addresses given to connect_cluster().
+    seeds: Vec<String>,
+    /// The TLS configuration, if enabled.
+    tls: Option<Arc<rustls::ClientConfig>>,
+    /// The cached cluster topology, and when it was fetched.
+    topology: Option<(Topology, Instant)>,
+    /// The credentials to re-authenticate with after reconnecting, if any.
+    credentials: Option<(String, String)>,
+    /// If true, the topology must be refreshed before the next request.
+    stale: bool,
+}
+
+impl Cluster {
+    /// How long the cached topology is used before it's refreshed.
+    const TOPOLOGY_TTL: Duration = Duration::from_secs(10);
+
+    /// The timeout when connecting to a node.
+    const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// Returns true if the topology must be refreshed.
+    fn is_expired(&self) -> bool {
+        self.stale
+            || self.topology.as_ref().is_none_or(|(_, at)| at.elapsed() >= Self::TOPOLOGY_TTL)
+    }
+
+    /// Connects to the leader in the cached topology, if its address is known.
+    fn connect_leader(&self) -> Result<Stream> {
+        let Some((topology, _)) = &self.topology else {
+            return errinput!("unknown cluster topology");
+        };
+        let Some(addr) = topology.nodes.get(&topology.leader) else {
+            return errinput!("unknown address for leader {}", topology.leader);
+        };
+        self.connect(addr)
+    }
+
+    /// Connects to any reachable node, trying the cached leader first, then
+    /// other nodes in the cached topology, and finally the seed addresses.
+    fn connect_any(&self) -> Result<Stream> {
+        let mut addrs = Vec::new();
+        if let Some((topology, _)) = &self.topology {
+            addrs.extend(topology.nodes.get(&topology.leader));
+            addrs.extend(
+                topology
+                    .nodes
+                    .iter()
+                    .filter(|(id, _)| **id != topology.leader)
+                    .map(|(_, addr)| addr),
+            );
+        }
+        addrs.extend(&self.seeds);
+        let mut error = errinput!("no node addresses given");
+        for addr in addrs.into_iter().unique() {
+            match self.connect(addr) {
+                Ok(stream) => return Ok(stream),
+                Err(err) => error = Err(err),
+            }
+        }
+        error
+    }
+
+    /// Connects to the given node address.
+    fn connect(&self, addr: &str) -> Result<Stream> {
+        connect_addr(addr, self.tls.as_ref())
+    }
+}
+
+/// Connects to a node address with a timeout, using TLS if given.
+fn connect_addr(addr: &str, tls: Option<&Arc<rustls::ClientConfig>>) -> Result<Stream> {
+    let mut error = errinput!("invalid address {addr}");
+    for socket_addr in std::net::ToSocketAddrs::to_socket_addrs(addr)? {
+        let socket =
+            match std::net::TcpStream::connect_timeout(&socket_addr, Cluster::CONNECT_TIMEOUT) {
+                Ok(socket) => socket,
+                Err(err) => {
+                    error = Err(err.into());
+                    continue;
+                }
+            };
+        // The certificate must be valid for the host name.
+        let host = addr.rsplit_once(':').map_or(addr, |(host, _)| host);
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        return Stream::connect(socket, tls, host);
+    }
+    error
+}
+
+/// Cancels the in-flight statement of a client, if any. The statement then
+/// errors with Error::Cancelled, unless it has already completed. Obtained via
+/// Client::cancel_handle().
+#[derive(Clone)]
+pub struct CancelHandle {
+    writer: Arc<Mutex<std::io::BufWriter<Stream>>>,
+}
+
+impl CancelHandle {
+    /// Cancels the in-flight statement, if any.
+    pub fn cancel(&self) -> Result<()> {
+        send(&self.writer, Request::Cancel)
+    }
+}
+
+/// Processes a handshake response, returning the negotiated protocol version
+/// and capabilities.
+pub(crate) fn handshake_response(response: Result<Response>) -> Result<(u32, Capabilities)> {
+    match response {
+        Ok(Response::Handshake { version, capabilities }) if version >= MIN_PROTOCOL_VERSION => {
+            Ok((version, capabilities))
+        }
+        Ok(Response::Handshake { version, .. }) => errinput!(
+            "unsupported server protocol version {version}, client supports versions {MIN_PROTOCOL_VERSION} to {PROTOCOL_VERSION}"
+        ),
+        Ok(response) => errdata!("unexpected response: {response:?}"),
+        // Servers that predate protocol negotiation can't decode the handshake
+        // request, and close the connection.
+        Err(Error::IO(_) | Error::InvalidData(_)) => {
+            errinput!("connection closed during protocol handshake, the server may be too old")
+        }
+        Err(error) => Err(error),
+    }
+}
+
+/// The size of the Request::CopyData chunks streamed by Client::copy_from().
+const COPY_CHUNK_SIZE: usize = 1024 * 1024;
+
+/// Sends a request to the server via the given shared request stream.
+fn send(writer: &Mutex<std::io::BufWriter<Stream>>, request: Request) -> Result<()> {
+    let mut writer = writer.lock()?;
+    request.encode_into(&mut *writer)?;
+    Ok(writer.flush()?)
+}
+
+/// A pool of client connections, reused across requests to avoid the cost of
+/// connecting for each one. It can be cloned and shared between threads.
+/// Clients are checked out via get(), and returned to the pool when the
+/// returned PooledClient is dropped. Any open transaction is rolled back
+/// first, and broken connections are discarded.
+#[derive(Clone)]
+pub struct Pool {
+    shared: Arc<PoolShared>,
+}
+
+/// Pool configuration.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PoolConfig {
+    /// The minimum number of connections, opened when the pool is created.
+    /// Broken connections are replaced on demand.
+    pub min_connections: usize,
+    /// The maximum number of connections, both checked out and idle.
+    pub max_connections: usize,
+    /// How long get() waits for a connection to become available when all
+    /// connections are checked out.
+    pub checkout_timeout: Duration,
+    /// Idle connections are health checked (via a status request) before
+    /// they're checked out if they've been idle for at least this long, or
+    /// None to never check them. Failed connections are discarded.
+    pub health_check_idle: Option<Duration>,
+    /// Idle connections beyond min_connections are closed after this long,
+    /// or None to keep them open.
+    pub idle_timeout: Option<Duration>,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        Self {
+            min_connections: 0,
+            max_connections: 10,
+            checkout_timeout: Duration::from_secs(30),
+            health_check_idle: Some(Duration::from_secs(10)),
+            idle_timeout: Some(Duration::from_secs(600)),
+        }
+    }
+}
+
+/// Pool status.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PoolStatus {
+    /// The number of open connections, both checked out and idle.
+    pub open: usize,
+    /// The number of idle connections.
+    pub idle: usize,
+}
+
+/// State shared between pool clones and pooled clients.
+struct PoolShared {
+    config: PoolConfig,
+    /// Opens a new connection.
+    connect: Box<dyn Fn() -> Result<Client> + Send + Sync>,
+    state: Mutex<PoolState>,
+    /// Notified when a connection is returned or closed.
+    available: Condvar,
+}
+
+/// Pool connection state.
+struct PoolState {
+    /// The number of open connections, both checked out and idle.
+    open: usize,
+    /// Idle connections, and when they were returned. The most recently used
+    /// connections are at the end, and are checked out first.
+    idle: Vec<(Client, Instant)>,
+}
+
+impl Pool {
+    /// Creates a new pool that opens connections via the given closure, e.g.
+    /// connecting with TLS and authenticating. min_connections are opened
+    /// immediately.
+    pub fn new(
+        config: PoolConfig,
+        connect: impl Fn() -> Result<Client> + Send + Sync + 'static,
+    ) -> Result<Self> {
+        if config.max_connections == 0 || config.min_connections > config.max_connections {
+            return errinput!("invalid pool connection limits");
+        }
+        let mut idle = Vec::with_capacity(config.max_connections);
+        for _ in 0..config.min_connections {
+            idle.push((connect()?, Instant::now()));
+        }
+        let state = Mutex::new(PoolState { open: idle.len(), idle });
+        let connect = Box::new(connect);
+        let shared = Arc::new(PoolShared { config, connect, state, available: Condvar::new() });
+        Ok(Self { shared })
+    }
+
+    /// Creates a new pool that connects to the given server address.
+    pub fn connect(
+        addr: impl std::net::ToSocketAddrs + Clone + Send + Sync + 'static,
+        config: PoolConfig,
+    ) -> Result<Self> {
+        Self::new(config, move || Client::connect(addr.clone()))
+    }
+
+    /// Checks out a client, reusing an idle connection if possible, otherwise
+    /// opening a new one. If max_connections are checked out, waits for one
+    /// to be returned, erroring after checkout_timeout.
+    pub fn get(&self) -> Result<PooledClient> {
+        let config = &self.shared.config;
+        let deadline = Instant::now() + config.checkout_timeout;
+        let mut state = self.shared.state.lock()?;
+        loop {
+            state.close_expired(config);
+
+            // Reuse the most recently used idle connection, health checking
+            // it if necessary. The lock isn't held during the health check.
+            if let Some((mut client, since)) = state.idle.pop() {
+                if config.health_check_idle.is_none_or(|idle| since.elapsed() < idle) {
+                    return Ok(self.pooled(client));
+                }
+                drop(state);
+                if client.status().is_ok() {
+                    return Ok(self.pooled(client));
+                }
+                state = self.shared.state.lock()?;
+                state.open -= 1;
+                continue;
+            }
+
+            // Open a new connection if below the limit, without holding the
+            // lock while connecting.
+            if state.open < config.max_connections {
+                state.open += 1;
+                drop(state);
+                return match (self.shared.connect)() {
+                    Ok(client) => Ok(self.pooled(client)),
+                    Err(error) => {
+                        self.shared.state.lock()?.open -= 1;
+                        self.shared.available.notify_one();
+                        Err(error)
+                    }
+                };
+            }
+
+            // Wait for a connection to be returned or closed.
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            if timeout.is_zero() {
+                return errinput!("timed out waiting for a pooled connection");
+            }
+            state = self.shared.available.wait_timeout(state, timeout)?.0;
+        }
+    }
+
+    /// Returns the pool status.
+    pub fn status(&self) -> Result<PoolStatus> {
+        let state = self.shared.state.lock()?;
+        Ok(PoolStatus { open: state.open, idle: state.idle.len() })
+    }
+
+    /// Wraps a checked out client.
+    fn pooled(&self, client: Client) -> PooledClient {
+        PooledClient { client: Some(client), shared: self.shared.clone() }
+    }
+}
+
+impl PoolState {
+    /// Closes idle connections beyond min_connections that have exceeded the
+    /// idle timeout. The oldest connections are at the front.
+    fn close_expired(&mut self, config: &PoolConfig) {
+        let Some(timeout) = config.idle_timeout else {
+            return;
+        };
+        let closable = self.open.saturating_sub(config.min_connections).min(self.idle.len());
+        let expired =
+            self.idle.iter().take(closable).take_while(|(_, since)| since.elapsed() >= timeout);
+        let expired = expired.count();
+        self.idle.drain(..expired);
+        self.open -= expired;
+    }
+}
+
+/// A client checked out from a Pool via Pool::get(). It's returned to the pool
+/// when dropped.
+pub struct PooledClient {
+    /// The client. Only None while dropping.
+    client: Option<Client>,
+    shared: Arc<PoolShared>,
+}
+
+impl Deref for PooledClient {
+    type Target = Client;
+
+    fn deref(&self) -> &Self::Target {
+        self.client.as_ref().expect("no client")
+    }
+}
+
+impl DerefMut for PooledClient {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.client.as_mut().expect("no client")
+    }
+}
+
+impl Drop for PooledClient {
+    /// Returns the client to the pool, rolling back any open transaction. If
+    /// the connection is broken, or the rollback fails, it's closed instead.
+    fn drop(&mut self) {
+        let Some(mut client) = self.client.take() else {
+            return;
+        };
+        let reusable = !client.broken && client.rollback_txn().is_ok();
+        let Ok(mut state) = self.shared.state.lock() else {
+            return;
+        };
+        match reusable {
+            true => state.idle.push((client, Instant::now())),
+            false => state.open -= 1,
+        }
+        self.shared.available.notify_one();
+    }
 }
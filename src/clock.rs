@@ -0,0 +1,218 @@
+//! Clocks. Components that enforce deadlines read the time via a Clock, such
+//! that tests can control time deterministically with a ManualClock rather than
+//! sleeping. Production code uses the SystemClock.
+//!
+//! Note that Raft measures election and heartbeat timeouts in logical ticks,
+//! and MVCC uses logical version timestamps, so neither reads the clock. The
+//! Raft simulator controls Raft time by ticking nodes.
+//!
+//! Transactions are additionally assigned hybrid logical clock (HLC)
+//! timestamps when they begin and commit, taken from a HybridClock. An HLC
+//! timestamp is the wall-clock time plus a logical counter, which never goes
+//! backwards and is always ahead of any timestamp the clock has observed from
+//! other nodes or clients. This makes the timestamps monotonic and causally
+//! consistent across nodes, while staying close to wall-clock time, such that
+//! they can be used for time-travel queries (see mvcc::MVCC::begin_as_of_timestamp).
+
+use std::fmt::{Debug, Display};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use serde::{Deserialize, Serialize};
+
+use crate::encoding;
+use crate::errinput;
+use crate::error::{Error, Result};
+
+/// A source of time.
+pub trait Clock: Debug + Send + Sync {
+    /// Returns the current monotonic time, for deadlines and timeouts.
+    fn now(&self) -> Instant;
+
+    /// Returns the current wall-clock time, as nanoseconds since the Unix
+    /// epoch. It isn't monotonic, and can go backwards.
+    fn wall_time(&self) -> u64;
+}
+
+/// Returns the system clock as a shared clock.
+pub fn system() -> Arc<dyn Clock> {
+    Arc::new(SystemClock)
+}
+
+/// The system clock.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn wall_time(&self) -> u64 {
+        let since_epoch = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH);
+        since_epoch.unwrap_or_default().as_nanos() as u64
+    }
+}
+
+/// A clock that only advances when told to, for tests. Clones share the same
+/// time.
+#[derive(Clone, Debug)]
+pub struct ManualClock {
+    /// The monotonic time when the clock was created.
+    start: Instant,
+    /// The wall-clock time when the clock was created, in nanoseconds.
+    wall_start: u64,
+    /// How far the clock has advanced.
+    elapsed: Arc<Mutex<Duration>>,
+}
+
+impl ManualClock {
+    /// Creates a new manual clock, with the given initial wall-clock time in
+    /// nanoseconds since the Unix epoch.
+    pub fn new(wall_start: u64) -> Self {
+        Self { start: Instant::now(), wall_start, elapsed: Arc::default() }
+    }
+
+    /// Advances the clock by the given duration.
+    pub fn advance(&self, duration: Duration) {
+        *self.elapsed.lock().expect("lock poisoned") += duration;
+    }
+
+    /// Returns how far the clock has advanced.
+    fn elapsed(&self) -> Duration {
+        *self.elapsed.lock().expect("lock poisoned")
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        self.start + self.elapsed()
+    }
+
+    fn wall_time(&self) -> u64 {
+        self.wall_start + self.elapsed().as_nanos() as u64
+    }
+}
+
+/// A hybrid logical clock timestamp. Ordered by wall time, then logical time.
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+pub struct Timestamp {
+    /// The wall-clock time, as nanoseconds since the Unix epoch.
+    pub wall: u64,
+    /// A logical counter, which orders timestamps with the same wall time.
+    pub logical: u32,
+}
+
+impl encoding::Value for Timestamp {}
+
+impl Timestamp {
+    /// Creates a new timestamp.
+    pub fn new(wall: u64, logical: u32) -> Self {
+        Self { wall, logical }
+    }
+
+    /// Returns the smallest timestamp after this one.
+    pub fn next(self) -> Self {
+        match self.logical.checked_add(1) {
+            Some(logical) => Self { wall: self.wall, logical },
+            None => Self { wall: self.wall + 1, logical: 0 },
+        }
+    }
+}
+
+/// Formats the timestamp as WALL.LOGICAL.
+impl Display for Timestamp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}", self.wall, self.logical)
+    }
+}
+
+/// Parses a timestamp as WALL.LOGICAL, or just WALL with a logical time of 0.
+impl FromStr for Timestamp {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (wall, logical) = s.split_once('.').unwrap_or((s, "0"));
+        match (wall.parse(), logical.parse()) {
+            (Ok(wall), Ok(logical)) => Ok(Self { wall, logical }),
+            _ => errinput!("invalid timestamp {s}"),
+        }
+    }
+}
+
+/// A hybrid logical clock. It hands out strictly increasing timestamps that
+/// track the wall clock, but never fall behind a timestamp previously handed
+/// out or observed via update(). Clones share the same clock.
+#[derive(Clone, Debug)]
+pub struct HybridClock {
+    /// The underlying wall clock.
+    clock: Arc<dyn Clock>,
+    /// The latest timestamp handed out or observed.
+    last: Arc<Mutex<Timestamp>>,
+}
+
+impl HybridClock {
+    /// Creates a new hybrid logical clock using the given wall clock.
+    pub fn new(clock: Arc<dyn Clock>) -> Self {
+        Self { clock, last: Arc::default() }
+    }
+
+    /// Returns a new timestamp, after all previous ones. Uses the wall-clock
+    /// time if it's ahead of the latest timestamp, otherwise increments the
+    /// latest timestamp's logical time.
+    pub fn now(&self) -> Timestamp {
+        let wall = self.clock.wall_time();
+        let mut last = self.last.lock().expect("lock poisoned");
+        *last = last.next().max(Timestamp::new(wall, 0));
+        *last
+    }
+
+    /// Observes a timestamp received from a different node or client, and
+    /// returns a new timestamp after both it and all previous ones.
+    pub fn update(&self, timestamp: Timestamp) -> Timestamp {
+        let mut last = self.last.lock().expect("lock poisoned");
+        *last = (*last).max(timestamp);
+        drop(last);
+        self.now()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Tests that the hybrid clock follows the wall clock, and falls back to
+    /// logical time when the wall clock stalls or lags an observed timestamp.
+    #[test]
+    fn hybrid_clock() {
+        let clock = ManualClock::new(100);
+        let hlc = HybridClock::new(Arc::new(clock.clone()));
+
+        assert_eq!(hlc.now(), Timestamp::new(100, 0));
+        assert_eq!(hlc.now(), Timestamp::new(100, 1));
+        clock.advance(Duration::from_nanos(5));
+        assert_eq!(hlc.now(), Timestamp::new(105, 0));
+
+        // Observing a timestamp ahead of the wall clock moves the clock past
+        // it, and it keeps using logical time until the wall clock catches up.
+        assert_eq!(hlc.update(Timestamp::new(200, 3)), Timestamp::new(200, 4));
+        assert_eq!(hlc.now(), Timestamp::new(200, 5));
+        clock.advance(Duration::from_nanos(100));
+        assert_eq!(hlc.now(), Timestamp::new(205, 0));
+
+        // Observing an older timestamp doesn't move the clock back.
+        assert_eq!(hlc.update(Timestamp::new(1, 0)), Timestamp::new(205, 1));
+    }
+
+    #[test]
+    fn timestamp_parse() {
+        assert_eq!("7.3".parse::<Timestamp>().ok(), Some(Timestamp::new(7, 3)));
+        assert_eq!("7".parse::<Timestamp>().ok(), Some(Timestamp::new(7, 0)));
+        assert!("7.x".parse::<Timestamp>().is_err());
+        assert!("".parse::<Timestamp>().is_err());
+        assert_eq!(Timestamp::new(7, u32::MAX).next(), Timestamp::new(8, 0));
+    }
+}
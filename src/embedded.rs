@@ -0,0 +1,130 @@
+//! Embedded (in-process) toyDB databases. A Database runs the SQL engine
+//! directly on a local storage engine inside the current process, without Raft
+//! or a network server, much like SQLite. It uses the same parser, planner, and
+//! executor as a toyDB cluster, via the Local SQL engine, so it's useful for
+//! tests and small tools.
+//!
+//! For example:
+//!
+//! ```
+//! let db = toydb::Database::open("data")?;
+//! let mut session = db.session();
+//! session.execute("CREATE TABLE movies (id INT PRIMARY KEY, title STRING)")?;
+//! session.execute("INSERT INTO movies VALUES (1, 'Sicario')")?;
+//! let result = session.execute("SELECT * FROM movies")?;
+//! ```
+//!
+//! Sessions are independent, and can be used concurrently from different
+//! threads, e.g. via std::thread::scope. Data is stored in a single BitCask
+//! file, or in memory. An embedded database can't be served by a toyDB node,
+//! since nodes store data through Raft.
+
+use std::path::Path;
+
+use crate::clock::{self, HybridClock};
+use crate::error::Result;
+use crate::sql::engine::{Local, Session, StatementResult};
+use crate::storage::{self, BitCask, Memory, mvcc};
+
+/// An embedded toyDB database. See the module documentation.
+pub struct Database<E: storage::Engine + 'static> {
+    /// The local SQL engine.
+    engine: Local<E>,
+}
+
+impl Database<BitCask> {
+    /// Opens a database stored in the given directory, creating it if it
+    /// doesn't exist. Only one process can open it at a time.
+    pub fn open(dir: impl AsRef<Path>) -> Result<Self> {
+        std::fs::create_dir_all(&dir)?;
+        Ok(Self::new(BitCask::new(dir.as_ref().join("sql"))?))
+    }
+}
+
+impl Database<Memory> {
+    /// Creates a new in-memory database, which is lost when dropped.
+    pub fn memory() -> Self {
+        Self::new(Memory::new())
+    }
+}
+
+impl<E: storage::Engine> Database<E> {
+    /// Creates a database using the given storage engine. Transactions are
+    /// timestamped with the system clock, for AS OF SYSTEM TIME queries.
+    pub fn new(engine: E) -> Self {
+        let mut engine = Local::new(engine);
+        engine.set_clock(HybridClock::new(clock::system()));
+        Self { engine }
+    }
+
+    /// Creates a new session, which executes SQL statements. Explicit
+    /// transactions are scoped to the session.
+    pub fn session(&self) -> Session<'_, Local<E>> {
+        Session::new(&self.engine)
+    }
+
+    /// Executes a single SQL statement in a new session, buffering any
+    /// SELECT rows. Use session() for explicit transactions.
+    pub fn execute(&self, statement: &str) -> Result<StatementResult> {
+        self.session().execute(statement)
+    }
+
+    /// Garbage collects old MVCC versions, retaining the given number of
+    /// versions for AS OF SYSTEM TIME queries. In a cluster, nodes do this
+    /// periodically.
+    pub fn gc(&self, retention: u64) -> Result<mvcc::GarbageCollection> {
+        self.engine.mvcc.gc(retention)
+    }
+
+    /// Purges expired rows from tables with a TTL, returning the number of
+    /// rows deleted. Expired rows are invisible to queries either way. In a
+    /// cluster, nodes do this periodically.
+    pub fn purge_expired(&self) -> Result<u64> {
+        self.session().purge_expired()
+    }
+
+    /// Returns the local SQL engine, e.g. for scrubs and checksums.
+    pub fn engine(&self) -> &Local<E> {
+        &self.engine
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sql::types::Value;
+
+    /// Tests that data persists across reopens, and that sessions are
+    /// isolated.
+    #[test]
+    fn open() -> Result<()> {
+        let dir = tempfile::TempDir::with_prefix("toydb")?;
+        let db = Database::open(dir.path())?;
+        db.execute("CREATE TABLE test (id INT PRIMARY KEY, value STRING)")?;
+        db.execute("INSERT INTO test VALUES (1, 'a')")?;
+
+        let (mut s1, mut s2) = (db.session(), db.session());
+        s1.execute("BEGIN")?;
+        s1.execute("INSERT INTO test VALUES (2, 'b')")?;
+        let StatementResult::Select { rows, .. } = s2.execute("SELECT COUNT(*) FROM test")? else {
+            panic!("expected select");
+        };
+        assert_eq!(rows, vec![vec![Value::Integer(1)]]);
+        s1.execute("COMMIT")?;
+        drop((s1, s2));
+        drop(db);
+
+        let db = Database::open(dir.path())?;
+        let StatementResult::Select { rows, .. } = db.execute("SELECT * FROM test")? else {
+            panic!("expected select");
+        };
+        assert_eq!(
+            rows,
+            vec![
+                vec![Value::Integer(1), Value::String("a".into())],
+                vec![Value::Integer(2), Value::String("b".into())],
+            ]
+        );
+        Ok(())
+    }
+}
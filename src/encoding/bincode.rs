@@ -26,6 +26,17 @@ pub fn deserialize<'de, T: Deserialize<'de>>(bytes: &'de [u8]) -> Result<T> {
     Ok(bincode::serde::borrow_decode_from_slice(bytes, CONFIG)?.0)
 }
 
+/// Deserializes a value from the start of a byte slice using Bincode,
+/// returning it along with the number of bytes read, or None if the slice
+/// doesn't contain a complete value yet.
+pub fn try_deserialize<T: DeserializeOwned>(bytes: &[u8]) -> Result<Option<(T, usize)>> {
+    match bincode::serde::decode_from_slice(bytes, CONFIG) {
+        Ok((t, n)) => Ok(Some((t, n))),
+        Err(bincode::error::DecodeError::UnexpectedEnd { .. }) => Ok(None),
+        Err(err) => Err(Error::from(err)),
+    }
+}
+
 /// Serializes a value to a writer using Bincode.
 pub fn serialize_into<W: Write, T: Serialize>(mut writer: W, value: &T) -> Result<()> {
     bincode::serde::encode_into_std_write(value, &mut writer, CONFIG)?;
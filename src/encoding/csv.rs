@@ -0,0 +1,199 @@
+//! CSV encoding of SQL rows, following RFC 4180. Fields are separated by
+//! commas, and fields containing commas, quotes, or newlines are quoted, with
+//! quotes escaped by doubling them. NULL is given as an empty field, and the
+//! empty string as a quoted "" to distinguish them.
+//!
+//! Strings are given as is, and other values via their SQL display format
+//! (e.g. TRUE or 3.14), except arrays which are given as JSON arrays.
+//!
+//! Records are decoded as raw string fields via Reader, which are converted to
+//! SQL values of a given datatype via Value::from_text().
+
+use std::fmt::Write as _;
+use std::io::BufRead;
+
+use crate::errinput;
+use crate::error::Result;
+use crate::sql::types::{Label, Value};
+
+/// Encodes a row as a CSV record, without a trailing newline.
+pub fn encode_row(row: &[Value]) -> String {
+    let mut record = String::new();
+    for (i, value) in row.iter().enumerate() {
+        if i > 0 {
+            record.push(',');
+        }
+        match value {
+            Value::Null => {}
+            Value::String(string) => write_field(&mut record, string),
+            Value::Json(json) => write_field(&mut record, &json.to_string()),
+            Value::Interval(interval) => write_field(&mut record, &interval.to_string()),
+            Value::Timestamp(timestamp) => write_field(&mut record, &timestamp.to_string()),
+            Value::Array(_) => {
+                let json = value.clone().to_json().map(|json| json.to_string());
+                write_field(&mut record, &json.unwrap_or_default())
+            }
+            value => write!(record, "{value}").expect("string write can't fail"),
+        }
+    }
+    record
+}
+
+/// Encodes column labels as a CSV header record, without a trailing newline.
+pub fn encode_header(columns: &[Label]) -> String {
+    let mut record = String::new();
+    for (i, label) in columns.iter().enumerate() {
+        if i > 0 {
+            record.push(',');
+        }
+        write_field(&mut record, label.as_header());
+    }
+    record
+}
+
+/// Writes a CSV field, quoting it if necessary. Empty fields are quoted, to
+/// distinguish them from NULL.
+fn write_field(record: &mut String, field: &str) {
+    if !field.is_empty() && !field.contains([',', '"', '\n', '\r']) {
+        record.push_str(field);
+        return;
+    }
+    record.push('"');
+    record.push_str(&field.replace('"', "\"\""));
+    record.push('"');
+}
+
+/// Reads CSV records from a buffered reader, yielding the line number that
+/// each record starts on (1-based) and its fields. Unquoted empty fields are
+/// returned as None (i.e. NULL). Quoted fields can contain newlines, spanning
+/// multiple lines. Lines can end with either \n or \r\n.
+pub struct Reader<R: BufRead> {
+    input: R,
+    line: usize,
+}
+
+impl<R: BufRead> Reader<R> {
+    /// Creates a new CSV reader.
+    pub fn new(input: R) -> Self {
+        Self { input, line: 0 }
+    }
+
+    /// Reads the next record, if any.
+    fn read_record(&mut self) -> Result<Option<(usize, Vec<Option<String>>)>> {
+        let mut line = String::new();
+        if self.input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        self.line += 1;
+        let start = self.line;
+
+        let mut fields = Vec::new();
+        let mut field = String::new();
+        let mut quoted = false; // the field is quoted
+        let mut in_quotes = false; // inside the quoted field
+        loop {
+            let mut chars = line.chars().peekable();
+            while let Some(c) = chars.next() {
+                match c {
+                    '"' if in_quotes && chars.peek() == Some(&'"') => {
+                        chars.next();
+                        field.push('"');
+                    }
+                    '"' if in_quotes => in_quotes = false,
+                    c if in_quotes => field.push(c),
+                    ',' => {
+                        let field = std::mem::take(&mut field);
+                        fields.push(Some(field).filter(|f| quoted || !f.is_empty()));
+                        quoted = false;
+                    }
+                    '\n' => break,
+                    '\r' if chars.peek().is_none_or(|c| *c == '\n') => break,
+                    '"' if !quoted && field.is_empty() => (quoted, in_quotes) = (true, true),
+                    '"' => return errinput!("line {}: unexpected quote in field", self.line),
+                    _ if quoted => {
+                        return errinput!(
+                            "line {}: unexpected character after quoted field",
+                            self.line
+                        );
+                    }
+                    c => field.push(c),
+                }
+            }
+            if !in_quotes {
+                break;
+            }
+            // The quoted field continues on the next line.
+            line.clear();
+            if self.input.read_line(&mut line)? == 0 {
+                return errinput!("line {start}: unterminated quoted field");
+            }
+            self.line += 1;
+        }
+        fields.push(Some(field).filter(|f| quoted || !f.is_empty()));
+        Ok(Some((start, fields)))
+    }
+}
+
+impl<R: BufRead> Iterator for Reader<R> {
+    type Item = Result<(usize, Vec<Option<String>>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_record().transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use super::*;
+
+    #[test_case(vec![] => ""; "empty")]
+    #[test_case(vec![Value::Null] => ""; "null")]
+    #[test_case(vec![Value::Null, Value::Null] => ","; "nulls")]
+    #[test_case(vec!["".into()] => r#""""#; "empty string")]
+    #[test_case(vec!["foo".into(), "bar baz".into()] => "foo,bar baz"; "strings")]
+    #[test_case(vec!["a,b".into(), "a\"b".into(), "a\nb".into()] => "\"a,b\",\"a\"\"b\",\"a\nb\""; "quoted")]
+    #[test_case(vec![1.into(), 1.5.into(), true.into(), Value::Null] => "1,1.5,TRUE,"; "scalars")]
+    #[test_case(vec![f64::NAN.into(), f64::INFINITY.into()] => "NaN,inf"; "floats")]
+    #[test_case(vec![Value::Array(vec![1.into(), "a".into()])] => r#""[1,""a""]""#; "array")]
+    fn encode_row(row: Vec<Value>) -> String {
+        super::encode_row(&row)
+    }
+
+    #[test]
+    fn encode_header() {
+        let columns = vec![
+            Label::Unqualified("id".into()),
+            Label::Qualified("t".into(), "a,b".into()),
+            Label::None,
+        ];
+        assert_eq!(super::encode_header(&columns), r#"id,"a,b",?"#);
+    }
+
+    #[test_case("" => Ok(vec![]); "empty")]
+    #[test_case("\n" => Ok(vec![(1, vec![None])]); "blank line")]
+    #[test_case("a,b\nc,d" => Ok(vec![
+        (1, vec![Some("a".into()), Some("b".into())]),
+        (2, vec![Some("c".into()), Some("d".into())]),
+    ]); "records")]
+    #[test_case("a,b\r\nc\r\n" => Ok(vec![
+        (1, vec![Some("a".into()), Some("b".into())]),
+        (2, vec![Some("c".into())]),
+    ]); "crlf")]
+    #[test_case(r#","",a"# => Ok(vec![(1, vec![None, Some("".into()), Some("a".into())])]); "null and empty")]
+    #[test_case(r#""a,b","a""b""# => Ok(vec![(1, vec![Some("a,b".into()), Some("a\"b".into())])]); "quoted")]
+    #[test_case("\"a\nb\",c\nd" => Ok(vec![
+        (1, vec![Some("a\nb".into()), Some("c".into())]),
+        (3, vec![Some("d".into())]),
+    ]); "multiline")]
+    #[test_case("a\n\"b" => Err("line 2: unterminated quoted field".into()); "unterminated")]
+    #[test_case(r#"a"b"# => Err("line 1: unexpected quote in field".into()); "unexpected quote")]
+    #[test_case(r#""a"b"# => Err("line 1: unexpected character after quoted field".into()); "after quote")]
+    fn reader(input: &str) -> std::result::Result<Vec<(usize, Vec<Option<String>>)>, String> {
+        Reader::new(input.as_bytes()).collect::<Result<_>>().map_err(|e| match e {
+            crate::error::Error::InvalidInput(message) => message,
+            error => error.to_string(),
+        })
+    }
+}
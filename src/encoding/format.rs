@@ -8,6 +8,7 @@ use itertools::Itertools as _;
 use regex::Regex;
 
 use super::{Key as _, Value as _, bincode};
+use crate::clock;
 use crate::raft;
 use crate::sql;
 use crate::storage::mvcc;
@@ -60,7 +61,11 @@ pub struct Raft<F: Formatter>(PhantomData<F>);
 impl<F: Formatter> Raft<F> {
     /// Formats a Raft entry.
     pub fn entry(entry: &raft::Entry) -> String {
-        let fmtcommand = entry.command.as_deref().map_or("None".to_string(), |c| F::value(&[], c));
+        let fmtcommand = match &entry.command {
+            raft::Command::Noop => "None".to_string(),
+            raft::Command::Write(command) => F::value(&[], command),
+            raft::Command::RemoveNode(id) => format!("RemoveNode({id})"),
+        };
         format!("{}@{} {fmtcommand}", entry.index, entry.term)
     }
 }
@@ -93,6 +98,10 @@ impl<F: Formatter> Formatter for Raft<F> {
                     Err(_) => Raw::bytes(value),
                 }
             }
+            raft::Key::TruncateIndex => match bincode::deserialize::<raft::Index>(value) {
+                Ok(index) => index.to_string(),
+                Err(_) => Raw::bytes(value),
+            },
             raft::Key::Entry(_) => match bincode::deserialize::<raft::Entry>(value) {
                 Ok(entry) => Self::entry(&entry),
                 Err(_) => Raw::bytes(value),
@@ -119,9 +128,37 @@ impl<F: Formatter> Formatter for MVCC<F> {
             mvcc::Key::Unversioned(innerkey) => {
                 format!("mvcc:Unversioned({})", F::key(&innerkey))
             }
-            mvcc::Key::NextVersion | mvcc::Key::TxnActive(_) | mvcc::Key::TxnActiveSnapshot(_) => {
-                format!("mvcc:{key:?}")
+            mvcc::Key::TxnSavepointWrite(version, seq, innerkey) => {
+                format!("mvcc:TxnSavepointWrite({version}, {seq}, {})", F::key(&innerkey))
+            }
+            mvcc::Key::Lock(innerkey) => format!("mvcc:Lock({})", F::key(&innerkey)),
+            mvcc::Key::LockWaiter(innerkey, seq) => {
+                format!("mvcc:LockWaiter({}, {seq})", F::key(&innerkey))
+            }
+            mvcc::Key::TxnLock(version, innerkey) => {
+                format!("mvcc:TxnLock({version}, {})", F::key(&innerkey))
+            }
+            mvcc::Key::TxnBulkWrite(version, innerkey) => {
+                format!("mvcc:TxnBulkWrite({version}, {})", F::key(&innerkey))
+            }
+            mvcc::Key::TxnCapture(version, prefix) => {
+                format!("mvcc:TxnCapture({version}, {})", F::key(&prefix))
             }
+            mvcc::Key::PrefixTombstone(version, prefix) => {
+                format!("mvcc:PrefixTombstone({version}, {})", F::key(&prefix))
+            }
+            mvcc::Key::NextVersion
+            | mvcc::Key::TxnActive(_)
+            | mvcc::Key::TxnActiveSnapshot(_)
+            | mvcc::Key::GCHorizon
+            | mvcc::Key::TxnSavepoint(_, _)
+            | mvcc::Key::TxnWaitsFor(_)
+            | mvcc::Key::NextCommitSeq
+            | mvcc::Key::CommitLog(_)
+            | mvcc::Key::Timestamp
+            | mvcc::Key::VersionTimestamp(_)
+            | mvcc::Key::CommitTimestamp(_)
+            | mvcc::Key::TxnExpires(_) => format!("mvcc:{key:?}"),
         }
     }
 
@@ -130,7 +167,12 @@ impl<F: Formatter> Formatter for MVCC<F> {
             return Raw::bytes(value); // invalid key
         };
         match key {
-            mvcc::Key::NextVersion => {
+            mvcc::Key::NextVersion
+            | mvcc::Key::GCHorizon
+            | mvcc::Key::Lock(_)
+            | mvcc::Key::LockWaiter(_, _)
+            | mvcc::Key::TxnWaitsFor(_)
+            | mvcc::Key::NextCommitSeq => {
                 let Ok(version) = bincode::deserialize::<mvcc::Version>(value) else {
                     return Raw::bytes(value);
                 };
@@ -142,17 +184,66 @@ impl<F: Formatter> Formatter for MVCC<F> {
                 };
                 format!("{{{}}}", active.iter().join(","))
             }
-            mvcc::Key::TxnActive(_) | mvcc::Key::TxnWrite(_, _) => Raw::bytes(value),
+            mvcc::Key::TxnActive(_) | mvcc::Key::TxnWrite(_, _) | mvcc::Key::TxnCapture(_, _) => {
+                Raw::bytes(value)
+            }
+            mvcc::Key::TxnLock(_, _) if value.is_empty() => Raw::bytes(value),
+            mvcc::Key::TxnLock(_, _) => match bincode::deserialize::<u64>(value) {
+                Ok(seq) => seq.to_string(),
+                Err(_) => Raw::bytes(value),
+            },
             mvcc::Key::Version(userkey, _) => match bincode::deserialize(value) {
                 Ok(Some(value)) => F::value(&userkey, value),
                 Ok(None) => "None".to_string(),
                 Err(_) => Raw::bytes(value),
             },
             mvcc::Key::Unversioned(userkey) => F::value(&userkey, value),
+            mvcc::Key::TxnBulkWrite(_, _) => F::key(value),
+            mvcc::Key::PrefixTombstone(_, _) => match bincode::deserialize::<Option<u64>>(value) {
+                Ok(savepoint) => format!("{savepoint:?}"),
+                Err(_) => Raw::bytes(value),
+            },
+            mvcc::Key::CommitLog(_) => match bincode::deserialize::<mvcc::Commit>(value) {
+                Ok(commit) => Self::commit(&commit),
+                Err(_) => Raw::bytes(value),
+            },
+            mvcc::Key::Timestamp
+            | mvcc::Key::VersionTimestamp(_)
+            | mvcc::Key::CommitTimestamp(_)
+            | mvcc::Key::TxnExpires(_) => match bincode::deserialize::<clock::Timestamp>(value) {
+                Ok(timestamp) => timestamp.to_string(),
+                Err(_) => Raw::bytes(value),
+            },
+            mvcc::Key::TxnSavepoint(_, _) => match bincode::deserialize::<String>(value) {
+                Ok(name) => name,
+                Err(_) => Raw::bytes(value),
+            },
+            mvcc::Key::TxnSavepointWrite(_, _, userkey) => {
+                match bincode::deserialize::<Option<Vec<u8>>>(value) {
+                    Ok(Some(value)) => match bincode::deserialize(&value) {
+                        Ok(Some(value)) => format!("Some({})", F::value(&userkey, value)),
+                        Ok(None) => "Some(None)".to_string(),
+                        Err(_) => Raw::bytes(&value),
+                    },
+                    Ok(None) => "None".to_string(),
+                    Err(_) => Raw::bytes(value),
+                }
+            }
         }
     }
 }
 
+impl<F: Formatter> MVCC<F> {
+    /// Formats a commit log entry.
+    pub fn commit(commit: &mvcc::Commit) -> String {
+        let mut writes = commit.writes.iter().map(|(key, value)| match value {
+            Some(value) => format!("{}={}", F::key(key), F::value(key, value)),
+            None => format!("{}=None", F::key(key)),
+        });
+        format!("v{} {}", commit.version, writes.join(" "))
+    }
+}
+
 /// Formats SQL keys/values.
 pub struct SQL;
 
@@ -168,6 +259,14 @@ impl SQL {
         let re = Regex::new(r#"\n\s*"#).expect("invalid regex");
         re.replace_all(&table.to_string(), " ").into_owned()
     }
+
+    /// Formats a user's privileges. The password hash is omitted.
+    fn user(user: sql::types::User) -> String {
+        let mut privileges = user.privileges.into_iter().map(|(table, privileges)| {
+            format!("{}: {}", table.as_deref().unwrap_or("*"), privileges.iter().join(","))
+        });
+        format!("{{{}}}", privileges.join(" "))
+    }
 }
 
 impl Formatter for SQL {
@@ -187,6 +286,9 @@ impl Formatter for SQL {
             sql::engine::Key::Row(table, id) => {
                 format!("sql:Row({table}, {id})")
             }
+            sql::engine::Key::User(name) => format!("sql:User({name})"),
+            sql::engine::Key::Idempotency(token) => format!("sql:Idempotency({token})"),
+            sql::engine::Key::SchemaFence(table) => format!("sql:SchemaFence({table})"),
         }
     }
 
@@ -220,6 +322,19 @@ impl Formatter for SQL {
                 };
                 Self::values(index)
             }
+            sql::engine::Key::User(_) => {
+                let Ok(user) = bincode::deserialize::<sql::types::User>(value) else {
+                    return Raw::bytes(value);
+                };
+                Self::user(user)
+            }
+            sql::engine::Key::Idempotency(_) => {
+                let Ok(result) = bincode::deserialize::<sql::engine::StatementResult>(value) else {
+                    return Raw::bytes(value);
+                };
+                format!("{result:?}")
+            }
+            sql::engine::Key::SchemaFence(_) => Raw::bytes(value),
         }
     }
 }
@@ -239,22 +354,63 @@ impl Formatter for SQLCommand {
         };
 
         let txn = match &write {
-            sql::engine::Write::Begin => None,
-            sql::engine::Write::Commit(txn)
+            sql::engine::Write::Begin(_)
+            | sql::engine::Write::BeginAsOfTimestamp(_)
+            | sql::engine::Write::GarbageCollect { .. }
+            | sql::engine::Write::Scrub
+            | sql::engine::Write::Checksum
+            | sql::engine::Write::BeginExpiring { .. }
+            | sql::engine::Write::ResolveExpired(_) => None,
+            sql::engine::Write::Commit { txn, .. }
             | sql::engine::Write::Rollback(txn)
             | sql::engine::Write::Delete { txn, .. }
             | sql::engine::Write::Insert { txn, .. }
             | sql::engine::Write::Update { txn, .. }
             | sql::engine::Write::CreateTable { txn, .. }
-            | sql::engine::Write::DropTable { txn, .. } => Some(txn),
+            | sql::engine::Write::DropTable { txn, .. }
+            | sql::engine::Write::CreateIndex { txn, .. }
+            | sql::engine::Write::CreateIndexOnline { txn, .. }
+            | sql::engine::Write::AdvanceIndex { txn, .. }
+            | sql::engine::Write::AbortIndex { txn, .. }
+            | sql::engine::Write::BackfillIndex { txn, .. }
+            | sql::engine::Write::CreateTrigger { txn, .. }
+            | sql::engine::Write::DropTrigger { txn, .. }
+            | sql::engine::Write::DropPartition { txn, .. }
+            | sql::engine::Write::TruncateTable { txn, .. }
+            | sql::engine::Write::Savepoint { txn, .. }
+            | sql::engine::Write::RollbackToSavepoint { txn, .. }
+            | sql::engine::Write::ReleaseSavepoint { txn, .. }
+            | sql::engine::Write::Lock { txn, .. }
+            | sql::engine::Write::CancelLockWaits(txn)
+            | sql::engine::Write::AbortTransaction { txn, .. }
+            | sql::engine::Write::BulkInsert { txn, .. }
+            | sql::engine::Write::SetChangefeed { txn, .. }
+            | sql::engine::Write::CreateUser { txn, .. }
+            | sql::engine::Write::DropUser { txn, .. }
+            | sql::engine::Write::Grant { txn, .. }
+            | sql::engine::Write::Revoke { txn, .. }
+            | sql::engine::Write::CommitIdempotent { txn, .. }
+            | sql::engine::Write::Heartbeat { txn, .. } => Some(txn),
         };
         let fmttxn =
             txn.filter(|t| !t.read_only).map_or("".to_string(), |t| format!("t{} ", t.version));
 
+        let online = matches!(write, sql::engine::Write::CreateIndexOnline { .. });
         let fmtcommand = match write {
-            sql::engine::Write::Begin => "BEGIN".to_string(),
-            sql::engine::Write::Commit(_) => "COMMIT".to_string(),
+            sql::engine::Write::Begin(_) => "BEGIN".to_string(),
+            sql::engine::Write::BeginExpiring { timeout, .. } => {
+                format!("BEGIN EXPIRING {}s", timeout.as_secs_f64())
+            }
+            sql::engine::Write::Heartbeat { timeout, .. } => {
+                format!("HEARTBEAT {}s", timeout.as_secs_f64())
+            }
+            sql::engine::Write::ResolveExpired(_) => "RESOLVE EXPIRED".to_string(),
+            sql::engine::Write::BeginAsOfTimestamp(timestamp) => {
+                format!("BEGIN READ ONLY AS OF SYSTEM TIME '{timestamp}'")
+            }
+            sql::engine::Write::Commit { .. } => "COMMIT".to_string(),
             sql::engine::Write::Rollback(_) => "ROLLBACK".to_string(),
+            sql::engine::Write::CommitIdempotent { token, .. } => format!("COMMIT TOKEN {token}"),
             sql::engine::Write::Delete { table, ids, .. } => {
                 format!("DELETE {table} {}", ids.iter().map(|id| id.to_string()).join(","))
             }
@@ -270,6 +426,79 @@ impl Formatter for SQLCommand {
             ),
             sql::engine::Write::CreateTable { schema, .. } => SQL::schema(schema),
             sql::engine::Write::DropTable { table, .. } => format!("DROP TABLE {table}"),
+            sql::engine::Write::CreateIndex { table, column, index_type, predicate, .. }
+            | sql::engine::Write::CreateIndexOnline {
+                table, column, index_type, predicate, ..
+            } => {
+                let mut sql = format!("CREATE INDEX ON {table} ({column})");
+                if index_type != sql::types::IndexType::BTree {
+                    sql += &format!(" USING {index_type}");
+                }
+                if let Some(predicate) = predicate {
+                    sql += &format!(" WHERE {}", predicate.format_constant());
+                }
+                if online {
+                    sql += " ONLINE";
+                }
+                sql
+            }
+            sql::engine::Write::AdvanceIndex { table, column, from, .. } => {
+                format!("ADVANCE INDEX ON {table} ({column}) FROM {from}")
+            }
+            sql::engine::Write::AbortIndex { table, column, .. } => {
+                format!("ABORT INDEX ON {table} ({column})")
+            }
+            sql::engine::Write::BackfillIndex { table, column, after, limit, .. } => {
+                let after = after.map_or("start".to_string(), |v| v.to_string());
+                format!("BACKFILL INDEX ON {table} ({column}) AFTER {after} LIMIT {limit}")
+            }
+            sql::engine::Write::CreateTrigger { table, trigger, .. } => {
+                let (name, timing, event) = (trigger.name, trigger.timing, trigger.event);
+                format!("CREATE TRIGGER {name} {timing} {event} ON {table} {}", trigger.action)
+            }
+            sql::engine::Write::DropTrigger { table, trigger, .. } => {
+                format!("DROP TRIGGER {trigger} ON {table}")
+            }
+            sql::engine::Write::DropPartition { table, partition, .. } => {
+                format!("ALTER TABLE {table} DROP PARTITION {partition}")
+            }
+            sql::engine::Write::TruncateTable { table, .. } => format!("TRUNCATE TABLE {table}"),
+            sql::engine::Write::GarbageCollect { retention } => format!("GC RETENTION {retention}"),
+            sql::engine::Write::Scrub => "SCRUB REPAIR".to_string(),
+            sql::engine::Write::Checksum => "CHECKSUM".to_string(),
+            sql::engine::Write::Savepoint { name, .. } => format!("SAVEPOINT {name}"),
+            sql::engine::Write::RollbackToSavepoint { name, .. } => {
+                format!("ROLLBACK TO SAVEPOINT {name}")
+            }
+            sql::engine::Write::ReleaseSavepoint { name, .. } => {
+                format!("RELEASE SAVEPOINT {name}")
+            }
+            sql::engine::Write::Lock { table, ids, .. } => {
+                format!("LOCK {table} {}", ids.iter().join(","))
+            }
+            sql::engine::Write::CancelLockWaits(_) => "CANCEL LOCK WAITS".to_string(),
+            sql::engine::Write::AbortTransaction { version, .. } => {
+                format!("ABORT TRANSACTION {version}")
+            }
+            sql::engine::Write::BulkInsert { table, rows, .. } => {
+                format!("BULK INSERT {table} {} rows", rows.len())
+            }
+            sql::engine::Write::SetChangefeed { table, changefeed: true, .. } => {
+                format!("CREATE CHANGEFEED FOR TABLE {table}")
+            }
+            sql::engine::Write::SetChangefeed { table, changefeed: false, .. } => {
+                format!("DROP CHANGEFEED FOR TABLE {table}")
+            }
+            sql::engine::Write::CreateUser { user, .. } => format!("CREATE USER {}", user.name),
+            sql::engine::Write::DropUser { name, .. } => format!("DROP USER {name}"),
+            sql::engine::Write::Grant { user, table, privileges, .. } => {
+                let table = table.as_deref().unwrap_or("*");
+                format!("GRANT {} ON {table} TO {user}", privileges.iter().join(", "))
+            }
+            sql::engine::Write::Revoke { user, table, privileges, .. } => {
+                let table = table.as_deref().unwrap_or("*");
+                format!("REVOKE {} ON {table} FROM {user}", privileges.iter().join(", "))
+            }
         };
         format!("{fmttxn}{fmtcommand}")
     }
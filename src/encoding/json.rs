@@ -0,0 +1,39 @@
+//! JSON lines encoding of SQL rows, with one JSON object per row keyed by
+//! column label in column order. Values are converted via Value::to_json(), and
+//! can be decoded via Value::from_json().
+
+use crate::error::Result;
+use crate::sql::types::{Label, Value};
+
+/// Encodes a row as a JSON object, without a trailing newline. The keys are
+/// written in column order, unlike serde_json's Map which would sort them.
+pub fn encode_row(columns: &[Label], row: Vec<Value>) -> Result<String> {
+    let mut object = String::from("{");
+    for (i, (label, value)) in columns.iter().zip(row).enumerate() {
+        if i > 0 {
+            object.push(',');
+        }
+        object.push_str(&serde_json::to_string(label.as_header())?);
+        object.push(':');
+        object.push_str(&value.to_json()?.to_string());
+    }
+    object.push('}');
+    Ok(object)
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use super::*;
+
+    #[test_case(vec![] => "{}"; "empty")]
+    #[test_case(vec![Value::Null, 1.into()] => r#"{"b":null,"a":1}"#; "column order")]
+    #[test_case(vec!["x\"y".into(), f64::NAN.into()] => r#"{"b":"x\"y","a":"NaN"}"#; "escaped")]
+    #[test_case(vec![Value::Array(vec![true.into()])] => r#"{"b":[true]}"#; "array")]
+    fn encode_row(row: Vec<Value>) -> String {
+        let columns =
+            vec![Label::Unqualified("b".into()), Label::Qualified("t".into(), "a".into())];
+        super::encode_row(&columns, row).expect("encode failed")
+    }
+}
@@ -64,17 +64,22 @@ pub fn serialize<T: Serialize>(key: &T) -> Vec<u8> {
 
 /// Deserializes a key from a binary Keycode representation.
 pub fn deserialize<'a, T: Deserialize<'a>>(input: &'a [u8]) -> Result<T> {
-    let mut deserializer = Deserializer::from_bytes(input);
-    let t = T::deserialize(&mut deserializer)?;
-    if !deserializer.input.is_empty() {
-        return errdata!(
-            "unexpected trailing bytes {:x?} at end of key {input:x?}",
-            deserializer.input,
-        );
+    let (t, rest) = deserialize_prefix(input)?;
+    if !rest.is_empty() {
+        return errdata!("unexpected trailing bytes {rest:x?} at end of key {input:x?}");
     }
     Ok(t)
 }
 
+/// Deserializes a key from the start of a binary Keycode representation,
+/// returning it along with the remaining bytes. Used to decode concatenated
+/// keys, e.g. in [`super::tuple`].
+pub fn deserialize_prefix<'a, T: Deserialize<'a>>(input: &'a [u8]) -> Result<(T, &'a [u8])> {
+    let mut deserializer = Deserializer::from_bytes(input);
+    let t = T::deserialize(&mut deserializer)?;
+    Ok((t, deserializer.input))
+}
+
 /// Generates a key range for a key prefix, used e.g. for prefix scans.
 ///
 /// The exclusive end bound is generated by adding 1 to the value of the last
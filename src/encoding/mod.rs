@@ -1,11 +1,17 @@
-//! Binary data encodings.
+//! Data encodings.
 //!
 //! * keycode: used for keys in the key/value store.
+//! * tuple: order-preserving keys for tuples of SQL values, built on keycode.
 //! * bincode: used for values in the key/value store and network protocols.
+//! * csv: used to import and export SQL rows.
+//! * json: used to export SQL rows as JSON lines.
 
 pub mod bincode;
+pub mod csv;
 pub mod format;
+pub mod json;
 pub mod keycode;
+pub mod tuple;
 
 use std::cmp::{Eq, Ord};
 use std::collections::{BTreeSet, HashSet};
@@ -0,0 +1,262 @@
+//! Tuple is an order-preserving binary encoding of tuples of SQL values, for
+//! use as keys in key/value stores. The encoded keys sort like the tuples, by
+//! comparing values column by column, where each column can be ordered in
+//! ascending or descending order with NULLs first or last. For example, an
+//! index on (a DESC, b ASC NULLS LAST) can be scanned in key order to emit rows
+//! in that order.
+//!
+//! Each value is encoded as follows:
+//!
+//! * NULL: `0x00` if NULLs are ordered first, `0xff` if last.
+//! * Ascending: the value's [`super::keycode`] encoding, i.e. its variant
+//!   index as a byte followed by the encoded content.
+//! * Descending: the ascending encoding with all bits inverted.
+//!
+//! The keycode encoding of a value is prefix-free (no encoding is a prefix of
+//! another), so inverting it reverses its order, and values can simply be
+//! concatenated. Variant indexes are in the range `0x01` to `0x08`, and thus
+//! `0xf7` to `0xfe` when inverted, so they can't collide with NULL markers.
+//! An ascending column with NULLs first is encoded exactly like keycode, and
+//! thus like the SQL engine's primary keys and index values.
+//!
+//! Values of the same type order like [`Value::cmp`] for booleans, integers,
+//! floats, strings, and timestamps. Intervals and JSON documents are encoded
+//! uniquely, so they can be used for equality lookups, but don't order like
+//! comparisons.
+//! Values of different types order by type, in variant order: this differs
+//! from [`Value::cmp`], which orders integers and floats by numeric value.
+//! Floats must be normalized (see [`Value::normalize`]), otherwise -0.0 and
+//! 0.0 have different encodings. Arrays can't be encoded.
+
+use std::ops::Bound;
+
+use super::keycode;
+use crate::error::Result;
+use crate::sql::types::Value;
+use crate::{errdata, errinput};
+
+/// The NULL marker when NULLs are ordered first.
+const NULL_FIRST: u8 = 0x00;
+
+/// The NULL marker when NULLs are ordered last.
+const NULL_LAST: u8 = 0xff;
+
+/// The order of a tuple column.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Order {
+    /// If true, values are ordered in descending order.
+    pub descending: bool,
+    /// If true, NULLs are ordered after all other values.
+    pub nulls_last: bool,
+}
+
+impl Order {
+    /// Ascending order with NULLs first, like SQL keys.
+    pub const ASC: Self = Self { descending: false, nulls_last: false };
+    /// Descending order with NULLs last, i.e. the reverse of ASC.
+    pub const DESC: Self = Self { descending: true, nulls_last: true };
+
+    /// Returns the order with NULLs ordered first.
+    pub fn nulls_first(self) -> Self {
+        Self { nulls_last: false, ..self }
+    }
+
+    /// Returns the order with NULLs ordered last.
+    pub fn nulls_last(self) -> Self {
+        Self { nulls_last: true, ..self }
+    }
+}
+
+/// Encodes a tuple of values, using the given column orders. There can be
+/// fewer values than orders, in which case a prefix of the tuple is encoded
+/// (e.g. for use with prefix_range).
+pub fn encode(values: &[Value], orders: &[Order]) -> Result<Vec<u8>> {
+    assert!(values.len() <= orders.len(), "more values than orders");
+    let mut output = Vec::new();
+    for (value, order) in values.iter().zip(orders) {
+        encode_value(value, *order, &mut output)?;
+    }
+    Ok(output)
+}
+
+/// Encodes a single value into the output.
+fn encode_value(value: &Value, order: Order, output: &mut Vec<u8>) -> Result<()> {
+    match value {
+        Value::Null if order.nulls_last => output.push(NULL_LAST),
+        Value::Null => output.push(NULL_FIRST),
+        Value::Array(_) => return errinput!("can't encode array {value} in key"),
+        value => {
+            let start = output.len();
+            output.extend(keycode::serialize(value));
+            if order.descending {
+                output[start..].iter_mut().for_each(|b| *b = !*b);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Decodes a tuple of values, using the given column orders. The tuple must
+/// have exactly one value per order.
+pub fn decode(mut input: &[u8], orders: &[Order]) -> Result<Vec<Value>> {
+    let mut values = Vec::with_capacity(orders.len());
+    for order in orders {
+        let (value, rest) = decode_value(input, *order)?;
+        values.push(value);
+        input = rest;
+    }
+    if !input.is_empty() {
+        return errdata!("unexpected trailing bytes {input:x?} at end of tuple");
+    }
+    Ok(values)
+}
+
+/// Decodes a single value from the start of the input, returning it along with
+/// the remaining input.
+fn decode_value(input: &[u8], order: Order) -> Result<(Value, &[u8])> {
+    let null = if order.nulls_last { NULL_LAST } else { NULL_FIRST };
+    let (value, len) = match input.first() {
+        None => return errdata!("unexpected end of tuple"),
+        Some(&b) if b == null => return Ok((Value::Null, &input[1..])),
+        Some(_) if !order.descending => {
+            let (value, rest) = keycode::deserialize_prefix(input)?;
+            (value, input.len() - rest.len())
+        }
+        // For descending values, invert the remaining input to decode the
+        // value, and use the length of the rest to find the end of the value.
+        Some(_) => {
+            let inverted: Vec<u8> = input.iter().map(|b| !b).collect();
+            let (value, rest) = keycode::deserialize_prefix(&inverted)?;
+            (value, input.len() - rest.len())
+        }
+    };
+    if value == Value::Null {
+        return errdata!("invalid NULL marker {:x?}", input[0]);
+    }
+    Ok((value, &input[len..]))
+}
+
+/// Returns the key range of all tuples that start with the given prefix
+/// values, e.g. for an index scan on the leading columns of a tuple.
+pub fn prefix_range(
+    values: &[Value],
+    orders: &[Order],
+) -> Result<(Bound<Vec<u8>>, Bound<Vec<u8>>)> {
+    Ok(keycode::prefix_range(&encode(values, orders)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ops::RangeBounds as _;
+
+    use itertools::Itertools as _;
+
+    use super::*;
+    use crate::sql::types::{Interval, Timestamp};
+
+    /// Values in ascending order, all of the same type except NULL.
+    fn sorted_values() -> Vec<Vec<Value>> {
+        vec![
+            vec![Value::Boolean(false), Value::Boolean(true)],
+            [i64::MIN, -1, 0, 1, 255, 256, i64::MAX].map(Value::Integer).to_vec(),
+            [f64::NEG_INFINITY, -1.5, -0.0001, 0.0, 0.0001, 1.5, f64::INFINITY, f64::NAN]
+                .map(Value::Float)
+                .to_vec(),
+            ["", "\0", "\0\0", "a", "a\0", "ab", "b", "ü"]
+                .map(|s| Value::String(s.to_string()))
+                .to_vec(),
+            [
+                "0001-01-01 00:00",
+                "1969-12-31 23:59:59.999999",
+                "1970-01-01 00:00",
+                "2024-02-29 12:00",
+            ]
+            .map(|s| Value::Timestamp(Timestamp::parse(s).expect("invalid timestamp")))
+            .to_vec(),
+        ]
+    }
+
+    /// Ascending NULLs-first tuples are encoded like keycode.
+    #[test]
+    fn keycode_compatible() -> Result<()> {
+        let values = vec![
+            Value::Null,
+            Value::Boolean(true),
+            Value::Integer(-7),
+            Value::Float(2.5),
+            Value::String("foo".into()),
+            Value::Interval(Interval::new(1, 2, 3)),
+            Value::Timestamp(Timestamp::parse("2024-02-29 12:00")?),
+        ];
+        let encoded = encode(&values, &[Order::ASC; 7])?;
+        assert_eq!(encoded, keycode::serialize(&values));
+        assert_eq!(decode(&encoded, &[Order::ASC; 7])?, values);
+        Ok(())
+    }
+
+    /// Encoded values order like the values, for all orders, and round-trip.
+    #[test]
+    fn order() -> Result<()> {
+        let orders = [Order::ASC, Order::ASC.nulls_last(), Order::DESC, Order::DESC.nulls_first()];
+        for (values, order) in sorted_values().into_iter().cartesian_product(orders) {
+            let mut expect = values.clone();
+            if order.descending {
+                expect.reverse();
+            }
+            match order.nulls_last {
+                true => expect.push(Value::Null),
+                false => expect.insert(0, Value::Null),
+            }
+
+            let mut encoded: Vec<_> = values
+                .iter()
+                .chain([&Value::Null])
+                .map(|v| encode(&[v.clone()], &[order]))
+                .try_collect()?;
+            encoded.sort();
+            let decoded: Vec<_> =
+                encoded.iter().map(|key| decode(key, &[order])).flatten_ok().try_collect()?;
+            assert_eq!(decoded, expect, "{order:?}");
+        }
+        Ok(())
+    }
+
+    /// Tuples order column by column, and prefix ranges contain the tuples
+    /// with the given prefix.
+    #[test]
+    fn tuples() -> Result<()> {
+        let orders = [Order::DESC, Order::ASC.nulls_last()];
+        let string = |s: &str| Value::String(s.to_string());
+        let tuples = vec![
+            vec![string("b"), string("a")],
+            vec![string("b"), string("ba")],
+            vec![string("ab"), string("a")],
+            vec![string("a"), string("\0")],
+            vec![string("a"), string("a")],
+            vec![string("a"), Value::Null],
+            vec![string(""), string("a")],
+            vec![Value::Null, string("a")],
+        ];
+        let encoded: Vec<_> = tuples.iter().map(|t| encode(t, &orders)).try_collect()?;
+        assert!(encoded.is_sorted());
+        for (key, tuple) in encoded.iter().zip(&tuples) {
+            assert_eq!(&decode(key, &orders)?, tuple);
+        }
+
+        let range = prefix_range(&[string("a")], &orders)?;
+        let matches = encoded.iter().filter(|key| range.contains(*key)).count();
+        assert_eq!(matches, 3);
+        Ok(())
+    }
+
+    /// Arrays and invalid inputs error.
+    #[test]
+    fn errors() {
+        assert!(encode(&[Value::Array(vec![])], &[Order::ASC]).is_err());
+        assert!(decode(&[], &[Order::ASC]).is_err());
+        assert!(decode(&[0x00, 0x00], &[Order::ASC]).is_err());
+        assert!(decode(&[0x00], &[Order::DESC]).is_err());
+        assert!(decode(&[0xff], &[Order::DESC.nulls_first()]).is_err());
+        assert!(decode(&[0x00], &[Order::ASC.nulls_last()]).is_err());
+    }
+}
@@ -2,24 +2,63 @@ use std::fmt::Display;
 
 use serde::{Deserialize, Serialize};
 
-/// toyDB errors.
+use crate::raft::NodeID;
+
+/// toyDB errors. Errors are returned to clients as is, so they can be handled
+/// programmatically by variant. Broadly, they fall into these categories:
+///
+/// * Transient errors, which can be retried (see `is_retryable()`): Abort,
+///   Busy, NotLeader, and the transaction conflicts Deadlock, Locked, and
+///   Serialization.
+/// * Rejected requests, which will fail again if retried: Constraint,
+///   InvalidInput, ReadOnly, and Truncated.
+/// * Cancelled statements: Cancelled and Timeout.
+/// * Node failures: InvalidData (i.e. data corruption) and IO.
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum Error {
     /// The operation was aborted and must be retried. This typically happens
     /// with e.g. Raft leader changes. This is used instead of implementing
     /// complex retry logic and replay protection in Raft.
     Abort,
+    /// The server is overloaded and rejected the connection or request, e.g.
+    /// because of connection or rate limits. Contains the reason. The request
+    /// can be retried later.
+    Busy(String),
+    /// The statement was cancelled by the client.
+    Cancelled,
+    /// The transaction was aborted because it would deadlock with other
+    /// transactions waiting for locks. It must be rolled back and retried.
+    Deadlock,
     /// Invalid data, typically decoding errors or unexpected internal values.
+    /// This indicates data corruption or a bug.
     InvalidData(String),
     /// Invalid user input, typically parser or query errors.
     InvalidInput(String),
     /// An IO error.
     IO(String),
+    /// A lock is held by the transaction with the given version, and the
+    /// operation should be retried when it completes. SQL sessions wait for
+    /// the lock internally, and return this once the lock timeout expires.
+    Locked(u64),
     /// A write was attempted in a read-only transaction.
     ReadOnly,
     /// A write transaction conflicted with a different writer and lost. The
     /// transaction must be retried.
     Serialization,
+    /// The statement exceeded the statement timeout and was cancelled.
+    Timeout,
+    /// A write violated a table constraint, e.g. a primary key, unique,
+    /// foreign key, or NOT NULL constraint.
+    Constraint(String),
+    /// The request was sent to a Raft node that isn't the leader and couldn't
+    /// forward it, e.g. during an election. Contains the leader, if known.
+    /// Unlike Abort, the request definitely wasn't executed, and can be
+    /// retried.
+    NotLeader(Option<NodeID>),
+    /// The requested Raft log entries may have been removed by log truncation.
+    /// Entries are only retained from the given index, e.g. for incremental
+    /// backups.
+    Truncated(u64),
 }
 
 impl std::error::Error for Error {}
@@ -28,11 +67,22 @@ impl Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
             Error::Abort => write!(f, "operation aborted"),
+            Error::Busy(msg) => write!(f, "server busy: {msg}"),
+            Error::Cancelled => write!(f, "statement cancelled"),
+            Error::Deadlock => write!(f, "deadlock detected, retry transaction"),
             Error::InvalidData(msg) => write!(f, "invalid data: {msg}"),
             Error::InvalidInput(msg) => write!(f, "invalid input: {msg}"),
             Error::IO(msg) => write!(f, "io error: {msg}"),
+            Error::Locked(version) => write!(f, "locked by transaction {version}"),
             Error::ReadOnly => write!(f, "read-only transaction"),
             Error::Serialization => write!(f, "serialization failure, retry transaction"),
+            Error::Timeout => write!(f, "statement timed out"),
+            Error::Constraint(msg) => write!(f, "constraint violation: {msg}"),
+            Error::NotLeader(Some(leader)) => write!(f, "not leader, leader is node {leader}"),
+            Error::NotLeader(None) => write!(f, "not leader, no leader known"),
+            Error::Truncated(index) => {
+                write!(f, "Raft log truncated, entries are only retained from index {index}")
+            }
         }
     }
 }
@@ -49,6 +99,13 @@ impl Error {
             // we consider them non-deterministic in case an abort should happen
             // unexpectedly below Raft.
             Error::Abort => false,
+            // Admission control only happens above Raft. See Abort.
+            Error::Busy(_) => false,
+            // Cancellation only happens during statement execution above Raft,
+            // never during application. See Abort.
+            Error::Cancelled => false,
+            // Deadlocks are detected deterministically from the lock state.
+            Error::Deadlock => true,
             // Possible data corruption local to this node.
             Error::InvalidData(_) => false,
             // Input errors are (likely) deterministic. They might not be in
@@ -56,12 +113,53 @@ impl Error {
             Error::InvalidInput(_) => true,
             // IO errors are typically local to the node (e.g. faulty disk).
             Error::IO(_) => false,
+            // Lock waits are deterministic, like write conflicts.
+            Error::Locked(_) => true,
             // Write commands in read-only transactions are deterministic.
             Error::ReadOnly => true,
             // Write conflicts are determinstic.
             Error::Serialization => true,
+            // Statement timeouts only happen above Raft. See Cancelled.
+            Error::Timeout => false,
+            // Constraint checks are deterministic on the data, like input
+            // errors.
+            Error::Constraint(_) => true,
+            // Leadership only matters above Raft. See Abort.
+            Error::NotLeader(_) => false,
+            // Log truncation is local to the node, and never happens during
+            // application.
+            Error::Truncated(_) => false,
         }
     }
+
+    /// Returns whether the error is transient, such that the transaction (or
+    /// statement) can be retried and may succeed. Used by Client::with_retry.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            // Leader changes and the like.
+            Error::Abort | Error::NotLeader(_) => true,
+            // Rejected by admission control, without executing anything.
+            Error::Busy(_) => true,
+            // Conflicts with concurrent transactions, which roll back.
+            Error::Deadlock | Error::Serialization => true,
+            // Lock waits time out in the server, and can be retried.
+            Error::Locked(_) => true,
+            Error::Cancelled
+            | Error::Constraint(_)
+            | Error::InvalidData(_)
+            | Error::InvalidInput(_)
+            | Error::IO(_)
+            | Error::ReadOnly
+            | Error::Timeout
+            | Error::Truncated(_) => false,
+        }
+    }
+}
+
+/// Constructs an Error::Constraint for the given format string.
+#[macro_export]
+macro_rules! errconstraint {
+    ($($args:tt)*) => { $crate::error::Error::Constraint(format!($($args)*)).into() };
 }
 
 /// Constructs an Error::InvalidData for the given format string.
@@ -163,18 +261,42 @@ impl From<log::SetLoggerError> for Error {
     }
 }
 
+impl From<rcgen::Error> for Error {
+    fn from(err: rcgen::Error) -> Self {
+        Error::InvalidInput(err.to_string())
+    }
+}
+
 impl From<regex::Error> for Error {
     fn from(err: regex::Error) -> Self {
         panic!("{err}") // faulty code
     }
 }
 
+impl From<rustls::Error> for Error {
+    fn from(err: rustls::Error) -> Self {
+        Error::IO(err.to_string())
+    }
+}
+
+impl From<rustls::server::VerifierBuilderError> for Error {
+    fn from(err: rustls::server::VerifierBuilderError) -> Self {
+        Error::InvalidInput(err.to_string())
+    }
+}
+
 impl From<rustyline::error::ReadlineError> for Error {
     fn from(err: rustyline::error::ReadlineError) -> Self {
         Error::IO(err.to_string())
     }
 }
 
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Self {
+        Error::InvalidInput(err.to_string())
+    }
+}
+
 impl From<std::array::TryFromSliceError> for Error {
     fn from(err: std::array::TryFromSliceError) -> Self {
         Error::InvalidData(err.to_string())
@@ -205,6 +327,12 @@ impl From<std::num::TryFromIntError> for Error {
     }
 }
 
+impl From<tonic::transport::Error> for Error {
+    fn from(err: tonic::transport::Error) -> Self {
+        Error::IO(err.to_string())
+    }
+}
+
 impl From<std::string::FromUtf8Error> for Error {
     fn from(err: std::string::FromUtf8Error) -> Self {
         Error::InvalidData(err.to_string())
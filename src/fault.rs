@@ -0,0 +1,124 @@
+//! Fault injection for chaos testing. A FaultInjector is shared by a node's
+//! Raft transport and storage engines (see storage::Faulty), and its faults
+//! can be changed at runtime via the hidden Request::InjectFaults admin
+//! request, if fault injection is enabled in the node config. All faults are
+//! disabled by default.
+//!
+//! Injected faults are not deterministic, since they're used against real
+//! clusters. For reproducible fault tests of Raft itself, see the Raft
+//! simulation tests.
+
+use std::collections::BTreeSet;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use rand::Rng as _;
+use serde::{Deserialize, Serialize};
+
+use crate::errinput;
+use crate::error::Result;
+use crate::raft::NodeID;
+
+/// Faults to inject into a node.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Faults {
+    /// Peers to partition the node from. Raft messages to and from them are
+    /// dropped.
+    pub partition: BTreeSet<NodeID>,
+    /// The probability of dropping an outbound Raft message, in [0,1].
+    pub drop: f64,
+    /// The probability of sending an outbound Raft message twice, in [0,1].
+    pub duplicate: f64,
+    /// How long to delay each outbound Raft message. Since messages to a peer
+    /// are sent in order, this also delays subsequent messages.
+    pub delay: Duration,
+    /// If true, storage engine flushes (i.e. fsyncs) fail with an IO error.
+    pub fail_flush: bool,
+    /// The probability of corrupting a value read from a storage engine, in
+    /// [0,1].
+    pub corrupt_read: f64,
+}
+
+impl Faults {
+    /// Validates the faults.
+    pub fn validate(&self) -> Result<()> {
+        for (name, p) in [
+            ("drop", self.drop),
+            ("duplicate", self.duplicate),
+            ("corrupt_read", self.corrupt_read),
+        ] {
+            if !(0.0..=1.0).contains(&p) {
+                return errinput!("{name} probability must be between 0 and 1, got {p}");
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Injects faults into a node's Raft transport and storage engines. Cloning
+/// it returns a handle to the same faults.
+#[derive(Clone, Debug, Default)]
+pub struct FaultInjector(Arc<RwLock<Faults>>);
+
+impl FaultInjector {
+    /// Creates a new fault injector, with all faults disabled.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the current faults.
+    pub fn get(&self) -> Faults {
+        self.read().clone()
+    }
+
+    /// Sets the faults to inject, replacing the current ones.
+    pub fn set(&self, faults: Faults) -> Result<()> {
+        faults.validate()?;
+        *self.0.write().expect("lock poisoned") = faults;
+        Ok(())
+    }
+
+    /// Returns the number of times to send an outbound Raft message to the
+    /// given peer: 0 if it's dropped, 2 if it's duplicated, otherwise 1.
+    pub fn send_count(&self, to: NodeID) -> usize {
+        let faults = self.read();
+        if faults.partition.contains(&to) || Self::chance(faults.drop) {
+            return 0;
+        }
+        if Self::chance(faults.duplicate) {
+            return 2;
+        }
+        1
+    }
+
+    /// Returns how long to delay an outbound Raft message.
+    pub fn send_delay(&self) -> Duration {
+        self.read().delay
+    }
+
+    /// Returns true if an inbound Raft message from the given peer should be
+    /// received, i.e. the peer isn't partitioned away.
+    pub fn receive(&self, from: NodeID) -> bool {
+        !self.read().partition.contains(&from)
+    }
+
+    /// Returns true if a storage engine flush should fail.
+    pub fn fail_flush(&self) -> bool {
+        self.read().fail_flush
+    }
+
+    /// Returns true if a storage engine read should be corrupted.
+    pub fn corrupt_read(&self) -> bool {
+        Self::chance(self.read().corrupt_read)
+    }
+
+    /// Returns true with the given probability.
+    fn chance(p: f64) -> bool {
+        p > 0.0 && rand::thread_rng().gen_bool(p)
+    }
+
+    /// Read-locks the faults.
+    fn read(&self) -> std::sync::RwLockReadGuard<'_, Faults> {
+        self.0.read().expect("lock poisoned")
+    }
+}
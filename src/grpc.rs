@@ -0,0 +1,514 @@
+//! A gRPC front end, which allows clients to be generated for other languages
+//! from the protobuf definitions in proto/toydb.proto. It serves two services:
+//!
+//! * Sql: executes SQL statements, either as single statements in a new
+//!   session via Execute, or in a long-lived session via the bidirectional
+//!   Session stream, which allows explicit transactions across requests.
+//!
+//! * Admin: cluster administration, i.e. server status, table schemas, and
+//!   garbage collection.
+//!
+//! Like in pgwire and http, $1-style parameters are substituted into the query
+//! text as SQL literals. If the server has authentication enabled, calls must
+//! authenticate via HTTP basic authentication in the "authorization" metadata.
+//!
+//! The server is implemented with tonic, running on a separate Tokio runtime.
+//! Sessions are blocking, so each call or session stream is executed in a
+//! blocking Tokio thread.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use itertools::Itertools as _;
+use log::{debug, error};
+use tokio::sync::{mpsc, watch};
+use tokio_stream::wrappers::{ReceiverStream, TcpListenerStream};
+use tonic::metadata::MetadataMap;
+use tonic::{Code, Request, Response, Status, Streaming};
+
+use crate::error::{Error, Result};
+use crate::http::basic_credentials;
+use crate::pgwire::{bind_params, bool_literal, float_literal, string_literal};
+use crate::raft;
+use crate::sql::engine::{Catalog as _, Raft, Session, StatementResult};
+use crate::sql::types::{Label, Row, Value};
+use crate::{errdata, errinput};
+
+/// How long to wait for blocking session threads when shutting down.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The generated protobuf messages and gRPC services, along with tonic
+/// clients for them.
+#[allow(clippy::all)]
+pub mod proto {
+    tonic::include_proto!("toydb");
+}
+
+use proto::session_request::Request as SessionRequest;
+use proto::session_response::Response as SessionResponse;
+use proto::statement_result::Result as ResultKind;
+
+/// Creates new sessions, configured like regular client sessions.
+type NewSession = Arc<dyn for<'a> Fn(&'a Raft) -> Session<'a, Raft> + Send + Sync>;
+
+/// Serves gRPC clients on the given listener until it fails or the shutdown
+/// channel disconnects, using TLS if given a config. Runs a Tokio runtime on
+/// the calling thread. On shutdown, stops accepting connections and gives
+/// in-flight calls and sessions the grace period to complete.
+#[allow(clippy::too_many_arguments)]
+pub fn serve(
+    listener: std::net::TcpListener,
+    tls: Option<Arc<rustls::ServerConfig>>,
+    id: raft::NodeID,
+    sql_engine: Raft,
+    new_session: impl for<'a> Fn(&'a Raft) -> Session<'a, Raft> + Send + Sync + 'static,
+    gc_retention: u64,
+    shutdown: crossbeam::channel::Receiver<()>,
+    grace: Duration,
+) -> Result<()> {
+    let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+    std::thread::spawn(move || {
+        _ = shutdown.recv();
+        _ = shutdown_tx.send(true);
+    });
+    let result = runtime.block_on(async move {
+        let shutdown = |mut shutdown_rx: watch::Receiver<bool>| async move {
+            _ = shutdown_rx.wait_for(|shutdown| *shutdown).await;
+        };
+        let signal = shutdown(shutdown_rx.clone());
+        let serve = tokio::spawn(async move {
+            listener.set_nonblocking(true)?;
+            let listener = tokio::net::TcpListener::from_std(listener)?;
+            let service =
+                Service { id, sql_engine, new_session: Arc::new(new_session), gc_retention };
+            let router = tonic::transport::Server::builder()
+                .add_service(proto::sql_server::SqlServer::new(service.clone()))
+                .add_service(proto::admin_server::AdminServer::new(service));
+            let Some(tls) = tls else {
+                let incoming = TcpListenerStream::new(listener);
+                return Ok(router.serve_with_incoming_shutdown(incoming, signal).await?);
+            };
+            // gRPC requires HTTP/2, which TLS clients negotiate via ALPN. Accept
+            // and handshake connections concurrently, passing them to the server.
+            let mut tls = (*tls).clone();
+            tls.alpn_protocols = vec![b"h2".to_vec()];
+            let acceptor = tokio_rustls::TlsAcceptor::from(Arc::new(tls));
+            let (stream_tx, stream_rx) = mpsc::channel(16);
+            tokio::spawn(async move {
+                loop {
+                    let (socket, peer) = match listener.accept().await {
+                        Ok((socket, peer)) => (socket, peer),
+                        Err(err) => {
+                            error!("gRPC client accept failed: {err}");
+                            continue;
+                        }
+                    };
+                    let (acceptor, stream_tx) = (acceptor.clone(), stream_tx.clone());
+                    tokio::spawn(async move {
+                        match acceptor.accept(socket).await {
+                            Ok(stream) => _ = stream_tx.send(Ok::<_, std::io::Error>(stream)).await,
+                            Err(err) => error!("gRPC client {peer} TLS handshake failed: {err}"),
+                        }
+                    });
+                }
+            });
+            let incoming = ReceiverStream::new(stream_rx);
+            Ok(router.serve_with_incoming_shutdown(incoming, signal).await?)
+        });
+        // Once shut down, give in-flight calls the grace period to complete.
+        let abort = serve.abort_handle();
+        tokio::spawn(async move {
+            shutdown(shutdown_rx).await;
+            tokio::time::sleep(grace).await;
+            abort.abort();
+        });
+        match serve.await {
+            Ok(result) => result,
+            Err(err) if err.is_cancelled() => Ok(()),
+            Err(err) => Err(Error::IO(err.to_string())),
+        }
+    });
+    // Don't wait for blocking session threads that outlived the grace period.
+    runtime.shutdown_timeout(SHUTDOWN_TIMEOUT);
+    result
+}
+
+/// The gRPC service implementation, for both the Sql and Admin services.
+#[derive(Clone)]
+struct Service {
+    /// The local Raft node ID.
+    id: raft::NodeID,
+    /// The SQL engine.
+    sql_engine: Raft,
+    /// Creates new sessions.
+    new_session: NewSession,
+    /// The number of recent MVCC versions retained by garbage collection.
+    gc_retention: u64,
+}
+
+impl Service {
+    /// Runs a closure with a new, authenticated session in a blocking thread.
+    async fn with_session<T, F>(
+        &self,
+        metadata: &MetadataMap,
+        f: F,
+    ) -> std::result::Result<T, Status>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Session<'_, Raft>) -> Result<T> + Send + 'static,
+    {
+        let credentials = credentials(metadata)?;
+        let (sql_engine, new_session) = (self.sql_engine.clone(), self.new_session.clone());
+        tokio::task::spawn_blocking(move || {
+            let mut session = new_session(&sql_engine);
+            authenticate(&mut session, credentials)?;
+            f(&mut session).map_err(status)
+        })
+        .await
+        .map_err(|err| Status::internal(err.to_string()))?
+    }
+}
+
+#[tonic::async_trait]
+impl proto::sql_server::Sql for Service {
+    async fn execute(
+        &self,
+        request: Request<proto::ExecuteRequest>,
+    ) -> std::result::Result<Response<proto::StatementResult>, Status> {
+        let (metadata, _, request) = request.into_parts();
+        debug!("Received gRPC execute request {request:?}");
+        self.with_session(&metadata, |session| execute(session, request)).await.map(Response::new)
+    }
+
+    type SessionStream = ReceiverStream<std::result::Result<proto::SessionResponse, Status>>;
+
+    /// Executes session requests in a blocking thread which owns the session,
+    /// until the client closes the request stream or goes away. While waiting
+    /// for requests, the session's transaction timeouts are enforced, like
+    /// for regular client sessions.
+    async fn session(
+        &self,
+        request: Request<Streaming<proto::SessionRequest>>,
+    ) -> std::result::Result<Response<Self::SessionStream>, Status> {
+        let credentials = credentials(request.metadata())?;
+        let mut requests = request.into_inner();
+        let (response_tx, response_rx) = mpsc::channel(1);
+        let (sql_engine, new_session) = (self.sql_engine.clone(), self.new_session.clone());
+        let runtime = tokio::runtime::Handle::current();
+        tokio::task::spawn_blocking(move || {
+            let mut session = new_session(&sql_engine);
+            if let Err(status) = authenticate(&mut session, credentials) {
+                _ = response_tx.blocking_send(Err(status));
+                return;
+            }
+            loop {
+                let received = match session.timeout_deadline() {
+                    Some(deadline) => {
+                        let deadline = tokio::time::Instant::from_std(deadline);
+                        match runtime
+                            .block_on(tokio::time::timeout_at(deadline, requests.message()))
+                        {
+                            Ok(received) => received,
+                            Err(_) => match session.check_timeout() {
+                                Ok(()) => continue,
+                                Err(error) => {
+                                    _ = response_tx.blocking_send(Err(status(error)));
+                                    return;
+                                }
+                            },
+                        }
+                    }
+                    None => runtime.block_on(requests.message()),
+                };
+                let request = match received {
+                    Ok(Some(request)) => request,
+                    Ok(None) => return, // the client closed the stream
+                    Err(status) => return debug!("gRPC session stream failed: {status}"),
+                };
+                debug!("Received gRPC session request {request:?}");
+                let response = match session_request(&mut session, request) {
+                    Ok(result) => SessionResponse::Result(result),
+                    Err(error) => SessionResponse::Error(proto::Error {
+                        code: code(&error) as i32,
+                        message: error.to_string(),
+                    }),
+                };
+                let response = proto::SessionResponse { response: Some(response) };
+                if response_tx.blocking_send(Ok(response)).is_err() {
+                    return; // the client has gone away
+                }
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(response_rx)))
+    }
+}
+
+#[tonic::async_trait]
+impl proto::admin_server::Admin for Service {
+    async fn status(
+        &self,
+        request: Request<proto::StatusRequest>,
+    ) -> std::result::Result<Response<proto::StatusResponse>, Status> {
+        let id = self.id;
+        let status = self
+            .with_session(request.metadata(), move |session| {
+                session.check_authenticated()?;
+                session.status()
+            })
+            .await?;
+        let storage = |status: crate::storage::Status| proto::StorageStatus {
+            name: status.name,
+            keys: status.keys,
+            size: status.size,
+            disk_size: status.disk_size,
+            live_disk_size: status.live_disk_size,
+        };
+        Ok(Response::new(proto::StatusResponse {
+            server: id as u64,
+            raft: Some(proto::RaftStatus {
+                leader: status.raft.leader as u64,
+                term: status.raft.term,
+                match_index: status
+                    .raft
+                    .match_index
+                    .into_iter()
+                    .map(|(id, index)| (id as u64, index))
+                    .collect(),
+                commit_index: status.raft.commit_index,
+                applied_index: status.raft.applied_index,
+                storage: Some(storage(status.raft.storage)),
+            }),
+            mvcc: Some(proto::MvccStatus {
+                versions: status.mvcc.versions,
+                active_txns: status.mvcc.active_txns,
+                gc_horizon: status.mvcc.gc_horizon,
+                storage: Some(storage(status.mvcc.storage)),
+            }),
+        }))
+    }
+
+    async fn list_tables(
+        &self,
+        request: Request<proto::ListTablesRequest>,
+    ) -> std::result::Result<Response<proto::ListTablesResponse>, Status> {
+        let tables = self
+            .with_session(request.metadata(), |session| {
+                session.check_authenticated()?;
+                session.with_txn(true, |txn| {
+                    Ok(txn.list_tables()?.into_iter().map(|table| table.name).collect())
+                })
+            })
+            .await?;
+        Ok(Response::new(proto::ListTablesResponse { tables }))
+    }
+
+    async fn get_table(
+        &self,
+        request: Request<proto::GetTableRequest>,
+    ) -> std::result::Result<Response<proto::GetTableResponse>, Status> {
+        let (metadata, _, request) = request.into_parts();
+        let table = self
+            .with_session(&metadata, move |session| {
+                session.check_authenticated()?;
+                session.with_txn(true, |txn| txn.must_get_table(&request.name))
+            })
+            .await?;
+        Ok(Response::new(proto::GetTableResponse { schema: table.to_string() }))
+    }
+
+    async fn garbage_collect(
+        &self,
+        request: Request<proto::GarbageCollectRequest>,
+    ) -> std::result::Result<Response<proto::GarbageCollectResponse>, Status> {
+        let retention = self.gc_retention;
+        let gc = self
+            .with_session(request.metadata(), move |session| {
+                let result = session.check_superuser().and_then(|_| session.gc(retention));
+                session.audit_command("GC", result.as_ref().err());
+                result
+            })
+            .await?;
+        Ok(Response::new(proto::GarbageCollectResponse {
+            horizon: gc.horizon,
+            versions: gc.versions,
+        }))
+    }
+}
+
+/// Extracts basic authentication credentials from the request metadata, if
+/// given.
+fn credentials(metadata: &MetadataMap) -> std::result::Result<Option<(String, String)>, Status> {
+    let Some(authorization) = metadata.get("authorization") else {
+        return Ok(None);
+    };
+    let authorization =
+        authorization.to_str().map_err(|err| Status::unauthenticated(err.to_string()))?;
+    let credentials = basic_credentials(authorization)
+        .map_err(|error| Status::unauthenticated(error.to_string()))?;
+    Ok(Some(credentials))
+}
+
+/// Authenticates the session with the given credentials. Without credentials,
+/// this errors if the server has authentication enabled.
+fn authenticate(
+    session: &mut Session<'_, Raft>,
+    credentials: Option<(String, String)>,
+) -> std::result::Result<(), Status> {
+    let result = match credentials {
+        Some((user, password)) => session.authenticate(&user, &password),
+        None => session.check_authenticated(),
+    };
+    result.map_err(|error| Status::unauthenticated(error.to_string()))
+}
+
+/// Executes a session request.
+fn session_request(
+    session: &mut Session<'_, Raft>,
+    request: proto::SessionRequest,
+) -> Result<proto::StatementResult> {
+    let statement = match request.request {
+        Some(SessionRequest::Execute(request)) => return execute(session, request),
+        Some(SessionRequest::Begin(begin)) => {
+            let mut statement = "BEGIN".to_string();
+            if begin.read_only {
+                statement.push_str(" READ ONLY");
+            }
+            if let Some(as_of) = begin.as_of {
+                statement.push_str(&format!(" AS OF SYSTEM TIME {as_of}"));
+            }
+            statement
+        }
+        Some(SessionRequest::Commit(_)) => "COMMIT".to_string(),
+        Some(SessionRequest::Rollback(_)) => "ROLLBACK".to_string(),
+        None => return errinput!("empty session request"),
+    };
+    statement_result(session.execute(&statement)?)
+}
+
+/// Executes a statement with parameters.
+fn execute(
+    session: &mut Session<'_, Raft>,
+    request: proto::ExecuteRequest,
+) -> Result<proto::StatementResult> {
+    let params = request.params.iter().map(param_literal).collect_vec();
+    let query = bind_params(&request.query, &params)?;
+    statement_result(session.execute(&query)?)
+}
+
+/// Converts a statement result to protobuf. Results without a dedicated
+/// protobuf field are given as JSON. The result type is the StatementResult
+/// variant name.
+fn statement_result(mut result: StatementResult) -> Result<proto::StatementResult> {
+    // RETURNING rows are given separately from the affected row count.
+    let returning = match &mut result {
+        StatementResult::Delete { returning, .. }
+        | StatementResult::Insert { returning, .. }
+        | StatementResult::Update { returning, .. } => {
+            returning.take().map(|returning| select_result(&returning.columns, returning.rows))
+        }
+        _ => None,
+    };
+    let (r#type, result) = match result {
+        StatementResult::Select { columns, rows } => {
+            ("Select".to_string(), ResultKind::Select(select_result(&columns, rows)))
+        }
+        StatementResult::Explain(plan) => {
+            ("Explain".to_string(), ResultKind::Explain(plan.to_string()))
+        }
+        result => {
+            let serde_json::Value::Object(object) = serde_json::to_value(&result)? else {
+                return errdata!("unexpected result {result:?}");
+            };
+            let Some((r#type, fields)) = object.into_iter().exactly_one().ok() else {
+                return errdata!("unexpected result {result:?}");
+            };
+            let kind = match result {
+                StatementResult::Begin(state) => ResultKind::Begin(proto::TransactionResult {
+                    version: state.version,
+                    read_only: state.read_only,
+                }),
+                StatementResult::Commit { version }
+                | StatementResult::Rollback { version }
+                | StatementResult::AbortTransaction { version } => ResultKind::Version(version),
+                StatementResult::Delete { count, .. }
+                | StatementResult::Insert { count, .. }
+                | StatementResult::Import { count }
+                | StatementResult::Export { count }
+                | StatementResult::Update { count, .. }
+                | StatementResult::TruncateTable { count, .. }
+                | StatementResult::DropPartition { count, .. } => ResultKind::Count(count),
+                _ => ResultKind::Other(fields.to_string()),
+            };
+            (r#type, kind)
+        }
+    };
+    Ok(proto::StatementResult { r#type, result: Some(result), returning })
+}
+
+/// Converts result rows to protobuf.
+fn select_result(columns: &[Label], rows: Vec<Row>) -> proto::SelectResult {
+    let columns = columns.iter().map(|label| label.as_header().to_string()).collect();
+    let rows = rows
+        .into_iter()
+        .map(|row| proto::Row { values: row.into_iter().map(value).collect() })
+        .collect();
+    proto::SelectResult { columns, rows }
+}
+
+/// Converts a SQL value to protobuf.
+fn value(value: Value) -> proto::Value {
+    use proto::value::Value as V;
+    let value = match value {
+        Value::Null => None,
+        Value::Boolean(boolean) => Some(V::Boolean(boolean)),
+        Value::Integer(integer) => Some(V::Integer(integer)),
+        Value::Float(float) => Some(V::Float(float)),
+        Value::String(string) => Some(V::String(string)),
+        Value::Json(json) => Some(V::Json(json.to_string())),
+        Value::Interval(interval) => Some(V::Interval(interval.to_string())),
+        Value::Timestamp(timestamp) => Some(V::Timestamp(timestamp.to_string())),
+        Value::Array(values) => {
+            Some(V::Array(proto::Array { values: values.into_iter().map(self::value).collect() }))
+        }
+    };
+    proto::Value { value }
+}
+
+/// Converts a protobuf parameter value to a SQL literal.
+fn param_literal(value: &proto::Value) -> String {
+    use proto::value::Value as V;
+    match &value.value {
+        None => "NULL".to_string(),
+        Some(V::Boolean(boolean)) => bool_literal(*boolean),
+        Some(V::Integer(integer)) => integer.to_string(),
+        Some(V::Float(float)) => float_literal(*float),
+        Some(V::String(string)) => string_literal(string),
+        Some(V::Json(json)) => format!("json({})", string_literal(json)),
+        Some(V::Interval(interval)) => format!("INTERVAL {}", string_literal(interval)),
+        Some(V::Timestamp(timestamp)) => format!("TIMESTAMP {}", string_literal(timestamp)),
+        Some(V::Array(array)) => {
+            format!("ARRAY[{}]", array.values.iter().map(param_literal).join(", "))
+        }
+    }
+}
+
+/// Returns the gRPC status code of an error. Errors that the client should
+/// retry are returned as ABORTED, or UNAVAILABLE if the node isn't the leader.
+fn code(error: &Error) -> Code {
+    match error {
+        Error::InvalidInput(_) => Code::InvalidArgument,
+        Error::Constraint(_) | Error::ReadOnly | Error::Truncated(_) => Code::FailedPrecondition,
+        Error::NotLeader(_) => Code::Unavailable,
+        Error::Abort | Error::Deadlock | Error::Serialization | Error::Locked(_) => Code::Aborted,
+        Error::Busy(_) => Code::ResourceExhausted,
+        Error::Cancelled => Code::Cancelled,
+        Error::Timeout => Code::DeadlineExceeded,
+        Error::InvalidData(_) | Error::IO(_) => Code::Internal,
+    }
+}
+
+/// Converts an error to a gRPC status.
+fn status(error: Error) -> Status {
+    Status::new(code(&error), error.to_string())
+}
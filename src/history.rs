@@ -0,0 +1,498 @@
+//! Operation histories and a linearizability checker, for Jepsen-style tests
+//! of toyDB clusters.
+//!
+//! A test runs concurrent clients against a cluster, typically while
+//! injecting faults (see fault), where each client wraps a toyDB client in a
+//! Recorder. Recorders perform read, write, and compare-and-set operations on
+//! a set of integer registers stored in a SQL table, and record their
+//! invocation and completion in a shared History. Afterwards, History::check()
+//! verifies that the recorded operations are linearizable: that there is a
+//! total order of the operations that respects their real-time order (an
+//! operation that completed before another was invoked must come first) and
+//! is valid for a sequential register.
+//!
+//! Operations that fail with an error may or may not have taken effect, e.g.
+//! if the connection was lost after the write was submitted to Raft. These are
+//! recorded as indeterminate, and the checker considers both possibilities.
+//!
+//! Linearizability is compositional, so each register is checked separately,
+//! using the Wing & Gong search algorithm with memoization of visited states
+//! (as in Lowe's and Porcupine's checkers). The search is exponential in the
+//! worst case, so histories should use enough registers to keep the number
+//! of concurrent operations per register small.
+
+use std::collections::{BTreeMap, HashSet};
+use std::fmt::Display;
+use std::sync::{Arc, Mutex};
+
+use itertools::Itertools as _;
+
+use crate::client::Client;
+use crate::error::{Error, Result};
+use crate::sql::engine::StatementResult;
+use crate::sql::types::Value;
+use crate::{errdata, errinput};
+
+/// A register key.
+pub type Key = i64;
+
+/// A register value. Registers are initially empty (None).
+pub type RegisterValue = i64;
+
+/// A register operation.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Op {
+    /// Reads a register.
+    Read(Key),
+    /// Writes a value to a register.
+    Write(Key, RegisterValue),
+    /// Writes a new value to a register if it has the expected current value.
+    Cas(Key, Option<RegisterValue>, RegisterValue),
+}
+
+impl Op {
+    /// Returns the operation's register key.
+    pub fn key(&self) -> Key {
+        match self {
+            Self::Read(key) | Self::Write(key, _) | Self::Cas(key, _, _) => *key,
+        }
+    }
+}
+
+impl Display for Op {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let fmt = |value: &Option<RegisterValue>| match value {
+            Some(value) => value.to_string(),
+            None => "NULL".to_string(),
+        };
+        match self {
+            Self::Read(key) => write!(f, "read {key}"),
+            Self::Write(key, value) => write!(f, "write {key}={value}"),
+            Self::Cas(key, from, to) => write!(f, "cas {key} {}→{to}", fmt(from)),
+        }
+    }
+}
+
+/// An operation outcome.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Outcome {
+    /// A read returned the given value.
+    Read(Option<RegisterValue>),
+    /// A write or compare-and-set took effect.
+    Ok,
+    /// The operation did not take effect, e.g. because a compare-and-set
+    /// didn't match or the transaction failed with a serialization error.
+    Fail,
+    /// The operation may or may not have taken effect, e.g. because the
+    /// connection was lost or the Raft leader changed.
+    Unknown,
+}
+
+impl Display for Outcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Read(Some(value)) => write!(f, "{value}"),
+            Self::Read(None) => write!(f, "NULL"),
+            Self::Ok => write!(f, "ok"),
+            Self::Fail => write!(f, "fail"),
+            Self::Unknown => write!(f, "unknown"),
+        }
+    }
+}
+
+/// A recorded operation.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Operation {
+    /// The client process that performed the operation.
+    pub process: usize,
+    /// The operation.
+    pub op: Op,
+    /// The operation outcome, or None if it hasn't completed yet.
+    pub outcome: Option<Outcome>,
+    /// The logical time at which the operation was invoked.
+    pub invoke: u64,
+    /// The logical time at which the operation completed, if it has.
+    pub complete: Option<u64>,
+}
+
+impl Display for Operation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "p{} {} ⇒ ", self.process, self.op)?;
+        match (self.outcome, self.complete) {
+            (Some(outcome), Some(complete)) => write!(f, "{outcome} [{},{complete}]", self.invoke),
+            _ => write!(f, "pending [{},∞]", self.invoke),
+        }
+    }
+}
+
+/// A history of concurrent operations. Cloning it returns a handle to the
+/// same history, which can be shared by recorders in different threads.
+/// Operations are timestamped by a logical clock, which captures their
+/// real-time order as observed by the clients.
+#[derive(Clone, Debug, Default)]
+pub struct History(Arc<Mutex<HistoryInner>>);
+
+#[derive(Debug, Default)]
+struct HistoryInner {
+    /// The recorded operations, in invocation order.
+    operations: Vec<Operation>,
+    /// The logical clock.
+    clock: u64,
+}
+
+impl History {
+    /// Creates a new, empty history.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records the invocation of an operation, returning its index in the
+    /// history.
+    pub fn invoke(&self, process: usize, op: Op) -> usize {
+        let mut inner = self.0.lock().expect("lock poisoned");
+        inner.clock += 1;
+        let invoke = inner.clock;
+        inner.operations.push(Operation { process, op, outcome: None, invoke, complete: None });
+        inner.operations.len() - 1
+    }
+
+    /// Records the completion of an invoked operation. Indeterminate outcomes
+    /// are recorded as never completing, since they may take effect at any
+    /// later time.
+    pub fn complete(&self, index: usize, outcome: Outcome) {
+        let mut inner = self.0.lock().expect("lock poisoned");
+        inner.clock += 1;
+        let complete = (outcome != Outcome::Unknown).then_some(inner.clock);
+        let operation = &mut inner.operations[index];
+        assert!(operation.outcome.is_none(), "operation already completed");
+        operation.outcome = Some(outcome);
+        operation.complete = complete;
+    }
+
+    /// Returns the recorded operations, in invocation order.
+    pub fn operations(&self) -> Vec<Operation> {
+        self.0.lock().expect("lock poisoned").operations.clone()
+    }
+
+    /// Checks whether the history is linearizable.
+    pub fn check(&self) -> Check {
+        check(&self.operations())
+    }
+}
+
+/// The result of a linearizability check.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Check {
+    /// The number of checked operations.
+    pub operations: usize,
+    /// The number of operations with indeterminate outcomes.
+    pub unknown: usize,
+    /// Registers whose histories aren't linearizable, with their operations.
+    pub violations: BTreeMap<Key, Vec<Operation>>,
+}
+
+impl Check {
+    /// Returns true if the history is linearizable.
+    pub fn is_linearizable(&self) -> bool {
+        self.violations.is_empty()
+    }
+}
+
+impl Display for Check {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Checked {} operations ({} unknown): ", self.operations, self.unknown)?;
+        if self.violations.is_empty() {
+            return write!(f, "linearizable");
+        }
+        write!(f, "not linearizable")?;
+        for (key, operations) in &self.violations {
+            write!(f, "\nRegister {key}:")?;
+            for operation in operations {
+                write!(f, "\n  {operation}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Checks whether the given operations are linearizable.
+pub fn check(operations: &[Operation]) -> Check {
+    let unknown = operations.iter().filter(|o| o.complete.is_none()).count();
+    let mut violations = BTreeMap::new();
+    for (key, operations) in operations.iter().into_group_map_by(|o| o.op.key()) {
+        if !Register::new(&operations).check() {
+            violations.insert(key, operations.into_iter().cloned().collect());
+        }
+    }
+    Check { operations: operations.len(), unknown, violations }
+}
+
+/// Checks the linearizability of a single register's history.
+struct Register {
+    /// The operations that may have taken effect. Failed operations and
+    /// indeterminate reads are omitted, since they have no effect.
+    operations: Vec<Operation>,
+    /// The states already explored: the set of linearized operations and
+    /// the register value.
+    visited: HashSet<(Vec<u64>, Option<RegisterValue>)>,
+}
+
+impl Register {
+    fn new(operations: &[&Operation]) -> Self {
+        let operations = operations
+            .iter()
+            .filter(|o| match (o.op, o.outcome) {
+                (_, Some(Outcome::Fail)) => false,
+                (Op::Read(_), Some(Outcome::Read(_))) => true,
+                (Op::Read(_), _) => false,
+                _ => true,
+            })
+            .map(|o| (*o).clone())
+            .collect();
+        Self { operations, visited: HashSet::new() }
+    }
+
+    /// Returns true if the register's history is linearizable.
+    fn check(&mut self) -> bool {
+        let linearized = vec![0; self.operations.len().div_ceil(64)];
+        self.search(linearized, None)
+    }
+
+    /// Searches for a linearization of the remaining operations, given the
+    /// set of linearized operations and the current register value.
+    fn search(&mut self, linearized: Vec<u64>, value: Option<RegisterValue>) -> bool {
+        let is_linearized = |i: usize| linearized[i / 64] & (1 << (i % 64)) != 0;
+
+        // The next operation must have been invoked before any remaining
+        // operation completed, otherwise that one must come first. If all
+        // completed operations are linearized, we're done: the remaining
+        // indeterminate operations may never have taken effect.
+        let pending = (0..self.operations.len()).filter(|i| !is_linearized(*i)).collect_vec();
+        let Some(deadline) = pending.iter().filter_map(|i| self.operations[*i].complete).min()
+        else {
+            return true;
+        };
+        if !self.visited.insert((linearized.clone(), value)) {
+            return false;
+        }
+        for i in pending {
+            let operation = &self.operations[i];
+            if operation.invoke > deadline {
+                continue;
+            }
+            let next = match (operation.op, operation.outcome) {
+                (Op::Read(_), Some(Outcome::Read(read))) if read == value => value,
+                (Op::Read(_), _) => continue,
+                (Op::Write(_, to), _) => Some(to),
+                (Op::Cas(_, from, to), _) if from == value => Some(to),
+                (Op::Cas(_, _, _), _) => continue,
+            };
+            let mut linearized = linearized.clone();
+            linearized[i / 64] |= 1 << (i % 64);
+            if self.search(linearized, next) {
+                return true;
+            }
+        }
+        false
+    }
+}
+
+/// Performs register operations via a toyDB client and records them in a
+/// history. Registers are stored as rows in the SQL table registers, which
+/// must be set up first via Recorder::setup().
+pub struct Recorder {
+    /// The toyDB client.
+    client: Client,
+    /// The history to record operations in.
+    history: History,
+    /// The client process ID.
+    process: usize,
+}
+
+impl Recorder {
+    /// Creates a new recorder for the given process, using a client.
+    pub fn new(client: Client, history: History, process: usize) -> Self {
+        Self { client, history, process }
+    }
+
+    /// Creates the registers table with the given number of empty registers,
+    /// with keys from 0. An existing registers table is replaced, since the
+    /// checker assumes registers are initially empty.
+    pub fn setup(client: &mut Client, registers: Key) -> Result<()> {
+        if registers < 1 {
+            return errinput!("must have at least 1 register");
+        }
+        client.execute("DROP TABLE IF EXISTS registers")?;
+        client.execute("CREATE TABLE registers (id INT PRIMARY KEY, value INT)")?;
+        let rows = (0..registers).map(|key| format!("({key}, NULL)")).join(", ");
+        client.execute(&format!("INSERT INTO registers VALUES {rows}"))?;
+        Ok(())
+    }
+
+    /// Performs and records an operation, returning its outcome. Errors
+    /// yield a failed or indeterminate outcome, depending on whether the
+    /// operation may have taken effect. They can be inspected via
+    /// Recorder::execute() instead.
+    pub fn perform(&mut self, op: Op) -> Outcome {
+        let index = self.history.invoke(self.process, op);
+        let outcome = match self.execute(op) {
+            Ok(outcome) => outcome,
+            Err(error) if Self::is_indeterminate(&error) => Outcome::Unknown,
+            Err(_) => Outcome::Fail,
+        };
+        self.history.complete(index, outcome);
+        outcome
+    }
+
+    /// Executes an operation without recording it.
+    pub fn execute(&mut self, op: Op) -> Result<Outcome> {
+        let statement = match op {
+            Op::Read(key) => format!("SELECT value FROM registers WHERE id = {key}"),
+            Op::Write(key, value) => {
+                format!("UPDATE registers SET value = {value} WHERE id = {key}")
+            }
+            Op::Cas(key, Some(from), to) => {
+                format!("UPDATE registers SET value = {to} WHERE id = {key} AND value = {from}")
+            }
+            Op::Cas(key, None, to) => {
+                format!("UPDATE registers SET value = {to} WHERE id = {key} AND value IS NULL")
+            }
+        };
+        match (op, self.client.execute(&statement)?) {
+            (Op::Read(_), StatementResult::Select { rows, .. }) => {
+                match rows.into_iter().exactly_one().map(|row| row.into_iter().exactly_one()) {
+                    Ok(Ok(Value::Integer(value))) => Ok(Outcome::Read(Some(value))),
+                    Ok(Ok(Value::Null)) => Ok(Outcome::Read(None)),
+                    _ => errdata!("unexpected register {} read result", op.key()),
+                }
+            }
+            (Op::Write(..), StatementResult::Update { count: 1, .. }) => Ok(Outcome::Ok),
+            (Op::Cas(..), StatementResult::Update { count: 1, .. }) => Ok(Outcome::Ok),
+            (Op::Cas(..), StatementResult::Update { count: 0, .. }) => Ok(Outcome::Fail),
+            (op, result) => errdata!("unexpected result for {op}: {result:?}"),
+        }
+    }
+
+    /// Returns true if a failed operation may still have taken effect. Raft
+    /// aborts, IO errors, and timeouts may happen after the write was
+    /// submitted to Raft, while other errors are returned before the
+    /// transaction commits.
+    fn is_indeterminate(error: &Error) -> bool {
+        matches!(error, Error::Abort | Error::IO(_) | Error::Timeout | Error::Cancelled)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use super::*;
+
+    /// Builds a history from (process, op, outcome, invoke, complete)
+    /// tuples. A complete time of 0 is indeterminate.
+    fn history(ops: &[(usize, Op, Outcome, u64, u64)]) -> Vec<Operation> {
+        ops.iter()
+            .map(|&(process, op, outcome, invoke, complete)| Operation {
+                process,
+                op,
+                outcome: Some(outcome),
+                invoke,
+                complete: (complete > 0).then_some(complete),
+            })
+            .collect()
+    }
+
+    use Op::*;
+    use Outcome::{Fail, Ok as Done, Read as Got, Unknown};
+
+    #[test_case(&[], true; "empty")]
+    #[test_case(&[(1, Read(0), Got(None), 1, 2)], true; "initial read")]
+    #[test_case(&[(1, Read(0), Got(Some(1)), 1, 2)], false; "read unwritten value")]
+    #[test_case(&[
+        (1, Write(0, 1), Done, 1, 2),
+        (2, Read(0), Got(Some(1)), 3, 4),
+    ], true; "sequential")]
+    #[test_case(&[
+        (1, Write(0, 1), Done, 1, 2),
+        (2, Read(0), Got(None), 3, 4),
+    ], false; "stale read")]
+    #[test_case(&[
+        (1, Write(0, 1), Done, 1, 4),
+        (2, Read(0), Got(None), 2, 5),
+        (3, Read(0), Got(Some(1)), 3, 6),
+    ], true; "concurrent reads")]
+    #[test_case(&[
+        (1, Write(0, 1), Done, 1, 6),
+        (2, Read(0), Got(Some(1)), 2, 3),
+        (3, Read(0), Got(None), 4, 5),
+    ], false; "reads observe write in reverse order")]
+    #[test_case(&[
+        (1, Write(0, 1), Done, 1, 2),
+        (2, Cas(0, Some(1), 2), Done, 3, 4),
+        (3, Cas(0, Some(1), 3), Fail, 5, 6),
+        (1, Read(0), Got(Some(2)), 7, 8),
+    ], true; "cas")]
+    #[test_case(&[
+        (1, Write(0, 1), Done, 1, 2),
+        (2, Cas(0, Some(1), 2), Done, 3, 6),
+        (3, Cas(0, Some(1), 3), Done, 4, 5),
+    ], false; "cas both succeed")]
+    #[test_case(&[
+        (1, Write(0, 1), Unknown, 1, 0),
+        (2, Read(0), Got(None), 2, 3),
+        (2, Read(0), Got(Some(1)), 4, 5),
+    ], true; "unknown write takes effect later")]
+    #[test_case(&[
+        (1, Write(0, 1), Unknown, 1, 0),
+        (2, Read(0), Got(None), 2, 3),
+    ], true; "unknown write never takes effect")]
+    #[test_case(&[
+        (1, Write(0, 1), Fail, 1, 2),
+        (2, Read(0), Got(Some(1)), 3, 4),
+    ], false; "failed write takes effect")]
+    #[test_case(&[
+        (1, Write(0, 1), Done, 1, 2),
+        (2, Read(1), Got(None), 3, 4),
+        (2, Write(1, 2), Done, 5, 6),
+        (1, Read(0), Got(Some(1)), 7, 8),
+    ], true; "independent registers")]
+    fn check_linearizable(ops: &[(usize, Op, Outcome, u64, u64)], expect: bool) {
+        let check = check(&history(ops));
+        assert_eq!(check.is_linearizable(), expect, "{check}");
+    }
+
+    /// Violations are reported per register.
+    #[test]
+    fn check_violations() {
+        let check = check(&history(&[
+            (1, Write(0, 1), Done, 1, 2),
+            (2, Read(0), Got(Some(1)), 3, 4),
+            (1, Write(1, 1), Done, 5, 6),
+            (2, Read(1), Got(None), 7, 8),
+        ]));
+        assert_eq!(check.violations.keys().copied().collect_vec(), vec![1]);
+        assert_eq!(
+            check.to_string(),
+            "Checked 4 operations (0 unknown): not linearizable\n\
+             Register 1:\n  \
+               p1 write 1=1 ⇒ ok [5,6]\n  \
+               p2 read 1 ⇒ NULL [7,8]"
+        );
+    }
+
+    /// The history records the logical order of operations.
+    #[test]
+    fn history_record() {
+        let history = History::new();
+        let a = history.invoke(1, Write(0, 1));
+        let b = history.invoke(2, Read(0));
+        history.complete(b, Got(None));
+        history.complete(a, Unknown);
+        let operations = history.operations();
+        assert_eq!(operations[0].invoke, 1);
+        assert_eq!(operations[0].complete, None);
+        assert_eq!(operations[1].invoke, 2);
+        assert_eq!(operations[1].complete, Some(3));
+        assert!(history.check().is_linearizable());
+    }
+}
@@ -0,0 +1,386 @@
+//! An HTTP/JSON front end, which allows scripts and dashboards to query toyDB
+//! over plain HTTP, without the native protocol or a Postgres driver. The
+//! server listens for HTTP requests on a separate address, and serves these
+//! endpoints:
+//!
+//! * `POST /query`: executes a SQL statement, given as a JSON object like
+//!   `{"query": "SELECT * FROM t WHERE id = $1", "params": [1]}`. Like in
+//!   pgwire, $1-style parameters are substituted into the query text as SQL
+//!   literals. SELECT results are returned as `{"columns": [...], "rows":
+//!   [[...], ...]}`, EXPLAIN results as `{"plan": "..."}`, and other results
+//!   as the serialized StatementResult, e.g. `{"Insert": {"count": 1}}`.
+//!
+//! * `GET /healthz`: returns `{"status": "ok"}` if the server process is
+//!   alive, for liveness probes. It doesn't check the node's state.
+//!
+//! * `GET /readyz`: returns `{"status": "ready", "lag": N}` if the node can
+//!   serve requests, for readiness probes and load balancers. The node must
+//!   reach the Raft leader, its applied index must lag the leader's commit
+//!   index by at most the configured number of entries, and its data directory
+//!   must be writable. Otherwise, returns 503 Service Unavailable.
+//!
+//! * `GET /status`: returns the SQL engine status (see Status).
+//!
+//! * `GET /metrics`: returns the server metrics in the Prometheus text format
+//!   (see metrics), for scraping by Prometheus.
+//!
+//! Errors are returned as `{"error": "..."}`, with a status code derived from
+//! the error kind.
+//!
+//! Each request is executed in a new SQL session, which is dropped after the
+//! response is sent, so explicit transactions can't span requests. If the
+//! server has authentication enabled, requests must authenticate via HTTP
+//! basic authentication (clients should use TLS in that case), except for
+//! /healthz and /readyz which don't return any data. Connections are kept
+//! alive between requests unless the client asks to close them.
+//!
+//! Only HTTP/1.1 (and 1.0) requests with a Content-Length body are supported,
+//! not chunked transfer encoding.
+
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::PathBuf;
+
+use base64::Engine as _;
+use itertools::Itertools as _;
+use log::warn;
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::error::{Error, Result};
+use crate::pgwire::{bind_params, bool_literal, float_literal, string_literal};
+use crate::raft;
+use crate::sql::engine::{Raft, Session, StatementResult};
+use crate::sql::execution::Returning;
+use crate::sql::types::{Label, Row, Value};
+use crate::tls::Stream;
+use crate::{errinput, metrics};
+
+/// The maximum size of a request body in bytes.
+const MAX_BODY_SIZE: usize = 16 * 1024 * 1024;
+
+/// The /readyz readiness criteria.
+#[derive(Clone, Debug)]
+pub struct Readiness {
+    /// The maximum number of entries the local applied index can lag behind
+    /// the leader's commit index.
+    pub max_lag: raft::Index,
+    /// The data directory, which must be writable. None skips the check.
+    pub data_dir: Option<PathBuf>,
+}
+
+/// A /query request body.
+#[derive(Deserialize)]
+struct QueryRequest {
+    /// The SQL statement to execute.
+    query: String,
+    /// The $1-style parameter values.
+    #[serde(default)]
+    params: Vec<serde_json::Value>,
+}
+
+/// An HTTP request.
+struct Request {
+    method: String,
+    path: String,
+    /// Header values, by lowercase name.
+    headers: HashMap<String, String>,
+    body: Vec<u8>,
+    /// Whether to close the connection after the response.
+    close: bool,
+}
+
+/// An HTTP response, with a JSON body unless otherwise specified.
+struct Response {
+    status: u16,
+    content_type: &'static str,
+    body: String,
+}
+
+impl Response {
+    /// Creates a 200 OK response.
+    fn ok(body: serde_json::Value) -> Self {
+        Self { status: 200, content_type: "application/json", body: body.to_string() }
+    }
+
+    /// Creates a 200 OK response with a plain text body.
+    fn text(content_type: &'static str, body: String) -> Self {
+        Self { status: 200, content_type, body }
+    }
+
+    /// Creates an error response.
+    fn error(status: u16, message: impl ToString) -> Self {
+        let body = json!({"error": message.to_string()}).to_string();
+        Self { status, content_type: "application/json", body }
+    }
+}
+
+/// Serves an HTTP client connection until the client disconnects, executing
+/// each request in a new session created by the given closure.
+pub fn serve<'a>(
+    stream: Stream,
+    readiness: &Readiness,
+    session: impl Fn() -> Session<'a, Raft>,
+) -> Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = BufWriter::new(stream);
+    while let Some(request) = read_request(&mut reader)? {
+        let response = match request {
+            Ok(request) => {
+                let response = handle(&request, readiness, session());
+                write_response(&mut writer, &response)?;
+                if request.close {
+                    break;
+                }
+                continue;
+            }
+            Err(error) => Response::error(400, error),
+        };
+        // Malformed requests can't be recovered from, so close the connection.
+        write_response(&mut writer, &response)?;
+        break;
+    }
+    Ok(())
+}
+
+/// Routes and handles a request.
+fn handle(request: &Request, readiness: &Readiness, mut session: Session<'_, Raft>) -> Response {
+    // Probes are served without authentication.
+    match (request.method.as_str(), request.path.as_str()) {
+        ("GET", "/healthz") => return Response::ok(json!({"status": "ok"})),
+        ("GET", "/readyz") => return ready(readiness, &session),
+        _ => {}
+    }
+    if let Err(error) = authenticate(request, &mut session) {
+        return Response::error(401, error);
+    }
+    let result = match (request.method.as_str(), request.path.as_str()) {
+        ("POST", "/query") => query(&request.body, &mut session),
+        ("GET", "/status") => session.status().and_then(|status| Ok(serde_json::to_value(status)?)),
+        ("GET", "/metrics") => return metrics(&session),
+        (_, "/query" | "/healthz" | "/readyz" | "/status" | "/metrics") => {
+            return Response::error(405, format!("method {} not allowed", request.method));
+        }
+        (_, path) => return Response::error(404, format!("unknown path {path}")),
+    };
+    match result {
+        Ok(body) => Response::ok(body),
+        Err(error) => Response::error(status_code(&error), error),
+    }
+}
+
+/// Authenticates the session via HTTP basic authentication, if given. If the
+/// server has authentication enabled, the session's statements then error
+/// unless it's authenticated.
+fn authenticate(request: &Request, session: &mut Session<'_, Raft>) -> Result<()> {
+    let Some(authorization) = request.headers.get("authorization") else {
+        return session.check_authenticated();
+    };
+    let (user, password) = basic_credentials(authorization)?;
+    session.authenticate(&user, &password)
+}
+
+/// Parses the user name and password from an HTTP basic authorization header
+/// value, e.g. "Basic YWRtaW46c2VjcmV0" for admin:secret. Also used by grpc.
+pub(crate) fn basic_credentials(authorization: &str) -> Result<(String, String)> {
+    let Some(credentials) = authorization.strip_prefix("Basic ") else {
+        return errinput!("unsupported authorization scheme");
+    };
+    let credentials = base64::engine::general_purpose::STANDARD
+        .decode(credentials.trim())
+        .ok()
+        .and_then(|credentials| String::from_utf8(credentials).ok());
+    let Some((user, password)) = credentials.as_deref().and_then(|c| c.split_once(':')) else {
+        return errinput!("invalid basic authorization credentials");
+    };
+    Ok((user.to_string(), password.to_string()))
+}
+
+/// Executes a /query request.
+fn query(body: &[u8], session: &mut Session<'_, Raft>) -> Result<serde_json::Value> {
+    let request: QueryRequest = serde_json::from_slice(body)?;
+    let params: Vec<String> = request.params.iter().map(param_literal).collect();
+    let query = bind_params(&request.query, &params)?;
+    Ok(match session.execute(&query)? {
+        StatementResult::Select { columns, rows } => rows_json(&columns, rows)?,
+        StatementResult::Explain(plan) => json!({"plan": plan.to_string()}),
+        // RETURNING rows are given with the affected row count.
+        StatementResult::Delete { count, returning } => {
+            json!({"Delete": write_json(count, returning)?})
+        }
+        StatementResult::Insert { count, returning } => {
+            json!({"Insert": write_json(count, returning)?})
+        }
+        StatementResult::Update { count, returning } => {
+            json!({"Update": write_json(count, returning)?})
+        }
+        result => serde_json::to_value(result)?,
+    })
+}
+
+/// Converts result rows to JSON, as columns and rows.
+fn rows_json(columns: &[Label], rows: Vec<Row>) -> Result<serde_json::Value> {
+    let columns = columns.iter().map(|label| label.as_header().to_string()).collect_vec();
+    let rows: Vec<Vec<_>> = rows
+        .into_iter()
+        .map(|row| row.into_iter().map(Value::to_json).try_collect())
+        .try_collect()?;
+    Ok(json!({"columns": columns, "rows": rows}))
+}
+
+/// Converts a write result to JSON, as the affected row count and any
+/// RETURNING rows.
+fn write_json(count: u64, returning: Option<Returning>) -> Result<serde_json::Value> {
+    let Some(Returning { columns, rows }) = returning else {
+        return Ok(json!({"count": count}));
+    };
+    let mut value = rows_json(&columns, rows)?;
+    value["count"] = count.into();
+    Ok(value)
+}
+
+/// Serves a /metrics request. The cluster gauges are sampled from the engine
+/// status first, but other metrics are still returned if that fails, e.g.
+/// when there's no Raft leader.
+fn metrics(session: &Session<'_, Raft>) -> Response {
+    match session.status() {
+        Ok(status) => metrics::observe_status(&status),
+        Err(error) => warn!("Failed to sample status for metrics: {error}"),
+    }
+    Response::text("text/plain; version=0.0.4", metrics::render())
+}
+
+/// Serves a /readyz request, checking the readiness criteria. The leader's
+/// commit index is fetched via a status request, which also checks that the
+/// leader is reachable, and compared with the local node's applied index.
+fn ready(readiness: &Readiness, session: &Session<'_, Raft>) -> Response {
+    let lag = match ready_lag(session) {
+        Ok(lag) if lag > readiness.max_lag => {
+            return Response::error(503, format!("node lags leader by {lag} entries"));
+        }
+        Ok(lag) => lag,
+        Err(error) => return Response::error(503, error),
+    };
+    if let Some(dir) = &readiness.data_dir {
+        let path = dir.join(".readyz");
+        if let Err(error) = std::fs::write(&path, b"").and_then(|()| std::fs::remove_file(&path)) {
+            return Response::error(503, format!("data directory not writable: {error}"));
+        }
+    }
+    Response::ok(json!({"status": "ready", "lag": lag}))
+}
+
+/// Returns how many entries the local applied index lags behind the leader's
+/// commit index.
+fn ready_lag(session: &Session<'_, Raft>) -> Result<raft::Index> {
+    let commit_index = session.status()?.raft.commit_index;
+    let (node, _) = session.node_status()?;
+    Ok(commit_index.saturating_sub(node.applied_index))
+}
+
+/// Converts a JSON parameter value to a SQL literal. Arrays and objects are
+/// given as JSON strings.
+fn param_literal(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::Null => "NULL".to_string(),
+        serde_json::Value::Bool(boolean) => bool_literal(*boolean),
+        serde_json::Value::Number(number) => match number.as_i64() {
+            Some(integer) => integer.to_string(),
+            None => float_literal(number.as_f64().unwrap_or(f64::NAN)),
+        },
+        serde_json::Value::String(string) => string_literal(string),
+        value @ (serde_json::Value::Array(_) | serde_json::Value::Object(_)) => {
+            string_literal(&value.to_string())
+        }
+    }
+}
+
+/// Returns the HTTP status code of an error. Errors that the client should
+/// retry are returned as 409 Conflict, or 503 Service Unavailable if the
+/// server is overloaded or isn't the leader.
+fn status_code(error: &Error) -> u16 {
+    match error {
+        Error::Constraint(_) | Error::InvalidInput(_) | Error::ReadOnly => 400,
+        Error::Truncated(_) => 410,
+        Error::Abort | Error::Deadlock | Error::Serialization | Error::Locked(_) => 409,
+        Error::Cancelled | Error::Timeout => 408,
+        Error::Busy(_) | Error::NotLeader(_) => 503,
+        Error::InvalidData(_) | Error::IO(_) => 500,
+    }
+}
+
+/// Reads an HTTP request, or None if the client disconnected. Returns an
+/// inner error if the request is malformed.
+fn read_request(reader: &mut impl BufRead) -> Result<Option<Result<Request>>> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    let Some((method, target, version)) = line.split_whitespace().collect_tuple() else {
+        return Ok(Some(errinput!("invalid request line {}", line.trim_end())));
+    };
+    let (method, version) = (method.to_string(), version.to_string());
+    let path = target.split_once('?').map_or(target, |(path, _)| path).to_string();
+
+    let mut headers = HashMap::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        let Some((name, value)) = line.split_once(':') else {
+            return Ok(Some(errinput!("invalid header {line}")));
+        };
+        headers.insert(name.trim().to_lowercase(), value.trim().to_string());
+    }
+
+    if headers.contains_key("transfer-encoding") {
+        return Ok(Some(errinput!("transfer encoding not supported")));
+    }
+    let length = match headers.get("content-length").map(|length| length.parse::<usize>()) {
+        Some(Ok(length)) if length > MAX_BODY_SIZE => {
+            return Ok(Some(errinput!("request body exceeds {MAX_BODY_SIZE} bytes")));
+        }
+        Some(Ok(length)) => length,
+        Some(Err(_)) => return Ok(Some(errinput!("invalid content length"))),
+        None => 0,
+    };
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body)?;
+
+    let close = match headers.get("connection").map(|c| c.to_lowercase()).as_deref() {
+        Some("close") => true,
+        Some("keep-alive") => false,
+        _ => version != "HTTP/1.1",
+    };
+    Ok(Some(Ok(Request { method, path, headers, body, close })))
+}
+
+/// Writes an HTTP response.
+fn write_response(writer: &mut impl Write, response: &Response) -> Result<()> {
+    let body = &response.body;
+    let reason = match response.status {
+        200 => "OK",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        409 => "Conflict",
+        503 => "Service Unavailable",
+        _ => "Internal Server Error",
+    };
+    write!(writer, "HTTP/1.1 {} {reason}\r\n", response.status)?;
+    write!(writer, "Content-Type: {}\r\n", response.content_type)?;
+    write!(writer, "Content-Length: {}\r\n", body.len())?;
+    if response.status == 401 {
+        write!(writer, "WWW-Authenticate: Basic realm=\"toydb\"\r\n")?;
+    }
+    write!(writer, "\r\n{body}")?;
+    writer.flush()?;
+    Ok(())
+}
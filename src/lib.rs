@@ -2,14 +2,28 @@
 #![allow(clippy::module_inception)]
 #![allow(clippy::type_complexity)]
 
+pub mod async_client;
 pub mod client;
+pub mod clock;
+pub mod embedded;
 pub mod encoding;
 pub mod error;
+pub mod fault;
+pub mod grpc;
+pub mod history;
+pub mod http;
+pub mod logging;
+pub mod metrics;
+pub mod pgwire;
 pub mod raft;
 pub mod server;
 pub mod sql;
 pub mod storage;
+pub mod tls;
+pub mod trace;
 
-pub use client::Client;
+pub use async_client::AsyncClient;
+pub use client::{Client, Pool};
+pub use embedded::Database;
 pub use server::Server;
 pub use sql::engine::StatementResult;
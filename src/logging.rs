@@ -0,0 +1,317 @@
+//! Structured server logging, via the log crate's macros (e.g. `info!`).
+//!
+//! Log records include the module they were emitted from, and the context they
+//! were emitted in, where known:
+//!
+//! * node: the ID of the local Raft node (see set_node).
+//! * term: the current Raft term of the local node (see set_term).
+//! * session: the ID of the SQL session executing on the current thread.
+//! * txn: the ID (version) of the SQL transaction executing on the current
+//!   thread.
+//!
+//! The node and term are process-wide, while the session and transaction are
+//! tracked per thread, and set for the duration of a scope via guards returned
+//! by with_session() and with_txn().
+//!
+//! Records are written as plain text lines, or as JSON objects with a field
+//! per context item, for ingestion by log pipelines. Errors are written to
+//! stderr and other records to stdout.
+//!
+//! Log levels are given by a Filter, which has a default level for toyDB's own
+//! modules and optional per-module levels, e.g. "info,toydb::raft=debug". The
+//! filter can be changed at runtime via set_filter(), e.g. when the server
+//! config is reloaded.
+
+use std::cell::Cell;
+use std::io::Write as _;
+use std::str::FromStr;
+use std::sync::RwLock;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use log::{LevelFilter, Log, Metadata, Record};
+use serde::Serialize;
+
+use crate::errinput;
+use crate::error::{Error, Result};
+use crate::raft::NodeID;
+
+/// The installed logger.
+static LOGGER: Logger = Logger { format: RwLock::new(Format::Text), filter: RwLock::new(None) };
+
+/// The local Raft node ID, or 0 if unknown.
+static NODE: AtomicU64 = AtomicU64::new(0);
+
+/// The current Raft term of the local node, or 0 if unknown.
+static TERM: AtomicU64 = AtomicU64::new(0);
+
+thread_local! {
+    /// The SQL session executing on the current thread, if any.
+    static SESSION: Cell<Option<u64>> = const { Cell::new(None) };
+    /// The SQL transaction executing on the current thread, if any.
+    static TXN: Cell<Option<u64>> = const { Cell::new(None) };
+}
+
+/// Installs the logger as the global logger, with the given format and filter.
+/// Can only be called once.
+pub fn init(format: Format, filter: Filter) -> Result<()> {
+    *LOGGER.format.write()? = format;
+    log::set_logger(&LOGGER).or_else(|err| errinput!("can't initialize logger: {err}"))?;
+    set_filter(filter)
+}
+
+/// Replaces the logger's filter, e.g. when the config is reloaded.
+pub fn set_filter(filter: Filter) -> Result<()> {
+    log::set_max_level(filter.max_level());
+    *LOGGER.filter.write()? = Some(filter);
+    Ok(())
+}
+
+/// Sets the local Raft node ID.
+pub fn set_node(id: NodeID) {
+    NODE.store(id as u64, Ordering::Relaxed);
+}
+
+/// Sets the current Raft term of the local node.
+pub fn set_term(term: u64) {
+    TERM.store(term, Ordering::Relaxed);
+}
+
+/// Sets the SQL session ID of log records emitted by the current thread, until
+/// the returned guard is dropped.
+pub fn with_session(id: u64) -> ContextGuard {
+    ContextGuard { session: Some(SESSION.replace(Some(id))), txn: None }
+}
+
+/// Sets the SQL transaction ID of log records emitted by the current thread,
+/// until the returned guard is dropped. None clears it.
+pub fn with_txn(id: Option<u64>) -> ContextGuard {
+    ContextGuard { session: None, txn: Some(TXN.replace(id)) }
+}
+
+/// Restores the previous thread context when dropped.
+#[must_use = "the context is reset when the guard is dropped"]
+pub struct ContextGuard {
+    /// The previous session, if it was replaced.
+    session: Option<Option<u64>>,
+    /// The previous transaction, if it was replaced.
+    txn: Option<Option<u64>>,
+}
+
+impl Drop for ContextGuard {
+    fn drop(&mut self) {
+        if let Some(session) = self.session {
+            SESSION.set(session);
+        }
+        if let Some(txn) = self.txn {
+            TXN.set(txn);
+        }
+    }
+}
+
+/// A log output format.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    /// Plain text lines, with context fields as key=value pairs after the
+    /// message, e.g.:
+    ///
+    /// 2024-01-02T03:04:05.678Z  INFO toydb::server: Listening node=1 term=2
+    Text,
+    /// JSON objects, one per line, e.g.:
+    ///
+    /// {"time":"2024-01-02T03:04:05.678Z","level":"INFO","module":
+    /// "toydb::server","message":"Listening","node":1,"term":2}
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            _ => errinput!("invalid log format {s}, expected text or json"),
+        }
+    }
+}
+
+/// A log level filter. Records from toyDB's own modules are logged at the
+/// default level, unless overridden by a per-module level. Records from other
+/// crates are only logged if given a per-module level, e.g. "rustls=debug".
+#[derive(Clone, Debug, PartialEq)]
+pub struct Filter {
+    /// The default level of toyDB modules.
+    default: LevelFilter,
+    /// Per-module levels, by module path. Applies to submodules too, and the
+    /// longest matching path takes precedence.
+    modules: Vec<(String, LevelFilter)>,
+}
+
+impl Filter {
+    /// Returns the level of a module path, i.e. a log record's target.
+    fn level(&self, module: &str) -> LevelFilter {
+        let matches = |prefix: &str| {
+            module
+                .strip_prefix(prefix)
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+        };
+        self.modules
+            .iter()
+            .filter(|(prefix, _)| matches(prefix))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(_, level)| *level)
+            .unwrap_or(match matches("toydb") {
+                true => self.default,
+                false => LevelFilter::Off,
+            })
+    }
+
+    /// Returns the most verbose level of any module.
+    fn max_level(&self) -> LevelFilter {
+        self.modules.iter().map(|(_, level)| *level).fold(self.default, Ord::max)
+    }
+}
+
+/// Parses a filter from a comma-separated list of a default level and/or
+/// module=level pairs, e.g. "info,toydb::raft=debug,toydb::sql=warn". Levels
+/// are OFF, ERROR, WARN, INFO, DEBUG, or TRACE, in any case. The default level
+/// is INFO if not given.
+impl FromStr for Filter {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let parse_level = |level: &str| {
+            level.trim().parse::<LevelFilter>().or_else(|_| {
+                errinput!("invalid log level {level}, expected DEBUG, INFO, WARN, or ERROR")
+            })
+        };
+        let mut filter = Self { default: LevelFilter::Info, modules: Vec::new() };
+        for (i, item) in s.split(',').map(str::trim).enumerate() {
+            match item.split_once('=') {
+                Some((module, level)) if !module.trim().is_empty() => {
+                    filter.modules.push((module.trim().to_string(), parse_level(level)?));
+                }
+                None if i == 0 => filter.default = parse_level(item)?,
+                _ => return errinput!("invalid log level {item}, expected MODULE=LEVEL"),
+            }
+        }
+        Ok(filter)
+    }
+}
+
+/// The logger. The filter is None until initialized.
+struct Logger {
+    format: RwLock<Format>,
+    filter: RwLock<Option<Filter>>,
+}
+
+/// A JSON log record. Unknown context fields are omitted.
+#[derive(Serialize)]
+struct JsonRecord<'a> {
+    time: &'a str,
+    level: &'a str,
+    module: &'a str,
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    node: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    term: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    session: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    txn: Option<u64>,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        let filter = self.filter.read().expect("lock poisoned");
+        filter.as_ref().is_some_and(|filter| metadata.level() <= filter.level(metadata.target()))
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let time = timestamp();
+        let level = record.level().as_str();
+        let module = record.target();
+        let known = |id: u64| Some(id).filter(|id| *id > 0);
+        let node = known(NODE.load(Ordering::Relaxed));
+        let term = known(TERM.load(Ordering::Relaxed));
+        let (session, txn) = (SESSION.get(), TXN.get());
+
+        let line = match *self.format.read().expect("lock poisoned") {
+            Format::Text => {
+                let mut line = format!("{time} {level:>5} {module}: {}", record.args());
+                let fields = [("node", node), ("term", term), ("session", session), ("txn", txn)];
+                for (name, value) in fields {
+                    if let Some(value) = value {
+                        line.push_str(&format!(" {name}={value}"));
+                    }
+                }
+                line
+            }
+            Format::Json => {
+                let message = record.args().to_string();
+                let record =
+                    JsonRecord { time: &time, level, module, message, node, term, session, txn };
+                serde_json::to_string(&record).expect("JSON serialization failed")
+            }
+        };
+        // Ignore write errors, there's nowhere to report them.
+        _ = match record.level() {
+            log::Level::Error => writeln!(std::io::stderr(), "{line}"),
+            _ => writeln!(std::io::stdout(), "{line}"),
+        };
+    }
+
+    fn flush(&self) {
+        _ = std::io::stdout().flush();
+        _ = std::io::stderr().flush();
+    }
+}
+
+/// Returns the current UTC time as an RFC 3339 timestamp with millisecond
+/// precision, e.g. 2024-01-02T03:04:05.678Z.
+pub(crate) fn timestamp() -> String {
+    let now = time::OffsetDateTime::now_utc();
+    let format = time::macros::format_description!(
+        "[year]-[month]-[day]T[hour]:[minute]:[second].[subsecond digits:3]Z"
+    );
+    now.format(format).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    /// Tests filter parsing and module levels.
+    #[test_case("info", &[("toydb", "INFO"), ("toydb::raft", "INFO"), ("rustls", "OFF")]; "default")]
+    #[test_case("DEBUG,toydb::raft=warn,toydb::raft::log=trace,rustls=error", &[
+        ("toydb::sql", "DEBUG"),
+        ("toydb::raft", "WARN"),
+        ("toydb::raft::node", "WARN"),
+        ("toydb::raft::log", "TRACE"),
+        ("toydb::raftish", "DEBUG"),
+        ("rustls::client", "ERROR"),
+        ("tokio", "OFF"),
+    ]; "modules")]
+    #[test_case("toydb::sql=debug", &[("toydb::sql", "DEBUG"), ("toydb::raft", "INFO")]; "module only")]
+    fn filter(spec: &str, expect: &[(&str, &str)]) {
+        let filter: Filter = spec.parse().expect("invalid filter");
+        for (module, level) in expect {
+            assert_eq!(filter.level(module).as_str(), *level, "module {module}");
+        }
+    }
+
+    /// Tests invalid filters.
+    #[test_case(""; "empty")]
+    #[test_case("loud"; "invalid level")]
+    #[test_case("info,toydb::raft=loud"; "invalid module level")]
+    #[test_case("info,warn"; "repeated default")]
+    #[test_case("info,=warn"; "empty module")]
+    fn filter_error(spec: &str) {
+        assert!(spec.parse::<Filter>().is_err());
+    }
+}
@@ -0,0 +1,365 @@
+//! A central registry of server metrics, exposed in the Prometheus text format
+//! via the HTTP `GET /metrics` endpoint (see http::serve).
+//!
+//! Metrics are process-wide statics that each module reports into directly,
+//! e.g. `metrics::STATEMENT_DURATION.observe(&["SELECT"], seconds)`. There are
+//! three kinds of metrics:
+//!
+//! * Counter: a monotonically increasing count, e.g. of committed transactions.
+//! * Gauge: a value that can go up and down, e.g. the storage size.
+//! * Histogram: a distribution of observed values in cumulative buckets, e.g.
+//!   of statement latencies, along with their sum and count.
+//!
+//! Each metric can have a fixed set of label names, and tracks a separate value
+//! for each combination of label values (e.g. statement="SELECT"). These should
+//! have low cardinality, since every combination is retained and exported.
+//!
+//! Cluster gauges such as the Raft term and storage sizes are sampled from the
+//! SQL engine status when metrics are scraped (see observe_status), rather than
+//! tracked continuously.
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::sync::{Mutex, MutexGuard};
+
+use crate::sql::engine::Status;
+
+/// Statement latencies, by SQL command.
+pub static STATEMENT_DURATION: Histogram = Histogram::new(
+    "toydb_statement_duration_seconds",
+    "SQL statement execution latency, by statement type.",
+    &["statement"],
+    LATENCY_BUCKETS,
+);
+
+/// Statement errors, by SQL command.
+pub static STATEMENT_ERRORS: Counter = Counter::new(
+    "toydb_statement_errors_total",
+    "SQL statements that returned an error, by statement type.",
+    &["statement"],
+);
+
+/// Read-write transaction commits.
+pub static TXN_COMMITS: Counter =
+    Counter::new("toydb_txn_commits_total", "Read-write transactions that committed.", &[]);
+
+/// Read-write transaction rollbacks.
+pub static TXN_ROLLBACKS: Counter = Counter::new(
+    "toydb_txn_rollbacks_total",
+    "Read-write transactions that were rolled back, explicitly or on errors.",
+    &[],
+);
+
+/// Retryable statement errors, by error kind.
+pub static TXN_RETRYABLE_ERRORS: Counter = Counter::new(
+    "toydb_txn_retryable_errors_total",
+    "Statements that failed with an error the client should retry, e.g. a \
+     serialization failure, by error kind.",
+    &["error"],
+);
+
+/// Server-side retries of SELECT FOR UPDATE while waiting for row locks.
+pub static LOCK_WAIT_RETRIES: Counter = Counter::new(
+    "toydb_lock_wait_retries_total",
+    "SELECT FOR UPDATE retries while waiting for rows locked by other \
+     transactions.",
+    &[],
+);
+
+/// Raft request latencies, by request type.
+pub static RAFT_REQUEST_DURATION: Histogram = Histogram::new(
+    "toydb_raft_request_duration_seconds",
+    "Latency of Raft requests submitted by the SQL engine, by request type. \
+     Writes are proposals, which return once committed and applied.",
+    &["request"],
+    LATENCY_BUCKETS,
+);
+
+/// Result cache lookups, by hit or miss.
+pub static RESULT_CACHE_REQUESTS: Counter = Counter::new(
+    "toydb_result_cache_requests_total",
+    "Query result cache lookups, by result (hit or miss).",
+    &["result"],
+);
+
+/// Plan cache lookups, by hit or miss.
+pub static PLAN_CACHE_REQUESTS: Counter = Counter::new(
+    "toydb_plan_cache_requests_total",
+    "Query plan cache lookups, by result (hit or miss).",
+    &["result"],
+);
+
+/// Replica divergences found by consistency checks.
+pub static REPLICA_DIVERGENCES: Counter = Counter::new(
+    "toydb_replica_divergences_total",
+    "Nodes found by consistency checks to have diverged from the leader's state \
+     machine at the same applied index, indicating a replication bug.",
+    &[],
+);
+
+/// The Raft term.
+pub static RAFT_TERM: Gauge = Gauge::new("toydb_raft_term", "The current Raft term.", &[]);
+
+/// The Raft commit index.
+pub static RAFT_COMMIT_INDEX: Gauge =
+    Gauge::new("toydb_raft_commit_index", "The Raft commit index of the leader.", &[]);
+
+/// The Raft applied index.
+pub static RAFT_APPLIED_INDEX: Gauge =
+    Gauge::new("toydb_raft_applied_index", "The Raft applied index of the leader.", &[]);
+
+/// The number of live storage keys, by store.
+pub static STORAGE_KEYS: Gauge = Gauge::new(
+    "toydb_storage_keys",
+    "Live keys in the leader's storage engines, by store (raft or sql).",
+    &["store"],
+);
+
+/// Storage sizes in bytes, by store and kind.
+pub static STORAGE_BYTES: Gauge = Gauge::new(
+    "toydb_storage_bytes",
+    "Size of the leader's storage engines in bytes, by store (raft or sql) and \
+     kind: logical size of live data, or total and live on-disk size.",
+    &["store", "kind"],
+);
+
+/// The number of MVCC versions.
+pub static MVCC_VERSIONS: Gauge = Gauge::new(
+    "toydb_mvcc_versions",
+    "The number of MVCC versions, i.e. read-write transactions.",
+    &[],
+);
+
+/// The number of active MVCC transactions.
+pub static MVCC_ACTIVE_TXNS: Gauge =
+    Gauge::new("toydb_mvcc_active_txns", "The number of active MVCC transactions.", &[]);
+
+/// All registered metrics, in export order.
+static METRICS: &[&dyn Metric] = &[
+    &STATEMENT_DURATION,
+    &STATEMENT_ERRORS,
+    &TXN_COMMITS,
+    &TXN_ROLLBACKS,
+    &TXN_RETRYABLE_ERRORS,
+    &LOCK_WAIT_RETRIES,
+    &RAFT_REQUEST_DURATION,
+    &RESULT_CACHE_REQUESTS,
+    &PLAN_CACHE_REQUESTS,
+    &REPLICA_DIVERGENCES,
+    &RAFT_TERM,
+    &RAFT_COMMIT_INDEX,
+    &RAFT_APPLIED_INDEX,
+    &STORAGE_KEYS,
+    &STORAGE_BYTES,
+    &MVCC_VERSIONS,
+    &MVCC_ACTIVE_TXNS,
+];
+
+/// Latency histogram buckets in seconds, from 0.5 ms to 10 s.
+const LATENCY_BUCKETS: &[f64] =
+    &[0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+/// Renders all metrics in the Prometheus text exposition format.
+pub fn render() -> String {
+    let mut output = String::new();
+    for metric in METRICS {
+        metric.render(&mut output);
+    }
+    output
+}
+
+/// Samples the cluster gauges from the SQL engine status.
+pub fn observe_status(status: &Status) {
+    RAFT_TERM.set(&[], status.raft.term as f64);
+    RAFT_COMMIT_INDEX.set(&[], status.raft.commit_index as f64);
+    RAFT_APPLIED_INDEX.set(&[], status.raft.applied_index as f64);
+    for (store, storage) in [("raft", &status.raft.storage), ("sql", &status.mvcc.storage)] {
+        STORAGE_KEYS.set(&[store], storage.keys as f64);
+        STORAGE_BYTES.set(&[store, "logical"], storage.size as f64);
+        STORAGE_BYTES.set(&[store, "disk"], storage.disk_size as f64);
+        STORAGE_BYTES.set(&[store, "live_disk"], storage.live_disk_size as f64);
+    }
+    MVCC_VERSIONS.set(&[], status.mvcc.versions as f64);
+    MVCC_ACTIVE_TXNS.set(&[], status.mvcc.active_txns as f64);
+}
+
+/// A metric, which can be rendered in the Prometheus text format.
+trait Metric: Sync {
+    /// Appends the metric's HELP and TYPE lines and samples to the output.
+    fn render(&self, output: &mut String);
+}
+
+/// A monotonically increasing counter.
+pub struct Counter {
+    name: &'static str,
+    help: &'static str,
+    labels: &'static [&'static str],
+    values: Mutex<BTreeMap<Vec<String>, u64>>,
+}
+
+impl Counter {
+    /// Creates a new counter with the given label names.
+    const fn new(name: &'static str, help: &'static str, labels: &'static [&'static str]) -> Self {
+        Self { name, help, labels, values: Mutex::new(BTreeMap::new()) }
+    }
+
+    /// Increments the counter for the given label values.
+    pub fn inc(&self, labels: &[&str]) {
+        self.add(labels, 1)
+    }
+
+    /// Adds to the counter for the given label values.
+    pub fn add(&self, labels: &[&str], value: u64) {
+        assert_eq!(labels.len(), self.labels.len(), "invalid labels for {}", self.name);
+        *lock(&self.values).entry(label_values(labels)).or_default() += value;
+    }
+}
+
+impl Metric for Counter {
+    fn render(&self, output: &mut String) {
+        write_header(output, self.name, self.help, "counter");
+        let values = lock(&self.values);
+        if values.is_empty() && self.labels.is_empty() {
+            writeln!(output, "{} 0", self.name).expect("write failed");
+        }
+        for (labels, value) in values.iter() {
+            let labels = format_labels(self.labels, labels, None);
+            writeln!(output, "{}{labels} {value}", self.name).expect("write failed");
+        }
+    }
+}
+
+/// A gauge, i.e. a value that can go up and down.
+pub struct Gauge {
+    name: &'static str,
+    help: &'static str,
+    labels: &'static [&'static str],
+    values: Mutex<BTreeMap<Vec<String>, f64>>,
+}
+
+impl Gauge {
+    /// Creates a new gauge with the given label names.
+    const fn new(name: &'static str, help: &'static str, labels: &'static [&'static str]) -> Self {
+        Self { name, help, labels, values: Mutex::new(BTreeMap::new()) }
+    }
+
+    /// Sets the gauge for the given label values.
+    pub fn set(&self, labels: &[&str], value: f64) {
+        assert_eq!(labels.len(), self.labels.len(), "invalid labels for {}", self.name);
+        lock(&self.values).insert(label_values(labels), value);
+    }
+}
+
+impl Metric for Gauge {
+    fn render(&self, output: &mut String) {
+        write_header(output, self.name, self.help, "gauge");
+        for (labels, value) in lock(&self.values).iter() {
+            let labels = format_labels(self.labels, labels, None);
+            writeln!(output, "{}{labels} {value}", self.name).expect("write failed");
+        }
+    }
+}
+
+/// A histogram of observed values, with cumulative buckets given by their
+/// inclusive upper bounds (plus an implicit +Inf bucket).
+pub struct Histogram {
+    name: &'static str,
+    help: &'static str,
+    labels: &'static [&'static str],
+    buckets: &'static [f64],
+    values: Mutex<BTreeMap<Vec<String>, HistogramValue>>,
+}
+
+/// The observations of a histogram for a set of label values.
+#[derive(Default)]
+struct HistogramValue {
+    /// The number of observations in each bucket (non-cumulative).
+    buckets: Vec<u64>,
+    /// The sum of all observations.
+    sum: f64,
+    /// The number of observations.
+    count: u64,
+}
+
+impl Histogram {
+    /// Creates a new histogram with the given label names and buckets.
+    const fn new(
+        name: &'static str,
+        help: &'static str,
+        labels: &'static [&'static str],
+        buckets: &'static [f64],
+    ) -> Self {
+        Self { name, help, labels, buckets, values: Mutex::new(BTreeMap::new()) }
+    }
+
+    /// Records an observation for the given label values.
+    pub fn observe(&self, labels: &[&str], value: f64) {
+        assert_eq!(labels.len(), self.labels.len(), "invalid labels for {}", self.name);
+        let mut values = lock(&self.values);
+        let entry = values.entry(label_values(labels)).or_default();
+        entry.buckets.resize(self.buckets.len(), 0);
+        if let Some(i) = self.buckets.iter().position(|bound| value <= *bound) {
+            entry.buckets[i] += 1;
+        }
+        entry.sum += value;
+        entry.count += 1;
+    }
+}
+
+impl Metric for Histogram {
+    fn render(&self, output: &mut String) {
+        write_header(output, self.name, self.help, "histogram");
+        for (labels, value) in lock(&self.values).iter() {
+            let mut cumulative = 0;
+            for (bound, count) in self.buckets.iter().zip(&value.buckets) {
+                cumulative += count;
+                let labels = format_labels(self.labels, labels, Some(&bound.to_string()));
+                writeln!(output, "{}_bucket{labels} {cumulative}", self.name)
+                    .expect("write failed");
+            }
+            let name = self.name;
+            let inf = format_labels(self.labels, labels, Some("+Inf"));
+            let labels = format_labels(self.labels, labels, None);
+            writeln!(output, "{name}_bucket{inf} {}", value.count).expect("write failed");
+            writeln!(output, "{name}_sum{labels} {}", value.sum).expect("write failed");
+            writeln!(output, "{name}_count{labels} {}", value.count).expect("write failed");
+        }
+    }
+}
+
+/// Locks a metric's values. Metrics are only updated via simple, non-panicking
+/// operations, so a poisoned lock still holds valid values.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Converts label values to an owned map key.
+fn label_values(labels: &[&str]) -> Vec<String> {
+    labels.iter().map(|label| label.to_string()).collect()
+}
+
+/// Writes the HELP and TYPE lines of a metric.
+fn write_header(output: &mut String, name: &str, help: &str, kind: &str) {
+    writeln!(output, "# HELP {name} {help}").expect("write failed");
+    writeln!(output, "# TYPE {name} {kind}").expect("write failed");
+}
+
+/// Formats a label set, e.g. {statement="SELECT",le="0.5"}, with an optional
+/// histogram bucket bound. Returns an empty string if there are no labels.
+fn format_labels(names: &[&str], values: &[String], le: Option<&str>) -> String {
+    let mut pairs: Vec<String> = names
+        .iter()
+        .zip(values)
+        .map(|(name, value)| {
+            let value = value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n");
+            format!("{name}=\"{value}\"")
+        })
+        .collect();
+    if let Some(le) = le {
+        pairs.push(format!("le=\"{le}\""));
+    }
+    if pairs.is_empty() {
+        return String::new();
+    }
+    format!("{{{}}}", pairs.join(","))
+}
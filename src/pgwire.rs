@@ -0,0 +1,993 @@
+//! A PostgreSQL wire protocol (version 3) front end, which allows existing
+//! Postgres clients and drivers (e.g. psql, psycopg2, or JDBC) to talk to
+//! toyDB. The server listens for Postgres connections on a separate address,
+//! and executes their statements in regular SQL sessions. For details on the
+//! protocol, see: <https://www.postgresql.org/docs/current/protocol.html>
+//!
+//! It supports:
+//!
+//! * Startup, with cleartext password authentication if the server has
+//!   authentication enabled (clients should use TLS in that case). SSL
+//!   encryption requests are accepted if the server has TLS enabled (see tls),
+//!   and declined otherwise, in which case clients fall back to unencrypted
+//!   connections. GSSAPI encryption requests are always declined.
+//!
+//! * Simple queries, with one or more statements per query.
+//!
+//! * Extended queries, i.e. Parse, Bind, Describe, Execute, Close, Sync, and
+//!   Flush messages. toyDB doesn't have query parameters, so $1-style
+//!   parameters are substituted into the query text as SQL literals. Untyped
+//!   text parameters are substituted as numbers or booleans if they parse as
+//!   such, and otherwise as strings.
+//!
+//! * Row descriptions, and text or binary result and parameter formats for
+//!   basic types. Result column types are inferred from the values, since
+//!   toyDB doesn't track result types, and columns without any values are
+//!   described as text.
+//!
+//! * Error responses, with SQLSTATE codes derived from the error kind.
+//!
+//! It does not support COPY, function calls, cancel requests, notices, or the
+//! Postgres system catalogs (e.g. pg_catalog) that some clients query. SET
+//! statements for session parameters, which drivers often send on startup,
+//! are accepted and ignored (except SET TRANSACTION).
+//!
+//! For simplicity, statement results are buffered before they're sent,
+//! since the row description must be sent before the rows.
+
+use std::collections::{HashMap, VecDeque};
+use std::io::{BufReader, BufWriter, ErrorKind, Read as _, Write as _};
+use std::net::TcpStream;
+use std::sync::Arc;
+
+use itertools::Itertools as _;
+use log::debug;
+
+use crate::errinput;
+use crate::error::{Error, Result};
+use crate::sql::engine::{IntervalStyle, Raft, Session, StatementResult, VARIABLES};
+use crate::sql::execution::Returning;
+use crate::sql::types::{Label, Row, Value};
+use crate::tls::Stream;
+
+/// The protocol version 3.0 startup code.
+const PROTOCOL_VERSION: i32 = 196608;
+
+/// The startup code of an SSL encryption request.
+const SSL_REQUEST: i32 = 80877103;
+
+/// The startup code of a GSSAPI encryption request.
+const GSSENC_REQUEST: i32 = 80877104;
+
+/// The startup code of a cancel request.
+const CANCEL_REQUEST: i32 = 80877102;
+
+/// The reported server version. Some drivers parse it to determine which
+/// features are available.
+const SERVER_VERSION: &str = "14.0";
+
+/// Postgres type OIDs.
+mod oid {
+    pub const UNSPECIFIED: i32 = 0;
+    pub const BOOL: i32 = 16;
+    pub const INT8: i32 = 20;
+    pub const INT2: i32 = 21;
+    pub const INT4: i32 = 23;
+    pub const TEXT: i32 = 25;
+    pub const JSON: i32 = 114;
+    pub const FLOAT4: i32 = 700;
+    pub const FLOAT8: i32 = 701;
+    pub const UNKNOWN: i32 = 705;
+    pub const VARCHAR: i32 = 1043;
+    pub const TIMESTAMP: i32 = 1114;
+    pub const INTERVAL: i32 = 1186;
+    pub const BOOL_ARRAY: i32 = 1000;
+    pub const INT8_ARRAY: i32 = 1016;
+    pub const TEXT_ARRAY: i32 = 1009;
+    pub const FLOAT8_ARRAY: i32 = 1022;
+    pub const JSON_ARRAY: i32 = 199;
+    pub const TIMESTAMP_ARRAY: i32 = 1115;
+    pub const INTERVAL_ARRAY: i32 = 1187;
+}
+
+/// The text format code.
+const TEXT: i16 = 0;
+
+/// The binary format code.
+const BINARY: i16 = 1;
+
+/// Serves a Postgres client connection, executing its statements in the given
+/// session until the client disconnects. If given a TLS config, clients can
+/// request an encrypted connection.
+pub fn serve(
+    socket: TcpStream,
+    tls: Option<Arc<rustls::ServerConfig>>,
+    session: Session<'_, Raft>,
+) -> Result<()> {
+    let stream = Stream::Tcp(socket);
+    let reader = BufReader::new(stream.try_clone()?);
+    let writer = BufWriter::new(stream);
+    let (statements, portals) = (HashMap::new(), HashMap::new());
+    let mut connection = Connection { reader, writer, tls, session, statements, portals };
+    if connection.startup()? {
+        connection.run()?;
+    }
+    Ok(())
+}
+
+/// A Postgres client connection.
+struct Connection<'a> {
+    reader: BufReader<Stream>,
+    writer: BufWriter<Stream>,
+    /// The TLS config, if SSL requests should be accepted.
+    tls: Option<Arc<rustls::ServerConfig>>,
+    session: Session<'a, Raft>,
+    /// Prepared statements, by name. The empty name is the unnamed statement.
+    statements: HashMap<String, Prepared>,
+    /// Portals, by name. The empty name is the unnamed portal.
+    portals: HashMap<String, Portal>,
+}
+
+/// A prepared statement, created by a Parse message.
+struct Prepared {
+    /// The query text, with $1-style parameters.
+    query: String,
+    /// The parameter type OIDs given by the client. May be shorter than the
+    /// number of parameters, and 0 means unspecified.
+    param_types: Vec<i32>,
+}
+
+/// A portal, created by a Bind message. It's executed when first described
+/// or executed, buffering the result until it's fetched by Execute messages.
+struct Portal {
+    /// The query text, with bound parameters.
+    query: String,
+    /// The result column format codes.
+    formats: Vec<i16>,
+    /// The buffered result, once executed. None for empty queries.
+    output: Option<Option<Output>>,
+}
+
+/// A buffered statement result.
+struct Output {
+    /// The command tag, without the row count for statements that return
+    /// rows (e.g. SELECT or INSERT RETURNING).
+    tag: String,
+    /// The result columns, for statements that return rows.
+    columns: Option<Vec<Column>>,
+    /// The result rows that haven't been sent yet.
+    rows: VecDeque<Row>,
+}
+
+/// A result column.
+struct Column {
+    name: String,
+    type_oid: i32,
+}
+
+impl Connection<'_> {
+    /// Handles connection startup. Returns false if the client disconnected
+    /// or sent a cancel request instead.
+    fn startup(&mut self) -> Result<bool> {
+        let mut params = Vec::new();
+        loop {
+            let mut len = [0; 4];
+            match self.reader.read_exact(&mut len) {
+                Err(err) if err.kind() == ErrorKind::UnexpectedEof => return Ok(false),
+                result => result?,
+            }
+            let len = i32::from_be_bytes(len);
+            if !(8..=10_000).contains(&len) {
+                return errinput!("invalid startup message length {len}");
+            }
+            let mut body = vec![0; len as usize - 4];
+            self.reader.read_exact(&mut body)?;
+            let mut body = Body(&body);
+            match body.i32()? {
+                SSL_REQUEST if self.tls.is_some() => self.start_tls()?,
+                SSL_REQUEST | GSSENC_REQUEST => {
+                    self.writer.write_all(b"N")?;
+                    self.writer.flush()?;
+                }
+                CANCEL_REQUEST => return Ok(false),
+                PROTOCOL_VERSION => {
+                    while body.0.first().is_some_and(|b| *b != 0) {
+                        params.push((body.string()?, body.string()?));
+                    }
+                    break;
+                }
+                code => {
+                    let (major, minor) = (code >> 16, code & 0xffff);
+                    return errinput!("unsupported protocol version {major}.{minor}");
+                }
+            }
+        }
+        debug!("Postgres client started with {params:?}");
+
+        // If the session requires authentication, ask for a cleartext
+        // password and authenticate as the startup user. On failure, send the
+        // error and disconnect.
+        if self.session.check_authenticated().is_err() {
+            self.send(b'R', |b| put_i32(b, 3))?; // AuthenticationCleartextPassword
+            self.writer.flush()?;
+            let password = match self.receive()? {
+                Some((b'p', body)) => Body(&body).string()?,
+                Some((kind, _)) => return errinput!("expected password, got {}", kind as char),
+                None => return Ok(false),
+            };
+            let user = params.iter().find(|(name, _)| name == "user").map(|(_, user)| user);
+            let user = user.map(|user| user.as_str()).unwrap_or_default();
+            if let Err(error) = self.session.authenticate(user, &password) {
+                self.send_error(&error)?;
+                self.writer.flush()?;
+                return Ok(false);
+            }
+        }
+        self.send(b'R', |b| put_i32(b, 0))?; // AuthenticationOk
+        let application_name = params
+            .iter()
+            .find(|(name, _)| name == "application_name")
+            .map(|(_, value)| value.as_str())
+            .unwrap_or_default()
+            .to_string();
+        let timezone = self.session.timezone().to_string();
+        let interval_style = self.session.interval_style().to_string();
+        for (name, value) in [
+            ("server_version", SERVER_VERSION),
+            ("server_encoding", "UTF8"),
+            ("client_encoding", "UTF8"),
+            ("DateStyle", "ISO, MDY"),
+            ("TimeZone", &timezone),
+            ("IntervalStyle", &interval_style),
+            ("integer_datetimes", "on"),
+            ("standard_conforming_strings", "on"),
+            ("application_name", &application_name),
+        ] {
+            self.send(b'S', |b| {
+                put_str(b, name);
+                put_str(b, value);
+            })?;
+        }
+        // Cancel requests aren't supported, so the key data is unused.
+        self.send(b'K', |b| {
+            put_i32(b, std::process::id() as i32);
+            put_i32(b, 0);
+        })?;
+        self.ready()?;
+        self.writer.flush()?;
+        Ok(true)
+    }
+
+    /// Accepts an SSL request, and performs a TLS handshake. The client waits
+    /// for the response before starting the handshake, so there shouldn't be
+    /// any buffered plaintext.
+    fn start_tls(&mut self) -> Result<()> {
+        let (Stream::Tcp(socket), Some(tls)) = (self.writer.get_ref(), &self.tls) else {
+            return errinput!("unexpected SSL request");
+        };
+        if !self.reader.buffer().is_empty() {
+            return errinput!("unexpected data after SSL request");
+        }
+        let socket = socket.try_clone()?;
+        self.writer.write_all(b"S")?;
+        self.writer.flush()?;
+        let stream = Stream::accept(socket, Some(tls))?;
+        self.reader = BufReader::new(stream.try_clone()?);
+        self.writer = BufWriter::new(stream);
+        Ok(())
+    }
+
+    /// Processes client messages until the client disconnects.
+    fn run(&mut self) -> Result<()> {
+        // After an error in an extended query, messages are discarded until
+        // the next Sync.
+        let mut failed = false;
+        while let Some((kind, body)) = self.receive()? {
+            if failed && kind != b'S' && kind != b'X' {
+                continue;
+            }
+            let body = Body(&body);
+            let result = match kind {
+                b'Q' => self.query(body),
+                b'P' => self.parse(body),
+                b'B' => self.bind(body),
+                b'D' => self.describe(body),
+                b'E' => self.execute(body),
+                b'C' => self.close(body),
+                b'S' => {
+                    failed = false;
+                    self.ready()
+                }
+                b'H' => Ok(()), // flush, which happens below anyway
+                b'X' => return Ok(()),
+                kind => errinput!("unsupported message type {}", kind as char),
+            };
+            if let Err(error) = result {
+                // If the socket failed, we can't send the error either.
+                if let Error::IO(_) = error {
+                    return Err(error);
+                }
+                self.send_error(&error)?;
+                match kind {
+                    b'Q' => self.ready()?,
+                    _ => failed = true,
+                }
+            }
+            self.writer.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Handles a simple query, executing its statements in order and sending
+    /// their results. Stops at the first error.
+    fn query(&mut self, mut body: Body) -> Result<()> {
+        let query = body.string()?;
+        let statements = split_statements(&query);
+        if statements.is_empty() {
+            self.send(b'I', |_| {})?; // EmptyQueryResponse
+        }
+        for statement in statements {
+            let mut output = self.execute_query(statement)?;
+            if let Some(columns) = &output.columns {
+                self.send_row_description(columns, &[])?;
+            }
+            self.send_rows(&mut output, &[], 0)?;
+        }
+        self.ready()
+    }
+
+    /// Handles a Parse message, creating a prepared statement.
+    fn parse(&mut self, mut body: Body) -> Result<()> {
+        let name = body.string()?;
+        let query = body.string()?;
+        let param_types = (0..body.i16()?).map(|_| body.i32()).try_collect()?;
+        if !name.is_empty() && self.statements.contains_key(&name) {
+            return errinput!("prepared statement {name} already exists");
+        }
+        self.statements.insert(name, Prepared { query, param_types });
+        self.send(b'1', |_| {}) // ParseComplete
+    }
+
+    /// Handles a Bind message, creating a portal by binding parameters to a
+    /// prepared statement.
+    fn bind(&mut self, mut body: Body) -> Result<()> {
+        let portal = body.string()?;
+        let name = body.string()?;
+        let Some(statement) = self.statements.get(&name) else {
+            return errinput!("prepared statement {name} does not exist");
+        };
+        let param_formats: Vec<i16> = (0..body.i16()?).map(|_| body.i16()).try_collect()?;
+        let mut params = Vec::new();
+        for i in 0..body.i16()? as usize {
+            let value = match body.i32()? {
+                -1 => None,
+                len if len < 0 => return errinput!("invalid parameter length {len}"),
+                len => Some(body.bytes(len as usize)?),
+            };
+            let type_oid = statement.param_types.get(i).copied().unwrap_or(oid::UNSPECIFIED);
+            params.push(param_literal(value, format_code(&param_formats, i), type_oid)?);
+        }
+        let formats = (0..body.i16()?).map(|_| body.i16()).try_collect()?;
+        let query = bind_params(&statement.query, &params)?;
+        self.portals.insert(portal, Portal { query, formats, output: None });
+        self.send(b'2', |_| {}) // BindComplete
+    }
+
+    /// Handles a Describe message for a prepared statement or portal.
+    /// Statement results can't be described without executing them, so
+    /// prepared statements are described as returning no data, while portals
+    /// are executed to describe their results.
+    fn describe(&mut self, mut body: Body) -> Result<()> {
+        let kind = body.u8()?;
+        let name = body.string()?;
+        match kind {
+            b'S' => {
+                let Some(statement) = self.statements.get(&name) else {
+                    return errinput!("prepared statement {name} does not exist");
+                };
+                let count = count_params(&statement.query).max(statement.param_types.len());
+                let types = (0..count)
+                    .map(|i| match statement.param_types.get(i).copied() {
+                        None | Some(oid::UNSPECIFIED) => oid::TEXT,
+                        Some(type_oid) => type_oid,
+                    })
+                    .collect_vec();
+                self.send(b't', |b| {
+                    put_i16(b, types.len() as i16);
+                    types.iter().for_each(|type_oid| put_i32(b, *type_oid));
+                })?;
+                self.send(b'n', |_| {}) // NoData
+            }
+            b'P' => {
+                let mut portal = self.take_portal(&name)?;
+                let result = self.execute_portal(&mut portal).and_then(|_| {
+                    match portal.output.iter().flatten().find_map(|o| o.columns.as_ref()) {
+                        Some(columns) => self.send_row_description(columns, &portal.formats),
+                        None => self.send(b'n', |_| {}), // NoData
+                    }
+                });
+                self.portals.insert(name, portal);
+                result
+            }
+            kind => errinput!("invalid describe kind {}", kind as char),
+        }
+    }
+
+    /// Handles an Execute message, executing a portal and sending up to the
+    /// given number of result rows (0 for all).
+    fn execute(&mut self, mut body: Body) -> Result<()> {
+        let name = body.string()?;
+        let max_rows = body.i32()?;
+        let mut portal = self.take_portal(&name)?;
+        let result = self.execute_portal(&mut portal).and_then(|_| {
+            match portal.output.iter_mut().flatten().next() {
+                Some(output) => self.send_rows(output, &portal.formats, max_rows),
+                None => self.send(b'I', |_| {}), // EmptyQueryResponse
+            }
+        });
+        self.portals.insert(name, portal);
+        result
+    }
+
+    /// Handles a Close message, closing a prepared statement or portal.
+    fn close(&mut self, mut body: Body) -> Result<()> {
+        let kind = body.u8()?;
+        let name = body.string()?;
+        match kind {
+            b'S' => self.statements.remove(&name).map(|_| ()),
+            b'P' => self.portals.remove(&name).map(|_| ()),
+            kind => return errinput!("invalid close kind {}", kind as char),
+        };
+        self.send(b'3', |_| {}) // CloseComplete
+    }
+
+    /// Removes and returns a portal. The caller must reinsert it.
+    fn take_portal(&mut self, name: &str) -> Result<Portal> {
+        match self.portals.remove(name) {
+            Some(portal) => Ok(portal),
+            None => errinput!("portal {name} does not exist"),
+        }
+    }
+
+    /// Executes a portal's query, unless it's already been executed, and
+    /// buffers its output.
+    fn execute_portal(&mut self, portal: &mut Portal) -> Result<()> {
+        if portal.output.is_none() {
+            portal.output = Some(match split_statements(&portal.query).as_slice() {
+                [] => None,
+                [statement] => Some(self.execute_query(statement)?),
+                _ => return errinput!("extended queries can only contain a single statement"),
+            });
+        }
+        Ok(())
+    }
+
+    /// Executes a single statement and buffers its result.
+    fn execute_query(&mut self, statement: &str) -> Result<Output> {
+        if is_ignored_set(statement) {
+            debug!("Ignoring Postgres statement {statement}");
+            return Ok(Output::complete("SET"));
+        }
+        use StatementResult::*;
+        let output = match self.session.execute(statement)? {
+            Begin(_) => Output::complete("BEGIN"),
+            Commit { .. } => Output::complete("COMMIT"),
+            Rollback { .. } => Output::complete("ROLLBACK"),
+            Savepoint { .. } => Output::complete("SAVEPOINT"),
+            RollbackToSavepoint { .. } => Output::complete("ROLLBACK"),
+            ReleaseSavepoint { .. } => Output::complete("RELEASE"),
+            AbortTransaction { .. } => Output::complete("ABORT TRANSACTION"),
+            SetTransaction { .. } => Output::complete("SET"),
+            Set { name, value } => {
+                // Report parameters tracked by Postgres clients.
+                let parameter = match name.as_str() {
+                    "timezone" => Some("TimeZone"),
+                    "intervalstyle" => Some("IntervalStyle"),
+                    _ => None,
+                };
+                if let Some(parameter) = parameter {
+                    self.send(b'S', |b| {
+                        put_str(b, parameter);
+                        put_str(b, &value);
+                    })?;
+                }
+                Output::complete("SET")
+            }
+            Explain(plan) => {
+                let column = Column { name: "QUERY PLAN".to_string(), type_oid: oid::TEXT };
+                let rows = plan.to_string().lines().map(|l| vec![l.into()]).collect();
+                Output { tag: "EXPLAIN".to_string(), columns: Some(vec![column]), rows }
+            }
+            CreateTable { .. } => Output::complete("CREATE TABLE"),
+            DropTable { .. } => Output::complete("DROP TABLE"),
+            CreateIndex { .. } => Output::complete("CREATE INDEX"),
+            CreateTrigger { .. } => Output::complete("CREATE TRIGGER"),
+            DropTrigger { .. } => Output::complete("DROP TRIGGER"),
+            CreateChangefeed { .. } => Output::complete("CREATE CHANGEFEED"),
+            DropChangefeed { .. } => Output::complete("DROP CHANGEFEED"),
+            DropPartition { .. } => Output::complete("ALTER TABLE"),
+            TruncateTable { .. } => Output::complete("TRUNCATE TABLE"),
+            CreateUser { .. } => Output::complete("CREATE ROLE"),
+            DropUser { .. } => Output::complete("DROP ROLE"),
+            Grant { .. } => Output::complete("GRANT"),
+            Revoke { .. } => Output::complete("REVOKE"),
+            // RETURNING rows are sent like SELECT rows, and the row count is
+            // appended to the tag by send_rows().
+            Delete { returning: Some(Returning { columns, rows }), .. } => {
+                Output::rows("DELETE", &columns, rows)
+            }
+            Insert { returning: Some(Returning { columns, rows }), .. } => {
+                Output::rows("INSERT 0", &columns, rows)
+            }
+            Update { returning: Some(Returning { columns, rows }), .. } => {
+                Output::rows("UPDATE", &columns, rows)
+            }
+            Delete { count, returning: None } => Output::complete(&format!("DELETE {count}")),
+            Insert { count, returning: None } => Output::complete(&format!("INSERT 0 {count}")),
+            Import { count } => Output::complete(&format!("IMPORT {count}")),
+            Export { count } => Output::complete(&format!("COPY {count}")),
+            Update { count, returning: None } => Output::complete(&format!("UPDATE {count}")),
+            Select { columns, rows } => Output::rows("SELECT", &columns, rows),
+        };
+        Ok(output)
+    }
+
+    /// Sends a row description for the given columns and format codes.
+    fn send_row_description(&mut self, columns: &[Column], formats: &[i16]) -> Result<()> {
+        self.send(b'T', |b| {
+            put_i16(b, columns.len() as i16);
+            for (i, column) in columns.iter().enumerate() {
+                put_str(b, &column.name);
+                put_i32(b, 0); // table OID
+                put_i16(b, 0); // column attribute number
+                put_i32(b, column.type_oid);
+                put_i16(b, type_size(column.type_oid));
+                put_i32(b, -1); // type modifier
+                put_i16(b, format_code(formats, i));
+            }
+        })
+    }
+
+    /// Sends up to max_rows buffered result rows (0 for all), followed by
+    /// PortalSuspended if there are more rows, or CommandComplete otherwise.
+    fn send_rows(&mut self, output: &mut Output, formats: &[i16], max_rows: i32) -> Result<()> {
+        let interval_style = self.session.interval_style();
+        let mut count = 0;
+        while let Some(row) = output.rows.front() {
+            if max_rows > 0 && count >= max_rows as u64 {
+                return self.send(b's', |_| {}); // PortalSuspended
+            }
+            let values: Vec<_> = row
+                .iter()
+                .enumerate()
+                .map(|(i, value)| match format_code(formats, i) {
+                    BINARY => encode_binary(value),
+                    _ => Ok(encode_text(value, interval_style).map(String::into_bytes)),
+                })
+                .try_collect()?;
+            self.send(b'D', |b| {
+                put_i16(b, values.len() as i16);
+                for value in &values {
+                    match value {
+                        Some(value) => {
+                            put_i32(b, value.len() as i32);
+                            b.extend_from_slice(value);
+                        }
+                        None => put_i32(b, -1),
+                    }
+                }
+            })?;
+            output.rows.pop_front();
+            count += 1;
+        }
+        let tag = match output.columns {
+            Some(_) => format!("{} {count}", output.tag),
+            None => output.tag.clone(),
+        };
+        self.send(b'C', |b| put_str(b, &tag)) // CommandComplete
+    }
+
+    /// Sends an error response.
+    fn send_error(&mut self, error: &Error) -> Result<()> {
+        debug!("Postgres client error: {error}");
+        self.send(b'E', |b| {
+            for (field, value) in [
+                (b'S', "ERROR"),
+                (b'V', "ERROR"),
+                (b'C', sqlstate(error)),
+                (b'M', &error.to_string()),
+            ] {
+                b.push(field);
+                put_str(b, value);
+            }
+            b.push(0);
+        })
+    }
+
+    /// Sends ReadyForQuery, with the session's transaction status.
+    fn ready(&mut self) -> Result<()> {
+        let status = match self.session.in_transaction() {
+            true => b'T',
+            false => b'I',
+        };
+        self.send(b'Z', |b| b.push(status))
+    }
+
+    /// Receives a client message, returning its type and body, or None if the
+    /// client disconnected.
+    fn receive(&mut self) -> Result<Option<(u8, Vec<u8>)>> {
+        let mut header = [0; 5];
+        match self.reader.read_exact(&mut header) {
+            Err(err) if err.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            result => result?,
+        }
+        let len = i32::from_be_bytes(header[1..].try_into()?);
+        if len < 4 {
+            return errinput!("invalid message length {len}");
+        }
+        let mut body = vec![0; len as usize - 4];
+        self.reader.read_exact(&mut body)?;
+        Ok(Some((header[0], body)))
+    }
+
+    /// Sends a server message of the given type, with a body built by the
+    /// given closure. It's buffered until the writer is flushed.
+    fn send(&mut self, kind: u8, build: impl FnOnce(&mut Vec<u8>)) -> Result<()> {
+        let mut body = Vec::new();
+        build(&mut body);
+        self.writer.write_all(&[kind])?;
+        self.writer.write_all(&(body.len() as i32 + 4).to_be_bytes())?;
+        self.writer.write_all(&body)?;
+        Ok(())
+    }
+}
+
+impl Output {
+    /// Creates an output for a statement that doesn't return rows.
+    fn complete(tag: &str) -> Self {
+        Self { tag: tag.to_string(), columns: None, rows: VecDeque::new() }
+    }
+
+    /// Creates an output for a statement that returns rows. The column types
+    /// are inferred from the first non-NULL value in each column.
+    fn rows(tag: &str, columns: &[Label], rows: Vec<Row>) -> Self {
+        let columns = columns
+            .iter()
+            .enumerate()
+            .map(|(i, label)| Column {
+                name: label.as_header().to_string(),
+                type_oid: rows
+                    .iter()
+                    .map(|row| &row[i])
+                    .find(|value| **value != Value::Null)
+                    .map(value_type)
+                    .unwrap_or(oid::TEXT),
+            })
+            .collect();
+        Self { tag: tag.to_string(), columns: Some(columns), rows: rows.into() }
+    }
+}
+
+/// A client message body, consumed as it's read.
+struct Body<'a>(&'a [u8]);
+
+impl<'a> Body<'a> {
+    /// Reads the given number of bytes.
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.0.len() < len {
+            return errinput!("unexpected end of message");
+        }
+        let (bytes, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(bytes)
+    }
+
+    /// Reads a byte.
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    /// Reads a big-endian 16-bit integer.
+    fn i16(&mut self) -> Result<i16> {
+        Ok(i16::from_be_bytes(self.bytes(2)?.try_into()?))
+    }
+
+    /// Reads a big-endian 32-bit integer.
+    fn i32(&mut self) -> Result<i32> {
+        Ok(i32::from_be_bytes(self.bytes(4)?.try_into()?))
+    }
+
+    /// Reads a null-terminated string.
+    fn string(&mut self) -> Result<String> {
+        let Some(len) = self.0.iter().position(|b| *b == 0) else {
+            return errinput!("unterminated string in message");
+        };
+        let string = String::from_utf8(self.bytes(len)?.to_vec())?;
+        self.bytes(1)?;
+        Ok(string)
+    }
+}
+
+/// Appends a big-endian 16-bit integer to a message body.
+fn put_i16(body: &mut Vec<u8>, value: i16) {
+    body.extend_from_slice(&value.to_be_bytes())
+}
+
+/// Appends a big-endian 32-bit integer to a message body.
+fn put_i32(body: &mut Vec<u8>, value: i32) {
+    body.extend_from_slice(&value.to_be_bytes())
+}
+
+/// Appends a null-terminated string to a message body.
+fn put_str(body: &mut Vec<u8>, value: &str) {
+    body.extend_from_slice(value.as_bytes());
+    body.push(0);
+}
+
+/// Returns the format code for the given column or parameter: no codes
+/// means text, a single code applies to all, otherwise there's one per item.
+fn format_code(formats: &[i16], i: usize) -> i16 {
+    match formats {
+        [] => TEXT,
+        [format] => *format,
+        formats => formats.get(i).copied().unwrap_or(TEXT),
+    }
+}
+
+/// Returns the SQLSTATE code for an error.
+fn sqlstate(error: &Error) -> &'static str {
+    match error {
+        Error::Abort => "40000",           // transaction_rollback
+        Error::Busy(_) => "53300",         // too_many_connections
+        Error::Cancelled => "57014",       // query_canceled
+        Error::Deadlock => "40P01",        // deadlock_detected
+        Error::InvalidData(_) => "XX000",  // internal_error
+        Error::InvalidInput(_) => "42000", // syntax_error_or_access_rule_violation
+        Error::IO(_) => "58030",           // io_error
+        Error::Locked(_) => "55P03",       // lock_not_available
+        Error::ReadOnly => "25006",        // read_only_sql_transaction
+        Error::Serialization => "40001",   // serialization_failure
+        Error::Timeout => "57014",         // query_canceled
+        Error::Constraint(_) => "23000",   // integrity_constraint_violation
+        Error::NotLeader(_) => "57P03",    // cannot_connect_now
+        Error::Truncated(_) => "55000",    // object_not_in_prerequisite_state
+    }
+}
+
+/// Returns the Postgres type OID of a value. Null values don't have a type,
+/// and are described as text.
+fn value_type(value: &Value) -> i32 {
+    match value {
+        Value::Null | Value::String(_) => oid::TEXT,
+        Value::Boolean(_) => oid::BOOL,
+        Value::Integer(_) => oid::INT8,
+        Value::Float(_) => oid::FLOAT8,
+        Value::Json(_) => oid::JSON,
+        Value::Interval(_) => oid::INTERVAL,
+        Value::Timestamp(_) => oid::TIMESTAMP,
+        Value::Array(values) => match values.iter().find(|v| **v != Value::Null).map(value_type) {
+            Some(oid::BOOL) => oid::BOOL_ARRAY,
+            Some(oid::INT8) => oid::INT8_ARRAY,
+            Some(oid::FLOAT8) => oid::FLOAT8_ARRAY,
+            Some(oid::JSON) => oid::JSON_ARRAY,
+            Some(oid::INTERVAL) => oid::INTERVAL_ARRAY,
+            Some(oid::TIMESTAMP) => oid::TIMESTAMP_ARRAY,
+            _ => oid::TEXT_ARRAY,
+        },
+    }
+}
+
+/// Returns the size of a type in bytes, or -1 for variable-length types.
+fn type_size(type_oid: i32) -> i16 {
+    match type_oid {
+        oid::BOOL => 1,
+        oid::INT8 | oid::FLOAT8 | oid::TIMESTAMP => 8,
+        oid::INTERVAL => 16,
+        _ => -1,
+    }
+}
+
+/// Encodes a value in the text format. None for NULL.
+fn encode_text(value: &Value, interval_style: IntervalStyle) -> Option<String> {
+    Some(match value {
+        Value::Null => return None,
+        Value::Boolean(true) => "t".to_string(),
+        Value::Boolean(false) => "f".to_string(),
+        Value::Integer(integer) => integer.to_string(),
+        Value::Float(float) if float.is_nan() => "NaN".to_string(),
+        Value::Float(float) if *float == f64::INFINITY => "Infinity".to_string(),
+        Value::Float(float) if *float == f64::NEG_INFINITY => "-Infinity".to_string(),
+        Value::Float(float) => float.to_string(),
+        Value::String(string) => string.clone(),
+        Value::Json(json) => json.to_string(),
+        Value::Interval(interval) => match interval_style {
+            IntervalStyle::Postgres => interval.to_string(),
+            IntervalStyle::Iso8601 => interval.to_iso8601(),
+        },
+        Value::Timestamp(timestamp) => timestamp.to_string(),
+        Value::Array(values) => {
+            let mut elements =
+                values.iter().map(|value| match (value, encode_text(value, interval_style)) {
+                    (_, None) => "NULL".to_string(),
+                    (
+                        Value::String(_)
+                        | Value::Json(_)
+                        | Value::Interval(_)
+                        | Value::Timestamp(_),
+                        Some(text),
+                    ) => {
+                        format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+                    }
+                    (_, Some(text)) => text,
+                });
+            format!("{{{}}}", elements.join(","))
+        }
+    })
+}
+
+/// Encodes a value in the binary format. None for NULL. Only scalar types
+/// other than intervals and timestamps are supported.
+fn encode_binary(value: &Value) -> Result<Option<Vec<u8>>> {
+    Ok(Some(match value {
+        Value::Null => return Ok(None),
+        Value::Boolean(boolean) => vec![*boolean as u8],
+        Value::Integer(integer) => integer.to_be_bytes().to_vec(),
+        Value::Float(float) => float.to_be_bytes().to_vec(),
+        Value::String(string) => string.as_bytes().to_vec(),
+        Value::Json(json) => json.to_string().into_bytes(),
+        Value::Interval(_) | Value::Timestamp(_) | Value::Array(_) => {
+            return errinput!("binary format not supported for {value}");
+        }
+    }))
+}
+
+/// Converts a bound parameter value to a SQL literal, given its format code
+/// and type OID. Parameters of unspecified type are numbers if they parse as
+/// such, and strings otherwise.
+fn param_literal(value: Option<&[u8]>, format: i16, type_oid: i32) -> Result<String> {
+    let Some(value) = value else {
+        return Ok("NULL".to_string());
+    };
+    if format == BINARY {
+        let invalid = || errinput!("invalid binary parameter for type {type_oid}");
+        return Ok(match (type_oid, value.len()) {
+            (oid::BOOL, 1) => bool_literal(value[0] != 0),
+            (oid::INT2, 2) => i16::from_be_bytes(value.try_into()?).to_string(),
+            (oid::INT4, 4) => i32::from_be_bytes(value.try_into()?).to_string(),
+            (oid::INT8, 8) => i64::from_be_bytes(value.try_into()?).to_string(),
+            (oid::FLOAT4, 4) => float_literal(f32::from_be_bytes(value.try_into()?) as f64),
+            (oid::FLOAT8, 8) => float_literal(f64::from_be_bytes(value.try_into()?)),
+            (oid::BOOL | oid::INT2 | oid::INT4 | oid::INT8 | oid::FLOAT4 | oid::FLOAT8, _) => {
+                return invalid();
+            }
+            (oid::UNSPECIFIED | oid::UNKNOWN | oid::TEXT | oid::VARCHAR | oid::JSON, _) => {
+                string_literal(&String::from_utf8(value.to_vec())?)
+            }
+            (type_oid, _) => {
+                return errinput!("binary format not supported for parameter type {type_oid}");
+            }
+        });
+    }
+    let text = String::from_utf8(value.to_vec())?;
+    Ok(match type_oid {
+        oid::BOOL => match text.to_lowercase().as_str() {
+            "t" | "true" | "y" | "yes" | "on" | "1" => bool_literal(true),
+            "f" | "false" | "n" | "no" | "off" | "0" => bool_literal(false),
+            _ => return errinput!("invalid boolean parameter {text}"),
+        },
+        oid::INT2 | oid::INT4 | oid::INT8 => text.trim().parse::<i64>()?.to_string(),
+        oid::FLOAT4 | oid::FLOAT8 => float_literal(text.trim().parse()?),
+        oid::UNSPECIFIED | oid::UNKNOWN if text.parse::<i64>().is_ok() => text,
+        oid::UNSPECIFIED | oid::UNKNOWN if text.parse::<f64>().is_ok_and(|f| f.is_finite()) => text,
+        oid::UNSPECIFIED | oid::UNKNOWN if text == "true" || text == "false" => text,
+        _ => string_literal(&text),
+    })
+}
+
+/// Formats a boolean SQL literal.
+pub(crate) fn bool_literal(value: bool) -> String {
+    match value {
+        true => "TRUE".to_string(),
+        false => "FALSE".to_string(),
+    }
+}
+
+/// Formats a float SQL literal.
+pub(crate) fn float_literal(value: f64) -> String {
+    match value {
+        value if value.is_nan() => "NAN".to_string(),
+        f64::INFINITY => "INFINITY".to_string(),
+        f64::NEG_INFINITY => "-INFINITY".to_string(),
+        value => format!("{value:?}"),
+    }
+}
+
+/// Formats a string SQL literal, escaping quotes.
+pub(crate) fn string_literal(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+/// Scans a query, calling the given closure with the byte offset of each
+/// character outside of string literals and quoted identifiers.
+fn scan_unquoted(query: &str, mut f: impl FnMut(usize, char)) {
+    let mut quote = None;
+    for (i, c) in query.char_indices() {
+        match (quote, c) {
+            (None, '\'' | '"') => quote = Some(c),
+            (None, c) => f(i, c),
+            (Some(q), c) if q == c => quote = None,
+            (Some(_), _) => {}
+        }
+    }
+}
+
+/// Splits a query into its semicolon-separated statements, omitting empty
+/// statements.
+fn split_statements(query: &str) -> Vec<&str> {
+    let mut statements = Vec::new();
+    let mut start = 0;
+    scan_unquoted(query, |i, c| {
+        if c == ';' {
+            statements.push(&query[start..i]);
+            start = i + 1;
+        }
+    });
+    statements.push(&query[start..]);
+    statements.retain(|s| !s.trim().is_empty());
+    statements
+}
+
+/// Returns the $1-style parameter positions in a query, as (offset, length,
+/// number) tuples.
+fn find_params(query: &str) -> Vec<(usize, usize, usize)> {
+    let mut dollars = Vec::new();
+    scan_unquoted(query, |i, c| {
+        if c == '$' {
+            dollars.push(i)
+        }
+    });
+    dollars
+        .into_iter()
+        .filter_map(|i| {
+            let digits = query[i + 1..].bytes().take_while(|b| b.is_ascii_digit()).count();
+            let number = query[i + 1..i + 1 + digits].parse().ok()?;
+            Some((i, digits + 1, number))
+        })
+        .collect()
+}
+
+/// Returns the number of parameters in a query, i.e. the highest $n.
+fn count_params(query: &str) -> usize {
+    find_params(query).into_iter().map(|(_, _, n)| n).max().unwrap_or(0)
+}
+
+/// Substitutes $1-style parameters in a query with the given SQL literals.
+pub(crate) fn bind_params(query: &str, params: &[String]) -> Result<String> {
+    let mut bound = String::with_capacity(query.len());
+    let mut last = 0;
+    for (offset, len, number) in find_params(query) {
+        let Some(param) = number.checked_sub(1).and_then(|i| params.get(i)) else {
+            return errinput!("no value given for parameter ${number}");
+        };
+        bound.push_str(&query[last..offset]);
+        bound.push_str(param);
+        last = offset + len;
+    }
+    bound.push_str(&query[last..]);
+    Ok(bound)
+}
+
+/// Returns true if the statement sets a Postgres session parameter that isn't
+/// a toyDB session variable, e.g. SET application_name = 'foo'. These are
+/// ignored.
+fn is_ignored_set(statement: &str) -> bool {
+    let mut words = statement
+        .split(|c: char| c.is_ascii_whitespace() || c == '=')
+        .filter(|w| !w.is_empty())
+        .map(|w| w.to_ascii_lowercase());
+    match (words.next().as_deref(), words.next().as_deref()) {
+        (Some("set"), Some("transaction")) => false,
+        (Some("set"), Some(name)) => !VARIABLES.contains(&name),
+        _ => false,
+    }
+}
@@ -4,7 +4,7 @@ use serde::{Deserialize, Serialize};
 
 use super::{NodeID, Term};
 use crate::encoding::{self, Key as _, Value as _, bincode};
-use crate::error::Result;
+use crate::error::{Error, Result};
 use crate::storage;
 
 /// A log index (entry position). Starts at 1. 0 indicates no index.
@@ -20,13 +20,30 @@ pub struct Entry {
     pub index: Index,
     /// The term in which the entry was added.
     pub term: Term,
-    /// The state machine command. None (noop) commands are used during leader
-    /// election to commit old entries, see section 5.4.2 in the Raft paper.
-    pub command: Option<Vec<u8>>,
+    /// The entry command.
+    pub command: Command,
 }
 
 impl encoding::Value for Entry {}
 
+/// A log entry command.
+///
+/// The variant order must not change, since Noop and Write are encoded like
+/// the None and Some values that older versions of the log used.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Command {
+    /// A noop command, used during leader election to commit old entries, see
+    /// section 5.4.2 in the Raft paper.
+    Noop,
+    /// A state machine command.
+    Write(Vec<u8>),
+    /// Removes a node from the cluster. Nodes use the latest removals in their
+    /// log as soon as they're appended, even if they're not yet committed, see
+    /// section 4.1 in the Raft thesis. Only one removal can be uncommitted at
+    /// a time. It's a noop for the state machine.
+    RemoveNode(NodeID),
+}
+
 /// A log storage key.
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum Key {
@@ -36,6 +53,8 @@ pub enum Key {
     TermVote,
     /// Stores the current commit index (if any).
     CommitIndex,
+    /// Stores the current truncation index (if any).
+    TruncateIndex,
 }
 
 impl encoding::Key<'_> for Key {}
@@ -44,7 +63,8 @@ impl encoding::Key<'_> for Key {}
 /// are replicated across nodes and applied sequentially to the local state
 /// machine. Each entry contains an index, command, and the term in which the
 /// leader proposed it. Commands may be noops (None), which are added when a
-/// leader is elected (see section 5.4.2 in the Raft paper). For example:
+/// leader is elected (see section 5.4.2 in the Raft paper), or node removals,
+/// which are handled by Raft itself. For example:
 ///
 /// Index | Term | Command
 /// ------|------|------------------------------------------------------
@@ -84,7 +104,8 @@ impl encoding::Key<'_> for Key {}
 /// * Entry terms are at or below the current term.
 /// * Appended entries are durable (flushed to disk).
 /// * Appended entries use the current term.
-/// * Committed entries are never changed or removed (no log truncation).
+/// * Committed entries are never changed, and only removed once they have
+///   been applied and replicated to all nodes (see [`Log::truncate`]).
 /// * Committed entries will eventually be replicated to all nodes.
 /// * Entries with the same index/term contain the same command.
 /// * If two logs contain a matching index/term, all previous entries
@@ -106,6 +127,8 @@ pub struct Log {
     commit_index: Index,
     /// The term of the last committed entry.
     commit_term: Term,
+    /// The index before which entries may have been removed by log truncation.
+    truncate_index: Index,
     /// If true, fsync entries to disk when appended. This is mandated by Raft,
     /// but comes with a hefty performance penalty (especially since we don't
     /// optimize for it by batching entries before fsyncing). Disabling it will
@@ -140,9 +163,24 @@ impl Log {
             .map(|v| bincode::deserialize(&v))
             .transpose()?
             .unwrap_or((0, 0));
+        let truncate_index = engine
+            .get(&Key::TruncateIndex.encode())?
+            .map(|v| bincode::deserialize(&v))
+            .transpose()?
+            .unwrap_or(0);
 
         let fsync = true; // fsync by default
-        Ok(Self { engine, term, vote, last_index, last_term, commit_index, commit_term, fsync })
+        Ok(Self {
+            engine,
+            term,
+            vote,
+            last_index,
+            last_term,
+            commit_index,
+            commit_term,
+            truncate_index,
+            fsync,
+        })
     }
 
     /// Controls whether to fsync writes. Disabling this may violate Raft
@@ -151,6 +189,11 @@ impl Log {
         self.fsync = fsync
     }
 
+    /// Returns the index before which entries may have been truncated.
+    pub fn get_truncate_index(&self) -> Index {
+        self.truncate_index
+    }
+
     /// Returns the commit index and term.
     pub fn get_commit_index(&self) -> (Index, Term) {
         (self.commit_index, self.commit_term)
@@ -188,18 +231,30 @@ impl Log {
     }
 
     /// Appends a command to the log at the current term, and flushes it to
-    /// disk, returning its index. None implies a noop command, typically after
+    /// disk, returning its index. Noop commands are typically appended after
     /// Raft leader changes.
-    pub fn append(&mut self, command: Option<Vec<u8>>) -> Result<Index> {
+    pub fn append(&mut self, command: Command) -> Result<Index> {
+        self.append_batch(vec![command])
+    }
+
+    /// Appends a batch of commands to the log at the current term, and flushes
+    /// them to disk once, returning the last index. This amortizes the fsync
+    /// across all commands in the batch. The batch can't be empty.
+    pub fn append_batch(&mut self, commands: Vec<Command>) -> Result<Index> {
         assert!(self.term > 0, "can't append entry in term 0");
-        let entry = Entry { index: self.last_index + 1, term: self.term, command };
-        self.engine.set(&Key::Entry(entry.index).encode(), entry.encode())?;
+        assert!(!commands.is_empty(), "can't append empty batch");
+        let mut index = self.last_index;
+        for command in commands {
+            index += 1;
+            let entry = Entry { index, term: self.term, command };
+            self.engine.set(&Key::Entry(entry.index).encode(), entry.encode())?;
+        }
         if self.fsync {
             self.engine.flush()?;
         }
-        self.last_index = entry.index;
-        self.last_term = entry.term;
-        Ok(entry.index)
+        self.last_index = index;
+        self.last_term = self.term;
+        Ok(index)
     }
 
     /// Commits entries up to and including the given index. The index must
@@ -255,7 +310,9 @@ impl Log {
     }
 
     /// Returns an iterator over entries that are ready to apply, starting after
-    /// the current applied index up to the commit index.
+    /// the current applied index up to the commit index. Errors with
+    /// Error::Truncated if the entries have been removed by log truncation,
+    /// e.g. when an incremental backup is taken from an old index.
     pub fn scan_apply(&mut self, applied_index: Index) -> Iterator {
         // NB: we don't assert that commit_index >= applied_index, because the
         // local commit index is not flushed to durable storage -- if lost on
@@ -263,6 +320,18 @@ impl Log {
         if applied_index >= self.commit_index {
             return Iterator::new(Box::new(std::iter::empty()));
         }
+        // Entries before the truncation index may have been removed, but
+        // engines only remove whole segments, so check.
+        if applied_index + 1 < self.truncate_index {
+            let error = match self.engine.get(&Key::Entry(applied_index + 1).encode()) {
+                Ok(Some(_)) => None,
+                Ok(None) => Some(Error::Truncated(self.truncate_index)),
+                Err(error) => Some(error),
+            };
+            if let Some(error) = error {
+                return Iterator::new(Box::new(std::iter::once(Err(error))));
+            }
+        }
         self.scan(applied_index + 1..=self.commit_index)
     }
 
@@ -342,6 +411,30 @@ impl Log {
         Ok(self.last_index)
     }
 
+    /// Removes entries before the given index, to the extent the storage engine
+    /// can do so cheaply (see [`storage::Engine::truncate`]), returning the
+    /// number of removed entries. The index must be committed, and the caller
+    /// must ensure that the removed entries have been applied and flushed to
+    /// the state machine, and that no node will need them again.
+    ///
+    /// Since there are no snapshots, entries are only truncated once they have
+    /// been replicated to all nodes, which the leader tracks and propagates
+    /// via heartbeats. The engine's segment retention gives some leeway beyond
+    /// that.
+    ///
+    /// The truncation index is persisted before removing entries, such that
+    /// requests for entries before it can be rejected after restarts too.
+    pub fn truncate(&mut self, index: Index) -> Result<u64> {
+        assert!(index <= self.commit_index, "truncate index {index} beyond commit");
+        if index <= self.truncate_index {
+            return Ok(0);
+        }
+        self.engine.set(&Key::TruncateIndex.encode(), bincode::serialize(&index))?;
+        self.engine.flush()?;
+        self.truncate_index = index;
+        self.engine.truncate(&Key::Entry(index).encode())
+    }
+
     /// Returns log engine status.
     pub fn status(&mut self) -> Result<storage::Status> {
         self.engine.status()
@@ -392,6 +485,60 @@ mod tests {
         goldenscript::run(&mut TestRunner::new(), path).expect("goldenscript failed")
     }
 
+    /// Tests log truncation with segmented storage, which the goldenscripts
+    /// can't exercise since they mirror BitCask and Memory engines.
+    #[test]
+    fn truncate() -> Result<(), Box<dyn Error>> {
+        let tempdir = TempDir::with_prefix("toydb")?;
+        let open = || storage::Segmented::new(tempdir.path().join("raft"), 256, 0);
+
+        // Write 100 entries across many segments, and commit 80 of them.
+        let mut log = Log::new(Box::new(open()?))?;
+        log.set_term(1, None)?;
+        for i in 1..=100_u64 {
+            log.append(Command::Write(i.to_be_bytes().to_vec()))?;
+        }
+        log.commit(80)?;
+
+        // Truncate before 50. Some earlier entries remain in the segment
+        // containing 50, but entries from 50 onwards are all retained.
+        let removed = log.truncate(50)?;
+        assert!(removed > 0 && removed < 50, "removed {removed} entries");
+        assert_eq!(log.get_truncate_index(), 50);
+        assert_eq!(log.truncate(40)?, 0);
+        let first = removed + 1;
+        assert_eq!(log.get(first - 1)?, None);
+        let indexes: Vec<Index> = log.scan(..).map_ok(|e| e.index).try_collect()?;
+        assert_eq!(indexes, (first..=100).collect_vec());
+
+        // Reopen the log. The truncated entries are still gone, and the
+        // metadata (including the truncation index) is retained.
+        drop(log);
+        let mut log = Log::new(Box::new(open()?))?;
+        assert_eq!(log.get_term(), (1, None));
+        assert_eq!(log.get_last_index(), (100, 1));
+        assert_eq!(log.get_commit_index(), (80, 1));
+        assert_eq!(log.get_truncate_index(), 50);
+        assert_eq!(log.get(first - 1)?, None);
+        assert_eq!(log.get(first)?.map(|e| e.index), Some(first));
+        assert_eq!(log.scan_apply(79).map_ok(|e| e.index).try_collect::<_, Vec<_>, _>()?, [80]);
+
+        // Scanning committed entries from a truncated index errors, but the
+        // retained entries before the truncation index can still be scanned.
+        assert_eq!(
+            log.scan_apply(first - 2).map_ok(|e| e.index).collect_vec(),
+            [Err(crate::error::Error::Truncated(50))]
+        );
+        assert_eq!(log.scan_apply(first - 1).map_ok(|e| e.index).next().transpose()?, Some(first));
+
+        // Truncating at the commit index retains it, along with the rest of
+        // its segment.
+        assert!(log.truncate(80)? > 0);
+        assert!(log.has(80, 1)?);
+        assert_eq!(log.scan(..).map_ok(|e| e.index).last().transpose()?, Some(100));
+        Ok(())
+    }
+
     /// Runs Raft log goldenscript tests. For available commands, see run().
     struct TestRunner {
         log: Log,
@@ -455,12 +602,29 @@ mod tests {
                     let mut args = command.consume_args();
                     let command = args.next_pos().map(|a| a.value.as_bytes().to_vec());
                     args.reject_rest()?;
-                    let index = self.log.append(command)?;
+                    let index = self.log.append(command.map_or(Command::Noop, Command::Write))?;
                     let entry = self.log.get(index)?.expect("entry not found");
                     let fmtentry = format::Raft::<format::Raw>::entry(&entry);
                     writeln!(output, "append → {fmtentry}")?;
                 }
 
+                // append_batch COMMAND...
+                "append_batch" => {
+                    let mut args = command.consume_args();
+                    let commands = args
+                        .rest_pos()
+                        .iter()
+                        .map(|a| Command::Write(a.value.as_bytes().to_vec()))
+                        .collect_vec();
+                    args.reject_rest()?;
+                    let (last_index, _) = self.log.get_last_index();
+                    let index = self.log.append_batch(commands)?;
+                    for entry in self.log.scan(last_index + 1..=index) {
+                        let fmtentry = format::Raft::<format::Raw>::entry(&entry?);
+                        writeln!(output, "append → {fmtentry}")?;
+                    }
+                }
+
                 // commit INDEX
                 "commit" => {
                     let mut args = command.consume_args();
@@ -576,8 +740,8 @@ mod tests {
                     for arg in args.rest_key() {
                         let (index, term) = Self::parse_index_term(arg.key.as_deref().unwrap())?;
                         let command = match arg.value.as_str() {
-                            "" => None,
-                            value => Some(value.as_bytes().to_vec()),
+                            "" => Command::Noop,
+                            value => Command::Write(value.as_bytes().to_vec()),
                         };
                         entries.push(Entry { index, term, command });
                     }
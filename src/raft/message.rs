@@ -1,4 +1,4 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 
 use serde::{Deserialize, Serialize};
 
@@ -72,6 +72,10 @@ pub enum Message {
         commit_index: Index,
         /// The leader's latest read sequence number in this term.
         read_seq: ReadSequence,
+        /// If non-zero, the index before which all nodes have the leader's log
+        /// entries. Followers may remove their log entries before it, once
+        /// applied (see [`super::Log::truncate`]).
+        truncate_index: Index,
     },
 
     /// Followers respond to leader heartbeats if they still consider it leader.
@@ -131,10 +135,17 @@ pub enum Message {
     /// Followers confirm leadership at the read sequence numbers.
     ReadResponse { seq: ReadSequence },
 
+    /// Leaders transfer leadership to a follower whose log is up-to-date by
+    /// telling it to campaign immediately, instead of waiting for an election
+    /// timeout (see Request::TransferLeadership). This is section 3.10 in the
+    /// Raft thesis, without halting proposals during the transfer.
+    TimeoutNow,
+
     /// A client request. This can be submitted to the leader, or to a follower
-    /// which will forward it to its leader. If there is no leader, or the
-    /// leader or term changes, the request is aborted with an Error::Abort
-    /// ClientResponse and the client must retry.
+    /// which will forward it to its leader. If there is no leader, the request
+    /// is rejected with an Error::NotLeader ClientResponse. If the leader or
+    /// term changes, it's aborted with an Error::Abort ClientResponse. In
+    /// either case, the client must retry.
     ClientRequest {
         /// The request ID. Must be globally unique for the request duration.
         id: RequestID,
@@ -167,11 +178,42 @@ pub enum Request {
     /// A state machine read command, executed via `State::read`. This is not
     /// replicated, and only evaluated on the leader.
     Read(Vec<u8>),
+    /// A state machine read command which reads an immutable snapshot that a
+    /// previous linearizable read has already observed, e.g. an MVCC snapshot.
+    /// Like `Read`, it's executed on the leader once a quorum has confirmed
+    /// its leadership, but only waits for the leader to apply the commit index
+    /// at the time the read was submitted (the read index).
+    SnapshotRead(Vec<u8>),
     /// A state machine write command, executed via `State::apply`. This is
     /// replicated across all nodes, and must produce a deterministic result.
     Write(Vec<u8>),
     /// Requests Raft cluster status from the leader.
     Status,
+    /// Transfers leadership from the leader to the follower with the lowest
+    /// node ID whose log is up-to-date, e.g. before the leader shuts down.
+    /// Errors with Error::Abort if no follower has caught up yet, in which case
+    /// the client can retry shortly.
+    TransferLeadership,
+    /// Requests the local node's status. Unlike Status, this is handled by
+    /// the local node in any role, and isn't forwarded to the leader.
+    NodeStatus,
+    /// A state machine read command, executed via `State::read` on the local
+    /// node in any role. The result may be stale, since the local state
+    /// machine can lag behind the leader, so this is only used to inspect the
+    /// local node (e.g. its storage status).
+    LocalRead(Vec<u8>),
+    /// Removes a follower from the cluster, by appending a node removal to the
+    /// log (see `Command::RemoveNode`). This is answered once the removal has
+    /// been committed. The leader can't remove itself, it must transfer
+    /// leadership first. Errors with Error::Abort if the leader hasn't yet
+    /// committed an entry in its term, in which case the client can retry.
+    RemoveNode(NodeID),
+    /// Returns the local node's committed log entries after the given index,
+    /// in any role. Committed entries never change, so any node can serve
+    /// them, although it may not have committed the leader's latest entries
+    /// yet. Used for incremental backups. Errors with Error::Truncated if
+    /// entries after the index have been removed by log truncation.
+    CommittedEntries(Index),
 }
 
 impl encoding::Value for Request {}
@@ -185,6 +227,14 @@ pub enum Response {
     Write(Vec<u8>),
     /// The current Raft leader status.
     Status(Status),
+    /// The node that leadership is being transferred to.
+    TransferLeadership(NodeID),
+    /// The local node status.
+    NodeStatus(NodeStatus),
+    /// The log index of a committed node removal.
+    RemoveNode(Index),
+    /// Committed log entries.
+    Entries(Vec<Entry>),
 }
 
 impl encoding::Value for Response {}
@@ -206,3 +256,48 @@ pub struct Status {
     /// The log storage engine status.
     pub storage: storage::Status,
 }
+
+/// Raft node status. Generated by the local node, from its own view of the
+/// cluster.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct NodeStatus {
+    /// The node ID.
+    pub id: NodeID,
+    /// The node's current role.
+    pub role: NodeRole,
+    /// The leader of the current term, if known.
+    pub leader: Option<NodeID>,
+    /// The current term.
+    pub term: Term,
+    /// The node's peers, i.e. the other cluster members.
+    pub peers: BTreeSet<NodeID>,
+    /// The index of the last log entry.
+    pub last_index: Index,
+    /// The commit index, as known by the node.
+    pub commit_index: Index,
+    /// The index of the last entry applied to the state machine.
+    pub applied_index: Index,
+    /// The log storage engine status.
+    pub storage: storage::Status,
+}
+
+/// A Raft node role.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum NodeRole {
+    Candidate,
+    Follower,
+    Leader,
+    /// A non-voting follower (see Options::learners).
+    Learner,
+}
+
+impl std::fmt::Display for NodeRole {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Candidate => "candidate",
+            Self::Follower => "follower",
+            Self::Leader => "leader",
+            Self::Learner => "learner",
+        })
+    }
+}
@@ -179,8 +179,9 @@
 //! node. They are only processed on the leader, but followers will proxy them
 //! to the leader (Raft thesis section 6.2). To avoid complications with message
 //! replays (Raft thesis section 6.3), requests are not retried internally, and
-//! are explicitly aborted with `Error::Abort` on leader/term changes as well as
-//! elections.
+//! are explicitly aborted with `Error::Abort` on leader/term changes. Requests
+//! submitted when there is no leader, e.g. during elections, are rejected with
+//! `Error::NotLeader` instead, since they definitely weren't executed.
 //!
 //! Write requests, `Request::Write`, are appended to the Raft log and
 //! replicated. The leader keeps track of the request and its log index in a
@@ -201,6 +202,15 @@
 //! quorum have confirmed a sequence number the read is executed and the result
 //! returned to the client.
 //!
+//! Snapshot reads, `Request::SnapshotRead`, read immutable state that a
+//! previous linearizable read has already observed, such as the MVCC snapshot
+//! of a read-only SQL transaction (which is begun via a linearizable read).
+//! These use the ReadIndex protocol (Raft thesis section 6.4): the leader
+//! records its commit index as the read index, confirms its leadership with a
+//! quorum as for other reads, and executes the read once it has applied the
+//! read index, without waiting for entries committed in the meanwhile.
+//! Read-only SQL transactions thus never go through the Raft log.
+//!
 //! IMPLEMENTATION CAVEATS
 //! ======================
 //!
@@ -209,21 +219,31 @@
 //! needed for a real production system. In particular:
 //!
 //! * No leases: for linearizability, every read request requires the leader to
-//!   confirm with followers that it's still the leader. This could be avoided
-//!   with a leader lease for a predefined time interval (Raft paper section 8,
-//!   Raft thesis section 6.3).
+//!   confirm with followers that it's still the leader.
+//!   This could be avoided with a leader lease for a predefined time interval
+//!   (Raft paper section 8, Raft thesis section 6.3).
 //!
-//! * No cluster membership changes: to add or remove nodes, the entire cluster
-//!   must be stopped and restarted with the new configuration, otherwise it
-//!   risks multiple leaders (Raft paper section 6).
+//! * Limited cluster membership changes: a single follower at a time can be
+//!   removed via a RemoveNode log entry, which takes effect when appended
+//!   (Raft thesis section 4.1). To add nodes, the entire cluster must be
+//!   stopped and restarted with the new configuration, otherwise it risks
+//!   multiple leaders (Raft paper section 6). On restart, removed nodes are
+//!   dropped from the configured peers.
 //!
 //! * No snapshots: new or lagging nodes must be caught up by replicating and
 //!   replaying the entire log, instead of sending a state machine snapshot
 //!   (Raft paper section 7).
 //!
-//! * No log truncation: because snapshots aren't supported, the entire Raft
-//!   log must be retained forever in order to catch up new/lagging nodes,
-//!   leading to excessive storage use (Raft paper section 7).
+//! * Limited log truncation: because snapshots aren't supported, log entries
+//!   can only be removed once all nodes have them (Raft paper section 7). With
+//!   the segmented log storage (`storage::Segmented`), the leader propagates
+//!   the lowest match index of all nodes via heartbeats, and each node deletes
+//!   the segment files before it once applied, retaining a configured number
+//!   of extra segments. A node that's down or partitioned blocks truncation,
+//!   and a node that loses its data or is added to the cluster can't be caught
+//!   up once the log has been truncated. Requests for truncated entries (e.g.
+//!   incremental backups) error with Error::Truncated. Otherwise, the entire
+//!   log is retained forever, leading to excessive storage use.
 //!
 //! * No pre-vote or check-quorum: a node that's partially partitioned (can
 //!   reach some but not all nodes) can cause persistent unavailability with
@@ -242,13 +262,17 @@
 mod log;
 mod message;
 mod node;
+#[cfg(test)]
+mod simulation;
 mod state;
 
 use std::ops::Range;
 use std::time::Duration;
 
-pub use log::{Entry, Index, Key, Log};
-pub use message::{Envelope, Message, ReadSequence, Request, RequestID, Response, Status};
+pub use log::{Command, Entry, Index, Key, Log};
+pub use message::{
+    Envelope, Message, NodeRole, NodeStatus, ReadSequence, Request, RequestID, Response, Status,
+};
 pub use node::{Node, NodeID, Options, Term, Ticks};
 pub use state::State;
 
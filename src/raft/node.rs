@@ -1,14 +1,17 @@
 use std::cmp::{max, min};
-use std::collections::{HashMap, HashSet, VecDeque};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::ops::Range;
 
 use crossbeam::channel::Sender;
 use itertools::Itertools as _;
 use log::{debug, info};
-use rand::Rng as _;
+use rand::rngs::StdRng;
+use rand::{Rng as _, SeedableRng as _};
 
-use super::log::{Index, Log};
-use super::message::{Envelope, Message, ReadSequence, Request, RequestID, Response, Status};
+use super::log::{Command, Entry, Index, Log};
+use super::message::{
+    Envelope, Message, NodeRole, NodeStatus, ReadSequence, Request, RequestID, Response, Status,
+};
 use super::state::State;
 use crate::errinput;
 use crate::error::{Error, Result};
@@ -31,6 +34,27 @@ pub struct Options {
     pub election_timeout_range: Range<Ticks>,
     /// Maximum number of entries to send in a single Append message.
     pub max_append_entries: usize,
+    /// Seeds the random election timeouts, for deterministic simulation
+    /// tests. The node ID is mixed into the seed, so that nodes with the same
+    /// seed choose different timeouts. If None, the node is seeded randomly.
+    pub seed: Option<u64>,
+    /// Non-voting learners, possibly including this node. Learners replicate
+    /// and apply the log like followers, but never campaign or vote, and don't
+    /// count towards quorums. They can serve stale local reads (e.g. for
+    /// analytics) without loading the voters. All nodes must be created with
+    /// the same learners, and at least one node must be a voter.
+    pub learners: HashSet<NodeID>,
+    /// If non-zero, the leader batches client writes (group commit) until
+    /// their commands reach this many bytes, the next tick, or until
+    /// `flush_batch()` is called. The batch is then appended to the log with a
+    /// single fsync and replicated together, and the writes are acknowledged
+    /// as they're applied. If 0, each write is proposed immediately.
+    pub group_commit_bytes: usize,
+    /// If true, truncate the log prefix once it has been applied and
+    /// replicated to all nodes. The leader propagates the truncation index via
+    /// heartbeats. This only reclaims space with segmented log storage (see
+    /// [`crate::storage::Segmented`]).
+    pub truncate_log: bool,
 }
 
 impl Default for Options {
@@ -39,6 +63,10 @@ impl Default for Options {
             heartbeat_interval: super::HEARTBEAT_INTERVAL,
             election_timeout_range: super::ELECTION_TIMEOUT_RANGE,
             max_append_entries: super::MAX_APPEND_ENTRIES,
+            seed: None,
+            learners: HashSet::new(),
+            group_commit_bytes: 0,
+            truncate_log: false,
         }
     }
 }
@@ -115,8 +143,8 @@ impl Node {
         opts: Options,
     ) -> Result<Self> {
         let node = RawNode::new(id, peers, log, state, tx, opts)?;
-        // If this is a single-node cluster, become leader immediately.
-        if node.cluster_size() == 1 {
+        // If this is the only voter, become leader immediately.
+        if node.cluster_size() == 1 && !node.is_learner(id) {
             return Ok(node.into_candidate()?.into_leader()?.into());
         }
         Ok(node.into())
@@ -136,7 +164,12 @@ impl Node {
     pub fn step(self, msg: Envelope) -> Result<Self> {
         with_rawnode!(self, |n| {
             assert_eq!(msg.to, n.id, "message to other node: {msg:?}");
-            assert!(n.peers.contains(&msg.from) || msg.from == n.id, "unknown sender: {msg:?}");
+            // Removed nodes may keep campaigning (possibly in later terms)
+            // until they're shut down. Ignore them to avoid disruption.
+            if !n.peers.contains(&msg.from) && msg.from != n.id {
+                debug!("Dropping message from non-member: {msg:?}");
+                return Ok(n.into());
+            }
             debug!("Stepping {msg:?}");
             n.step(msg)
         })
@@ -146,6 +179,27 @@ impl Node {
     pub fn tick(self) -> Result<Self> {
         with_rawnode!(self, |n| n.tick())
     }
+
+    /// Returns true if the node is a leader with batched client writes that
+    /// haven't been proposed yet, see `Options::group_commit_bytes`.
+    pub fn has_batch(&self) -> bool {
+        match self {
+            Node::Leader(node) => !node.role.batch.is_empty(),
+            Node::Candidate(_) | Node::Follower(_) => false,
+        }
+    }
+
+    /// Proposes any batched client writes, if the node is a leader. Otherwise,
+    /// this does nothing.
+    pub fn flush_batch(self) -> Result<Self> {
+        match self {
+            Node::Leader(mut node) => {
+                node.propose_batch()?;
+                Ok(node.into())
+            }
+            node => Ok(node),
+        }
+    }
 }
 
 impl From<RawNode<Candidate>> for Node {
@@ -176,10 +230,14 @@ pub trait Role {}
 pub struct RawNode<R: Role> {
     /// The node ID. Must be unique in this cluster.
     id: NodeID,
-    /// The IDs of the other nodes in the cluster. Does not change while
-    /// running. Can change on restart, but all nodes must have the same node
-    /// set to avoid multiple leaders (i.e. split brain).
+    /// The IDs of the other nodes in the cluster. Initially given when
+    /// created, excluding any nodes removed in the log. All nodes must be
+    /// created with the same node set to avoid multiple leaders (i.e. split
+    /// brain). Node removals take effect when appended to the log.
     peers: HashSet<NodeID>,
+    /// Node removals in the log, by index. Used to revert removals if their
+    /// uncommitted entries are replaced by a new leader.
+    removals: BTreeMap<Index, NodeID>,
     /// The Raft log, containing client commands to be executed.
     log: Log,
     /// The Raft state machine, on which client commands are executed.
@@ -188,6 +246,8 @@ pub struct RawNode<R: Role> {
     tx: Sender<Envelope>,
     /// Node options.
     opts: Options,
+    /// The random number generator for election timeouts.
+    rng: StdRng,
     /// Role-specific state.
     role: R,
 }
@@ -198,10 +258,12 @@ impl<R: Role> RawNode<R> {
         RawNode {
             id: self.id,
             peers: self.peers,
+            removals: self.removals,
             log: self.log,
             state: self.state,
             tx: self.tx,
             opts: self.opts,
+            rng: self.rng,
             role,
         }
     }
@@ -211,9 +273,14 @@ impl<R: Role> RawNode<R> {
         self.log.get_term().0
     }
 
-    /// Returns the cluster size as number of nodes.
+    /// Returns the cluster size as number of voters, i.e. excluding learners.
     fn cluster_size(&self) -> usize {
-        self.peers.len() + 1
+        self.peers.iter().chain([&self.id]).filter(|id| !self.is_learner(**id)).count()
+    }
+
+    /// Returns true if the given node is a non-voting learner.
+    fn is_learner(&self, id: NodeID) -> bool {
+        self.opts.learners.contains(&id)
     }
 
     /// Returns the cluster quorum size (strict majority).
@@ -222,15 +289,16 @@ impl<R: Role> RawNode<R> {
     }
 
     /// Returns the quorum value of the given unsorted vector, in descending
-    /// order. The slice must have the same size as the cluster.
+    /// order. The slice must have the same size as the cluster, i.e. contain
+    /// a value for each voter.
     fn quorum_value<T: Ord + Copy>(&self, mut values: Vec<T>) -> T {
         assert_eq!(values.len(), self.cluster_size(), "vector size must match cluster size");
         *values.select_nth_unstable_by(self.quorum_size() - 1, |a, b| a.cmp(b).reverse()).1
     }
 
     /// Generates a random election timeout.
-    fn random_election_timeout(&self) -> Ticks {
-        rand::thread_rng().gen_range(self.opts.election_timeout_range.clone())
+    fn random_election_timeout(&mut self) -> Ticks {
+        self.rng.gen_range(self.opts.election_timeout_range.clone())
     }
 
     /// Sends a message to the given recipient.
@@ -252,6 +320,80 @@ impl<R: Role> RawNode<R> {
         }
         Ok(())
     }
+
+    /// Returns true if this node has been removed from the cluster.
+    fn is_removed(&self) -> bool {
+        self.removals.values().any(|id| *id == self.id)
+    }
+
+    /// Records a node removal appended to the log at the given index, and
+    /// removes the node from the peers.
+    fn add_removal(&mut self, index: Index, id: NodeID) {
+        info!("Removing node {id} from the cluster");
+        self.removals.insert(index, id);
+        self.peers.remove(&id);
+    }
+
+    /// Reverts a node removal whose entry was replaced or truncated, adding
+    /// the node back to the peers. The caller must remove it from removals.
+    fn revert_removal(&mut self, id: NodeID) {
+        info!("Reverting removal of node {id}");
+        if id != self.id {
+            self.peers.insert(id);
+        }
+    }
+
+    /// Handles a local client request, which is answered by this node in any
+    /// role rather than by the leader. The role and leader are given by the
+    /// caller, since they're role-specific.
+    fn step_local(
+        &mut self,
+        id: RequestID,
+        request: Request,
+        role: NodeRole,
+        leader: Option<NodeID>,
+    ) -> Result<()> {
+        let response = match request {
+            Request::NodeStatus => self.node_status(role, leader).map(Response::NodeStatus),
+            Request::LocalRead(command) => self.state.read(command).map(Response::Read),
+            Request::CommittedEntries(index) => {
+                self.log.scan_apply(index).collect::<Result<_>>().map(Response::Entries)
+            }
+            request => panic!("unexpected local request {request:?}"),
+        };
+        self.send(self.id, Message::ClientResponse { id, response })
+    }
+
+    /// Generates the local node status.
+    fn node_status(&mut self, role: NodeRole, leader: Option<NodeID>) -> Result<NodeStatus> {
+        Ok(NodeStatus {
+            id: self.id,
+            role,
+            leader,
+            term: self.term(),
+            peers: self.peers.iter().copied().collect(),
+            last_index: self.log.get_last_index().0,
+            commit_index: self.log.get_commit_index().0,
+            applied_index: self.state.get_applied_index(),
+            storage: self.log.status()?,
+        })
+    }
+
+    /// Truncates log entries before the given index, or the applied index if
+    /// lower. The state machine is flushed first, since the removed entries
+    /// can't be replayed into it after a crash. 0 is a noop.
+    fn maybe_truncate(&mut self, index: Index) -> Result<()> {
+        let index = min(index, self.state.get_applied_index());
+        if index <= self.log.get_truncate_index() {
+            return Ok(());
+        }
+        self.state.flush()?;
+        let removed = self.log.truncate(index)?;
+        if removed > 0 {
+            info!("Truncated {removed} log entries before index {index}");
+        }
+        Ok(())
+    }
 }
 
 // A follower replicates log entries from a leader and forwards client requests.
@@ -292,7 +434,26 @@ impl RawNode<Follower> {
             return errinput!("node ID {id} can't be in peers");
         }
         let role = Follower::new(None, 0);
-        let mut node = Self { id, peers, log, state, tx, opts, role };
+        let removals = BTreeMap::new();
+        let rng = match opts.seed {
+            Some(seed) => StdRng::seed_from_u64(seed ^ id as u64),
+            None => StdRng::from_entropy(),
+        };
+        let mut node = Self { id, peers, removals, log, state, tx, opts, rng, role };
+
+        // Load node removals from the log. This scans the entire log, since
+        // removals aren't indexed separately.
+        let mut scan = node.log.scan(..);
+        let mut removals = Vec::new();
+        while let Some(entry) = scan.next().transpose()? {
+            if let Command::RemoveNode(id) = entry.command {
+                removals.push((entry.index, id));
+            }
+        }
+        drop(scan);
+        for (index, id) in removals {
+            node.add_removal(index, id);
+        }
         node.role.election_timeout = node.random_election_timeout();
 
         // Apply any pending entries following restart. Unlike the Raft log,
@@ -370,8 +531,9 @@ impl RawNode<Follower> {
         match msg.message {
             // The leader sends periodic heartbeats. If we don't have a leader
             // yet, follow it. If the commit_index advances, apply commands.
-            Message::Heartbeat { last_index, commit_index, read_seq } => {
+            Message::Heartbeat { last_index, commit_index, read_seq, truncate_index } => {
                 assert!(commit_index <= last_index, "commit_index after last_index");
+                assert!(truncate_index <= commit_index, "truncate_index after commit_index");
 
                 // Make sure the heartbeat is from our leader, or follow it.
                 match self.role.leader {
@@ -392,6 +554,9 @@ impl RawNode<Follower> {
                     self.log.commit(commit_index)?;
                     self.maybe_apply()?;
                 }
+
+                // Truncate the log if requested by the leader.
+                self.maybe_truncate(truncate_index)?;
             }
 
             // Append log entries from the leader to the local log.
@@ -410,7 +575,7 @@ impl RawNode<Follower> {
                 let (mut reject_index, mut match_index) = (0, 0);
                 if base_index == 0 || self.log.has(base_index, base_term)? {
                     match_index = entries.last().map(|e| e.index).unwrap_or(base_index);
-                    self.log.splice(entries)?;
+                    self.splice(entries)?;
                 } else {
                     // Otherwise, reject the base index. If the local log is
                     // shorter than the base index, lower the reject index to
@@ -432,8 +597,24 @@ impl RawNode<Follower> {
                 self.send(msg.from, Message::ReadResponse { seq })?;
             }
 
+            // The leader is transferring leadership to us. Campaign at once.
+            Message::TimeoutNow => {
+                match self.role.leader {
+                    Some(leader) => assert_eq!(msg.from, leader, "multiple leaders in term"),
+                    None => self = self.into_follower(msg.term, Some(msg.from))?,
+                }
+                info!("Leader {} is transferring leadership to us", msg.from);
+                return Ok(self.into_candidate()?.into());
+            }
+
             // A candidate is requesting our vote. We'll only grant one.
+            // Learners don't vote.
             Message::Campaign { last_index, last_term } => {
+                if self.is_learner(self.id) {
+                    self.send(msg.from, Message::CampaignResponse { vote: false })?;
+                    return Ok(self.into());
+                }
+
                 // Don't vote if we already voted for someone else in this term.
                 // We can repeat our vote though.
                 if let (_, Some(vote)) = self.log.get_term() {
@@ -458,25 +639,54 @@ impl RawNode<Follower> {
                 self.send(msg.from, Message::CampaignResponse { vote: true })?;
             }
 
-            // Forward client requests to the leader, or abort them if there is
-            // none. These will not be retried, the client should use timeouts.
-            // Local client requests use our node ID as the sender.
-            Message::ClientRequest { id, request: _ } => {
-                assert_eq!(msg.from, self.id, "client request from other node");
+            // Local client requests are handled by us.
+            Message::ClientRequest {
+                id,
+                request:
+                    request @ (Request::NodeStatus
+                    | Request::LocalRead(_)
+                    | Request::CommittedEntries(_)),
+            } => {
+                assert_eq!(msg.from, self.id, "local request from other node");
+                let leader = self.role.leader;
+                let role = match self.is_learner(self.id) {
+                    true => NodeRole::Learner,
+                    false => NodeRole::Follower,
+                };
+                self.step_local(id, request, role, leader)?;
+            }
 
-                if let Some(leader) = self.role.leader {
+            // Forward client requests to the leader, or reject them if there
+            // is none. These will not be retried, the client should use
+            // timeouts. Local client requests use our node ID as the sender. If
+            // we've been removed from the cluster, the leader ignores us, so
+            // reject them. A forwarded request from another node means we were
+            // the leader in this term before restarting, so reject it.
+            Message::ClientRequest { id, request: _ } => {
+                if msg.from != self.id {
+                    let response = Err(Error::NotLeader(self.role.leader));
+                    self.send(msg.from, Message::ClientResponse { id, response })?
+                } else if self.is_removed() {
+                    let error = format!("node {} has been removed from the cluster", self.id);
+                    let response = Err(Error::InvalidInput(error));
+                    self.send(msg.from, Message::ClientResponse { id, response })?
+                } else if let Some(leader) = self.role.leader {
                     debug!("Forwarding request to leader {leader}: {msg:?}");
                     self.role.forwarded.insert(id);
                     self.send(leader, msg.message)?
                 } else {
-                    let response = Err(Error::Abort);
+                    let response = Err(Error::NotLeader(None));
                     self.send(msg.from, Message::ClientResponse { id, response })?
                 }
             }
 
-            // Client responses from the leader are passed on to the client.
+            // Client responses from the leader are passed on to the client. If
+            // we restarted, we may not know the leader, and have lost track of
+            // the forwarded request, so ignore it.
             Message::ClientResponse { id, response } => {
-                assert_eq!(Some(msg.from), self.role.leader, "client response from non-leader");
+                if let Some(leader) = self.role.leader {
+                    assert_eq!(msg.from, leader, "client response from non-leader");
+                }
 
                 if self.role.forwarded.remove(&id) {
                     self.send(self.id, Message::ClientResponse { id, response })?;
@@ -496,15 +706,53 @@ impl RawNode<Follower> {
         Ok(self.into())
     }
 
-    /// Processes a logical clock tick.
+    /// Processes a logical clock tick. Removed nodes and learners don't
+    /// campaign.
     fn tick(mut self) -> Result<Node> {
         self.role.leader_seen += 1;
-        if self.role.leader_seen >= self.role.election_timeout {
+        if self.role.leader_seen >= self.role.election_timeout
+            && !self.is_removed()
+            && !self.is_learner(self.id)
+        {
             return Ok(self.into_candidate()?.into());
         }
         Ok(self.into())
     }
 
+    /// Splices entries from the leader into the log, and updates the peers
+    /// with any node removals in them. Removals in entries that were replaced
+    /// or truncated are reverted.
+    fn splice(&mut self, entries: Vec<Entry>) -> Result<()> {
+        let (Some(first), Some(last)) = (entries.first(), entries.last()) else {
+            return Ok(());
+        };
+        let (first, last) = (first.index, last.index);
+        let removals: BTreeMap<Index, NodeID> = entries
+            .iter()
+            .filter_map(|e| match e.command {
+                Command::RemoveNode(id) => Some((e.index, id)),
+                _ => None,
+            })
+            .collect();
+        let last_index = self.log.splice(entries)?;
+
+        // The log now contains the spliced entries in [first,last]. Entries
+        // after them are unchanged, unless the log was truncated.
+        for (index, id) in self.removals.split_off(&first) {
+            if removals.get(&index) == Some(&id) || (index > last && index <= last_index) {
+                self.removals.insert(index, id);
+            } else {
+                self.revert_removal(id);
+            }
+        }
+        for (index, id) in removals {
+            if !self.removals.contains_key(&index) {
+                self.add_removal(index, id);
+            }
+        }
+        Ok(())
+    }
+
     /// Aborts all forwarded requests (e.g. on term/leader changes).
     fn abort_forwarded(&mut self) -> Result<()> {
         // Sort by ID for test determinism.
@@ -584,7 +832,7 @@ impl RawNode<Candidate> {
         // previous entries in the log. See section 5.4.2 in the Raft paper.
         // We do this prior to the heartbeat, to avoid a wasted replication
         // roundtrip if the heartbeat response indicates the peer is behind.
-        node.propose(None)?;
+        node.propose(Command::Noop)?;
         node.maybe_commit_and_apply()?;
         node.heartbeat()?;
 
@@ -623,13 +871,29 @@ impl RawNode<Candidate> {
 
             // If we hear from a leader in this term, we lost the election.
             // Follow it and step the message.
-            Message::Heartbeat { .. } | Message::Append { .. } | Message::Read { .. } => {
+            Message::Heartbeat { .. }
+            | Message::Append { .. }
+            | Message::Read { .. }
+            | Message::TimeoutNow => {
                 return self.into_follower(msg.term, Some(msg.from))?.step(msg);
             }
 
-            // Abort client requests while campaigning. The client must retry.
+            // Local client requests are handled by us.
+            Message::ClientRequest {
+                id,
+                request:
+                    request @ (Request::NodeStatus
+                    | Request::LocalRead(_)
+                    | Request::CommittedEntries(_)),
+            } => {
+                assert_eq!(msg.from, self.id, "local request from other node");
+                self.step_local(id, request, NodeRole::Candidate, None)?;
+            }
+
+            // Reject client requests while campaigning. The client must retry.
             Message::ClientRequest { id, request: _ } => {
-                self.send(msg.from, Message::ClientResponse { id, response: Err(Error::Abort) })?;
+                let response = Err(Error::NotLeader(None));
+                self.send(msg.from, Message::ClientResponse { id, response })?;
             }
 
             // We're not a leader in this term, nor are we forwarding requests,
@@ -652,7 +916,7 @@ impl RawNode<Candidate> {
     }
 
     /// Hold a new election by increasing the term, voting for ourself, and
-    /// soliciting votes from all peers.
+    /// soliciting votes from all peers. Learners will reject the vote.
     fn campaign(&mut self) -> Result<()> {
         let term = self.term() + 1;
         info!("Starting new election for term {term}");
@@ -684,6 +948,11 @@ pub struct Leader {
     read_seq: ReadSequence,
     /// Number of ticks since last heartbeat.
     since_heartbeat: Ticks,
+    /// Client writes batched for group commit, which haven't been proposed
+    /// yet. See `Options::group_commit_bytes`.
+    batch: Vec<(Vec<u8>, Write)>,
+    /// The total size of the batched write commands, in bytes.
+    batch_size: usize,
 }
 
 /// Follower replication progress (in this term).
@@ -754,6 +1023,10 @@ struct Read {
     id: RequestID,
     /// The read command.
     command: Vec<u8>,
+    /// For snapshot reads, the read index: the commit index when the read was
+    /// submitted, which must be applied before the read is served. None for
+    /// regular reads, which wait until the entire commit index is applied.
+    index: Option<Index>,
 }
 
 impl Leader {
@@ -770,6 +1043,8 @@ impl Leader {
             reads: VecDeque::new(),
             read_seq: 0,
             since_heartbeat: 0,
+            batch: Vec::new(),
+            batch_size: 0,
         }
     }
 }
@@ -784,9 +1059,11 @@ impl RawNode<Leader> {
         assert!(term > self.term(), "leader can only become follower in later term");
         info!("Discovered new term {term}");
 
-        // Abort in-flight requests. The client must retry. Sort the requests
-        // by ID for test determinism.
-        for write in std::mem::take(&mut self.role.writes).into_values().sorted_by_key(|w| w.id) {
+        // Abort in-flight requests, including batched writes. The client must
+        // retry. Sort the requests by ID for test determinism.
+        let batch = std::mem::take(&mut self.role.batch).into_iter().map(|(_, write)| write);
+        let writes = std::mem::take(&mut self.role.writes).into_values().chain(batch);
+        for write in writes.sorted_by_key(|w| w.id) {
             let response = Err(Error::Abort);
             self.send(write.from, Message::ClientResponse { id: write.id, response })?;
         }
@@ -902,11 +1179,24 @@ impl RawNode<Leader> {
             // A client submitted a write request. Propose it, and wait until
             // it's replicated and applied to the state machine before returning
             // the response to the client.
+            //
+            // With group commit, the write is batched instead, and proposed
+            // along with other writes once the batch is large enough or on the
+            // next tick (or when the caller flushes it).
             Message::ClientRequest { id, request: Request::Write(command) } => {
-                let index = self.propose(Some(command))?;
-                self.role.writes.insert(index, Write { from: msg.from, id });
-                if self.cluster_size() == 1 {
-                    self.maybe_commit_and_apply()?;
+                let write = Write { from: msg.from, id };
+                if self.opts.group_commit_bytes == 0 {
+                    let index = self.propose(Command::Write(command))?;
+                    self.role.writes.insert(index, write);
+                    if self.cluster_size() == 1 {
+                        self.maybe_commit_and_apply()?;
+                    }
+                } else {
+                    self.role.batch_size += command.len();
+                    self.role.batch.push((command, write));
+                    if self.role.batch_size >= self.opts.group_commit_bytes {
+                        self.propose_batch()?;
+                    }
                 }
             }
 
@@ -915,7 +1205,27 @@ impl RawNode<Leader> {
             // sequence number and wait for quorum confirmation.
             Message::ClientRequest { id, request: Request::Read(command) } => {
                 self.role.read_seq += 1;
-                let read = Read { seq: self.role.read_seq, from: msg.from, id, command };
+                let read =
+                    Read { seq: self.role.read_seq, from: msg.from, id, command, index: None };
+                self.role.reads.push_back(read);
+                self.broadcast(Message::Read { seq: self.role.read_seq })?;
+                if self.cluster_size() == 1 {
+                    self.maybe_read()?;
+                }
+            }
+
+            // A client submitted a snapshot read request. This uses the
+            // ReadIndex protocol: we record the current commit index as the
+            // read index, confirm leadership with a quorum like other reads,
+            // and serve the read once we've applied the read index. Unlike
+            // regular reads, it doesn't have to wait for entries committed
+            // after it was submitted. Until we've committed an entry from our
+            // own term, our commit index may be stale, so it's not recorded.
+            Message::ClientRequest { id, request: Request::SnapshotRead(command) } => {
+                self.role.read_seq += 1;
+                let (commit_index, commit_term) = self.log.get_commit_index();
+                let index = Some(commit_index).filter(|_| commit_term == self.term());
+                let read = Read { seq: self.role.read_seq, from: msg.from, id, command, index };
                 self.role.reads.push_back(read);
                 self.broadcast(Message::Read { seq: self.role.read_seq })?;
                 if self.cluster_size() == 1 {
@@ -929,6 +1239,73 @@ impl RawNode<Leader> {
                 self.send(msg.from, Message::ClientResponse { id, response })?;
             }
 
+            // A client submitted a local request.
+            Message::ClientRequest {
+                id,
+                request:
+                    request @ (Request::NodeStatus
+                    | Request::LocalRead(_)
+                    | Request::CommittedEntries(_)),
+            } => {
+                assert_eq!(msg.from, self.id, "local request from other node");
+                let leader = Some(self.id);
+                self.step_local(id, request, NodeRole::Leader, leader)?;
+            }
+
+            // A client requested a leadership transfer. Tell the first
+            // voting follower whose log is up-to-date to campaign. It will
+            // win, since its log is as up-to-date as any voter's, and we step
+            // down once we see its campaign in the next term.
+            Message::ClientRequest { id, request: Request::TransferLeadership } => {
+                let (last_index, _) = self.log.get_last_index();
+                let voters = self.peers.iter().copied().filter(|p| !self.is_learner(*p)).sorted();
+                let response = match voters.clone().find(|peer| {
+                    self.role.progress.get(peer).is_some_and(|p| p.match_index == last_index)
+                }) {
+                    Some(peer) => {
+                        info!("Transferring leadership to {peer}");
+                        self.send(peer, Message::TimeoutNow)?;
+                        Ok(Response::TransferLeadership(peer))
+                    }
+                    None if voters.len() == 0 => {
+                        errinput!("no peers to transfer leadership to")
+                    }
+                    None => Err(Error::Abort),
+                };
+                self.send(msg.from, Message::ClientResponse { id, response })?;
+            }
+
+            // A client requested a node removal. Propose it, and stop
+            // replicating to the node at once, since the removal takes effect
+            // when appended. Respond once it's committed and applied.
+            Message::ClientRequest { id, request: Request::RemoveNode(node) } => {
+                let (commit_index, commit_term) = self.log.get_commit_index();
+                let error = if node == self.id {
+                    errinput!("can't remove leader {node}, transfer leadership first")
+                } else if !self.peers.contains(&node) {
+                    errinput!("node {node} is not a cluster member")
+                } else if self.removals.range(commit_index + 1..).next().is_some() {
+                    errinput!("a node removal is already in progress")
+                } else if commit_term < self.term() {
+                    // We must commit an entry in our term before changing the
+                    // cluster, to not build on an uncommitted removal from a
+                    // previous term (see the Raft thesis errata).
+                    Err(Error::Abort)
+                } else {
+                    Ok(())
+                };
+                if let Err(error) = error {
+                    self.send(msg.from, Message::ClientResponse { id, response: Err(error) })?;
+                    return Ok(self.into());
+                }
+                let index = self.propose(Command::RemoveNode(node))?;
+                self.add_removal(index, node);
+                self.role.progress.remove(&node);
+                self.role.writes.insert(index, Write { from: msg.from, id });
+                self.maybe_commit_and_apply()?;
+                self.maybe_read()?;
+            }
+
             // Don't grant any votes (we've already voted for ourself).
             Message::Campaign { .. } => {
                 self.send(msg.from, Message::CampaignResponse { vote: false })?
@@ -938,7 +1315,10 @@ impl RawNode<Leader> {
             Message::CampaignResponse { .. } => {}
 
             // There can't be another leader in this term.
-            Message::Heartbeat { .. } | Message::Append { .. } | Message::Read { .. } => {
+            Message::Heartbeat { .. }
+            | Message::Append { .. }
+            | Message::Read { .. }
+            | Message::TimeoutNow => {
                 panic!("saw other leader {} in term {}", msg.from, msg.term);
             }
 
@@ -951,6 +1331,7 @@ impl RawNode<Leader> {
 
     /// Processes a logical clock tick.
     fn tick(mut self) -> Result<Node> {
+        self.propose_batch()?;
         self.role.since_heartbeat += 1;
         if self.role.since_heartbeat >= self.opts.heartbeat_interval {
             self.heartbeat()?;
@@ -965,20 +1346,59 @@ impl RawNode<Leader> {
         let read_seq = self.role.read_seq;
         assert_eq!(last_term, self.term(), "leader's last_term not in current term");
 
+        // Without snapshots, we can only truncate entries that all nodes have.
+        // Followers that haven't responded in this term have match_index 0.
+        let mut truncate_index = 0;
+        if self.opts.truncate_log {
+            let min_match = self.role.progress.values().map(|p| p.match_index).min();
+            truncate_index = min(min_match.unwrap_or(commit_index), commit_index);
+            self.maybe_truncate(truncate_index)?;
+        }
+
         self.role.since_heartbeat = 0;
-        self.broadcast(Message::Heartbeat { last_index, commit_index, read_seq })
+        self.broadcast(Message::Heartbeat { last_index, commit_index, read_seq, truncate_index })
     }
 
     /// Proposes a command for consensus by appending it to our log and
     /// replicating it to peers. If successful, it will eventually be committed
     /// and applied to the state machine.
-    fn propose(&mut self, command: Option<Vec<u8>>) -> Result<Index> {
-        let index = self.log.append(command)?;
+    ///
+    /// Any batched writes are proposed first, to retain the request order.
+    fn propose(&mut self, command: Command) -> Result<Index> {
+        self.propose_batch()?;
+        self.append(vec![command])
+    }
+
+    /// Proposes the batched client writes, if any (group commit). They're
+    /// appended to the log with a single fsync and replicated together, and
+    /// clients are responded to once they're applied.
+    fn propose_batch(&mut self) -> Result<()> {
+        if self.role.batch.is_empty() {
+            return Ok(());
+        }
+        self.role.batch_size = 0;
+        let (commands, writes): (Vec<_>, Vec<_>) =
+            std::mem::take(&mut self.role.batch).into_iter().unzip();
+        debug!("Proposing batch of {} writes", writes.len());
+        let last_index = self.append(commands.into_iter().map(Command::Write).collect())?;
+        let first_index = last_index + 1 - writes.len() as Index;
+        self.role.writes.extend((first_index..=last_index).zip(writes));
+        if self.cluster_size() == 1 {
+            self.maybe_commit_and_apply()?;
+        }
+        Ok(())
+    }
+
+    /// Appends a batch of commands to our log and replicates them to peers,
+    /// returning the last index.
+    fn append(&mut self, commands: Vec<Command>) -> Result<Index> {
+        let (old_last_index, _) = self.log.get_last_index();
+        let index = self.log.append_batch(commands)?;
         for peer in self.peers.iter().copied().sorted() {
-            // Eagerly send the entry to the peer if it's in steady state and
+            // Eagerly send the entries to the peer if it's in steady state and
             // we've sent all previous entries. Otherwise, the peer is lagging
             // and we're probing past entries for a match.
-            if index == self.progress(peer).next_index {
+            if old_last_index + 1 == self.progress(peer).next_index {
                 self.maybe_send_append(peer, false)?;
             }
         }
@@ -991,7 +1411,7 @@ impl RawNode<Leader> {
         // Determine the new commit index by quorum.
         let (last_index, _) = self.log.get_last_index();
         let quorum_index = self.quorum_value(
-            self.role.progress.values().map(|p| p.match_index).chain([last_index]).collect(),
+            self.voter_progress().map(|p| p.match_index).chain([last_index]).collect(),
         );
 
         // If the commit index doesn't advance, do nothing. We don't assert on
@@ -1019,10 +1439,15 @@ impl RawNode<Leader> {
         while let Some(entry) = iter.next().transpose()? {
             debug!("Applying {entry:?}");
             let write = self.role.writes.remove(&entry.index);
+            let (index, removal) = (entry.index, matches!(entry.command, Command::RemoveNode(_)));
             let result = self.state.apply(entry);
 
             if let Some(Write { id, from: to }) = write {
-                let message = Message::ClientResponse { id, response: result.map(Response::Write) };
+                let response = match removal {
+                    true => result.map(|_| Response::RemoveNode(index)),
+                    false => result.map(Response::Write),
+                };
+                let message = Message::ClientResponse { id, response };
                 Self::send_with(&self.tx, Envelope { from: self.id, term, to, message })?;
             }
         }
@@ -1043,24 +1468,25 @@ impl RawNode<Leader> {
             return Ok(());
         }
 
-        // It's only safe to read if we've committed and applied an entry from
-        // our own term (the leader appends an entry when elected). Otherwise we
-        // may be behind on application and serve stale reads.
+        // It's only safe to read if we've committed an entry from our own term
+        // (the leader appends an entry when elected). Otherwise we may be
+        // behind on commits and serve stale reads.
         let (commit_index, commit_term) = self.log.get_commit_index();
         let applied_index = self.state.get_applied_index();
-        if commit_term < self.term() || applied_index < commit_index {
+        if commit_term < self.term() {
             return Ok(());
         }
 
         // Determine the maximum read sequence confirmed by quorum.
         let quorum_read_seq = self.quorum_value(
-            self.role.progress.values().map(|p| p.read_seq).chain([self.role.read_seq]).collect(),
+            self.voter_progress().map(|p| p.read_seq).chain([self.role.read_seq]).collect(),
         );
 
-        // Execute ready reads. The VecDeque is ordered by read_seq, so we
-        // can keep pulling until we hit quorum_read_seq.
+        // Execute ready reads, once we've applied their read index (or the
+        // entire commit index for regular reads). The VecDeque is ordered by
+        // read_seq, so we can keep pulling until we hit quorum_read_seq.
         while let Some(read) = self.role.reads.front() {
-            if read.seq > quorum_read_seq {
+            if read.seq > quorum_read_seq || applied_index < read.index.unwrap_or(commit_index) {
                 break;
             }
             let read = self.role.reads.pop_front().unwrap();
@@ -1153,6 +1579,11 @@ impl RawNode<Leader> {
     fn progress(&mut self, id: NodeID) -> &mut Progress {
         self.role.progress.get_mut(&id).expect("unknown node")
     }
+
+    /// Returns the progress of voting followers, excluding learners.
+    fn voter_progress(&self) -> impl Iterator<Item = &Progress> {
+        self.role.progress.iter().filter(|(id, _)| !self.is_learner(**id)).map(|(_, p)| p)
+    }
 }
 
 /// Most Raft tests are Goldenscripts under src/raft/testscripts.
@@ -1224,7 +1655,7 @@ mod tests {
 
     /// Test helpers for Node.
     impl Node {
-        fn dismantle(self) -> (Log, Box<dyn State>) {
+        pub(in crate::raft) fn dismantle(self) -> (Log, Box<dyn State>) {
             with_rawnode!(self, |n| (n.log, n.state))
         }
 
@@ -1296,8 +1727,11 @@ mod tests {
                     self.campaign(&ids, &mut output)?;
                 }
 
-                // cluster nodes=N [leader=ID] [heartbeat_interval=N] [election_timeout=N] [max_append_entries=N]
-                // Creates a new Raft cluster.
+                // cluster nodes=N [leader=ID] [heartbeat_interval=N] [election_timeout=N] [max_append_entries=N] [learners=ID,...] [group_commit=BYTES] [truncate_log=BOOL]
+                // Creates a new Raft cluster, with the given non-voting learners.
+                // If group_commit is given, leaders batch writes up to the
+                // given number of bytes. If truncate_log is true, leaders
+                // propagate log truncation indexes.
                 "cluster" => {
                     let mut opts = Options::default();
                     let mut args = command.consume_args();
@@ -1312,6 +1746,16 @@ mod tests {
                     if let Some(max_append_entries) = args.lookup_parse("max_append_entries")? {
                         opts.max_append_entries = max_append_entries;
                     }
+                    if let Some(learners) = args.lookup("learners") {
+                        opts.learners =
+                            learners.value.split(',').map(|id| id.parse()).try_collect()?;
+                    }
+                    if let Some(group_commit) = args.lookup_parse("group_commit")? {
+                        opts.group_commit_bytes = group_commit;
+                    }
+                    if let Some(truncate_log) = args.lookup_parse("truncate_log")? {
+                        opts.truncate_log = truncate_log;
+                    }
                     args.reject_rest()?;
                     self.cluster(nodes, leader, opts, &mut output)?;
                 }
@@ -1327,15 +1771,44 @@ mod tests {
                     self.deliver(&ids, from, &mut output)?;
                 }
 
-                // get ID KEY
+                // entries ID INDEX
+                // Sends a client request to the given node for its committed
+                // log entries after the given index.
+                "entries" => {
+                    let mut args = command.consume_args();
+                    let id = args.next_pos().ok_or("must specify node ID")?.parse()?;
+                    let index = args.next_pos().ok_or("must specify index")?.parse()?;
+                    args.reject_rest()?;
+                    self.request(id, Request::CommittedEntries(index), &mut output)?;
+                }
+
+                // flush [ID...]
+                // Proposes the batched writes on the given leader nodes.
+                "flush" => {
+                    let ids = self.parse_ids_or_all(&command.args)?;
+                    for id in ids {
+                        self.transition(id, |n| n.flush_batch(), &mut output)?;
+                    }
+                }
+
+                // get [snapshot=BOOL] [local=BOOL] ID KEY
                 // Sends a client request to the given node to read the given
-                // key from the state machine (key/value store).
+                // key from the state machine (key/value store). If snapshot is
+                // true, it's sent as a snapshot read. If local is true, it's
+                // sent as a local read.
                 "get" => {
                     let mut args = command.consume_args();
+                    let snapshot = args.lookup_parse("snapshot")?.unwrap_or(false);
+                    let local = args.lookup_parse("local")?.unwrap_or(false);
                     let id = args.next_pos().ok_or("must specify node ID")?.parse()?;
                     let key = args.next_pos().ok_or("must specify key")?.value.clone();
                     args.reject_rest()?;
-                    let request = Request::Read(KVCommand::Get { key }.encode());
+                    let command = KVCommand::Get { key }.encode();
+                    let request = match (snapshot, local) {
+                        (_, true) => Request::LocalRead(command),
+                        (true, false) => Request::SnapshotRead(command),
+                        (false, false) => Request::Read(command),
+                    };
                     self.request(id, request, &mut output)?;
                 }
 
@@ -1382,6 +1855,17 @@ mod tests {
                     self.request(id, request, &mut output)?;
                 }
 
+                // remove_node ID NODE
+                // Sends a client request to the given node to remove the
+                // given node from the cluster.
+                "remove_node" => {
+                    let mut args = command.consume_args();
+                    let id = args.next_pos().ok_or("must specify node ID")?.parse()?;
+                    let node = args.next_pos().ok_or("must specify node to remove")?.parse()?;
+                    args.reject_rest()?;
+                    self.request(id, Request::RemoveNode(node), &mut output)?;
+                }
+
                 // restart [commit_index=INDEX] [applied_index=INDEX] [ID...]
                 // Restarts the given nodes (or all nodes). They retain their
                 // log and state, unless applied_index is given (which reverts
@@ -1415,19 +1899,25 @@ mod tests {
                     self.state(&ids, &mut output)?;
                 }
 
-                // status [request=BOOL] [ID...]
+                // status [request=BOOL] [local=BOOL] [ID...]
                 // Prints the current node status of the given nodes. If request
-                // is true, sends a status client request to a single node,
-                // otherwise fetches status directly from each node.
+                // is true, sends a status client request to a single node (a
+                // node status request if local is true), otherwise fetches
+                // status directly from each node.
                 "status" => {
                     let mut args = command.consume_args();
                     let request = args.lookup_parse("request")?.unwrap_or(false);
+                    let local = args.lookup_parse("local")?.unwrap_or(false);
                     let ids = self.parse_ids_or_all(&args.rest())?;
                     if request {
                         let [id] = *ids.as_slice() else {
                             return Err("request=true requires 1 node ID".into());
                         };
-                        self.request(id, Request::Status, &mut output)?;
+                        let request = match local {
+                            true => Request::NodeStatus,
+                            false => Request::Status,
+                        };
+                        self.request(id, request, &mut output)?;
                     } else {
                         self.status(&ids, &mut output)?;
                     }
@@ -1453,6 +1943,16 @@ mod tests {
                     }
                 }
 
+                // transfer_leadership ID
+                // Sends a client request to the given node to transfer
+                // leadership away from the leader.
+                "transfer_leadership" => {
+                    let mut args = command.consume_args();
+                    let id = args.next_pos().ok_or("must specify node ID")?.parse()?;
+                    args.reject_rest()?;
+                    self.request(id, Request::TransferLeadership, &mut output)?;
+                }
+
                 name => return Err(format!("unknown command {name}").into()),
             }
             Ok(output)
@@ -2029,9 +2529,10 @@ mod tests {
 
         /// Formats an entry.
         fn format_entry(entry: &Entry) -> String {
-            let command = match entry.command.as_ref() {
-                Some(raw) => KVCommand::decode(raw).expect("invalid command").to_string(),
-                None => "None".to_string(),
+            let command = match &entry.command {
+                Command::Write(raw) => KVCommand::decode(raw).expect("invalid command").to_string(),
+                Command::Noop => "None".to_string(),
+                Command::RemoveNode(id) => format!("remove n{id}"),
             };
             format!("{index}@{term} {command}", index = entry.index, term = entry.term)
         }
@@ -2045,10 +2546,14 @@ mod tests {
                 Message::CampaignResponse { vote } => {
                     format!("CampaignResponse vote={vote}")
                 }
-                Message::Heartbeat { last_index, commit_index, read_seq } => {
-                    format!(
+                Message::Heartbeat { last_index, commit_index, read_seq, truncate_index } => {
+                    let mut s = format!(
                         "Heartbeat last_index={last_index} commit_index={commit_index} read_seq={read_seq}"
-                    )
+                    );
+                    if *truncate_index > 0 {
+                        s += &format!(" truncate_index={truncate_index}");
+                    }
+                    s
                 }
                 Message::HeartbeatResponse { match_index, read_seq } => {
                     format!("HeartbeatResponse match_index={match_index} read_seq={read_seq}")
@@ -2071,14 +2576,25 @@ mod tests {
                 Message::ReadResponse { seq } => {
                     format!("ReadResponse seq={seq}")
                 }
+                Message::TimeoutNow => "TimeoutNow".to_string(),
                 Message::ClientRequest { id, request } => {
                     format!(
                         "ClientRequest id=0x{} {}",
                         hex::encode(id).trim_start_matches("00"),
                         match request {
                             Request::Read(v) => format!("read 0x{}", hex::encode(v)),
+                            Request::SnapshotRead(v) => {
+                                format!("snapshot read 0x{}", hex::encode(v))
+                            }
                             Request::Write(v) => format!("write 0x{}", hex::encode(v)),
                             Request::Status => "status".to_string(),
+                            Request::TransferLeadership => "transfer leadership".to_string(),
+                            Request::NodeStatus => "node status".to_string(),
+                            Request::LocalRead(v) => format!("local read 0x{}", hex::encode(v)),
+                            Request::RemoveNode(id) => format!("remove node n{id}"),
+                            Request::CommittedEntries(index) => {
+                                format!("committed entries after {index}")
+                            }
                         }
                     )
                 }
@@ -2090,6 +2606,15 @@ mod tests {
                             Ok(Response::Read(v)) => format!("read 0x{}", hex::encode(v)),
                             Ok(Response::Write(v)) => format!("write 0x{}", hex::encode(v)),
                             Ok(Response::Status(v)) => format!("status {v:?}"),
+                            Ok(Response::TransferLeadership(id)) => {
+                                format!("transfer leadership n{id}")
+                            }
+                            Ok(Response::NodeStatus(v)) => format!("node status {v:?}"),
+                            Ok(Response::RemoveNode(index)) => format!("remove node at {index}"),
+                            Ok(Response::Entries(entries)) => format!(
+                                "entries [{}]",
+                                entries.iter().map(Self::format_entry).join(", ")
+                            ),
                             Err(error) => format!("Error::{error:#?}"),
                         }
                     )
@@ -2108,7 +2633,10 @@ mod tests {
                 Node::Candidate(_) => "candidate".to_string(),
                 Node::Follower(node) => {
                     let leader = node.role.leader.map(|id| format!("n{id}")).unwrap_or_default();
-                    format!("follower({leader})")
+                    match node.is_learner(node.id) {
+                        true => format!("learner({leader})"),
+                        false => format!("follower({leader})"),
+                    }
                 }
                 Node::Leader(_) => "leader".to_string(),
             };
@@ -2118,8 +2646,15 @@ mod tests {
         /// Formats a request.
         fn format_request(request: &Request) -> String {
             match request {
-                Request::Read(c) | Request::Write(c) => KVCommand::decode(c).unwrap().to_string(),
+                Request::Read(c)
+                | Request::SnapshotRead(c)
+                | Request::Write(c)
+                | Request::LocalRead(c) => KVCommand::decode(c).unwrap().to_string(),
                 Request::Status => "status".to_string(),
+                Request::TransferLeadership => "transfer leadership".to_string(),
+                Request::NodeStatus => "node status".to_string(),
+                Request::RemoveNode(id) => format!("remove node n{id}"),
+                Request::CommittedEntries(index) => format!("committed entries after {index}"),
             }
         }
 
@@ -2130,6 +2665,13 @@ mod tests {
                     KVResponse::decode(r).unwrap().to_string()
                 }
                 Ok(Response::Status(status)) => format!("{status:#?}"),
+                Ok(Response::TransferLeadership(id)) => format!("n{id}"),
+                Ok(Response::NodeStatus(status)) => format!("{status:#?}"),
+                Ok(Response::RemoveNode(index)) => format!("removed at {index}"),
+                Ok(Response::Entries(entries)) => match entries.is_empty() {
+                    true => "no entries".to_string(),
+                    false => entries.iter().map(Self::format_entry).join("\n"),
+                },
                 Err(error) => format!("Error::{error:?} ({error})"),
             }
         }
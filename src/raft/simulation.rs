@@ -0,0 +1,370 @@
+//! A deterministic simulation test harness for Raft clusters.
+//!
+//! Raft nodes are already deterministic state machines driven by inbound
+//! messages and logical clock ticks, with pluggable storage. A simulation runs
+//! a cluster of nodes with in-memory storage, where a scheduler seeded with a
+//! random seed decides which event happens next: ticking a node's clock,
+//! delivering or dropping an in-flight message, submitting a client write,
+//! partitioning or healing the network, or crashing and restarting a node. Election timeouts are seeded by the same seed (see
+//! Options::seed), so a simulation run is fully reproducible from its seed.
+//!
+//! Throughout the run, the simulation checks Raft's safety invariants:
+//!
+//! * Election safety: there's at most one leader per term.
+//! * State machine safety: all nodes apply the same entry at a given index.
+//! * Durability: an acknowledged client write is applied at the returned
+//!   index.
+//!
+//! At the end, the network is healed and crashed nodes are restarted, and the
+//! cluster must then converge: elect a leader, commit a final write, and apply
+//! it on all nodes.
+//!
+//! On failure, the panic message includes the seed. To debug it, rerun the
+//! simulation with the seed and inspect its event trace (Simulation::trace).
+
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+
+use crossbeam::channel::Receiver;
+use itertools::Itertools as _;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom as _;
+use rand::{Rng as _, SeedableRng as _};
+use test_case::test_case;
+use uuid::Uuid;
+
+use super::state::test::{self as teststate, KVCommand, KVResponse};
+use super::{Command, Entry, Envelope, Index, Log, Message, Node, NodeID, Options};
+use super::{Request, RequestID, Response, Term};
+use crate::encoding::Value as _;
+use crate::storage;
+
+/// The number of seeds to simulate for each cluster size.
+const SEEDS: u64 = 20;
+
+/// The number of events to simulate for each seed.
+const EVENTS: usize = 2000;
+
+/// The maximum number of rounds for the cluster to converge at the end.
+const CONVERGE_ROUNDS: usize = 1000;
+
+/// Runs simulations of clusters with the given number of nodes.
+#[test_case(1; "1 node")]
+#[test_case(3; "3 nodes")]
+#[test_case(5; "5 nodes")]
+fn simulate(nodes: u8) {
+    for seed in 0..SEEDS {
+        Simulation::new(nodes, seed).run(EVENTS);
+    }
+}
+
+/// Simulations with the same seed are identical, and different seeds
+/// diverge.
+#[test]
+fn simulate_deterministic() {
+    let run = |seed| {
+        let mut sim = Simulation::new(3, seed);
+        sim.run(EVENTS);
+        sim.trace
+    };
+    assert_eq!(run(7), run(7));
+    assert_ne!(run(7), run(8));
+}
+
+/// A simulated Raft cluster.
+struct Simulation {
+    /// The simulation seed.
+    seed: u64,
+    /// The random number generator driving the simulation.
+    rng: StdRng,
+    /// IDs of all cluster nodes, in order.
+    ids: Vec<NodeID>,
+    /// Node options.
+    opts: Options,
+    /// Running nodes.
+    nodes: BTreeMap<NodeID, Node>,
+    /// The durable logs of crashed nodes. Their state machines are lost, and
+    /// rebuilt from the log on restart.
+    crashed: BTreeMap<NodeID, Log>,
+    /// Outbound messages from running nodes.
+    nodes_rx: BTreeMap<NodeID, Receiver<Envelope>>,
+    /// Applied entries from running nodes.
+    applied_rx: BTreeMap<NodeID, Receiver<Entry>>,
+    /// In-flight messages between nodes, in send order. Like the TCP
+    /// transport, messages on a given link are delivered in order, but links
+    /// are interleaved randomly.
+    network: Vec<Envelope>,
+    /// Nodes partitioned away from the rest of the cluster, if any.
+    partitioned: BTreeSet<NodeID>,
+    /// The leader of each term.
+    leaders: BTreeMap<Term, NodeID>,
+    /// The entries applied by any node, by index.
+    applied: BTreeMap<Index, Entry>,
+    /// The last applied index of each node.
+    applied_index: BTreeMap<NodeID, Index>,
+    /// In-flight client writes by request ID, with the node they were
+    /// submitted to and the key/value pair.
+    requests: BTreeMap<RequestID, (NodeID, String, String)>,
+    /// Acknowledged client writes by applied index.
+    acked: BTreeMap<Index, (String, String)>,
+    /// The number of submitted client requests.
+    next_request: u64,
+    /// A trace of simulation events.
+    trace: Vec<String>,
+}
+
+impl Simulation {
+    /// Creates a new simulated cluster with the given number of nodes.
+    fn new(nodes: u8, seed: u64) -> Self {
+        let opts = Options { seed: Some(seed), ..Options::default() };
+        let mut sim = Self {
+            seed,
+            rng: StdRng::seed_from_u64(seed),
+            ids: (1..=nodes).collect(),
+            opts,
+            nodes: BTreeMap::new(),
+            crashed: BTreeMap::new(),
+            nodes_rx: BTreeMap::new(),
+            applied_rx: BTreeMap::new(),
+            network: Vec::new(),
+            partitioned: BTreeSet::new(),
+            leaders: BTreeMap::new(),
+            applied: BTreeMap::new(),
+            applied_index: BTreeMap::new(),
+            requests: BTreeMap::new(),
+            acked: BTreeMap::new(),
+            next_request: 0,
+            trace: Vec::new(),
+        };
+        for id in sim.ids.clone() {
+            let log = Log::new(Box::new(storage::Memory::new())).expect("log failed");
+            sim.start(id, log);
+        }
+        sim
+    }
+
+    /// Runs the given number of random events, then heals the cluster and
+    /// checks that it converges.
+    fn run(&mut self, events: usize) {
+        for _ in 0..events {
+            self.event();
+        }
+        self.converge();
+    }
+
+    /// Runs a random event.
+    fn event(&mut self) {
+        let running = self.nodes.keys().copied().collect_vec();
+        match self.rng.gen_range(0..100) {
+            // Deliver or drop the next message on a random link.
+            0..50 if !self.network.is_empty() => {
+                let i = self.next_message();
+                let msg = self.network.remove(i);
+                match self.rng.gen_range(0..100) {
+                    0..5 => self.trace(format!("drop {msg:?}")),
+                    _ => self.deliver(msg),
+                }
+            }
+            // Tick a node.
+            0..85 if !running.is_empty() => {
+                let id = running[self.rng.gen_range(0..running.len())];
+                self.transition(id, |node| node.tick());
+            }
+            // Submit a client write.
+            85..95 if !running.is_empty() => {
+                let id = running[self.rng.gen_range(0..running.len())];
+                self.write(id);
+            }
+            // Partition or heal the network.
+            95..97 => {
+                if self.partitioned.is_empty() {
+                    let count = self.rng.gen_range(1..=self.ids.len().div_ceil(2));
+                    let mut ids = self.ids.clone();
+                    ids.shuffle(&mut self.rng);
+                    self.partitioned = ids.into_iter().take(count).collect();
+                    self.trace(format!("partition {:?}", self.partitioned));
+                } else {
+                    self.partitioned.clear();
+                    self.trace("heal".to_string());
+                }
+            }
+            // Crash or restart a node.
+            97..100 => {
+                let id = self.ids[self.rng.gen_range(0..self.ids.len())];
+                match self.crashed.remove(&id) {
+                    Some(log) => {
+                        self.trace(format!("restart n{id}"));
+                        self.start(id, log);
+                    }
+                    None => self.crash(id),
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Heals the network, restarts crashed nodes, and runs the cluster until
+    /// it has committed a final client write and applied it on all nodes.
+    fn converge(&mut self) {
+        self.partitioned.clear();
+        for (id, log) in std::mem::take(&mut self.crashed) {
+            self.start(id, log);
+        }
+        let mut write: Option<String> = None;
+        for _ in 0..CONVERGE_ROUNDS {
+            for id in self.ids.clone() {
+                self.transition(id, |node| node.tick());
+            }
+            while !self.network.is_empty() {
+                let i = self.next_message();
+                let msg = self.network.remove(i);
+                self.deliver(msg);
+            }
+
+            // Once the write is acknowledged, wait for all nodes to apply it.
+            let acked = self.acked.iter().find(|(_, (key, _))| Some(key) == write.as_ref());
+            if let Some((&index, _)) = acked {
+                if self.applied_index.values().all(|applied| *applied >= index) {
+                    return;
+                }
+                continue;
+            }
+
+            // Submit the write to the leader, once there is one, and resubmit
+            // it if it's aborted.
+            if self.requests.values().any(|(_, key, _)| Some(key) == write.as_ref()) {
+                continue;
+            }
+            if let Some(&id) = self.nodes.iter().find_map(|(id, node)| match node {
+                Node::Leader(_) => Some(id),
+                _ => None,
+            }) {
+                write = Some(self.write(id));
+            }
+        }
+        panic!("seed {}: cluster failed to converge", self.seed);
+    }
+
+    /// Starts a node with the given log.
+    fn start(&mut self, id: NodeID, log: Log) {
+        let peers: HashSet<NodeID> = self.ids.iter().copied().filter(|p| *p != id).collect();
+        let (node_tx, node_rx) = crossbeam::channel::unbounded();
+        let (applied_tx, applied_rx) = crossbeam::channel::unbounded();
+        let state = teststate::Emit::new(teststate::KV::new(), applied_tx);
+        let node = Node::new(id, peers, log, state, node_tx, self.opts.clone())
+            .unwrap_or_else(|e| panic!("seed {}: n{id} failed to start: {e}", self.seed));
+        self.nodes.insert(id, node);
+        self.nodes_rx.insert(id, node_rx);
+        self.applied_rx.insert(id, applied_rx);
+        self.applied_index.insert(id, 0);
+        self.receive(id);
+    }
+
+    /// Crashes a node, retaining its durable log. Its in-flight client
+    /// requests are lost, as are in-flight messages to and from it (its
+    /// connections are reset).
+    fn crash(&mut self, id: NodeID) {
+        self.trace(format!("crash n{id}"));
+        let node = self.nodes.remove(&id).expect("node not running");
+        self.nodes_rx.remove(&id);
+        self.applied_rx.remove(&id);
+        self.requests.retain(|_, (node, _, _)| *node != id);
+        self.network.retain(|msg| msg.from != id && msg.to != id);
+        let (log, _) = node.dismantle();
+        self.crashed.insert(id, Log::new(log.engine).expect("log failed"));
+    }
+
+    /// Returns the network position of the oldest message on a random link.
+    fn next_message(&mut self) -> usize {
+        let msg = &self.network[self.rng.gen_range(0..self.network.len())];
+        let (from, to) = (msg.from, msg.to);
+        self.network.iter().position(|msg| msg.from == from && msg.to == to).expect("no message")
+    }
+
+    /// Delivers a message to its recipient, unless it's crashed or
+    /// partitioned from the sender.
+    fn deliver(&mut self, msg: Envelope) {
+        if !self.nodes.contains_key(&msg.to)
+            || self.partitioned.contains(&msg.from) != self.partitioned.contains(&msg.to)
+        {
+            self.trace(format!("lost {msg:?}"));
+            return;
+        }
+        self.trace(format!("deliver {msg:?}"));
+        self.transition(msg.to, |node| node.step(msg));
+    }
+
+    /// Submits a client write of a unique key to the given node. Returns the
+    /// key.
+    fn write(&mut self, id: NodeID) -> String {
+        self.next_request += 1;
+        let request_id = Uuid::from_u64_pair(0, self.next_request);
+        let (key, value) = (format!("k{}", self.next_request), self.next_request.to_string());
+        self.requests.insert(request_id, (id, key.clone(), value.clone()));
+        let command = KVCommand::Put { key: key.clone(), value };
+        self.trace(format!("c{id} {command}"));
+        let term = self.nodes[&id].term();
+        let message =
+            Message::ClientRequest { id: request_id, request: Request::Write(command.encode()) };
+        self.transition(id, |node| node.step(Envelope { from: id, to: id, term, message }));
+        key
+    }
+
+    /// Applies a node transition, then checks invariants and receives its
+    /// outbound messages and applied entries.
+    fn transition(&mut self, id: NodeID, f: impl FnOnce(Node) -> crate::error::Result<Node>) {
+        let Some(node) = self.nodes.remove(&id) else { return };
+        let node = f(node).unwrap_or_else(|e| panic!("seed {}: n{id} failed: {e}", self.seed));
+        if let Node::Leader(_) = node {
+            let leader = *self.leaders.entry(node.term()).or_insert(id);
+            assert_eq!(leader, id, "seed {}: multiple leaders in term {}", self.seed, node.term());
+        }
+        self.nodes.insert(id, node);
+        self.receive(id);
+    }
+
+    /// Receives a node's outbound messages and applied entries, checking that
+    /// all nodes apply the same entries and that acknowledged writes were
+    /// applied. Messages are ordered by recipient, since peers are iterated
+    /// in random hash order.
+    fn receive(&mut self, id: NodeID) {
+        let seed = self.seed;
+        for entry in self.applied_rx[&id].try_iter().collect_vec() {
+            let applied_index = self.applied_index.get_mut(&id).expect("unknown node");
+            assert_eq!(entry.index, *applied_index + 1, "seed {seed}: n{id} applied out of order");
+            *applied_index = entry.index;
+            let expect = self.applied.entry(entry.index).or_insert_with(|| entry.clone());
+            assert_eq!(*expect, entry, "seed {seed}: n{id} applied divergent entry");
+        }
+
+        let mut responses = Vec::new();
+        for msg in self.nodes_rx[&id].try_iter().sorted_by_key(|msg| msg.to) {
+            match msg.message {
+                Message::ClientResponse { id, response } if msg.from == msg.to => {
+                    responses.push((id, response))
+                }
+                _ => self.network.push(msg),
+            }
+        }
+        for (request_id, response) in responses.into_iter().sorted_by_key(|(id, _)| *id) {
+            let (_, key, value) = self.requests.remove(&request_id).expect("unknown request");
+            self.trace(format!("c{id} put {key}={value} ⇒ {response:?}"));
+            let index = match response {
+                Ok(Response::Write(response)) => match KVResponse::decode(&response) {
+                    Ok(KVResponse::Put(index)) => index,
+                    _ => panic!("seed {seed}: invalid write response"),
+                },
+                Ok(response) => panic!("seed {seed}: unexpected response {response:?}"),
+                Err(_) => continue, // aborted
+            };
+            let entry = self.applied.get(&index).expect("acknowledged write not applied");
+            let expect = KVCommand::Put { key: key.clone(), value: value.clone() }.encode();
+            assert_eq!(entry.command, Command::Write(expect), "seed {seed}: lost write {key}");
+            self.acked.insert(index, (key, value));
+        }
+    }
+
+    /// Records a simulation event in the trace.
+    fn trace(&mut self, event: String) {
+        self.trace.push(event);
+    }
+}
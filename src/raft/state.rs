@@ -38,8 +38,9 @@ pub trait State: Send {
     /// machine is responsible for panicing when appropriate.
     ///
     /// The entry may contain a noop command, which is committed by Raft during
-    /// leader changes. This still needs to be applied to the state machine to
-    /// properly update the applied index, and should return an empty result.
+    /// leader changes, or a node removal, which is handled by Raft itself.
+    /// These still need to be applied to the state machine to properly update
+    /// the applied index, and should return an empty result.
     fn apply(&mut self, entry: Entry) -> Result<Vec<u8>>;
 
     /// Executes a read command in the state machine, returning a client result.
@@ -48,6 +49,11 @@ pub trait State: Send {
     /// This is only executed on a single node, so it must not result in any
     /// state changes (i.e. it must not write).
     fn read(&self, command: Vec<u8>) -> Result<Vec<u8>>;
+
+    /// Flushes applied state to durable storage. This is called before
+    /// truncating the Raft log, since the truncated entries can't be replayed
+    /// into the state machine after a crash.
+    fn flush(&mut self) -> Result<()>;
 }
 
 /// Test helper state machines.
@@ -61,6 +67,7 @@ pub mod test {
 
     use super::*;
     use crate::encoding::{self, Value as _};
+    use crate::raft::Command;
 
     /// Wraps a state machine and emits applied entries to the provided channel.
     pub struct Emit {
@@ -88,6 +95,10 @@ pub mod test {
         fn read(&self, command: Vec<u8>) -> Result<Vec<u8>> {
             self.inner.read(command)
         }
+
+        fn flush(&mut self) -> Result<()> {
+            self.inner.flush()
+        }
     }
 
     /// A simple string key/value store. Takes KVCommands.
@@ -108,7 +119,10 @@ pub mod test {
         }
 
         fn apply(&mut self, entry: Entry) -> Result<Vec<u8>> {
-            let command = entry.command.as_deref().map(KVCommand::decode).transpose()?;
+            let command = match &entry.command {
+                Command::Write(command) => Some(KVCommand::decode(command)?),
+                Command::Noop | Command::RemoveNode(_) => None,
+            };
             let response = match command {
                 Some(KVCommand::Put { key, value }) => {
                     self.data.insert(key, value);
@@ -132,6 +146,10 @@ pub mod test {
                 c @ KVCommand::Put { .. } => panic!("{c} submitted as read command"),
             }
         }
+
+        fn flush(&mut self) -> Result<()> {
+            Ok(())
+        }
     }
 
     /// A KV command. Returns the corresponding KVResponse.
@@ -207,5 +225,9 @@ pub mod test {
         fn read(&self, _: Vec<u8>) -> Result<Vec<u8>> {
             Ok(Vec::new())
         }
+
+        fn flush(&mut self) -> Result<()> {
+            Ok(())
+        }
     }
 }
@@ -1,20 +1,41 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::io::{BufReader, BufWriter, Write as _};
-use std::net::{TcpListener, TcpStream, ToSocketAddrs};
-use std::time::Duration;
+use std::net::{
+    IpAddr, Ipv4Addr, Ipv6Addr, Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs,
+};
+use std::os::unix::fs::PermissionsExt as _;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-use crossbeam::channel::{Receiver, Sender};
+use crossbeam::channel::{Receiver, RecvTimeoutError, Sender, TryRecvError};
+use itertools::Itertools as _;
 use log::{debug, error, info};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
+use crate::client::Client;
+use crate::clock::{self, HybridClock, Timestamp};
 use crate::encoding::{self, Value as _};
-use crate::error::Result;
+use crate::error::{Error, Result};
+use crate::fault::{FaultInjector, Faults};
+use crate::grpc;
+use crate::http;
+use crate::logging;
+use crate::metrics;
+use crate::pgwire;
 use crate::raft;
 use crate::sql;
-use crate::sql::engine::{Catalog as _, Engine as _, StatementResult};
-use crate::sql::types::{Row, Table};
+use crate::sql::engine::{
+    AuditLog, Backup, Catalog as _, Change, Checksum, Engine as _, IncrementalBackup, PlanCache,
+    ResultCache, Scrub, SlowQueryLog, StatementResult, Statistics, Transaction as _,
+};
+use crate::sql::execution::CancelToken;
+use crate::sql::types::{Function, Privilege, Row, Table, TriggerCallback, User};
 use crate::storage;
+use crate::tls::{self, Stream};
+use crate::{errdata, errinput};
 
 /// The outbound Raft peer channel capacity. This buffers messages when a Raft
 /// peer is slow or unavailable. Beyond this, messages will be dropped.
@@ -23,16 +44,55 @@ const RAFT_PEER_CHANNEL_CAPACITY: usize = 1000;
 /// The retry interval when connecting to a Raft peer.
 const RAFT_PEER_RETRY_INTERVAL: Duration = Duration::from_secs(1);
 
+/// How long to try transferring Raft leadership on shutdown.
+const LEADERSHIP_TRANSFER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long to wait for a rejected client's first request.
+const REJECT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How often to poll for progress while shutting down.
+const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How many entries a node's applied index can be behind the commit index
+/// before it's considered lagging in the cluster status.
+const LAGGING_THRESHOLD: raft::Index = 1000;
+
+/// How long a consistency check waits for nodes to apply its checksum entry.
+const CONSISTENCY_CHECK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How often to poll nodes for checksums during a consistency check.
+const CONSISTENCY_CHECK_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
 /// A toyDB server. Routes messages to/from an inner Raft node.
 ///
 /// * Listens for inbound SQL connections from clients via TCP and passes
 ///   requests to the local Raft node.
 ///
+/// * Optionally listens for inbound SQL connections from Postgres clients via
+///   TCP, using the Postgres wire protocol (see pgwire).
+///
+/// * Optionally listens for inbound HTTP/JSON queries via TCP (see http).
+///
+/// * Optionally listens for inbound gRPC clients via TCP (see grpc).
+///
+/// * Optionally listens for inbound SQL connections from local clients via a
+///   Unix socket.
+///
 /// * Listens for inbound Raft connections from other toyDB nodes via TCP and
 ///   passes messages to the local Raft node.
 ///
 /// * Connects to other toyDB nodes via TCP and sends outbound Raft messages
 ///   from the local Raft node.
+///
+/// If TLS is enabled, SQL client, HTTP, gRPC, and Raft peer connections are
+/// encrypted (see tls).
+///
+/// The server's hybrid logical clock timestamps transactions (see clock). Its
+/// timestamp is sent along with every outbound Raft message, and inbound
+/// timestamps are merged into it, keeping clocks causally consistent across
+/// nodes. Clients can do the same via Request::Clock.
+///
+/// The server can be shut down gracefully via a ShutdownHandle, see serve().
 pub struct Server {
     /// The inner Raft node.
     node: raft::Node,
@@ -40,29 +100,420 @@ pub struct Server {
     node_rx: Receiver<raft::Envelope>,
     /// Raft peer IDs and addresses.
     peers: HashMap<raft::NodeID, String>,
+    /// User-defined SQL functions, registered with the SQL engine when serving.
+    functions: Vec<Function>,
+    /// Trigger callbacks, registered with the SQL engine when serving.
+    triggers: Vec<TriggerCallback>,
+    /// Settings that can be changed while serving.
+    settings: SettingsHandle,
+    /// The query result cache size in bytes, if enabled.
+    result_cache_size: Option<usize>,
+    /// The query plan cache size in plans, if enabled.
+    plan_cache_size: Option<usize>,
+    /// The slow query log, if enabled.
+    slow_query_log: Option<Arc<SlowQueryLog>>,
+    /// The audit log, if enabled.
+    audit_log: Option<Arc<AuditLog>>,
+    /// The number of recent MVCC versions retained by garbage collection.
+    gc_retention: u64,
+    /// The maximum size of a streamed SELECT row frame in bytes.
+    frame_size: usize,
+    /// The interval between background garbage collection runs, if enabled.
+    gc_interval: Option<Duration>,
+    /// The interval between background consistency checks, if enabled.
+    consistency_check_interval: Option<Duration>,
+    /// The interval between purges of expired TTL rows, if enabled.
+    ttl_purge_interval: Option<Duration>,
+    /// The transaction intent timeout, if enabled.
+    intent_timeout: Option<Duration>,
+    /// The SQL state machine's lock waiter wakeups, if any.
+    lock_waits: Option<Arc<storage::mvcc::LockWaits>>,
+    /// The Postgres wire protocol listen address, if enabled.
+    listen_pg: Option<String>,
+    /// The HTTP/JSON listen address, if enabled.
+    listen_http: Option<String>,
+    /// The gRPC listen address, if enabled.
+    listen_grpc: Option<String>,
+    /// The Unix socket path to listen for SQL clients on, if any.
+    listen_unix: Option<PathBuf>,
+    /// The Unix socket's file permissions.
+    unix_socket_mode: u32,
+    /// The TLS configuration, if enabled.
+    tls: Option<tls::Config>,
+    /// The admin superuser password, if authentication is enabled.
+    admin_password: Option<String>,
+    /// The directory that relative IMPORT and EXPORT file paths are resolved
+    /// against.
+    file_dir: Option<PathBuf>,
+    /// The HTTP /readyz readiness criteria.
+    readiness: http::Readiness,
+    /// The SQL client addresses of cluster nodes, by node ID, for clients to
+    /// redirect to the leader.
+    sql_addrs: HashMap<raft::NodeID, String>,
+    /// Triggers a graceful shutdown.
+    shutdown: ShutdownHandle,
+    /// Disconnected when the server is shut down.
+    shutdown_rx: Receiver<()>,
+    /// How long in-flight statements and transactions can run on shutdown.
+    shutdown_grace: Duration,
+    /// Injects faults into the Raft transport, if enabled.
+    fault_injector: Option<FaultInjector>,
+    /// The group commit window, if enabled.
+    group_commit_window: Option<Duration>,
+    /// The hybrid logical clock, for transaction timestamps.
+    clock: HybridClock,
 }
 
 impl Server {
-    /// Creates a new toyDB server.
+    /// The default number of recent MVCC versions retained by garbage
+    /// collection.
+    pub const DEFAULT_GC_RETENTION: u64 = 1_000_000;
+
+    /// The default maximum size of a streamed SELECT row frame in bytes.
+    pub const DEFAULT_FRAME_SIZE: usize = 64 * 1024;
+
+    /// The default Unix socket file permissions.
+    pub const DEFAULT_UNIX_SOCKET_MODE: u32 = 0o600;
+
+    /// The default shutdown grace period.
+    pub const DEFAULT_SHUTDOWN_GRACE: Duration = Duration::from_secs(10);
+
+    /// The default number of entries a node can lag behind the leader's
+    /// commit index while reporting ready via HTTP /readyz.
+    pub const DEFAULT_READY_MAX_LAG: raft::Index = 1000;
+
+    /// Creates a new toyDB server, using the given Raft options, e.g. for
+    /// learners and group commit.
     pub fn new(
         id: raft::NodeID,
         peers: HashMap<raft::NodeID, String>,
         raft_log: raft::Log,
         raft_state: Box<dyn raft::State>,
+        raft_opts: raft::Options,
     ) -> Result<Self> {
         let (node_tx, node_rx) = crossbeam::channel::unbounded();
+        let (shutdown_tx, shutdown_rx) = crossbeam::channel::bounded(0);
         let node = raft::Node::new(
             id,
             peers.keys().copied().collect(),
             raft_log,
             raft_state,
             node_tx,
-            raft::Options::default(),
+            raft_opts,
         )?;
-        Ok(Self { node, peers, node_rx })
+        Ok(Self {
+            node,
+            peers,
+            node_rx,
+            functions: Vec::new(),
+            triggers: Vec::new(),
+            settings: SettingsHandle::default(),
+            result_cache_size: None,
+            plan_cache_size: None,
+            slow_query_log: None,
+            audit_log: None,
+            gc_retention: Self::DEFAULT_GC_RETENTION,
+            frame_size: Self::DEFAULT_FRAME_SIZE,
+            gc_interval: None,
+            consistency_check_interval: None,
+            ttl_purge_interval: None,
+            intent_timeout: None,
+            lock_waits: None,
+            listen_pg: None,
+            listen_http: None,
+            listen_grpc: None,
+            listen_unix: None,
+            unix_socket_mode: Self::DEFAULT_UNIX_SOCKET_MODE,
+            tls: None,
+            admin_password: None,
+            file_dir: None,
+            readiness: http::Readiness { max_lag: Self::DEFAULT_READY_MAX_LAG, data_dir: None },
+            sql_addrs: HashMap::new(),
+            shutdown: ShutdownHandle(Arc::new(Mutex::new(Some(shutdown_tx)))),
+            shutdown_rx,
+            shutdown_grace: Self::DEFAULT_SHUTDOWN_GRACE,
+            fault_injector: None,
+            group_commit_window: None,
+            clock: HybridClock::new(clock::system()),
+        })
+    }
+
+    /// Registers a user-defined scalar SQL function, which can be called by
+    /// all SQL clients of this server. Functions are local to the server, and
+    /// all nodes must register the same functions.
+    pub fn register_function(&mut self, function: Function) {
+        self.functions.push(function);
+    }
+
+    /// Registers a trigger callback, which fires for row writes to its table
+    /// by all SQL clients of this server. Callbacks are local to the server,
+    /// and fire on the node that executes the write statement.
+    pub fn register_trigger(&mut self, callback: TriggerCallback) {
+        self.triggers.push(callback);
+    }
+
+    /// Sets the memory limit for each SQL statement in bytes, or None for no
+    /// limit (the default). Statements that buffer more rows than this, e.g.
+    /// for sorting, hash joins, or aggregation, will error.
+    pub fn set_memory_limit(&mut self, memory_limit: Option<usize>) {
+        self.settings.update(|settings| settings.memory_limit = memory_limit);
+    }
+
+    /// Sets the size of the query result cache in bytes, or None to disable it
+    /// (the default). The cache is shared by all SQL clients of this server,
+    /// and reuses SELECT results while the tables they read are unchanged.
+    pub fn set_result_cache_size(&mut self, result_cache_size: Option<usize>) {
+        self.result_cache_size = result_cache_size;
+    }
+
+    /// Sets the size of the query plan cache in plans, or None to disable it
+    /// (the default). The cache is shared by all SQL clients of this server,
+    /// and reuses plans of statements that only differ in their literal values
+    /// while the schema of the tables they use is unchanged.
+    pub fn set_plan_cache_size(&mut self, plan_cache_size: Option<usize>) {
+        self.plan_cache_size = plan_cache_size;
+    }
+
+    /// Sets the slow query log, or None to disable it (the default). It's
+    /// shared by all SQL clients of this server, and logs statements that take
+    /// at least its threshold to execute.
+    pub fn set_slow_query_log(&mut self, slow_query_log: Option<SlowQueryLog>) {
+        self.slow_query_log = slow_query_log.map(Arc::new);
+    }
+
+    /// Sets the audit log, or None to disable it (the default). It's shared by
+    /// all SQL clients of this server, and logs schema, user, and privilege
+    /// changes, administrative commands, and writes to its tables.
+    pub fn set_audit_log(&mut self, audit_log: Option<AuditLog>) {
+        self.audit_log = audit_log.map(Arc::new);
+    }
+
+    /// Sets the number of recent MVCC versions retained by garbage collection,
+    /// which can be read by time-travel queries. Older versions are removed
+    /// when they're no longer visible to active transactions. Defaults to
+    /// DEFAULT_GC_RETENTION.
+    pub fn set_gc_retention(&mut self, gc_retention: u64) {
+        self.gc_retention = gc_retention;
+    }
+
+    /// Sets the maximum size in bytes of the row frames that SELECT results are
+    /// streamed to clients in, based on the approximate in-memory size of the
+    /// rows. This bounds the rows buffered for each connection, since a frame
+    /// is sent as soon as it's full, and the session blocks until the client
+    /// has received it. Defaults to DEFAULT_FRAME_SIZE.
+    pub fn set_frame_size(&mut self, frame_size: usize) {
+        self.frame_size = frame_size;
+    }
+
+    /// Sets the interval between background garbage collection runs, or None
+    /// to disable it (the default). Clients can also run it via Request::GC.
+    pub fn set_gc_interval(&mut self, gc_interval: Option<Duration>) {
+        self.gc_interval = gc_interval;
+    }
+
+    /// Sets the interval between background replica consistency checks, or
+    /// None to disable them (the default). Checks are only run while the node
+    /// is the Raft leader, and log an error on divergence. Clients can also
+    /// run them via Request::CheckConsistency.
+    pub fn set_consistency_check_interval(&mut self, interval: Option<Duration>) {
+        self.consistency_check_interval = interval;
+    }
+
+    /// Sets the interval between background purges of expired rows from
+    /// tables with a TTL, or None to disable them (the default). Purges are
+    /// only run while the node is the Raft leader.
+    pub fn set_ttl_purge_interval(&mut self, interval: Option<Duration>) {
+        self.ttl_purge_interval = interval;
+    }
+
+    /// Sets how long a read-write transaction can go without a heartbeat
+    /// before it's considered abandoned, or None to disable it (the default).
+    /// See sql::engine::Raft::set_intent_timeout. Expired transactions are
+    /// also rolled back in the background every timeout while the node is the
+    /// Raft leader.
+    pub fn set_intent_timeout(&mut self, intent_timeout: Option<Duration>) {
+        self.intent_timeout = intent_timeout;
+    }
+
+    /// Sets the SQL state machine's lock waiter wakeups, or None to poll lock
+    /// waits instead (the default). See sql::engine::Raft::set_lock_waits.
+    pub fn set_lock_waits(&mut self, lock_waits: Option<Arc<storage::mvcc::LockWaits>>) {
+        self.lock_waits = lock_waits;
+    }
+
+    /// Sets how long an explicit SQL transaction can run before it's rolled
+    /// back, or None for no limit (the default).
+    pub fn set_transaction_timeout(&mut self, transaction_timeout: Option<Duration>) {
+        self.settings.update(|settings| settings.transaction_timeout = transaction_timeout);
+    }
+
+    /// Sets how long an explicit SQL transaction can be idle between
+    /// statements before it's rolled back, or None for no limit (the default).
+    /// This cleans up after clients that go away mid-transaction without
+    /// closing the connection, whose transactions would otherwise block
+    /// conflicting writes and locks indefinitely.
+    pub fn set_idle_timeout(&mut self, idle_timeout: Option<Duration>) {
+        self.settings.update(|settings| settings.idle_timeout = idle_timeout);
+    }
+
+    /// Sets the default for how long a SQL statement can execute before it's
+    /// cancelled with Error::Timeout, or None for no limit (the default).
+    /// Sessions can override it via SET statement_timeout.
+    pub fn set_statement_timeout(&mut self, statement_timeout: Option<Duration>) {
+        self.settings.update(|settings| settings.statement_timeout = statement_timeout);
+    }
+
+    /// Sets the address to listen for Postgres wire protocol clients on, or
+    /// None to disable it (the default). This allows existing Postgres clients
+    /// and drivers to connect, see pgwire for the supported protocol subset.
+    pub fn set_listen_pg(&mut self, listen_pg: Option<String>) {
+        self.listen_pg = listen_pg;
+    }
+
+    /// Sets the address to listen for HTTP/JSON queries on, or None to disable
+    /// it (the default). This allows scripts and dashboards to query toyDB
+    /// over plain HTTP, see http for the endpoints.
+    pub fn set_listen_http(&mut self, listen_http: Option<String>) {
+        self.listen_http = listen_http;
+    }
+
+    /// Sets the address to listen for gRPC clients on, or None to disable it
+    /// (the default). This allows clients in other languages to be generated
+    /// from the protobuf definitions, see grpc for the services.
+    pub fn set_listen_grpc(&mut self, listen_grpc: Option<String>) {
+        self.listen_grpc = listen_grpc;
+    }
+
+    /// Sets the path of a Unix socket to listen for SQL clients on, or None to
+    /// disable it (the default). This gives local clients lower latency than
+    /// TCP, and access can be controlled via the socket's file permissions
+    /// (see set_unix_socket_mode). Unix socket connections don't use TLS.
+    pub fn set_listen_unix(&mut self, listen_unix: Option<PathBuf>) {
+        self.listen_unix = listen_unix;
     }
 
-    /// Serves Raft and SQL requests indefinitely. Consumes the server.
+    /// Sets the maximum number of concurrent SQL client connections via TCP
+    /// and Unix sockets, or None for no limit (the default). Beyond this, new
+    /// connections are rejected with Error::Busy.
+    pub fn set_max_connections(&mut self, max_connections: Option<usize>) {
+        self.settings.update(|settings| settings.max_connections = max_connections);
+    }
+
+    /// Sets the maximum number of concurrent SQL client TCP connections from
+    /// each IP address, or None for no limit (the default). Beyond this, new
+    /// connections are rejected with Error::Busy.
+    pub fn set_max_connections_per_ip(&mut self, max_connections_per_ip: Option<usize>) {
+        self.settings.update(|settings| settings.max_connections_per_ip = max_connections_per_ip);
+    }
+
+    /// Sets the maximum number of statements per second for each SQL client
+    /// session, or None for no limit (the default). Statements beyond this
+    /// (with bursts of up to one second's worth) are rejected with Error::Busy
+    /// without being executed, and can be retried.
+    pub fn set_session_qps(&mut self, session_qps: Option<u32>) {
+        self.settings.update(|settings| settings.session_qps = session_qps);
+    }
+
+    /// Sets the Unix socket's file permissions, e.g. 0o660 to allow the
+    /// server's group to connect. Defaults to 0o600, i.e. only the server's
+    /// user can connect.
+    pub fn set_unix_socket_mode(&mut self, unix_socket_mode: u32) {
+        self.unix_socket_mode = unix_socket_mode;
+    }
+
+    /// Sets the TLS configuration, or None to disable TLS (the default). When
+    /// enabled, all SQL client, HTTP, gRPC, and Raft peer connections must use
+    /// TLS (i.e. HTTPS), and Postgres clients can request it. All nodes must
+    /// enable it.
+    pub fn set_tls(&mut self, tls: Option<tls::Config>) {
+        self.tls = tls;
+    }
+
+    /// Sets the admin superuser password, or None to disable authentication
+    /// (the default). When enabled, SQL clients must authenticate as admin or
+    /// as a user created with CREATE USER, and users can only access tables
+    /// they've been granted privileges on. All nodes should use the same
+    /// password.
+    pub fn set_admin_password(&mut self, admin_password: Option<String>) {
+        self.admin_password = admin_password;
+    }
+
+    /// Sets the directory that relative IMPORT and EXPORT file paths are
+    /// resolved against, typically the data directory. If None (the default),
+    /// they're relative to the server's working directory.
+    pub fn set_file_dir(&mut self, file_dir: Option<PathBuf>) {
+        self.file_dir = file_dir;
+    }
+
+    /// Sets the data directory, which must be writable for the node to report
+    /// ready via HTTP /readyz. If None (the default), it isn't checked.
+    pub fn set_data_dir(&mut self, data_dir: Option<PathBuf>) {
+        self.readiness.data_dir = data_dir;
+    }
+
+    /// Sets how many entries the node's applied index can lag behind the
+    /// leader's commit index while reporting ready via HTTP /readyz. Defaults
+    /// to DEFAULT_READY_MAX_LAG.
+    pub fn set_ready_max_lag(&mut self, max_lag: raft::Index) {
+        self.readiness.max_lag = max_lag;
+    }
+
+    /// Sets the SQL client addresses of cluster nodes, including this one, by
+    /// node ID. Clients fetch them via Request::Topology, to connect directly
+    /// to the Raft leader instead of having their requests forwarded to it, and
+    /// to fail over to other nodes. Nodes without an address are omitted.
+    pub fn set_sql_addrs(&mut self, sql_addrs: HashMap<raft::NodeID, String>) {
+        self.sql_addrs = sql_addrs;
+    }
+
+    /// Sets how long in-flight statements and open transactions are given to
+    /// complete when shutting down, before they're cancelled and rolled back.
+    /// Defaults to DEFAULT_SHUTDOWN_GRACE.
+    pub fn set_shutdown_grace(&mut self, shutdown_grace: Duration) {
+        self.shutdown_grace = shutdown_grace;
+    }
+
+    /// Sets the group commit window, if raft::Options::group_commit_bytes is
+    /// enabled. The leader's batch of writes is proposed at most this long
+    /// after the first write was batched, unless it fills up sooner. If None
+    /// (the default), it's proposed on the next Raft tick.
+    pub fn set_group_commit_window(&mut self, window: Option<Duration>) {
+        self.group_commit_window = window;
+    }
+
+    /// Enables fault injection into the Raft transport via the given fault
+    /// injector, for chaos testing. It should also be used for the storage
+    /// engines (see storage::Faulty). Faults are then changed via the hidden
+    /// Request::InjectFaults admin request.
+    pub fn set_fault_injector(&mut self, fault_injector: Option<FaultInjector>) {
+        self.fault_injector = fault_injector;
+    }
+
+    /// Returns a handle that changes the server's settings while serving, i.e.
+    /// the statement memory limit, transaction and statement timeouts,
+    /// connection limits, and session rate limit (see Settings).
+    pub fn settings_handle(&self) -> SettingsHandle {
+        self.settings.clone()
+    }
+
+    /// Returns a handle that shuts down the server gracefully, see serve().
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    /// Serves Raft and SQL requests until shut down via a ShutdownHandle.
+    /// Consumes the server.
+    ///
+    /// On shutdown, the server stops accepting client connections and closes
+    /// idle client sessions. Sessions with an in-flight statement or an open
+    /// transaction are closed once it completes, or when the shutdown grace
+    /// period expires, in which case the statement is cancelled and the
+    /// transaction rolled back. Postgres and HTTP connections are given the
+    /// grace period to disconnect, and gRPC calls to complete.
+    ///
+    /// Finally, if the node is the Raft leader, it transfers leadership to an
+    /// up-to-date follower, so that clients don't have to wait for an election
+    /// timeout. The Raft node keeps running until the process exits.
     pub fn serve(self, raft_addr: impl ToSocketAddrs, sql_addr: impl ToSocketAddrs) -> Result<()> {
         let raft_listener = TcpListener::bind(raft_addr)?;
         let sql_listener = TcpListener::bind(sql_addr)?;
@@ -71,46 +522,289 @@ impl Server {
             sql_listener.local_addr()?,
             raft_listener.local_addr()?
         );
+        let pg_listener = self.listen_pg.as_ref().map(TcpListener::bind).transpose()?;
+        if let Some(pg_listener) = &pg_listener {
+            info!("Listening on {} (Postgres)", pg_listener.local_addr()?);
+        }
+        let http_listener = self.listen_http.as_ref().map(TcpListener::bind).transpose()?;
+        if let Some(http_listener) = &http_listener {
+            info!("Listening on {} (HTTP)", http_listener.local_addr()?);
+        }
+        let grpc_listener = self.listen_grpc.as_ref().map(TcpListener::bind).transpose()?;
+        if let Some(grpc_listener) = &grpc_listener {
+            info!("Listening on {} (gRPC)", grpc_listener.local_addr()?);
+        }
+        let unix_listener = self
+            .listen_unix
+            .as_deref()
+            .map(|path| Self::bind_unix(path, self.unix_socket_mode))
+            .transpose()?;
+        if let Some(path) = &self.listen_unix {
+            info!("Listening on {} (SQL Unix socket)", path.display());
+        }
+        // The listeners that must be woken up to stop accepting on shutdown.
+        let mut wake_addrs = vec![sql_listener.local_addr()?];
+        for listener in pg_listener.iter().chain(http_listener.iter()) {
+            wake_addrs.push(listener.local_addr()?);
+        }
+
+        // Set up the SQL engine, and register any user-defined functions and
+        // trigger callbacks.
+        let (raft_request_tx, raft_request_rx) = crossbeam::channel::unbounded();
+        let mut sql_engine = sql::engine::Raft::new(raft_request_tx, self.clock.clone());
+        sql_engine.set_intent_timeout(self.intent_timeout);
+        sql_engine.set_lock_waits(self.lock_waits.clone());
+        for function in self.functions {
+            sql_engine.register_function(function)?;
+        }
+        for callback in self.triggers {
+            sql_engine.register_trigger(callback)?;
+        }
+
+        // The Raft node and garbage collection run in detached threads until
+        // the process exits, since Raft is needed while shutting down.
+        let id = self.node.id();
+        let (raft_step_tx, raft_step_rx) = crossbeam::channel::unbounded();
+
+        // Serve inbound Raft connections.
+        let raft_tls = self.tls.as_ref().map(|tls| tls.raft_server.clone());
+        let (faults, clock) = (self.fault_injector.clone(), self.clock.clone());
+        std::thread::spawn(move || {
+            Self::raft_accept(raft_listener, raft_tls, raft_step_tx, faults, clock)
+        });
+
+        // Establish outbound Raft connections to peers.
+        let mut raft_peers_tx = HashMap::new();
+        let has_peers = !self.peers.is_empty();
+        for (id, addr) in self.peers.into_iter() {
+            let (raft_peer_tx, raft_peer_rx) =
+                crossbeam::channel::bounded(RAFT_PEER_CHANNEL_CAPACITY);
+            raft_peers_tx.insert(id, raft_peer_tx);
+            let tls = self.tls.as_ref().map(|tls| tls.raft_client.clone());
+            let (faults, clock) = (self.fault_injector.clone(), self.clock.clone());
+            std::thread::spawn(move || {
+                Self::raft_send_peer(addr, tls, raft_peer_rx, faults, clock)
+            });
+        }
 
-        std::thread::scope(move |s| {
-            let id = self.node.id();
-            let (raft_request_tx, raft_request_rx) = crossbeam::channel::unbounded();
-            let (raft_step_tx, raft_step_rx) = crossbeam::channel::unbounded();
+        // Route Raft messages between the local node, peers, and clients.
+        let group_commit =
+            self.group_commit_window.map(|window| GroupCommit::new(window, clock::system()));
+        std::thread::spawn(move || {
+            Self::raft_route(
+                self.node,
+                self.node_rx,
+                raft_step_rx,
+                raft_peers_tx,
+                raft_request_rx,
+                group_commit,
+            )
+        });
+
+        // Periodically garbage collect old MVCC versions, if enabled.
+        let gc_retention = self.gc_retention;
+        if let Some(gc_interval) = self.gc_interval {
+            let sql_engine = sql_engine.clone();
+            std::thread::spawn(move || Self::gc(sql_engine, gc_interval, gc_retention));
+        }
 
-            // Serve inbound Raft connections.
-            s.spawn(move || Self::raft_accept(raft_listener, raft_step_tx));
+        let (shutdown, grace) = (self.shutdown_rx, self.shutdown_grace);
+        let listen_unix = self.listen_unix;
+        let admission =
+            &Admission { settings: self.settings.clone(), connections: Mutex::default() };
+        std::thread::scope(|s| {
+            // Serve inbound SQL connections.
+            let sessions = SessionConfig {
+                settings: self.settings.clone(),
+                result_cache: self.result_cache_size.map(|size| Arc::new(ResultCache::new(size))),
+                plan_cache: self.plan_cache_size.map(|size| Arc::new(PlanCache::new(size))),
+                slow_query_log: self.slow_query_log,
+                audit_log: self.audit_log,
+                statistics: Arc::new(Statistics::new()),
+                admin_password: self.admin_password,
+                file_dir: self.file_dir,
+            };
+            let requests = RequestConfig {
+                gc_retention,
+                frame_size: self.frame_size,
+                sql_addrs: Arc::new(self.sql_addrs.into_iter().collect()),
+                settings: self.settings,
+                peer_tls: self.tls.as_ref().map(|tls| tls.sql_client.clone()),
+                admin_password: sessions.admin_password.clone(),
+                faults: self.fault_injector,
+                clock: self.clock,
+            };
 
-            // Establish outbound Raft connections to peers.
-            let mut raft_peers_tx = HashMap::new();
-            for (id, addr) in self.peers.into_iter() {
-                let (raft_peer_tx, raft_peer_rx) =
-                    crossbeam::channel::bounded(RAFT_PEER_CHANNEL_CAPACITY);
-                raft_peers_tx.insert(id, raft_peer_tx);
-                s.spawn(move || Self::raft_send_peer(addr, raft_peer_rx));
+            // Periodically check replica consistency while leader, if enabled.
+            // Like garbage collection, this runs until the process exits.
+            if let Some(interval) = self.consistency_check_interval {
+                let (sql_engine, requests) = (sql_engine.clone(), requests.clone());
+                std::thread::spawn(move || {
+                    Self::consistency_check(id, sql_engine, requests, interval)
+                });
             }
 
-            // Route Raft messages between the local node, peers, and clients.
+            // Periodically purge expired TTL rows while leader, if enabled.
+            if let Some(interval) = self.ttl_purge_interval {
+                let sql_engine = sql_engine.clone();
+                std::thread::spawn(move || Self::purge_expired(id, sql_engine, interval));
+            }
+
+            // Periodically roll back abandoned transactions while leader, if
+            // enabled.
+            if let Some(timeout) = self.intent_timeout {
+                let sql_engine = sql_engine.clone();
+                std::thread::spawn(move || Self::resolve_expired(id, sql_engine, timeout));
+            }
+
+            let sql_tls = self.tls.map(|tls| tls.sql);
+            let signal = ShutdownSignal { rx: &shutdown, grace };
+            if let Some(pg_listener) = pg_listener {
+                let (sql_engine, sessions, tls) =
+                    (sql_engine.clone(), sessions.clone(), sql_tls.clone());
+                s.spawn(move || Self::pg_accept(pg_listener, tls, sql_engine, sessions, signal));
+            }
+            if let Some(http_listener) = http_listener {
+                let (sql_engine, sessions, tls) =
+                    (sql_engine.clone(), sessions.clone(), sql_tls.clone());
+                let readiness = self.readiness;
+                s.spawn(move || {
+                    Self::http_accept(http_listener, tls, sql_engine, sessions, readiness, signal)
+                });
+            }
+            if let Some(grpc_listener) = grpc_listener {
+                let (sql_engine, sessions, tls) =
+                    (sql_engine.clone(), sessions.clone(), sql_tls.clone());
+                let shutdown = shutdown.clone();
+                s.spawn(move || {
+                    let result = grpc::serve(
+                        grpc_listener,
+                        tls,
+                        id,
+                        sql_engine,
+                        move |sql_engine| sessions.session(sql_engine),
+                        gc_retention,
+                        shutdown,
+                        grace,
+                    );
+                    if let Err(err) = result {
+                        error!("gRPC server failed: {err}");
+                    }
+                });
+            }
+            if let Some(unix_listener) = unix_listener {
+                let (sql_engine, sessions, requests) =
+                    (sql_engine.clone(), sessions.clone(), requests.clone());
+                s.spawn(move || {
+                    let listener = unix_listener;
+                    Self::sql_accept_unix(
+                        id, listener, sql_engine, sessions, requests, admission, signal,
+                    )
+                });
+            }
+            let sql_engine = sql_engine.clone();
             s.spawn(move || {
-                Self::raft_route(
-                    self.node,
-                    self.node_rx,
-                    raft_step_rx,
-                    raft_peers_tx,
-                    raft_request_rx,
+                let (listener, tls) = (sql_listener, sql_tls);
+                Self::sql_accept(
+                    id, listener, tls, sql_engine, sessions, requests, admission, signal,
                 )
             });
 
-            // Serve inbound SQL connections.
-            let sql_engine = sql::engine::Raft::new(raft_request_tx);
-            s.spawn(move || Self::sql_accept(id, sql_listener, sql_engine));
+            // Wait for shutdown, and wake up the accept loops by connecting to
+            // their listeners. The scope then waits for clients to drain.
+            _ = shutdown.recv();
+            info!("Shutting down, draining clients for up to {grace:?}");
+            for addr in wake_addrs {
+                Self::wake(addr);
+            }
+            if let Some(path) = &listen_unix {
+                if let Err(err) = UnixStream::connect(path) {
+                    error!("Failed waking up listener {}: {err}", path.display());
+                }
+            }
         });
+        if let Some(path) = &listen_unix {
+            std::fs::remove_file(path).ok();
+        }
 
+        // Transfer leadership away from this node, if it's the leader.
+        if has_peers {
+            Self::transfer_leadership(id, &sql_engine);
+        }
+        info!("Shut down");
         Ok(())
     }
 
+    /// Binds a Unix socket listener at the given path, with the given file
+    /// permissions. Removes a stale socket file left behind by a previous
+    /// server, but errors if another server is listening on it.
+    fn bind_unix(path: &Path, mode: u32) -> Result<UnixListener> {
+        if path.exists() {
+            if UnixStream::connect(path).is_ok() {
+                return errinput!("Unix socket {} is already in use", path.display());
+            }
+            std::fs::remove_file(path)?;
+        }
+        let listener = UnixListener::bind(path)?;
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))?;
+        Ok(listener)
+    }
+
+    /// Wakes up a listener blocked in accept() by connecting to it.
+    fn wake(mut addr: SocketAddr) {
+        if addr.ip().is_unspecified() {
+            addr.set_ip(match addr {
+                SocketAddr::V4(_) => Ipv4Addr::LOCALHOST.into(),
+                SocketAddr::V6(_) => Ipv6Addr::LOCALHOST.into(),
+            });
+        }
+        if let Err(err) = TcpStream::connect(addr) {
+            error!("Failed waking up listener {addr}: {err}");
+        }
+    }
+
+    /// Transfers Raft leadership to an up-to-date follower if the local node
+    /// is the leader, and waits for the follower to take over. Retries until
+    /// LEADERSHIP_TRANSFER_TIMEOUT, e.g. while followers catch up, and logs
+    /// errors.
+    fn transfer_leadership(id: raft::NodeID, sql_engine: &sql::engine::Raft) {
+        let deadline = Instant::now() + LEADERSHIP_TRANSFER_TIMEOUT;
+        let mut transferred = None;
+        while Instant::now() < deadline {
+            match sql_engine.status().map(|status| status.raft.leader) {
+                Ok(leader) if leader != id && transferred.is_some() => {
+                    info!("Transferred Raft leadership to {leader}");
+                    return;
+                }
+                Ok(leader) if leader != id => return, // not leader
+                Ok(_) if transferred.is_none() => match sql_engine.transfer_leadership() {
+                    Ok(to) => transferred = Some(to),
+                    Err(Error::Abort) => {} // no follower caught up yet
+                    Err(err) => {
+                        error!("Raft leadership transfer failed: {err}");
+                        return;
+                    }
+                },
+                Ok(_) | Err(Error::Abort | Error::NotLeader(_)) => {} // transfer or election in progress
+                Err(err) => {
+                    error!("Raft leadership transfer failed: {err}");
+                    return;
+                }
+            }
+            std::thread::sleep(SHUTDOWN_POLL_INTERVAL);
+        }
+        error!("Raft leadership transfer timed out");
+    }
+
     /// Accepts new inbound Raft connections from peers and spawns threads
     /// routing inbound messages to the local Raft node.
-    fn raft_accept(listener: TcpListener, raft_step_tx: Sender<raft::Envelope>) {
+    fn raft_accept(
+        listener: TcpListener,
+        tls: Option<Arc<rustls::ServerConfig>>,
+        raft_step_tx: Sender<raft::Envelope>,
+        faults: Option<FaultInjector>,
+        clock: HybridClock,
+    ) {
         std::thread::scope(|s| {
             loop {
                 let (socket, peer) = match listener.accept() {
@@ -121,9 +815,13 @@ impl Server {
                     }
                 };
                 let raft_step_tx = raft_step_tx.clone();
+                let (tls, faults, clock) = (tls.as_ref(), faults.as_ref(), &clock);
                 s.spawn(move || {
                     debug!("Raft peer {peer} connected");
-                    match Self::raft_receive_peer(socket, raft_step_tx) {
+                    let result = Stream::accept(socket, tls).and_then(|stream| {
+                        Self::raft_receive_peer(stream, raft_step_tx, faults, clock)
+                    });
+                    match result {
                         Ok(()) => debug!("Raft peer {peer} disconnected"),
                         Err(err) => error!("Raft peer {peer} error: {err}"),
                     }
@@ -133,20 +831,47 @@ impl Server {
     }
 
     /// Receives inbound messages from a peer via TCP, and queues them for
-    /// stepping into the Raft node.
-    fn raft_receive_peer(socket: TcpStream, raft_step_tx: Sender<raft::Envelope>) -> Result<()> {
+    /// stepping into the Raft node. Messages from partitioned peers are
+    /// dropped, if faults are injected. The peer's clock timestamp is merged
+    /// into the local clock.
+    fn raft_receive_peer(
+        socket: Stream,
+        raft_step_tx: Sender<raft::Envelope>,
+        faults: Option<&FaultInjector>,
+        clock: &HybridClock,
+    ) -> Result<()> {
         let mut socket = BufReader::new(socket);
-        while let Some(message) = raft::Envelope::maybe_decode_from(&mut socket)? {
+        while let Some((timestamp, message)) =
+            <(Timestamp, raft::Envelope)>::maybe_decode_from(&mut socket)?
+        {
+            if faults.is_some_and(|faults| !faults.receive(message.from)) {
+                continue;
+            }
+            clock.update(timestamp);
             raft_step_tx.send(message)?;
         }
         Ok(())
     }
 
-    /// Sends outbound messages to a peer via TCP. Retries indefinitely if the
-    /// connection fails.
-    fn raft_send_peer(addr: String, raft_node_rx: Receiver<raft::Envelope>) {
+    /// Sends outbound messages to a peer via TCP, using TLS if given a config,
+    /// along with a timestamp from the local clock. Retries indefinitely if the
+    /// connection fails. Messages are dropped, duplicated, or delayed if faults
+    /// are injected.
+    fn raft_send_peer(
+        addr: String,
+        tls: Option<Arc<rustls::ClientConfig>>,
+        raft_node_rx: Receiver<raft::Envelope>,
+        faults: Option<FaultInjector>,
+        clock: HybridClock,
+    ) {
+        // The peer's certificate must be valid for the host name.
+        let host = addr.rsplit_once(':').map_or(addr.as_str(), |(host, _)| host);
+        let host = host.trim_start_matches('[').trim_end_matches(']');
         loop {
-            let mut socket = match TcpStream::connect(&addr) {
+            let result = TcpStream::connect(&addr)
+                .map_err(Into::into)
+                .and_then(|socket| Stream::connect(socket, tls.as_ref(), host));
+            let mut socket = match result {
                 Ok(socket) => BufWriter::new(socket),
                 Err(err) => {
                     error!("Failed connecting to Raft peer {addr}: {err}");
@@ -154,11 +879,19 @@ impl Server {
                     continue;
                 }
             };
-            while let Ok(message) = raft_node_rx.recv() {
-                if let Err(err) = message.encode_into(&mut socket).and_then(|_| Ok(socket.flush()?))
-                {
-                    error!("Failed sending to Raft peer {addr}: {err}");
-                    break;
+            'send: while let Ok(message) = raft_node_rx.recv() {
+                let mut count = 1;
+                if let Some(faults) = &faults {
+                    std::thread::sleep(faults.send_delay());
+                    count = faults.send_count(message.to);
+                }
+                let frame = (clock.now(), message);
+                for _ in 0..count {
+                    let result = frame.encode_into(&mut socket).and_then(|_| Ok(socket.flush()?));
+                    if let Err(err) = result {
+                        error!("Failed sending to Raft peer {addr}: {err}");
+                        break 'send;
+                    }
                 }
             }
             debug!("Disconnected from Raft peer {addr}");
@@ -180,6 +913,9 @@ impl Server {
     /// * peers_tx: outbound per-peer channels sent via TCP connections.
     ///   Messages from the local node's node_rx are sent here.
     ///
+    /// If group_commit is given, batched writes are proposed once its window
+    /// has passed since the node started batching them.
+    ///
     /// Panics on any errors, since the Raft node can't recover from failed
     /// state transitions.
     fn raft_route(
@@ -188,17 +924,24 @@ impl Server {
         peers_rx: Receiver<raft::Envelope>,
         mut peers_tx: HashMap<raft::NodeID, Sender<raft::Envelope>>,
         request_rx: Receiver<(raft::Request, Sender<Result<raft::Response>>)>,
+        mut group_commit: Option<GroupCommit>,
     ) {
         // Track response channels by request ID. The Raft node will emit
         // ClientResponse messages that we forward to the response channel.
         let mut response_txs = HashMap::<raft::RequestID, Sender<Result<raft::Response>>>::new();
 
         let ticker = crossbeam::channel::tick(raft::TICK_INTERVAL);
+        let never = crossbeam::channel::never();
         loop {
+            logging::set_term(node.term());
+            let group_commit_timer = group_commit.as_ref().and_then(|gc| gc.timer());
             crossbeam::select! {
                 // Periodically tick the node.
                 recv(ticker) -> _ => node = node.tick().expect("tick failed"),
 
+                // Wake up when the group commit window may have expired.
+                recv(group_commit_timer.as_ref().unwrap_or(&never)) -> _ => {},
+
                 // Step messages from peers into the node.
                 recv(peers_rx) -> result => {
                     let msg = result.expect("peers_rx disconnected");
@@ -244,11 +987,116 @@ impl Server {
                     response_txs.insert(id, response_tx);
                 }
             }
+
+            // Propose batched writes when the group commit window expires.
+            // The window is started when the node begins a batch, either with
+            // local client writes or writes forwarded by followers.
+            if let Some(group_commit) = group_commit.as_mut() {
+                if group_commit.expired() {
+                    node = node.flush_batch().expect("flush failed");
+                }
+                group_commit.update(node.has_batch());
+            }
+        }
+    }
+
+    /// Garbage collects old MVCC versions at the given interval, indefinitely.
+    /// Each run is replicated through Raft, and errors are logged.
+    fn gc(sql_engine: sql::engine::Raft, interval: Duration, retention: u64) {
+        loop {
+            std::thread::sleep(interval);
+            match sql_engine.gc(retention) {
+                Ok(gc) => debug!("Garbage collected {} versions below {}", gc.versions, gc.horizon),
+                Err(err) => error!("Garbage collection failed: {err}"),
+            }
+        }
+    }
+
+    /// Periodically checks replica consistency while the local node is the
+    /// Raft leader. Divergence is logged by check_consistency().
+    fn consistency_check(
+        id: raft::NodeID,
+        sql_engine: sql::engine::Raft,
+        config: RequestConfig,
+        interval: Duration,
+    ) {
+        let session = sql_engine.session();
+        loop {
+            std::thread::sleep(interval);
+            match session.node_status() {
+                Ok((status, _)) if status.leader == Some(id) => {}
+                Ok(_) => continue,
+                Err(err) => {
+                    error!("Consistency check failed: {err}");
+                    continue;
+                }
+            }
+            match Self::check_consistency(id, &session, &config) {
+                Ok(check) if check.is_consistent() => {
+                    debug!("Consistency check passed at index {}", check.index)
+                }
+                Ok(_) => {}
+                Err(err) => error!("Consistency check failed: {err}"),
+            }
+        }
+    }
+
+    /// Periodically purges expired rows from tables with a TTL while the local
+    /// node is the Raft leader. The deletes are regular SQL transactions, so
+    /// this only avoids every node purging the same rows concurrently.
+    fn purge_expired(id: raft::NodeID, sql_engine: sql::engine::Raft, interval: Duration) {
+        let mut session = sql_engine.session();
+        loop {
+            std::thread::sleep(interval);
+            match session.node_status() {
+                Ok((status, _)) if status.leader == Some(id) => {}
+                Ok(_) => continue,
+                Err(err) => {
+                    error!("TTL purge failed: {err}");
+                    continue;
+                }
+            }
+            match session.purge_expired() {
+                Ok(0) => {}
+                Ok(count) => debug!("Purged {count} expired TTL rows"),
+                Err(err) => error!("TTL purge failed: {err}"),
+            }
+        }
+    }
+
+    /// Periodically rolls back expired transactions while the node is the Raft
+    /// leader (see sql::engine::Raft::resolve_expired).
+    fn resolve_expired(id: raft::NodeID, sql_engine: sql::engine::Raft, interval: Duration) {
+        loop {
+            std::thread::sleep(interval);
+            match sql_engine.node_status() {
+                Ok((status, _)) if status.leader == Some(id) => {}
+                Ok(_) => continue,
+                Err(err) => {
+                    error!("Intent resolution failed: {err}");
+                    continue;
+                }
+            }
+            match sql_engine.resolve_expired() {
+                Ok(versions) if versions.is_empty() => {}
+                Ok(versions) => info!("Rolled back expired transactions {versions:?}"),
+                Err(err) => error!("Intent resolution failed: {err}"),
+            }
         }
     }
 
     /// Accepts new SQL client connections and spawns session threads for them.
-    fn sql_accept(id: raft::NodeID, listener: TcpListener, sql_engine: sql::engine::Raft) {
+    #[allow(clippy::too_many_arguments)]
+    fn sql_accept(
+        id: raft::NodeID,
+        listener: TcpListener,
+        tls: Option<Arc<rustls::ServerConfig>>,
+        sql_engine: sql::engine::Raft,
+        sessions: SessionConfig,
+        requests: RequestConfig,
+        admission: &Admission,
+        signal: ShutdownSignal,
+    ) {
         std::thread::scope(|s| {
             loop {
                 let (socket, peer) = match listener.accept() {
@@ -258,10 +1106,27 @@ impl Server {
                         continue;
                     }
                 };
-                let session = sql_engine.session();
+                if signal.is_shutdown() {
+                    break;
+                }
+                let permit = admission.admit(Some(peer.ip()));
+                let session = sessions.session(&sql_engine);
+                let (tls, requests) = (tls.as_ref(), requests.clone());
                 s.spawn(move || {
+                    let _session = logging::with_session(session.id());
                     debug!("Client {peer} connected");
-                    match Self::sql_session(id, socket, session) {
+                    let result = match permit {
+                        Ok(_permit) => Stream::accept(socket, tls).and_then(|stream| {
+                            Self::sql_session(id, stream, session, requests, signal)
+                        }),
+                        Err(error) => {
+                            debug!("Rejecting client {peer}: {error}");
+                            socket.set_read_timeout(Some(REJECT_TIMEOUT)).ok();
+                            Stream::accept(socket, tls)
+                                .and_then(|stream| Self::sql_reject(stream, error))
+                        }
+                    };
+                    match result {
                         Ok(()) => debug!("Client {peer} disconnected"),
                         Err(err) => error!("Client {peer} error: {err}"),
                     }
@@ -270,55 +1135,1077 @@ impl Server {
         })
     }
 
+    /// Accepts new local SQL client connections via a Unix socket, and spawns
+    /// session threads for them. These don't use TLS.
+    fn sql_accept_unix(
+        id: raft::NodeID,
+        listener: UnixListener,
+        sql_engine: sql::engine::Raft,
+        sessions: SessionConfig,
+        requests: RequestConfig,
+        admission: &Admission,
+        signal: ShutdownSignal,
+    ) {
+        std::thread::scope(|s| {
+            loop {
+                let socket = match listener.accept() {
+                    Ok((socket, _)) => socket,
+                    Err(err) => {
+                        error!("Unix socket client accept failed: {err}");
+                        continue;
+                    }
+                };
+                if signal.is_shutdown() {
+                    break;
+                }
+                let permit = admission.admit(None);
+                let session = sessions.session(&sql_engine);
+                let requests = requests.clone();
+                s.spawn(move || {
+                    let _session = logging::with_session(session.id());
+                    debug!("Unix socket client connected");
+                    let result = match permit {
+                        Ok(_permit) => {
+                            Self::sql_session(id, Stream::Unix(socket), session, requests, signal)
+                        }
+                        Err(error) => {
+                            debug!("Rejecting Unix socket client: {error}");
+                            socket.set_read_timeout(Some(REJECT_TIMEOUT)).ok();
+                            Self::sql_reject(Stream::Unix(socket), error)
+                        }
+                    };
+                    match result {
+                        Ok(()) => debug!("Unix socket client disconnected"),
+                        Err(err) => error!("Unix socket client error: {err}"),
+                    }
+                });
+            }
+        })
+    }
+
+    /// Accepts new Postgres client connections and spawns session threads for
+    /// them. Sessions are configured like regular SQL sessions.
+    fn pg_accept(
+        listener: TcpListener,
+        tls: Option<Arc<rustls::ServerConfig>>,
+        sql_engine: sql::engine::Raft,
+        sessions: SessionConfig,
+        signal: ShutdownSignal,
+    ) {
+        let connections = Connections::default();
+        std::thread::scope(|s| {
+            loop {
+                let (socket, peer) = match listener.accept() {
+                    Ok((socket, peer)) => (socket, peer),
+                    Err(err) => {
+                        error!("Postgres client accept failed: {err}");
+                        continue;
+                    }
+                };
+                if signal.is_shutdown() {
+                    break;
+                }
+                let session = sessions.session(&sql_engine);
+                let (tls, connections) = (tls.clone(), &connections);
+                s.spawn(move || {
+                    let _session = logging::with_session(session.id());
+                    debug!("Postgres client {peer} connected");
+                    let result = connections
+                        .add(peer, &socket)
+                        .and_then(|()| pgwire::serve(socket, tls, session));
+                    connections.remove(peer);
+                    match result {
+                        Ok(()) => debug!("Postgres client {peer} disconnected"),
+                        Err(err) => error!("Postgres client {peer} error: {err}"),
+                    }
+                });
+            }
+            connections.drain(signal.grace);
+        })
+    }
+
+    /// Accepts new HTTP client connections and spawns threads serving them.
+    /// Each request is executed in a new session, configured like regular SQL
+    /// sessions.
+    fn http_accept(
+        listener: TcpListener,
+        tls: Option<Arc<rustls::ServerConfig>>,
+        sql_engine: sql::engine::Raft,
+        sessions: SessionConfig,
+        readiness: http::Readiness,
+        signal: ShutdownSignal,
+    ) {
+        let connections = Connections::default();
+        std::thread::scope(|s| {
+            loop {
+                let (socket, peer) = match listener.accept() {
+                    Ok((socket, peer)) => (socket, peer),
+                    Err(err) => {
+                        error!("HTTP client accept failed: {err}");
+                        continue;
+                    }
+                };
+                if signal.is_shutdown() {
+                    break;
+                }
+                let (sql_engine, sessions, tls) = (&sql_engine, &sessions, tls.as_ref());
+                let (connections, readiness) = (&connections, &readiness);
+                s.spawn(move || {
+                    debug!("HTTP client {peer} connected");
+                    let result = connections
+                        .add(peer, &socket)
+                        .and_then(|()| Stream::accept(socket, tls))
+                        .and_then(|stream| {
+                            http::serve(stream, readiness, || sessions.session(sql_engine))
+                        });
+                    connections.remove(peer);
+                    match result {
+                        Ok(()) => debug!("HTTP client {peer} disconnected"),
+                        Err(err) => error!("HTTP client {peer} error: {err}"),
+                    }
+                });
+            }
+            connections.drain(signal.grace);
+        })
+    }
+
     /// Processes a client SQL session, executing SQL statements against the
     /// Raft node.
+    ///
+    /// Requests are received in a separate thread, such that the client can
+    /// cancel an in-flight statement with Request::Cancel while it's executing.
+    /// While waiting for requests, the session's transaction timeouts are
+    /// enforced, rolling back the transaction if the client has gone idle.
+    ///
+    /// On server shutdown, the session is closed once it's idle outside of a
+    /// transaction. If the grace period expires first, the in-flight statement
+    /// is cancelled and the session closed, rolling back its transaction.
     fn sql_session(
         id: raft::NodeID,
-        socket: TcpStream,
+        socket: Stream,
         mut session: sql::engine::Session<sql::engine::Raft>,
+        requests: RequestConfig,
+        signal: ShutdownSignal,
     ) -> Result<()> {
-        let mut reader = BufReader::new(socket.try_clone()?);
-        let mut writer = BufWriter::new(socket);
+        let reader = BufReader::new(socket.try_clone()?);
+        let mut writer = BufWriter::new(socket.try_clone()?);
+        let (request_tx, request_rx) = crossbeam::channel::unbounded();
+        let (done_tx, done_rx) = crossbeam::channel::bounded::<()>(0);
+        let cancel = Mutex::new(CancelToken::new());
+        let mut limiter = requests.settings.get().session_qps.map(RateLimiter::new);
+        let mut copy_data = Vec::new();
 
-        while let Some(request) = Request::maybe_decode_from(&mut reader)? {
-            // Execute request.
-            debug!("Received request {request:?}");
-            let response = match request {
-                Request::Execute(query) => session.execute(&query).map(Response::Execute),
-                Request::GetTable(table) => {
-                    session.with_txn(true, |txn| txn.must_get_table(&table)).map(Response::GetTable)
+        std::thread::scope(|s| {
+            let receiver = s.spawn(|| Self::sql_receive(reader, request_tx, &cancel));
+
+            // On shutdown, cancel the in-flight statement (if any) once the
+            // grace period expires, unless the session closes first.
+            s.spawn(|| {
+                crossbeam::select! {
+                    recv(signal.rx) -> _ => {},
+                    recv(done_rx) -> _ => return,
+                }
+                if done_rx.recv_timeout(signal.grace) == Err(RecvTimeoutError::Timeout) {
+                    debug!("Cancelling in-flight request on shutdown");
+                    cancel.lock().expect("lock poisoned").cancel();
+                }
+            });
+
+            let never = crossbeam::channel::never();
+            let mut drain_deadline = None;
+            let result = loop {
+                if let Some(deadline) = drain_deadline {
+                    let idle = !session.in_transaction() && request_rx.is_empty();
+                    if idle || Instant::now() >= deadline {
+                        debug!("Closing session on shutdown");
+                        break Ok(());
+                    }
+                }
+                let deadline = session.timeout_deadline().into_iter().chain(drain_deadline).min();
+                let timeout = deadline.map_or(crossbeam::channel::never(), crossbeam::channel::at);
+                let shutdown = if drain_deadline.is_none() { signal.rx } else { &never };
+                let (request, cancel) = crossbeam::select! {
+                    recv(request_rx) -> received => match received {
+                        Ok(received) => received,
+                        Err(_) => break Ok(()),
+                    },
+                    recv(shutdown) -> _ => {
+                        drain_deadline = Some(Instant::now() + signal.grace);
+                        continue;
+                    },
+                    recv(timeout) -> _ => match session.check_timeout() {
+                        Ok(()) => continue,
+                        Err(error) => break Err(error),
+                    },
+                };
+                // Buffer COPY data until the Request::Copy that imports it.
+                if let Request::CopyData(data) = request {
+                    copy_data.extend(data);
+                    continue;
+                }
+                // Reject statements beyond the session's rate limit.
+                let statement = matches!(
+                    request,
+                    Request::Execute(_)
+                        | Request::ExecuteBatch(_)
+                        | Request::ExecuteIdempotent { .. }
+                        | Request::Copy(_)
+                );
+                if statement && !limiter.as_mut().is_none_or(|limiter| limiter.try_acquire()) {
+                    copy_data.clear();
+                    let error = Error::Busy("session statement rate limit exceeded".into());
+                    let result = Result::<Response>::Err(error).encode_into(&mut writer);
+                    if let Err(error) = result.and_then(|()| Ok(writer.flush()?)) {
+                        break Err(error);
+                    }
+                    continue;
+                }
+                if let Err(error) = Self::sql_request(
+                    id,
+                    &mut session,
+                    &requests,
+                    request,
+                    &mut copy_data,
+                    &cancel,
+                    &mut writer,
+                ) {
+                    break Err(error);
                 }
-                Request::ListTables => session
-                    .with_txn(true, |txn| {
-                        Ok(txn.list_tables()?.into_iter().map(|t| t.name).collect())
-                    })
-                    .map(Response::ListTables),
-                Request::Status => session
-                    .status()
-                    .map(|s| Status { server: id, raft: s.raft, mvcc: s.mvcc })
-                    .map(Response::Status),
             };
+            drop(done_tx);
 
-            // Process response.
-            debug!("Returning response {response:?}");
-            response.encode_into(&mut writer)?;
+            // Shut down the socket to stop the receiver, in case we errored or
+            // shut down. The socket may already be closed, so ignore errors.
+            socket.shutdown(Shutdown::Both).ok();
+            let received = receiver.join().expect("receiver panicked");
+            result.and(received)
+        })
+    }
+
+    /// Rejects a client connection that wasn't admitted, by responding to its
+    /// first request (typically Request::Handshake) with the given error.
+    fn sql_reject(socket: Stream, error: Error) -> Result<()> {
+        let mut reader = BufReader::new(socket.try_clone()?);
+        let mut writer = BufWriter::new(socket.try_clone()?);
+        if Request::maybe_decode_from(&mut reader)?.is_some() {
+            Result::<Response>::Err(error).encode_into(&mut writer)?;
             writer.flush()?;
         }
+        socket.shutdown(Shutdown::Both).ok();
+        Ok(())
+    }
+
+    /// Receives client requests and passes them to the session along with a
+    /// cancellation token, which is also stored in the given mutex.
+    /// Request::Cancel cancels the token of the most recent request, if it's
+    /// still executing.
+    fn sql_receive(
+        mut reader: BufReader<Stream>,
+        request_tx: Sender<(Request, CancelToken)>,
+        cancel: &Mutex<CancelToken>,
+    ) -> Result<()> {
+        while let Some(request) = Request::maybe_decode_from(&mut reader)? {
+            match request {
+                Request::Cancel => {
+                    debug!("Cancelling in-flight request");
+                    cancel.lock()?.cancel();
+                }
+                request => {
+                    let token = CancelToken::new();
+                    *cancel.lock()? = token.clone();
+                    if request_tx.send((request, token)).is_err() {
+                        break; // the session has shut down
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Executes a client request and sends the response.
+    fn sql_request(
+        id: raft::NodeID,
+        session: &mut sql::engine::Session<sql::engine::Raft>,
+        config: &RequestConfig,
+        request: Request,
+        copy_data: &mut Vec<u8>,
+        cancel: &CancelToken,
+        writer: &mut BufWriter<Stream>,
+    ) -> Result<()> {
+        // Execute request. Statements check privileges when executed, other
+        // requests are checked here.
+        debug!("Received request {request:?}");
+        let authorized = match &request {
+            Request::Authenticate { .. }
+            | Request::Execute(_)
+            | Request::ExecuteBatch(_)
+            | Request::ExecuteIdempotent { .. }
+            | Request::Copy(_)
+            | Request::Topology
+            | Request::Handshake { .. } => Ok(()),
+            Request::GetTable(_) | Request::ListTables | Request::Status | Request::Clock(_) => {
+                session.check_authenticated()
+            }
+            Request::Changes { table, .. } => {
+                session.check_privilege(Some(table), Privilege::Select)
+            }
+            Request::GC
+            | Request::Backup
+            | Request::BackupIncremental { .. }
+            | Request::NodeStatus
+            | Request::ClusterStatus
+            | Request::TransferLeadership
+            | Request::RemoveNode(_)
+            | Request::Scrub { .. }
+            | Request::CheckConsistency
+            | Request::Checksum(_)
+            | Request::InjectFaults(_) => session.check_superuser(),
+            Request::Cancel => panic!("unexpected cancel request"),
+            Request::CopyData(_) => panic!("unexpected copy data request"),
+        };
+        let command = match &request {
+            Request::GC => Some("GC".to_string()),
+            Request::Backup => Some("BACKUP".to_string()),
+            Request::BackupIncremental { since } => Some(format!("BACKUP SINCE {since}")),
+            Request::TransferLeadership => Some("TRANSFER LEADERSHIP".to_string()),
+            Request::RemoveNode(node) => Some(format!("REMOVE NODE {node}")),
+            Request::Scrub { repair: false } => Some("SCRUB".to_string()),
+            Request::Scrub { repair: true } => Some("SCRUB REPAIR".to_string()),
+            Request::CheckConsistency => Some("CHECK CONSISTENCY".to_string()),
+            Request::InjectFaults(faults) => Some(format!("INJECT FAULTS {faults:?}")),
+            _ => None,
+        };
+        let response = authorized.and_then(|()| match request {
+            Request::Authenticate { user, password } => {
+                session.authenticate(&user, &password).map(|_| Response::Authenticate)
+            }
+            // SELECT results are streamed as Response::Rows frames following
+            // the initial Response::Execute, terminated by an empty frame.
+            // Each frame is sent as soon as it reaches the frame size.
+            Request::Execute(query) => session
+                .execute_stream(&query, cancel, |columns, rows| {
+                    let columns = columns.to_vec();
+                    let result = StatementResult::Select { columns, rows: Vec::new() };
+                    Ok(Response::Execute(result)).encode_into(&mut *writer)?;
+                    let (mut frame, mut size) = (Vec::new(), 0);
+                    for row in rows {
+                        let row = row?;
+                        size += row.iter().map(|value| value.size()).sum::<usize>();
+                        frame.push(row);
+                        if size >= config.frame_size {
+                            Ok(Response::Rows(std::mem::take(&mut frame)))
+                                .encode_into(&mut *writer)?;
+                            writer.flush()?;
+                            size = 0;
+                        }
+                    }
+                    if !frame.is_empty() {
+                        Ok(Response::Rows(frame)).encode_into(&mut *writer)?;
+                    }
+                    Ok(())
+                })
+                .map(|result| match result {
+                    StatementResult::Select { .. } => Response::Rows(Vec::new()),
+                    result => Response::Execute(result),
+                }),
+            Request::ExecuteBatch(statements) => {
+                let mut results = Vec::with_capacity(statements.len());
+                for statement in statements {
+                    let mut buffer = Vec::new();
+                    let result = session
+                        .execute_stream(&statement, cancel, |_, rows| {
+                            buffer = rows.try_collect()?;
+                            Ok(())
+                        })
+                        .map(|mut result| {
+                            if let StatementResult::Select { rows, .. } = &mut result {
+                                *rows = buffer;
+                            }
+                            result
+                        });
+                    let failed = result.is_err();
+                    results.push(result);
+                    if failed {
+                        break;
+                    }
+                }
+                Ok(Response::ExecuteBatch(results))
+            }
+            Request::ExecuteIdempotent { statement, token } => session
+                .execute_idempotent(&statement, &token, cancel)
+                .map(Response::Execute),
+            Request::Copy(statement) => session
+                .execute_copy(&statement, std::mem::take(copy_data), cancel)
+                .map(Response::Execute),
+            Request::GetTable(table) => {
+                session.with_txn(true, |txn| txn.must_get_table(&table)).map(Response::GetTable)
+            }
+            Request::ListTables => session
+                .with_txn(true, |txn| Ok(txn.list_tables()?.into_iter().map(|t| t.name).collect()))
+                .map(Response::ListTables),
+            Request::Status => session
+                .status()
+                .map(|s| Status { server: id, raft: s.raft, mvcc: s.mvcc })
+                .map(Response::Status),
+            Request::Topology => session
+                .status()
+                .map(|s| Topology {
+                    server: id,
+                    leader: s.raft.leader,
+                    term: s.raft.term,
+                    nodes: config.sql_addrs.as_ref().clone(),
+                })
+                .map(Response::Topology),
+            Request::NodeStatus => session
+                .node_status()
+                .map(|(raft, mvcc)| NodeStatus { raft, mvcc })
+                .map(Response::NodeStatus),
+            Request::ClusterStatus => {
+                Self::cluster_status(id, session, config).map(Response::ClusterStatus)
+            }
+            Request::TransferLeadership => {
+                session.transfer_leadership().map(Response::TransferLeadership)
+            }
+            Request::RemoveNode(node) => session.remove_node(node).map(Response::RemoveNode),
+            Request::Scrub { repair } => session.scrub(repair).map(Response::Scrub),
+            Request::CheckConsistency => {
+                Self::check_consistency(id, session, config).map(Response::CheckConsistency)
+            }
+            Request::Checksum(index) => session.local_checksum(index).map(Response::Checksum),
+            Request::Clock(timestamp) => Ok(Response::Clock(config.clock.update(timestamp))),
+            Request::InjectFaults(faults) => match &config.faults {
+                Some(injector) => {
+                    let previous = injector.get();
+                    injector.set(faults).map(|()| Response::InjectFaults(previous))
+                }
+                None => errinput!("fault injection is not enabled"),
+            },
+            Request::GC => session.gc(config.gc_retention).map(Response::GC),
+            Request::Changes { table, after, limit } => session
+                .with_txn(true, |txn| txn.changes(&table, after, limit))
+                .map(Response::Changes),
+            Request::Backup => session.backup().map(Response::Backup),
+            Request::BackupIncremental { since } => {
+                session.backup_incremental(since).map(Response::BackupIncremental)
+            }
+            Request::Handshake { version, .. } if version < MIN_PROTOCOL_VERSION => errinput!(
+                "unsupported client protocol version {version}, server supports versions {MIN_PROTOCOL_VERSION} to {PROTOCOL_VERSION}"
+            ),
+            Request::Handshake { version, capabilities } => Ok(Response::Handshake {
+                version: version.min(PROTOCOL_VERSION),
+                capabilities: capabilities.intersection(Capabilities::ALL),
+            }),
+            Request::Cancel => panic!("unexpected cancel request"),
+            Request::CopyData(_) => panic!("unexpected copy data request"),
+        });
+
+        // Audit administrative commands.
+        if let Some(command) = command {
+            session.audit_command(&command, response.as_ref().err());
+        }
+
+        // Process response.
+        debug!("Returning response {response:?}");
+        response.encode_into(&mut *writer)?;
+        writer.flush()?;
         Ok(())
     }
+
+    /// Collects the status of all cluster nodes. The local node's status is
+    /// read directly, and peers are queried concurrently at their SQL client
+    /// addresses, authenticating as admin if authentication is enabled. Since
+    /// each node reports its own view, this works without a Raft leader.
+    fn cluster_status(
+        id: raft::NodeID,
+        session: &sql::engine::Session<sql::engine::Raft>,
+        config: &RequestConfig,
+    ) -> Result<ClusterStatus> {
+        let (raft, mvcc) = session.node_status()?;
+        let peers = raft.peers.clone();
+        let mut statuses = BTreeMap::from([(id, Ok(NodeStatus { raft, mvcc }))]);
+        std::thread::scope(|s| {
+            let handles = peers
+                .into_iter()
+                .map(|peer| (peer, s.spawn(move || Self::peer_status(peer, config))))
+                .collect_vec();
+            for (peer, handle) in handles {
+                let result = handle.join().unwrap_or_else(|_| errdata!("status thread panicked"));
+                statuses.insert(peer, result);
+            }
+        });
+
+        // Use the latest term seen by any node, and its leader if known. The
+        // commit index is the leader's, or the highest one seen otherwise.
+        let reachable = statuses.values().filter_map(|result| result.as_ref().ok()).collect_vec();
+        let term = reachable.iter().map(|status| status.raft.term).max().unwrap_or(0);
+        let leader = reachable
+            .iter()
+            .filter(|status| status.raft.term == term)
+            .find_map(|status| status.raft.leader);
+        let commit_index = reachable
+            .iter()
+            .find(|status| Some(status.raft.id) == leader)
+            .or_else(|| reachable.iter().max_by_key(|status| status.raft.commit_index))
+            .map(|status| status.raft.commit_index)
+            .unwrap_or(0);
+
+        let nodes = statuses
+            .into_iter()
+            .map(|(node, result)| {
+                let address = config.sql_addrs.get(&node).cloned();
+                let (health, status) = match result {
+                    Ok(status) if status.raft.leader.is_none() => (Health::NoLeader, Some(status)),
+                    Ok(status)
+                        if commit_index.saturating_sub(status.raft.applied_index)
+                            > LAGGING_THRESHOLD =>
+                    {
+                        (Health::Lagging, Some(status))
+                    }
+                    Ok(status) => (Health::Healthy, Some(status)),
+                    Err(error) => (Health::Unreachable(error.to_string()), None),
+                };
+                (node, ClusterNode { address, health, status })
+            })
+            .collect();
+        Ok(ClusterStatus { server: id, leader, term, commit_index, nodes })
+    }
+
+    /// Fetches a peer's node status via its SQL client address.
+    fn peer_status(peer: raft::NodeID, config: &RequestConfig) -> Result<NodeStatus> {
+        Self::peer_connect(peer, config)?.node_status()
+    }
+
+    /// Connects to a peer via its SQL client address, authenticating as admin
+    /// if enabled.
+    fn peer_connect(peer: raft::NodeID, config: &RequestConfig) -> Result<Client> {
+        let Some(addr) = config.sql_addrs.get(&peer) else {
+            return errinput!("unknown SQL address for node {peer}");
+        };
+        let mut client = Client::connect_node(addr, config.peer_tls.as_ref())?;
+        if let Some(password) = &config.admin_password {
+            client.authenticate(User::ADMIN, password)?;
+        }
+        Ok(client)
+    }
+
+    /// Checks replica consistency across all cluster nodes. Submits a checksum
+    /// computation through Raft, such that every node checksums its state
+    /// machine at the same applied index, then fetches each node's checksums
+    /// and compares them with the leader's. Divergent nodes are logged as
+    /// errors, since they indicate a replication bug.
+    fn check_consistency(
+        id: raft::NodeID,
+        session: &sql::engine::Session<sql::engine::Raft>,
+        config: &RequestConfig,
+    ) -> Result<ConsistencyCheck> {
+        let (index, checksum) = session.checksum()?;
+        let peers = session.node_status()?.0.peers;
+        let deadline = Instant::now() + CONSISTENCY_CHECK_TIMEOUT;
+
+        // Poll nodes for their checksums until they've applied the index.
+        let poll = |mut fetch: Box<dyn FnMut() -> Result<Option<Checksum>> + '_>| loop {
+            if let Some(checksum) = fetch()? {
+                return Ok(checksum);
+            }
+            if Instant::now() >= deadline {
+                return errinput!("timed out waiting for index {index} to apply");
+            }
+            std::thread::sleep(CONSISTENCY_CHECK_POLL_INTERVAL);
+        };
+        let mut checksums = BTreeMap::new();
+        std::thread::scope(|s| {
+            let handles = peers
+                .into_iter()
+                .map(|peer| {
+                    let handle = s.spawn(move || {
+                        let mut client = Self::peer_connect(peer, config)?;
+                        poll(Box::new(move || client.checksum(index)))
+                    });
+                    (peer, handle)
+                })
+                .collect_vec();
+            checksums.insert(id, poll(Box::new(|| session.local_checksum(index))));
+            for (peer, handle) in handles {
+                let result = handle.join().unwrap_or_else(|_| errdata!("checksum thread panicked"));
+                checksums.insert(peer, result);
+            }
+        });
+
+        let nodes = checksums
+            .into_iter()
+            .map(|(node, result)| {
+                let consistency = match result {
+                    Ok(node_checksum) => match node_checksum.diff(&checksum) {
+                        ranges if ranges.is_empty() => Consistency::Consistent,
+                        ranges => {
+                            error!(
+                                "Replica divergence: node {node} differs from the leader at index {index} in {}",
+                                ranges.join(", ")
+                            );
+                            metrics::REPLICA_DIVERGENCES.inc(&[]);
+                            Consistency::Divergent(ranges)
+                        }
+                    },
+                    Err(error) => Consistency::Unavailable(error.to_string()),
+                };
+                (node, consistency)
+            })
+            .collect();
+        Ok(ConsistencyCheck { index, version: checksum.version, nodes })
+    }
+}
+
+/// Triggers a graceful server shutdown, see Server::serve().
+#[derive(Clone)]
+pub struct ShutdownHandle(Arc<Mutex<Option<Sender<()>>>>);
+
+impl ShutdownHandle {
+    /// Shuts down the server. Does nothing if already shut down.
+    pub fn shutdown(&self) {
+        // Dropping the sender disconnects the channel, notifying all receivers.
+        self.0.lock().expect("lock poisoned").take();
+    }
+}
+
+/// Server settings that can be changed while serving via a SettingsHandle,
+/// e.g. when reloading the config file. See the corresponding Server setters.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Settings {
+    /// The memory limit for each SQL statement in bytes, if any.
+    pub memory_limit: Option<usize>,
+    /// How long an explicit SQL transaction can run, if limited.
+    pub transaction_timeout: Option<Duration>,
+    /// How long an explicit SQL transaction can be idle, if limited.
+    pub idle_timeout: Option<Duration>,
+    /// How long a SQL statement can execute by default, if limited.
+    pub statement_timeout: Option<Duration>,
+    /// The maximum number of SQL client connections, if any.
+    pub max_connections: Option<usize>,
+    /// The maximum number of SQL client connections per IP address, if any.
+    pub max_connections_per_ip: Option<usize>,
+    /// The maximum number of statements per second per SQL session, if any.
+    pub session_qps: Option<u32>,
+}
+
+/// Changes a server's settings, see Server::settings_handle(). Changes apply
+/// to new connections and sessions, existing sessions keep their settings.
+#[derive(Clone, Default)]
+pub struct SettingsHandle(Arc<Mutex<Settings>>);
+
+impl SettingsHandle {
+    /// Returns the current settings.
+    pub fn get(&self) -> Settings {
+        self.0.lock().expect("lock poisoned").clone()
+    }
+
+    /// Replaces the settings.
+    pub fn set(&self, settings: Settings) {
+        *self.0.lock().expect("lock poisoned") = settings;
+    }
+
+    /// Changes the settings via the given closure.
+    fn update(&self, f: impl FnOnce(&mut Settings)) {
+        f(&mut self.0.lock().expect("lock poisoned"))
+    }
+}
+
+/// Notifies client listeners and sessions about a server shutdown.
+#[derive(Clone, Copy)]
+struct ShutdownSignal<'a> {
+    /// Disconnects on shutdown.
+    rx: &'a Receiver<()>,
+    /// How long in-flight statements and transactions can run on shutdown.
+    grace: Duration,
+}
+
+impl ShutdownSignal<'_> {
+    /// Returns true if the server is shutting down.
+    fn is_shutdown(&self) -> bool {
+        self.rx.try_recv() == Err(TryRecvError::Disconnected)
+    }
+}
+
+/// Open client connections, by peer address, for closing them on shutdown.
+#[derive(Default)]
+struct Connections(Mutex<HashMap<SocketAddr, TcpStream>>);
+
+impl Connections {
+    /// Adds a connection.
+    fn add(&self, peer: SocketAddr, socket: &TcpStream) -> Result<()> {
+        self.0.lock()?.insert(peer, socket.try_clone()?);
+        Ok(())
+    }
+
+    /// Removes a connection.
+    fn remove(&self, peer: SocketAddr) {
+        self.0.lock().expect("lock poisoned").remove(&peer);
+    }
+
+    /// Waits for connections to close until the grace period expires, then
+    /// closes the remaining ones.
+    fn drain(&self, grace: Duration) {
+        let deadline = Instant::now() + grace;
+        while !self.0.lock().expect("lock poisoned").is_empty() && Instant::now() < deadline {
+            std::thread::sleep(SHUTDOWN_POLL_INTERVAL);
+        }
+        for (peer, socket) in self.0.lock().expect("lock poisoned").drain() {
+            debug!("Closing client {peer} on shutdown");
+            socket.shutdown(Shutdown::Both).ok();
+        }
+    }
+}
+
+/// SQL session settings, applied to every client session.
+#[derive(Clone)]
+struct SessionConfig {
+    settings: SettingsHandle,
+    result_cache: Option<Arc<ResultCache>>,
+    plan_cache: Option<Arc<PlanCache>>,
+    slow_query_log: Option<Arc<SlowQueryLog>>,
+    audit_log: Option<Arc<AuditLog>>,
+    statistics: Arc<Statistics>,
+    admin_password: Option<String>,
+    file_dir: Option<PathBuf>,
+}
+
+impl SessionConfig {
+    /// Creates a new session with these settings.
+    fn session<'a>(
+        &self,
+        sql_engine: &'a sql::engine::Raft,
+    ) -> sql::engine::Session<'a, sql::engine::Raft> {
+        let settings = self.settings.get();
+        let mut session = sql_engine.session();
+        session.set_memory_limit(settings.memory_limit);
+        session.set_result_cache(self.result_cache.clone());
+        session.set_plan_cache(self.plan_cache.clone());
+        session.set_slow_query_log(self.slow_query_log.clone());
+        session.set_audit_log(self.audit_log.clone());
+        session.set_statistics(Some(self.statistics.clone()));
+        session.set_transaction_timeout(settings.transaction_timeout);
+        session.set_idle_timeout(settings.idle_timeout);
+        session.set_statement_timeout(settings.statement_timeout);
+        session.set_admin_password(self.admin_password.clone());
+        session.set_file_dir(self.file_dir.clone());
+        session
+    }
+}
+
+/// Settings for handling SQL client requests.
+#[derive(Clone)]
+struct RequestConfig {
+    /// The number of recent MVCC versions retained by garbage collection.
+    gc_retention: u64,
+    /// The maximum size of a streamed SELECT row frame in bytes.
+    frame_size: usize,
+    /// The SQL client addresses of cluster nodes, by node ID.
+    sql_addrs: Arc<BTreeMap<raft::NodeID, String>>,
+    /// The server settings, for the session rate limit.
+    settings: SettingsHandle,
+    /// The TLS configuration for SQL connections to peers, if enabled.
+    peer_tls: Option<Arc<rustls::ClientConfig>>,
+    /// The admin password to authenticate with to peers, if enabled.
+    admin_password: Option<String>,
+    /// Injects faults, if enabled.
+    faults: Option<FaultInjector>,
+    /// The server's hybrid logical clock, for Request::Clock.
+    clock: HybridClock,
+}
+
+/// Admission control for SQL client connections, enforcing connection limits.
+struct Admission {
+    /// The server settings, for the connection limits.
+    settings: SettingsHandle,
+    /// The number of open connections, in total and by IP address.
+    connections: Mutex<(usize, HashMap<IpAddr, usize>)>,
+}
+
+impl Admission {
+    /// Admits a new connection from the given IP address (None for Unix
+    /// sockets), returning a permit that releases it when dropped. Errors with
+    /// Error::Busy if a connection limit is reached.
+    fn admit(&self, ip: Option<IpAddr>) -> Result<Permit<'_>> {
+        let settings = self.settings.get();
+        let mut connections = self.connections.lock()?;
+        let (total, by_ip) = &mut *connections;
+        if settings.max_connections.is_some_and(|max| *total >= max) {
+            return Err(Error::Busy("too many connections".into()));
+        }
+        if let Some(ip) = ip {
+            let count = by_ip.get(&ip).copied().unwrap_or(0);
+            if settings.max_connections_per_ip.is_some_and(|max| count >= max) {
+                return Err(Error::Busy(format!("too many connections from {ip}")));
+            }
+            by_ip.insert(ip, count + 1);
+        }
+        *total += 1;
+        Ok(Permit { admission: self, ip })
+    }
+}
+
+/// An admitted connection, see Admission::admit().
+struct Permit<'a> {
+    admission: &'a Admission,
+    ip: Option<IpAddr>,
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        let mut connections = self.admission.connections.lock().expect("lock poisoned");
+        let (total, by_ip) = &mut *connections;
+        *total -= 1;
+        if let Some(ip) = self.ip {
+            let count = by_ip.get_mut(&ip).expect("no connections for IP");
+            *count -= 1;
+            if *count == 0 {
+                by_ip.remove(&ip);
+            }
+        }
+    }
+}
+
+/// A token bucket rate limiter. Allows the given number of operations per
+/// second on average, with bursts of up to that many operations.
+struct RateLimiter {
+    /// The rate in operations per second, and the bucket's capacity.
+    rate: f64,
+    /// The number of available tokens.
+    tokens: f64,
+    /// When the tokens were last refilled.
+    refilled: Instant,
+}
+
+impl RateLimiter {
+    /// Creates a new rate limiter with a full bucket.
+    fn new(rate: u32) -> Self {
+        let rate = rate as f64;
+        Self { rate, tokens: rate, refilled: Instant::now() }
+    }
+
+    /// Takes a token if available, returning false if rate limited.
+    fn try_acquire(&mut self) -> bool {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.refilled).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.rate);
+        self.refilled = now;
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+        true
+    }
+}
+
+/// Tracks the group commit window of a Raft leader's batched writes (see
+/// Server::set_group_commit_window). The window starts when the node begins a
+/// batch, and writes batched within it are proposed together once it expires.
+/// The time is read from the given clock, such that tests can control it.
+struct GroupCommit {
+    /// The group commit window.
+    window: Duration,
+    /// The clock to read the time from.
+    clock: Arc<dyn clock::Clock>,
+    /// When the current window expires, if the node has a batch.
+    deadline: Option<Instant>,
+}
+
+impl GroupCommit {
+    /// Creates a new group commit window tracker.
+    fn new(window: Duration, clock: Arc<dyn clock::Clock>) -> Self {
+        Self { window, clock, deadline: None }
+    }
+
+    /// Updates the window after stepping the node, given whether it has a
+    /// batch. Starts the window when the node begins a batch, and stops it if
+    /// the batch was proposed.
+    fn update(&mut self, has_batch: bool) {
+        if !has_batch {
+            self.deadline = None;
+        } else if self.deadline.is_none() {
+            self.deadline = Some(self.clock.now() + self.window);
+        }
+    }
+
+    /// Returns true if the current window has expired.
+    fn expired(&self) -> bool {
+        self.deadline.is_some_and(|deadline| self.clock.now() >= deadline)
+    }
+
+    /// Returns a timer that fires when the current window expires, if any.
+    fn timer(&self) -> Option<Receiver<Instant>> {
+        let remaining = self.deadline?.saturating_duration_since(self.clock.now());
+        Some(crossbeam::channel::after(remaining))
+    }
+}
+
+/// The SQL client protocol version. It's negotiated via Request::Handshake,
+/// and must be bumped when making incompatible changes to Request or Response.
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// The oldest SQL client protocol version supported by servers and clients.
+pub const MIN_PROTOCOL_VERSION: u32 = 2;
+
+/// Optional SQL client protocol features, negotiated via Request::Handshake.
+/// Clients fall back to other requests when the server lacks a capability.
+/// It's a bit set rather than an enum, so that peers can ignore unknown
+/// capabilities.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Capabilities(pub u64);
+
+impl Capabilities {
+    /// No capabilities.
+    pub const NONE: Self = Self(0);
+    /// Request::ExecuteBatch.
+    pub const BATCH: Self = Self(1 << 0);
+    /// Request::Topology.
+    pub const TOPOLOGY: Self = Self(1 << 1);
+    /// Request::ExecuteIdempotent.
+    pub const IDEMPOTENCY: Self = Self(1 << 2);
+    /// Request::CopyData and Request::Copy.
+    pub const COPY: Self = Self(1 << 3);
+    /// Request::NodeStatus and Request::ClusterStatus.
+    pub const CLUSTER_STATUS: Self = Self(1 << 4);
+    /// Request::TransferLeadership and Request::RemoveNode.
+    pub const DECOMMISSION: Self = Self(1 << 5);
+    /// Request::Scrub.
+    pub const SCRUB: Self = Self(1 << 6);
+    /// Request::CheckConsistency and Request::Checksum.
+    pub const CONSISTENCY_CHECK: Self = Self(1 << 7);
+    /// Request::Clock.
+    pub const CLOCK: Self = Self(1 << 8);
+    /// All capabilities supported by this version.
+    pub const ALL: Self = Self(
+        Self::BATCH.0
+            | Self::TOPOLOGY.0
+            | Self::IDEMPOTENCY.0
+            | Self::COPY.0
+            | Self::CLUSTER_STATUS.0
+            | Self::DECOMMISSION.0
+            | Self::SCRUB.0
+            | Self::CONSISTENCY_CHECK.0
+            | Self::CLOCK.0,
+    );
+
+    /// Returns true if all of the given capabilities are set.
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Returns the capabilities set in both.
+    pub fn intersection(self, other: Self) -> Self {
+        Self(self.0 & other.0)
+    }
+}
+
+impl std::fmt::Display for Capabilities {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let names = [
+            (Self::BATCH, "batch"),
+            (Self::TOPOLOGY, "topology"),
+            (Self::IDEMPOTENCY, "idempotency"),
+            (Self::COPY, "copy"),
+            (Self::CLUSTER_STATUS, "cluster_status"),
+            (Self::DECOMMISSION, "decommission"),
+            (Self::SCRUB, "scrub"),
+            (Self::CONSISTENCY_CHECK, "consistency_check"),
+            (Self::CLOCK, "clock"),
+        ];
+        let mut names = names.into_iter().filter(|(c, _)| self.contains(*c)).map(|(_, n)| n);
+        write!(f, "{}", names.join(", "))
+    }
 }
 
 /// A SQL client request.
 #[derive(Debug, Serialize, Deserialize)]
 pub enum Request {
+    /// Authenticates the session as the given user. If authentication is
+    /// enabled, this must be the first request.
+    Authenticate { user: String, password: String },
     /// Executes a SQL statement.
     Execute(String),
+    /// Executes multiple SQL statements in order, stopping at the first error.
+    /// SELECT results are buffered rather than streamed. Request::Cancel
+    /// cancels the in-flight statement, and the rest of the batch.
+    ExecuteBatch(Vec<String>),
     /// Fetches the given table schema.
     GetTable(String),
     /// Lists all tables.
     ListTables,
     /// Returns server status.
     Status,
+    /// Returns the cluster topology, for clients to redirect to the leader.
+    /// Doesn't require authentication.
+    Topology,
+    /// Garbage collects old MVCC versions, retaining the server's configured
+    /// number of recent versions.
+    GC,
+    /// Cancels the in-flight statement, if any. Can be sent while waiting for
+    /// an Execute response, which then errors with Error::Cancelled unless the
+    /// statement has already completed. Has no response.
+    Cancel,
+    /// Fetches row changes committed to a table with a changefeed by up to
+    /// limit commits after the given commit sequence number.
+    Changes { table: String, after: u64, limit: usize },
+    /// Takes a full backup of the SQL database, for point-in-time recovery
+    /// together with the Raft log.
+    Backup,
+    /// Negotiates the protocol version and capabilities, given the client's
+    /// latest supported version and its capabilities. Clients should send
+    /// this first, and servers treat clients that don't as version 1. Errors
+    /// if the server doesn't support the version.
+    Handshake { version: u32, capabilities: Capabilities },
+    /// Executes an INSERT, UPDATE, or DELETE statement at most once for the
+    /// given idempotency token, returning the original result if it already
+    /// committed (see Session::execute_idempotent). Returns Response::Execute.
+    ExecuteIdempotent { statement: String, token: String },
+    /// A chunk of data for the following Request::Copy, streamed by the
+    /// client. Has no response.
+    CopyData(Vec<u8>),
+    /// Executes a COPY FROM STDIN statement, importing the data of the
+    /// preceding Request::CopyData chunks (see Session::execute_copy).
+    /// Returns Response::Execute.
+    Copy(String),
+    /// Returns the status of the server's own node, without contacting the
+    /// Raft leader. Requires superuser privileges.
+    NodeStatus,
+    /// Returns the status of all cluster nodes, collected by the server from
+    /// its peers via Request::NodeStatus. Requires superuser privileges.
+    ClusterStatus,
+    /// Transfers Raft leadership from the current leader to an up-to-date
+    /// follower, returning its ID. Errors with Error::Abort if no follower is
+    /// caught up yet. Requires superuser privileges.
+    TransferLeadership,
+    /// Removes a node from the Raft cluster, returning the log index of the
+    /// committed removal. The leader can't be removed, so leadership must be
+    /// transferred away from it first. Requires superuser privileges.
+    RemoveNode(raft::NodeID),
+    /// Checks the consistency of the stored data (MVCC metadata, catalog,
+    /// rows, and secondary indexes), and optionally repairs it via the Raft
+    /// log. Requires superuser privileges.
+    Scrub { repair: bool },
+    /// Checks that all cluster nodes have the same state machine contents at
+    /// the same applied index, by comparing checksums. Requires superuser
+    /// privileges.
+    CheckConsistency,
+    /// Returns the server's own checksums computed at the given applied index
+    /// by Request::CheckConsistency, or None if it hasn't applied it yet.
+    /// Requires superuser privileges.
+    Checksum(raft::Index),
+    /// Sets the faults injected into the server's Raft transport and storage
+    /// engines, returning the previous faults. For chaos testing, and only
+    /// available if fault injection is enabled in the node config. Requires
+    /// superuser privileges.
+    #[doc(hidden)]
+    InjectFaults(Faults),
+    /// Merges the latest hybrid logical clock timestamp observed by the
+    /// client into the server's clock, and returns a new server timestamp
+    /// after it. Clients can use this to carry causality across nodes, e.g.
+    /// for AS OF SYSTEM TIME queries.
+    Clock(Timestamp),
+    /// Takes an incremental backup of the SQL database, containing the
+    /// changes after the Raft log index of a previous full or incremental
+    /// backup. Requires superuser privileges.
+    BackupIncremental { since: raft::Index },
 }
 
 impl encoding::Value for Request {}
@@ -326,11 +2213,37 @@ impl encoding::Value for Request {}
 /// A SQL server response.
 #[derive(Debug, Serialize, Deserialize)]
 pub enum Response {
+    Authenticate,
     Execute(StatementResult),
-    Row(Option<Row>),
+    /// The results of the executed batch statements. Only the last one can
+    /// be an error, in which case the remaining statements weren't executed.
+    ExecuteBatch(Vec<Result<StatementResult>>),
+    /// A frame of streamed SELECT result rows. An empty frame ends the result.
+    Rows(Vec<Row>),
     GetTable(Table),
     ListTables(Vec<String>),
     Status(Status),
+    Topology(Topology),
+    GC(storage::mvcc::GarbageCollection),
+    Changes(Vec<Change>),
+    Backup(Backup),
+    /// The negotiated protocol version, i.e. the latest version supported by
+    /// both, and the capabilities supported by both.
+    Handshake {
+        version: u32,
+        capabilities: Capabilities,
+    },
+    NodeStatus(NodeStatus),
+    ClusterStatus(ClusterStatus),
+    TransferLeadership(raft::NodeID),
+    RemoveNode(raft::Index),
+    Scrub(Scrub),
+    CheckConsistency(ConsistencyCheck),
+    Checksum(Option<Checksum>),
+    #[doc(hidden)]
+    InjectFaults(Faults),
+    Clock(Timestamp),
+    BackupIncremental(IncrementalBackup),
 }
 
 impl encoding::Value for Response {}
@@ -342,3 +2255,255 @@ pub struct Status {
     pub raft: raft::Status,
     pub mvcc: storage::mvcc::Status,
 }
+
+/// A node's status, from its own view of the cluster. The state machine may
+/// lag behind the leader.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct NodeStatus {
+    pub raft: raft::NodeStatus,
+    pub mvcc: storage::mvcc::Status,
+}
+
+/// Cluster status, collected by a server from all cluster nodes.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ClusterStatus {
+    /// The server that collected the status.
+    pub server: raft::NodeID,
+    /// The Raft leader in the latest term, if known by any reachable node.
+    pub leader: Option<raft::NodeID>,
+    /// The latest Raft term of any reachable node.
+    pub term: raft::Term,
+    /// The leader's commit index, or the highest one of any reachable node if
+    /// the leader is unknown or unreachable.
+    pub commit_index: raft::Index,
+    /// The cluster nodes, by node ID.
+    pub nodes: BTreeMap<raft::NodeID, ClusterNode>,
+}
+
+/// A node in the cluster status.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ClusterNode {
+    /// The node's SQL client address, if known.
+    pub address: Option<String>,
+    /// The node's health.
+    pub health: Health,
+    /// The node's status, unless it's unreachable.
+    pub status: Option<NodeStatus>,
+}
+
+/// A node's health in the cluster status.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Health {
+    /// The node has a leader, and has applied the committed entries.
+    Healthy,
+    /// The node doesn't know of a leader in its current term.
+    NoLeader,
+    /// The node's applied index is more than LAGGING_THRESHOLD entries behind
+    /// the commit index.
+    Lagging,
+    /// The node couldn't be queried, with the given error.
+    Unreachable(String),
+}
+
+impl std::fmt::Display for Health {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Healthy => write!(f, "healthy"),
+            Self::NoLeader => write!(f, "no leader"),
+            Self::Lagging => write!(f, "lagging"),
+            Self::Unreachable(error) => write!(f, "unreachable: {error}"),
+        }
+    }
+}
+
+/// Formats the cluster status as a table with a row per node, e.g.:
+///
+/// Cluster: 3 nodes, leader n1 in term 2 at commit index 42 (via n1)
+///
+/// Node  Address         Health   Role      Term  Applied  Raft log  SQL data
+/// n1    localhost:9601  healthy  leader    2     42       12.3 KB   34.5 KB
+/// n2    localhost:9602  healthy  follower  2     42       12.3 KB   34.5 KB
+/// n3    localhost:9603  unreachable: Connection refused (os error 111)
+impl std::fmt::Display for ClusterStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let leader = self.leader.map_or("no leader".to_string(), |id| format!("leader n{id}"));
+        let nodes = match self.nodes.len() {
+            1 => "1 node".to_string(),
+            n => format!("{n} nodes"),
+        };
+        writeln!(
+            f,
+            "Cluster: {nodes}, {leader} in term {} at commit index {} (via n{})",
+            self.term, self.commit_index, self.server
+        )?;
+        writeln!(f)?;
+        let size = |bytes: u64| match bytes {
+            0..1_000 => format!("{bytes} B"),
+            1_000..1_000_000 => format!("{:.1} KB", bytes as f64 / 1e3),
+            1_000_000..1_000_000_000 => format!("{:.1} MB", bytes as f64 / 1e6),
+            _ => format!("{:.1} GB", bytes as f64 / 1e9),
+        };
+        let mut rows = vec![
+            ["Node", "Address", "Health", "Role", "Term", "Applied", "Raft log", "SQL data"]
+                .map(String::from)
+                .to_vec(),
+        ];
+        for (id, node) in &self.nodes {
+            let mut row = vec![
+                format!("n{id}"),
+                node.address.clone().unwrap_or_default(),
+                node.health.to_string(),
+            ];
+            if let Some(status) = &node.status {
+                row.extend([
+                    status.raft.role.to_string(),
+                    status.raft.term.to_string(),
+                    status.raft.applied_index.to_string(),
+                    size(status.raft.storage.disk_size),
+                    size(status.mvcc.storage.disk_size),
+                ]);
+            }
+            rows.push(row);
+        }
+        // Pad columns to their widest value, except for unreachable nodes'
+        // health errors, which are the last value in their row.
+        let widths = (0..rows[0].len())
+            .map(|i| rows.iter().filter(|row| row.len() > i + 1).map(|row| row[i].len()).max())
+            .map(|width| width.unwrap_or(0))
+            .collect_vec();
+        for row in rows {
+            let last = row.len() - 1;
+            let line = row
+                .iter()
+                .enumerate()
+                .map(|(i, v)| match i {
+                    i if i == last => v.clone(),
+                    i => format!("{v:<width$}", width = widths[i]),
+                })
+                .join("  ");
+            writeln!(f, "{line}")?;
+        }
+        Ok(())
+    }
+}
+
+/// The result of a replica consistency check (see Request::CheckConsistency).
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ConsistencyCheck {
+    /// The Raft index the checksums were computed at.
+    pub index: raft::Index,
+    /// The MVCC version the checksums were computed at, on the leader.
+    pub version: storage::mvcc::Version,
+    /// The consistency of each cluster node with the leader, by node ID.
+    pub nodes: BTreeMap<raft::NodeID, Consistency>,
+}
+
+impl ConsistencyCheck {
+    /// Returns true if all nodes were checked and found consistent.
+    pub fn is_consistent(&self) -> bool {
+        self.nodes.values().all(|consistency| *consistency == Consistency::Consistent)
+    }
+}
+
+/// A node's consistency with the leader in a consistency check.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Consistency {
+    /// The node's checksums match the leader's.
+    Consistent,
+    /// The node's checksums differ from the leader's in the given ranges.
+    Divergent(Vec<String>),
+    /// The node's checksums couldn't be fetched, with the given error.
+    Unavailable(String),
+}
+
+/// Formats the consistency check with a line per node, e.g.:
+///
+/// Consistency check at index 42 (version 7): divergent
+/// n1  consistent
+/// n2  divergent: table foo, users
+/// n3  unavailable: Connection refused (os error 111)
+impl std::fmt::Display for ConsistencyCheck {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let result = match self.is_consistent() {
+            true => "consistent",
+            false if self.nodes.values().any(|c| matches!(c, Consistency::Divergent(_))) => {
+                "divergent"
+            }
+            false => "incomplete",
+        };
+        writeln!(
+            f,
+            "Consistency check at index {} (version {}): {result}",
+            self.index, self.version
+        )?;
+        for (node, consistency) in &self.nodes {
+            match consistency {
+                Consistency::Consistent => writeln!(f, "n{node}  consistent")?,
+                Consistency::Divergent(ranges) => {
+                    writeln!(f, "n{node}  divergent: {}", ranges.join(", "))?
+                }
+                Consistency::Unavailable(error) => writeln!(f, "n{node}  unavailable: {error}")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The cluster topology, as seen by a server. Clients use it to connect to the
+/// Raft leader, and to fail over to other nodes (see Client::connect_cluster).
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Topology {
+    /// The responding server's node ID.
+    pub server: raft::NodeID,
+    /// The current Raft leader.
+    pub leader: raft::NodeID,
+    /// The current Raft term.
+    pub term: raft::Term,
+    /// The SQL client addresses of cluster nodes, by node ID, where known.
+    pub nodes: BTreeMap<raft::NodeID, String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+
+    /// Tests that writes batched within the group commit window are proposed
+    /// together once it expires, using a manual clock.
+    #[test]
+    fn group_commit() {
+        let clock = ManualClock::new(0);
+        let mut group_commit = GroupCommit::new(Duration::from_millis(5), Arc::new(clock.clone()));
+
+        // Without a batch, there's no window.
+        group_commit.update(false);
+        assert!(!group_commit.expired());
+        assert!(group_commit.timer().is_none());
+
+        // The first batched write starts the window.
+        group_commit.update(true);
+        assert!(!group_commit.expired());
+        assert!(group_commit.timer().is_some());
+
+        // Later writes are batched into the same window, which isn't extended.
+        clock.advance(Duration::from_millis(3));
+        group_commit.update(true);
+        assert!(!group_commit.expired());
+
+        // The window expires 5 ms after the first write.
+        clock.advance(Duration::from_millis(2));
+        assert!(group_commit.expired());
+        assert!(group_commit.timer().is_some_and(|timer| timer.try_recv().is_ok()));
+
+        // Once the batch is proposed, the window stops.
+        group_commit.update(false);
+        assert!(!group_commit.expired());
+
+        // The next batched write starts a new window.
+        group_commit.update(true);
+        clock.advance(Duration::from_millis(4));
+        assert!(!group_commit.expired());
+        clock.advance(Duration::from_millis(1));
+        assert!(group_commit.expired());
+    }
+}
@@ -0,0 +1,137 @@
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::Write as _;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use log::error;
+use serde::Serialize;
+
+use crate::error::Result;
+use crate::sql::parser::{ast, redact};
+
+/// An append-only audit log of schema changes, user and privilege changes, and
+/// administrative commands, shared by a node's sessions. DML statements
+/// (INSERT, UPDATE, DELETE, and IMPORT) are also audited for the configured
+/// tables. Entries are written to a file as JSON objects, one per line, with
+/// the authenticated user, time, and statement text, e.g.:
+///
+/// {"time":"2024-01-02T03:04:05.678Z","session":7,"user":"admin",
+/// "kind":"ddl","statement":"CREATE TABLE test (id INT PRIMARY KEY)"}
+///
+/// Statements are audited when they've executed, including failed ones (e.g.
+/// due to missing privileges), which include the error. Writes in explicit
+/// transactions are audited even if the transaction is later rolled back.
+/// CREATE USER statements have their literal values redacted, to not log
+/// passwords. Failed writes are logged, but don't fail the statement.
+pub struct AuditLog {
+    /// The log file path.
+    path: PathBuf,
+    /// The tables to audit DML statements for.
+    tables: HashSet<String>,
+    /// The open log file.
+    file: Mutex<File>,
+}
+
+/// The kind of an audited operation.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(super) enum AuditKind {
+    /// A schema change, e.g. CREATE TABLE or CREATE INDEX.
+    Ddl,
+    /// A user or privilege change, e.g. CREATE USER or GRANT.
+    Privilege,
+    /// An administrative command, e.g. a backup or ABORT TRANSACTION.
+    Admin,
+    /// A write to an audited table.
+    Dml,
+}
+
+/// An audit log entry. Unknown fields are omitted.
+#[derive(Serialize)]
+pub(super) struct AuditEvent<'a> {
+    /// The time the operation completed.
+    pub time: String,
+    /// The session ID.
+    pub session: u64,
+    /// The authenticated user, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user: Option<&'a str>,
+    /// The kind of operation.
+    pub kind: AuditKind,
+    /// The statement text or administrative command.
+    pub statement: String,
+    /// The error, if the operation failed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl AuditLog {
+    /// Opens an audit log at the given path, appending to an existing file.
+    /// DML statements are audited for the given tables.
+    pub fn open(
+        path: impl Into<PathBuf>,
+        tables: impl IntoIterator<Item = String>,
+    ) -> Result<Self> {
+        let path = path.into();
+        let file = File::options().create(true).append(true).open(&path)?;
+        Ok(Self { path, tables: tables.into_iter().collect(), file: Mutex::new(file) })
+    }
+
+    /// Returns the kind of audited operation of a statement, or None if it
+    /// isn't audited. EXPLAIN statements aren't audited, since they don't
+    /// execute the statement, nor are temporary tables.
+    pub(super) fn kind(&self, statement: &ast::Statement) -> Option<AuditKind> {
+        use ast::Statement::*;
+        match statement {
+            CreateTable { temporary: true, .. } => None,
+            CreateTable { .. }
+            | DropTable { .. }
+            | AlterTable { .. }
+            | CreateIndex { .. }
+            | CreateTrigger { .. }
+            | DropTrigger { .. }
+            | CreateChangefeed { .. }
+            | DropChangefeed { .. }
+            | TruncateTable { .. } => Some(AuditKind::Ddl),
+            CreateUser { .. } | DropUser { .. } | Grant { .. } | Revoke { .. } => {
+                Some(AuditKind::Privilege)
+            }
+            AbortTransaction(_)
+            | Import { source: ast::ImportSource::File { .. }, .. }
+            | Export { .. } => Some(AuditKind::Admin),
+            Insert { table, .. }
+            | Update { table, .. }
+            | Delete { table, .. }
+            | Import { table, .. }
+                if self.tables.contains(table) =>
+            {
+                Some(AuditKind::Dml)
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns the statement text to log for an audited statement.
+    pub(super) fn statement_text(statement: &ast::Statement, text: &str) -> String {
+        match statement {
+            ast::Statement::CreateUser { .. } => redact(text),
+            _ => text.trim().to_string(),
+        }
+    }
+
+    /// Logs an audit event. Errors are logged rather than returned.
+    pub(super) fn log(&self, event: AuditEvent) {
+        let mut line = serde_json::to_string(&event).expect("JSON serialization failed");
+        line.push('\n');
+        if let Err(error) = self.write(line.as_bytes()) {
+            error!("Failed writing audit log {}: {error}", self.path.display());
+        }
+    }
+
+    /// Appends a line to the log file.
+    fn write(&self, line: &[u8]) -> Result<()> {
+        self.file.lock()?.write_all(line)?;
+        Ok(())
+    }
+}
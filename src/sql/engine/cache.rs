@@ -0,0 +1,584 @@
+use std::cell::RefCell;
+use std::collections::{BTreeSet, HashMap};
+use std::sync::{Arc, Mutex};
+
+use serde::{Deserialize, Serialize};
+
+use itertools::Itertools as _;
+
+use super::{Catalog, Transaction};
+use crate::error::Result;
+use crate::metrics;
+use crate::sql::parser::ast;
+use crate::sql::planner::{Node, Plan};
+use crate::sql::types::{Expression, Label, Row, Rows, Value};
+use crate::storage::mvcc;
+
+/// A cache of SELECT result sets, shared by a node's sessions. This offloads
+/// repeated identical read-only queries, e.g. dashboards that run the same
+/// aggregation over and over.
+///
+/// Results are keyed by the parsed statement, and cached along with the tables
+/// they read and the MVCC snapshot they were computed at. A cached result is
+/// valid for a later read-only transaction if no transaction that's invisible
+/// to the cached snapshot has written to the tables since, which is checked via
+/// Transaction::last_write_version(). This is conservative: uncommitted and
+/// rolled back writes also invalidate results, as does any schema change.
+///
+/// Queries that read information schema tables or call user-defined functions
+/// (which may not be deterministic) aren't cached. When the cache exceeds its
+/// capacity, the least recently used results are evicted.
+pub struct ResultCache {
+    /// The maximum total size of cached results in bytes.
+    capacity: usize,
+    /// The cache state.
+    state: Mutex<State>,
+}
+
+/// The result cache state.
+#[derive(Default)]
+struct State {
+    /// Cached results, by statement key.
+    entries: HashMap<String, Entry>,
+    /// The total size of cached results in bytes.
+    size: usize,
+    /// A logical clock, incremented on every access, for LRU eviction.
+    clock: u64,
+    /// The number of cache hits.
+    hits: u64,
+    /// The number of cache misses.
+    misses: u64,
+}
+
+/// A cached result set.
+struct Entry {
+    /// The version of the transaction that computed the result.
+    version: mvcc::Version,
+    /// The lowest version invisible to the transaction that computed the
+    /// result. Writes at or above this version invalidate it.
+    horizon: mvcc::Version,
+    /// The tables read by the query.
+    tables: Vec<String>,
+    /// The result columns.
+    columns: Vec<Label>,
+    /// The result rows.
+    rows: Arc<Vec<Row>>,
+    /// The approximate size of the result in bytes.
+    size: usize,
+    /// The logical clock value of the last access.
+    accessed: u64,
+}
+
+/// Result cache status.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ResultCacheStatus {
+    /// The number of cached results.
+    pub entries: usize,
+    /// The total size of cached results in bytes.
+    pub size: usize,
+    /// The number of cache hits.
+    pub hits: u64,
+    /// The number of cache misses.
+    pub misses: u64,
+}
+
+impl ResultCache {
+    /// Creates a new result cache with the given capacity in bytes.
+    pub fn new(capacity: usize) -> Self {
+        Self { capacity, state: Mutex::new(State::default()) }
+    }
+
+    /// Fetches a cached result for the given statement key, if it's still
+    /// valid for the given read-only transaction. Invalid results are evicted.
+    pub fn get(
+        &self,
+        key: &str,
+        txn: &impl Transaction,
+    ) -> Result<Option<(Vec<Label>, Arc<Vec<Row>>)>> {
+        let mut state = self.state.lock()?;
+        let state = &mut *state;
+        let Some(entry) = state.entries.get_mut(key) else {
+            state.misses += 1;
+            metrics::RESULT_CACHE_REQUESTS.inc(&["miss"]);
+            return Ok(None);
+        };
+        // The transaction must see everything the cached snapshot saw, which
+        // isn't the case for older (e.g. AS OF) transactions. Otherwise, the
+        // tables must not have been written since.
+        if !txn.read_only() || txn.version() < entry.version {
+            state.misses += 1;
+            metrics::RESULT_CACHE_REQUESTS.inc(&["miss"]);
+            return Ok(None);
+        }
+        if txn.last_write_version(&entry.tables)? >= entry.horizon {
+            state.size -= entry.size;
+            state.entries.remove(key);
+            state.misses += 1;
+            metrics::RESULT_CACHE_REQUESTS.inc(&["miss"]);
+            return Ok(None);
+        }
+        state.clock += 1;
+        state.hits += 1;
+        metrics::RESULT_CACHE_REQUESTS.inc(&["hit"]);
+        entry.accessed = state.clock;
+        Ok(Some((entry.columns.clone(), entry.rows.clone())))
+    }
+
+    /// Caches a result for the given statement key, computed by the given
+    /// read-only transaction from the given tables. Evicts the least recently
+    /// used results if the cache exceeds its capacity. Results larger than the
+    /// capacity, or that are already invalid, aren't cached.
+    pub fn insert(
+        &self,
+        key: String,
+        txn: &impl Transaction,
+        tables: Vec<String>,
+        columns: Vec<Label>,
+        rows: Vec<Row>,
+    ) -> Result<()> {
+        let state = txn.state();
+        let horizon = state.active.first().copied().unwrap_or(state.version).min(state.version);
+        if !txn.read_only() || txn.last_write_version(&tables)? >= horizon {
+            return Ok(());
+        }
+        let size = rows.iter().map(row_size).sum::<usize>() + key.len();
+        if size > self.capacity {
+            return Ok(());
+        }
+
+        let mut state = self.state.lock()?;
+        if let Some(old) = state.entries.remove(&key) {
+            state.size -= old.size;
+        }
+        while state.size + size > self.capacity {
+            let Some(lru) = state.entries.iter().min_by_key(|(_, e)| e.accessed) else {
+                break;
+            };
+            let lru = lru.0.clone();
+            let evicted = state.entries.remove(&lru).expect("no entry");
+            state.size -= evicted.size;
+        }
+        state.clock += 1;
+        state.size += size;
+        let (version, accessed, rows) = (txn.version(), state.clock, Arc::new(rows));
+        let entry = Entry { version, horizon, tables, columns, rows, size, accessed };
+        state.entries.insert(key, entry);
+        Ok(())
+    }
+
+    /// Returns the cache status.
+    pub fn status(&self) -> Result<ResultCacheStatus> {
+        let state = self.state.lock()?;
+        Ok(ResultCacheStatus {
+            entries: state.entries.len(),
+            size: state.size,
+            hits: state.hits,
+            misses: state.misses,
+        })
+    }
+
+    /// Wraps a result row iterator such that its rows are captured as they're
+    /// emitted, up to the cache capacity. The returned closure yields the rows
+    /// once the iterator is exhausted, or None if it wasn't, if it errored, or
+    /// if the rows exceeded the capacity.
+    pub fn capture(&self, rows: Rows) -> (Rows, impl FnOnce() -> Option<Vec<Row>> + use<>) {
+        let captured =
+            Arc::new(Mutex::new(Captured { rows: Some(Vec::new()), ..Default::default() }));
+        let iter =
+            CaptureIterator { inner: rows, captured: captured.clone(), capacity: self.capacity };
+        let result = move || {
+            let captured = Arc::into_inner(captured)?.into_inner().ok()?;
+            captured.rows.filter(|_| captured.done)
+        };
+        (Box::new(iter), result)
+    }
+
+    /// Returns the tables read by a SELECT plan, or None if its result can't
+    /// be cached because it reads information schema tables, calls
+    /// user-defined functions, reads tables with a TTL (whose rows expire
+    /// without writes), or calls age() with a constant timestamp (which
+    /// age(timestamp) plans for the current date).
+    pub fn tables(root: &Node) -> Option<Vec<String>> {
+        let tables = RefCell::new(Some(BTreeSet::new()));
+        let is_function = |expr: &Expression| match expr {
+            Expression::Function(..) => true,
+            Expression::Age(lhs, _) => matches!(**lhs, Expression::Constant(Value::Timestamp(_))),
+            _ => false,
+        };
+        root.clone()
+            .transform(
+                &|node| {
+                    match &node {
+                        Node::IndexLookup { table, .. }
+                        | Node::KeyLookup { table, .. }
+                        | Node::Scan { table, .. } => {
+                            if table.ttl.is_some() {
+                                *tables.borrow_mut() = None;
+                            } else if let Some(tables) = tables.borrow_mut().as_mut() {
+                                tables.insert(table.name.clone());
+                            }
+                        }
+                        Node::SchemaScan { .. } => *tables.borrow_mut() = None,
+                        _ => {}
+                    }
+                    node.transform_expressions(
+                        &|expr| {
+                            if expr.contains(&is_function) {
+                                *tables.borrow_mut() = None;
+                            }
+                            Ok(expr)
+                        },
+                        &Ok,
+                    )
+                },
+                &Ok,
+            )
+            .ok()?;
+        Some(tables.into_inner()?.into_iter().collect())
+    }
+}
+
+/// A cache of query plans, shared by a node's sessions. This avoids planning
+/// (and the catalog lookups it involves) for hot query shapes, e.g. the same
+/// point lookup with different keys.
+///
+/// Plans are keyed by the normalized statement, where integer, float, and
+/// string literals are replaced by parameters (equal literals share the same
+/// parameter). On a hit, the cached plan's parameters are bound to the
+/// statement's literals, and the plan is then optimized as usual. LIMIT and
+/// OFFSET values are evaluated during planning, so they're part of the key.
+/// Only SELECT (without AS OF SYSTEM TIME or FOR UPDATE), INSERT, UPDATE, and
+/// DELETE statements are cached. Statements are still parsed, to normalize
+/// them.
+///
+/// A cached plan is valid for a later transaction if no transaction that's
+/// invisible to the planning transaction has changed the schema of the tables
+/// it uses, which is checked via Transaction::last_schema_version(). There are
+/// no table statistics, the optimizer is purely heuristic, so only schema
+/// changes invalidate plans. Plans that read information schema tables or
+/// tables with a TTL, or call user-defined functions or age(timestamp), aren't
+/// cached. When the
+/// cache exceeds its capacity, the least recently used plans are evicted.
+pub struct PlanCache {
+    /// The maximum number of cached plans.
+    capacity: usize,
+    /// The cache state.
+    state: Mutex<PlanState>,
+}
+
+/// The plan cache state.
+#[derive(Default)]
+struct PlanState {
+    /// Cached plans, by normalized statement key.
+    entries: HashMap<String, PlanEntry>,
+    /// A logical clock, incremented on every access, for LRU eviction.
+    clock: u64,
+    /// The number of cache hits.
+    hits: u64,
+    /// The number of cache misses.
+    misses: u64,
+}
+
+/// A cached plan.
+struct PlanEntry {
+    /// The version of the transaction that planned the statement.
+    version: mvcc::Version,
+    /// The lowest version invisible to the transaction that planned the
+    /// statement. Schema changes at or above this version invalidate it.
+    horizon: mvcc::Version,
+    /// The tables used by the plan.
+    tables: Vec<String>,
+    /// The unoptimized plan, with parameters.
+    plan: Plan,
+    /// The logical clock value of the last access.
+    accessed: u64,
+}
+
+/// Plan cache status.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PlanCacheStatus {
+    /// The number of cached plans.
+    pub entries: usize,
+    /// The number of cache hits.
+    pub hits: u64,
+    /// The number of cache misses.
+    pub misses: u64,
+}
+
+impl PlanCache {
+    /// Creates a new plan cache with the given capacity in plans.
+    pub fn new(capacity: usize) -> Self {
+        Self { capacity, state: Mutex::new(PlanState::default()) }
+    }
+
+    /// Builds an unoptimized plan for the statement at the given wall-clock
+    /// time (see Plan::build_at), using a cached plan if possible. Otherwise,
+    /// the normalized statement is planned and cached. If it can't be
+    /// planned, e.g. because it errors, the original statement is planned
+    /// instead.
+    pub fn plan(
+        &self,
+        mut statement: ast::Statement,
+        txn: &(impl Transaction + Catalog),
+        wall_time: u64,
+    ) -> Result<Plan> {
+        let Some(literals) = Self::normalize(&mut statement) else {
+            return Plan::build_at(statement, txn, wall_time);
+        };
+        let params = literals.iter().map(Self::value).collect_vec();
+        let key = format!("{statement:?}");
+        if let Some(plan) = self.get(&key, txn)? {
+            return plan.bind(&params);
+        }
+        let Ok(plan) = Plan::build_at(statement.clone(), txn, wall_time) else {
+            Self::denormalize(&mut statement, &literals);
+            return Plan::build_at(statement, txn, wall_time);
+        };
+        if let Some(tables) = Self::tables(&plan) {
+            self.insert(key, txn, tables, plan.clone())?;
+        }
+        plan.bind(&params)
+    }
+
+    /// Fetches a cached plan for the given statement key, if it's still valid
+    /// for the given transaction. Invalid plans are evicted.
+    fn get(&self, key: &str, txn: &impl Transaction) -> Result<Option<Plan>> {
+        let mut state = self.state.lock()?;
+        let state = &mut *state;
+        let Some(entry) = state.entries.get_mut(key) else {
+            state.misses += 1;
+            metrics::PLAN_CACHE_REQUESTS.inc(&["miss"]);
+            return Ok(None);
+        };
+        // The transaction must see the schema the plan was built for, which
+        // isn't the case for older transactions. Otherwise, the schema must
+        // not have changed since (including by the transaction itself).
+        if txn.version() < entry.version {
+            state.misses += 1;
+            metrics::PLAN_CACHE_REQUESTS.inc(&["miss"]);
+            return Ok(None);
+        }
+        if txn.last_schema_version(&entry.tables)? >= entry.horizon {
+            state.entries.remove(key);
+            state.misses += 1;
+            metrics::PLAN_CACHE_REQUESTS.inc(&["miss"]);
+            return Ok(None);
+        }
+        state.clock += 1;
+        state.hits += 1;
+        metrics::PLAN_CACHE_REQUESTS.inc(&["hit"]);
+        entry.accessed = state.clock;
+        Ok(Some(entry.plan.clone()))
+    }
+
+    /// Caches a plan for the given statement key, built by the given
+    /// transaction using the given tables. Evicts the least recently used plan
+    /// if the cache is full. Plans that are already invalid, e.g. because the
+    /// transaction changed the schema or uses temporary tables, aren't cached.
+    fn insert(
+        &self,
+        key: String,
+        txn: &impl Transaction,
+        tables: Vec<String>,
+        plan: Plan,
+    ) -> Result<()> {
+        let state = txn.state();
+        let horizon = state.active.first().copied().unwrap_or(state.version).min(state.version);
+        if self.capacity == 0 || txn.last_schema_version(&tables)? >= horizon {
+            return Ok(());
+        }
+
+        let mut state = self.state.lock()?;
+        if !state.entries.contains_key(&key) && state.entries.len() >= self.capacity {
+            if let Some(lru) = state.entries.iter().min_by_key(|(_, e)| e.accessed) {
+                let lru = lru.0.clone();
+                state.entries.remove(&lru);
+            }
+        }
+        state.clock += 1;
+        let (version, accessed) = (txn.version(), state.clock);
+        state.entries.insert(key, PlanEntry { version, horizon, tables, plan, accessed });
+        Ok(())
+    }
+
+    /// Returns the cache status.
+    pub fn status(&self) -> Result<PlanCacheStatus> {
+        let state = self.state.lock()?;
+        Ok(PlanCacheStatus { entries: state.entries.len(), hits: state.hits, misses: state.misses })
+    }
+
+    /// Normalizes a statement by replacing its literals with parameters,
+    /// returning the literal of each parameter, or None if the statement can't
+    /// be cached.
+    fn normalize(statement: &mut ast::Statement) -> Option<Vec<ast::Literal>> {
+        let mut literals: Vec<ast::Literal> = Vec::new();
+        for expr in Self::expressions(statement)? {
+            expr.walk_mut(&mut |expr| {
+                let ast::Expression::Literal(
+                    literal @ (ast::Literal::Integer(_)
+                    | ast::Literal::Float(_)
+                    | ast::Literal::String(_)),
+                ) = expr
+                else {
+                    return;
+                };
+                let index = literals.iter().position(|l| l == literal).unwrap_or_else(|| {
+                    literals.push(literal.clone());
+                    literals.len() - 1
+                });
+                *expr = ast::Expression::Parameter(index);
+            });
+        }
+        Some(literals)
+    }
+
+    /// Reverts normalize(), replacing parameters with the given literals.
+    fn denormalize(statement: &mut ast::Statement, literals: &[ast::Literal]) {
+        for expr in Self::expressions(statement).into_iter().flatten() {
+            expr.walk_mut(&mut |expr| {
+                if let ast::Expression::Parameter(index) = expr {
+                    *expr = ast::Expression::Literal(literals[*index].clone());
+                }
+            });
+        }
+    }
+
+    /// Returns the expressions of a statement that literals are normalized in,
+    /// or None if the statement can't be cached.
+    fn expressions(statement: &mut ast::Statement) -> Option<Vec<&mut ast::Expression>> {
+        /// Adds the expressions of a FROM item.
+        fn from<'a>(item: &'a mut ast::From, exprs: &mut Vec<&'a mut ast::Expression>) {
+            match item {
+                ast::From::Table { .. } => {}
+                ast::From::Join { left, right, predicate, .. } => {
+                    from(left, exprs);
+                    from(right, exprs);
+                    exprs.extend(predicate.as_mut());
+                }
+                ast::From::Unnest { expression, .. } => exprs.push(expression),
+            }
+        }
+
+        let mut exprs = Vec::new();
+        match statement {
+            ast::Statement::Select {
+                select,
+                from: items,
+                as_of: None,
+                r#where,
+                group_by,
+                having,
+                order_by,
+                for_update: false,
+                ..
+            } => {
+                exprs.extend(select.iter_mut().map(|(expr, _)| expr));
+                items.iter_mut().for_each(|item| from(item, &mut exprs));
+                exprs.extend(r#where.as_mut());
+                exprs.extend(group_by.iter_mut());
+                exprs.extend(having.as_mut());
+                exprs.extend(order_by.iter_mut().map(|(expr, _)| expr));
+            }
+            ast::Statement::Insert { values, returning, .. } => {
+                exprs.extend(values.iter_mut().flatten());
+                exprs.extend(returning.iter_mut().map(|(expr, _)| expr));
+            }
+            ast::Statement::Update { set, r#where, returning, .. } => {
+                exprs.extend(set.values_mut().flatten());
+                exprs.extend(r#where.as_mut());
+                exprs.extend(returning.iter_mut().map(|(expr, _)| expr));
+            }
+            ast::Statement::Delete { r#where, returning, .. } => {
+                exprs.extend(r#where.as_mut());
+                exprs.extend(returning.iter_mut().map(|(expr, _)| expr));
+            }
+            _ => return None,
+        }
+        Some(exprs)
+    }
+
+    /// Converts a normalized literal to a parameter value.
+    fn value(literal: &ast::Literal) -> Value {
+        match literal {
+            ast::Literal::Integer(i) => Value::Integer(*i),
+            ast::Literal::Float(f) => Value::Float(*f),
+            ast::Literal::String(s) => Value::String(s.clone()),
+            literal => panic!("unexpected parameter literal {literal:?}"), // see normalize()
+        }
+    }
+
+    /// Returns the tables used by a plan, or None if it can't be cached (see
+    /// ResultCache::tables).
+    fn tables(plan: &Plan) -> Option<Vec<String>> {
+        let is_function = |expr: &Expression| matches!(expr, Expression::Function(..));
+        let (table, source, returning) = match plan {
+            Plan::Select(root) => (None, root, &[] as &[_]),
+            Plan::Delete { table, source, returning, .. } => {
+                (Some(table), source, returning.as_slice())
+            }
+            Plan::Insert { table, source, returning, .. } => {
+                (Some(&table.name), source, returning.as_slice())
+            }
+            Plan::Update { table, source, expressions, returning, .. } => {
+                if expressions.iter().any(|(_, expr)| expr.contains(&is_function)) {
+                    return None;
+                }
+                (Some(&table.name), source, returning.as_slice())
+            }
+            _ => return None,
+        };
+        if returning.iter().any(|(expr, _)| expr.contains(&is_function)) {
+            return None;
+        }
+        let mut tables = ResultCache::tables(source)?;
+        tables.extend(table.cloned());
+        Some(tables.into_iter().sorted().dedup().collect())
+    }
+}
+
+/// Rows captured by a CaptureIterator.
+#[derive(Default)]
+struct Captured {
+    /// The captured rows, or None if capturing gave up.
+    rows: Option<Vec<Row>>,
+    /// The approximate size of the captured rows in bytes.
+    size: usize,
+    /// Whether the iterator was exhausted.
+    done: bool,
+}
+
+/// A row iterator that captures the emitted rows (see ResultCache::capture).
+#[derive(Clone)]
+struct CaptureIterator {
+    inner: Rows,
+    captured: Arc<Mutex<Captured>>,
+    capacity: usize,
+}
+
+impl Iterator for CaptureIterator {
+    type Item = Result<Row>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let next = self.inner.next();
+        let mut captured = self.captured.lock().expect("lock poisoned");
+        let Captured { rows, size, done } = &mut *captured;
+        match &next {
+            Some(Ok(row)) => {
+                *size += row_size(row);
+                match rows {
+                    Some(_) if *size > self.capacity => *rows = None,
+                    Some(rows) => rows.push(row.clone()),
+                    None => {}
+                }
+            }
+            Some(Err(_)) => *rows = None,
+            None => *done = true,
+        }
+        next
+    }
+}
+
+/// Returns the approximate size of a row in bytes.
+fn row_size(row: &Row) -> usize {
+    row.iter().map(|v| v.size()).sum::<usize>() + size_of::<Row>()
+}
@@ -1,9 +1,18 @@
 use std::collections::{BTreeMap, BTreeSet};
+use std::ops::Bound;
+use std::sync::Arc;
 
-use super::Session;
+use serde::{Deserialize, Serialize};
+
+use super::{Session, Statistics};
+use crate::clock::Timestamp;
+use crate::encoding;
 use crate::errinput;
 use crate::error::Result;
-use crate::sql::types::{Expression, Row, Rows, Table, Value};
+use crate::sql::types::{
+    Expression, Function, IndexState, IndexType, Privilege, Row, Rows, Table, Trigger,
+    TriggerCallback, User, Value,
+};
 use crate::storage::mvcc;
 
 /// A SQL engine. This provides low-level CRUD (create, read, update, delete)
@@ -20,8 +29,26 @@ pub trait Engine<'a>: Sized {
     fn begin(&'a self) -> Result<Self::Transaction>;
     /// Begins a read-only transaction.
     fn begin_read_only(&'a self) -> Result<Self::Transaction>;
+    /// Begins a read-only transaction that reads from the local replica, which
+    /// may be stale, instead of the latest state. Defaults to a regular
+    /// read-only transaction, for engines without replicas.
+    fn begin_replica_read(&'a self) -> Result<Self::Transaction> {
+        self.begin_read_only()
+    }
     /// Begins a read-only transaction as of a historical version.
     fn begin_as_of(&'a self, version: mvcc::Version) -> Result<Self::Transaction>;
+    /// Begins a read-only transaction as of a historical HLC timestamp, seeing
+    /// the transactions that committed at or before it. Errors if the
+    /// timestamp is in the future.
+    fn begin_as_of_timestamp(&'a self, timestamp: Timestamp) -> Result<Self::Transaction>;
+
+    /// Registers a user-defined scalar function, which can then be called in
+    /// SQL expressions by all sessions. Errors if the name is already taken.
+    fn register_function(&self, function: Function) -> Result<()>;
+
+    /// Registers a trigger callback, which fires for row writes to its table
+    /// by all sessions. Errors if the name is already taken on the table.
+    fn register_trigger(&self, callback: TriggerCallback) -> Result<()>;
 
     /// Creates a session for executing SQL statements. Can't outlive engine.
     fn session(&'a self) -> Session<'a, Self> {
@@ -35,7 +62,12 @@ pub trait Engine<'a>: Sized {
 /// All methods operate on row batches rather than single rows to amortize the
 /// cost. With the Raft engine, each call results in a Raft roundtrip, and we'd
 /// rather not have to do that for every single row that's modified.
-pub trait Transaction {
+///
+/// Transactions must be Sync, such that parallel query execution can scan
+/// several key ranges of a table concurrently from worker threads. They can be
+/// used as trait objects (except for commit and rollback), e.g. by trigger
+/// callbacks.
+pub trait Transaction: Sync {
     /// The transaction's internal MVCC state.
     fn state(&self) -> &mvcc::TransactionState;
     /// The transaction's MVCC version. Unique for read/write transactions.
@@ -48,9 +80,55 @@ pub trait Transaction {
     }
 
     /// Commits the transaction.
-    fn commit(self) -> Result<()>;
+    fn commit(self) -> Result<()>
+    where
+        Self: Sized;
     /// Rolls back the transaction.
-    fn rollback(self) -> Result<()>;
+    fn rollback(self) -> Result<()>
+    where
+        Self: Sized;
+    /// Commits the transaction under a client-supplied idempotency token,
+    /// recording the given encoded statement result for it. If a transaction
+    /// has already committed with the token, this one is rolled back instead
+    /// and the recorded result is returned. Errors with Error::Serialization
+    /// if a concurrent transaction commits with the token first.
+    fn commit_idempotent(self, token: &str, result: Vec<u8>) -> Result<Option<Vec<u8>>>
+    where
+        Self: Sized;
+    /// Fetches the recorded statement result of the committed transaction
+    /// with the given idempotency token, if any (see commit_idempotent).
+    fn get_idempotent(&self, token: &str) -> Result<Option<Vec<u8>>>;
+    /// Creates a savepoint with the given name. Later savepoints with the
+    /// same name shadow it.
+    fn savepoint(&self, name: &str) -> Result<()>;
+    /// Rolls back to the latest savepoint with the given name, undoing all
+    /// writes made since. The savepoint is retained.
+    fn rollback_to_savepoint(&self, name: &str) -> Result<()>;
+    /// Releases the latest savepoint with the given name, along with any
+    /// later savepoints, retaining their writes.
+    fn release_savepoint(&self, name: &str) -> Result<()>;
+    /// Removes the transaction from all row lock wait queues, retaining held
+    /// locks. Called when giving up on a lock wait.
+    fn cancel_lock_waits(&self) -> Result<()>;
+    /// Returns the lock waiter wakeups, used to wait for the transaction
+    /// blocking a lock to complete. If None, lock waits must poll instead.
+    fn lock_waits(&self) -> Option<Arc<mvcc::LockWaits>> {
+        None
+    }
+    /// Takes a new snapshot of committed data for subsequent reads, switching
+    /// the transaction to read committed isolation. Called before each
+    /// statement in read committed transactions. Errors if read-only.
+    fn refresh_snapshot(&mut self) -> Result<()>;
+    /// Forcibly aborts a different active read-write transaction by version,
+    /// rolling back its writes and releasing its locks. Used for ABORT
+    /// TRANSACTION.
+    fn abort_transaction(&self, version: mvcc::Version) -> Result<()>;
+
+    /// Errors with Error::Serialization if any row in the given primary key
+    /// ranges has been written by a concurrent transaction, whether or not it
+    /// committed. Used to validate the reads of serializable transactions
+    /// before they commit (see `storage::mvcc` module for details).
+    fn check_read(&self, table: &str, ranges: &[(Bound<Value>, Bound<Value>)]) -> Result<()>;
 
     /// Deletes table rows by primary key, if they exist.
     fn delete(&self, table: &str, ids: &[Value]) -> Result<()>;
@@ -58,19 +136,77 @@ pub trait Transaction {
     fn get(&self, table: &str, ids: &[Value]) -> Result<Vec<Row>>;
     /// Inserts new table rows.
     fn insert(&self, table: &str, rows: Vec<Row>) -> Result<()>;
+    /// Inserts a batch of new table rows, sorted by primary key, skipping
+    /// per-row MVCC conflict checks and write records (see
+    /// mvcc::Transaction::set_bulk). Errors if any rows exist (or have existed,
+    /// until garbage collected) in the primary key range spanned by the batch.
+    /// Used for IMPORT.
+    fn bulk_insert(&self, table: &str, rows: Vec<Row>) -> Result<()>;
+    /// Acquires exclusive row locks by primary key, in order. Returns None if
+    /// all locks were acquired, otherwise the version of the first
+    /// transaction that must complete before retrying. Locked rows are read at
+    /// their latest committed version, and released when the transaction
+    /// completes. Used for SELECT FOR UPDATE.
+    fn lock(&self, table: &str, ids: &[Value]) -> Result<Option<mvcc::Version>>;
     /// Looks up a set of primary keys by index values. BTreeSet for testing.
     fn lookup_index(&self, table: &str, column: &str, values: &[Value]) -> Result<BTreeSet<Value>>;
+    /// Returns a version at or above that of every transaction that may have
+    /// written to the given tables (including schema changes), whether or not
+    /// it committed. Used to invalidate cached query results (see
+    /// ResultCache).
+    fn last_write_version(&self, tables: &[String]) -> Result<mvcc::Version>;
+    /// Like last_write_version(), but only considers schema changes (e.g.
+    /// CREATE INDEX). Used to invalidate cached query plans (see PlanCache).
+    fn last_schema_version(&self, tables: &[String]) -> Result<mvcc::Version>;
+    /// Returns the row changes committed to a table with a changefeed by up to
+    /// limit commits after the given commit sequence number, in commit order.
+    /// Unlike other reads, this reflects the current commit log rather than
+    /// the transaction's snapshot, but the table must have a changefeed as of
+    /// the snapshot. Errors if the changes have been garbage collected.
+    fn changes(&self, table: &str, after: u64, limit: usize) -> Result<Vec<Change>>;
+    /// Returns the smallest and largest primary key of a table, or None if
+    /// the table is empty. Used to partition parallel scans.
+    fn key_range(&self, table: &str) -> Result<Option<(Value, Value)>>;
     /// Scans a table's rows, optionally applying the given filter.
-    fn scan(&self, table: &str, filter: Option<Expression>) -> Result<Rows>;
+    fn scan(&self, table: &str, filter: Option<Expression>) -> Result<Rows> {
+        self.scan_range(table, (Bound::Unbounded, Bound::Unbounded), filter)
+    }
+    /// Scans a table's rows in the given primary key range, optionally
+    /// applying the given filter.
+    fn scan_range(
+        &self,
+        table: &str,
+        range: (Bound<Value>, Bound<Value>),
+        filter: Option<Expression>,
+    ) -> Result<Rows>;
     /// Updates table rows by primary key. Uses BTreeMap for testing.
     fn update(&self, table: &str, rows: BTreeMap<Value, Row>) -> Result<()>;
 }
 
-/// The catalog stores table schema information. It must be implemented for
-/// Engine::Transaction, and is thus fully transactional. For simplicity, it
-/// only supports creating and dropping tables. There are no ALTER TABLE schema
-/// changes, nor CREATE INDEX -- everything has to be specified when the table
-/// is initially created.
+/// A row change committed to a table with a changefeed (see
+/// Transaction::changes).
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Change {
+    /// The commit sequence number, in commit order. Changefeeds resume after
+    /// the last sequence number they've seen.
+    pub seq: u64,
+    /// The version of the committed transaction.
+    pub version: mvcc::Version,
+    /// The row's primary key.
+    pub id: Value,
+    /// The row as of the commit, or None if it was deleted.
+    pub row: Option<Row>,
+}
+
+impl encoding::Value for Change {}
+
+/// The catalog stores table schema information and user accounts. It must be
+/// implemented for Engine::Transaction, and is thus fully transactional. For
+/// simplicity, it only supports creating and dropping tables, adding secondary
+/// indexes, creating and dropping triggers and changefeeds, dropping
+/// partitions, and managing users and their privileges.
+/// There are no ALTER TABLE schema changes -- columns have to be specified when
+/// the table is initially created.
 ///
 /// This type is separate from Transaction, even though Engine::Transaction
 /// requires transactions to implement it. This allows better control of when
@@ -78,16 +214,115 @@ pub trait Transaction {
 pub trait Catalog {
     /// Creates a new table. Errors if it already exists.
     fn create_table(&self, table: Table) -> Result<()>;
+    /// Creates a new temporary table, which only exists in the current session
+    /// and is dropped when the session ends. Errors if a temporary table with
+    /// the same name already exists. Temporary tables are only supported via a
+    /// Session (see SessionTransaction), so this errors by default.
+    fn create_temporary_table(&self, table: Table) -> Result<()> {
+        errinput!("temporary tables are only supported in sessions, can't create {}", table.name)
+    }
     /// Drops a table. Errors if it does not exist, unless if_exists is true.
     /// Returns true if the table existed and was deleted.
     fn drop_table(&self, table: &str, if_exists: bool) -> Result<bool>;
+    /// Creates a secondary index of the given type on a table column, indexing
+    /// existing rows. If a predicate is given, only rows matching it are
+    /// indexed (i.e. a partial index). Errors if the column already has an
+    /// index, or an index build in progress.
+    fn create_index(
+        &self,
+        table: &str,
+        column: &str,
+        index_type: IndexType,
+        predicate: Option<Expression>,
+    ) -> Result<()>;
+    /// Begins an online index build (see IndexState), by creating a
+    /// delete-only secondary index without indexing existing rows. If the
+    /// column already has an in-progress build of the same index, returns its
+    /// state such that the build can be resumed. Errors if the column already
+    /// has a public index, or a build of a different index.
+    fn create_index_online(
+        &self,
+        table: &str,
+        column: &str,
+        index_type: IndexType,
+        predicate: Option<Expression>,
+    ) -> Result<IndexState>;
+    /// Moves an in-progress index build to its next state, and returns it.
+    /// Errors if the column's index isn't in the given previous state.
+    fn advance_index(&self, table: &str, column: &str, from: IndexState) -> Result<IndexState>;
+    /// Aborts an in-progress index build, removing the index and its entries.
+    /// Errors if the column has no index build in progress.
+    fn abort_index(&self, table: &str, column: &str) -> Result<()>;
+    /// Adds a batch of at most limit existing rows, with primary keys after
+    /// the given key (if any), to a write-only index. Returns the primary key
+    /// to resume after if there may be more rows.
+    fn backfill_index(
+        &self,
+        table: &str,
+        column: &str,
+        after: Option<Value>,
+        limit: usize,
+    ) -> Result<Option<Value>>;
+    /// Creates a trigger on a table. Errors if the table doesn't exist, or if
+    /// it already has a trigger with the same name.
+    fn create_trigger(&self, table: &str, trigger: Trigger) -> Result<()>;
+    /// Drops a trigger from a table. Errors if the table or trigger doesn't
+    /// exist, unless if_exists is true. Returns true if the trigger existed
+    /// and was dropped.
+    fn drop_trigger(&self, table: &str, trigger: &str, if_exists: bool) -> Result<bool>;
+    /// Creates or drops a table's changefeed (see Table::changefeed). Errors if
+    /// the table doesn't exist, or if it already has (or doesn't have) a
+    /// changefeed.
+    fn set_changefeed(&self, table: &str, changefeed: bool) -> Result<()>;
+    /// Drops a range partition of a table, deleting all rows in it, and
+    /// returns the number of deleted rows. Subsequent rows in the partition's
+    /// key range belong to the next partition, if any. Errors if the table or
+    /// partition doesn't exist.
+    fn drop_partition(&self, table: &str, partition: &str) -> Result<u64>;
+    /// Deletes all rows in a table, along with its index entries, and returns
+    /// the number of deleted rows. Unlike a DELETE, this doesn't decode the
+    /// rows or fire triggers, but concurrent transactions still see the rows
+    /// until the truncating transaction commits. Errors if the table doesn't
+    /// exist, or if it's referenced by another table's foreign key.
+    fn truncate_table(&self, table: &str) -> Result<u64>;
+    /// Creates a user account. Errors if it already exists.
+    fn create_user(&self, user: User) -> Result<()>;
+    /// Drops a user account. Errors if it does not exist, unless if_exists is
+    /// true. Returns true if the user existed and was dropped.
+    fn drop_user(&self, name: &str, if_exists: bool) -> Result<bool>;
+    /// Grants privileges on a table, or all tables if None, to a user. Errors
+    /// if the user or table doesn't exist.
+    fn grant(&self, user: &str, table: Option<&str>, privileges: &[Privilege]) -> Result<()>;
+    /// Revokes privileges on a table, or all tables if None, from a user.
+    /// Errors if the user doesn't exist.
+    fn revoke(&self, user: &str, table: Option<&str>, privileges: &[Privilege]) -> Result<()>;
     /// Fetches a table schema, or None if it doesn't exist.
     fn get_table(&self, table: &str) -> Result<Option<Table>>;
     /// Returns a list of all table schemas.
     fn list_tables(&self) -> Result<Vec<Table>>;
+    /// Lists the active read-write transactions. Unlike other catalog reads,
+    /// this reflects the current state rather than the transaction's snapshot.
+    fn list_transactions(&self) -> Result<Vec<mvcc::TransactionInfo>>;
+    /// Returns the node's statement and table statistics, if tracked.
+    /// Statistics are only tracked via a Session (see SessionTransaction), so
+    /// this returns None by default.
+    fn statistics(&self) -> Option<Arc<Statistics>> {
+        None
+    }
+    /// Fetches a user account, or None if it doesn't exist.
+    fn get_user(&self, name: &str) -> Result<Option<User>>;
+    /// Fetches a user-defined function, or None if it doesn't exist.
+    fn get_function(&self, name: &str) -> Result<Option<Function>>;
+    /// Fetches the trigger callbacks on a table, in registration order.
+    fn get_trigger_callbacks(&self, table: &str) -> Result<Vec<TriggerCallback>>;
 
     /// Fetches a table schema, or errors if it does not exist.
     fn must_get_table(&self, table: &str) -> Result<Table> {
         self.get_table(table)?.ok_or_else(|| errinput!("table {table} does not exist"))
     }
+
+    /// Fetches a user account, or errors if it does not exist.
+    fn must_get_user(&self, name: &str) -> Result<User> {
+        self.get_user(name)?.ok_or_else(|| errinput!("user {name} does not exist"))
+    }
 }
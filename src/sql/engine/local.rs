@@ -1,27 +1,79 @@
 use std::borrow::Cow;
-use std::collections::{BTreeMap, BTreeSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::ops::Bound;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 use itertools::Itertools as _;
 use serde::{Deserialize, Serialize};
 
-use super::Catalog;
-use crate::encoding::{self, Key as _, Value as _};
-use crate::errinput;
+use super::{Catalog, Change, Transaction as _};
+use crate::clock::{HybridClock, Timestamp};
+use crate::encoding::{self, Key as _, Value as _, keycode};
 use crate::error::Result;
-use crate::sql::types::{Expression, Row, Rows, Table, Value};
+use crate::sql::types::{
+    Column, Expression, Function, Functions, IndexState, IndexType, Privilege, Row, Rows, Table,
+    Trigger, TriggerCallback, TriggerCallbacks, User, Value,
+};
 use crate::storage::{self, mvcc};
+use crate::{errconstraint, errdata, errinput, trace};
 
 /// A SQL engine using local storage. This provides the main SQL storage logic,
 /// and the Raft SQL engine just dispatches to this for node-local SQL storage.
 pub struct Local<E: storage::Engine + 'static> {
     /// The local MVCC storage engine.
     pub mvcc: mvcc::MVCC<E>,
+    /// User-defined functions, shared with transactions.
+    functions: Arc<Functions>,
+    /// Trigger callbacks, shared with transactions.
+    triggers: Arc<TriggerCallbacks>,
+    /// Table write versions, shared with transactions.
+    writes: Arc<Mutex<WriteVersions>>,
+    /// The hybrid logical clock used to timestamp transactions, if any.
+    clock: Option<HybridClock>,
 }
 
 impl<E: storage::Engine> Local<E> {
     /// Creates a new local SQL engine using the given storage engine.
     pub fn new(engine: E) -> Self {
-        Self { mvcc: mvcc::MVCC::new(engine) }
+        Self {
+            mvcc: mvcc::MVCC::new(engine),
+            functions: Arc::new(Functions::new()),
+            triggers: Arc::new(TriggerCallbacks::new()),
+            writes: Arc::new(Mutex::new(WriteVersions::default())),
+            clock: None,
+        }
+    }
+
+    /// Sets a hybrid logical clock, used to timestamp transactions when they
+    /// begin and commit. This enables AS OF SYSTEM TIME queries by timestamp.
+    /// The Raft state machine doesn't use a clock, since it must be
+    /// deterministic; timestamps are instead taken by the Raft engine.
+    pub fn set_clock(&mut self, clock: HybridClock) {
+        self.clock = Some(clock);
+    }
+
+    /// Creates a new SQL transaction for the given MVCC transaction.
+    fn transaction(&self, txn: mvcc::Transaction<E>) -> Transaction<E> {
+        Transaction::new(
+            txn,
+            self.functions.clone(),
+            self.triggers.clone(),
+            self.writes.clone(),
+            self.clock.clone(),
+        )
+    }
+
+    /// Begins a read-write transaction at the given HLC timestamp.
+    pub fn begin_at(&self, timestamp: Timestamp) -> Result<Transaction<E>> {
+        self.begin_transaction(self.mvcc.begin_at(timestamp)?)
+    }
+
+    /// Creates a new SQL transaction for a newly begun MVCC transaction,
+    /// which runs at the latest version.
+    fn begin_transaction(&self, txn: mvcc::Transaction<E>) -> Result<Transaction<E>> {
+        self.writes.lock()?.floor.get_or_insert(txn.version());
+        Ok(self.transaction(txn))
     }
 
     /// Resumes a transaction from the given state. This is usually encapsulated
@@ -29,7 +81,7 @@ impl<E: storage::Engine> Local<E> {
     /// transaction between each request since it may be executed across
     /// different leader nodes, so it instead keeps the state in the session.
     pub fn resume(&self, state: mvcc::TransactionState) -> Result<Transaction<E>> {
-        Ok(Transaction::new(self.mvcc.resume(state)?))
+        Ok(self.transaction(self.mvcc.resume(state)?))
     }
 
     /// Gets an unversioned key, or None if it doesn't exist.
@@ -41,33 +93,319 @@ impl<E: storage::Engine> Local<E> {
     pub fn set_unversioned(&self, key: &[u8], value: Vec<u8>) -> Result<()> {
         self.mvcc.set_unversioned(key, value)
     }
+
+    /// Checks the consistency of the stored data, reporting any
+    /// inconsistencies. First checks the MVCC invariants (see
+    /// mvcc::MVCC::scrub), then the catalog, table rows, and secondary indexes
+    /// at the latest committed version.
+    ///
+    /// If repair is true, inconsistencies are repaired where possible, in a
+    /// read-write transaction: invalid MVCC keys and orphaned intents are
+    /// removed, as are rows and index entries of unknown tables and indexes,
+    /// and secondary indexes are rebuilt from the table rows. Invalid schemas
+    /// and rows are only reported, since removing them would lose data.
+    pub fn scrub(&self, repair: bool) -> Result<Scrub> {
+        let mut scrub =
+            Scrub { issues: self.mvcc.scrub(repair)?, repaired: repair, ..Scrub::default() };
+        let txn = match repair {
+            true => self.begin_transaction(self.mvcc.begin()?)?,
+            false => self.begin_transaction(self.mvcc.begin_read_only()?)?,
+        };
+        match txn.scrub(repair, &mut scrub) {
+            Ok(()) => txn.commit()?,
+            Err(error) => {
+                txn.rollback()?;
+                return Err(error);
+            }
+        }
+        Ok(scrub)
+    }
+
+    /// Computes checksums of the SQL state at the latest committed version,
+    /// for comparison across replicas. The keyspace is split into ranges: one
+    /// per table (its schema, rows, and index entries), plus user accounts and
+    /// idempotency tokens, such that divergence can be narrowed down to a
+    /// range. Only visible data is checksummed, not MVCC history or metadata,
+    /// which may legitimately differ (e.g. due to garbage collection timing).
+    pub fn checksum(&self) -> Result<Checksum> {
+        let txn = self.mvcc.begin_read_only()?;
+        let mut hashers: BTreeMap<String, ring::digest::Context> = BTreeMap::new();
+        let mut scan = txn.scan(..);
+        while let Some((key, value)) = scan.next().transpose()? {
+            let range = match Key::decode(&key)? {
+                Key::Table(table)
+                | Key::Index(table, _, _)
+                | Key::Row(table, _)
+                | Key::SchemaFence(table) => format!("table {table}"),
+                Key::User(_) => "users".to_string(),
+                Key::Idempotency(_) => "idempotency".to_string(),
+            };
+            let hasher = hashers
+                .entry(range)
+                .or_insert_with(|| ring::digest::Context::new(&ring::digest::SHA256));
+            for bytes in [&key, &value] {
+                hasher.update(&(bytes.len() as u64).to_be_bytes());
+                hasher.update(bytes);
+            }
+        }
+        let ranges = hashers
+            .into_iter()
+            .map(|(range, hasher)| {
+                let digest = hasher.finish();
+                let checksum = u64::from_be_bytes(digest.as_ref()[..8].try_into()?);
+                Ok((range, checksum))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Checksum { version: txn.version(), ranges })
+    }
+}
+
+/// Checksums of the SQL state at an MVCC version (see Local::checksum).
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Checksum {
+    /// The MVCC version the checksums were computed at.
+    pub version: mvcc::Version,
+    /// Checksums by keyspace range, e.g. "table foo" or "users".
+    pub ranges: BTreeMap<String, u64>,
+}
+
+impl encoding::Value for Checksum {}
+
+impl Checksum {
+    /// Returns the ranges whose checksums differ from the other checksums,
+    /// including ranges missing from either. A differing MVCC version is
+    /// reported as the range "version".
+    pub fn diff(&self, other: &Checksum) -> Vec<String> {
+        let mut diff = Vec::new();
+        if self.version != other.version {
+            diff.push("version".to_string());
+        }
+        diff.extend(
+            self.ranges
+                .keys()
+                .chain(other.ranges.keys())
+                .unique()
+                .filter(|range| self.ranges.get(*range) != other.ranges.get(*range))
+                .sorted()
+                .cloned(),
+        );
+        diff
+    }
+}
+
+/// The result of a storage scrub (see Local::scrub).
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Scrub {
+    /// The number of tables checked.
+    pub tables: u64,
+    /// The number of rows checked.
+    pub rows: u64,
+    /// The number of secondary index entries checked.
+    pub index_entries: u64,
+    /// Descriptions of the inconsistencies found.
+    pub issues: Vec<String>,
+    /// Whether the inconsistencies were repaired (where possible).
+    pub repaired: bool,
+}
+
+impl encoding::Value for Scrub {}
+
+/// Formats the scrub result as a summary line followed by an issue per line,
+/// e.g.:
+///
+/// Checked 2 tables, 10 rows, 8 index entries: 1 issue (repaired)
+/// missing index entry for row 3 in test.value
+impl std::fmt::Display for Scrub {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let issues = match self.issues.len() {
+            0 => "no issues".to_string(),
+            1 => "1 issue".to_string(),
+            n => format!("{n} issues"),
+        };
+        let repaired = if self.repaired && !self.issues.is_empty() { " (repaired)" } else { "" };
+        let plural = |n: u64, one: &str, many: &str| match n {
+            1 => format!("1 {one}"),
+            n => format!("{n} {many}"),
+        };
+        writeln!(
+            f,
+            "Checked {}, {}, {}: {issues}{repaired}",
+            plural(self.tables, "table", "tables"),
+            plural(self.rows, "row", "rows"),
+            plural(self.index_entries, "index entry", "index entries"),
+        )?;
+        for issue in &self.issues {
+            writeln!(f, "{issue}")?;
+        }
+        Ok(())
+    }
 }
 
 impl<E: storage::Engine> super::Engine<'_> for Local<E> {
     type Transaction = Transaction<E>;
 
     fn begin(&self) -> Result<Self::Transaction> {
-        Ok(Self::Transaction::new(self.mvcc.begin()?))
+        match &self.clock {
+            Some(clock) => self.begin_at(clock.now()),
+            None => self.begin_transaction(self.mvcc.begin()?),
+        }
     }
 
     fn begin_read_only(&self) -> Result<Self::Transaction> {
-        Ok(Self::Transaction::new(self.mvcc.begin_read_only()?))
+        self.begin_transaction(self.mvcc.begin_read_only()?)
     }
 
     fn begin_as_of(&self, version: mvcc::Version) -> Result<Self::Transaction> {
-        Ok(Self::Transaction::new(self.mvcc.begin_as_of(version)?))
+        Ok(self.transaction(self.mvcc.begin_as_of(version)?))
+    }
+
+    fn begin_as_of_timestamp(&self, timestamp: Timestamp) -> Result<Self::Transaction> {
+        let Some(clock) = &self.clock else {
+            return errinput!("AS OF SYSTEM TIME timestamps require a clock");
+        };
+        if timestamp > clock.now() {
+            return errinput!("timestamp {timestamp} is in the future");
+        }
+        Ok(self.transaction(self.mvcc.begin_as_of_timestamp(timestamp)?))
+    }
+
+    fn register_function(&self, function: Function) -> Result<()> {
+        self.functions.register(function)
+    }
+
+    fn register_trigger(&self, callback: TriggerCallback) -> Result<()> {
+        self.triggers.register(callback)
     }
 }
 
+/// Tracks the highest transaction version that has written to each table,
+/// including schema changes, whether or not the transaction committed. This is
+/// used to invalidate cached query results (see ResultCache). Schema changes
+/// are also tracked separately, to invalidate cached query plans (see
+/// PlanCache).
+///
+/// It's kept in memory, so it only knows about writes since the engine was
+/// created. Transactions older than the first transaction begun by the engine
+/// may have written to any table.
+#[derive(Default)]
+struct WriteVersions {
+    /// The version of the first transaction begun by the engine, if any.
+    floor: Option<mvcc::Version>,
+    /// The highest writer version of each table.
+    tables: HashMap<String, mvcc::Version>,
+    /// The highest schema writer version of each table.
+    schemas: HashMap<String, mvcc::Version>,
+}
+
 /// A SQL transaction, wrapping an MVCC transaction.
 pub struct Transaction<E: storage::Engine + 'static> {
     txn: mvcc::Transaction<E>,
+    functions: Arc<Functions>,
+    triggers: Arc<TriggerCallbacks>,
+    writes: Arc<Mutex<WriteVersions>>,
+    clock: Option<HybridClock>,
 }
 
 impl<E: storage::Engine> Transaction<E> {
-    /// Creates a new SQL transaction using the given MVCC transaction.
-    fn new(txn: mvcc::Transaction<E>) -> Self {
-        Self { txn }
+    /// Creates a new SQL transaction using the given MVCC transaction,
+    /// user-defined functions, trigger callbacks, table write versions, and
+    /// commit clock.
+    fn new(
+        txn: mvcc::Transaction<E>,
+        functions: Arc<Functions>,
+        triggers: Arc<TriggerCallbacks>,
+        writes: Arc<Mutex<WriteVersions>>,
+        clock: Option<HybridClock>,
+    ) -> Self {
+        Self { txn, functions, triggers, writes, clock }
+    }
+
+    /// Commits the transaction at the given HLC timestamp, if any.
+    pub fn commit_at(self, timestamp: Option<Timestamp>) -> Result<()> {
+        match timestamp {
+            Some(timestamp) => self.txn.commit_at(timestamp),
+            None => self.txn.commit(),
+        }
+    }
+
+    /// Records a heartbeat at the given HLC timestamp, expiring the
+    /// transaction after the timeout (see mvcc::Transaction::heartbeat).
+    pub fn heartbeat(&self, timestamp: Timestamp, timeout: Duration) -> Result<()> {
+        self.txn.heartbeat(timestamp, timeout)
+    }
+
+    /// Like commit_idempotent(), but commits at the given HLC timestamp, if
+    /// any.
+    pub fn commit_idempotent_at(
+        self,
+        token: &str,
+        result: Vec<u8>,
+        timestamp: Option<Timestamp>,
+    ) -> Result<Option<Vec<u8>>> {
+        if let Some(existing) = self.get_idempotent(token)? {
+            self.txn.rollback()?;
+            return Ok(Some(existing));
+        }
+        if let Err(error) = self.txn.set(&Key::Idempotency(token.into()).encode(), result) {
+            self.txn.rollback()?;
+            return Err(error);
+        }
+        self.commit_at(timestamp)?;
+        Ok(None)
+    }
+
+    /// Records a write to the given table by this transaction.
+    fn record_write(&self, table: &str) -> Result<()> {
+        let mut writes = self.writes.lock()?;
+        let version = writes.tables.entry(table.to_string()).or_default();
+        *version = (*version).max(self.txn.version());
+        Ok(())
+    }
+
+    /// Records a schema change to the given table by this transaction. This
+    /// is also recorded as a write to the table.
+    fn record_schema_write(&self, table: &str) -> Result<()> {
+        let mut writes = self.writes.lock()?;
+        let writes = &mut *writes;
+        for versions in [&mut writes.tables, &mut writes.schemas] {
+            let version = versions.entry(table.to_string()).or_default();
+            *version = (*version).max(self.txn.version());
+        }
+        Ok(())
+    }
+
+    /// Fences off concurrent writes to a table for a schema change that they
+    /// must observe, e.g. writing rows to a dropped table or without updating
+    /// a new index. Writes the table's schema fence, which concurrent writers
+    /// check after writing to the table (see check_fence), and errors with a
+    /// serialization failure if a concurrent transaction has already written
+    /// to the table's rows. Either way, one of the transactions fails.
+    ///
+    /// The fence is written as a tombstone, since conflicts are detected via
+    /// versions, and it then leaves no visible key. Checking for concurrent
+    /// row writes scans all versions of the table's rows. Online index builds
+    /// aren't fenced, since they're designed to run alongside writes.
+    fn fence(&self, table: &str) -> Result<()> {
+        self.txn.delete(&Key::SchemaFence(table.into()).encode())?;
+        self.txn.check_read(row_key_range(table, (Bound::Unbounded, Bound::Unbounded)))
+    }
+
+    /// Errors with a serialization failure if a concurrent transaction has
+    /// fenced off writes to the table for a schema change (see fence()). Must
+    /// be called after writing to the table, such that either the writer or
+    /// the schema change sees the other.
+    fn check_fence(&self, table: &str) -> Result<()> {
+        let key = Key::SchemaFence(table.into()).encode();
+        self.txn.check_read(key.clone()..=key)
+    }
+
+    /// Records the transaction's row writes to the given table in the MVCC
+    /// commit log when it commits, if the table has a changefeed.
+    fn capture(&self, table: &Table) -> Result<()> {
+        if !table.changefeed {
+            return Ok(());
+        }
+        self.txn.capture(&KeyPrefix::Row((&table.name).into()).encode())
     }
 
     /// Returns the transaction's internal state.
@@ -77,12 +415,14 @@ impl<E: storage::Engine> Transaction<E> {
 
     /// Fetches the matching primary keys for the given secondary index value,
     /// or an empty set if there is none. The value must already be normalized.
-    fn get_index(&self, table: &str, column: &str, value: &Value) -> Result<BTreeSet<Value>> {
-        debug_assert!(self.has_index(table, column)?, "no index on {table}.{column}");
+    /// It's folded using the column's collation.
+    fn get_index(&self, table: &str, column: &Column, value: &Value) -> Result<BTreeSet<Value>> {
+        debug_assert!(self.has_index(table, &column.name)?, "no index on {table}.{}", column.name);
         debug_assert!(value.is_normalized(), "value not normalized");
+        let value = column.collation.fold(value);
         Ok(self
             .txn
-            .get(&Key::Index(table.into(), column.into(), value.into()).encode())?
+            .get(&Key::Index(table.into(), (&column.name).into(), value).encode())?
             .map(|v| BTreeSet::decode(&v))
             .transpose()?
             .unwrap_or_default())
@@ -98,6 +438,35 @@ impl<E: storage::Engine> Transaction<E> {
             .transpose()
     }
 
+    /// Scans a batch of at most limit table rows in primary key order, within
+    /// the given primary key range and optionally applying the given filter.
+    /// Returns the rows, along with the primary key to resume the scan after
+    /// if there may be more rows. Used by the Raft engine to stream table scans
+    /// in batches rather than buffering the entire table.
+    pub fn scan_batch(
+        &self,
+        table: &str,
+        range: (Bound<Value>, Bound<Value>),
+        filter: Option<Expression>,
+        limit: usize,
+    ) -> Result<(Vec<Row>, Option<Value>)> {
+        let rows: Vec<Row> = self.scan_range(table, range, filter)?.take(limit).try_collect()?;
+        if rows.len() < limit {
+            return Ok((rows, None));
+        }
+        let primary_key = self.must_get_table(table)?.primary_key;
+        let last = rows.last().map(|row| row[primary_key].clone());
+        Ok((rows, last))
+    }
+
+    /// Decodes the primary key of an encoded row key.
+    fn decode_row_key(key: &[u8]) -> Result<Value> {
+        match Key::decode(key)? {
+            Key::Row(_, id) => Ok(id.into_owned()),
+            key => errdata!("expected row key, got {key:?}"),
+        }
+    }
+
     /// Returns true if the given secondary index exists.
     fn has_index(&self, table: &str, column: &str) -> Result<bool> {
         let table = self.must_get_table(table)?;
@@ -105,21 +474,235 @@ impl<E: storage::Engine> Transaction<E> {
     }
 
     /// Stores a secondary index entry for the given column value, replacing the
-    /// existing entry. The value and ids must already be normalized.
+    /// existing entry. The value and ids must already be normalized. The value
+    /// is folded using the column's collation.
     fn set_index(
         &self,
         table: &str,
-        column: &str,
+        column: &Column,
         value: &Value,
         ids: BTreeSet<Value>,
     ) -> Result<()> {
-        debug_assert!(self.has_index(table, column)?, "no index on {table}.{column}");
+        debug_assert!(self.has_index(table, &column.name)?, "no index on {table}.{}", column.name);
         debug_assert!(value.is_normalized(), "value not normalized");
         debug_assert!(ids.iter().all(|v| v.is_normalized()), "value not normalized");
-        let key = Key::Index(table.into(), column.into(), value.into()).encode();
+        let value = column.collation.fold(value);
+        let key = Key::Index(table.into(), (&column.name).into(), value).encode();
         if ids.is_empty() { self.txn.delete(&key) } else { self.txn.set(&key, ids.encode()) }
     }
 
+    /// Returns all user accounts.
+    fn list_users(&self) -> Result<Vec<User>> {
+        self.txn
+            .scan_prefix(&KeyPrefix::User.encode())
+            .map(|r| r.and_then(|(_, v)| User::decode(&v)))
+            .collect()
+    }
+
+    /// Returns the index of the given table column, or errors if it doesn't
+    /// exist.
+    fn column_index(table: &Table, column: &str) -> Result<usize> {
+        match table.columns.iter().position(|c| c.name == column) {
+            Some(index) => Ok(index),
+            None => errinput!("unknown column {column} in table {}", table.name),
+        }
+    }
+
+    /// Adds at most limit existing rows in the given primary key range to the
+    /// index of column i, merging them into the existing index entries.
+    /// Returns the primary key to resume after if there may be more rows.
+    ///
+    /// Like drop_table(), this buffers the index entries in memory, keyed by
+    /// the collation-folded index keys, since storage::Engine doesn't support
+    /// writing while scanning.
+    fn backfill(
+        &self,
+        table: &Table,
+        i: usize,
+        range: (Bound<Value>, Bound<Value>),
+        limit: usize,
+    ) -> Result<Option<Value>> {
+        let column = &table.columns[i];
+        let mut entries: BTreeMap<Value, BTreeSet<Value>> = BTreeMap::new();
+        let (mut count, mut last) = (0, None);
+        let mut rows = self.scan_range(&table.name, range, None)?.take(limit);
+        while let Some(row) = rows.next().transpose()? {
+            for key in index_keys(column, &row, i)? {
+                let key = column.collation.fold(key).into_owned();
+                entries.entry(key).or_default().insert(row[table.primary_key].clone());
+            }
+            count += 1;
+            last = Some(row[table.primary_key].clone());
+        }
+        drop(rows);
+        for (value, mut ids) in entries {
+            ids.extend(self.get_index(&table.name, column, &value)?);
+            self.set_index(&table.name, column, &value, ids)?;
+        }
+        Ok(last.filter(|_| count == limit))
+    }
+
+    /// Checks the catalog, rows, and secondary indexes (see Local::scrub).
+    /// Keys are ordered by variant, so table schemas are scanned before any
+    /// index entries and rows.
+    fn scrub(&self, repair: bool, scrub: &mut Scrub) -> Result<()> {
+        let mut tables: BTreeMap<String, Table> = BTreeMap::new();
+        let mut remove = Vec::new();
+        let mut scan = self.txn.scan(..);
+        while let Some((key, value)) = scan.next().transpose()? {
+            match Key::decode(&key) {
+                Err(error) => {
+                    scrub.issues.push(format!("invalid SQL key {key:?}: {error}"));
+                    remove.push(key);
+                }
+                Ok(Key::Table(name)) => match Table::decode(&value) {
+                    Ok(table) if table.name == name => {
+                        tables.insert(table.name.clone(), table);
+                    }
+                    Ok(table) => scrub
+                        .issues
+                        .push(format!("table {name} has schema for table {}", table.name)),
+                    Err(error) => {
+                        scrub.issues.push(format!("table {name} has invalid schema: {error}"))
+                    }
+                },
+                Ok(Key::Row(table, _)) if !tables.contains_key(table.as_ref()) => {
+                    scrub.issues.push(format!("row in unknown table {table}"));
+                    remove.push(key);
+                }
+                Ok(Key::Index(table, column, _))
+                    if !tables
+                        .get(table.as_ref())
+                        .and_then(|t| t.columns.iter().find(|c| c.name == column))
+                        .is_some_and(|c| c.index) =>
+                {
+                    scrub.issues.push(format!("index entry for unknown index {table}.{column}"));
+                    remove.push(key);
+                }
+                Ok(Key::User(name)) => {
+                    if let Err(error) = User::decode(&value) {
+                        scrub.issues.push(format!("user {name} is invalid: {error}"));
+                    }
+                }
+                Ok(_) => {}
+            }
+        }
+        drop(scan);
+        if repair {
+            for key in remove {
+                self.txn.delete(&key)?;
+            }
+        }
+
+        for table in tables.values() {
+            scrub.tables += 1;
+            if let Err(error) = table.validate(self) {
+                scrub.issues.push(format!("table {} has invalid schema: {error}", table.name));
+                continue;
+            }
+            self.scrub_table(table, repair, scrub)?;
+        }
+        Ok(())
+    }
+
+    /// Checks a table's rows and secondary indexes (see Local::scrub). The
+    /// index entries expected from the rows are buffered in memory, like in
+    /// backfill().
+    fn scrub_table(&self, table: &Table, repair: bool, scrub: &mut Scrub) -> Result<()> {
+        let name = &table.name;
+        let indexes = table.columns.iter().positions(|c| c.index).collect_vec();
+        let mut expected: Vec<BTreeMap<Value, BTreeSet<Value>>> =
+            vec![BTreeMap::new(); indexes.len()];
+        let mut scan = self.txn.scan_prefix(&KeyPrefix::Row(name.into()).encode());
+        while let Some((key, value)) = scan.next().transpose()? {
+            scrub.rows += 1;
+            let id = Self::decode_row_key(&key)?;
+            let row = match Row::decode(&value) {
+                Ok(row) if row.len() != table.columns.len() => {
+                    let (len, columns) = (row.len(), table.columns.len());
+                    scrub.issues.push(format!("row {id} in {name} has {len} of {columns} values"));
+                    continue;
+                }
+                Ok(row) => row,
+                Err(error) => {
+                    scrub.issues.push(format!("row {id} in {name} is invalid: {error}"));
+                    continue;
+                }
+            };
+            if row[table.primary_key] != id {
+                let pk = &row[table.primary_key];
+                scrub.issues.push(format!("row {id} in {name} has primary key {pk}"));
+                continue;
+            }
+            for (&i, entries) in indexes.iter().zip(expected.iter_mut()) {
+                let column = &table.columns[i];
+                for key in index_keys(column, &row, i)? {
+                    let key = column.collation.fold(key).into_owned();
+                    entries.entry(key).or_default().insert(id.clone());
+                }
+            }
+        }
+        drop(scan);
+
+        for (&i, mut expected) in indexes.iter().zip(expected) {
+            let column = &table.columns[i];
+            let complete = column.index_state == IndexState::Public;
+            let prefix = KeyPrefix::Index(name.into(), (&column.name).into()).encode();
+            let mut repairs = Vec::new();
+            let mut scan = self.txn.scan_prefix(&prefix);
+            while let Some((key, value)) = scan.next().transpose()? {
+                scrub.index_entries += 1;
+                let Key::Index(_, _, value_key) = Key::decode(&key)? else {
+                    return errdata!("expected index key, got {key:?}");
+                };
+                let value_key = value_key.into_owned();
+                let ids = BTreeSet::<Value>::decode(&value)?;
+                let expect = expected.remove(&value_key).unwrap_or_default();
+                let index = format!("index {name}.{}", column.name);
+                for id in ids.difference(&expect) {
+                    scrub.issues.push(format!("{index} has stale entry {value_key} for row {id}"));
+                }
+                if complete {
+                    for id in expect.difference(&ids) {
+                        scrub
+                            .issues
+                            .push(format!("{index} is missing entry {value_key} for row {id}"));
+                    }
+                }
+                if column.unique && ids.len() > 1 {
+                    scrub
+                        .issues
+                        .push(format!("unique {index} has {} rows for {value_key}", ids.len()));
+                }
+                let fixed = match complete {
+                    true => expect,
+                    false => ids.intersection(&expect).cloned().collect(),
+                };
+                if fixed != ids {
+                    repairs.push((value_key, fixed));
+                }
+            }
+            drop(scan);
+            if complete {
+                for (value_key, ids) in expected {
+                    for id in &ids {
+                        let index = format!("index {name}.{}", column.name);
+                        scrub
+                            .issues
+                            .push(format!("{index} is missing entry {value_key} for row {id}"));
+                    }
+                    repairs.push((value_key, ids));
+                }
+            }
+            if repair {
+                for (value_key, ids) in repairs {
+                    self.set_index(name, column, &value_key, ids)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
     /// Returns all tables referencing a table, as (table, column index) pairs.
     /// This includes references from the table itself.
     fn table_references(&self, table: &str) -> Result<Vec<(Table, Vec<usize>)>> {
@@ -147,15 +730,65 @@ impl<E: storage::Engine> super::Transaction for Transaction<E> {
     }
 
     fn commit(self) -> Result<()> {
-        self.txn.commit()
+        let timestamp = self.clock.as_ref().map(|clock| clock.now());
+        self.commit_at(timestamp)
     }
 
     fn rollback(self) -> Result<()> {
         self.txn.rollback()
     }
 
+    /// The token's result is written as a regular key in the transaction, so
+    /// it's committed atomically with the transaction's writes, and concurrent
+    /// transactions with the same token conflict.
+    fn commit_idempotent(self, token: &str, result: Vec<u8>) -> Result<Option<Vec<u8>>> {
+        let timestamp = self.clock.as_ref().map(|clock| clock.now());
+        self.commit_idempotent_at(token, result, timestamp)
+    }
+
+    fn get_idempotent(&self, token: &str) -> Result<Option<Vec<u8>>> {
+        self.txn.get(&Key::Idempotency(token.into()).encode())
+    }
+
+    fn savepoint(&self, name: &str) -> Result<()> {
+        self.txn.savepoint(name)
+    }
+
+    fn rollback_to_savepoint(&self, name: &str) -> Result<()> {
+        self.txn.rollback_to_savepoint(name)
+    }
+
+    fn release_savepoint(&self, name: &str) -> Result<()> {
+        self.txn.release_savepoint(name)
+    }
+
+    fn cancel_lock_waits(&self) -> Result<()> {
+        self.txn.cancel_lock_waits()
+    }
+
+    fn lock_waits(&self) -> Option<Arc<mvcc::LockWaits>> {
+        Some(self.txn.lock_waits())
+    }
+
+    fn refresh_snapshot(&mut self) -> Result<()> {
+        self.txn.refresh_snapshot()
+    }
+
+    fn abort_transaction(&self, version: mvcc::Version) -> Result<()> {
+        self.txn.abort_transaction(version)
+    }
+
+    fn check_read(&self, table: &str, ranges: &[(Bound<Value>, Bound<Value>)]) -> Result<()> {
+        for range in ranges {
+            self.txn.check_read(row_key_range(table, range.clone()))?;
+        }
+        Ok(())
+    }
+
     fn delete(&self, table: &str, ids: &[Value]) -> Result<()> {
+        self.record_write(table)?;
         let table = self.must_get_table(table)?;
+        self.capture(&table)?;
         let indexes = table.columns.iter().enumerate().filter(|(_, c)| c.index).collect_vec();
 
         // Check for foreign key references to the deleted rows.
@@ -185,7 +818,7 @@ impl<E: storage::Engine> super::Transaction for Transaction<E> {
                 if let Some(source_id) = source_ids.first() {
                     let table = source.name;
                     let column = &source.columns[source.primary_key].name;
-                    return errinput!("row referenced by {table}.{column}={source_id}");
+                    return errconstraint!("row referenced by {table}.{column}={source_id}");
                 }
             }
         }
@@ -198,24 +831,40 @@ impl<E: storage::Engine> super::Transaction for Transaction<E> {
             if !indexes.is_empty() {
                 if let Some(row) = self.get_row(&table.name, &id)? {
                     for (i, column) in indexes.iter().copied() {
-                        let mut index = self.get_index(&table.name, &column.name, &row[i])?;
-                        index.remove(&id);
-                        self.set_index(&table.name, &column.name, &row[i], index)?;
+                        for key in index_keys(column, &row, i)? {
+                            let mut index = self.get_index(&table.name, column, &key)?;
+                            index.remove(&id);
+                            self.set_index(&table.name, column, &key, index)?;
+                        }
                     }
                 }
             }
 
             self.txn.delete(&Key::Row((&table.name).into(), id).encode())?;
         }
-        Ok(())
+        self.check_fence(&table.name)
     }
 
     fn get(&self, table: &str, ids: &[Value]) -> Result<Vec<Row>> {
+        let span = trace::span("storage.get");
+        span.attr("table", table);
         ids.iter().filter_map(|id| self.get_row(table, &id.normalize_ref()).transpose()).collect()
     }
 
+    fn lock(&self, table: &str, ids: &[Value]) -> Result<Option<mvcc::Version>> {
+        for id in ids {
+            let key = Key::Row(table.into(), id.normalize_ref()).encode();
+            if let Some(version) = self.txn.lock(&key)? {
+                return Ok(Some(version));
+            }
+        }
+        Ok(None)
+    }
+
     fn insert(&self, table: &str, rows: Vec<Row>) -> Result<()> {
+        self.record_write(table)?;
         let table = self.must_get_table(table)?;
+        self.capture(&table)?;
         for mut row in rows {
             // Normalize the row.
             row.iter_mut().for_each(|v| v.normalize());
@@ -225,48 +874,154 @@ impl<E: storage::Engine> super::Transaction for Transaction<E> {
             let id = &row[table.primary_key];
             self.txn.set(&Key::Row((&table.name).into(), id.into()).encode(), row.encode())?;
 
-            // Update any secondary indexes.
-            for (i, column) in table.columns.iter().enumerate().filter(|(_, c)| c.index) {
-                let mut index = self.get_index(&table.name, &column.name, &row[i])?;
-                index.insert(id.clone());
-                self.set_index(&table.name, &column.name, &row[i], index)?;
+            // Update any secondary indexes, except delete-only ones.
+            let indexes = table.columns.iter().enumerate().filter(|(_, c)| c.index);
+            for (i, column) in
+                indexes.filter(|(_, c)| !matches!(c.index_state, IndexState::DeleteOnly(_)))
+            {
+                for key in index_keys(column, &row, i)? {
+                    let mut index = self.get_index(&table.name, column, &key)?;
+                    index.insert(id.clone());
+                    self.set_index(&table.name, column, &key, index)?;
+                }
             }
         }
-        Ok(())
+        self.check_fence(&table.name)
+    }
+
+    fn bulk_insert(&self, table: &str, rows: Vec<Row>) -> Result<()> {
+        self.record_write(table)?;
+        let table = self.must_get_table(table)?;
+        self.capture(&table)?;
+        let mut writes: Vec<(Vec<u8>, Vec<u8>)> = Vec::with_capacity(rows.len());
+        for mut row in rows {
+            row.iter_mut().for_each(|v| v.normalize());
+
+            // The bulk write errors if the key range has existing rows, so
+            // skip the per-row primary key check, as for updates.
+            table.validate_row(&row, true, self)?;
+            let id = &row[table.primary_key];
+            let key = Key::Row((&table.name).into(), id.into()).encode();
+            match writes.last() {
+                Some((last, _)) if *last == key => {
+                    return errconstraint!("primary key {id} already exists");
+                }
+                Some((last, _)) if *last > key => {
+                    return errinput!("rows must be sorted by primary key");
+                }
+                _ => {}
+            }
+
+            // Secondary indexes are updated as usual.
+            let indexes = table.columns.iter().enumerate().filter(|(_, c)| c.index);
+            for (i, column) in
+                indexes.filter(|(_, c)| !matches!(c.index_state, IndexState::DeleteOnly(_)))
+            {
+                for key in index_keys(column, &row, i)? {
+                    let mut index = self.get_index(&table.name, column, &key)?;
+                    index.insert(id.clone());
+                    self.set_index(&table.name, column, &key, index)?;
+                }
+            }
+            writes.push((key, row.encode()));
+        }
+        self.txn.set_bulk(writes)?;
+        self.check_fence(&table.name)
     }
 
     fn lookup_index(&self, table: &str, column: &str, values: &[Value]) -> Result<BTreeSet<Value>> {
         debug_assert!(self.has_index(table, column)?, "no index on {table}.{column}");
+        let span = trace::span("storage.lookup_index");
+        span.attr("table", table);
+        span.attr("column", column);
+        let table = self.must_get_table(table)?;
+        let Some(column) = table.columns.iter().find(|c| c.name == column) else {
+            return errinput!("unknown column {column} in table {}", table.name);
+        };
         values
             .iter()
-            .map(|v| self.get_index(table, column, &v.normalize_ref()))
+            .map(|v| match column.index_type {
+                // Hash indexes are keyed by the hash of the folded value.
+                IndexType::Hash => {
+                    let value = column.collation.fold(v.normalize_ref());
+                    self.get_index(&table.name, column, &IndexType::hash(&value))
+                }
+                _ => self.get_index(&table.name, column, &v.normalize_ref()),
+            })
             .flatten_ok()
             .collect()
     }
 
-    fn scan(&self, table: &str, filter: Option<Expression>) -> Result<Rows> {
+    fn last_write_version(&self, tables: &[String]) -> Result<mvcc::Version> {
+        let writes = self.writes.lock()?;
+        let Some(floor) = writes.floor else {
+            return Ok(mvcc::Version::MAX);
+        };
+        let last = tables.iter().filter_map(|table| writes.tables.get(table).copied()).max();
+        Ok(last.unwrap_or_default().max(floor.saturating_sub(1)))
+    }
+
+    fn last_schema_version(&self, tables: &[String]) -> Result<mvcc::Version> {
+        let writes = self.writes.lock()?;
+        let Some(floor) = writes.floor else {
+            return Ok(mvcc::Version::MAX);
+        };
+        let last = tables.iter().filter_map(|table| writes.schemas.get(table).copied()).max();
+        Ok(last.unwrap_or_default().max(floor.saturating_sub(1)))
+    }
+
+    fn changes(&self, table: &str, after: u64, limit: usize) -> Result<Vec<Change>> {
+        let table = self.must_get_table(table)?;
+        if !table.changefeed {
+            return errinput!("table {} has no changefeed", table.name);
+        }
+        let prefix = KeyPrefix::Row((&table.name).into()).encode();
+        let mut changes = Vec::new();
+        for commit in self.txn.scan_commits(after, limit, &prefix)? {
+            for (key, value) in commit.writes {
+                let id = Self::decode_row_key(&key)?;
+                let row = value.map(|v| Row::decode(&v)).transpose()?;
+                changes.push(Change { seq: commit.seq, version: commit.version, id, row });
+            }
+        }
+        Ok(changes)
+    }
+
+    fn key_range(&self, table: &str) -> Result<Option<(Value, Value)>> {
+        let range = row_key_range(table, (Bound::Unbounded, Bound::Unbounded));
+        let Some((first, _)) = self.txn.scan(range.clone()).next().transpose()? else {
+            return Ok(None);
+        };
+        let Some((last, _)) = self.txn.scan_last(range)? else {
+            return errdata!("no last row in table {table}");
+        };
+        Ok(Some((Self::decode_row_key(&first)?, Self::decode_row_key(&last)?)))
+    }
+
+    fn scan_range(
+        &self,
+        table: &str,
+        range: (Bound<Value>, Bound<Value>),
+        filter: Option<Expression>,
+    ) -> Result<Rows> {
         // TODO: this could be simpler if process_results() implemented Clone.
         let rows = self
             .txn
-            .scan_prefix(&KeyPrefix::Row(table.into()).encode())
+            .scan(row_key_range(table, range))
             .map(|result| result.and_then(|(_, value)| Row::decode(&value)));
         let Some(filter) = filter else {
             return Ok(Box::new(rows));
         };
         let rows = rows.filter_map(move |result| {
-            result
-                .and_then(|row| match filter.evaluate(Some(&row))? {
-                    Value::Boolean(true) => Ok(Some(row)),
-                    Value::Boolean(false) | Value::Null => Ok(None),
-                    value => errinput!("filter returned {value}, expected boolean"),
-                })
-                .transpose()
+            result.and_then(|row| Ok(evaluate_filter(&filter, &row)?.then_some(row))).transpose()
         });
         Ok(Box::new(rows))
     }
 
     fn update(&self, table: &str, rows: BTreeMap<Value, Row>) -> Result<()> {
+        self.record_write(table)?;
         let table = self.must_get_table(table)?;
+        self.capture(&table)?;
         for (mut id, mut row) in rows {
             // Normalize the ID and row.
             id.normalize();
@@ -289,32 +1044,45 @@ impl<E: storage::Engine> super::Transaction for Transaction<E> {
             if !indexes.is_empty() {
                 let old = self.get(&table.name, &[id.clone()])?.remove(0);
                 for (i, column) in indexes {
-                    // If the value didn't change, we don't have to do anything.
-                    if old[i] == row[i] {
+                    // If the index keys didn't change (e.g. the value didn't
+                    // change and the row remains in a partial index), we don't
+                    // have to do anything.
+                    let (old_keys, new_keys) =
+                        (index_keys(column, &old, i)?, index_keys(column, &row, i)?);
+                    if old_keys == new_keys {
                         continue;
                     }
 
-                    // Remove the old value from the index entry.
-                    let mut index = self.get_index(&table.name, &column.name, &old[i])?;
-                    index.remove(&id);
-                    self.set_index(&table.name, &column.name, &old[i], index)?;
+                    // Remove the row from the old index entries.
+                    for key in old_keys.iter().filter(|key| !new_keys.contains(key)) {
+                        let mut index = self.get_index(&table.name, column, key)?;
+                        index.remove(&id);
+                        self.set_index(&table.name, column, key, index)?;
+                    }
 
-                    // Insert the new value into the index entry.
-                    let mut index = self.get_index(&table.name, &column.name, &row[i])?;
-                    index.insert(id.clone());
-                    self.set_index(&table.name, &column.name, &row[i], index)?;
+                    // Insert the row into the new index entries, unless the
+                    // index is delete-only.
+                    if matches!(column.index_state, IndexState::DeleteOnly(_)) {
+                        continue;
+                    }
+                    for key in new_keys.iter().filter(|key| !old_keys.contains(key)) {
+                        let mut index = self.get_index(&table.name, column, key)?;
+                        index.insert(id.clone());
+                        self.set_index(&table.name, column, key, index)?;
+                    }
                 }
             }
 
             // Update the row.
             self.txn.set(&Key::Row((&table.name).into(), (&id).into()).encode(), row.encode())?;
         }
-        Ok(())
+        self.check_fence(&table.name)
     }
 }
 
 impl<E: storage::Engine> Catalog for Transaction<E> {
     fn create_table(&self, table: Table) -> Result<()> {
+        self.record_schema_write(&table.name)?;
         if self.get_table(&table.name)?.is_some() {
             return errinput!("table {} already exists", table.name);
         }
@@ -323,6 +1091,7 @@ impl<E: storage::Engine> Catalog for Transaction<E> {
     }
 
     fn drop_table(&self, table: &str, if_exists: bool) -> Result<bool> {
+        self.record_schema_write(table)?;
         let table = match self.get_table(table)? {
             Some(table) => table,
             None if if_exists => return Ok(false),
@@ -341,8 +1110,9 @@ impl<E: storage::Engine> Catalog for Transaction<E> {
             );
         }
 
-        // Delete the table schema entry.
+        // Delete the table schema entry, and fence off concurrent writes.
         self.txn.delete(&Key::Table((&table.name).into()).encode())?;
+        self.fence(&table.name)?;
 
         // Delete the table rows. storage::Engine doesn't support writing while
         // scanning, so we buffer all keys in a vector. We could also do this in
@@ -363,9 +1133,284 @@ impl<E: storage::Engine> Catalog for Transaction<E> {
                 self.txn.delete(&key)?;
             }
         }
+
+        // Revoke any user privileges on the table, such that they don't apply
+        // to a new table with the same name.
+        for mut user in self.list_users()? {
+            if user.privileges.remove(&Some(table.name.clone())).is_some() {
+                self.txn.set(&Key::User((&user.name).into()).encode(), user.encode())?;
+            }
+        }
+        Ok(true)
+    }
+
+    fn create_index(
+        &self,
+        table: &str,
+        column: &str,
+        index_type: IndexType,
+        predicate: Option<Expression>,
+    ) -> Result<()> {
+        self.record_schema_write(table)?;
+        let mut table = self.must_get_table(table)?;
+        let index = Self::column_index(&table, column)?;
+        let column = &mut table.columns[index];
+        if column.index && column.index_state != IndexState::Public {
+            return errinput!("column {} has an index build in progress", column.name);
+        } else if column.index {
+            return errinput!("column {} already has an index", column.name);
+        }
+        column.index = true;
+        column.index_type = index_type;
+        column.index_predicate = predicate;
+        table.validate(self)?;
+        self.txn.set(&Key::Table((&table.name).into()).encode(), table.encode())?;
+        self.fence(&table.name)?;
+        self.backfill(&table, index, (Bound::Unbounded, Bound::Unbounded), usize::MAX)?;
+        Ok(())
+    }
+
+    fn create_index_online(
+        &self,
+        table: &str,
+        column: &str,
+        index_type: IndexType,
+        predicate: Option<Expression>,
+    ) -> Result<IndexState> {
+        self.record_schema_write(table)?;
+        let mut table = self.must_get_table(table)?;
+        let index = Self::column_index(&table, column)?;
+        let column = &mut table.columns[index];
+        if column.index {
+            if column.index_state == IndexState::Public {
+                return errinput!("column {} already has an index", column.name);
+            }
+            if column.index_type != index_type || column.index_predicate != predicate {
+                return errinput!("column {} has a build of a different index", column.name);
+            }
+            return Ok(column.index_state);
+        }
+        column.index = true;
+        column.index_type = index_type;
+        column.index_predicate = predicate;
+        column.index_state = IndexState::DeleteOnly(self.txn.version());
+        let state = column.index_state;
+        table.validate(self)?;
+        self.txn.set(&Key::Table((&table.name).into()).encode(), table.encode())?;
+        Ok(state)
+    }
+
+    fn advance_index(&self, table: &str, column: &str, from: IndexState) -> Result<IndexState> {
+        self.record_schema_write(table)?;
+        let mut table = self.must_get_table(table)?;
+        let index = Self::column_index(&table, column)?;
+        let column = &mut table.columns[index];
+        let next = from.next(self.txn.version()).filter(|_| column.index_state == from);
+        let Some(next) = next.filter(|_| column.index) else {
+            return errinput!("index on {}.{} is not {from}", table.name, column.name);
+        };
+        column.index_state = next;
+        table.validate(self)?;
+        self.txn.set(&Key::Table((&table.name).into()).encode(), table.encode())?;
+        Ok(next)
+    }
+
+    fn abort_index(&self, table: &str, column: &str) -> Result<()> {
+        self.record_schema_write(table)?;
+        let mut table = self.must_get_table(table)?;
+        let index = Self::column_index(&table, column)?;
+        let column = &mut table.columns[index];
+        if !column.index || column.index_state == IndexState::Public {
+            return errinput!("column {} has no index build in progress", column.name);
+        }
+        column.index = false;
+        column.index_type = IndexType::default();
+        column.index_predicate = None;
+        column.index_state = IndexState::default();
+        let prefix = KeyPrefix::Index((&table.name).into(), (&column.name).into()).encode();
+        table.validate(self)?;
+        self.txn.set(&Key::Table((&table.name).into()).encode(), table.encode())?;
+        self.txn.delete_prefix(&prefix)?;
+        Ok(())
+    }
+
+    fn backfill_index(
+        &self,
+        table: &str,
+        column: &str,
+        after: Option<Value>,
+        limit: usize,
+    ) -> Result<Option<Value>> {
+        self.record_write(table)?;
+        let table = self.must_get_table(table)?;
+        let index = Self::column_index(&table, column)?;
+        let column = &table.columns[index];
+        if !column.index || !matches!(column.index_state, IndexState::WriteOnly(_)) {
+            return errinput!("index on {}.{} is not WRITE_ONLY", table.name, column.name);
+        }
+        let start = after.map_or(Bound::Unbounded, Bound::Excluded);
+        self.backfill(&table, index, (start, Bound::Unbounded), limit)
+    }
+
+    fn create_trigger(&self, table: &str, trigger: Trigger) -> Result<()> {
+        self.record_schema_write(table)?;
+        let mut table = self.must_get_table(table)?;
+        if table.triggers.iter().any(|t| t.name == trigger.name) {
+            return errinput!("trigger {} already exists on table {}", trigger.name, table.name);
+        }
+        table.triggers.push(trigger);
+        table.validate(self)?;
+        self.txn.set(&Key::Table((&table.name).into()).encode(), table.encode())?;
+        self.fence(&table.name)
+    }
+
+    fn drop_trigger(&self, table: &str, trigger: &str, if_exists: bool) -> Result<bool> {
+        self.record_schema_write(table)?;
+        let mut table = match self.get_table(table)? {
+            Some(table) => table,
+            None if if_exists => return Ok(false),
+            None => return errinput!("table {table} does not exist"),
+        };
+        let Some(index) = table.triggers.iter().position(|t| t.name == trigger) else {
+            if if_exists {
+                return Ok(false);
+            }
+            return errinput!("trigger {trigger} does not exist on table {}", table.name);
+        };
+        table.triggers.remove(index);
+        self.txn.set(&Key::Table((&table.name).into()).encode(), table.encode())?;
+        self.fence(&table.name)?;
+        Ok(true)
+    }
+
+    fn set_changefeed(&self, table: &str, changefeed: bool) -> Result<()> {
+        self.record_schema_write(table)?;
+        let mut table = self.must_get_table(table)?;
+        match (table.changefeed, changefeed) {
+            (true, true) => return errinput!("table {} already has a changefeed", table.name),
+            (false, false) => return errinput!("table {} has no changefeed", table.name),
+            _ => {}
+        }
+        table.changefeed = changefeed;
+        self.txn.set(&Key::Table((&table.name).into()).encode(), table.encode())?;
+        self.fence(&table.name)
+    }
+
+    fn drop_partition(&self, table: &str, partition: &str) -> Result<u64> {
+        self.record_schema_write(table)?;
+        let mut table = self.must_get_table(table)?;
+        self.capture(&table)?;
+        let Some(index) = table.partitions.iter().position(|p| p.name == partition) else {
+            return errinput!("partition {partition} does not exist in table {}", table.name);
+        };
+        if table.partitions.len() == 1 {
+            return errinput!("can't drop the only partition of table {}", table.name);
+        }
+        let range = row_key_range(&table.name, table.partition_range(index));
+
+        // Unless the rows are indexed or referenced by other tables, delete the
+        // partition's key range directly rather than going through delete().
+        // This avoids decoding the rows and checking references. Like
+        // drop_table(), storage::Engine doesn't support writing while scanning,
+        // so we buffer the keys.
+        let referenced =
+            self.table_references(&table.name)?.iter().any(|(t, _)| t.name != table.name);
+        let count = if !referenced && table.columns.iter().all(|c| !c.index) {
+            let keys: Vec<Vec<u8>> = self.txn.scan(range).map_ok(|(key, _)| key).try_collect()?;
+            for key in &keys {
+                self.txn.delete(key)?;
+            }
+            keys.len()
+        } else {
+            let ids: Vec<Value> = self
+                .txn
+                .scan(range)
+                .map(|r| r.and_then(|(key, _)| Self::decode_row_key(&key)))
+                .try_collect()?;
+            self.delete(&table.name, &ids)?;
+            ids.len()
+        };
+
+        table.partitions.remove(index);
+        table.validate(self)?;
+        self.txn.set(&Key::Table((&table.name).into()).encode(), table.encode())?;
+        self.fence(&table.name)?;
+        Ok(count as u64)
+    }
+
+    fn truncate_table(&self, table: &str) -> Result<u64> {
+        self.record_write(table)?;
+        let table = self.must_get_table(table)?;
+        self.capture(&table)?;
+        if let Some((source, refs)) =
+            self.table_references(&table.name)?.iter().find(|(t, _)| t.name != table.name)
+        {
+            return errinput!(
+                "table {} is referenced from {}.{}",
+                table.name,
+                source.name,
+                source.columns[refs[0]].name
+            );
+        }
+
+        // Delete the row and index key prefixes with MVCC prefix tombstones,
+        // rather than deleting each row via delete(), such that the number of
+        // writes doesn't depend on the table size. The rows remain visible to
+        // concurrent transactions, and are removed by MVCC garbage collection.
+        // Changefeeds record individual row writes, so the rows of tables with
+        // a changefeed are deleted one by one instead.
+        let prefix = KeyPrefix::Row((&table.name).into()).encode();
+        let count = if table.changefeed {
+            let keys: Vec<_> =
+                self.txn.scan_prefix(&prefix).map_ok(|(key, _)| key).try_collect()?;
+            for key in &keys {
+                self.txn.delete(key)?;
+            }
+            keys.len() as u64
+        } else {
+            self.txn.delete_prefix(&prefix)?
+        };
+        for column in table.columns.iter().filter(|c| c.index) {
+            let prefix = &KeyPrefix::Index((&table.name).into(), (&column.name).into()).encode();
+            self.txn.delete_prefix(prefix)?;
+        }
+        self.check_fence(&table.name)?;
+        Ok(count)
+    }
+
+    fn create_user(&self, user: User) -> Result<()> {
+        if self.get_user(&user.name)?.is_some() {
+            return errinput!("user {} already exists", user.name);
+        }
+        self.txn.set(&Key::User((&user.name).into()).encode(), user.encode())
+    }
+
+    fn drop_user(&self, name: &str, if_exists: bool) -> Result<bool> {
+        if self.get_user(name)?.is_none() {
+            if if_exists {
+                return Ok(false);
+            }
+            return errinput!("user {name} does not exist");
+        }
+        self.txn.delete(&Key::User(name.into()).encode())?;
         Ok(true)
     }
 
+    fn grant(&self, user: &str, table: Option<&str>, privileges: &[Privilege]) -> Result<()> {
+        let mut user = self.must_get_user(user)?;
+        if let Some(table) = table {
+            self.must_get_table(table)?;
+        }
+        user.grant(table, privileges);
+        self.txn.set(&Key::User((&user.name).into()).encode(), user.encode())
+    }
+
+    fn revoke(&self, user: &str, table: Option<&str>, privileges: &[Privilege]) -> Result<()> {
+        let mut user = self.must_get_user(user)?;
+        user.revoke(table, privileges);
+        self.txn.set(&Key::User((&user.name).into()).encode(), user.encode())
+    }
+
     fn get_table(&self, table: &str) -> Result<Option<Table>> {
         self.txn.get(&Key::Table(table.into()).encode())?.map(|v| Table::decode(&v)).transpose()
     }
@@ -376,6 +1421,65 @@ impl<E: storage::Engine> Catalog for Transaction<E> {
             .map(|r| r.and_then(|(_, v)| Table::decode(&v)))
             .collect()
     }
+
+    fn list_transactions(&self) -> Result<Vec<mvcc::TransactionInfo>> {
+        self.txn.scan_transactions()
+    }
+
+    fn get_user(&self, name: &str) -> Result<Option<User>> {
+        self.txn.get(&Key::User(name.into()).encode())?.map(|v| User::decode(&v)).transpose()
+    }
+
+    fn get_function(&self, name: &str) -> Result<Option<Function>> {
+        self.functions.get(name)
+    }
+
+    fn get_trigger_callbacks(&self, table: &str) -> Result<Vec<TriggerCallback>> {
+        self.triggers.get(table)
+    }
+}
+
+/// Returns the encoded key range for a table's rows in the given primary key
+/// range.
+fn row_key_range(
+    table: &str,
+    range: (Bound<Value>, Bound<Value>),
+) -> (Bound<Vec<u8>>, Bound<Vec<u8>>) {
+    let encode = |id: Value| Key::Row(table.into(), id.normalize_ref()).encode();
+    let (prefix_start, prefix_end) = keycode::prefix_range(&KeyPrefix::Row(table.into()).encode());
+    let start = match range.0 {
+        Bound::Included(id) => Bound::Included(encode(id)),
+        Bound::Excluded(id) => Bound::Excluded(encode(id)),
+        Bound::Unbounded => prefix_start,
+    };
+    let end = match range.1 {
+        Bound::Included(id) => Bound::Included(encode(id)),
+        Bound::Excluded(id) => Bound::Excluded(encode(id)),
+        Bound::Unbounded => prefix_end,
+    };
+    (start, end)
+}
+
+/// Returns the secondary index keys of the given row's value in column i (see
+/// IndexType::keys). Returns no keys if the row doesn't satisfy the partial
+/// index predicate (if any). The column must have an index.
+fn index_keys<'a>(column: &Column, row: &'a Row, i: usize) -> Result<Vec<Cow<'a, Value>>> {
+    debug_assert!(column.index, "column {} has no index", column.name);
+    if let Some(predicate) = &column.index_predicate {
+        if !evaluate_filter(predicate, row)? {
+            return Ok(Vec::new());
+        }
+    }
+    Ok(column.index_type.keys(column.collation.fold(&row[i])))
+}
+
+/// Evaluates a scan filter for a row, returning true if the row matches.
+fn evaluate_filter(filter: &Expression, row: &Row) -> Result<bool> {
+    match filter.evaluate(Some(row))? {
+        Value::Boolean(true) => Ok(true),
+        Value::Boolean(false) | Value::Null => Ok(false),
+        value => errinput!("filter returned {value}, expected boolean"),
+    }
 }
 
 /// SQL engine keys, using the Keycode order-preserving encoding. For
@@ -392,6 +1496,14 @@ pub enum Key<'a> {
     Index(Cow<'a, str>, Cow<'a, str>, Cow<'a, Value>),
     /// A table row, by table name and primary key value.
     Row(Cow<'a, str>, Cow<'a, Value>),
+    /// A user account by user name.
+    User(Cow<'a, str>),
+    /// The recorded statement result of an idempotency token.
+    Idempotency(Cow<'a, str>),
+    /// A table's schema fence, by table name. Schema changes write it to
+    /// conflict with concurrent writes to the table (see Transaction::fence).
+    /// It's only ever deleted, so it never has a visible value.
+    SchemaFence(Cow<'a, str>),
 }
 
 impl<'a> encoding::Key<'a> for Key<'a> {}
@@ -406,6 +1518,12 @@ enum KeyPrefix<'a> {
     Index(Cow<'a, str>, Cow<'a, str>),
     /// An entire table's rows, by table name.
     Row(Cow<'a, str>),
+    /// All user accounts.
+    User,
+    /// All idempotency tokens.
+    Idempotency,
+    /// All schema fences.
+    SchemaFence,
 }
 
 impl<'a> encoding::Key<'a> for KeyPrefix<'a> {}
@@ -3,12 +3,22 @@
 //! storage, while the `Raft` engine submits commands through Raft consensus
 //! before dispatching to the `Local` engine on each node.
 
+mod audit;
+mod cache;
 mod engine;
 mod local;
 mod raft;
 mod session;
+mod slowlog;
+mod statistics;
+mod temporary;
 
-pub use engine::{Catalog, Engine, Transaction};
-pub use local::{Key, Local};
-pub use raft::{Raft, Status, Write};
-pub use session::{Session, StatementResult};
+pub use audit::AuditLog;
+pub use cache::{PlanCache, PlanCacheStatus, ResultCache, ResultCacheStatus};
+pub use engine::{Catalog, Change, Engine, Transaction};
+pub use local::{Checksum, Key, Local, Scrub};
+pub use raft::{Backup, IncrementalBackup, Raft, Status, Write};
+pub use session::{IntervalStyle, Session, StatementResult, VARIABLES};
+pub use slowlog::SlowQueryLog;
+pub use statistics::{StatementStatistics, Statistics, TableEstimates, TableStatistics};
+pub use temporary::{SessionTransaction, Temporary};
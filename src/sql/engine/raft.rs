@@ -1,17 +1,24 @@
 use std::borrow::Cow;
-use std::collections::{BTreeMap, BTreeSet};
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::ops::Bound;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use crossbeam::channel::Sender;
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 
-use super::{Catalog, Engine as _, Transaction as _};
-use crate::encoding::{self, Value as _, bincode};
-use crate::errdata;
+use super::{Catalog, Change, Checksum, Engine as _, Scrub, Transaction as _};
+use crate::clock::{HybridClock, Timestamp};
+use crate::encoding::{self, Key as _, Value as _, bincode};
 use crate::error::Result;
-use crate::raft;
-use crate::sql::types::{Expression, Row, Rows, Table, Value};
+use crate::sql::types::{
+    Expression, Function, Functions, IndexState, IndexType, Privilege, Row, Rows, Table, Trigger,
+    TriggerCallback, TriggerCallbacks, User, Value,
+};
 use crate::storage::{self, mvcc};
+use crate::{errdata, errinput};
+use crate::{metrics, raft, trace};
 
 /// A Raft-based SQL engine. This dispatches to the `Local` engine for local
 /// storage and processing on each node, but plumbs read/write commands through
@@ -25,9 +32,27 @@ use crate::storage::{self, mvcc};
 ///
 /// For more details on how SQL statements flow through the engine, see the
 /// `sql` module documentation.
+#[derive(Clone)]
 pub struct Raft {
     /// Sends requests to the local Raft node, along with a response channel.
     tx: Sender<(raft::Request, Sender<Result<raft::Response>>)>,
+    /// User-defined functions. These are local to this node, and not
+    /// replicated through Raft.
+    functions: Arc<Functions>,
+    /// Trigger callbacks. Like functions, these are local to this node, and
+    /// fire on the node executing the statement.
+    triggers: Arc<TriggerCallbacks>,
+    /// The node's hybrid logical clock. Transactions are timestamped with it
+    /// when they begin and commit, since the state machine must be
+    /// deterministic and can't read the clock itself.
+    clock: HybridClock,
+    /// If set, read-write transactions expire unless they heartbeat within
+    /// this timeout, such that abandoned transactions are rolled back.
+    intent_timeout: Option<Duration>,
+    /// Wakes up lock waiters when the local state machine applies the
+    /// completion of the transaction they're waiting for. If None, lock waits
+    /// poll instead.
+    lock_waits: Option<Arc<mvcc::LockWaits>>,
 }
 
 impl Raft {
@@ -35,10 +60,39 @@ impl Raft {
     /// for simplicity.
     pub const APPLIED_INDEX_KEY: &'static [u8] = b"applied_index";
 
+    /// The number of rows to fetch per Read::Scan request during table scans.
+    const SCAN_BATCH_SIZE: usize = 1000;
+
     /// Creates a new Raft-based SQL engine, given a Raft request channel to the
     /// local Raft node.
-    pub fn new(tx: Sender<(raft::Request, Sender<Result<raft::Response>>)>) -> Self {
-        Self { tx }
+    pub fn new(
+        tx: Sender<(raft::Request, Sender<Result<raft::Response>>)>,
+        clock: HybridClock,
+    ) -> Self {
+        Self {
+            tx,
+            functions: Arc::new(Functions::new()),
+            triggers: Arc::new(TriggerCallbacks::new()),
+            clock,
+            intent_timeout: None,
+            lock_waits: None,
+        }
+    }
+
+    /// Sets the intent timeout, or None to disable it (the default). Read-write
+    /// transactions heartbeat while in use, and expire if they don't heartbeat
+    /// within the timeout, e.g. because the node coordinating them died. Their
+    /// writes and locks are then rolled back when they block other
+    /// transactions, or by resolve_expired(). Transactions must therefore not
+    /// sit idle for longer than the timeout.
+    pub fn set_intent_timeout(&mut self, intent_timeout: Option<Duration>) {
+        self.intent_timeout = intent_timeout;
+    }
+
+    /// Sets the lock waiter wakeups of the local state machine (see
+    /// State::lock_waits), or None to poll lock waits instead (the default).
+    pub fn set_lock_waits(&mut self, lock_waits: Option<Arc<mvcc::LockWaits>>) {
+        self.lock_waits = lock_waits;
     }
 
     /// Creates the Raft-managed state machine for the Raft engine. Receives
@@ -49,9 +103,27 @@ impl Raft {
 
     /// Executes a request against the Raft cluster, waiting for the response.
     fn execute(&self, request: raft::Request) -> Result<raft::Response> {
+        let (kind, span) = match &request {
+            raft::Request::Read(_) => ("read", "raft.read"),
+            raft::Request::SnapshotRead(_) => ("snapshot_read", "raft.snapshot_read"),
+            raft::Request::Write(_) => ("write", "raft.write"),
+            raft::Request::Status => ("status", "raft.status"),
+            raft::Request::TransferLeadership => {
+                ("transfer_leadership", "raft.transfer_leadership")
+            }
+            raft::Request::NodeStatus => ("node_status", "raft.node_status"),
+            raft::Request::LocalRead(_) => ("local_read", "raft.local_read"),
+            raft::Request::RemoveNode(_) => ("remove_node", "raft.remove_node"),
+            raft::Request::CommittedEntries(_) => ("committed_entries", "raft.committed_entries"),
+        };
+        let span = trace::span(span);
+        let start = Instant::now();
         let (response_tx, response_rx) = crossbeam::channel::bounded(1);
         self.tx.send((request, response_tx))?;
-        response_rx.recv()?
+        let response = response_rx.recv()?;
+        metrics::RAFT_REQUEST_DURATION.observe(&[kind], start.elapsed().as_secs_f64());
+        span.result(&response);
+        response
     }
 
     /// Writes through Raft, deserializing the response into the return type.
@@ -63,13 +135,31 @@ impl Raft {
     }
 
     /// Reads from Raft, deserializing the response into the return type.
+    /// Reads in read-only transactions only read the transaction's snapshot,
+    /// which was observed by the linearizable BeginReadOnly read, so they're
+    /// submitted as snapshot reads which only wait for the read index to be
+    /// applied.
     fn read<V: DeserializeOwned>(&self, read: Read) -> Result<V> {
-        match self.execute(raft::Request::Read(read.encode()))? {
+        let request = match read.is_snapshot() {
+            true => raft::Request::SnapshotRead(read.encode()),
+            false => raft::Request::Read(read.encode()),
+        };
+        match self.execute(request)? {
             raft::Response::Read(response) => bincode::deserialize(&response),
             response => errdata!("unexpected Raft read response {response:?}"),
         }
     }
 
+    /// Reads from the local node's state machine, without contacting the
+    /// leader, deserializing the response into the return type. The local
+    /// node may lag behind the leader.
+    fn local_read<V: DeserializeOwned>(&self, read: Read) -> Result<V> {
+        match self.execute(raft::Request::LocalRead(read.encode()))? {
+            raft::Response::Read(response) => bincode::deserialize(&response),
+            response => errdata!("unexpected Raft local read response {response:?}"),
+        }
+    }
+
     /// Raft SQL engine status.
     pub fn status(&self) -> Result<Status> {
         let raft = match self.execute(raft::Request::Status)? {
@@ -79,6 +169,91 @@ impl Raft {
         let mvcc = self.read(Read::Status)?;
         Ok(Status { raft, mvcc })
     }
+
+    /// Returns the status of the local node, without contacting the leader.
+    /// The local node may lag behind the leader, or not know of one.
+    pub fn node_status(&self) -> Result<(raft::NodeStatus, mvcc::Status)> {
+        let raft = match self.execute(raft::Request::NodeStatus)? {
+            raft::Response::NodeStatus(status) => status,
+            response => return errdata!("unexpected Raft node status response {response:?}"),
+        };
+        let mvcc = self.local_read(Read::Status)?;
+        Ok((raft, mvcc))
+    }
+
+    /// Garbage collects old MVCC versions on all nodes, retaining the given
+    /// number of recent versions (see mvcc::MVCC::gc).
+    pub fn gc(&self, retention: u64) -> Result<mvcc::GarbageCollection> {
+        self.write(Write::GarbageCollect { retention })
+    }
+
+    /// Rolls back all expired transactions on all nodes, returning their
+    /// versions (see mvcc::MVCC::resolve_expired).
+    pub fn resolve_expired(&self) -> Result<Vec<mvcc::Version>> {
+        self.write(Write::ResolveExpired(self.clock.now()))
+    }
+
+    /// Checks the consistency of the stored data (see Local::scrub). Without
+    /// repair, only the local node's data is checked, via a local read. With
+    /// repair, the scrub is submitted through Raft, such that all nodes check
+    /// and repair their data, and the leader's result is returned.
+    pub fn scrub(&self, repair: bool) -> Result<Scrub> {
+        if repair {
+            return self.write(Write::Scrub);
+        }
+        self.local_read(Read::Scrub)
+    }
+
+    /// Computes checksums of the SQL state on all nodes at the same applied
+    /// index, by submitting the computation through Raft (see
+    /// Local::checksum). Returns the index and the leader's checksums. The
+    /// other nodes' checksums can be fetched via local_checksum().
+    pub fn checksum(&self) -> Result<(raft::Index, Checksum)> {
+        self.write(Write::Checksum)
+    }
+
+    /// Returns the local node's checksums computed at the given applied index
+    /// (see checksum), or None if the node hasn't applied it yet or it's no
+    /// longer retained.
+    pub fn local_checksum(&self, index: raft::Index) -> Result<Option<Checksum>> {
+        self.local_read(Read::Checksum(index))
+    }
+
+    /// Takes a full backup of the SQL state machine (see Backup).
+    pub fn backup(&self) -> Result<Backup> {
+        self.read(Read::Backup)
+    }
+
+    /// Takes an incremental backup of the changes after the given Raft log
+    /// index, i.e. the local node's committed entries after it (see
+    /// IncrementalBackup). Errors with Error::Truncated if the node's log has
+    /// been truncated past the index, in which case a new full backup is needed.
+    pub fn backup_incremental(&self, since: raft::Index) -> Result<IncrementalBackup> {
+        match self.execute(raft::Request::CommittedEntries(since))? {
+            raft::Response::Entries(entries) => {
+                Ok(IncrementalBackup { base_index: since, entries })
+            }
+            response => errdata!("unexpected Raft committed entries response {response:?}"),
+        }
+    }
+
+    /// Transfers Raft leadership to an up-to-date follower, returning its ID
+    /// (see raft::Request::TransferLeadership).
+    pub fn transfer_leadership(&self) -> Result<raft::NodeID> {
+        match self.execute(raft::Request::TransferLeadership)? {
+            raft::Response::TransferLeadership(id) => Ok(id),
+            response => errdata!("unexpected Raft transfer response {response:?}"),
+        }
+    }
+
+    /// Removes a follower from the Raft cluster, returning the log index of
+    /// the committed removal (see raft::Request::RemoveNode).
+    pub fn remove_node(&self, id: raft::NodeID) -> Result<raft::Index> {
+        match self.execute(raft::Request::RemoveNode(id))? {
+            raft::Response::RemoveNode(index) => Ok(index),
+            response => errdata!("unexpected Raft remove node response {response:?}"),
+        }
+    }
 }
 
 impl<'a> super::Engine<'a> for Raft {
@@ -92,9 +267,36 @@ impl<'a> super::Engine<'a> for Raft {
         Transaction::begin(self, true, None)
     }
 
+    /// The transaction and its reads are submitted as local reads to the
+    /// local node's state machine, which may lag behind the leader, e.g. on
+    /// a non-voting read replica (see raft::Options::learners). This avoids
+    /// loading the leader and voters with e.g. analytical scans.
+    fn begin_replica_read(&'a self) -> Result<Self::Transaction> {
+        let state = self.local_read(Read::BeginReadOnly { as_of: None })?;
+        Ok(Transaction { local: true, ..Transaction::new(self, state) })
+    }
+
     fn begin_as_of(&'a self, version: mvcc::Version) -> Result<Self::Transaction> {
         Transaction::begin(self, true, Some(version))
     }
+
+    /// This is submitted as a write, since the state machine bumps its latest
+    /// timestamp to the given one, keeping the snapshot stable.
+    fn begin_as_of_timestamp(&'a self, timestamp: Timestamp) -> Result<Self::Transaction> {
+        if timestamp > self.clock.now() {
+            return errinput!("timestamp {timestamp} is in the future");
+        }
+        let state = self.write(Write::BeginAsOfTimestamp(timestamp))?;
+        Ok(Transaction::new(self, state))
+    }
+
+    fn register_function(&self, function: Function) -> Result<()> {
+        self.functions.register(function)
+    }
+
+    fn register_trigger(&self, callback: TriggerCallback) -> Result<()> {
+        self.triggers.register(callback)
+    }
 }
 
 /// A Raft SQL engine transaction.
@@ -105,26 +307,76 @@ impl<'a> super::Engine<'a> for Raft {
 /// can't maintain this state between individual requests (which could execute
 /// on different leaders). Instead, we use `mvcc::Transaction::resume` to resume
 /// the transaction using the provided transaction state for each request.
+///
+/// If the engine has an intent timeout, read-write transactions heartbeat when
+/// half of it has elapsed since the last heartbeat, as part of the next request.
 pub struct Transaction<'a> {
     /// The Raft SQL engine, used to communicate with Raft.
     engine: &'a Raft,
     /// The MVCC transaction state.
     state: mvcc::TransactionState,
+    /// The time of the last heartbeat.
+    heartbeat: Mutex<Instant>,
+    /// If true, this is a read-only transaction which reads from the local
+    /// node (see Raft::begin_replica_read).
+    local: bool,
 }
 
 impl<'a> Transaction<'a> {
+    /// Creates a transaction with the given state.
+    fn new(engine: &'a Raft, state: mvcc::TransactionState) -> Self {
+        Self { engine, state, heartbeat: Mutex::new(Instant::now()), local: false }
+    }
+
     /// Starts a transaction in the given mode.
     fn begin(engine: &'a Raft, read_only: bool, as_of: Option<mvcc::Version>) -> Result<Self> {
         assert!(as_of.is_none() || read_only, "can't use as_of without read_only");
         // Read-only transactions don't need to persist anything, they just need
         // to grab the current transaction state, so submit them as reads to
         // avoid a replication roundtrip.
-        let state = if read_only || as_of.is_some() {
-            engine.read(Read::BeginReadOnly { as_of })?
-        } else {
-            engine.write(Write::Begin)?
+        let timestamp = engine.clock.now();
+        let state = match engine.intent_timeout {
+            _ if read_only || as_of.is_some() => engine.read(Read::BeginReadOnly { as_of })?,
+            Some(timeout) => engine.write(Write::BeginExpiring { timestamp, timeout })?,
+            None => engine.write(Write::Begin(timestamp))?,
         };
-        Ok(Self { engine, state })
+        Ok(Self::new(engine, state))
+    }
+
+    /// Heartbeats the transaction if half of the intent timeout has elapsed
+    /// since the last heartbeat, keeping it from expiring.
+    fn heartbeat(&self) -> Result<()> {
+        let Some(timeout) = self.engine.intent_timeout.filter(|_| !self.state.read_only) else {
+            return Ok(());
+        };
+        let mut heartbeat = self.heartbeat.lock()?;
+        if heartbeat.elapsed() < timeout / 2 {
+            return Ok(());
+        }
+        let timestamp = self.engine.clock.now();
+        self.engine.write::<()>(Write::Heartbeat {
+            txn: (&self.state).into(),
+            timestamp,
+            timeout,
+        })?;
+        *heartbeat = Instant::now();
+        Ok(())
+    }
+
+    /// Writes through Raft (see Raft::write), heartbeating first if needed.
+    fn write<V: DeserializeOwned>(&self, write: Write) -> Result<V> {
+        self.heartbeat()?;
+        self.engine.write(write)
+    }
+
+    /// Reads from Raft (see Raft::read), heartbeating first if needed. Local
+    /// transactions read from the local node instead.
+    fn read<V: DeserializeOwned>(&self, read: Read) -> Result<V> {
+        if self.local {
+            return self.engine.local_read(read);
+        }
+        self.heartbeat()?;
+        self.engine.read(read)
     }
 }
 
@@ -137,38 +389,114 @@ impl super::Transaction for Transaction<'_> {
         if self.state.read_only {
             return Ok(()); // noop
         }
-        self.engine.write(Write::Commit(self.state.into()))
+        let timestamp = self.engine.clock.now();
+        self.engine.write::<()>(Write::Commit { txn: self.state.into(), timestamp })?;
+        metrics::TXN_COMMITS.inc(&[]);
+        Ok(())
     }
 
     fn rollback(self) -> Result<()> {
         if self.state.read_only {
             return Ok(()); // noop
         }
-        self.engine.write(Write::Rollback(self.state.into()))
+        self.engine.write::<()>(Write::Rollback(self.state.into()))?;
+        metrics::TXN_ROLLBACKS.inc(&[]);
+        Ok(())
     }
 
-    fn delete(&self, table: &str, ids: &[Value]) -> Result<()> {
-        self.engine.write(Write::Delete {
+    fn commit_idempotent(self, token: &str, result: Vec<u8>) -> Result<Option<Vec<u8>>> {
+        if self.state.read_only {
+            return errinput!("can't commit read-only transaction with idempotency token");
+        }
+        let existing: Option<Vec<u8>> = self.engine.write(Write::CommitIdempotent {
+            txn: self.state.into(),
+            token: token.into(),
+            result,
+            timestamp: self.engine.clock.now(),
+        })?;
+        // If the token was already committed, the transaction was rolled back.
+        match existing {
+            Some(_) => metrics::TXN_ROLLBACKS.inc(&[]),
+            None => metrics::TXN_COMMITS.inc(&[]),
+        }
+        Ok(existing)
+    }
+
+    fn get_idempotent(&self, token: &str) -> Result<Option<Vec<u8>>> {
+        self.read(Read::GetIdempotent { txn: (&self.state).into(), token: token.into() })
+    }
+
+    fn savepoint(&self, name: &str) -> Result<()> {
+        self.write(Write::Savepoint { txn: (&self.state).into(), name: name.into() })
+    }
+
+    fn rollback_to_savepoint(&self, name: &str) -> Result<()> {
+        self.write(Write::RollbackToSavepoint { txn: (&self.state).into(), name: name.into() })
+    }
+
+    fn release_savepoint(&self, name: &str) -> Result<()> {
+        self.write(Write::ReleaseSavepoint { txn: (&self.state).into(), name: name.into() })
+    }
+
+    fn cancel_lock_waits(&self) -> Result<()> {
+        if self.state.read_only {
+            return Ok(()); // noop
+        }
+        self.write(Write::CancelLockWaits((&self.state).into()))
+    }
+
+    fn lock_waits(&self) -> Option<Arc<mvcc::LockWaits>> {
+        self.engine.lock_waits.clone()
+    }
+
+    fn refresh_snapshot(&mut self) -> Result<()> {
+        self.state = self.read(Read::RefreshSnapshot { txn: (&self.state).into() })?;
+        Ok(())
+    }
+
+    fn abort_transaction(&self, version: mvcc::Version) -> Result<()> {
+        self.write(Write::AbortTransaction { txn: (&self.state).into(), version })
+    }
+
+    fn check_read(&self, table: &str, ranges: &[(Bound<Value>, Bound<Value>)]) -> Result<()> {
+        self.read(Read::Validate {
             txn: (&self.state).into(),
             table: table.into(),
-            ids: ids.into(),
+            ranges: ranges.into(),
         })
     }
 
-    fn get(&self, table: &str, ids: &[Value]) -> Result<Vec<Row>> {
-        self.engine.read(Read::Get {
+    fn delete(&self, table: &str, ids: &[Value]) -> Result<()> {
+        self.write(Write::Delete {
             txn: (&self.state).into(),
             table: table.into(),
             ids: ids.into(),
         })
     }
 
+    fn get(&self, table: &str, ids: &[Value]) -> Result<Vec<Row>> {
+        let span = trace::span("storage.get");
+        span.attr("table", table);
+        self.read(Read::Get { txn: (&self.state).into(), table: table.into(), ids: ids.into() })
+    }
+
     fn insert(&self, table: &str, rows: Vec<Row>) -> Result<()> {
-        self.engine.write(Write::Insert { txn: (&self.state).into(), table: table.into(), rows })
+        self.write(Write::Insert { txn: (&self.state).into(), table: table.into(), rows })
+    }
+
+    fn bulk_insert(&self, table: &str, rows: Vec<Row>) -> Result<()> {
+        self.write(Write::BulkInsert { txn: (&self.state).into(), table: table.into(), rows })
+    }
+
+    fn lock(&self, table: &str, ids: &[Value]) -> Result<Option<mvcc::Version>> {
+        self.write(Write::Lock { txn: (&self.state).into(), table: table.into(), ids: ids.into() })
     }
 
     fn lookup_index(&self, table: &str, column: &str, values: &[Value]) -> Result<BTreeSet<Value>> {
-        self.engine.read(Read::LookupIndex {
+        let span = trace::span("storage.lookup_index");
+        span.attr("table", table);
+        span.attr("column", column);
+        self.read(Read::LookupIndex {
             txn: (&self.state).into(),
             table: table.into(),
             column: column.into(),
@@ -176,39 +504,269 @@ impl super::Transaction for Transaction<'_> {
         })
     }
 
-    fn scan(&self, table: &str, filter: Option<Expression>) -> Result<Rows> {
-        let scan: Vec<Row> = self.engine.read(Read::Scan {
-            txn: (&self.state).into(),
-            table: table.into(),
+    fn last_write_version(&self, tables: &[String]) -> Result<mvcc::Version> {
+        self.read(Read::LastWriteVersion { txn: (&self.state).into(), tables: tables.into() })
+    }
+
+    fn last_schema_version(&self, tables: &[String]) -> Result<mvcc::Version> {
+        self.read(Read::LastSchemaVersion { txn: (&self.state).into(), tables: tables.into() })
+    }
+
+    fn changes(&self, table: &str, after: u64, limit: usize) -> Result<Vec<Change>> {
+        self.read(Read::Changes { txn: (&self.state).into(), table: table.into(), after, limit })
+    }
+
+    fn key_range(&self, table: &str) -> Result<Option<(Value, Value)>> {
+        self.read(Read::KeyRange { txn: (&self.state).into(), table: table.into() })
+    }
+
+    fn scan_range(
+        &self,
+        table: &str,
+        range: (Bound<Value>, Bound<Value>),
+        filter: Option<Expression>,
+    ) -> Result<Rows> {
+        Ok(Box::new(ScanIterator {
+            engine: self.engine.clone(),
+            txn: self.state.clone(),
+            table: table.to_string(),
+            range,
             filter,
-        })?;
-        Ok(Box::new(scan.into_iter().map(Ok)))
+            local: self.local,
+            buffer: Vec::new().into_iter(),
+            done: false,
+        }))
     }
 
     fn update(&self, table: &str, rows: BTreeMap<Value, Row>) -> Result<()> {
-        self.engine.write(Write::Update { txn: (&self.state).into(), table: table.into(), rows })
+        self.write(Write::Update { txn: (&self.state).into(), table: table.into(), rows })
     }
 }
 
 impl Catalog for Transaction<'_> {
     fn create_table(&self, schema: Table) -> Result<()> {
-        self.engine.write(Write::CreateTable { txn: (&self.state).into(), schema })
+        self.write(Write::CreateTable { txn: (&self.state).into(), schema })
     }
 
     fn drop_table(&self, table: &str, if_exists: bool) -> Result<bool> {
-        self.engine.write(Write::DropTable {
+        self.write(Write::DropTable { txn: (&self.state).into(), table: table.into(), if_exists })
+    }
+
+    fn create_index(
+        &self,
+        table: &str,
+        column: &str,
+        index_type: IndexType,
+        predicate: Option<Expression>,
+    ) -> Result<()> {
+        self.write(Write::CreateIndex {
+            txn: (&self.state).into(),
+            table: table.into(),
+            column: column.into(),
+            index_type,
+            predicate,
+        })
+    }
+
+    fn create_index_online(
+        &self,
+        table: &str,
+        column: &str,
+        index_type: IndexType,
+        predicate: Option<Expression>,
+    ) -> Result<IndexState> {
+        self.write(Write::CreateIndexOnline {
             txn: (&self.state).into(),
             table: table.into(),
+            column: column.into(),
+            index_type,
+            predicate,
+        })
+    }
+
+    fn advance_index(&self, table: &str, column: &str, from: IndexState) -> Result<IndexState> {
+        self.write(Write::AdvanceIndex {
+            txn: (&self.state).into(),
+            table: table.into(),
+            column: column.into(),
+            from,
+        })
+    }
+
+    fn abort_index(&self, table: &str, column: &str) -> Result<()> {
+        self.write(Write::AbortIndex {
+            txn: (&self.state).into(),
+            table: table.into(),
+            column: column.into(),
+        })
+    }
+
+    fn backfill_index(
+        &self,
+        table: &str,
+        column: &str,
+        after: Option<Value>,
+        limit: usize,
+    ) -> Result<Option<Value>> {
+        self.write(Write::BackfillIndex {
+            txn: (&self.state).into(),
+            table: table.into(),
+            column: column.into(),
+            after,
+            limit,
+        })
+    }
+
+    fn create_trigger(&self, table: &str, trigger: Trigger) -> Result<()> {
+        self.write(Write::CreateTrigger { txn: (&self.state).into(), table: table.into(), trigger })
+    }
+
+    fn drop_trigger(&self, table: &str, trigger: &str, if_exists: bool) -> Result<bool> {
+        self.write(Write::DropTrigger {
+            txn: (&self.state).into(),
+            table: table.into(),
+            trigger: trigger.into(),
             if_exists,
         })
     }
 
+    fn set_changefeed(&self, table: &str, changefeed: bool) -> Result<()> {
+        self.write(Write::SetChangefeed {
+            txn: (&self.state).into(),
+            table: table.into(),
+            changefeed,
+        })
+    }
+
+    fn drop_partition(&self, table: &str, partition: &str) -> Result<u64> {
+        self.write(Write::DropPartition {
+            txn: (&self.state).into(),
+            table: table.into(),
+            partition: partition.into(),
+        })
+    }
+
+    fn truncate_table(&self, table: &str) -> Result<u64> {
+        self.write(Write::TruncateTable { txn: (&self.state).into(), table: table.into() })
+    }
+
+    fn create_user(&self, user: User) -> Result<()> {
+        self.write(Write::CreateUser { txn: (&self.state).into(), user })
+    }
+
+    fn drop_user(&self, name: &str, if_exists: bool) -> Result<bool> {
+        self.write(Write::DropUser { txn: (&self.state).into(), name: name.into(), if_exists })
+    }
+
+    fn grant(&self, user: &str, table: Option<&str>, privileges: &[Privilege]) -> Result<()> {
+        self.write(Write::Grant {
+            txn: (&self.state).into(),
+            user: user.into(),
+            table: table.map(|t| t.into()),
+            privileges: privileges.into(),
+        })
+    }
+
+    fn revoke(&self, user: &str, table: Option<&str>, privileges: &[Privilege]) -> Result<()> {
+        self.write(Write::Revoke {
+            txn: (&self.state).into(),
+            user: user.into(),
+            table: table.map(|t| t.into()),
+            privileges: privileges.into(),
+        })
+    }
+
     fn get_table(&self, table: &str) -> Result<Option<Table>> {
-        self.engine.read(Read::GetTable { txn: (&self.state).into(), table: table.into() })
+        self.read(Read::GetTable { txn: (&self.state).into(), table: table.into() })
     }
 
     fn list_tables(&self) -> Result<Vec<Table>> {
-        self.engine.read(Read::ListTables { txn: (&self.state).into() })
+        self.read(Read::ListTables { txn: (&self.state).into() })
+    }
+
+    fn list_transactions(&self) -> Result<Vec<mvcc::TransactionInfo>> {
+        self.read(Read::ListTransactions { txn: (&self.state).into() })
+    }
+
+    fn get_user(&self, name: &str) -> Result<Option<User>> {
+        self.read(Read::GetUser { txn: (&self.state).into(), name: name.into() })
+    }
+
+    fn get_function(&self, name: &str) -> Result<Option<Function>> {
+        self.engine.functions.get(name)
+    }
+
+    fn get_trigger_callbacks(&self, table: &str) -> Result<Vec<TriggerCallback>> {
+        self.engine.triggers.get(table)
+    }
+}
+
+/// A Raft table scan iterator. Rows are fetched lazily in batches of
+/// SCAN_BATCH_SIZE as the iterator is consumed, such that large scans don't
+/// have to be buffered in memory. All batches read from the same transaction
+/// snapshot, so the scan is consistent.
+#[derive(Clone)]
+struct ScanIterator {
+    /// The Raft SQL engine.
+    engine: Raft,
+    /// The transaction state.
+    txn: mvcc::TransactionState,
+    /// The scanned table.
+    table: String,
+    /// The remaining primary key range to scan. The start bound is moved past
+    /// the last fetched row after each batch.
+    range: (Bound<Value>, Bound<Value>),
+    /// The scan filter, if any.
+    filter: Option<Expression>,
+    /// If true, reads from the local node (see Transaction::local).
+    local: bool,
+    /// The current batch of rows.
+    buffer: std::vec::IntoIter<Row>,
+    /// Whether the last batch has been fetched.
+    done: bool,
+}
+
+impl ScanIterator {
+    /// Fetches the next batch of rows into the buffer.
+    fn fetch(&mut self) -> Result<()> {
+        let span = trace::span("storage.scan");
+        span.attr("table", &self.table);
+        let read = Read::Scan {
+            txn: (&self.txn).into(),
+            table: (&self.table).into(),
+            range: self.range.clone(),
+            filter: self.filter.clone(),
+            limit: Raft::SCAN_BATCH_SIZE,
+        };
+        let (rows, after): (Vec<Row>, Option<Value>) = match self.local {
+            true => self.engine.local_read(read)?,
+            false => self.engine.read(read)?,
+        };
+        self.buffer = rows.into_iter();
+        match after {
+            Some(after) => self.range.0 = Bound::Excluded(after),
+            None => self.done = true,
+        }
+        Ok(())
+    }
+}
+
+impl Iterator for ScanIterator {
+    type Item = Result<Row>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(row) = self.buffer.next() {
+                return Some(Ok(row));
+            }
+            if self.done {
+                return None;
+            }
+            if let Err(error) = self.fetch() {
+                self.done = true;
+                return Some(Err(error));
+            }
+        }
     }
 }
 
@@ -217,15 +775,17 @@ impl Catalog for Transaction<'_> {
 /// work, using a `storage::Engine` for storage.
 ///
 /// For simplicity, we don't attempt to stream large requests or responses,
-/// instead simply delivering them as one large chunk. This means that e.g. a
-/// full table scan will pull the entire table into memory, serialize it, and
-/// send it across the network as one message. The simplest way to address this
-/// would likely be to send row batches, but this is fine for our purposes.
+/// instead simply delivering them as one large chunk. The exception is table
+/// scans, which are fetched in row batches via repeated Read::Scan requests
+/// (see ScanIterator), to avoid pulling entire tables into memory.
 pub struct State<E: storage::Engine + 'static> {
     /// The local SQL engine.
     local: super::Local<E>,
     /// The last applied index. This tells Raft which command to apply next.
     applied_index: raft::Index,
+    /// Recently computed checksums by applied index, for comparison across
+    /// replicas. Retains the last CHECKSUM_HISTORY entries.
+    checksums: VecDeque<(raft::Index, Checksum)>,
 }
 
 impl<E: storage::Engine> State<E> {
@@ -238,19 +798,119 @@ impl<E: storage::Engine> State<E> {
             .map(|b| bincode::deserialize(&b))
             .transpose()?
             .unwrap_or(0);
-        Ok(State { local, applied_index })
+        Ok(State { local, applied_index, checksums: VecDeque::new() })
     }
 
+    /// Returns the lock waiter wakeups of the local MVCC engine. These are
+    /// notified as the state machine applies transaction completions, and can
+    /// be passed to the node's Raft engine via Raft::set_lock_waits.
+    pub fn lock_waits(&self) -> Arc<mvcc::LockWaits> {
+        self.local.mvcc.lock_waits()
+    }
+
+    /// Restores the state machine to a point in time, by replaying committed
+    /// entries from the given Raft logs on top of it (typically a Backup). The
+    /// logs are replayed in order, e.g. incremental backups followed by the
+    /// node's Raft log, and must continue from the applied index without gaps.
+    /// Gaps due to log truncation error with Error::Truncated. If a version is
+    /// given, replay stops before the transaction at that version begins,
+    /// yielding the state seen by AS OF SYSTEM TIME at the version. Otherwise,
+    /// all committed entries are replayed.
+    ///
+    /// Transactions that were in flight at that point are rolled back, and the
+    /// applied index is reset to 0, such that the state machine can seed a new
+    /// cluster with empty Raft logs. Returns the last replayed log index.
+    pub fn restore(
+        &mut self,
+        logs: &mut [raft::Log],
+        as_of: Option<mvcc::Version>,
+    ) -> Result<raft::Index> {
+        let mut next_version = self.local.mvcc.status()?.versions + 1;
+        if let Some(as_of) = as_of {
+            if as_of < next_version {
+                return errinput!("backup is newer than version {as_of}");
+            }
+        }
+
+        'logs: for log in logs {
+            let (commit_index, _) = log.get_commit_index();
+            if self.applied_index > commit_index {
+                return errinput!(
+                    "backup at index {} is beyond Raft log commit index {commit_index}",
+                    self.applied_index
+                );
+            }
+            let mut scan = log.scan_apply(self.applied_index);
+            while let Some(entry) = scan.next().transpose()? {
+                if entry.index != self.applied_index + 1 {
+                    return errinput!("Raft log is missing index {}", self.applied_index + 1);
+                }
+                let begin = match &entry.command {
+                    raft::Command::Write(command) => {
+                        matches!(
+                            Write::decode(command)?,
+                            Write::Begin(_) | Write::BeginExpiring { .. }
+                        )
+                    }
+                    raft::Command::Noop | raft::Command::RemoveNode(_) => false,
+                };
+                if begin && Some(next_version) == as_of {
+                    break 'logs;
+                }
+                // Apply errors are returned to the client, and considered applied.
+                _ = raft::State::apply(self, entry);
+                if begin {
+                    next_version = self.local.mvcc.status()?.versions + 1;
+                }
+            }
+        }
+        if let Some(as_of) = as_of {
+            if as_of > next_version {
+                return errinput!("version {as_of} is beyond the end of the Raft log");
+            }
+        }
+
+        let index = self.applied_index;
+        self.local.mvcc.rollback_active()?;
+        self.applied_index = 0;
+        self.local.set_unversioned(Raft::APPLIED_INDEX_KEY, bincode::serialize(&0))?;
+        Ok(index)
+    }
+
+    /// The number of recent checksums retained by the state machine.
+    const CHECKSUM_HISTORY: usize = 16;
+
     /// Executes a write command.
-    fn write(&self, command: Write) -> Result<Vec<u8>> {
+    fn write(&mut self, command: Write) -> Result<Vec<u8>> {
         Ok(match command {
-            Write::Begin => self.local.begin()?.state().encode(),
-            Write::Commit(txn) => {
-                bincode::serialize(&self.local.resume(txn.into_owned())?.commit()?)
+            Write::Begin(timestamp) => self.local.begin_at(timestamp)?.state().encode(),
+            Write::BeginExpiring { timestamp, timeout } => {
+                let txn = self.local.begin_at(timestamp)?;
+                txn.heartbeat(timestamp, timeout)?;
+                txn.state().encode()
+            }
+            Write::Heartbeat { txn, timestamp, timeout } => bincode::serialize(
+                &self.local.resume(txn.into_owned())?.heartbeat(timestamp, timeout)?,
+            ),
+            Write::ResolveExpired(timestamp) => {
+                bincode::serialize(&self.local.mvcc.resolve_expired(timestamp)?)
+            }
+            Write::BeginAsOfTimestamp(timestamp) => {
+                self.local.mvcc.begin_as_of_timestamp(timestamp)?.state().encode()
             }
+            Write::Commit { txn, timestamp } => bincode::serialize(
+                &self.local.resume(txn.into_owned())?.commit_at(Some(timestamp))?,
+            ),
             Write::Rollback(txn) => {
                 bincode::serialize(&self.local.resume(txn.into_owned())?.rollback()?)
             }
+            Write::CommitIdempotent { txn, token, result, timestamp } => {
+                bincode::serialize(&self.local.resume(txn.into_owned())?.commit_idempotent_at(
+                    &token,
+                    result,
+                    Some(timestamp),
+                )?)
+            }
 
             Write::Delete { txn, table, ids } => {
                 bincode::serialize(&self.local.resume(txn.into_owned())?.delete(&table, &ids)?)
@@ -268,6 +928,102 @@ impl<E: storage::Engine> State<E> {
             Write::DropTable { txn, table, if_exists } => bincode::serialize(
                 &self.local.resume(txn.into_owned())?.drop_table(&table, if_exists)?,
             ),
+            Write::CreateIndex { txn, table, column, index_type, predicate } => bincode::serialize(
+                &self
+                    .local
+                    .resume(txn.into_owned())?
+                    .create_index(&table, &column, index_type, predicate)?,
+            ),
+            Write::CreateIndexOnline { txn, table, column, index_type, predicate } => {
+                bincode::serialize(
+                    &self
+                        .local
+                        .resume(txn.into_owned())?
+                        .create_index_online(&table, &column, index_type, predicate)?,
+                )
+            }
+            Write::AdvanceIndex { txn, table, column, from } => bincode::serialize(
+                &self.local.resume(txn.into_owned())?.advance_index(&table, &column, from)?,
+            ),
+            Write::AbortIndex { txn, table, column } => bincode::serialize(
+                &self.local.resume(txn.into_owned())?.abort_index(&table, &column)?,
+            ),
+            Write::BackfillIndex { txn, table, column, after, limit } => bincode::serialize(
+                &self
+                    .local
+                    .resume(txn.into_owned())?
+                    .backfill_index(&table, &column, after, limit)?,
+            ),
+            Write::CreateTrigger { txn, table, trigger } => bincode::serialize(
+                &self.local.resume(txn.into_owned())?.create_trigger(&table, trigger)?,
+            ),
+            Write::DropTrigger { txn, table, trigger, if_exists } => bincode::serialize(
+                &self.local.resume(txn.into_owned())?.drop_trigger(&table, &trigger, if_exists)?,
+            ),
+            Write::DropPartition { txn, table, partition } => bincode::serialize(
+                &self.local.resume(txn.into_owned())?.drop_partition(&table, &partition)?,
+            ),
+            Write::TruncateTable { txn, table } => {
+                bincode::serialize(&self.local.resume(txn.into_owned())?.truncate_table(&table)?)
+            }
+
+            Write::GarbageCollect { retention } => self.local.mvcc.gc(retention)?.encode(),
+            Write::Scrub => self.local.scrub(true)?.encode(),
+            Write::Checksum => {
+                // The command is applied at the next index.
+                let index = self.applied_index + 1;
+                let checksum = self.local.checksum()?;
+                if self.checksums.len() >= Self::CHECKSUM_HISTORY {
+                    self.checksums.pop_front();
+                }
+                self.checksums.push_back((index, checksum.clone()));
+                bincode::serialize(&(index, checksum))
+            }
+
+            Write::Savepoint { txn, name } => {
+                bincode::serialize(&self.local.resume(txn.into_owned())?.savepoint(&name)?)
+            }
+            Write::RollbackToSavepoint { txn, name } => bincode::serialize(
+                &self.local.resume(txn.into_owned())?.rollback_to_savepoint(&name)?,
+            ),
+            Write::ReleaseSavepoint { txn, name } => {
+                bincode::serialize(&self.local.resume(txn.into_owned())?.release_savepoint(&name)?)
+            }
+            Write::Lock { txn, table, ids } => {
+                bincode::serialize(&self.local.resume(txn.into_owned())?.lock(&table, &ids)?)
+            }
+            Write::CancelLockWaits(txn) => {
+                bincode::serialize(&self.local.resume(txn.into_owned())?.cancel_lock_waits()?)
+            }
+            Write::AbortTransaction { txn, version } => bincode::serialize(
+                &self.local.resume(txn.into_owned())?.abort_transaction(version)?,
+            ),
+            Write::BulkInsert { txn, table, rows } => {
+                bincode::serialize(&self.local.resume(txn.into_owned())?.bulk_insert(&table, rows)?)
+            }
+            Write::SetChangefeed { txn, table, changefeed } => bincode::serialize(
+                &self.local.resume(txn.into_owned())?.set_changefeed(&table, changefeed)?,
+            ),
+            Write::CreateUser { txn, user } => {
+                bincode::serialize(&self.local.resume(txn.into_owned())?.create_user(user)?)
+            }
+            Write::DropUser { txn, name, if_exists } => bincode::serialize(
+                &self.local.resume(txn.into_owned())?.drop_user(&name, if_exists)?,
+            ),
+            Write::Grant { txn, user, table, privileges } => {
+                bincode::serialize(&self.local.resume(txn.into_owned())?.grant(
+                    &user,
+                    table.as_deref(),
+                    &privileges,
+                )?)
+            }
+            Write::Revoke { txn, user, table, privileges } => {
+                bincode::serialize(&self.local.resume(txn.into_owned())?.revoke(
+                    &user,
+                    table.as_deref(),
+                    &privileges,
+                )?)
+            }
         })
     }
 }
@@ -281,15 +1037,15 @@ impl<E: storage::Engine> raft::State for State<E> {
         assert_eq!(entry.index, self.applied_index + 1, "entry index not after applied index");
 
         let result = match &entry.command {
-            Some(command) => match self.write(Write::decode(command)?) {
+            raft::Command::Write(command) => match self.write(Write::decode(command)?) {
                 // Panic on non-deterministic apply failures, to prevent node
                 // state divergence. See [`raft::State`] docs for details.
                 Err(e) if !e.is_deterministic() => panic!("non-deterministic apply failure: {e}"),
                 result => result,
             },
-            // Raft submits noop commands on leader changes. Ignore them, but
-            // record the applied index below.
-            None => Ok(Vec::new()),
+            // Raft submits noop commands on leader changes, and handles node
+            // removals itself. Ignore them, but record the applied index below.
+            raft::Command::Noop | raft::Command::RemoveNode(_) => Ok(Vec::new()),
         };
 
         // Persist the applied index. We don't have to flush, because it's ok to
@@ -310,6 +1066,15 @@ impl<E: storage::Engine> raft::State for State<E> {
                 txn.state().encode()
             }
             Read::Status => self.local.mvcc.status()?.encode(),
+            Read::Scrub => self.local.scrub(false)?.encode(),
+            Read::Checksum(index) => bincode::serialize(
+                &self.checksums.iter().find(|(i, _)| *i == index).map(|(_, checksum)| checksum),
+            ),
+            Read::Backup => {
+                let mut engine = self.local.mvcc.engine.lock()?;
+                let data = engine.scan(..).collect::<Result<_>>()?;
+                Backup { applied_index: self.applied_index, data }.encode()
+            }
 
             Read::Get { txn, table, ids } => {
                 self.local.resume(txn.into_owned())?.get(&table, &ids)?.encode()
@@ -319,14 +1084,23 @@ impl<E: storage::Engine> raft::State for State<E> {
                 .resume(txn.into_owned())?
                 .lookup_index(&table, &column, &values)?
                 .encode(),
-            Read::Scan { txn, table, filter } => {
-                // For simplicity, buffer the entire scan. See `State` comment.
-                self.local
-                    .resume(txn.into_owned())?
-                    .scan(&table, filter)?
-                    .collect::<Result<Vec<Row>>>()?
-                    .encode()
+            Read::LastWriteVersion { txn, tables } => {
+                self.local.resume(txn.into_owned())?.last_write_version(&tables)?.encode()
+            }
+            Read::LastSchemaVersion { txn, tables } => {
+                self.local.resume(txn.into_owned())?.last_schema_version(&tables)?.encode()
+            }
+            Read::Changes { txn, table, after, limit } => {
+                self.local.resume(txn.into_owned())?.changes(&table, after, limit)?.encode()
             }
+            Read::KeyRange { txn, table } => {
+                self.local.resume(txn.into_owned())?.key_range(&table)?.encode()
+            }
+            Read::Scan { txn, table, range, filter, limit } => self
+                .local
+                .resume(txn.into_owned())?
+                .scan_batch(&table, range, filter, limit)?
+                .encode(),
 
             Read::GetTable { txn, table } => {
                 self.local.resume(txn.into_owned())?.get_table(&table)?.encode()
@@ -334,8 +1108,34 @@ impl<E: storage::Engine> raft::State for State<E> {
             Read::ListTables { txn } => {
                 self.local.resume(txn.into_owned())?.list_tables()?.encode()
             }
+            Read::ListTransactions { txn } => {
+                self.local.resume(txn.into_owned())?.list_transactions()?.encode()
+            }
+            Read::GetUser { txn, name } => {
+                self.local.resume(txn.into_owned())?.get_user(&name)?.encode()
+            }
+
+            Read::RefreshSnapshot { txn } => {
+                let mut txn = self.local.resume(txn.into_owned())?;
+                txn.refresh_snapshot()?;
+                txn.state().encode()
+            }
+
+            Read::Validate { txn, table, ranges } => bincode::serialize(
+                &self.local.resume(txn.into_owned())?.check_read(&table, &ranges)?,
+            ),
+
+            Read::GetIdempotent { txn, token } => {
+                bincode::serialize(&self.local.resume(txn.into_owned())?.get_idempotent(&token)?)
+            }
         })
     }
+
+    fn flush(&mut self) -> Result<()> {
+        // Applied writes (and the applied index) aren't flushed on their own,
+        // since they can be replayed from the Raft log.
+        self.local.mvcc.engine.lock()?.flush()
+    }
 }
 
 /// A Raft engine read. Values correspond to engine method parameters. Uses
@@ -346,6 +1146,7 @@ pub enum Read<'a> {
         as_of: Option<mvcc::Version>,
     },
     Status,
+    Backup,
 
     Get {
         txn: Cow<'a, mvcc::TransactionState>,
@@ -358,10 +1159,26 @@ pub enum Read<'a> {
         column: Cow<'a, str>,
         values: Cow<'a, [Value]>,
     },
+    LastWriteVersion {
+        txn: Cow<'a, mvcc::TransactionState>,
+        tables: Cow<'a, [String]>,
+    },
+    Changes {
+        txn: Cow<'a, mvcc::TransactionState>,
+        table: Cow<'a, str>,
+        after: u64,
+        limit: usize,
+    },
+    KeyRange {
+        txn: Cow<'a, mvcc::TransactionState>,
+        table: Cow<'a, str>,
+    },
     Scan {
         txn: Cow<'a, mvcc::TransactionState>,
         table: Cow<'a, str>,
+        range: (Bound<Value>, Bound<Value>),
         filter: Option<Expression>,
+        limit: usize,
     },
 
     GetTable {
@@ -371,6 +1188,63 @@ pub enum Read<'a> {
     ListTables {
         txn: Cow<'a, mvcc::TransactionState>,
     },
+    ListTransactions {
+        txn: Cow<'a, mvcc::TransactionState>,
+    },
+    GetUser {
+        txn: Cow<'a, mvcc::TransactionState>,
+        name: Cow<'a, str>,
+    },
+
+    RefreshSnapshot {
+        txn: Cow<'a, mvcc::TransactionState>,
+    },
+
+    Validate {
+        txn: Cow<'a, mvcc::TransactionState>,
+        table: Cow<'a, str>,
+        ranges: Cow<'a, [(Bound<Value>, Bound<Value>)]>,
+    },
+
+    GetIdempotent {
+        txn: Cow<'a, mvcc::TransactionState>,
+        token: Cow<'a, str>,
+    },
+
+    Scrub,
+    Checksum(raft::Index),
+
+    LastSchemaVersion {
+        txn: Cow<'a, mvcc::TransactionState>,
+        tables: Cow<'a, [String]>,
+    },
+}
+
+impl Read<'_> {
+    /// Returns true if this reads the snapshot of a read-only transaction.
+    fn is_snapshot(&self) -> bool {
+        match self {
+            Self::BeginReadOnly { .. }
+            | Self::Status
+            | Self::Backup
+            | Self::Scrub
+            | Self::Checksum(_)
+            | Self::Changes { .. }
+            | Self::ListTransactions { .. }
+            | Self::RefreshSnapshot { .. }
+            | Self::Validate { .. } => false,
+            Self::Get { txn, .. }
+            | Self::LookupIndex { txn, .. }
+            | Self::LastWriteVersion { txn, .. }
+            | Self::LastSchemaVersion { txn, .. }
+            | Self::KeyRange { txn, .. }
+            | Self::Scan { txn, .. }
+            | Self::GetTable { txn, .. }
+            | Self::ListTables { txn }
+            | Self::GetUser { txn, .. }
+            | Self::GetIdempotent { txn, .. } => txn.read_only,
+        }
+    }
 }
 
 impl encoding::Value for Read<'_> {}
@@ -379,16 +1253,168 @@ impl encoding::Value for Read<'_> {}
 /// Cows to allow borrowed encoding (for borrowed params) and owned decoding.
 #[derive(Debug, Serialize, Deserialize)]
 pub enum Write<'a> {
-    Begin,
-    Commit(Cow<'a, mvcc::TransactionState>),
+    Begin(Timestamp),
+    Commit {
+        txn: Cow<'a, mvcc::TransactionState>,
+        timestamp: Timestamp,
+    },
     Rollback(Cow<'a, mvcc::TransactionState>),
 
-    Delete { txn: Cow<'a, mvcc::TransactionState>, table: Cow<'a, str>, ids: Cow<'a, [Value]> },
-    Insert { txn: Cow<'a, mvcc::TransactionState>, table: Cow<'a, str>, rows: Vec<Row> },
-    Update { txn: Cow<'a, mvcc::TransactionState>, table: Cow<'a, str>, rows: BTreeMap<Value, Row> },
+    Delete {
+        txn: Cow<'a, mvcc::TransactionState>,
+        table: Cow<'a, str>,
+        ids: Cow<'a, [Value]>,
+    },
+    Insert {
+        txn: Cow<'a, mvcc::TransactionState>,
+        table: Cow<'a, str>,
+        rows: Vec<Row>,
+    },
+    Update {
+        txn: Cow<'a, mvcc::TransactionState>,
+        table: Cow<'a, str>,
+        rows: BTreeMap<Value, Row>,
+    },
 
-    CreateTable { txn: Cow<'a, mvcc::TransactionState>, schema: Table },
-    DropTable { txn: Cow<'a, mvcc::TransactionState>, table: Cow<'a, str>, if_exists: bool },
+    CreateTable {
+        txn: Cow<'a, mvcc::TransactionState>,
+        schema: Table,
+    },
+    DropTable {
+        txn: Cow<'a, mvcc::TransactionState>,
+        table: Cow<'a, str>,
+        if_exists: bool,
+    },
+    CreateIndex {
+        txn: Cow<'a, mvcc::TransactionState>,
+        table: Cow<'a, str>,
+        column: Cow<'a, str>,
+        index_type: IndexType,
+        predicate: Option<Expression>,
+    },
+    CreateIndexOnline {
+        txn: Cow<'a, mvcc::TransactionState>,
+        table: Cow<'a, str>,
+        column: Cow<'a, str>,
+        index_type: IndexType,
+        predicate: Option<Expression>,
+    },
+    AdvanceIndex {
+        txn: Cow<'a, mvcc::TransactionState>,
+        table: Cow<'a, str>,
+        column: Cow<'a, str>,
+        from: IndexState,
+    },
+    AbortIndex {
+        txn: Cow<'a, mvcc::TransactionState>,
+        table: Cow<'a, str>,
+        column: Cow<'a, str>,
+    },
+    BackfillIndex {
+        txn: Cow<'a, mvcc::TransactionState>,
+        table: Cow<'a, str>,
+        column: Cow<'a, str>,
+        after: Option<Value>,
+        limit: usize,
+    },
+    CreateTrigger {
+        txn: Cow<'a, mvcc::TransactionState>,
+        table: Cow<'a, str>,
+        trigger: Trigger,
+    },
+    DropTrigger {
+        txn: Cow<'a, mvcc::TransactionState>,
+        table: Cow<'a, str>,
+        trigger: Cow<'a, str>,
+        if_exists: bool,
+    },
+    DropPartition {
+        txn: Cow<'a, mvcc::TransactionState>,
+        table: Cow<'a, str>,
+        partition: Cow<'a, str>,
+    },
+    TruncateTable {
+        txn: Cow<'a, mvcc::TransactionState>,
+        table: Cow<'a, str>,
+    },
+
+    GarbageCollect {
+        retention: u64,
+    },
+
+    Savepoint {
+        txn: Cow<'a, mvcc::TransactionState>,
+        name: Cow<'a, str>,
+    },
+    RollbackToSavepoint {
+        txn: Cow<'a, mvcc::TransactionState>,
+        name: Cow<'a, str>,
+    },
+    ReleaseSavepoint {
+        txn: Cow<'a, mvcc::TransactionState>,
+        name: Cow<'a, str>,
+    },
+    Lock {
+        txn: Cow<'a, mvcc::TransactionState>,
+        table: Cow<'a, str>,
+        ids: Cow<'a, [Value]>,
+    },
+    CancelLockWaits(Cow<'a, mvcc::TransactionState>),
+    AbortTransaction {
+        txn: Cow<'a, mvcc::TransactionState>,
+        version: mvcc::Version,
+    },
+    BulkInsert {
+        txn: Cow<'a, mvcc::TransactionState>,
+        table: Cow<'a, str>,
+        rows: Vec<Row>,
+    },
+    SetChangefeed {
+        txn: Cow<'a, mvcc::TransactionState>,
+        table: Cow<'a, str>,
+        changefeed: bool,
+    },
+    CreateUser {
+        txn: Cow<'a, mvcc::TransactionState>,
+        user: User,
+    },
+    DropUser {
+        txn: Cow<'a, mvcc::TransactionState>,
+        name: Cow<'a, str>,
+        if_exists: bool,
+    },
+    Grant {
+        txn: Cow<'a, mvcc::TransactionState>,
+        user: Cow<'a, str>,
+        table: Option<Cow<'a, str>>,
+        privileges: Cow<'a, [Privilege]>,
+    },
+    Revoke {
+        txn: Cow<'a, mvcc::TransactionState>,
+        user: Cow<'a, str>,
+        table: Option<Cow<'a, str>>,
+        privileges: Cow<'a, [Privilege]>,
+    },
+    CommitIdempotent {
+        txn: Cow<'a, mvcc::TransactionState>,
+        token: Cow<'a, str>,
+        #[serde(with = "serde_bytes")]
+        result: Vec<u8>,
+        timestamp: Timestamp,
+    },
+    Scrub,
+    Checksum,
+    BeginAsOfTimestamp(Timestamp),
+    BeginExpiring {
+        timestamp: Timestamp,
+        timeout: Duration,
+    },
+    Heartbeat {
+        txn: Cow<'a, mvcc::TransactionState>,
+        timestamp: Timestamp,
+        timeout: Duration,
+    },
+    ResolveExpired(Timestamp),
 }
 
 impl encoding::Value for Write<'_> {}
@@ -399,3 +1425,50 @@ pub struct Status {
     pub raft: raft::Status,
     pub mvcc: mvcc::Status,
 }
+
+/// A full backup of the SQL state machine, as the raw key/value pairs of its
+/// storage engine at the given applied Raft index. Combined with the Raft log,
+/// which retains all entries, it can be restored to any later point in time
+/// via State::restore.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Backup {
+    /// The last Raft log index applied to the backup.
+    pub applied_index: raft::Index,
+    /// The storage engine key/value pairs, in key order.
+    pub data: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+impl encoding::Value for Backup {}
+
+/// An incremental backup, as the committed Raft log entries after the index of
+/// a previous full or incremental backup. It's restored by layering it on top
+/// of the previous backups, replaying its entries via State::restore.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct IncrementalBackup {
+    /// The Raft log index of the previous backup.
+    pub base_index: raft::Index,
+    /// The committed entries after the base index, in index order.
+    pub entries: Vec<raft::Entry>,
+}
+
+impl encoding::Value for IncrementalBackup {}
+
+impl IncrementalBackup {
+    /// Returns the last Raft log index in the backup, which later incremental
+    /// backups can build on.
+    pub fn index(&self) -> raft::Index {
+        self.entries.last().map(|e| e.index).unwrap_or(self.base_index)
+    }
+
+    /// Writes the backup to the given storage engine as a Raft log, which can
+    /// be opened via raft::Log::new and replayed via State::restore. The log
+    /// only contains the backup's entries, committed up to its last index.
+    pub fn write(self, engine: &mut dyn storage::Engine) -> Result<()> {
+        let commit = (self.index(), self.entries.last().map(|e| e.term).unwrap_or(0));
+        for entry in self.entries {
+            engine.set(&raft::Key::Entry(entry.index).encode(), entry.encode())?;
+        }
+        engine.set(&raft::Key::CommitIndex.encode(), bincode::serialize(&commit))?;
+        engine.flush()
+    }
+}
@@ -1,51 +1,948 @@
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
 use itertools::Itertools as _;
 use log::error;
 use serde::{Deserialize, Serialize};
 
-use super::raft::{Raft, Status};
-use super::{Engine, Transaction as _};
+use super::audit::{AuditEvent, AuditKind};
+use super::local::{Checksum, Scrub};
+use super::raft::{Backup, IncrementalBackup, Raft, Status};
+use super::slowlog::SlowQuery;
+use super::{
+    AuditLog, Catalog as _, Engine, PlanCache, ResultCache, SessionTransaction, SlowQueryLog,
+    Statistics, Temporary, Transaction as _,
+};
+use crate::clock::{self, Clock};
+use crate::encoding::bincode;
 use crate::error::{Error, Result};
-use crate::sql::execution::ExecutionResult;
-use crate::sql::parser::{Parser, ast};
-use crate::sql::planner::Plan;
-use crate::sql::types::{Label, Row, Rows, Value};
+use crate::sql::execution::{CancelToken, ExecutionResult, MemoryAccountant, Returning};
+use crate::sql::parser::{Parser, ast, redact};
+use crate::sql::planner::{Node, OptimizerOptions, Plan};
+use crate::sql::types::{IndexState, Label, Privilege, Row, Rows, User, Value};
 use crate::storage::mvcc;
 use crate::{errdata, errinput};
+use crate::{logging, metrics, raft, trace};
+
+/// The number of rows to backfill per transaction in online index builds.
+const BACKFILL_BATCH_SIZE: usize = 1000;
+
+/// How long to wait between checks for concurrent transactions to finish
+/// during online index builds.
+const BACKFILL_WAIT: Duration = Duration::from_millis(10);
+
+/// The default time to wait for row locks held by other transactions in
+/// SELECT FOR UPDATE before erroring (see Session::set_lock_timeout).
+const LOCK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long to wait for the transaction blocking a row lock to complete
+/// before checking the lock timeout and cancellation again. Waiters are woken
+/// up as soon as the blocker completes, unless the engine doesn't support
+/// lock waiter wakeups, in which case this is the polling interval.
+const LOCK_WAIT: Duration = Duration::from_millis(100);
+
+/// The next session ID.
+static NEXT_SESSION_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Session variables, which can be set via SET and shown via SHOW.
+pub const VARIABLES: [&str; 12] = [
+    "enable_hash_join",
+    "enable_index_lookup",
+    "idle_transaction_timeout",
+    "intervalstyle",
+    "lock_timeout",
+    "memory_limit",
+    "parallelism",
+    "replica_reads",
+    "statement_timeout",
+    "timezone",
+    "transaction_isolation",
+    "transaction_timeout",
+];
 
 /// A SQL client session. Executes raw SQL statements against a SQL engine and
-/// handles transaction control.
+/// handles transaction control. Temporary tables are stored in the session, and
+/// dropped with it.
 pub struct Session<'a, E: Engine<'a>> {
+    /// The session ID, unique within the process. Used to identify the
+    /// session in logs.
+    id: u64,
     /// The SQL engine.
     engine: &'a E,
+    /// The session's temporary tables.
+    temporary: Temporary,
     /// The current transaction, if any.
-    txn: Option<E::Transaction>,
+    txn: Option<SessionTransaction<E::Transaction>>,
+    /// The number of partitions to execute table scans and aggregations
+    /// across. 1 disables parallel execution.
+    parallelism: usize,
+    /// The memory limit for each statement in bytes, if any.
+    memory_limit: Option<usize>,
+    /// The query result cache, if any. Usually shared with other sessions.
+    result_cache: Option<Arc<ResultCache>>,
+    /// The query plan cache, if any. Usually shared with other sessions.
+    plan_cache: Option<Arc<PlanCache>>,
+    /// The slow query log, if any. Usually shared with other sessions.
+    slow_query_log: Option<Arc<SlowQueryLog>>,
+    /// Statement and table statistics, if any. Usually shared with other
+    /// sessions.
+    statistics: Option<Arc<Statistics>>,
+    /// The plan summary of the current statement, if it was planned and the
+    /// slow query log is enabled.
+    plan_summary: Option<String>,
+    /// The audit log, if any. Usually shared with other sessions.
+    audit_log: Option<Arc<AuditLog>>,
+    /// How long to wait for row locks in SELECT FOR UPDATE.
+    lock_timeout: Duration,
+    /// How long an explicit transaction can run before it's rolled back.
+    transaction_timeout: Option<Duration>,
+    /// How long an explicit transaction can be idle between statements
+    /// before it's rolled back.
+    idle_timeout: Option<Duration>,
+    /// When the current explicit transaction began.
+    txn_started: Instant,
+    /// When the last statement completed.
+    txn_idle: Instant,
+    /// The version of an explicit transaction that timed out and was rolled
+    /// back, until the client ends it with ROLLBACK or COMMIT.
+    timed_out: Option<mvcc::Version>,
+    /// The admin superuser password, if authentication is enabled.
+    admin_password: Option<String>,
+    /// The authenticated user, if any.
+    user: Option<String>,
+    /// The isolation level of new explicit read-write transactions.
+    isolation: mvcc::IsolationLevel,
+    /// If true, read-only transactions read from the local replica, which may
+    /// be stale (see Engine::begin_replica_read).
+    replica_reads: bool,
+    /// How long a statement can execute before it's cancelled.
+    statement_timeout: Option<Duration>,
+    /// The session time zone, as UTC or a +HH:MM offset.
+    timezone: String,
+    /// The interval output format for Postgres clients.
+    interval_style: IntervalStyle,
+    /// If false, the optimizer doesn't use hash joins, unless hinted.
+    enable_hash_join: bool,
+    /// If false, the optimizer doesn't use primary key or index lookups,
+    /// unless hinted.
+    enable_index_lookup: bool,
+    /// The original values of session variables changed via SET, which SET
+    /// ... TO DEFAULT and RESET restore.
+    defaults: BTreeMap<String, String>,
+    /// The directory that relative IMPORT and EXPORT file paths are resolved
+    /// against.
+    file_dir: Option<PathBuf>,
+    /// The clock used for transaction and statement timeouts.
+    clock: Arc<dyn Clock>,
 }
 
 impl<'a, E: Engine<'a>> Session<'a, E> {
     /// Creates a new session using the given SQL engine.
     pub fn new(engine: &'a E) -> Self {
-        Self { engine, txn: None }
+        let clock = clock::system();
+        Self {
+            id: NEXT_SESSION_ID.fetch_add(1, Ordering::Relaxed),
+            engine,
+            temporary: Temporary::new(),
+            txn: None,
+            parallelism: 1,
+            memory_limit: None,
+            result_cache: None,
+            plan_cache: None,
+            slow_query_log: None,
+            statistics: None,
+            plan_summary: None,
+            audit_log: None,
+            lock_timeout: LOCK_TIMEOUT,
+            transaction_timeout: None,
+            idle_timeout: None,
+            txn_started: clock.now(),
+            txn_idle: clock.now(),
+            timed_out: None,
+            admin_password: None,
+            user: None,
+            isolation: mvcc::IsolationLevel::Snapshot,
+            replica_reads: false,
+            statement_timeout: None,
+            timezone: "UTC".to_string(),
+            interval_style: IntervalStyle::Postgres,
+            enable_hash_join: true,
+            enable_index_lookup: true,
+            defaults: BTreeMap::new(),
+            file_dir: None,
+            clock,
+        }
+    }
+
+    /// Returns the session ID.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Returns the session's parallelism (see set_parallelism).
+    pub fn parallelism(&self) -> usize {
+        self.parallelism
+    }
+
+    /// Sets the number of partitions to execute table scans and aggregations
+    /// across, using a worker thread per partition. 1 disables parallel
+    /// execution (the default).
+    pub fn set_parallelism(&mut self, parallelism: usize) {
+        self.parallelism = parallelism;
+    }
+
+    /// Returns the session's statement memory limit (see set_memory_limit).
+    pub fn memory_limit(&self) -> Option<usize> {
+        self.memory_limit
+    }
+
+    /// Sets the memory limit for each statement in bytes, or None for no
+    /// limit (the default). A statement whose buffered rows (e.g. for sorting,
+    /// hash joins, or aggregation) exceed the limit errors.
+    pub fn set_memory_limit(&mut self, memory_limit: Option<usize>) {
+        self.memory_limit = memory_limit;
+    }
+
+    /// Returns the session's row lock timeout (see set_lock_timeout).
+    pub fn lock_timeout(&self) -> Duration {
+        self.lock_timeout
+    }
+
+    /// Sets how long SELECT FOR UPDATE waits for row locks held by other
+    /// transactions before erroring with Error::Locked, which is retryable.
+    /// Defaults to 10 seconds.
+    pub fn set_lock_timeout(&mut self, lock_timeout: Duration) {
+        self.lock_timeout = lock_timeout;
+    }
+
+    /// Returns the session's transaction timeout (see set_transaction_timeout).
+    pub fn transaction_timeout(&self) -> Option<Duration> {
+        self.transaction_timeout
+    }
+
+    /// Sets how long an explicit transaction can run before it's rolled back,
+    /// or None for no limit (the default). The timeout is checked before each
+    /// statement and by check_timeout(), not while a statement is executing.
+    pub fn set_transaction_timeout(&mut self, transaction_timeout: Option<Duration>) {
+        self.transaction_timeout = transaction_timeout;
+    }
+
+    /// Returns the session's idle transaction timeout (see set_idle_timeout).
+    pub fn idle_timeout(&self) -> Option<Duration> {
+        self.idle_timeout
+    }
+
+    /// Sets how long an explicit transaction can be idle between statements
+    /// before it's rolled back, or None for no limit (the default). This
+    /// releases the locks and write conflicts held by transactions of clients
+    /// that have gone away.
+    pub fn set_idle_timeout(&mut self, idle_timeout: Option<Duration>) {
+        self.idle_timeout = idle_timeout;
+    }
+
+    /// Returns the session's statement timeout (see set_statement_timeout).
+    pub fn statement_timeout(&self) -> Option<Duration> {
+        self.statement_timeout
+    }
+
+    /// Sets how long a statement can execute before it's cancelled, or None
+    /// for no limit (the default). A timed out statement errors with
+    /// Error::Timeout. In an explicit transaction, the transaction is also
+    /// rolled back to release its locks and writes, and the client must end
+    /// it with ROLLBACK.
+    pub fn set_statement_timeout(&mut self, statement_timeout: Option<Duration>) {
+        self.statement_timeout = statement_timeout;
+    }
+
+    /// Returns the session time zone, as UTC or a +HH:MM offset. There are no
+    /// time types yet, but the time zone is reported to Postgres clients.
+    pub fn timezone(&self) -> &str {
+        &self.timezone
     }
 
-    /// Executes a client statement.
+    /// Returns the interval output format for Postgres clients.
+    pub fn interval_style(&self) -> IntervalStyle {
+        self.interval_style
+    }
+
+    /// Returns the optimizer options for the given statement, from the
+    /// enable_* session variables, the statement's query hints, and the
+    /// table statistics (if enabled).
+    pub fn optimizer_options(&self, statement: &ast::Statement) -> OptimizerOptions {
+        OptimizerOptions {
+            hash_join: self.enable_hash_join,
+            index_lookup: self.enable_index_lookup,
+            hints: statement.hints().to_vec(),
+            statistics: self.statistics.clone(),
+        }
+    }
+
+    /// Returns the value of a session variable (see VARIABLES) as a string.
+    /// Durations are given in milliseconds, and 0 means no limit.
+    pub fn variable(&self, name: &str) -> Result<String> {
+        let millis = |duration: Option<Duration>| duration.map_or(0, |d| d.as_millis());
+        let boolean = |value: bool| if value { "on" } else { "off" }.to_string();
+        Ok(match name {
+            "enable_hash_join" => boolean(self.enable_hash_join),
+            "enable_index_lookup" => boolean(self.enable_index_lookup),
+            "idle_transaction_timeout" => millis(self.idle_timeout).to_string(),
+            "intervalstyle" => self.interval_style.to_string(),
+            "lock_timeout" => self.lock_timeout.as_millis().to_string(),
+            "memory_limit" => self.memory_limit.unwrap_or(0).to_string(),
+            "parallelism" => self.parallelism.to_string(),
+            "replica_reads" => boolean(self.replica_reads),
+            "statement_timeout" => millis(self.statement_timeout).to_string(),
+            "timezone" => self.timezone.clone(),
+            "transaction_isolation" => self.isolation.to_string().to_lowercase(),
+            "transaction_timeout" => millis(self.transaction_timeout).to_string(),
+            name => return errinput!("unknown session variable {name}"),
+        })
+    }
+
+    /// Sets the value of a session variable (see VARIABLES) from a string,
+    /// as given by SET.
+    fn set_variable(&mut self, name: &str, value: &str) -> Result<()> {
+        let number = || match value.parse::<u64>() {
+            Ok(number) => Ok(number),
+            Err(_) => errinput!("invalid value {value} for {name}, expected non-negative integer"),
+        };
+        let millis =
+            || -> Result<_> { Ok(Some(number()?).filter(|n| *n > 0).map(Duration::from_millis)) };
+        let boolean = || match value.to_lowercase().as_str() {
+            "on" | "true" => Ok(true),
+            "off" | "false" => Ok(false),
+            _ => errinput!("invalid value {value} for {name}, expected on or off"),
+        };
+        match name {
+            "enable_hash_join" => self.enable_hash_join = boolean()?,
+            "enable_index_lookup" => self.enable_index_lookup = boolean()?,
+            "idle_transaction_timeout" => self.idle_timeout = millis()?,
+            "intervalstyle" => {
+                self.interval_style = match value.to_lowercase().as_str() {
+                    "postgres" => IntervalStyle::Postgres,
+                    "iso_8601" => IntervalStyle::Iso8601,
+                    _ => return errinput!("invalid intervalstyle {value}"),
+                }
+            }
+            "lock_timeout" => self.lock_timeout = Duration::from_millis(number()?),
+            "memory_limit" => self.memory_limit = Some(number()? as usize).filter(|n| *n > 0),
+            "parallelism" => match number()? {
+                0 => return errinput!("parallelism must be at least 1"),
+                n => self.parallelism = n as usize,
+            },
+            "replica_reads" => self.replica_reads = boolean()?,
+            "statement_timeout" => self.statement_timeout = millis()?,
+            "timezone" => self.timezone = parse_timezone(value)?,
+            "transaction_isolation" => {
+                use mvcc::IsolationLevel::*;
+                self.isolation = match value.to_lowercase().replace('_', " ").as_str() {
+                    "read committed" => ReadCommitted,
+                    "snapshot" => Snapshot,
+                    "serializable" => Serializable,
+                    _ => return errinput!("invalid transaction_isolation {value}"),
+                }
+            }
+            "transaction_timeout" => self.transaction_timeout = millis()?,
+            name => return errinput!("unknown session variable {name}"),
+        }
+        Ok(())
+    }
+
+    /// Enables authentication with the given admin superuser password, or
+    /// disables it with None (the default). When enabled, the session must be
+    /// authenticated via authenticate() before executing statements, and users
+    /// other than admin can only access tables they've been granted privileges
+    /// on (see Privilege).
+    pub fn set_admin_password(&mut self, admin_password: Option<String>) {
+        self.admin_password = admin_password;
+    }
+
+    /// Returns the authenticated user, if any.
+    pub fn user(&self) -> Option<&str> {
+        self.user.as_deref()
+    }
+
+    /// Authenticates the session as the given user. The admin superuser's
+    /// password is given by set_admin_password(), other users are stored in
+    /// the catalog. If authentication is disabled, this does nothing.
+    pub fn authenticate(&mut self, user: &str, password: &str) -> Result<()> {
+        let Some(admin_password) = self.admin_password.clone() else {
+            return Ok(());
+        };
+        let valid = match user {
+            User::ADMIN => password == admin_password,
+            user => self.with_txn(true, |txn| {
+                Ok(txn.get_user(user)?.is_some_and(|user| user.verify_password(password)))
+            })?,
+        };
+        if !valid {
+            return errinput!("invalid user name or password");
+        }
+        self.user = Some(user.to_string());
+        Ok(())
+    }
+
+    /// Errors if authentication is enabled and the session isn't
+    /// authenticated.
+    pub fn check_authenticated(&self) -> Result<()> {
+        if self.admin_password.is_some() && self.user.is_none() {
+            return errinput!("not authenticated");
+        }
+        Ok(())
+    }
+
+    /// Errors if authentication is enabled and the session isn't
+    /// authenticated as the admin superuser.
+    pub fn check_superuser(&self) -> Result<()> {
+        self.check_authenticated()?;
+        match self.user.as_deref() {
+            Some(User::ADMIN) | None => Ok(()),
+            Some(_) => errinput!("permission denied: superuser required"),
+        }
+    }
+
+    /// Errors if authentication is enabled and the session's user doesn't
+    /// have the given privilege on a table, or on all tables if None.
+    /// Temporary tables are only visible to the session, and tables that don't
+    /// exist error during planning, so neither require privileges.
+    pub fn check_privilege(&mut self, table: Option<&str>, privilege: Privilege) -> Result<()> {
+        self.check_privileges(&[(table, privilege)])
+    }
+
+    /// Like check_privilege(), but for several privileges.
+    fn check_privileges(&mut self, privileges: &[(Option<&str>, Privilege)]) -> Result<()> {
+        self.check_authenticated()?;
+        let Some(name) = self.user.clone().filter(|user| user != User::ADMIN) else {
+            return Ok(());
+        };
+        if privileges.is_empty() {
+            return Ok(());
+        }
+        self.with_txn(true, |txn| {
+            let user = txn.get_user(&name)?;
+            for (table, privilege) in privileges.iter().copied() {
+                if let Some(table) = table {
+                    if txn.get_table(table)?.is_none() || txn.is_temporary(table)? {
+                        continue;
+                    }
+                }
+                if !user.as_ref().is_some_and(|user| user.has_privilege(table, privilege)) {
+                    return match table {
+                        Some(table) => errinput!(
+                            "permission denied: user {name} has no {privilege} privilege on table {table}"
+                        ),
+                        None => errinput!("permission denied: user {name} has no {privilege} privilege"),
+                    };
+                }
+            }
+            Ok(())
+        })
+    }
+
+    /// Returns true if the session is in an explicit transaction, including
+    /// one that timed out and must be ended with ROLLBACK.
+    pub fn in_transaction(&self) -> bool {
+        self.txn.is_some() || self.timed_out.is_some()
+    }
+
+    /// Returns when the current explicit transaction times out, if it has a
+    /// timeout. Callers should call check_timeout() at this point if the
+    /// session is still idle.
+    pub fn timeout_deadline(&self) -> Option<Instant> {
+        self.txn.as_ref()?;
+        let started = self.transaction_timeout.map(|timeout| self.txn_started + timeout);
+        let idle = self.idle_timeout.map(|timeout| self.txn_idle + timeout);
+        started.into_iter().chain(idle).min()
+    }
+
+    /// Rolls back the current explicit transaction if it has timed out.
+    /// Subsequent statements then error until the client runs ROLLBACK or
+    /// COMMIT, rather than running in implicit transactions.
+    pub fn check_timeout(&mut self) -> Result<()> {
+        if self.timeout_deadline().is_none_or(|deadline| self.clock.now() < deadline) {
+            return Ok(());
+        }
+        let txn = self.txn.take().expect("no transaction");
+        self.timed_out = Some(txn.version());
+        txn.rollback()
+    }
+
+    /// Returns the current wall-clock time of the session's clock, in
+    /// nanoseconds since the Unix epoch. Statements are planned at this time,
+    /// which determines which rows have expired in tables with a TTL.
+    pub fn wall_time(&self) -> u64 {
+        self.clock.wall_time()
+    }
+
+    /// Sets the clock used for timeouts and TTL expiry. Defaults to the system
+    /// clock.
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.txn_started = clock.now();
+        self.txn_idle = clock.now();
+        self.clock = clock;
+    }
+
+    /// Sets the directory that relative IMPORT and EXPORT file paths are
+    /// resolved against, e.g. the server's data directory. If None (the
+    /// default), they're relative to the current working directory.
+    pub fn set_file_dir(&mut self, file_dir: Option<PathBuf>) {
+        self.file_dir = file_dir;
+    }
+
+    /// Sets the query result cache, or None to disable caching (the default).
+    /// SELECT results in read-only transactions (including implicit ones) are
+    /// then cached and reused while the tables they read are unchanged.
+    pub fn set_result_cache(&mut self, result_cache: Option<Arc<ResultCache>>) {
+        self.result_cache = result_cache;
+    }
+
+    /// Sets the query plan cache, or None to disable caching (the default).
+    /// Plans of SELECT, INSERT, UPDATE, and DELETE statements are then cached
+    /// by their normalized form, and reused while the schema of the tables
+    /// they use is unchanged.
+    pub fn set_plan_cache(&mut self, plan_cache: Option<Arc<PlanCache>>) {
+        self.plan_cache = plan_cache;
+    }
+
+    /// Sets the slow query log, or None to disable it (the default).
+    /// Statements that take at least its threshold are then logged, with
+    /// their text, duration, rows, and plan summary.
+    pub fn set_slow_query_log(&mut self, slow_query_log: Option<Arc<SlowQueryLog>>) {
+        self.slow_query_log = slow_query_log;
+    }
+
+    /// Sets the statement and table statistics, or None to disable them (the
+    /// default). Statement executions and table row reads and writes are then
+    /// recorded, and can be queried via the system.statement_statistics and
+    /// system.table_statistics tables.
+    pub fn set_statistics(&mut self, statistics: Option<Arc<Statistics>>) {
+        self.statistics = statistics;
+    }
+
+    /// Sets the audit log, or None to disable it (the default). Schema, user,
+    /// and privilege changes, administrative statements, and writes to its
+    /// tables are then logged.
+    pub fn set_audit_log(&mut self, audit_log: Option<Arc<AuditLog>>) {
+        self.audit_log = audit_log;
+    }
+
+    /// Executes a client statement, buffering any result rows.
     pub fn execute(&mut self, statement: &str) -> Result<StatementResult> {
-        // Parse and execute the statement. Transaction control is done here,
-        // other statements are executed by the SQL engine.
-        Ok(match Parser::new(statement).parse()? {
+        let mut buffer = Vec::new();
+        let mut result = self.execute_stream(statement, &CancelToken::new(), |_, rows| {
+            buffer = rows.try_collect()?;
+            Ok(())
+        })?;
+        if let StatementResult::Select { rows, .. } = &mut result {
+            *rows = buffer;
+        }
+        Ok(result)
+    }
+
+    /// Executes a client statement, streaming any SELECT result rows. Instead
+    /// of buffering the rows, the result columns and row iterator are passed to
+    /// the given closure while the statement's transaction is still open, so
+    /// rows can be consumed as they're produced. The returned
+    /// StatementResult::Select does not contain any rows.
+    ///
+    /// The statement can be cancelled via the given cancellation token, e.g.
+    /// from a different thread, in which case it errors with Error::Cancelled.
+    /// No further rows are emitted once the statement is cancelled. If it
+    /// exceeds the statement timeout, it errors with Error::Timeout (see
+    /// set_statement_timeout).
+    pub fn execute_stream(
+        &mut self,
+        statement: &str,
+        cancel: &CancelToken,
+        stream: impl FnOnce(&[Label], Rows) -> Result<()>,
+    ) -> Result<StatementResult> {
+        let _trace = Self::trace(statement);
+        self.check_authenticated()?;
+        let text = statement;
+        let statement = Self::parse(statement)?;
+        if let ast::Statement::Import { source: ast::ImportSource::Stdin { .. }, .. } = statement {
+            return errinput!("COPY FROM STDIN requires data streamed by the client");
+        }
+        self.execute_parsed(text, statement, cancel, stream)
+    }
+
+    /// Executes a COPY FROM STDIN statement (or IMPORT FROM STDIN), importing
+    /// the given CSV or JSON data streamed by the client.
+    pub fn execute_copy(
+        &mut self,
+        statement: &str,
+        data: Vec<u8>,
+        cancel: &CancelToken,
+    ) -> Result<StatementResult> {
+        let _trace = Self::trace(statement);
+        self.check_authenticated()?;
+        let text = statement;
+        let mut statement = Self::parse(statement)?;
+        let ast::Statement::Import { source: ast::ImportSource::Stdin { data: stdin, .. }, .. } =
+            &mut statement
+        else {
+            return errinput!("expected COPY FROM STDIN statement");
+        };
+        *stdin = data;
+        self.execute_parsed(text, statement, cancel, |_, _| Ok(()))
+    }
+
+    /// Starts the trace of a client statement (see trace).
+    fn trace(statement: &str) -> trace::SpanGuard {
+        let span = trace::root("query");
+        span.attr("db.statement", statement);
+        span
+    }
+
+    /// Parses a client statement.
+    fn parse(statement: &str) -> Result<ast::Statement> {
+        trace::in_span("parse", || Parser::new(statement).parse())
+    }
+
+    /// Executes a parsed client statement (see execute_stream). The statement
+    /// text is only used for the slow query log.
+    fn execute_parsed(
+        &mut self,
+        text: &str,
+        mut statement: ast::Statement,
+        cancel: &CancelToken,
+        stream: impl FnOnce(&[Label], Rows) -> Result<()>,
+    ) -> Result<StatementResult> {
+        // Resolve relative IMPORT and EXPORT file paths against the file
+        // directory.
+        if let (
+            ast::Statement::Import { source: ast::ImportSource::File { path, .. }, .. }
+            | ast::Statement::Export { path, .. },
+            Some(dir),
+        ) = (&mut statement, &self.file_dir)
+        {
+            *path = dir.join(&*path).to_string_lossy().into_owned();
+        }
+
+        // If the explicit transaction timed out, it's been rolled back, and
+        // the client must end it before running other statements.
+        self.check_timeout()?;
+        if let Some(version) = self.timed_out {
+            match statement {
+                ast::Statement::Rollback => {
+                    self.timed_out = None;
+                    return Ok(StatementResult::Rollback { version });
+                }
+                ast::Statement::Commit => {
+                    self.timed_out = None;
+                    return errinput!("transaction {version} timed out and was rolled back");
+                }
+                _ => return errinput!("transaction {version} timed out, run ROLLBACK"),
+            }
+        }
+
+        let cancel = match self.statement_timeout {
+            Some(timeout) => cancel.with_deadline(self.clock.now() + timeout, self.clock.clone()),
+            None => cancel.clone(),
+        };
+        let _session = logging::with_session(self.id);
+        let _txn = logging::with_txn(self.txn.as_ref().map(|txn| txn.version()));
+        let name = statement.name();
+        let start = self.clock.now();
+
+        // Count streamed rows for the slow query log.
+        let streamed = Arc::new(AtomicU64::new(0));
+        let stream = {
+            let streamed = streamed.clone();
+            move |columns: &[Label], rows: Rows| {
+                let rows = rows.inspect(move |_| _ = streamed.fetch_add(1, Ordering::Relaxed));
+                stream(columns, Box::new(rows))
+            }
+        };
+        self.plan_summary = None;
+        let audit = self.audit_log.as_ref().and_then(|log| log.kind(&statement)).map(|kind| {
+            let text = AuditLog::statement_text(&statement, text);
+            (kind, text)
+        });
+        let result = self
+            .authorize(&statement)
+            .and_then(|_| self.execute_statement(statement, &cancel, stream));
+        self.txn_idle = self.clock.now();
+        if let Some((kind, text)) = audit {
+            self.audit(kind, text, result.as_ref().err());
+        }
+        let duration = self.clock.now().saturating_duration_since(start);
+        metrics::STATEMENT_DURATION.observe(&[name], duration.as_secs_f64());
+        let rows = match &result {
+            Ok(StatementResult::Select { .. }) => Some(streamed.load(Ordering::Relaxed)),
+            Ok(
+                StatementResult::Delete { count, .. }
+                | StatementResult::Insert { count, .. }
+                | StatementResult::Import { count }
+                | StatementResult::Export { count }
+                | StatementResult::Update { count, .. },
+            ) => Some(*count),
+            _ => None,
+        };
+        if let Some(statistics) = &self.statistics {
+            let (rows, error) = (rows.unwrap_or(0), result.is_err());
+            statistics.record_statement(&redact(text), duration, rows, error)?;
+        }
+        if let Some(log) = self.slow_query_log.as_ref().filter(|log| duration >= log.threshold()) {
+            log.log(SlowQuery {
+                time: logging::timestamp(),
+                duration_ms: duration.as_secs_f64() * 1000.0,
+                session: self.id,
+                user: self.user.as_deref(),
+                statement: redact(text),
+                rows,
+                plan: self.plan_summary.take(),
+                error: result.as_ref().err().map(|error| error.to_string()),
+            });
+        }
+        if let Err(error) = &result {
+            metrics::STATEMENT_ERRORS.inc(&[name]);
+            if let Some(kind) = retryable_error_kind(error) {
+                metrics::TXN_RETRYABLE_ERRORS.inc(&[kind]);
+            }
+        }
+
+        // If the statement timed out in an explicit transaction, roll it back.
+        if let (Err(Error::Timeout), Some(txn)) = (&result, &self.txn) {
+            self.timed_out = Some(txn.version());
+            self.txn.take().expect("no transaction").rollback()?;
+        }
+        result
+    }
+
+    /// Executes an INSERT, UPDATE, or DELETE statement at most once for the
+    /// given client-supplied idempotency token, in an implicit transaction.
+    /// If a statement has already committed with the token, it isn't executed
+    /// again, and its original result is returned instead -- even if it was a
+    /// different statement. This allows clients to safely retry statements
+    /// after ambiguous failures, e.g. when the connection is lost or the
+    /// leader changes before the result is received.
+    ///
+    /// The token is recorded when the transaction commits through Raft, so a
+    /// concurrent retry either conflicts with the original and errors with
+    /// Error::Serialization, or observes its result. Tokens are retained
+    /// indefinitely.
+    pub fn execute_idempotent(
+        &mut self,
+        statement: &str,
+        token: &str,
+        cancel: &CancelToken,
+    ) -> Result<StatementResult> {
+        let _trace = Self::trace(statement);
+        self.check_authenticated()?;
+        let text = statement;
+        let statement = Self::parse(statement)?;
+        let audit = self.audit_log.as_ref().and_then(|log| log.kind(&statement));
+        let result = self.execute_idempotent_parsed(statement, token, cancel);
+        if let Some(kind) = audit {
+            self.audit(kind, text.trim().to_string(), result.as_ref().err());
+        }
+        result
+    }
+
+    /// Executes a parsed statement with an idempotency token (see
+    /// execute_idempotent).
+    fn execute_idempotent_parsed(
+        &mut self,
+        statement: ast::Statement,
+        token: &str,
+        cancel: &CancelToken,
+    ) -> Result<StatementResult> {
+        self.check_timeout()?;
+        if self.in_transaction() {
+            return errinput!("idempotency tokens can't be used in an explicit transaction");
+        }
+        if !matches!(
+            statement,
+            ast::Statement::Insert { .. }
+                | ast::Statement::Update { .. }
+                | ast::Statement::Delete { .. }
+        ) {
+            return errinput!("idempotency tokens can only be used with INSERT, UPDATE, or DELETE");
+        }
+        if token.is_empty() {
+            return errinput!("idempotency token can't be empty");
+        }
+        self.authorize(&statement)?;
+
+        let cancel = match self.statement_timeout {
+            Some(timeout) => cancel.with_deadline(self.clock.now() + timeout, self.clock.clone()),
+            None => cancel.clone(),
+        };
+        let txn = self.begin_session_txn(self.engine.begin()?)?;
+        if let Some(result) = txn.get_idempotent(token)? {
+            txn.rollback()?;
+            return bincode::deserialize(&result);
+        }
+
+        // Execute the statement in the transaction as if it was explicit,
+        // then commit it with the token.
+        self.txn = Some(txn);
+        self.txn_started = self.clock.now();
+        let result = self.execute_statement(statement, &cancel, |_, _| Ok(()));
+        self.txn_idle = self.clock.now();
+        let Some(txn) = self.txn.take() else {
+            return result; // rolled back by the statement
+        };
+        let result = match result {
+            Ok(result) => result,
+            Err(error) => {
+                txn.rollback()?;
+                return Err(error);
+            }
+        };
+        match txn.commit_idempotent(token, bincode::serialize(&result))? {
+            Some(existing) => bincode::deserialize(&existing),
+            None => Ok(result),
+        }
+    }
+
+    /// Writes an audit log entry for a statement or command, if the audit log
+    /// is enabled.
+    fn audit(&self, kind: AuditKind, statement: String, error: Option<&Error>) {
+        let Some(log) = &self.audit_log else {
+            return;
+        };
+        log.log(AuditEvent {
+            time: logging::timestamp(),
+            session: self.id,
+            user: self.user.as_deref(),
+            kind,
+            statement,
+            error: error.map(|error| error.to_string()),
+        });
+    }
+
+    /// Writes an audit log entry for an administrative command that isn't a
+    /// SQL statement, e.g. a backup requested by a client, if the audit log
+    /// is enabled. The error is given if the command failed.
+    pub fn audit_command(&self, command: &str, error: Option<&Error>) {
+        self.audit(AuditKind::Admin, command.to_string(), error)
+    }
+
+    /// Checks that the session's user has the privileges required to execute
+    /// the statement (see Privilege). User management, aborting other
+    /// transactions, and importing server files require the superuser.
+    /// Transaction control, SET, SHOW, and DESCRIBE are allowed for all users.
+    fn authorize(&mut self, statement: &ast::Statement) -> Result<()> {
+        use ast::Statement::*;
+        let mut privileges = Vec::new();
+        let mut statement = statement;
+        while let Explain(inner) = statement {
+            statement = inner;
+        }
+        match statement {
+            Begin { .. }
+            | Commit
+            | Rollback
+            | Savepoint(_)
+            | RollbackToSavepoint(_)
+            | ReleaseSavepoint(_)
+            | SetTransaction { .. }
+            | Set { .. }
+            | Show { .. }
+            | ShowTables
+            | ShowCreateTable { .. }
+            | Describe { .. } => {}
+            Explain(_) => unreachable!("unwrapped above"),
+            AbortTransaction(_)
+            | CreateUser { .. }
+            | DropUser { .. }
+            | Grant { .. }
+            | Revoke { .. }
+            | Import { source: ast::ImportSource::File { .. }, .. }
+            | Export { .. } => {
+                return self.check_superuser();
+            }
+            CreateTable { temporary: true, .. } => {}
+            CreateTable { temporary: false, .. } => privileges.push((None, Privilege::Create)),
+            DropTable { name, .. } => privileges.push((Some(name.as_str()), Privilege::Drop)),
+            AlterTable { name: table, .. }
+            | CreateIndex { table, .. }
+            | CreateTrigger { table, .. }
+            | DropTrigger { table, .. }
+            | CreateChangefeed { table }
+            | DropChangefeed { table } => privileges.push((Some(table.as_str()), Privilege::Alter)),
+            TruncateTable { name } => privileges.push((Some(name.as_str()), Privilege::Delete)),
+            Insert { table, returning, .. } => {
+                privileges.push((Some(table.as_str()), Privilege::Insert));
+                if !returning.is_empty() {
+                    privileges.push((Some(table.as_str()), Privilege::Select));
+                }
+            }
+            Import { table, .. } => privileges.push((Some(table.as_str()), Privilege::Insert)),
+            Update { table, r#where, returning, .. } => {
+                privileges.push((Some(table.as_str()), Privilege::Update));
+                if r#where.is_some() || !returning.is_empty() {
+                    privileges.push((Some(table.as_str()), Privilege::Select));
+                }
+            }
+            Delete { table, r#where, returning } => {
+                privileges.push((Some(table.as_str()), Privilege::Delete));
+                if r#where.is_some() || !returning.is_empty() {
+                    privileges.push((Some(table.as_str()), Privilege::Select));
+                }
+            }
+            Select { from, .. } => {
+                let mut from: Vec<&ast::From> = from.iter().collect();
+                while let Some(item) = from.pop() {
+                    match item {
+                        ast::From::Table { name, .. } => {
+                            privileges.push((Some(name.as_str()), Privilege::Select))
+                        }
+                        ast::From::Join { left, right, .. } => from.extend([&**left, &**right]),
+                        ast::From::Unnest { .. } => {}
+                    }
+                }
+            }
+        }
+        self.check_privileges(&privileges)
+    }
+
+    /// Executes a parsed statement (see execute_stream). Transaction control
+    /// is done here, other statements are executed by the SQL engine.
+    fn execute_statement(
+        &mut self,
+        statement: ast::Statement,
+        cancel: &CancelToken,
+        stream: impl FnOnce(&[Label], Rows) -> Result<()>,
+    ) -> Result<StatementResult> {
+        let parallelism = self.parallelism;
+        let memory = MemoryAccountant::new(self.memory_limit);
+        let result_cache = self.result_cache.clone();
+        let plan_cache = self.plan_cache.clone();
+        let summarize = self.slow_query_log.is_some();
+        let wall_time = self.wall_time();
+        let options = self.optimizer_options(&statement);
+        Ok(match statement {
             ast::Statement::Begin { read_only, as_of } => {
                 if self.txn.is_some() {
                     return errinput!("already in a transaction");
                 }
                 let txn = match (read_only, as_of) {
                     (false, None) => self.engine.begin()?,
-                    (true, None) => self.engine.begin_read_only()?,
-                    (true, Some(as_of)) => self.engine.begin_as_of(as_of)?,
+                    (true, None) => self.begin_read_only()?,
+                    (true, Some(as_of)) => self.begin_as_of(as_of)?,
                     (false, Some(_)) => {
                         return errinput!("can't start read-write transaction in a given version");
                     }
                 };
+                // Read-write transactions use the session's isolation level.
+                let mut txn = self.begin_session_txn(txn)?;
+                if !read_only {
+                    match self.isolation {
+                        mvcc::IsolationLevel::Snapshot => {}
+                        mvcc::IsolationLevel::ReadCommitted => txn.refresh_snapshot()?,
+                        mvcc::IsolationLevel::Serializable => txn.set_serializable(),
+                    }
+                }
                 let state = txn.state().clone();
                 self.txn = Some(txn);
+                self.txn_started = self.clock.now();
                 StatementResult::Begin(state)
             }
             ast::Statement::Commit => {
@@ -64,37 +961,420 @@ impl<'a, E: Engine<'a>> Session<'a, E> {
                 txn.rollback()?;
                 StatementResult::Rollback { version }
             }
+            ast::Statement::Savepoint(name) => {
+                let Some(txn) = self.txn.as_ref() else {
+                    return errinput!("not in a transaction");
+                };
+                txn.savepoint(&name)?;
+                StatementResult::Savepoint { name }
+            }
+            ast::Statement::RollbackToSavepoint(name) => {
+                let Some(txn) = self.txn.as_ref() else {
+                    return errinput!("not in a transaction");
+                };
+                txn.rollback_to_savepoint(&name)?;
+                StatementResult::RollbackToSavepoint { name }
+            }
+            ast::Statement::ReleaseSavepoint(name) => {
+                let Some(txn) = self.txn.as_ref() else {
+                    return errinput!("not in a transaction");
+                };
+                txn.release_savepoint(&name)?;
+                StatementResult::ReleaseSavepoint { name }
+            }
+            ast::Statement::AbortTransaction(version) => {
+                self.with_txn(false, |txn| txn.abort_transaction(version))?;
+                StatementResult::AbortTransaction { version }
+            }
+            // Switching to READ COMMITTED takes a statement snapshot, which
+            // marks the transaction as read committed. Switching back isn't
+            // possible, since the transaction has already seen newer data.
+            // SERIALIZABLE must be set before any reads, since only reads made
+            // after it are recorded and validated at commit.
+            ast::Statement::SetTransaction { isolation } => {
+                use mvcc::IsolationLevel::*;
+                let Some(txn) = self.txn.as_mut() else {
+                    return errinput!("not in a transaction");
+                };
+                if isolation != Snapshot && txn.read_only() {
+                    return errinput!("{isolation} requires a read-write transaction");
+                }
+                match (txn.isolation(), isolation) {
+                    (from, to) if from == to => {}
+                    (Snapshot, ReadCommitted) => txn.refresh_snapshot()?,
+                    (Snapshot, Serializable) if txn.has_read() => {
+                        return errinput!("SERIALIZABLE must be set before any reads");
+                    }
+                    (Snapshot, Serializable) => txn.set_serializable(),
+                    (from, _) => return errinput!("can't change isolation level from {from}"),
+                }
+                StatementResult::SetTransaction { isolation }
+            }
+            // The original value of a variable is recorded when it's first
+            // changed, and restored by DEFAULT.
+            ast::Statement::Set { name, value } => {
+                let current = self.variable(&name)?;
+                match value {
+                    Some(value) => {
+                        self.set_variable(&name, &value)?;
+                        self.defaults.entry(name.clone()).or_insert(current);
+                    }
+                    None => {
+                        if let Some(default) = self.defaults.remove(&name) {
+                            self.set_variable(&name, &default)?;
+                        }
+                    }
+                }
+                StatementResult::Set { value: self.variable(&name)?, name }
+            }
+            ast::Statement::Show { name: Some(name) } => {
+                let value = self.variable(&name)?;
+                let columns = vec![Label::Unqualified(name)];
+                stream(&columns, Box::new(std::iter::once(Ok(vec![value.into()]))))?;
+                StatementResult::Select { columns, rows: Vec::new() }
+            }
+            ast::Statement::Show { name: None } => {
+                let columns =
+                    vec![Label::Unqualified("name".into()), Label::Unqualified("setting".into())];
+                let rows: Vec<Row> = VARIABLES
+                    .into_iter()
+                    .map(|name| -> Result<Row> {
+                        Ok(vec![name.into(), self.variable(name)?.into()])
+                    })
+                    .try_collect()?;
+                stream(&columns, Box::new(rows.into_iter().map(Ok)))?;
+                StatementResult::Select { columns, rows: Vec::new() }
+            }
             ast::Statement::Explain(statement) => self.with_txn(true, |txn| {
-                Ok(StatementResult::Explain(Plan::build(*statement, txn)?.optimize()?))
+                let plan = trace::in_span("plan", || {
+                    Plan::build_at(*statement, txn, wall_time)?
+                        .optimize(&options)?
+                        .parallelize(parallelism)
+                })?;
+                Ok(StatementResult::Explain(plan))
             })?,
+            // Bulk writes can't be rolled back to savepoints, so IMPORT always
+            // runs in an implicit transaction.
+            ast::Statement::Import { .. } if self.txn.is_some() => {
+                return errinput!("IMPORT can't be used in an explicit transaction");
+            }
+            statement @ ast::Statement::CreateIndex { .. } if self.txn.is_none() => {
+                self.create_index_online(statement, cancel)?
+            }
+            // SELECT FOR UPDATE retries the statement when the transaction
+            // blocking a row lock completes, until the lock timeout. Locks
+            // acquired so far are retained between attempts.
+            statement @ ast::Statement::Select { for_update: true, .. } => {
+                let lock_timeout = self.lock_timeout;
+                let clock = self.clock.clone();
+                let mut summary = None;
+                let result = self.with_txn(false, |txn| {
+                    let plan = trace::in_span("plan", || {
+                        Plan::build_at(statement, txn, wall_time)?.optimize(&options)
+                    })?;
+                    if summarize {
+                        summary = Some(plan.summary());
+                    }
+                    let _span = trace::span("execute");
+                    let start = clock.now();
+                    let lock_waits = txn.lock_waits();
+                    loop {
+                        // Fetch the wakeup sequence number before attempting
+                        // to lock, to avoid missing wakeups in between.
+                        let seq = lock_waits.as_ref().map(|w| w.seq()).transpose()?;
+                        let version = match plan.clone().execute(txn, cancel, &memory) {
+                            Ok(ExecutionResult::Select { rows, columns }) => {
+                                stream(&columns, rows)?;
+                                return Ok(StatementResult::Select { columns, rows: Vec::new() });
+                            }
+                            Ok(result) => return result.try_into(),
+                            Err(Error::Locked(version)) => version,
+                            Err(error) => return Err(error),
+                        };
+                        let elapsed = clock.now().saturating_duration_since(start);
+                        if elapsed >= lock_timeout {
+                            txn.cancel_lock_waits()?;
+                            return Err(Error::Locked(version));
+                        }
+                        if let Err(error) = cancel.check() {
+                            txn.cancel_lock_waits()?;
+                            return Err(error);
+                        }
+                        metrics::LOCK_WAIT_RETRIES.inc(&[]);
+                        let wait = LOCK_WAIT.min(lock_timeout - elapsed);
+                        match (&lock_waits, seq) {
+                            (Some(lock_waits), Some(seq)) => {
+                                lock_waits.wait(version, seq, wait)?;
+                            }
+                            _ => std::thread::sleep(wait),
+                        }
+                    }
+                });
+                self.plan_summary = summary;
+                // On deadlocks, roll back the explicit transaction (if any) to
+                // release its locks, so that the other transactions can proceed.
+                if let Err(Error::Deadlock) = result {
+                    if let Some(txn) = self.txn.take() {
+                        txn.rollback()?;
+                    }
+                }
+                result?
+            }
             statement => {
-                let read_only = matches!(statement, ast::Statement::Select { .. });
-                self.with_txn(read_only, |txn| {
-                    Plan::build(statement, txn)?.optimize()?.execute(txn)?.try_into()
-                })?
+                let read_only = matches!(
+                    statement,
+                    ast::Statement::Select { .. }
+                        | ast::Statement::Export { .. }
+                        | ast::Statement::ShowTables
+                        | ast::Statement::ShowCreateTable { .. }
+                        | ast::Statement::Describe { .. }
+                );
+                let as_of = match &statement {
+                    ast::Statement::Select { as_of, .. } => *as_of,
+                    ast::Statement::Export { query, .. } => match **query {
+                        ast::Statement::Select { as_of, .. } => as_of,
+                        _ => None,
+                    },
+                    _ => None,
+                };
+                // Users are granted all privileges on the tables they create.
+                let owner = match &statement {
+                    ast::Statement::CreateTable { temporary: false, .. } => {
+                        self.user.clone().filter(|user| user != User::ADMIN)
+                    }
+                    _ => None,
+                };
+                let mut summary = None;
+                let result = self.with_txn_as_of(read_only, as_of, |txn| {
+                    // Use a cached SELECT result in read-only transactions, if
+                    // possible. Results are keyed by the parsed statement.
+                    let cache = result_cache
+                        .filter(|_| txn.read_only())
+                        .filter(|_| matches!(statement, ast::Statement::Select { .. }))
+                        .map(|cache| (cache, format!("{statement:?}")));
+                    if let Some((cache, key)) = &cache {
+                        if let Some((columns, rows)) = cache.get(key, txn)? {
+                            let rows = Arc::unwrap_or_clone(rows).into_iter().map(Ok);
+                            stream(&columns, Box::new(rows))?;
+                            return Ok(StatementResult::Select { columns, rows: Vec::new() });
+                        }
+                    }
+
+                    let plan = trace::in_span("plan", || {
+                        match plan_cache {
+                            Some(cache) => cache.plan(statement, txn, wall_time)?,
+                            None => Plan::build_at(statement, txn, wall_time)?,
+                        }
+                        .optimize(&options)?
+                        .parallelize(parallelism)
+                    })?;
+                    if summarize {
+                        summary = Some(plan.summary());
+                    }
+                    let tables = match (&cache, &plan) {
+                        (Some(_), Plan::Select(root)) => ResultCache::tables(root),
+                        _ => None,
+                    };
+                    trace::in_span("execute", || match plan.execute(txn, cancel, &memory)? {
+                        ExecutionResult::Select { rows, columns } => {
+                            let (Some((cache, key)), Some(tables)) = (cache, tables) else {
+                                stream(&columns, rows)?;
+                                return Ok(StatementResult::Select { columns, rows: Vec::new() });
+                            };
+                            // Capture the rows as they're streamed, and cache
+                            // them if they were all streamed.
+                            let (rows, captured) = cache.capture(rows);
+                            stream(&columns, rows)?;
+                            if let Some(rows) = captured() {
+                                cache.insert(key, txn, tables, columns.clone(), rows)?;
+                            }
+                            Ok(StatementResult::Select { columns, rows: Vec::new() })
+                        }
+                        ExecutionResult::CreateTable { name } if owner.is_some() => {
+                            let owner = owner.as_deref().expect("no owner");
+                            txn.grant(owner, Some(&name), &Privilege::TABLE)?;
+                            Ok(StatementResult::CreateTable { name })
+                        }
+                        result => result.try_into(),
+                    })
+                });
+                self.plan_summary = summary;
+                result?
             }
         })
     }
 
+    /// Creates an index outside of an explicit transaction, without blocking
+    /// concurrent writes for the duration. The index is stepped through its
+    /// states (see IndexState) in separate transactions:
+    ///
+    /// 1. Create the index in the delete-only state.
+    /// 2. Once older transactions (unaware of the index) finish, make the
+    ///    index write-only.
+    /// 3. Once older transactions (using the delete-only index) finish,
+    ///    backfill the existing rows in batches of separate transactions.
+    /// 4. Make the index public.
+    ///
+    /// If the build is cancelled, the index is left in its current state
+    /// without being used by queries, and running the CREATE INDEX statement
+    /// again resumes the build. If it fails, e.g. because the index predicate
+    /// errors for an existing row, the build is aborted and the index removed.
+    /// Temporary tables aren't visible to other sessions, so their indexes are
+    /// created directly.
+    fn create_index_online(
+        &mut self,
+        statement: ast::Statement,
+        cancel: &CancelToken,
+    ) -> Result<StatementResult> {
+        let (plan, temporary) = self.with_txn(true, |txn| {
+            let plan = Plan::build(statement, txn)?;
+            let temporary = match &plan {
+                Plan::CreateIndex { table, .. } => txn.is_temporary(&table.name)?,
+                plan => return errdata!("unexpected plan {plan}"),
+            };
+            Ok((plan, temporary))
+        })?;
+        if temporary {
+            let memory = MemoryAccountant::new(self.memory_limit);
+            return self.with_txn(false, |txn| plan.execute(txn, cancel, &memory)?.try_into());
+        }
+        let Plan::CreateIndex { table, column, index_type, predicate } = plan else {
+            return errdata!("unexpected plan {plan}");
+        };
+        let (table, column) = (table.name, table.columns[column].name.clone());
+
+        // Create the index, or resume an existing build.
+        let mut state = self.with_txn(false, |txn| {
+            txn.create_index_online(&table, &column, index_type, predicate)
+        })?;
+
+        while let Some(version) = state.version() {
+            self.wait_for_older(version, cancel)?;
+            if let IndexState::WriteOnly(_) = state {
+                let mut after = None;
+                loop {
+                    cancel.check()?;
+                    let result = self.with_txn(false, |txn| {
+                        txn.backfill_index(&table, &column, after.clone(), BACKFILL_BATCH_SIZE)
+                    });
+                    match result {
+                        Ok(Some(last)) => after = Some(last),
+                        Ok(None) => break,
+                        Err(Error::Serialization) => std::thread::sleep(BACKFILL_WAIT),
+                        Err(error @ (Error::Cancelled | Error::Timeout)) => return Err(error),
+                        Err(error) => {
+                            self.with_txn(false, |txn| txn.abort_index(&table, &column))?;
+                            return Err(error);
+                        }
+                    }
+                }
+            }
+            state = self.with_txn(false, |txn| txn.advance_index(&table, &column, state))?;
+        }
+        Ok(StatementResult::CreateIndex { table, column })
+    }
+
+    /// Waits for all read-write transactions older than the given version to
+    /// finish. Errors with Error::Cancelled or Error::Timeout if cancelled while
+    /// waiting.
+    fn wait_for_older(&self, version: mvcc::Version, cancel: &CancelToken) -> Result<()> {
+        loop {
+            let txn = self.engine.begin_read_only()?;
+            let waiting = txn.state().active.iter().any(|v| *v < version);
+            txn.rollback()?;
+            if !waiting {
+                return Ok(());
+            }
+            cancel.check()?;
+            std::thread::sleep(BACKFILL_WAIT);
+        }
+    }
+
     /// Runs a closure in the session's explicit transaction, if there is one,
     /// otherwise a temporary implicit transaction. If read_only is true, uses a
     /// read-only implicit transaction. Does not retry errors.
     pub fn with_txn<F, T>(&mut self, read_only: bool, f: F) -> Result<T>
     where
-        F: FnOnce(&mut E::Transaction) -> Result<T>,
+        F: FnOnce(&mut SessionTransaction<E::Transaction>) -> Result<T>,
+    {
+        self.with_txn_as_of(read_only, None, f)
+    }
+
+    /// Purges expired rows from all tables with a TTL, and returns the number
+    /// of rows deleted. Each table is purged in a separate transaction, or in
+    /// the explicit transaction if there is one. Expired rows are already
+    /// invisible to queries, so this only reclaims storage and frees up their
+    /// primary keys. toyDB nodes do this periodically, see Server.
+    pub fn purge_expired(&mut self) -> Result<u64> {
+        let tables = self.with_txn(true, |txn| txn.list_tables())?;
+        let mut count = 0;
+        for table in tables {
+            let Some(ttl) = &table.ttl else { continue };
+            let datatype = &table.columns[ttl.column].datatype;
+            let filter = Some(ttl.expired_filter(datatype, self.wall_time()));
+            let plan = Plan::Delete {
+                table: table.name.clone(),
+                primary_key: table.primary_key,
+                source: Node::Scan { table, alias: None, filter, partitions: None },
+                returning: Vec::new(),
+            }
+            .optimize(&OptimizerOptions::default())?;
+            let memory = MemoryAccountant::new(self.memory_limit);
+            let cancel = CancelToken::new();
+            match self.with_txn(false, |txn| plan.execute(txn, &cancel, &memory)?.try_into())? {
+                StatementResult::Delete { count: deleted, .. } => count += deleted,
+                result => return errdata!("unexpected result {result:?}"),
+            }
+        }
+        Ok(count)
+    }
+
+    /// Begins a read-only transaction, reading from the local replica if
+    /// replica_reads is enabled.
+    fn begin_read_only(&self) -> Result<E::Transaction> {
+        match self.replica_reads {
+            true => self.engine.begin_replica_read(),
+            false => self.engine.begin_read_only(),
+        }
+    }
+
+    /// Begins a read-only transaction for an AS OF SYSTEM TIME clause.
+    fn begin_as_of(&self, as_of: ast::AsOf) -> Result<E::Transaction> {
+        match as_of {
+            ast::AsOf::Version(version) => self.engine.begin_as_of(version),
+            ast::AsOf::Timestamp(timestamp) => self.engine.begin_as_of_timestamp(timestamp),
+        }
+    }
+
+    /// Like with_txn(), but if as_of is given, runs the closure in an implicit
+    /// read-only transaction as of it (i.e. AS OF SYSTEM TIME).
+    /// Errors if there's an explicit transaction in that case.
+    fn with_txn_as_of<F, T>(&mut self, read_only: bool, as_of: Option<ast::AsOf>, f: F) -> Result<T>
+    where
+        F: FnOnce(&mut SessionTransaction<E::Transaction>) -> Result<T>,
     {
-        // Use the current explicit transaction, if there is one.
+        // Use the current explicit transaction, if there is one. Read
+        // committed transactions take a new snapshot for every statement.
         if let Some(ref mut txn) = self.txn {
+            if as_of.is_some() {
+                return errinput!("can't use AS OF SYSTEM TIME in a transaction");
+            }
+            if txn.state().isolation() == mvcc::IsolationLevel::ReadCommitted {
+                txn.refresh_snapshot()?;
+            }
             return f(txn);
         }
         // Otherwise, use an implicit transaction. Doing this session-side
         // results in additional Raft roundtrips to begin and complete the
         // transaction -- we could avoid this if the below-Raft engine supported
         // implicit transactions, but we keep it simple.
-        let mut txn = match read_only {
-            true => self.engine.begin_read_only()?,
-            false => self.engine.begin()?,
+        let txn = match (read_only, as_of) {
+            (_, Some(as_of)) => self.begin_as_of(as_of)?,
+            (true, None) => self.begin_read_only()?,
+            (false, None) => self.engine.begin()?,
         };
+        let mut txn = self.begin_session_txn(txn)?;
+        let _txn = logging::with_txn(Some(txn.version()));
         let result = f(&mut txn);
         match result {
             Ok(_) => txn.commit()?,
@@ -102,6 +1382,14 @@ impl<'a, E: Engine<'a>> Session<'a, E> {
         }
         result
     }
+
+    /// Wraps an engine transaction in a session transaction, which also
+    /// handles temporary tables and table statistics.
+    fn begin_session_txn(&self, txn: E::Transaction) -> Result<SessionTransaction<E::Transaction>> {
+        let mut txn = self.temporary.begin(txn)?;
+        txn.set_statistics(self.statistics.clone());
+        Ok(txn)
+    }
 }
 
 impl Session<'_, Raft> {
@@ -109,6 +1397,58 @@ impl Session<'_, Raft> {
     pub fn status(&self) -> Result<Status> {
         self.engine.status()
     }
+
+    /// Returns the local node's Raft and MVCC status, without contacting the
+    /// leader.
+    pub fn node_status(&self) -> Result<(crate::raft::NodeStatus, mvcc::Status)> {
+        self.engine.node_status()
+    }
+
+    /// Garbage collects old MVCC versions, retaining the given number of
+    /// recent versions.
+    pub fn gc(&self, retention: u64) -> Result<mvcc::GarbageCollection> {
+        self.engine.gc(retention)
+    }
+
+    /// Takes a full backup of the SQL state machine.
+    pub fn backup(&self) -> Result<Backup> {
+        self.engine.backup()
+    }
+
+    /// Takes an incremental backup of the changes after the given Raft log
+    /// index.
+    pub fn backup_incremental(&self, since: raft::Index) -> Result<IncrementalBackup> {
+        self.engine.backup_incremental(since)
+    }
+
+    /// Transfers Raft leadership to an up-to-date follower, returning its ID.
+    pub fn transfer_leadership(&self) -> Result<crate::raft::NodeID> {
+        self.engine.transfer_leadership()
+    }
+
+    /// Removes a follower from the Raft cluster, returning the log index of
+    /// the committed removal.
+    pub fn remove_node(&self, id: crate::raft::NodeID) -> Result<crate::raft::Index> {
+        self.engine.remove_node(id)
+    }
+
+    /// Checks the consistency of the stored data, optionally repairing it
+    /// (see Local::scrub).
+    pub fn scrub(&self, repair: bool) -> Result<Scrub> {
+        self.engine.scrub(repair)
+    }
+
+    /// Computes checksums of the SQL state on all nodes at the same applied
+    /// index, returning the index and the leader's checksums.
+    pub fn checksum(&self) -> Result<(crate::raft::Index, Checksum)> {
+        self.engine.checksum()
+    }
+
+    /// Returns the local node's checksums at the given applied index, if
+    /// computed and retained.
+    pub fn local_checksum(&self, index: crate::raft::Index) -> Result<Option<Checksum>> {
+        self.engine.local_checksum(index)
+    }
 }
 
 /// If the session has an open transaction when dropped, roll it back.
@@ -128,15 +1468,35 @@ pub enum StatementResult {
     Begin(mvcc::TransactionState),
     Commit { version: mvcc::Version },
     Rollback { version: mvcc::Version },
+    Savepoint { name: String },
+    RollbackToSavepoint { name: String },
+    ReleaseSavepoint { name: String },
+    AbortTransaction { version: mvcc::Version },
+    SetTransaction { isolation: mvcc::IsolationLevel },
+    Set { name: String, value: String },
     Explain(Plan),
     CreateTable { name: String },
     DropTable { name: String, existed: bool },
-    Delete { count: u64 },
-    Insert { count: u64 },
-    Update { count: u64 },
-    // For simplicity, we buffer and send the entire set of rows as a vector
-    // instead of streaming them to the client. Streaming reads haven't been
-    // implemented from Raft either, so they're buffered all the way through.
+    CreateIndex { table: String, column: String },
+    CreateTrigger { table: String, name: String },
+    DropTrigger { table: String, name: String, existed: bool },
+    CreateChangefeed { table: String },
+    DropChangefeed { table: String },
+    DropPartition { table: String, partition: String, count: u64 },
+    TruncateTable { name: String, count: u64 },
+    CreateUser { name: String },
+    DropUser { name: String, existed: bool },
+    Grant { user: String },
+    Revoke { user: String },
+    // INSERT, UPDATE, and DELETE results include the affected row count, and
+    // any rows returned by a RETURNING clause.
+    Delete { count: u64, returning: Option<Returning> },
+    Insert { count: u64, returning: Option<Returning> },
+    Import { count: u64 },
+    Export { count: u64 },
+    Update { count: u64, returning: Option<Returning> },
+    // The rows are empty when streamed via Session::execute_stream(), in which
+    // case the server sends them separately as Response::Rows frames.
     Select { columns: Vec<Label>, rows: Vec<Row> },
 }
 
@@ -148,9 +1508,26 @@ impl TryFrom<ExecutionResult> for StatementResult {
         Ok(match result {
             ExecutionResult::CreateTable { name } => Self::CreateTable { name },
             ExecutionResult::DropTable { name, existed } => Self::DropTable { name, existed },
-            ExecutionResult::Delete { count } => Self::Delete { count },
-            ExecutionResult::Insert { count } => Self::Insert { count },
-            ExecutionResult::Update { count } => Self::Update { count },
+            ExecutionResult::CreateIndex { table, column } => Self::CreateIndex { table, column },
+            ExecutionResult::CreateTrigger { table, name } => Self::CreateTrigger { table, name },
+            ExecutionResult::DropTrigger { table, name, existed } => {
+                Self::DropTrigger { table, name, existed }
+            }
+            ExecutionResult::CreateChangefeed { table } => Self::CreateChangefeed { table },
+            ExecutionResult::DropChangefeed { table } => Self::DropChangefeed { table },
+            ExecutionResult::DropPartition { table, partition, count } => {
+                Self::DropPartition { table, partition, count }
+            }
+            ExecutionResult::TruncateTable { name, count } => Self::TruncateTable { name, count },
+            ExecutionResult::CreateUser { name } => Self::CreateUser { name },
+            ExecutionResult::DropUser { name, existed } => Self::DropUser { name, existed },
+            ExecutionResult::Grant { user } => Self::Grant { user },
+            ExecutionResult::Revoke { user } => Self::Revoke { user },
+            ExecutionResult::Delete { count, returning } => Self::Delete { count, returning },
+            ExecutionResult::Insert { count, returning } => Self::Insert { count, returning },
+            ExecutionResult::Import { count } => Self::Import { count },
+            ExecutionResult::Export { count } => Self::Export { count },
+            ExecutionResult::Update { count, returning } => Self::Update { count, returning },
             ExecutionResult::Select { rows, columns } => {
                 // We buffer the entire set of rows, for simplicity.
                 Self::Select { columns, rows: rows.try_collect()? }
@@ -226,3 +1603,103 @@ impl TryFrom<StatementResult> for String {
         Value::try_from(result)?.try_into()
     }
 }
+
+/// The interval output format for Postgres clients, set via the intervalstyle
+/// session variable.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum IntervalStyle {
+    /// The Postgres format, e.g. 1 year 2 mons 3 days 04:05:06.
+    Postgres,
+    /// The ISO 8601 format, e.g. P1Y2M3DT4H5M6S.
+    Iso8601,
+}
+
+impl Display for IntervalStyle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Postgres => "postgres",
+            Self::Iso8601 => "iso_8601",
+        })
+    }
+}
+
+/// Parses and normalizes a session time zone, either UTC or a UTC offset as
+/// +HH or +HH:MM (up to ±14:00). A zero offset is normalized to UTC.
+fn parse_timezone(value: &str) -> Result<String> {
+    if value.eq_ignore_ascii_case("utc") {
+        return Ok("UTC".to_string());
+    }
+    let invalid = || errinput!("invalid timezone {value}, expected UTC or +HH:MM offset");
+    let (sign, offset) = match value.split_at_checked(1) {
+        Some(("+", offset)) => ('+', offset),
+        Some(("-", offset)) => ('-', offset),
+        _ => return invalid(),
+    };
+    let (hours, minutes) = offset.split_once(':').unwrap_or((offset, "00"));
+    if hours.len() != 2 || minutes.len() != 2 {
+        return invalid();
+    }
+    let (Ok(hours), Ok(minutes)) = (hours.parse::<u16>(), minutes.parse::<u16>()) else {
+        return invalid();
+    };
+    if minutes >= 60 || hours * 60 + minutes > 14 * 60 {
+        return invalid();
+    }
+    if hours == 0 && minutes == 0 {
+        return Ok("UTC".to_string());
+    }
+    Ok(format!("{sign}{hours:02}:{minutes:02}"))
+}
+
+/// Returns the metrics label of a retryable error (see Error::is_retryable),
+/// or None if the error isn't retryable.
+fn retryable_error_kind(error: &Error) -> Option<&'static str> {
+    if !error.is_retryable() {
+        return None;
+    }
+    Some(match error {
+        Error::Abort => "abort",
+        Error::Busy(_) => "busy",
+        Error::NotLeader(_) => "not_leader",
+        Error::Deadlock => "deadlock",
+        Error::Serialization => "serialization",
+        Error::Locked(_) => "locked",
+        _ => "other",
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use crate::sql::engine::Local;
+    use crate::storage;
+
+    /// Tests that SELECT FOR UPDATE lock waits time out via the session's
+    /// clock, rather than the wall clock.
+    #[test]
+    fn lock_timeout_manual_clock() -> Result<()> {
+        let engine = Local::new(storage::Memory::new());
+        let clock = ManualClock::new(0);
+        let mut s1 = Session::new(&engine);
+        let mut s2 = Session::new(&engine);
+        s2.set_clock(Arc::new(clock.clone()));
+        s2.set_lock_timeout(Duration::from_secs(3600));
+
+        s1.execute("CREATE TABLE test (id INT PRIMARY KEY)")?;
+        s1.execute("INSERT INTO test VALUES (1)")?;
+        s1.execute("BEGIN")?;
+        s1.execute("SELECT * FROM test WHERE id = 1 FOR UPDATE")?;
+
+        // Advance the clock past the lock timeout while s2 waits for the lock.
+        // With the wall clock, this would wait for an hour.
+        let advance = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            clock.advance(Duration::from_secs(3600));
+        });
+        let result = s2.execute("SELECT * FROM test WHERE id = 1 FOR UPDATE");
+        advance.join().expect("advance failed");
+        assert_eq!(result, Err(Error::Locked(3)));
+        Ok(())
+    }
+}
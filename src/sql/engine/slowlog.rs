@@ -0,0 +1,107 @@
+use std::fs::File;
+use std::io::Write as _;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use log::warn;
+use serde::Serialize;
+
+use crate::error::Result;
+
+/// A log of slow SQL statements, shared by a node's sessions. Statements that
+/// take at least the threshold to execute are written to a file as JSON
+/// objects, one per line, with the statement text (with literal values
+/// redacted), duration, row count, and a plan summary, e.g.:
+///
+/// {"time":"2024-01-02T03:04:05.678Z","duration_ms":1234.5,"session":7,
+/// "statement":"SELECT * FROM test WHERE id > ?","rows":1000,
+/// "plan":"Projection(Scan test)"}
+///
+/// When the file exceeds its maximum size, it's rotated: it's renamed with a
+/// .1 suffix (replacing any previous one), and a new file is started. Failed
+/// writes are logged, but don't fail the statement.
+pub struct SlowQueryLog {
+    /// The minimum statement duration to log.
+    threshold: Duration,
+    /// The log file path.
+    path: PathBuf,
+    /// The maximum file size in bytes before it's rotated, or 0 to disable
+    /// rotation.
+    max_size: u64,
+    /// The open log file, and its current size.
+    file: Mutex<(File, u64)>,
+}
+
+/// A slow query log entry. Unknown fields are omitted.
+#[derive(Serialize)]
+pub(super) struct SlowQuery<'a> {
+    /// The time the statement completed.
+    pub time: String,
+    /// The statement duration in milliseconds.
+    pub duration_ms: f64,
+    /// The session ID.
+    pub session: u64,
+    /// The authenticated user, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user: Option<&'a str>,
+    /// The statement text, with literal values redacted.
+    pub statement: String,
+    /// The number of rows returned or written, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rows: Option<u64>,
+    /// The plan summary, if the statement was planned (see Plan::summary).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub plan: Option<String>,
+    /// The error, if the statement failed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl SlowQueryLog {
+    /// Opens a slow query log at the given path, appending to an existing
+    /// file. Statements that take at least the threshold are logged, and the
+    /// file is rotated when it exceeds max_size bytes (0 disables rotation).
+    pub fn open(path: impl Into<PathBuf>, threshold: Duration, max_size: u64) -> Result<Self> {
+        let path = path.into();
+        let (file, size) = Self::open_file(&path)?;
+        Ok(Self { threshold, path, max_size, file: Mutex::new((file, size)) })
+    }
+
+    /// Returns the minimum statement duration to log.
+    pub fn threshold(&self) -> Duration {
+        self.threshold
+    }
+
+    /// Opens the log file for appending, returning it and its size.
+    fn open_file(path: &Path) -> Result<(File, u64)> {
+        let file = File::options().create(true).append(true).open(path)?;
+        let size = file.metadata()?.len();
+        Ok((file, size))
+    }
+
+    /// Logs a slow statement. Errors are logged rather than returned.
+    pub(super) fn log(&self, entry: SlowQuery) {
+        let mut line = serde_json::to_string(&entry).expect("JSON serialization failed");
+        line.push('\n');
+        if let Err(error) = self.write(line.as_bytes()) {
+            warn!("Failed writing slow query log {}: {error}", self.path.display());
+        }
+    }
+
+    /// Writes a log line, rotating the file first if it would exceed the
+    /// maximum size.
+    fn write(&self, line: &[u8]) -> Result<()> {
+        let mut file = self.file.lock()?;
+        let (ref mut file, ref mut size) = *file;
+        if self.max_size > 0 && *size > 0 && *size + line.len() as u64 > self.max_size {
+            let mut rotated = self.path.clone().into_os_string();
+            rotated.push(".1");
+            std::fs::rename(&self.path, rotated)?;
+            (*file, *size) = Self::open_file(&self.path)?;
+        }
+        file.write_all(line)?;
+        *size += line.len() as u64;
+        Ok(())
+    }
+}
@@ -0,0 +1,293 @@
+use std::collections::{BTreeSet, HashMap};
+use std::hash::{DefaultHasher, Hash as _, Hasher as _};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::error::Result;
+use crate::sql::types::{Row, Rows, Value};
+
+/// In-memory statement and table statistics, shared by a node's sessions, for
+/// lightweight performance triage. They're exposed via the
+/// system.statement_statistics and system.table_statistics virtual tables.
+///
+/// Statements are grouped by fingerprint, i.e. their text with literal values
+/// redacted (see parser::redact), such that e.g. point lookups with different
+/// keys share statistics. At most MAX_STATEMENTS fingerprints are tracked,
+/// later ones are ignored. Table statistics count the rows read and written by
+/// sessions, including reads and writes made by triggers and foreign key
+/// checks via the session transaction, but not temporary tables.
+///
+/// Full table scans also record the table's row count and per-column distinct
+/// value counts, which the optimizer uses to estimate plan cardinalities (see
+/// TableEstimates).
+///
+/// Statistics are local to the node and reset when it restarts.
+#[derive(Debug, Default)]
+pub struct Statistics {
+    /// Statement statistics by fingerprint.
+    statements: Mutex<HashMap<String, StatementStatistics>>,
+    /// Table row counters by table name.
+    tables: Mutex<HashMap<String, Arc<TableCounters>>>,
+}
+
+/// Execution statistics for a statement fingerprint.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct StatementStatistics {
+    /// The statement text, with literal values redacted.
+    pub fingerprint: String,
+    /// The number of executions.
+    pub count: u64,
+    /// The number of executions that errored.
+    pub errors: u64,
+    /// The total number of rows returned or written.
+    pub rows: u64,
+    /// The total execution time.
+    pub total_duration: Duration,
+    /// The longest execution time.
+    pub max_duration: Duration,
+}
+
+/// Row statistics for a table.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TableStatistics {
+    /// The table name.
+    pub table: String,
+    /// The number of rows read.
+    pub rows_read: u64,
+    /// The number of rows inserted.
+    pub rows_inserted: u64,
+    /// The number of rows updated.
+    pub rows_updated: u64,
+    /// The number of rows deleted.
+    pub rows_deleted: u64,
+}
+
+/// Row count and distinct value estimates for a table, used by the optimizer.
+/// They're taken during full table scans, and the row count is adjusted for
+/// rows inserted and deleted since.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TableEstimates {
+    /// The estimated number of rows.
+    pub rows: u64,
+    /// The estimated number of distinct values in each column, by index.
+    pub distinct: Vec<u64>,
+}
+
+/// Row counters for a table. These are atomic, such that scans can count rows
+/// as they're read without taking a lock.
+#[derive(Debug, Default)]
+pub struct TableCounters {
+    /// The number of rows read.
+    pub rows_read: AtomicU64,
+    /// The number of rows inserted.
+    pub rows_inserted: AtomicU64,
+    /// The number of rows updated.
+    pub rows_updated: AtomicU64,
+    /// The number of rows deleted.
+    pub rows_deleted: AtomicU64,
+    /// The estimates from the last full table scan, along with the number of
+    /// rows inserted and deleted at the time.
+    scanned: Mutex<Option<(TableEstimates, u64, u64)>>,
+}
+
+impl Statistics {
+    /// The maximum number of statement fingerprints to track.
+    pub const MAX_STATEMENTS: usize = 1000;
+
+    /// Creates empty statistics.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records an execution of the statement with the given fingerprint.
+    pub fn record_statement(
+        &self,
+        fingerprint: &str,
+        duration: Duration,
+        rows: u64,
+        error: bool,
+    ) -> Result<()> {
+        let mut statements = self.statements.lock()?;
+        if !statements.contains_key(fingerprint) && statements.len() >= Self::MAX_STATEMENTS {
+            return Ok(());
+        }
+        let stats = statements.entry(fingerprint.to_string()).or_insert_with(|| {
+            StatementStatistics { fingerprint: fingerprint.to_string(), ..Default::default() }
+        });
+        stats.count += 1;
+        stats.errors += error as u64;
+        stats.rows += rows;
+        stats.total_duration += duration;
+        stats.max_duration = stats.max_duration.max(duration);
+        Ok(())
+    }
+
+    /// Returns the row counters for the given table.
+    pub fn table(&self, table: &str) -> Result<Arc<TableCounters>> {
+        let mut tables = self.tables.lock()?;
+        if let Some(counters) = tables.get(table) {
+            return Ok(counters.clone());
+        }
+        Ok(tables.entry(table.to_string()).or_default().clone())
+    }
+
+    /// Returns the row count and distinct value estimates for the given table,
+    /// or None if it hasn't been fully scanned yet.
+    pub fn table_estimates(&self, table: &str) -> Result<Option<TableEstimates>> {
+        let Some(counters) = self.tables.lock()?.get(table).cloned() else {
+            return Ok(None);
+        };
+        counters.estimates()
+    }
+
+    /// Returns the statement statistics, ordered by fingerprint.
+    pub fn statements(&self) -> Result<Vec<StatementStatistics>> {
+        let mut statements: Vec<_> = self.statements.lock()?.values().cloned().collect();
+        statements.sort_by(|a, b| a.fingerprint.cmp(&b.fingerprint));
+        Ok(statements)
+    }
+
+    /// Returns the table statistics, ordered by table name.
+    pub fn tables(&self) -> Result<Vec<TableStatistics>> {
+        let mut tables: Vec<_> = self
+            .tables
+            .lock()?
+            .iter()
+            .map(|(table, counters)| TableStatistics {
+                table: table.clone(),
+                rows_read: counters.rows_read.load(Ordering::Relaxed),
+                rows_inserted: counters.rows_inserted.load(Ordering::Relaxed),
+                rows_updated: counters.rows_updated.load(Ordering::Relaxed),
+                rows_deleted: counters.rows_deleted.load(Ordering::Relaxed),
+            })
+            .collect();
+        tables.sort_by(|a, b| a.table.cmp(&b.table));
+        Ok(tables)
+    }
+}
+
+impl TableCounters {
+    /// Returns the table's current estimates, if it has been fully scanned.
+    pub fn estimates(&self) -> Result<Option<TableEstimates>> {
+        let Some((estimates, inserted, deleted)) = self.scanned.lock()?.clone() else {
+            return Ok(None);
+        };
+        let inserted = self.rows_inserted.load(Ordering::Relaxed) - inserted;
+        let deleted = self.rows_deleted.load(Ordering::Relaxed) - deleted;
+        let rows = (estimates.rows + inserted).saturating_sub(deleted);
+        let distinct = estimates.distinct.into_iter().map(|d| d.min(rows)).collect();
+        Ok(Some(TableEstimates { rows, distinct }))
+    }
+
+    /// Clears the table's estimates, e.g. when it's dropped.
+    pub fn clear_estimates(&self) -> Result<()> {
+        *self.scanned.lock()? = None;
+        Ok(())
+    }
+
+    /// Records the estimates from a full table scan.
+    fn record_scan(&self, estimates: TableEstimates) -> Result<()> {
+        let inserted = self.rows_inserted.load(Ordering::Relaxed);
+        let deleted = self.rows_deleted.load(Ordering::Relaxed);
+        *self.scanned.lock()? = Some((estimates, inserted, deleted));
+        Ok(())
+    }
+}
+
+/// Counts the rows read by a full table scan, and records the table's row
+/// count and distinct value estimates once the scan completes.
+#[derive(Clone)]
+pub struct EstimatingScan {
+    /// The table scan.
+    rows: Rows,
+    /// The table's counters.
+    counters: Arc<TableCounters>,
+    /// The number of rows scanned so far.
+    count: u64,
+    /// Distinct value sketches for each column.
+    sketches: Vec<DistinctSketch>,
+    /// Whether the scan has completed or errored.
+    done: bool,
+}
+
+impl EstimatingScan {
+    /// Wraps a full table scan.
+    pub fn new(rows: Rows, counters: Arc<TableCounters>) -> Self {
+        Self { rows, counters, count: 0, sketches: Vec::new(), done: false }
+    }
+
+    /// Counts a scanned row.
+    fn count(&mut self, row: &Row) {
+        self.counters.rows_read.fetch_add(1, Ordering::Relaxed);
+        self.count += 1;
+        self.sketches.resize_with(row.len(), DistinctSketch::default);
+        for (sketch, value) in self.sketches.iter_mut().zip(row) {
+            sketch.insert(value);
+        }
+    }
+}
+
+impl Iterator for EstimatingScan {
+    type Item = Result<Row>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return self.rows.next();
+        }
+        match self.rows.next() {
+            Some(Ok(row)) => {
+                self.count(&row);
+                Some(Ok(row))
+            }
+            Some(Err(error)) => {
+                self.done = true;
+                Some(Err(error))
+            }
+            None => {
+                self.done = true;
+                let rows = self.count;
+                let distinct = self.sketches.iter().map(|s| s.estimate().min(rows)).collect();
+                if let Err(error) = self.counters.record_scan(TableEstimates { rows, distinct }) {
+                    return Some(Err(error));
+                }
+                None
+            }
+        }
+    }
+}
+
+/// Estimates the number of distinct values in a column, using a k-minimum
+/// values sketch: it retains the K smallest value hashes, and if there are
+/// more, estimates the count from how densely they cover the hash space.
+#[derive(Clone, Default)]
+struct DistinctSketch {
+    hashes: BTreeSet<u64>,
+}
+
+impl DistinctSketch {
+    /// The number of hashes to retain. Estimates are exact below this.
+    const K: usize = 256;
+
+    /// Adds a value to the sketch.
+    fn insert(&mut self, value: &Value) {
+        let mut hasher = DefaultHasher::new();
+        value.hash(&mut hasher);
+        let hash = hasher.finish();
+        if self.hashes.len() < Self::K {
+            self.hashes.insert(hash);
+        } else if self.hashes.last().is_some_and(|last| hash < *last) && self.hashes.insert(hash) {
+            self.hashes.pop_last();
+        }
+    }
+
+    /// Returns the estimated number of distinct values.
+    fn estimate(&self) -> u64 {
+        match self.hashes.last() {
+            Some(last) if self.hashes.len() >= Self::K => {
+                ((Self::K - 1) as f64 * u64::MAX as f64 / *last as f64) as u64
+            }
+            _ => self.hashes.len() as u64,
+        }
+    }
+}
@@ -0,0 +1,564 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::ops::Bound;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use super::statistics::{EstimatingScan, TableCounters};
+use super::{Catalog, Change, Engine as _, Local, Statistics, Transaction};
+use crate::errinput;
+use crate::error::Result;
+use crate::sql::types::{
+    Expression, Function, IndexState, IndexType, Privilege, Row, Rows, Table, Trigger,
+    TriggerCallback, User, Value,
+};
+use crate::storage::{self, mvcc};
+
+/// Session-local storage for temporary tables. Each session has its own
+/// in-memory SQL engine, which isn't replicated via Raft and isn't visible to
+/// other sessions. It's discarded when the session is dropped (e.g. when the
+/// client disconnects), along with all temporary tables.
+pub struct Temporary(Local<storage::Memory>);
+
+impl Temporary {
+    /// Creates a new, empty temporary table store.
+    pub fn new() -> Self {
+        Self(Local::new(storage::Memory::new()))
+    }
+
+    /// Begins a transaction in the temporary store, wrapping the given engine
+    /// transaction. It's read-only if the engine transaction is.
+    pub fn begin<T: Transaction + Catalog>(&self, txn: T) -> Result<SessionTransaction<T>> {
+        let temp = match txn.read_only() {
+            true => self.0.begin_read_only(),
+            false => self.0.begin(),
+        };
+        match temp {
+            Ok(temp) => Ok(SessionTransaction {
+                txn,
+                temp,
+                reads: None,
+                has_read: AtomicBool::new(false),
+                statistics: None,
+            }),
+            Err(error) => {
+                txn.rollback()?;
+                Err(error)
+            }
+        }
+    }
+}
+
+impl Default for Temporary {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A session transaction. Wraps an engine transaction along with a transaction
+/// in the session's temporary table store, and routes table operations to the
+/// store that contains the table. Temporary tables shadow regular tables with
+/// the same name.
+///
+/// The transaction state and version are those of the engine transaction.
+/// Temporary tables are always read at their latest version, even in AS OF
+/// transactions, and they can't reference or be referenced by regular tables
+/// via foreign keys.
+///
+/// For serializable transactions, the primary key ranges read from regular
+/// tables are recorded, and validated via Transaction::check_read() before
+/// the engine transaction commits. Reads made internally by the engine, e.g.
+/// for foreign key checks, aren't recorded.
+///
+/// If statistics are enabled, the rows read and written in regular tables are
+/// counted in their table statistics.
+pub struct SessionTransaction<T: Transaction + Catalog> {
+    /// The engine transaction.
+    txn: T,
+    /// The temporary table transaction.
+    temp: super::local::Transaction<storage::Memory>,
+    /// The primary key ranges read from each regular table, if serializable.
+    reads: Option<Mutex<BTreeMap<String, Vec<(Bound<Value>, Bound<Value>)>>>>,
+    /// Whether the transaction has read from a regular table.
+    has_read: AtomicBool,
+    /// The statistics to count table reads and writes in, if any.
+    statistics: Option<Arc<Statistics>>,
+}
+
+impl<T: Transaction + Catalog> SessionTransaction<T> {
+    /// Returns true if the given table is a temporary table.
+    pub fn is_temporary(&self, table: &str) -> Result<bool> {
+        Ok(self.temp.get_table(table)?.is_some())
+    }
+
+    /// Returns the transaction's isolation level.
+    pub fn isolation(&self) -> mvcc::IsolationLevel {
+        match self.reads {
+            Some(_) => mvcc::IsolationLevel::Serializable,
+            None => self.state().isolation(),
+        }
+    }
+
+    /// Returns true if the transaction has read from a regular table.
+    pub fn has_read(&self) -> bool {
+        self.has_read.load(Ordering::Relaxed)
+    }
+
+    /// Makes the transaction serializable, recording subsequent reads for
+    /// validation at commit. Must be called before any reads.
+    pub fn set_serializable(&mut self) {
+        self.reads.get_or_insert_default();
+    }
+
+    /// Sets the statistics to count table reads and writes in, if any.
+    pub fn set_statistics(&mut self, statistics: Option<Arc<Statistics>>) {
+        self.statistics = statistics;
+    }
+
+    /// Adds the given number of rows to a regular table's counter, selected
+    /// by the given function, if statistics are enabled.
+    fn count(&self, table: &str, rows: usize, f: fn(&TableCounters) -> &AtomicU64) -> Result<()> {
+        if let Some(statistics) = &self.statistics {
+            f(&*statistics.table(table)?).fetch_add(rows as u64, Ordering::Relaxed);
+        }
+        Ok(())
+    }
+
+    /// Records a read of the given primary key range from a regular table.
+    fn record_read(&self, table: &str, range: (Bound<Value>, Bound<Value>)) -> Result<()> {
+        self.has_read.store(true, Ordering::Relaxed);
+        if let Some(reads) = &self.reads {
+            reads.lock()?.entry(table.to_string()).or_default().push(range);
+        }
+        Ok(())
+    }
+
+    /// Validates the reads recorded by a serializable transaction against
+    /// concurrent writes (see Transaction::check_read).
+    fn validate_reads(&mut self) -> Result<()> {
+        let Some(reads) = self.reads.take() else {
+            return Ok(());
+        };
+        for (table, ranges) in reads.into_inner()? {
+            self.txn.check_read(&table, &ranges)?;
+        }
+        Ok(())
+    }
+}
+
+impl<T: Transaction + Catalog> Transaction for SessionTransaction<T> {
+    fn state(&self) -> &mvcc::TransactionState {
+        self.txn.state()
+    }
+
+    /// Commits the engine transaction, then the temporary transaction. If the
+    /// engine commit fails, the temporary changes are rolled back. Serializable
+    /// transactions first validate their reads, and roll back on conflicts.
+    fn commit(mut self) -> Result<()> {
+        if let Err(error) = self.validate_reads() {
+            self.txn.rollback()?;
+            self.temp.rollback()?;
+            return Err(error);
+        }
+        if let Err(error) = self.txn.commit() {
+            self.temp.rollback()?;
+            return Err(error);
+        }
+        self.temp.commit()
+    }
+
+    fn rollback(self) -> Result<()> {
+        let result = self.txn.rollback();
+        self.temp.rollback()?;
+        result
+    }
+
+    /// Like commit(), but commits the engine transaction under the token. If
+    /// it was rolled back instead, so are the temporary changes.
+    fn commit_idempotent(mut self, token: &str, result: Vec<u8>) -> Result<Option<Vec<u8>>> {
+        if let Err(error) = self.validate_reads() {
+            self.txn.rollback()?;
+            self.temp.rollback()?;
+            return Err(error);
+        }
+        match self.txn.commit_idempotent(token, result) {
+            Ok(None) => {
+                self.temp.commit()?;
+                Ok(None)
+            }
+            Ok(Some(existing)) => {
+                self.temp.rollback()?;
+                Ok(Some(existing))
+            }
+            Err(error) => {
+                self.temp.rollback()?;
+                Err(error)
+            }
+        }
+    }
+
+    fn get_idempotent(&self, token: &str) -> Result<Option<Vec<u8>>> {
+        self.txn.get_idempotent(token)
+    }
+
+    fn savepoint(&self, name: &str) -> Result<()> {
+        self.txn.savepoint(name)?;
+        self.temp.savepoint(name)
+    }
+
+    fn rollback_to_savepoint(&self, name: &str) -> Result<()> {
+        self.txn.rollback_to_savepoint(name)?;
+        self.temp.rollback_to_savepoint(name)
+    }
+
+    fn release_savepoint(&self, name: &str) -> Result<()> {
+        self.txn.release_savepoint(name)?;
+        self.temp.release_savepoint(name)
+    }
+
+    fn cancel_lock_waits(&self) -> Result<()> {
+        self.txn.cancel_lock_waits()
+    }
+
+    fn lock_waits(&self) -> Option<Arc<mvcc::LockWaits>> {
+        self.txn.lock_waits()
+    }
+
+    /// Temporary tables are session-local, so there are no concurrent writes
+    /// to see, and the temporary transaction's snapshot is kept.
+    fn refresh_snapshot(&mut self) -> Result<()> {
+        self.txn.refresh_snapshot()
+    }
+
+    fn abort_transaction(&self, version: mvcc::Version) -> Result<()> {
+        self.txn.abort_transaction(version)
+    }
+
+    /// Temporary tables are session-local, so they can't have concurrent
+    /// writes.
+    fn check_read(&self, table: &str, ranges: &[(Bound<Value>, Bound<Value>)]) -> Result<()> {
+        match self.is_temporary(table)? {
+            true => Ok(()),
+            false => self.txn.check_read(table, ranges),
+        }
+    }
+
+    fn delete(&self, table: &str, ids: &[Value]) -> Result<()> {
+        match self.is_temporary(table)? {
+            true => self.temp.delete(table, ids),
+            false => {
+                self.count(table, ids.len(), |c| &c.rows_deleted)?;
+                self.txn.delete(table, ids)
+            }
+        }
+    }
+
+    fn get(&self, table: &str, ids: &[Value]) -> Result<Vec<Row>> {
+        match self.is_temporary(table)? {
+            true => self.temp.get(table, ids),
+            false => {
+                for id in ids {
+                    self.record_read(
+                        table,
+                        (Bound::Included(id.clone()), Bound::Included(id.clone())),
+                    )?;
+                }
+                let rows = self.txn.get(table, ids)?;
+                self.count(table, rows.len(), |c| &c.rows_read)?;
+                Ok(rows)
+            }
+        }
+    }
+
+    fn insert(&self, table: &str, rows: Vec<Row>) -> Result<()> {
+        match self.is_temporary(table)? {
+            true => self.temp.insert(table, rows),
+            false => {
+                self.count(table, rows.len(), |c| &c.rows_inserted)?;
+                self.txn.insert(table, rows)
+            }
+        }
+    }
+
+    fn bulk_insert(&self, table: &str, rows: Vec<Row>) -> Result<()> {
+        match self.is_temporary(table)? {
+            true => self.temp.bulk_insert(table, rows),
+            false => {
+                self.count(table, rows.len(), |c| &c.rows_inserted)?;
+                self.txn.bulk_insert(table, rows)
+            }
+        }
+    }
+
+    /// Temporary tables are session-local, so their rows needn't be locked.
+    fn lock(&self, table: &str, ids: &[Value]) -> Result<Option<mvcc::Version>> {
+        match self.is_temporary(table)? {
+            true => Ok(None),
+            false => self.txn.lock(table, ids),
+        }
+    }
+
+    fn lookup_index(&self, table: &str, column: &str, values: &[Value]) -> Result<BTreeSet<Value>> {
+        match self.is_temporary(table)? {
+            true => self.temp.lookup_index(table, column, values),
+            false => {
+                // Index entries are written along with their rows, so the
+                // whole table is recorded as read, rather than the entries.
+                self.record_read(table, (Bound::Unbounded, Bound::Unbounded))?;
+                self.txn.lookup_index(table, column, values)
+            }
+        }
+    }
+
+    /// Temporary tables aren't tracked, so their results are never cached.
+    fn last_write_version(&self, tables: &[String]) -> Result<mvcc::Version> {
+        for table in tables {
+            if self.is_temporary(table)? {
+                return Ok(mvcc::Version::MAX);
+            }
+        }
+        self.txn.last_write_version(tables)
+    }
+
+    fn last_schema_version(&self, tables: &[String]) -> Result<mvcc::Version> {
+        for table in tables {
+            if self.is_temporary(table)? {
+                return Ok(mvcc::Version::MAX);
+            }
+        }
+        self.txn.last_schema_version(tables)
+    }
+
+    fn changes(&self, table: &str, after: u64, limit: usize) -> Result<Vec<Change>> {
+        match self.is_temporary(table)? {
+            true => self.temp.changes(table, after, limit),
+            false => self.txn.changes(table, after, limit),
+        }
+    }
+
+    fn key_range(&self, table: &str) -> Result<Option<(Value, Value)>> {
+        match self.is_temporary(table)? {
+            true => self.temp.key_range(table),
+            false => {
+                self.record_read(table, (Bound::Unbounded, Bound::Unbounded))?;
+                self.txn.key_range(table)
+            }
+        }
+    }
+
+    fn scan_range(
+        &self,
+        table: &str,
+        range: (Bound<Value>, Bound<Value>),
+        filter: Option<Expression>,
+    ) -> Result<Rows> {
+        match self.is_temporary(table)? {
+            true => self.temp.scan_range(table, range, filter),
+            false => {
+                self.record_read(table, range.clone())?;
+                let full = range == (Bound::Unbounded, Bound::Unbounded) && filter.is_none();
+                let rows = self.txn.scan_range(table, range, filter)?;
+                let Some(statistics) = &self.statistics else {
+                    return Ok(rows);
+                };
+                let counters = statistics.table(table)?;
+                if full {
+                    return Ok(Box::new(EstimatingScan::new(rows, counters)));
+                }
+                Ok(Box::new(rows.inspect(move |row| {
+                    if row.is_ok() {
+                        counters.rows_read.fetch_add(1, Ordering::Relaxed);
+                    }
+                })))
+            }
+        }
+    }
+
+    fn update(&self, table: &str, rows: BTreeMap<Value, Row>) -> Result<()> {
+        match self.is_temporary(table)? {
+            true => self.temp.update(table, rows),
+            false => {
+                self.count(table, rows.len(), |c| &c.rows_updated)?;
+                self.txn.update(table, rows)
+            }
+        }
+    }
+}
+
+impl<T: Transaction + Catalog> Catalog for SessionTransaction<T> {
+    fn create_table(&self, table: Table) -> Result<()> {
+        self.txn.create_table(table)
+    }
+
+    fn create_temporary_table(&self, table: Table) -> Result<()> {
+        self.temp.create_table(table)
+    }
+
+    fn drop_table(&self, table: &str, if_exists: bool) -> Result<bool> {
+        match self.is_temporary(table)? {
+            true => self.temp.drop_table(table, if_exists),
+            false => {
+                if let Some(statistics) = &self.statistics {
+                    statistics.table(table)?.clear_estimates()?;
+                }
+                self.txn.drop_table(table, if_exists)
+            }
+        }
+    }
+
+    fn create_index(
+        &self,
+        table: &str,
+        column: &str,
+        index_type: IndexType,
+        predicate: Option<Expression>,
+    ) -> Result<()> {
+        match self.is_temporary(table)? {
+            true => self.temp.create_index(table, column, index_type, predicate),
+            false => self.txn.create_index(table, column, index_type, predicate),
+        }
+    }
+
+    fn create_index_online(
+        &self,
+        table: &str,
+        column: &str,
+        index_type: IndexType,
+        predicate: Option<Expression>,
+    ) -> Result<IndexState> {
+        match self.is_temporary(table)? {
+            true => self.temp.create_index_online(table, column, index_type, predicate),
+            false => self.txn.create_index_online(table, column, index_type, predicate),
+        }
+    }
+
+    fn advance_index(&self, table: &str, column: &str, from: IndexState) -> Result<IndexState> {
+        match self.is_temporary(table)? {
+            true => self.temp.advance_index(table, column, from),
+            false => self.txn.advance_index(table, column, from),
+        }
+    }
+
+    fn abort_index(&self, table: &str, column: &str) -> Result<()> {
+        match self.is_temporary(table)? {
+            true => self.temp.abort_index(table, column),
+            false => self.txn.abort_index(table, column),
+        }
+    }
+
+    fn backfill_index(
+        &self,
+        table: &str,
+        column: &str,
+        after: Option<Value>,
+        limit: usize,
+    ) -> Result<Option<Value>> {
+        match self.is_temporary(table)? {
+            true => self.temp.backfill_index(table, column, after, limit),
+            false => self.txn.backfill_index(table, column, after, limit),
+        }
+    }
+
+    fn create_trigger(&self, table: &str, trigger: Trigger) -> Result<()> {
+        match self.is_temporary(table)? {
+            true => self.temp.create_trigger(table, trigger),
+            false => self.txn.create_trigger(table, trigger),
+        }
+    }
+
+    fn drop_trigger(&self, table: &str, trigger: &str, if_exists: bool) -> Result<bool> {
+        match self.is_temporary(table)? {
+            true => self.temp.drop_trigger(table, trigger, if_exists),
+            false => self.txn.drop_trigger(table, trigger, if_exists),
+        }
+    }
+
+    /// Temporary tables aren't visible to other sessions, so they can't have
+    /// changefeeds.
+    fn set_changefeed(&self, table: &str, changefeed: bool) -> Result<()> {
+        match self.is_temporary(table)? {
+            true => errinput!("temporary table {table} can't have a changefeed"),
+            false => self.txn.set_changefeed(table, changefeed),
+        }
+    }
+
+    fn drop_partition(&self, table: &str, partition: &str) -> Result<u64> {
+        match self.is_temporary(table)? {
+            true => self.temp.drop_partition(table, partition),
+            false => {
+                let rows = self.txn.drop_partition(table, partition)?;
+                self.count(table, rows as usize, |c| &c.rows_deleted)?;
+                Ok(rows)
+            }
+        }
+    }
+
+    fn truncate_table(&self, table: &str) -> Result<u64> {
+        match self.is_temporary(table)? {
+            true => self.temp.truncate_table(table),
+            false => {
+                let rows = self.txn.truncate_table(table)?;
+                self.count(table, rows as usize, |c| &c.rows_deleted)?;
+                Ok(rows)
+            }
+        }
+    }
+
+    fn create_user(&self, user: User) -> Result<()> {
+        self.txn.create_user(user)
+    }
+
+    fn drop_user(&self, name: &str, if_exists: bool) -> Result<bool> {
+        self.txn.drop_user(name, if_exists)
+    }
+
+    /// Temporary tables are only visible to the session, which has all
+    /// privileges on them, so they can't be granted.
+    fn grant(&self, user: &str, table: Option<&str>, privileges: &[Privilege]) -> Result<()> {
+        if let Some(table) = table {
+            if self.is_temporary(table)? {
+                return errinput!("can't grant privileges on temporary table {table}");
+            }
+        }
+        self.txn.grant(user, table, privileges)
+    }
+
+    fn revoke(&self, user: &str, table: Option<&str>, privileges: &[Privilege]) -> Result<()> {
+        self.txn.revoke(user, table, privileges)
+    }
+
+    fn get_table(&self, table: &str) -> Result<Option<Table>> {
+        match self.temp.get_table(table)? {
+            Some(table) => Ok(Some(table)),
+            None => self.txn.get_table(table),
+        }
+    }
+
+    fn list_tables(&self) -> Result<Vec<Table>> {
+        let mut tables: BTreeMap<String, Table> =
+            self.txn.list_tables()?.into_iter().map(|t| (t.name.clone(), t)).collect();
+        tables.extend(self.temp.list_tables()?.into_iter().map(|t| (t.name.clone(), t)));
+        Ok(tables.into_values().collect())
+    }
+
+    fn list_transactions(&self) -> Result<Vec<mvcc::TransactionInfo>> {
+        self.txn.list_transactions()
+    }
+
+    fn statistics(&self) -> Option<Arc<Statistics>> {
+        self.statistics.clone()
+    }
+
+    fn get_user(&self, name: &str) -> Result<Option<User>> {
+        self.txn.get_user(name)
+    }
+
+    fn get_function(&self, name: &str) -> Result<Option<Function>> {
+        self.txn.get_function(name)
+    }
+
+    /// Trigger callbacks are registered with the engine, so they also fire
+    /// for temporary tables with the same name.
+    fn get_trigger_callbacks(&self, table: &str) -> Result<Vec<TriggerCallback>> {
+        self.txn.get_trigger_callbacks(table)
+    }
+}
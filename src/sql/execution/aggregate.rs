@@ -1,22 +1,27 @@
 use std::collections::BTreeMap;
+use std::collections::btree_map::Entry;
 
 use itertools::Itertools as _;
 
+use super::MemoryAccountant;
+use super::batch::{self, Batch, Batches};
 use crate::error::Result;
 use crate::sql::planner::Aggregate;
 use crate::sql::types::{Expression, Row, Rows, Value};
 
 /// Aggregates row values from the source according to the aggregates, using the
 /// group_by expressions as buckets. Emits rows with group_by buckets then
-/// aggregates in the given order.
+/// aggregates in the given order. Memory is reserved for each bucket. The
+/// source is aggregated a batch at a time.
 pub fn aggregate(
-    mut source: Rows,
+    mut source: Batches,
     group_by: Vec<Expression>,
     aggregates: Vec<Aggregate>,
+    memory: &MemoryAccountant,
 ) -> Result<Rows> {
-    let mut aggregator = Aggregator::new(group_by, aggregates);
-    while let Some(row) = source.next().transpose()? {
-        aggregator.add(row)?;
+    let mut aggregator = Aggregator::new(group_by, aggregates, memory.clone());
+    while let Some(batch) = source.next().transpose()? {
+        aggregator.add_batch(batch)?;
     }
     aggregator.into_rows()
 }
@@ -31,11 +36,17 @@ struct Aggregator {
     group_by: Vec<Expression>,
     /// Expressions to accumulate. Indexes map to accumulators.
     expressions: Vec<Expression>,
+    /// Accounts for bucket memory.
+    memory: MemoryAccountant,
 }
 
 impl Aggregator {
     /// Creates a new aggregator for the given GROUP BY buckets and aggregates.
-    fn new(group_by: Vec<Expression>, aggregates: Vec<Aggregate>) -> Self {
+    fn new(
+        group_by: Vec<Expression>,
+        aggregates: Vec<Aggregate>,
+        memory: MemoryAccountant,
+    ) -> Self {
         use Aggregate::*;
         let accumulators = aggregates.iter().map(Accumulator::new).collect();
         let expressions = aggregates
@@ -44,7 +55,7 @@ impl Aggregator {
                 Average(expr) | Count(expr) | Max(expr) | Min(expr) | Sum(expr) => expr,
             })
             .collect();
-        Self { buckets: BTreeMap::new(), empty: accumulators, group_by, expressions }
+        Self { buckets: BTreeMap::new(), empty: accumulators, group_by, expressions, memory }
     }
 
     /// Adds a row to the aggregator.
@@ -54,13 +65,58 @@ impl Aggregator {
             self.group_by.iter().map(|expr| expr.evaluate(Some(&row))).try_collect()?;
 
         // Compute and accumulate the input values.
-        let accumulators = self.buckets.entry(bucket).or_insert_with(|| self.empty.clone());
+        let accumulators = Self::bucket(&mut self.buckets, &self.empty, &self.memory, bucket)?;
         for (accumulator, expr) in accumulators.iter_mut().zip(&self.expressions) {
             accumulator.add(expr.evaluate(Some(&row))?)?;
         }
         Ok(())
     }
 
+    /// Adds a batch of rows to the aggregator. The bucket and input values are
+    /// evaluated vectorized, falling back to adding one row at a time if
+    /// evaluation fails, to return the same error as row-at-a-time execution.
+    fn add_batch(&mut self, batch: Batch) -> Result<()> {
+        let evaluate = |exprs: &[Expression]| -> Result<Vec<Vec<Value>>> {
+            exprs.iter().map(|expr| batch::evaluate(expr, &batch)).try_collect()
+        };
+        let (buckets, inputs) = match (evaluate(&self.group_by), evaluate(&self.expressions)) {
+            (Ok(buckets), Ok(inputs)) => (buckets, inputs),
+            _ => return batch.into_rows().into_iter().try_for_each(|row| self.add(row)),
+        };
+
+        // Transpose the bucket columns into bucket values, and iterate over
+        // the input columns in lockstep.
+        let mut buckets = buckets.into_iter().map(|column| column.into_iter()).collect_vec();
+        let mut inputs = inputs.into_iter().map(|column| column.into_iter()).collect_vec();
+        for _ in 0..batch.len() {
+            let bucket = buckets.iter_mut().map(|c| c.next().expect("short column")).collect();
+            let accumulators = Self::bucket(&mut self.buckets, &self.empty, &self.memory, bucket)?;
+            for (accumulator, input) in accumulators.iter_mut().zip(&mut inputs) {
+                accumulator.add(input.next().expect("short column"))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the accumulators for the given bucket, creating and reserving
+    /// memory for it if it doesn't exist. Takes the fields separately, to
+    /// allow borrowing the expressions while accumulating.
+    fn bucket<'a>(
+        buckets: &'a mut BTreeMap<Vec<Value>, Vec<Accumulator>>,
+        empty: &[Accumulator],
+        memory: &MemoryAccountant,
+        bucket: Vec<Value>,
+    ) -> Result<&'a mut Vec<Accumulator>> {
+        Ok(match buckets.entry(bucket) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                memory.reserve_values(entry.key())?;
+                memory.reserve(size_of_val(empty))?;
+                entry.insert(empty.to_vec())
+            }
+        })
+    }
+
     /// Returns a row iterator over the aggregate result.
     fn into_rows(self) -> Result<Rows> {
         // If there were no rows and no group_by expressions, return a row of
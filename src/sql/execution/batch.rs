@@ -0,0 +1,291 @@
+use dyn_clone::DynClone;
+use itertools::Itertools as _;
+
+use crate::errinput;
+use crate::error::{Error, Result};
+use crate::sql::types::{Expression, Row, Rows, Value};
+
+/// The maximum number of rows in a batch.
+pub const BATCH_SIZE: usize = 1024;
+
+/// A batch of rows in columnar form, i.e. with a vector of values for each
+/// column. Filters, projections, and aggregations process a batch at a time,
+/// evaluating expressions over entire columns (see evaluate). This amortizes
+/// the cost of walking the expression tree across the batch, and avoids
+/// building a row for every intermediate result.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Batch {
+    /// The column values. All columns have the same length.
+    columns: Vec<Vec<Value>>,
+    /// The number of rows.
+    len: usize,
+}
+
+impl Batch {
+    /// Creates a batch from the given rows, which must have the same width.
+    pub fn from_rows(rows: Vec<Row>) -> Self {
+        let len = rows.len();
+        let width = rows.first().map(|row| row.len()).unwrap_or(0);
+        let mut columns = vec![Vec::with_capacity(len); width];
+        for row in rows {
+            for (column, value) in columns.iter_mut().zip(row) {
+                column.push(value);
+            }
+        }
+        Self { columns, len }
+    }
+
+    /// Returns the number of rows in the batch.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns true if the batch has no rows.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the row at the given index.
+    pub fn row(&self, index: usize) -> Row {
+        self.columns.iter().map(|column| column[index].clone()).collect()
+    }
+
+    /// Converts the batch into rows.
+    pub fn into_rows(self) -> Vec<Row> {
+        let mut rows = vec![Vec::with_capacity(self.columns.len()); self.len];
+        for column in self.columns {
+            for (row, value) in rows.iter_mut().zip(column) {
+                row.push(value);
+            }
+        }
+        rows
+    }
+
+    /// Retains the rows whose mask entry is true.
+    fn retain(&mut self, mask: &[bool]) {
+        for column in &mut self.columns {
+            let mut mask = mask.iter();
+            column.retain(|_| *mask.next().expect("short mask"));
+        }
+        self.len = mask.iter().filter(|keep| **keep).count();
+    }
+}
+
+/// A batch iterator. Like Rows, it must be clonable and object-safe.
+pub type Batches = Box<dyn BatchIterator>;
+
+/// A batch iterator trait, with a blanket implementation for all matching
+/// iterators (see RowIterator).
+pub trait BatchIterator: Iterator<Item = Result<Batch>> + DynClone + Send {}
+impl<I: Iterator<Item = Result<Batch>> + DynClone + Send> BatchIterator for I {}
+dyn_clone::clone_trait_object!(BatchIterator);
+
+/// Collects source rows into batches of up to BATCH_SIZE rows. If the source
+/// errors, the rows before the error are emitted as a batch first.
+pub fn batches(source: Rows) -> Batches {
+    Box::new(Chunks { source, error: None, done: false })
+}
+
+/// An iterator that collects rows into batches (see batches).
+#[derive(Clone)]
+struct Chunks {
+    source: Rows,
+    /// A source error to emit after the current batch.
+    error: Option<Error>,
+    /// Whether the source is exhausted or errored.
+    done: bool,
+}
+
+impl Iterator for Chunks {
+    type Item = Result<Batch>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(error) = self.error.take() {
+            return Some(Err(error));
+        }
+        if self.done {
+            return None;
+        }
+        let mut rows = Vec::new();
+        while rows.len() < BATCH_SIZE {
+            match self.source.next() {
+                Some(Ok(row)) => rows.push(row),
+                Some(Err(error)) if rows.is_empty() => {
+                    self.done = true;
+                    return Some(Err(error));
+                }
+                Some(Err(error)) => {
+                    (self.error, self.done) = (Some(error), true);
+                    break;
+                }
+                None => {
+                    self.done = true;
+                    break;
+                }
+            }
+        }
+        (!rows.is_empty()).then(|| Ok(Batch::from_rows(rows)))
+    }
+}
+
+/// Converts batches back into rows.
+pub fn rows(source: Batches) -> Rows {
+    Box::new(source.flat_map(|batch| match batch {
+        Ok(batch) => batch.into_rows().into_iter().map(Ok).collect_vec(),
+        Err(error) => vec![Err(error)],
+    }))
+}
+
+/// Filters the input batches (i.e. WHERE).
+pub fn filter(source: Batches, predicate: Expression) -> Batches {
+    Box::new(source.flat_map(move |batch| {
+        let mut batch = match batch {
+            Ok(batch) => batch,
+            Err(error) => return vec![Err(error)],
+        };
+        let mask: Result<Vec<bool>> = evaluate(&predicate, &batch).and_then(|values| {
+            values.into_iter().map(|value| filter_value(value).map(|keep| keep.is_some())).collect()
+        });
+        match mask {
+            Ok(mask) => {
+                batch.retain(&mask);
+                if batch.is_empty() { vec![] } else { vec![Ok(batch)] }
+            }
+            // On errors, evaluate the rows one at a time, to emit the rows
+            // before the failing row like row-at-a-time execution would.
+            Err(_) => {
+                by_row(batch, |row| Ok(filter_value(predicate.evaluate(Some(&row))?)?.map(|_| row)))
+            }
+        }
+    }))
+}
+
+/// Returns Some if a filter predicate value matches, None if it doesn't, or
+/// an error if it isn't a boolean or NULL.
+fn filter_value(value: Value) -> Result<Option<()>> {
+    match value {
+        Value::Boolean(true) => Ok(Some(())),
+        Value::Boolean(false) | Value::Null => Ok(None),
+        value => errinput!("filter returned {value}, expected boolean"),
+    }
+}
+
+/// Projects the batches using the given expressions (i.e. SELECT).
+pub fn project(source: Batches, expressions: Vec<Expression>) -> Batches {
+    Box::new(source.flat_map(move |batch| {
+        let batch = match batch {
+            Ok(batch) => batch,
+            Err(error) => return vec![Err(error)],
+        };
+        let columns: Result<Vec<_>> = expressions.iter().map(|e| evaluate(e, &batch)).collect();
+        match columns {
+            Ok(columns) => vec![Ok(Batch { columns, len: batch.len })],
+            // On errors, evaluate the rows one at a time (see filter).
+            Err(_) => by_row(batch, |row| {
+                Ok(Some(expressions.iter().map(|e| e.evaluate(Some(&row))).try_collect()?))
+            }),
+        }
+    }))
+}
+
+/// Processes a batch one row at a time, emitting the resulting rows as a
+/// batch, followed by the first error (if any).
+fn by_row(batch: Batch, f: impl Fn(Row) -> Result<Option<Row>>) -> Vec<Result<Batch>> {
+    let mut rows = Vec::new();
+    let mut error = None;
+    for row in batch.into_rows() {
+        match f(row) {
+            Ok(Some(row)) => rows.push(row),
+            Ok(None) => {}
+            Err(err) => {
+                error = Some(err);
+                break;
+            }
+        }
+    }
+    let batch = (!rows.is_empty()).then(|| Ok(Batch::from_rows(rows)));
+    batch.into_iter().chain(error.map(Err)).collect()
+}
+
+/// Evaluates an expression for each row in the batch, returning a column of
+/// values. Logical, comparison, and arithmetic operators are evaluated
+/// column-wise with vectorized kernels, other expressions one row at a time.
+///
+/// Unlike row-at-a-time evaluation, a column is fully evaluated before the
+/// next one, so if several rows error, the returned error may come from a
+/// different row. Callers fall back to row-at-a-time evaluation on errors.
+pub fn evaluate(expr: &Expression, batch: &Batch) -> Result<Vec<Value>> {
+    use Expression::*;
+
+    // Evaluates a binary operator with the given kernel.
+    let binary = |lhs: &Expression, rhs: &Expression, kernel: fn(Value, Value) -> Result<Value>| {
+        let (lhs, rhs) = (evaluate(lhs, batch)?, evaluate(rhs, batch)?);
+        lhs.into_iter().zip(rhs).map(|(lhs, rhs)| kernel(lhs, rhs)).collect()
+    };
+
+    match expr {
+        Constant(value) => Ok(vec![value.clone(); batch.len]),
+        Column(index) => Ok(batch.columns[*index].clone()),
+
+        And(lhs, rhs) => binary(lhs, rhs, |lhs, rhs| match (lhs, rhs) {
+            (Value::Boolean(lhs), Value::Boolean(rhs)) => Ok(Value::Boolean(lhs && rhs)),
+            (lhs, rhs) => scalar(And, lhs, rhs),
+        }),
+        Or(lhs, rhs) => binary(lhs, rhs, |lhs, rhs| match (lhs, rhs) {
+            (Value::Boolean(lhs), Value::Boolean(rhs)) => Ok(Value::Boolean(lhs || rhs)),
+            (lhs, rhs) => scalar(Or, lhs, rhs),
+        }),
+        Not(expr) => evaluate(expr, batch)?
+            .into_iter()
+            .map(|value| match value {
+                Value::Boolean(b) => Ok(Value::Boolean(!b)),
+                value => Not(Box::new(Constant(value))).evaluate(None),
+            })
+            .collect(),
+
+        // Comparisons have fast paths for integers, floats, and strings. Float
+        // comparisons follow IEEE 754 like the scalar evaluation (e.g. NaN !=
+        // NaN), not Value's total order.
+        #[allow(clippy::float_cmp)]
+        Equal(lhs, rhs) => binary(lhs, rhs, |lhs, rhs| match (lhs, rhs) {
+            (Value::Integer(lhs), Value::Integer(rhs)) => Ok(Value::Boolean(lhs == rhs)),
+            (Value::Float(lhs), Value::Float(rhs)) => Ok(Value::Boolean(lhs == rhs)),
+            (Value::String(lhs), Value::String(rhs)) => Ok(Value::Boolean(lhs == rhs)),
+            (lhs, rhs) => scalar(Equal, lhs, rhs),
+        }),
+        GreaterThan(lhs, rhs) => binary(lhs, rhs, |lhs, rhs| match (lhs, rhs) {
+            (Value::Integer(lhs), Value::Integer(rhs)) => Ok(Value::Boolean(lhs > rhs)),
+            (Value::Float(lhs), Value::Float(rhs)) => Ok(Value::Boolean(lhs > rhs)),
+            (Value::String(lhs), Value::String(rhs)) => Ok(Value::Boolean(lhs > rhs)),
+            (lhs, rhs) => scalar(GreaterThan, lhs, rhs),
+        }),
+        LessThan(lhs, rhs) => binary(lhs, rhs, |lhs, rhs| match (lhs, rhs) {
+            (Value::Integer(lhs), Value::Integer(rhs)) => Ok(Value::Boolean(lhs < rhs)),
+            (Value::Float(lhs), Value::Float(rhs)) => Ok(Value::Boolean(lhs < rhs)),
+            (Value::String(lhs), Value::String(rhs)) => Ok(Value::Boolean(lhs < rhs)),
+            (lhs, rhs) => scalar(LessThan, lhs, rhs),
+        }),
+        Is(expr, Value::Null) => Ok(evaluate(expr, batch)?
+            .into_iter()
+            .map(|v| Value::Boolean(v == Value::Null))
+            .collect()),
+
+        Add(lhs, rhs) => binary(lhs, rhs, |lhs, rhs| lhs.checked_add(&rhs)),
+        Divide(lhs, rhs) => binary(lhs, rhs, |lhs, rhs| lhs.checked_div(&rhs)),
+        Multiply(lhs, rhs) => binary(lhs, rhs, |lhs, rhs| lhs.checked_mul(&rhs)),
+        Remainder(lhs, rhs) => binary(lhs, rhs, |lhs, rhs| lhs.checked_rem(&rhs)),
+        Subtract(lhs, rhs) => binary(lhs, rhs, |lhs, rhs| lhs.checked_sub(&rhs)),
+
+        expr => (0..batch.len).map(|i| expr.evaluate(Some(&batch.row(i)))).collect(),
+    }
+}
+
+/// Evaluates a binary operator on two values with scalar evaluation, for
+/// values without a kernel fast path.
+fn scalar(
+    op: fn(Box<Expression>, Box<Expression>) -> Expression,
+    lhs: Value,
+    rhs: Value,
+) -> Result<Value> {
+    op(Box::new(Expression::Constant(lhs)), Box::new(Expression::Constant(rhs))).evaluate(None)
+}
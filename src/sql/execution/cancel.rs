@@ -0,0 +1,77 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
+
+use crate::clock::Clock;
+use crate::error::{Error, Result};
+use crate::sql::types::Rows;
+
+/// A cancellation token for a statement execution. Clones share the same
+/// cancellation flag, such that a statement can be cancelled from a different
+/// thread (e.g. on a client cancel request) while it's executing. Executor
+/// nodes check the token between each row they emit, and error with
+/// Error::Cancelled once it's cancelled.
+///
+/// A token can also have a deadline (e.g. a statement timeout), after which
+/// it's considered cancelled, and errors with Error::Timeout instead. The
+/// deadline is checked against the given clock.
+#[derive(Clone, Debug, Default)]
+pub struct CancelToken {
+    cancelled: Arc<AtomicBool>,
+    deadline: Option<(Instant, Arc<dyn Clock>)>,
+}
+
+impl CancelToken {
+    /// Creates a new, uncancelled token.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns a token that shares this token's cancellation flag, but is
+    /// also cancelled at the given deadline of the given clock (or this
+    /// token's deadline, if earlier).
+    pub fn with_deadline(&self, deadline: Instant, clock: Arc<dyn Clock>) -> Self {
+        let deadline = match &self.deadline {
+            Some((earlier, clock)) if *earlier <= deadline => (*earlier, clock.clone()),
+            _ => (deadline, clock),
+        };
+        Self { cancelled: self.cancelled.clone(), deadline: Some(deadline) }
+    }
+
+    /// Cancels the statement. This can't be undone.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed)
+    }
+
+    /// Returns true if the statement has been cancelled, or its deadline has
+    /// passed.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed) || self.expired()
+    }
+
+    /// Errors with Error::Cancelled if the statement has been cancelled, or
+    /// Error::Timeout if its deadline has passed.
+    pub fn check(&self) -> Result<()> {
+        if self.cancelled.load(Ordering::Relaxed) {
+            return Err(Error::Cancelled);
+        }
+        if self.expired() {
+            return Err(Error::Timeout);
+        }
+        Ok(())
+    }
+
+    /// Returns true if the deadline has passed.
+    fn expired(&self) -> bool {
+        self.deadline.as_ref().is_some_and(|(deadline, clock)| clock.now() >= *deadline)
+    }
+
+    /// Wraps a row iterator, checking the token before emitting each row.
+    pub fn wrap(&self, rows: Rows) -> Rows {
+        let token = self.clone();
+        Box::new(rows.map(move |row| {
+            token.check()?;
+            row
+        }))
+    }
+}
@@ -0,0 +1,183 @@
+use std::collections::VecDeque;
+use std::ops::Bound;
+use std::thread::JoinHandle;
+
+use crossbeam::channel::Receiver;
+use itertools::Itertools as _;
+
+use super::{CancelToken, MemoryAccountant, aggregate, batch, source};
+use crate::error::Result;
+use crate::sql::engine::Transaction;
+use crate::sql::planner::Node;
+use crate::sql::types::{Row, Rows, Value};
+
+/// A primary key range partition.
+type Range = (Bound<Value>, Bound<Value>);
+
+/// Executes a plan fragment in parallel across the given number of partitions,
+/// by splitting the fragment's table scan into primary key ranges and
+/// executing each range in a separate worker thread. The partition results are
+/// streamed in primary key range order (see Exchange).
+pub fn exchange(
+    txn: &impl Transaction,
+    source: Node,
+    partitions: usize,
+    cancel: &CancelToken,
+    memory: &MemoryAccountant,
+) -> Result<Rows> {
+    let table = scan_table(&source);
+    let partitions = partition(txn.key_range(table)?, partitions)
+        .into_iter()
+        .map(|range| execute_partition(source.clone(), txn, range, cancel, memory))
+        .try_collect()?;
+    Ok(Box::new(Exchange::new(partitions)))
+}
+
+/// Streams partition results from worker threads. Each worker sends its rows
+/// through a bounded channel, and blocks when the channel is full until the
+/// consumer catches up, so only a few rows per partition are buffered at a
+/// time. Rows are emitted in partition order. The workers are started on the
+/// first call to next(), and exit when the exchange is dropped.
+///
+/// Clones start their own workers, skipping the rows already emitted. This
+/// relies on partitions emitting the same rows every time, which holds since
+/// they read from the same transaction snapshot.
+struct Exchange {
+    /// The partition row iterators, before they're started.
+    partitions: Vec<Rows>,
+    /// The remaining partitions' workers, once started.
+    workers: Option<VecDeque<(Receiver<Result<Row>>, JoinHandle<()>)>>,
+    /// The current partition, and the number of rows emitted from it.
+    position: (usize, usize),
+}
+
+impl Exchange {
+    /// The number of rows buffered per partition. Uses 2 in tests to exercise
+    /// blocked workers.
+    const BUFFER_SIZE: usize = if cfg!(test) { 2 } else { 1024 };
+
+    /// Creates a new exchange for the given partitions.
+    fn new(partitions: Vec<Rows>) -> Self {
+        Self { partitions, workers: None, position: (0, 0) }
+    }
+
+    /// Starts worker threads for the remaining partitions.
+    fn start(&self) -> VecDeque<(Receiver<Result<Row>>, JoinHandle<()>)> {
+        let (current, emitted) = self.position;
+        let partitions = self.partitions.iter().cloned().enumerate().skip(current);
+        partitions
+            .map(|(i, rows)| {
+                let (tx, rx) = crossbeam::channel::bounded(Self::BUFFER_SIZE);
+                let skip = if i == current { emitted } else { 0 };
+                let worker = std::thread::spawn(move || {
+                    for row in rows.skip(skip) {
+                        // The consumer dropped the exchange.
+                        if tx.send(row).is_err() {
+                            return;
+                        }
+                    }
+                });
+                (rx, worker)
+            })
+            .collect()
+    }
+}
+
+impl Clone for Exchange {
+    fn clone(&self) -> Self {
+        Self { partitions: self.partitions.clone(), workers: None, position: self.position }
+    }
+}
+
+impl Iterator for Exchange {
+    type Item = Result<Row>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.workers.is_none() {
+            self.workers = Some(self.start());
+        }
+        let workers = self.workers.as_mut()?;
+        while let Some((rx, _)) = workers.front() {
+            if let Ok(row) = rx.recv() {
+                self.position.1 += 1;
+                return Some(row);
+            }
+            // The worker completed. Propagate any panic.
+            let (_, worker) = workers.pop_front()?;
+            worker.join().expect("partition worker panicked");
+            self.position = (self.position.0 + 1, 0);
+        }
+        None
+    }
+}
+
+/// Executes a plan fragment for a single partition, scanning the given primary
+/// key range. Filters, projections, and aggregates are evaluated a batch at a
+/// time, like in execute(). Panics on nodes that can't be partitioned.
+fn execute_partition(
+    node: Node,
+    txn: &impl Transaction,
+    range: Range,
+    cancel: &CancelToken,
+    memory: &MemoryAccountant,
+) -> Result<Rows> {
+    let rows = match node {
+        Node::Aggregate { source, group_by, aggregates } => {
+            let source = execute_partition(*source, txn, range, cancel, memory)?;
+            aggregate::aggregate(batch::batches(source), group_by, aggregates, memory)?
+        }
+        Node::Filter { source, predicate } => {
+            let source = execute_partition(*source, txn, range, cancel, memory)?;
+            batch::rows(batch::filter(batch::batches(source), predicate))
+        }
+        Node::Projection { source, expressions, aliases: _ } => {
+            let source = execute_partition(*source, txn, range, cancel, memory)?;
+            batch::rows(batch::project(batch::batches(source), expressions))
+        }
+        Node::Scan { table, filter, alias: _, partitions } => {
+            source::scan_range(txn, table, range, filter, partitions)?
+        }
+        node => panic!("can't partition node {node:?}"),
+    };
+    Ok(cancel.wrap(rows))
+}
+
+/// Returns the name of the table scanned by a plan fragment.
+fn scan_table(node: &Node) -> &str {
+    match node {
+        Node::Aggregate { source, .. }
+        | Node::Filter { source, .. }
+        | Node::Projection { source, .. } => scan_table(source),
+        Node::Scan { table, .. } => &table.name,
+        node => panic!("can't partition node {node:?}"),
+    }
+}
+
+/// Splits a table's primary key range into partitions, given its smallest and
+/// largest key. Integer and float keys are split into evenly sized ranges,
+/// assuming an even key distribution. Other key types, empty tables and small
+/// key ranges may yield fewer partitions, down to a single unbounded range.
+fn partition(key_range: Option<(Value, Value)>, partitions: usize) -> Vec<Range> {
+    let splits: Vec<Value> = match key_range {
+        Some((Value::Integer(min), Value::Integer(max))) => (1..partitions)
+            .map(|i| min as i128 + (max as i128 - min as i128) * i as i128 / partitions as i128)
+            .filter(|split| *split > min as i128)
+            .dedup()
+            .map(|split| Value::Integer(split as i64))
+            .collect(),
+        Some((Value::Float(min), Value::Float(max))) if min.is_finite() && max.is_finite() => (1
+            ..partitions)
+            .map(|i| min + (max - min) * i as f64 / partitions as f64)
+            .filter(|split| *split > min && *split <= max)
+            .dedup()
+            .map(Value::Float)
+            .collect(),
+        _ => Vec::new(),
+    };
+
+    // Each range ends where the next begins. The outer ranges are unbounded.
+    let starts =
+        std::iter::once(Bound::Unbounded).chain(splits.clone().into_iter().map(Bound::Included));
+    let ends = splits.into_iter().map(Bound::Excluded).chain(std::iter::once(Bound::Unbounded));
+    starts.zip(ends).collect()
+}
@@ -1,22 +1,50 @@
-use super::{aggregate, join, source, transform, write};
+use super::batch::{self, Batches};
+use super::trigger::Triggers;
+use super::{
+    CancelToken, MemoryAccountant, aggregate, exchange, export, import, join, source, transform,
+    write,
+};
+use serde::{Deserialize, Serialize};
+
 use crate::error::Result;
 use crate::sql::engine::{Catalog, Transaction};
 use crate::sql::planner::{Node, Plan};
-use crate::sql::types::{Label, Rows};
+use crate::sql::types::{Label, Row, Rows, TriggerEvent};
 
 /// Executes a plan, returning an execution result.
 ///
 /// Takes the transaction and catalog separately, even though Transaction must
 /// implement Catalog, to ensure the catalog is primarily used during planning.
+/// The statement can be cancelled via the given cancellation token, and the
+/// memory used by buffering nodes is tracked and limited by the accountant.
 pub fn execute_plan(
     plan: Plan,
     txn: &impl Transaction,
     catalog: &impl Catalog,
+    cancel: &CancelToken,
+    memory: &MemoryAccountant,
 ) -> Result<ExecutionResult> {
+    execute_plan_at_depth(plan, txn, catalog, cancel, memory, 0)
+}
+
+/// Executes a plan at the given trigger nesting depth, i.e. as the action of a
+/// trigger fired by a statement at depth - 1. Top-level statements have depth 0.
+pub(super) fn execute_plan_at_depth(
+    plan: Plan,
+    txn: &impl Transaction,
+    catalog: &impl Catalog,
+    cancel: &CancelToken,
+    memory: &MemoryAccountant,
+    depth: usize,
+) -> Result<ExecutionResult> {
+    cancel.check()?;
     Ok(match plan {
-        Plan::CreateTable { schema } => {
+        Plan::CreateTable { schema, temporary } => {
             let name = schema.name.clone();
-            catalog.create_table(schema)?;
+            match temporary {
+                true => catalog.create_temporary_table(schema)?,
+                false => catalog.create_table(schema)?,
+            }
             ExecutionResult::CreateTable { name }
         }
 
@@ -25,28 +53,131 @@ pub fn execute_plan(
             ExecutionResult::DropTable { name: table, existed }
         }
 
-        Plan::Delete { table, primary_key, source } => {
-            let source = execute(source, txn)?;
-            let count = write::delete(txn, table, primary_key, source)?;
-            ExecutionResult::Delete { count }
+        Plan::CreateIndex { table, column, index_type, predicate } => {
+            let column = table.columns[column].name.clone();
+            catalog.create_index(&table.name, &column, index_type, predicate)?;
+            ExecutionResult::CreateIndex { table: table.name, column }
+        }
+
+        Plan::CreateTrigger { table, trigger } => {
+            let name = trigger.name.clone();
+            catalog.create_trigger(&table, trigger)?;
+            ExecutionResult::CreateTrigger { table, name }
+        }
+
+        Plan::DropTrigger { table, trigger, if_exists } => {
+            let existed = catalog.drop_trigger(&table, &trigger, if_exists)?;
+            ExecutionResult::DropTrigger { table, name: trigger, existed }
+        }
+
+        Plan::CreateChangefeed { table } => {
+            catalog.set_changefeed(&table, true)?;
+            ExecutionResult::CreateChangefeed { table }
+        }
+
+        Plan::DropChangefeed { table } => {
+            catalog.set_changefeed(&table, false)?;
+            ExecutionResult::DropChangefeed { table }
+        }
+
+        Plan::DropPartition { table, partition } => {
+            let count = catalog.drop_partition(&table, &partition)?;
+            ExecutionResult::DropPartition { table, partition, count }
+        }
+
+        Plan::TruncateTable { table } => {
+            let count = catalog.truncate_table(&table)?;
+            ExecutionResult::TruncateTable { name: table, count }
+        }
+
+        Plan::CreateUser { user } => {
+            let name = user.name.clone();
+            catalog.create_user(user)?;
+            ExecutionResult::CreateUser { name }
+        }
+
+        Plan::DropUser { name, if_exists } => {
+            let existed = catalog.drop_user(&name, if_exists)?;
+            ExecutionResult::DropUser { name, existed }
+        }
+
+        Plan::Grant { user, table, privileges } => {
+            catalog.grant(&user, table.as_deref(), &privileges)?;
+            ExecutionResult::Grant { user }
+        }
+
+        Plan::Revoke { user, table, privileges } => {
+            catalog.revoke(&user, table.as_deref(), &privileges)?;
+            ExecutionResult::Revoke { user }
+        }
+
+        Plan::Delete { table, primary_key, source, returning } => {
+            let table = catalog.must_get_table(&table)?;
+            let triggers =
+                Triggers::new(&table, TriggerEvent::Delete, txn, catalog, cancel, memory, depth)?;
+            let source = execute(source, txn, cancel, memory)?;
+            let (expressions, columns): (Vec<_>, _) = returning.into_iter().unzip();
+            let (count, rows) =
+                write::delete(txn, &table, primary_key, source, &expressions, &triggers)?;
+            ExecutionResult::Delete { count, returning: Returning::new(columns, rows) }
+        }
+
+        Plan::Insert { table, column_map, source, returning } => {
+            let triggers =
+                Triggers::new(&table, TriggerEvent::Insert, txn, catalog, cancel, memory, depth)?;
+            let source = execute(source, txn, cancel, memory)?;
+            let (expressions, columns): (Vec<_>, _) = returning.into_iter().unzip();
+            let (count, rows) =
+                write::insert(txn, &table, column_map, source, &expressions, &triggers)?;
+            ExecutionResult::Insert { count, returning: Returning::new(columns, rows) }
+        }
+
+        Plan::Import { table, column_map, source } => {
+            let triggers =
+                Triggers::new(&table, TriggerEvent::Insert, txn, catalog, cancel, memory, depth)?;
+            let source = execute(source, txn, cancel, memory)?;
+            let count = write::import(txn, &table, column_map, source, &triggers)?;
+            ExecutionResult::Import { count }
         }
 
-        Plan::Insert { table, column_map, source } => {
-            let source = execute(source, txn)?;
-            let count = write::insert(txn, table, column_map, source)?;
-            ExecutionResult::Insert { count }
+        Plan::ImportFile { table, columns, input, format, header } => {
+            let triggers =
+                Triggers::new(&table, TriggerEvent::Insert, txn, catalog, cancel, memory, depth)?;
+            let (column_map, rows) =
+                import::decode(&table, columns, input, format, header, cancel)?;
+            let source = Box::new(rows.into_iter().map(Ok));
+            let count = write::import(txn, &table, Some(column_map), source, &triggers)?;
+            ExecutionResult::Import { count }
         }
 
         Plan::Select(root) => {
             let columns = (0..root.columns()).map(|i| root.column_label(i)).collect();
-            let rows = execute(root, txn)?;
+            let rows = execute(root, txn, cancel, memory)?;
             ExecutionResult::Select { rows, columns }
         }
 
-        Plan::Update { table, primary_key, source, expressions } => {
-            let source = execute(source, txn)?;
-            let count = write::update(txn, table.name, primary_key, source, expressions)?;
-            ExecutionResult::Update { count }
+        Plan::Export { root, path, format, header } => {
+            let columns: Vec<_> = (0..root.columns()).map(|i| root.column_label(i)).collect();
+            let rows = execute(root, txn, cancel, memory)?;
+            let count = export::export(&path, format, header, &columns, rows)?;
+            ExecutionResult::Export { count }
+        }
+
+        Plan::Update { table, primary_key, source, expressions, returning } => {
+            let triggers =
+                Triggers::new(&table, TriggerEvent::Update, txn, catalog, cancel, memory, depth)?;
+            let source = execute(source, txn, cancel, memory)?;
+            let (returning, columns): (Vec<_>, _) = returning.into_iter().unzip();
+            let (count, rows) = write::update(
+                txn,
+                &table,
+                primary_key,
+                source,
+                expressions,
+                &returning,
+                &triggers,
+            )?;
+            ExecutionResult::Update { count, returning: Returning::new(columns, rows) }
         }
     })
 }
@@ -75,23 +206,37 @@ pub fn execute_plan(
 /// joins the rows from the two tables, then the Filter node discards old
 /// movies, the Projection node picks out the requested columns, and the Order
 /// node sorts them before emitting the rows to the client.
-pub fn execute(node: Node, txn: &impl Transaction) -> Result<Rows> {
-    Ok(match node {
+///
+/// Each node checks the cancellation token before emitting a row, such that
+/// cancellation also interrupts nodes that buffer their input (e.g. Order).
+///
+/// Filter, Projection, and Aggregate nodes process batches of rows in columnar
+/// form rather than individual rows, see execute_batches().
+pub fn execute(
+    node: Node,
+    txn: &impl Transaction,
+    cancel: &CancelToken,
+    memory: &MemoryAccountant,
+) -> Result<Rows> {
+    let rows = match node {
         Node::Aggregate { source, group_by, aggregates } => {
-            let source = execute(*source, txn)?;
-            aggregate::aggregate(source, group_by, aggregates)?
+            let source = execute_batches(*source, txn, cancel, memory)?;
+            aggregate::aggregate(source, group_by, aggregates, memory)?
         }
 
-        Node::Filter { source, predicate } => {
-            let source = execute(*source, txn)?;
-            transform::filter(source, predicate)
+        Node::Exchange { source, partitions } => {
+            exchange::exchange(txn, *source, partitions, cancel, memory)?
+        }
+
+        node @ (Node::Filter { .. } | Node::Projection { .. }) => {
+            batch::rows(execute_batches(node, txn, cancel, memory)?)
         }
 
         Node::HashJoin { left, left_column, right, right_column, outer } => {
             let right_size = right.columns();
-            let left = execute(*left, txn)?;
-            let right = execute(*right, txn)?;
-            join::hash(left, left_column, right, right_column, right_size, outer)?
+            let left = execute(*left, txn, cancel, memory)?;
+            let right = execute(*right, txn, cancel, memory)?;
+            join::hash(left, left_column, right, right_column, right_size, outer, memory)?
         }
 
         Node::IndexLookup { table, column, values, alias: _ } => {
@@ -103,42 +248,77 @@ pub fn execute(node: Node, txn: &impl Transaction) -> Result<Rows> {
         Node::KeyLookup { table, keys, alias: _ } => source::lookup_key(txn, table.name, keys)?,
 
         Node::Limit { source, limit } => {
-            let source = execute(*source, txn)?;
+            let source = execute(*source, txn, cancel, memory)?;
             transform::limit(source, limit)
         }
 
+        Node::Lock { source, table, predicate } => {
+            let source = execute(*source, txn, cancel, memory)?;
+            source::lock(txn, source, table, predicate)?
+        }
+
         Node::NestedLoopJoin { left, right, predicate, outer } => {
             let right_size = right.columns();
-            let left = execute(*left, txn)?;
-            let right = execute(*right, txn)?;
+            let left = execute(*left, txn, cancel, memory)?;
+            let right = execute(*right, txn, cancel, memory)?;
             join::nested_loop(left, right, right_size, predicate, outer)?
         }
 
         Node::Nothing { .. } => source::nothing(),
 
         Node::Offset { source, offset } => {
-            let source = execute(*source, txn)?;
+            let source = execute(*source, txn, cancel, memory)?;
             transform::offset(source, offset)
         }
 
         Node::Order { source, key: orders } => {
-            let source = execute(*source, txn)?;
-            transform::order(source, orders)?
-        }
-
-        Node::Projection { source, expressions, aliases: _ } => {
-            let source = execute(*source, txn)?;
-            transform::project(source, expressions)
+            let source = execute(*source, txn, cancel, memory)?;
+            transform::order(source, orders, memory)?
         }
 
         Node::Remap { source, targets } => {
-            let source = execute(*source, txn)?;
+            let source = execute(*source, txn, cancel, memory)?;
             transform::remap(source, targets)
         }
 
-        Node::Scan { table, filter, alias: _ } => source::scan(txn, table, filter)?,
+        Node::Scan { table, filter, alias: _, partitions } => {
+            source::scan(txn, table, filter, partitions)?
+        }
+
+        Node::SchemaScan { rows, .. } => Box::new(rows.into_iter().map(Ok)),
+
+        Node::Unnest { source, expression, alias: _ } => {
+            let source = execute(*source, txn, cancel, memory)?;
+            transform::unnest(source, expression)
+        }
 
         Node::Values { rows } => source::values(rows),
+    };
+    Ok(cancel.wrap(rows))
+}
+
+/// Executes a plan node, returning batches of rows. Filter and Projection
+/// nodes evaluate their expressions a batch at a time, such that a pipeline of
+/// them only converts between rows and batches at its ends. Other nodes
+/// execute row by row, and their output rows are collected into batches.
+fn execute_batches(
+    node: Node,
+    txn: &impl Transaction,
+    cancel: &CancelToken,
+    memory: &MemoryAccountant,
+) -> Result<Batches> {
+    Ok(match node {
+        Node::Filter { source, predicate } => {
+            let source = execute_batches(*source, txn, cancel, memory)?;
+            batch::filter(source, predicate)
+        }
+
+        Node::Projection { source, expressions, aliases: _ } => {
+            let source = execute_batches(*source, txn, cancel, memory)?;
+            batch::project(source, expressions)
+        }
+
+        node => batch::batches(execute(node, txn, cancel, memory)?),
     })
 }
 
@@ -146,8 +326,38 @@ pub fn execute(node: Node, txn: &impl Transaction) -> Result<Rows> {
 pub enum ExecutionResult {
     CreateTable { name: String },
     DropTable { name: String, existed: bool },
-    Delete { count: u64 },
-    Insert { count: u64 },
-    Update { count: u64 },
+    CreateIndex { table: String, column: String },
+    CreateTrigger { table: String, name: String },
+    DropTrigger { table: String, name: String, existed: bool },
+    CreateChangefeed { table: String },
+    DropChangefeed { table: String },
+    DropPartition { table: String, partition: String, count: u64 },
+    TruncateTable { name: String, count: u64 },
+    CreateUser { name: String },
+    DropUser { name: String, existed: bool },
+    Grant { user: String },
+    Revoke { user: String },
+    Delete { count: u64, returning: Option<Returning> },
+    Insert { count: u64, returning: Option<Returning> },
+    Import { count: u64 },
+    Export { count: u64 },
+    Update { count: u64, returning: Option<Returning> },
     Select { rows: Rows, columns: Vec<Label> },
 }
+
+/// Rows returned by the RETURNING clause of an INSERT, UPDATE, or DELETE, one
+/// per written row. They're buffered, since the write must complete before
+/// the result is returned.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Returning {
+    pub columns: Vec<Label>,
+    pub rows: Vec<Row>,
+}
+
+impl Returning {
+    /// Creates a RETURNING result from the given columns and rows, or None if
+    /// there are no columns (i.e. no RETURNING clause).
+    fn new(columns: Vec<Label>, rows: Vec<Row>) -> Option<Self> {
+        (!columns.is_empty()).then_some(Self { columns, rows })
+    }
+}
@@ -0,0 +1,49 @@
+use std::io::BufWriter;
+
+use crate::encoding::{csv, json};
+use crate::error::{Error, Result};
+use crate::sql::types::{FileFormat, Label, Rows};
+
+/// Streams the rows of an EXPORT (see Plan::Export) to the given file, as CSV
+/// records or JSON lines, replacing the file if it exists. Rows are written as
+/// they're emitted, so the result isn't buffered in memory. On errors, the
+/// partially written file is removed. Returns the number of rows exported.
+pub fn export(
+    path: &str,
+    format: FileFormat,
+    header: bool,
+    columns: &[Label],
+    rows: Rows,
+) -> Result<u64> {
+    let file = std::fs::File::create(path)
+        .map_err(|err| Error::IO(format!("can't create file '{path}': {err}")))?;
+    let result = write(BufWriter::new(file), format, header, columns, rows);
+    if result.is_err() {
+        std::fs::remove_file(path).ok();
+    }
+    result
+}
+
+/// Writes the rows to the given writer.
+fn write(
+    mut writer: impl std::io::Write,
+    format: FileFormat,
+    header: bool,
+    columns: &[Label],
+    rows: Rows,
+) -> Result<u64> {
+    if header {
+        writeln!(writer, "{}", csv::encode_header(columns))?;
+    }
+    let mut count = 0;
+    for row in rows {
+        let row = row?;
+        match format {
+            FileFormat::Csv => writeln!(writer, "{}", csv::encode_row(&row))?,
+            FileFormat::Json => writeln!(writer, "{}", json::encode_row(columns, row)?)?,
+        }
+        count += 1;
+    }
+    writer.flush()?;
+    Ok(count)
+}
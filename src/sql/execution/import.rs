@@ -0,0 +1,160 @@
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader};
+
+use super::CancelToken;
+use crate::encoding::csv;
+use crate::error::{Error, Result};
+use crate::sql::planner::ImportInput;
+use crate::sql::types::{FileFormat, Row, Table, Value};
+use crate::{errconstraint, errinput};
+
+/// Decodes the rows of an IMPORT from a file or client data (see
+/// Plan::ImportFile), converting fields to the column datatypes. Returns the
+/// table → row column map and the rows, for write::import(). Errors for bad
+/// rows are prefixed with their line number.
+pub fn decode(
+    table: &Table,
+    columns: Option<Vec<usize>>,
+    input: ImportInput,
+    format: FileFormat,
+    header: bool,
+    cancel: &CancelToken,
+) -> Result<(HashMap<usize, usize>, Vec<Row>)> {
+    let reader: Box<dyn BufRead> = match input {
+        ImportInput::File(path) => match std::fs::File::open(&path) {
+            Ok(file) => Box::new(BufReader::new(file)),
+            Err(err) => return Err(Error::IO(format!("can't open file '{path}': {err}"))),
+        },
+        ImportInput::Data(data) => Box::new(std::io::Cursor::new(data)),
+    };
+    let (columns, rows) = match format {
+        FileFormat::Csv => decode_csv(table, columns, reader, header, cancel)?,
+        FileFormat::Json => decode_json(table, reader, cancel)?,
+    };
+    let column_map = columns.into_iter().enumerate().map(|(vi, ci)| (ci, vi)).collect();
+    Ok((column_map, rows))
+}
+
+/// Decodes CSV records. Without a column list or header, the fields are the
+/// table's non-generated columns in order.
+fn decode_csv(
+    table: &Table,
+    columns: Option<Vec<usize>>,
+    reader: impl BufRead,
+    header: bool,
+    cancel: &CancelToken,
+) -> Result<(Vec<usize>, Vec<Row>)> {
+    let mut records = csv::Reader::new(reader);
+    let mut columns = columns;
+    if header {
+        let (line, names) = records.next().transpose()?.unwrap_or((1, Vec::new()));
+        let names: Vec<String> = names
+            .into_iter()
+            .map(|name| name.ok_or_else(|| errinput!("line {line}: empty column name")))
+            .collect::<Result<_>>()?;
+        if columns.is_none() {
+            columns = Some(table.write_columns(&names).map_err(at_line(line))?);
+        }
+    }
+    let columns = columns.unwrap_or_else(|| {
+        (0..table.columns.len()).filter(|i| table.columns[*i].generated.is_none()).collect()
+    });
+
+    let mut rows = Vec::new();
+    for record in records {
+        cancel.check()?;
+        let (line, fields) = record?;
+        if fields.len() != columns.len() {
+            return errinput!(
+                "line {line}: expected {} fields, found {}",
+                columns.len(),
+                fields.len()
+            );
+        }
+        let row = fields
+            .into_iter()
+            .zip(&columns)
+            .map(|(field, &i)| {
+                let value = match field {
+                    Some(field) => Value::from_text(&field, &table.columns[i].datatype)?,
+                    None => Value::Null,
+                };
+                check_null(table, i, &value)?;
+                Ok(value)
+            })
+            .collect::<Result<_>>()
+            .map_err(at_line(line))?;
+        rows.push(row);
+    }
+    Ok((columns, rows))
+}
+
+/// Decodes JSON lines, each an object keyed by column name. Missing columns
+/// use the column default. Blank lines are skipped.
+fn decode_json(
+    table: &Table,
+    reader: impl BufRead,
+    cancel: &CancelToken,
+) -> Result<(Vec<usize>, Vec<Row>)> {
+    let columns: Vec<usize> =
+        (0..table.columns.len()).filter(|i| table.columns[*i].generated.is_none()).collect();
+    let mut rows = Vec::new();
+    for (line, text) in (1..).zip(reader.lines()) {
+        cancel.check()?;
+        let text = text?;
+        if text.trim().is_empty() {
+            continue;
+        }
+        let decode_row = || -> Result<Row> {
+            let mut object: serde_json::Map<String, serde_json::Value> =
+                serde_json::from_str(&text)
+                    .or_else(|err| errinput!("invalid JSON object: {err}"))?;
+            let row = columns
+                .iter()
+                .map(|&i| {
+                    let column = &table.columns[i];
+                    let value = match (object.remove(&column.name), &column.default) {
+                        (Some(json), _) => Value::from_json(json, &column.datatype)?,
+                        (None, Some(default)) => default.clone(),
+                        (None, None) => {
+                            return errinput!(
+                                "no value given for column {} with no default",
+                                column.name
+                            );
+                        }
+                    };
+                    check_null(table, i, &value)?;
+                    Ok(value)
+                })
+                .collect::<Result<_>>()?;
+            // The remaining keys are unknown or generated columns, which
+            // write_columns() rejects.
+            if let Some(name) = object.keys().next() {
+                table.write_columns(&[name.clone()])?;
+            }
+            Ok(row)
+        };
+        rows.push(decode_row().map_err(at_line(line))?);
+    }
+    Ok((columns, rows))
+}
+
+/// Errors if the value is NULL and the column isn't nullable. Rows are
+/// validated again when written, but this reports the line of the bad row.
+fn check_null(table: &Table, column: usize, value: &Value) -> Result<()> {
+    let column = &table.columns[column];
+    if matches!(value, Value::Null) && !column.nullable {
+        return errconstraint!("NULL value not allowed for column {}", column.name);
+    }
+    Ok(())
+}
+
+/// Prefixes an input or constraint error with the line number of the row that
+/// caused it.
+fn at_line(line: usize) -> impl Fn(Error) -> Error {
+    move |error| match error {
+        Error::InvalidInput(message) => Error::InvalidInput(format!("line {line}: {message}")),
+        Error::Constraint(message) => Error::Constraint(format!("line {line}: {message}")),
+        error => error,
+    }
+}
@@ -3,6 +3,7 @@ use std::iter::Peekable;
 use itertools::Itertools as _;
 use std::collections::HashMap;
 
+use super::MemoryAccountant;
 use crate::errinput;
 use crate::error::Result;
 use crate::sql::types::{Expression, Row, Rows, Value};
@@ -118,7 +119,8 @@ impl Iterator for NestedLoopIterator {
 /// keyed on the join value, then iterates over the left source and looks up
 /// matching rows in the hash table. If outer is true, and there is no match
 /// in the right source for a row in the left source, a row with NULL values
-/// for the right source is emitted instead.
+/// for the right source is emitted instead. Memory is reserved for the hash
+/// table rows.
 pub fn hash(
     left: Rows,
     left_column: usize,
@@ -126,6 +128,7 @@ pub fn hash(
     right_column: usize,
     right_size: usize,
     outer: bool,
+    memory: &MemoryAccountant,
 ) -> Result<Rows> {
     // Build the hash table from the right source.
     let mut rows = right;
@@ -135,6 +138,7 @@ pub fn hash(
         if value.is_undefined() {
             continue; // NULL and NAN equality is always false
         }
+        memory.reserve_values(&row)?;
         right.entry(value).or_default().push(row);
     }
 
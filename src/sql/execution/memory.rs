@@ -0,0 +1,54 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::errinput;
+use crate::error::Result;
+use crate::sql::types::Value;
+
+/// Accounts for the memory used by a statement's buffering executor nodes (i.e.
+/// Order, HashJoin, and Aggregate), and errors once it exceeds the statement's
+/// memory limit. This prevents a single large query from running the node out
+/// of memory. Clones share the same accounting, such that it can be used across
+/// parallel workers.
+///
+/// Memory is only released when the statement completes, even though some
+/// nodes drop their buffers earlier. This overestimates the memory usage in
+/// some cases, but keeps it simple. Memory sizes are also only estimates, based
+/// on the size of the buffered values (see Value::size).
+///
+/// Nodes don't spill to disk when the limit is exceeded, the statement errors.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryAccountant {
+    /// The number of bytes used so far.
+    used: Arc<AtomicUsize>,
+    /// The memory limit in bytes, if any.
+    limit: Option<usize>,
+}
+
+impl MemoryAccountant {
+    /// Creates a new memory accountant with the given limit in bytes, if any.
+    pub fn new(limit: Option<usize>) -> Self {
+        Self { used: Arc::default(), limit }
+    }
+
+    /// Returns the number of bytes used so far.
+    pub fn used(&self) -> usize {
+        self.used.load(Ordering::Relaxed)
+    }
+
+    /// Reserves the given number of bytes. Errors if this exceeds the limit.
+    pub fn reserve(&self, bytes: usize) -> Result<()> {
+        let used = self.used.fetch_add(bytes, Ordering::Relaxed) + bytes;
+        match self.limit {
+            Some(limit) if used > limit => {
+                errinput!("statement exceeded memory limit of {limit} bytes")
+            }
+            Some(_) | None => Ok(()),
+        }
+    }
+
+    /// Reserves memory for the given values, e.g. a row.
+    pub fn reserve_values(&self, values: &[Value]) -> Result<()> {
+        self.reserve(values.iter().map(|v| v.size()).sum::<usize>() + size_of::<Vec<Value>>())
+    }
+}
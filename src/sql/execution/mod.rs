@@ -1,10 +1,19 @@
 //! Executes a `Plan` against a `sql::engine::Engine`.
 
 mod aggregate;
+mod batch;
+mod cancel;
+mod exchange;
 mod execute;
+mod export;
+mod import;
 mod join;
+mod memory;
 mod source;
 mod transform;
+mod trigger;
 mod write;
 
-pub use execute::{ExecutionResult, execute_plan};
+pub use cancel::CancelToken;
+pub use execute::{ExecutionResult, Returning, execute_plan};
+pub use memory::MemoryAccountant;
@@ -1,10 +1,69 @@
-use crate::error::Result;
+use std::cmp::Ordering;
+use std::ops::Bound;
+
+use itertools::Itertools as _;
+
+use super::transform;
+use crate::error::{Error, Result};
 use crate::sql::engine::Transaction;
 use crate::sql::types::{Expression, Rows, Table, Value};
 
-/// A table scan source.
-pub fn scan(txn: &impl Transaction, table: Table, filter: Option<Expression>) -> Result<Rows> {
-    Ok(Box::new(txn.scan(&table.name, filter)?))
+/// A table scan source, optionally limited to the given table partitions.
+pub fn scan(
+    txn: &impl Transaction,
+    table: Table,
+    filter: Option<Expression>,
+    partitions: Option<Vec<usize>>,
+) -> Result<Rows> {
+    scan_range(txn, table, (Bound::Unbounded, Bound::Unbounded), filter, partitions)
+}
+
+/// A table scan source over a primary key range, optionally limited to the
+/// given table partitions. Each partition is scanned separately, in order.
+pub fn scan_range(
+    txn: &impl Transaction,
+    table: Table,
+    range: (Bound<Value>, Bound<Value>),
+    filter: Option<Expression>,
+    partitions: Option<Vec<usize>>,
+) -> Result<Rows> {
+    let Some(partitions) = partitions else {
+        return Ok(Box::new(txn.scan_range(&table.name, range, filter)?));
+    };
+    let scans: Vec<Rows> = partitions
+        .into_iter()
+        .filter_map(|i| intersect(range.clone(), table.partition_range(i)))
+        .map(|range| txn.scan_range(&table.name, range, filter.clone()))
+        .try_collect()?;
+    Ok(Box::new(scans.into_iter().flatten()))
+}
+
+/// Intersects two primary key ranges, returning None if they don't overlap.
+fn intersect(
+    a: (Bound<Value>, Bound<Value>),
+    b: (Bound<Value>, Bound<Value>),
+) -> Option<(Bound<Value>, Bound<Value>)> {
+    use Bound::*;
+    let start = match (a.0, b.0) {
+        (Unbounded, bound) | (bound, Unbounded) => bound,
+        (Included(a), Included(b)) => Included(a.max(b)),
+        (Excluded(a), Excluded(b)) => Excluded(a.max(b)),
+        (Included(i), Excluded(e)) | (Excluded(e), Included(i)) if i > e => Included(i),
+        (Included(_), Excluded(e)) | (Excluded(e), Included(_)) => Excluded(e),
+    };
+    let end = match (a.1, b.1) {
+        (Unbounded, bound) | (bound, Unbounded) => bound,
+        (Included(a), Included(b)) => Included(a.min(b)),
+        (Excluded(a), Excluded(b)) => Excluded(a.min(b)),
+        (Included(i), Excluded(e)) | (Excluded(e), Included(i)) if i < e => Included(i),
+        (Included(_), Excluded(e)) | (Excluded(e), Included(_)) => Excluded(e),
+    };
+    let empty = match (&start, &end) {
+        (Included(s), Included(e)) => s.cmp(e) == Ordering::Greater,
+        (Included(s) | Excluded(s), Included(e) | Excluded(e)) => s.cmp(e) != Ordering::Less,
+        (Unbounded, _) | (_, Unbounded) => false,
+    };
+    (!empty).then_some((start, end))
 }
 
 /// A primary key lookup source.
@@ -23,6 +82,26 @@ pub fn lookup_index(
     Ok(Box::new(txn.get(&table, &ids)?.into_iter().map(Ok)))
 }
 
+/// Locks the source table rows (i.e. FOR UPDATE), then re-reads them at their
+/// latest version and emits the ones that still match the predicate. Errors
+/// with Error::Locked if a lock is held by a different transaction.
+pub fn lock(
+    txn: &impl Transaction,
+    source: Rows,
+    table: Table,
+    predicate: Option<Expression>,
+) -> Result<Rows> {
+    let ids: Vec<Value> = source.map_ok(|row| row[table.primary_key].clone()).try_collect()?;
+    if let Some(version) = txn.lock(&table.name, &ids)? {
+        return Err(Error::Locked(version));
+    }
+    let rows: Rows = Box::new(txn.get(&table.name, &ids)?.into_iter().map(Ok));
+    Ok(match predicate {
+        Some(predicate) => transform::filter(rows, predicate),
+        None => rows,
+    })
+}
+
 /// Returns nothing. Used to short-circuit nodes that can't produce any rows.
 pub fn nothing() -> Rows {
     Box::new(std::iter::empty())
@@ -2,6 +2,7 @@ use std::cmp::Ordering;
 
 use itertools::{Itertools as _, izip};
 
+use super::MemoryAccountant;
 use crate::errinput;
 use crate::error::Result;
 use crate::sql::planner::Direction;
@@ -30,16 +31,25 @@ pub fn offset(source: Rows, offset: usize) -> Rows {
 }
 
 /// Sorts the rows (i.e. ORDER BY).
-pub fn order(source: Rows, order: Vec<(Expression, Direction)>) -> Result<Rows> {
+pub fn order(
+    source: Rows,
+    order: Vec<(Expression, Direction)>,
+    memory: &MemoryAccountant,
+) -> Result<Rows> {
     // We can't use sort_by_cached_key(), since expression evaluation is
     // fallible, and since we may have to vary the sort direction of each
     // expression. Precompute the sort values instead, and map them based on
     // the row index.
-    let mut irows: Vec<_> = source.enumerate().map(|(i, r)| r.map(|row| (i, row))).try_collect()?;
-    let mut sort_values = Vec::with_capacity(irows.len());
-    for (_, row) in &irows {
-        let values: Vec<_> = order.iter().map(|(e, _)| e.evaluate(Some(row))).try_collect()?;
-        sort_values.push(values)
+    // Reserve memory for the buffered rows and sort values.
+    let mut irows = Vec::new();
+    let mut sort_values = Vec::new();
+    for (i, row) in source.enumerate() {
+        let row = row?;
+        let values: Vec<_> = order.iter().map(|(e, _)| e.evaluate(Some(&row))).try_collect()?;
+        memory.reserve_values(&row)?;
+        memory.reserve_values(&values)?;
+        irows.push((i, row));
+        sort_values.push(values);
     }
 
     irows.sort_by(|&(a, _), &(b, _)| {
@@ -57,13 +67,6 @@ pub fn order(source: Rows, order: Vec<(Expression, Direction)>) -> Result<Rows>
     Ok(Box::new(irows.into_iter().map(|(_, row)| Ok(row))))
 }
 
-/// Projects the rows using the given expressions (i.e. SELECT).
-pub fn project(source: Rows, expressions: Vec<Expression>) -> Rows {
-    Box::new(source.map(move |result| {
-        result.and_then(|row| expressions.iter().map(|e| e.evaluate(Some(&row))).collect())
-    }))
-}
-
 /// Remaps source columns to target column indexes, or drops them if None.
 pub fn remap(source: Rows, targets: Vec<Option<usize>>) -> Rows {
     let size = targets.iter().filter_map(|v| *v).map(|i| i + 1).max().unwrap_or(0);
@@ -77,3 +80,19 @@ pub fn remap(source: Rows, targets: Vec<Option<usize>>) -> Rows {
         out
     }))
 }
+
+/// Expands an array into one row per element (i.e. UNNEST), appending the
+/// element to the source row. NULL arrays don't emit any rows.
+pub fn unnest(source: Rows, expression: Expression) -> Rows {
+    Box::new(source.flat_map(move |result| {
+        let rows = result.and_then(|row| match expression.evaluate(Some(&row))? {
+            Value::Array(values) => Ok(values
+                .into_iter()
+                .map(|value| Ok(row.iter().cloned().chain([value]).collect()))
+                .collect()),
+            Value::Null => Ok(Vec::new()),
+            value => errinput!("can't unnest {value}, expected array"),
+        });
+        rows.unwrap_or_else(|err| vec![Err(err)])
+    }))
+}
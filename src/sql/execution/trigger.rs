@@ -0,0 +1,103 @@
+use super::execute::execute_plan_at_depth;
+use super::{CancelToken, MemoryAccountant};
+use crate::errinput;
+use crate::error::Result;
+use crate::sql::engine::{Catalog, Transaction};
+use crate::sql::parser::{Parser, ast};
+use crate::sql::planner::{OptimizerOptions, Planner, TriggerRows};
+use crate::sql::types::{Row, Table, Trigger, TriggerCallback, TriggerEvent, TriggerTiming};
+
+/// The maximum trigger nesting depth, i.e. triggers whose actions fire other
+/// triggers. This guards against infinite recursion, e.g. when a trigger
+/// writes to its own table.
+const MAX_DEPTH: usize = 16;
+
+/// The row-level triggers and trigger callbacks on a table for a single write
+/// event, which fire for each row written by a statement. SQL triggers fire in
+/// creation order, followed by trigger callbacks in registration order.
+///
+/// Trigger actions are parsed and planned for every row, since the old and new
+/// rows are planned as constants, and executed in the writing transaction.
+pub struct Triggers<'a, T: Transaction, C: Catalog> {
+    table: &'a Table,
+    event: TriggerEvent,
+    triggers: Vec<Trigger>,
+    callbacks: Vec<TriggerCallback>,
+    txn: &'a T,
+    catalog: &'a C,
+    cancel: &'a CancelToken,
+    memory: &'a MemoryAccountant,
+    /// The trigger nesting depth of the writing statement.
+    depth: usize,
+}
+
+impl<'a, T: Transaction, C: Catalog> Triggers<'a, T, C> {
+    /// Fetches the triggers for the given table and event.
+    pub fn new(
+        table: &'a Table,
+        event: TriggerEvent,
+        txn: &'a T,
+        catalog: &'a C,
+        cancel: &'a CancelToken,
+        memory: &'a MemoryAccountant,
+        depth: usize,
+    ) -> Result<Self> {
+        let triggers = table.triggers.iter().filter(|t| t.event == event).cloned().collect();
+        let callbacks = catalog
+            .get_trigger_callbacks(&table.name)?
+            .into_iter()
+            .filter(|c| c.event == event)
+            .collect();
+        Ok(Self { table, event, triggers, callbacks, txn, catalog, cancel, memory, depth })
+    }
+
+    /// Returns true if any triggers fire at the given timing.
+    pub fn fires(&self, timing: TriggerTiming) -> bool {
+        self.triggers.iter().any(|t| t.timing == timing)
+            || self.callbacks.iter().any(|c| c.timing == timing)
+    }
+
+    /// Fires the triggers with the given timing for a row. BEFORE triggers can
+    /// modify the new row, if any.
+    pub fn fire(
+        &self,
+        timing: TriggerTiming,
+        old: Option<&Row>,
+        mut new: Option<&mut Row>,
+    ) -> Result<()> {
+        for trigger in self.triggers.iter().filter(|t| t.timing == timing) {
+            if self.depth >= MAX_DEPTH {
+                return errinput!("trigger depth limit {MAX_DEPTH} exceeded by {}", trigger.name);
+            }
+            let rows =
+                TriggerRows { table: self.table, event: self.event, old, new: new.as_deref() };
+            let mut planner = Planner::for_trigger(self.catalog, rows);
+            match Parser::new(&trigger.action).parse_trigger_action()? {
+                ast::TriggerAction::Set(set) => {
+                    let values: Vec<_> = planner
+                        .build_trigger_set(set)?
+                        .into_iter()
+                        .map(|(i, expr)| Ok((i, expr.evaluate(None)?)))
+                        .collect::<Result<_>>()?;
+                    let Some(new) = new.as_deref_mut() else {
+                        return errinput!("no new row in {} triggers", self.event);
+                    };
+                    for (i, value) in values {
+                        new[i] = value;
+                    }
+                }
+                ast::TriggerAction::Statement(statement) => {
+                    let plan = planner.build(*statement)?.optimize(&OptimizerOptions::default())?;
+                    let (txn, catalog, cancel, memory) =
+                        (self.txn, self.catalog, self.cancel, self.memory);
+                    execute_plan_at_depth(plan, txn, catalog, cancel, memory, self.depth + 1)?;
+                }
+            }
+        }
+        for callback in self.callbacks.iter().filter(|c| c.timing == timing) {
+            self.cancel.check()?;
+            callback.call(self.txn, old, new.as_deref_mut())?;
+        }
+        Ok(())
+    }
+}
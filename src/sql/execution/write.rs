@@ -2,24 +2,52 @@ use std::collections::{BTreeMap, HashMap};
 
 use itertools::Itertools as _;
 
-use crate::errinput;
+use super::trigger::Triggers;
 use crate::error::Result;
-use crate::sql::engine::Transaction;
-use crate::sql::types::{Expression, Rows, Table, Value};
+use crate::sql::engine::{Catalog, Transaction};
+use crate::sql::types::{Expression, Row, Rows, Table, TriggerTiming, Value};
+use crate::{errconstraint, errinput};
+
+/// The number of rows per bulk insert batch for IMPORT. Each batch is a
+/// separate Raft command. Small in tests, to exercise batching.
+const IMPORT_BATCH_SIZE: usize = if cfg!(test) { 2 } else { 10_000 };
 
 /// Deletes rows, taking primary keys from the source (i.e. DELETE) using the
-/// primary_key column index. Returns the number of rows deleted.
+/// primary_key column index. Returns the number of rows deleted, and the
+/// RETURNING expressions evaluated against each deleted row (if any). The
+/// source rows are complete table rows, which are given to triggers as the old
+/// rows.
 pub fn delete(
     txn: &impl Transaction,
-    table: String,
+    table: &Table,
     primary_key: usize,
-    source: Rows,
-) -> Result<u64> {
-    let ids: Vec<Value> =
-        source.map_ok(|row| row.into_iter().nth(primary_key).expect("short row")).try_collect()?;
+    mut source: Rows,
+    returning: &[Expression],
+    triggers: &Triggers<impl Transaction, impl Catalog>,
+) -> Result<(u64, Vec<Row>)> {
+    let (fires_before, fires_after) =
+        (triggers.fires(TriggerTiming::Before), triggers.fires(TriggerTiming::After));
+    let mut ids = Vec::new();
+    let mut deleted = Vec::new();
+    let mut returned = Vec::new();
+    while let Some(row) = source.next().transpose()? {
+        if fires_before {
+            triggers.fire(TriggerTiming::Before, Some(&row), None)?;
+        }
+        if !returning.is_empty() {
+            returned.push(evaluate_returning(returning, &row)?);
+        }
+        ids.push(row[primary_key].clone());
+        if fires_after {
+            deleted.push(row);
+        }
+    }
     let count = ids.len() as u64;
-    txn.delete(&table, &ids)?;
-    Ok(count)
+    txn.delete(&table.name, &ids)?;
+    for row in deleted {
+        triggers.fire(TriggerTiming::After, Some(&row), None)?;
+    }
+    Ok((count, returned))
 }
 
 /// Inserts rows into a table (i.e. INSERT) from the given source.
@@ -28,70 +56,183 @@ pub fn delete(
 /// columns in source. Otherwise, every column in source is the corresponding
 /// column in table, but the source may not have all columns in table (there may
 /// be a missing tail).
+///
+/// BEFORE triggers fire for each complete row before generated columns are
+/// computed, and can modify it. AFTER triggers fire once all rows are written.
+///
+/// Returns the number of rows inserted, and the RETURNING expressions evaluated
+/// against each inserted row (if any).
 pub fn insert(
     txn: &impl Transaction,
-    table: Table,
+    table: &Table,
     column_map: Option<HashMap<usize, usize>>,
     mut source: Rows,
-) -> Result<u64> {
+    returning: &[Expression],
+    triggers: &Triggers<impl Transaction, impl Catalog>,
+) -> Result<(u64, Vec<Row>)> {
+    let has_generated = table.columns.iter().any(|c| c.generated.is_some());
+    let (fires_before, fires_after) =
+        (triggers.fires(TriggerTiming::Before), triggers.fires(TriggerTiming::After));
     let mut rows = Vec::new();
     while let Some(values) = source.next().transpose()? {
-        // Fast path: the row is already complete, with no column mapping.
-        if values.len() == table.columns.len() && column_map.is_none() {
+        // Fast path: the row is already complete, with no column mapping,
+        // generated columns, or BEFORE triggers.
+        if values.len() == table.columns.len()
+            && column_map.is_none()
+            && !has_generated
+            && !fires_before
+        {
             rows.push(values);
             continue;
         }
-        if values.len() > table.columns.len() {
-            return errinput!("too many values for table {}", table.name);
+        let mut row = map_row(table, column_map.as_ref(), values)?;
+        if fires_before {
+            triggers.fire(TriggerTiming::Before, None, Some(&mut row))?;
         }
-        if let Some(column_map) = &column_map {
-            if column_map.len() != values.len() {
-                return errinput!("column and value counts do not match");
-            }
+        compute_generated(table, &mut row)?;
+        rows.push(row);
+    }
+    let count = rows.len() as u64;
+    let returned = match returning.is_empty() {
+        true => Vec::new(),
+        false => rows.iter().map(|row| evaluate_returning(returning, row)).try_collect()?,
+    };
+    let inserted = if fires_after { rows.clone() } else { Vec::new() };
+    txn.insert(&table.name, rows)?;
+    for mut row in inserted {
+        triggers.fire(TriggerTiming::After, None, Some(&mut row))?;
+    }
+    Ok((count, returned))
+}
+
+/// Bulk loads rows into a table (i.e. IMPORT) from the given source, like
+/// insert() but via Transaction::bulk_insert(). The rows are sorted by primary
+/// key and written in batches. Errors if the table has insert triggers.
+pub fn import(
+    txn: &impl Transaction,
+    table: &Table,
+    column_map: Option<HashMap<usize, usize>>,
+    mut source: Rows,
+    triggers: &Triggers<impl Transaction, impl Catalog>,
+) -> Result<u64> {
+    if triggers.fires(TriggerTiming::Before) || triggers.fires(TriggerTiming::After) {
+        return errinput!("can't import into table {} with insert triggers", table.name);
+    }
+    let mut rows = Vec::new();
+    while let Some(values) = source.next().transpose()? {
+        let mut row = map_row(table, column_map.as_ref(), values)?;
+        compute_generated(table, &mut row)?;
+        rows.push(row);
+    }
+    rows.sort_by(|a, b| a[table.primary_key].cmp(&b[table.primary_key]));
+    if let Some(w) = rows.windows(2).find(|w| w[0][table.primary_key] == w[1][table.primary_key]) {
+        return errconstraint!("primary key {} already exists", w[0][table.primary_key]);
+    }
+    let count = rows.len() as u64;
+    let mut rows = rows.into_iter().peekable();
+    while rows.peek().is_some() {
+        txn.bulk_insert(&table.name, rows.by_ref().take(IMPORT_BATCH_SIZE).collect())?;
+    }
+    Ok(count)
+}
+
+/// Maps source values to a complete table row (without generated columns),
+/// using the column_map if given and filling in default values (see insert()).
+fn map_row(table: &Table, column_map: Option<&HashMap<usize, usize>>, values: Row) -> Result<Row> {
+    if values.len() > table.columns.len() {
+        return errinput!("too many values for table {}", table.name);
+    }
+    if let Some(column_map) = column_map {
+        if column_map.len() != values.len() {
+            return errinput!("column and value counts do not match");
         }
+    }
 
-        // Map source columns to table columns, and fill in default values.
-        let mut row = Vec::with_capacity(table.columns.len());
-        for (i, column) in table.columns.iter().enumerate() {
+    // Map source columns to table columns, and fill in default values.
+    let mut row = Vec::with_capacity(table.columns.len());
+    for (i, column) in table.columns.iter().enumerate() {
+        if column.generated.is_some() {
+            // Generated columns are computed separately. The planner rejects
+            // them in column_map, but check positional values here.
             if column_map.is_none() && i < values.len() {
-                // Pass through the source column to the table column.
-                row.push(values[i].clone())
-            } else if let Some(vi) = column_map.as_ref().and_then(|c| c.get(&i)).copied() {
-                // Map the source column to the table column.
-                row.push(values[vi].clone())
-            } else if let Some(default) = &column.default {
-                // Column not given in source, use the default.
-                row.push(default.clone())
-            } else {
-                return errinput!("no value given for column {} with no default", column.name);
+                return errinput!("can't insert into generated column {}", column.name);
             }
+            row.push(Value::Null);
+        } else if column_map.is_none() && i < values.len() {
+            // Pass through the source column to the table column.
+            row.push(values[i].clone())
+        } else if let Some(vi) = column_map.and_then(|c| c.get(&i)).copied() {
+            // Map the source column to the table column.
+            row.push(values[vi].clone())
+        } else if let Some(default) = &column.default {
+            // Column not given in source, use the default.
+            row.push(default.clone())
+        } else {
+            return errinput!("no value given for column {} with no default", column.name);
         }
-        rows.push(row);
     }
-    let count = rows.len() as u64;
-    txn.insert(&table.name, rows)?;
-    Ok(count)
+    Ok(row)
 }
 
 /// Updates rows passed in from the source (i.e. UPDATE). Returns the number of
-/// rows updated.
+/// rows updated, and the RETURNING expressions evaluated against each updated
+/// row (if any).
+///
+/// BEFORE triggers fire for each updated row and can modify it, in which case
+/// generated columns are recomputed. AFTER triggers fire once all rows are
+/// written.
 pub fn update(
     txn: &impl Transaction,
-    table: String,
+    table: &Table,
     primary_key: usize,
     mut source: Rows,
     expressions: Vec<(usize, Expression)>,
-) -> Result<u64> {
+    returning: &[Expression],
+    triggers: &Triggers<impl Transaction, impl Catalog>,
+) -> Result<(u64, Vec<Row>)> {
+    let (fires_before, fires_after) =
+        (triggers.fires(TriggerTiming::Before), triggers.fires(TriggerTiming::After));
     let mut updates = BTreeMap::new();
+    let mut updated = Vec::new();
+    let mut returned = Vec::new();
     while let Some(row) = source.next().transpose()? {
         let mut update = row.clone();
         for (column, expr) in &expressions {
             update[*column] = expr.evaluate(Some(&row))?;
         }
+        if fires_before {
+            triggers.fire(TriggerTiming::Before, Some(&row), Some(&mut update))?;
+            compute_generated(table, &mut update)?;
+        }
+        if !returning.is_empty() {
+            returned.push(evaluate_returning(returning, &update)?);
+        }
+        if fires_after {
+            updated.push((row.clone(), update.clone()));
+        }
         let id = row.into_iter().nth(primary_key).expect("short row");
         updates.insert(id, update);
     }
     let count = updates.len() as u64;
-    txn.update(&table, updates)?;
-    Ok(count)
+    txn.update(&table.name, updates)?;
+    for (old, mut new) in updated {
+        triggers.fire(TriggerTiming::After, Some(&old), Some(&mut new))?;
+    }
+    Ok((count, returned))
+}
+
+/// Evaluates the RETURNING expressions of a write against a written row.
+fn evaluate_returning(returning: &[Expression], row: &Row) -> Result<Row> {
+    returning.iter().map(|expr| expr.evaluate(Some(row))).collect()
+}
+
+/// Computes the generated columns of a row. These can't reference other
+/// generated columns, so any existing values are ignored.
+fn compute_generated(table: &Table, row: &mut Row) -> Result<()> {
+    for (i, column) in table.columns.iter().enumerate() {
+        if let Some(generated) = &column.generated {
+            row[i] = generated.evaluate(Some(row))?;
+        }
+    }
+    Ok(())
 }
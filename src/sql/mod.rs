@@ -5,7 +5,7 @@
 //!    `sql::engine::Session` in `Server::sql_session`.
 //!
 //! 2. `toySQL` submits a SQL `SELECT` string, which the server executes via
-//!     `Session::execute`.
+//!     `Session::execute_stream`, the streaming variant of `Session::execute`.
 //!
 //! 3. `Session::execute` calls `Parser::parse` to parse the SQL `SELECT` string
 //!     into an `ast::Statement::Select` AST (Abstract Syntax Tree). The parser
@@ -33,8 +33,9 @@
 //!    performs full table scans from storage. It is executed by
 //!    `sql::execution::source::scan`, which calls `Transaction::scan`.
 //!
-//! 9. The upper `sql::engine::Raft` engine submits a `Read::Scan` request to
-//!    Raft via `Raft::read` and `Raft::execute`. This is submitted through the
+//! 9. The upper `sql::engine::Raft` engine returns a `ScanIterator`, which
+//!    lazily submits `Read::Scan` requests for batches of rows to Raft via
+//!    `Raft::read` and `Raft::execute`. This is submitted through the
 //!    crossbeam channel `Raft::tx`, which is routed to the local Raft node in
 //!    `Server::raft_route` via `raft::Node::step`.
 //!
@@ -45,7 +46,7 @@
 //!
 //! 11. `State` wraps the `sql::engine::Local` SQL execution engine that runs
 //!     on each node, using local storage. `State::read` calls
-//!     `Transaction::scan` using a `Local::Transaction`.
+//!     `Transaction::scan_batch` using a `Local::Transaction`.
 //!
 //! 12. The `Local` engine uses a `storage::BitCask` engine for local storage,
 //!     with `storage::mvcc` providing transactions. See their documentation
@@ -56,13 +57,14 @@
 //!     scans rows under this prefix by calling `MVCC::scan_prefix`, which in
 //!     turn dispatches to `BitCask::scan_prefix`. It returns a row iterator.
 //!
-//! 14. A row iterator is propagated back up through the stack:
+//! 14. A batch of rows is propagated back up through the stack:
 //!     `BitCask` → `MVCC` → `Local` → `State` → `Raft` → `scan` → `Plan::execute`
 //!
-//! 15. `Plan::execute` collects the results in a `ExecutionResult::Select`,
-//!     and returns it to `Session::execute`. It in turns returns it to
-//!     `Server::sql_session`, which encodes it and sends it across the wire
-//!     to `toySQL`, which displays them to the user.
+//! 15. `Plan::execute` returns the row iterator in an `ExecutionResult::Select`
+//!     to `Session::execute`. It in turn passes it to `Server::sql_session`
+//!     while the transaction is still open, which pulls rows through the plan
+//!     (fetching further row batches as needed) and streams them across the
+//!     wire to `toySQL` as they're produced, which displays them to the user.
 //!
 //! TODO: expand this into a "Life of a SQL statement" document.
 
@@ -80,24 +82,37 @@ mod tests {
     use std::fmt::Write as _;
     use std::path::Path;
     use std::result::Result;
+    use std::sync::Arc;
+    use std::time::Duration;
 
     use crossbeam::channel::Receiver;
     use itertools::Itertools as _;
     use tempfile::TempDir;
     use test_each_file::test_each_path;
 
-    use super::engine::{Catalog as _, Session};
+    use super::engine::{Catalog as _, Change, Session, Transaction as _};
+    use super::execution::{CancelToken, Returning};
     use super::parser::Parser;
     use super::planner::{OPTIMIZERS, Plan};
+    use crate::clock::{HybridClock, ManualClock};
     use crate::encoding::format::{self, Formatter as _};
-    use crate::sql::engine::{Engine, Local, StatementResult};
+    use crate::encoding::{Key as _, Value as _};
+    use crate::sql::engine::{
+        AuditLog, Engine, Key, Local, PlanCache, ResultCache, SlowQueryLog, StatementResult,
+        Statistics,
+    };
     use crate::sql::planner::{Planner, Scope};
+    use crate::sql::types::Value;
     use crate::storage::engine::test as testengine;
+    use crate::storage::mvcc;
     use crate::storage::{self, Engine as _};
+    use crate::trace;
 
     // Run goldenscript tests in src/sql/testscripts.
+    test_each_path! { in "src/sql/testscripts/clock" as clock => test_goldenscript_clock }
     test_each_path! { in "src/sql/testscripts/expressions" as expressions => test_goldenscript_expr }
     test_each_path! { in "src/sql/testscripts/optimizers" as optimizers => test_goldenscript }
+    test_each_path! { in "src/sql/testscripts/parser" as parser => test_goldenscript_parser }
     test_each_path! { in "src/sql/testscripts/queries" as queries => test_goldenscript }
     test_each_path! { in "src/sql/testscripts/schema" as schema => test_goldenscript }
     test_each_path! { in "src/sql/testscripts/transactions" as transactions => test_goldenscript }
@@ -105,6 +120,18 @@ mod tests {
 
     /// Runs SQL goldenscripts.
     fn test_goldenscript(path: &Path) {
+        run_goldenscript(path, None)
+    }
+
+    /// Runs SQL goldenscripts with a hybrid logical clock, which timestamps
+    /// transactions. It's driven by a manual clock shared by all sessions,
+    /// advanced via the advance command.
+    fn test_goldenscript_clock(path: &Path) {
+        run_goldenscript(path, Some(ManualClock::new(0)))
+    }
+
+    /// Runs a SQL goldenscript, with a hybrid logical clock if given.
+    fn run_goldenscript(path: &Path, clock: Option<ManualClock>) {
         // The runner's Session can't borrow from an Engine in the same struct,
         // so pass an engine reference. Use both BitCask and Memory engines and
         // mirror operations across them. Emit engine operations to op_rx.
@@ -113,23 +140,137 @@ mod tests {
         let bitcask =
             storage::BitCask::new(tempdir.path().join("bitcask")).expect("bitcask failed");
         let memory = storage::Memory::new();
-        let engine =
+        let mut engine =
             Local::new(testengine::Emit::new(testengine::Mirror::new(bitcask, memory), op_tx));
+        if let Some(clock) = &clock {
+            engine.set_clock(HybridClock::new(Arc::new(clock.clone())));
+        }
+        register_functions(&engine).expect("function registration failed");
+        register_triggers(&engine).expect("trigger registration failed");
         let mut runner = SQLRunner::new(&engine, op_rx);
+        runner.clock = clock;
 
         goldenscript::run(&mut runner, path).expect("goldenscript failed")
     }
 
+    /// Registers user-defined functions for SQL goldenscripts.
+    fn register_functions(engine: &TestEngine) -> crate::error::Result<()> {
+        use crate::sql::types::{DataType, Function, Value};
+
+        engine.register_function(Function::new(
+            "twice",
+            vec![DataType::Integer],
+            DataType::Integer,
+            |args| match &args[0] {
+                Value::Integer(i) => match i.checked_mul(2) {
+                    Some(i) => Ok(Value::Integer(i)),
+                    None => crate::errinput!("integer overflow"),
+                },
+                _ => Ok(Value::Null),
+            },
+        ))?;
+        engine.register_function(Function::new(
+            "Concat",
+            vec![DataType::String, DataType::String],
+            DataType::String,
+            |args| match (&args[0], &args[1]) {
+                (Value::String(a), Value::String(b)) => Ok(Value::String(format!("{a}{b}"))),
+                _ => Ok(Value::Null),
+            },
+        ))?;
+        engine.register_function(Function::new(
+            "hypot",
+            vec![DataType::Float, DataType::Float],
+            DataType::Float,
+            |args| match (&args[0], &args[1]) {
+                (Value::Float(a), Value::Float(b)) => Ok(Value::Float(a.hypot(*b))),
+                _ => Ok(Value::Null),
+            },
+        ))?;
+        // Returns the wrong type.
+        engine.register_function(Function::new("broken", vec![], DataType::Integer, |_| {
+            Ok(Value::String("oops".to_string()))
+        }))?;
+        Ok(())
+    }
+
+    /// Registers trigger callbacks for SQL goldenscripts.
+    fn register_triggers(engine: &TestEngine) -> crate::error::Result<()> {
+        use crate::sql::types::{TriggerCallback, TriggerEvent, TriggerTiming, Value};
+
+        // Upper-cases the second column of rows inserted into callback.
+        engine.register_trigger(TriggerCallback::new(
+            "upper",
+            "callback",
+            TriggerTiming::Before,
+            TriggerEvent::Insert,
+            |_, _, new| {
+                if let Some(Value::String(s)) = new.and_then(|row| row.get_mut(1)) {
+                    *s = s.to_uppercase();
+                }
+                Ok(())
+            },
+        ))?;
+        // Logs the IDs of rows deleted from callback in callback_log.
+        engine.register_trigger(TriggerCallback::new(
+            "log",
+            "callback",
+            TriggerTiming::After,
+            TriggerEvent::Delete,
+            |txn, old, _| {
+                let id = old.expect("no old row")[0].clone();
+                txn.insert("callback_log", vec![vec![id]])
+            },
+        ))?;
+        // Errors on updates.
+        engine.register_trigger(TriggerCallback::new(
+            "readonly",
+            "callback",
+            TriggerTiming::Before,
+            TriggerEvent::Update,
+            |_, _, _| crate::errinput!("callback is read-only"),
+        ))?;
+        Ok(())
+    }
+
     /// Runs expression goldenscripts.
     fn test_goldenscript_expr(path: &Path) {
         goldenscript::run(&mut ExpressionRunner, path).expect("goldenscript failed")
     }
 
-    /// The SQL test runner.
+    /// Runs parser goldenscripts.
+    fn test_goldenscript_parser(path: &Path) {
+        goldenscript::run(&mut ParserRunner, path).expect("goldenscript failed")
+    }
+
+    /// The SQL test runner. Commands are SQL statements, optionally prefixed
+    /// by a session name, or one of the runner commands in run(). Statements
+    /// are executed and SELECT rows are output. Statements take these tags:
+    ///
+    /// * plan: the optimized query plan, before executing the statement.
+    /// * opt: the initial plan and each optimizer's changes to it.
+    /// * result: the statement result, for non-SELECT statements.
+    /// * header: the SELECT column labels.
+    /// * ops: the storage engine operations performed by the statement.
+    /// * trace: the trace spans of the statement's execution.
+    /// * cancel: executes the statement with a cancelled token.
+    ///
+    /// Planner changes thus show up as plan diffs in the goldenscripts. Run the
+    /// tests with UPDATE_GOLDENFILES=1 to regenerate the expected output.
     struct SQLRunner<'a> {
         engine: &'a TestEngine,
         sessions: HashMap<String, Session<'a, TestEngine>>,
         op_rx: Receiver<testengine::Operation>,
+        result_cache: Option<Arc<ResultCache>>,
+        plan_cache: Option<Arc<PlanCache>>,
+        slow_query_log: Option<Arc<SlowQueryLog>>,
+        audit_log: Option<Arc<AuditLog>>,
+        statistics: Option<Arc<Statistics>>,
+        /// A manual clock shared by all sessions, if enabled.
+        clock: Option<ManualClock>,
+        /// The directory of files written via the file command, which
+        /// relative IMPORT paths are resolved against.
+        files: TempDir,
     }
 
     type TestEngine =
@@ -137,7 +278,19 @@ mod tests {
 
     impl<'a> SQLRunner<'a> {
         fn new(engine: &'a TestEngine, op_rx: Receiver<testengine::Operation>) -> Self {
-            Self { engine, sessions: HashMap::new(), op_rx }
+            let files = TempDir::with_prefix("toydb").expect("tempdir failed");
+            Self {
+                engine,
+                sessions: HashMap::new(),
+                op_rx,
+                result_cache: None,
+                plan_cache: None,
+                slow_query_log: None,
+                audit_log: None,
+                statistics: None,
+                clock: None,
+                files,
+            }
         }
     }
 
@@ -147,10 +300,311 @@ mod tests {
 
             // Obtain a session based on the command prefix ("" if none).
             let prefix = command.prefix.clone().unwrap_or_default();
-            let session = self.sessions.entry(prefix).or_insert_with(|| self.engine.session());
+
+            // disconnect: drops the session, e.g. along with its temporary
+            // tables. A new session is created on the next command.
+            if command.name == "disconnect" {
+                command.consume_args().reject_rest()?;
+                self.sessions.remove(&prefix);
+                return Ok(output);
+            }
+
+            // result_cache [SIZE]: enables a result cache of the given size
+            // in bytes shared by all sessions, or disables it if not given.
+            if command.name == "result_cache" {
+                let mut args = command.consume_args();
+                let size = args.next_pos().map(|arg| arg.parse()).transpose()?;
+                args.reject_rest()?;
+                self.result_cache = size.map(|size| Arc::new(ResultCache::new(size)));
+                for session in self.sessions.values_mut() {
+                    session.set_result_cache(self.result_cache.clone());
+                }
+                return Ok(output);
+            }
+
+            // plan_cache [SIZE]: enables a plan cache of the given size in
+            // plans shared by all sessions, or disables it if not given.
+            if command.name == "plan_cache" {
+                let mut args = command.consume_args();
+                let size = args.next_pos().map(|arg| arg.parse()).transpose()?;
+                args.reject_rest()?;
+                self.plan_cache = size.map(|size| Arc::new(PlanCache::new(size)));
+                for session in self.sessions.values_mut() {
+                    session.set_plan_cache(self.plan_cache.clone());
+                }
+                return Ok(output);
+            }
+
+            // plan_cache_status: outputs the plan cache status.
+            if command.name == "plan_cache_status" {
+                command.consume_args().reject_rest()?;
+                let cache = self.plan_cache.as_ref().ok_or("plan cache not enabled")?;
+                writeln!(output, "{:?}", cache.status()?)?;
+                return Ok(output);
+            }
+
+            // manual_clock: switches all sessions to a shared manual clock,
+            // which only advances via the advance command.
+            if command.name == "manual_clock" {
+                command.consume_args().reject_rest()?;
+                let clock = ManualClock::new(0);
+                for session in self.sessions.values_mut() {
+                    session.set_clock(Arc::new(clock.clone()));
+                }
+                self.clock = Some(clock);
+                return Ok(output);
+            }
+
+            // advance MILLIS: advances the manual clock.
+            if command.name == "advance" {
+                let mut args = command.consume_args();
+                let millis = args.next_pos().ok_or("duration not given")?.parse()?;
+                args.reject_rest()?;
+                let clock = self.clock.as_ref().ok_or("manual clock not enabled")?;
+                clock.advance(Duration::from_millis(millis));
+                return Ok(output);
+            }
+
+            // result_cache_status: outputs the result cache status.
+            if command.name == "result_cache_status" {
+                command.consume_args().reject_rest()?;
+                let cache = self.result_cache.as_ref().ok_or("result cache not enabled")?;
+                writeln!(output, "{:?}", cache.status()?)?;
+                return Ok(output);
+            }
+
+            // slow_query_log [THRESHOLD] [size=BYTES]: enables a slow query
+            // log in the file slow.log with the given threshold in
+            // milliseconds shared by all sessions, or disables it if not
+            // given.
+            if command.name == "slow_query_log" {
+                let mut args = command.consume_args();
+                let threshold = args.next_pos().map(|arg| arg.parse()).transpose()?;
+                let size = args.lookup_parse("size")?.unwrap_or(0);
+                args.reject_rest()?;
+                self.slow_query_log = threshold
+                    .map(|threshold| {
+                        let path = self.files.path().join("slow.log");
+                        SlowQueryLog::open(path, Duration::from_millis(threshold), size)
+                    })
+                    .transpose()?
+                    .map(Arc::new);
+                for session in self.sessions.values_mut() {
+                    session.set_slow_query_log(self.slow_query_log.clone());
+                }
+                return Ok(output);
+            }
+
+            // slow_queries [NAME]: outputs the entries of a slow query log
+            // file (slow.log by default), without times, durations, and
+            // session IDs.
+            if command.name == "slow_queries" {
+                let mut args = command.consume_args();
+                let name = args.next_pos().map_or("slow.log".to_string(), |a| a.value.clone());
+                args.reject_rest()?;
+                for line in std::fs::read_to_string(self.files.path().join(name))?.lines() {
+                    let mut entry: serde_json::Map<String, serde_json::Value> =
+                        serde_json::from_str(line)?;
+                    entry.remove("time");
+                    entry.remove("duration_ms");
+                    entry.remove("session");
+                    writeln!(output, "{}", serde_json::Value::Object(entry))?;
+                }
+                return Ok(output);
+            }
+
+            // audit_log [TABLE...]: enables an audit log in the file
+            // audit.log shared by all sessions, which also audits DML for the
+            // given tables.
+            if command.name == "audit_log" {
+                let mut args = command.consume_args();
+                let tables = args.rest_pos().iter().map(|arg| arg.value.clone()).collect_vec();
+                args.reject_rest()?;
+                let log = AuditLog::open(self.files.path().join("audit.log"), tables)?;
+                self.audit_log = Some(Arc::new(log));
+                for session in self.sessions.values_mut() {
+                    session.set_audit_log(self.audit_log.clone());
+                }
+                return Ok(output);
+            }
+
+            // statistics: enables statement and table statistics shared by
+            // all sessions.
+            if command.name == "statistics" {
+                command.consume_args().reject_rest()?;
+                self.statistics = Some(Arc::new(Statistics::new()));
+                for session in self.sessions.values_mut() {
+                    session.set_statistics(self.statistics.clone());
+                }
+                return Ok(output);
+            }
+
+            // audit_events: outputs the entries of the audit log file, without
+            // times and session IDs.
+            if command.name == "audit_events" {
+                command.consume_args().reject_rest()?;
+                for line in std::fs::read_to_string(self.files.path().join("audit.log"))?.lines() {
+                    let mut entry: serde_json::Map<String, serde_json::Value> =
+                        serde_json::from_str(line)?;
+                    entry.remove("time");
+                    entry.remove("session");
+                    writeln!(output, "{}", serde_json::Value::Object(entry))?;
+                }
+                return Ok(output);
+            }
+
+            // file NAME [LINE...]: writes the given lines to a file, which
+            // can be imported via IMPORT or copy.
+            if command.name == "file" {
+                let mut args = command.consume_args();
+                let name = args.next_pos().ok_or("file name not given")?.value.clone();
+                let lines = args.rest_pos().iter().map(|arg| format!("{}\n", arg.value)).join("");
+                args.reject_rest()?;
+                std::fs::write(self.files.path().join(name), lines)?;
+                return Ok(output);
+            }
+
+            // cat NAME: outputs the contents of a file, e.g. one written by
+            // EXPORT.
+            if command.name == "cat" {
+                let mut args = command.consume_args();
+                let name = args.next_pos().ok_or("file name not given")?.value.clone();
+                args.reject_rest()?;
+                output.push_str(&std::fs::read_to_string(self.files.path().join(name))?);
+                return Ok(output);
+            }
+
+            // scrub [repair=BOOL]: scrubs the stored data, optionally
+            // repairing it.
+            if command.name == "scrub" {
+                let mut args = command.consume_args();
+                let repair = args.lookup_parse("repair")?.unwrap_or(false);
+                args.reject_rest()?;
+                write!(output, "{}", self.engine.scrub(repair)?)?;
+                return Ok(output);
+            }
+
+            // checksum: outputs the SQL state checksums by range.
+            if command.name == "checksum" {
+                command.consume_args().reject_rest()?;
+                let checksum = self.engine.checksum()?;
+                writeln!(output, "version {}", checksum.version)?;
+                for (range, checksum) in checksum.ranges {
+                    writeln!(output, "{range}: {checksum:016x}")?;
+                }
+                return Ok(output);
+            }
+
+            // corrupt_index TABLE COLUMN VALUE [ID...]: writes a raw index
+            // entry, bypassing the SQL engine. Removes it if no IDs are given.
+            // Values are integers or strings.
+            //
+            // corrupt_row TABLE ID: removes a raw row, bypassing the SQL
+            // engine.
+            //
+            // corrupt_intent VERSION KEY: writes an MVCC write intent for the
+            // given transaction version and raw key.
+            if command.name.starts_with("corrupt_") {
+                let parse = |s: &str| s.parse().map_or(Value::String(s.into()), Value::Integer);
+                let mut args = command.consume_args();
+                let values = args.rest_pos().iter().map(|arg| arg.value.clone()).collect_vec();
+                args.reject_rest()?;
+                let txn = self.engine.mvcc.begin()?;
+                match (command.name.as_str(), values.as_slice()) {
+                    ("corrupt_index", [table, column, value, ids @ ..]) => {
+                        let key = Key::Index(table.into(), column.into(), parse(value).into());
+                        let ids: std::collections::BTreeSet<Value> =
+                            ids.iter().map(|id| parse(id)).collect();
+                        match ids.is_empty() {
+                            true => txn.delete(&key.encode())?,
+                            false => txn.set(&key.encode(), ids.encode())?,
+                        }
+                    }
+                    ("corrupt_row", [table, id]) => {
+                        txn.delete(&Key::Row(table.into(), parse(id).into()).encode())?
+                    }
+                    ("corrupt_intent", [version, key]) => {
+                        let key = mvcc::Key::TxnWrite(version.parse()?, key.as_bytes().into());
+                        let mut engine = self.engine.mvcc.engine.lock().expect("mutex failed");
+                        engine.set(&key.encode(), vec![])?;
+                    }
+                    (name, _) => return Err(format!("invalid {name} arguments").into()),
+                }
+                txn.commit()?;
+                return Ok(output);
+            }
+
+            let session = self.sessions.entry(prefix).or_insert_with(|| {
+                let mut session = self.engine.session();
+                session.set_result_cache(self.result_cache.clone());
+                session.set_plan_cache(self.plan_cache.clone());
+                session.set_slow_query_log(self.slow_query_log.clone());
+                session.set_audit_log(self.audit_log.clone());
+                session.set_statistics(self.statistics.clone());
+                session.set_file_dir(Some(self.files.path().to_path_buf()));
+                if let Some(clock) = &self.clock {
+                    session.set_clock(Arc::new(clock.clone()));
+                }
+                session
+            });
 
             // Handle runner commands.
             match command.name.as_str() {
+                // admin_password [PASSWORD]
+                "admin_password" => {
+                    let mut args = command.consume_args();
+                    let password = args.next_pos().map(|arg| arg.value.clone());
+                    args.reject_rest()?;
+                    session.set_admin_password(password);
+                    return Ok(output);
+                }
+
+                // authenticate USER PASSWORD
+                "authenticate" => {
+                    let mut args = command.consume_args();
+                    let user = args.next_pos().ok_or("user not given")?.value.clone();
+                    let password = args.next_pos().ok_or("password not given")?.value.clone();
+                    args.reject_rest()?;
+                    session.authenticate(&user, &password)?;
+                    return Ok(output);
+                }
+
+                // changes TABLE [AFTER] [limit=N]
+                "changes" => {
+                    let mut args = command.consume_args();
+                    let table = args.next_pos().ok_or("table not given")?.value.clone();
+                    let after = args.next_pos().map(|arg| arg.parse()).transpose()?.unwrap_or(0);
+                    let limit = args.lookup_parse("limit")?.unwrap_or(usize::MAX);
+                    args.reject_rest()?;
+                    let changes =
+                        session.with_txn(true, |txn| txn.changes(&table, after, limit))?;
+                    for Change { seq, version, id, row } in changes {
+                        let row = row.map_or("None".to_string(), |row| row.iter().join(", "));
+                        writeln!(output, "{seq}: v{version} {id} → {row}")?;
+                    }
+                    return Ok(output);
+                }
+
+                // check_timeout
+                "check_timeout" => {
+                    command.consume_args().reject_rest()?;
+                    session.check_timeout()?;
+                    return Ok(output);
+                }
+
+                // copy FILE STATEMENT: executes a COPY FROM STDIN statement
+                // with the file's data.
+                "copy" => {
+                    let mut args = command.consume_args();
+                    let name = args.next_pos().ok_or("file name not given")?.value.clone();
+                    let statement = args.next_pos().ok_or("statement not given")?.value.clone();
+                    args.reject_rest()?;
+                    let data = std::fs::read(self.files.path().join(name))?;
+                    let result = session.execute_copy(&statement, data, &CancelToken::new())?;
+                    writeln!(output, "{result:?}")?;
+                    return Ok(output);
+                }
+
                 // dump
                 "dump" => {
                     command.consume_args().reject_rest()?;
@@ -164,6 +618,49 @@ mod tests {
                     return Ok(output);
                 }
 
+                // idle_timeout [MILLIS]
+                "idle_timeout" => {
+                    let mut args = command.consume_args();
+                    let millis = args.next_pos().map(|arg| arg.parse()).transpose()?;
+                    args.reject_rest()?;
+                    session.set_idle_timeout(millis.map(Duration::from_millis));
+                    return Ok(output);
+                }
+
+                // lock_timeout MILLIS
+                "lock_timeout" => {
+                    let mut args = command.consume_args();
+                    let millis = args.next_pos().ok_or("timeout not given")?.parse()?;
+                    args.reject_rest()?;
+                    session.set_lock_timeout(Duration::from_millis(millis));
+                    return Ok(output);
+                }
+
+                // memory_limit [BYTES]
+                "memory_limit" => {
+                    let mut args = command.consume_args();
+                    let limit = args.next_pos().map(|arg| arg.parse()).transpose()?;
+                    args.reject_rest()?;
+                    session.set_memory_limit(limit);
+                    return Ok(output);
+                }
+
+                // parallelism PARTITIONS
+                "parallelism" => {
+                    let mut args = command.consume_args();
+                    let parallelism = args.next_pos().ok_or("partitions not given")?.parse()?;
+                    args.reject_rest()?;
+                    session.set_parallelism(parallelism);
+                    return Ok(output);
+                }
+
+                // purge_expired: purges expired rows from tables with a TTL.
+                "purge_expired" => {
+                    command.consume_args().reject_rest()?;
+                    writeln!(output, "purged {}", session.purge_expired()?)?;
+                    return Ok(output);
+                }
+
                 // schema [TABLE...]
                 "schema" => {
                     let mut args = command.consume_args();
@@ -181,6 +678,15 @@ mod tests {
                     return Ok(schemas.into_iter().join("\n"));
                 }
 
+                // transaction_timeout [MILLIS]
+                "transaction_timeout" => {
+                    let mut args = command.consume_args();
+                    let millis = args.next_pos().map(|arg| arg.parse()).transpose()?;
+                    args.reject_rest()?;
+                    session.set_transaction_timeout(millis.map(Duration::from_millis));
+                    return Ok(output);
+                }
+
                 // Otherwise, fall through to SQL execution.
                 _ => {}
             }
@@ -195,8 +701,15 @@ mod tests {
             // Output the plan if requested.
             if tags.remove("plan") {
                 let ast = Parser::new(input).parse()?;
-                let plan =
-                    session.with_txn(true, |txn| Planner::new(txn).build(ast)?.optimize())?;
+                let (parallelism, wall_time) = (session.parallelism(), session.wall_time());
+                let options = session.optimizer_options(&ast);
+                let plan = session.with_txn(true, |txn| {
+                    Planner::new(txn)
+                        .at_time(wall_time)
+                        .build(ast)?
+                        .optimize(&options)?
+                        .parallelize(parallelism)
+                })?;
                 writeln!(output, "{plan}")?;
             }
 
@@ -206,22 +719,44 @@ mod tests {
                     return Err("using both plan and opt is redundant".into());
                 }
                 let ast = Parser::new(input).parse()?;
-                let plan = session.with_txn(true, |txn| Planner::new(txn).build(ast))?;
+                let wall_time = session.wall_time();
+                let options = session.optimizer_options(&ast);
+                let plan = session
+                    .with_txn(true, |txn| Planner::new(txn).at_time(wall_time).build(ast))?;
                 let Plan::Select(mut root) = plan else {
                     return Err("can only use opt with SELECT plans".into());
                 };
                 writeln!(output, "{}", format!("Initial:\n{root}").replace('\n', "\n   "))?;
                 for (name, optimizer) in OPTIMIZERS {
                     let prev = root.clone();
-                    root = optimizer(root)?;
+                    root = optimizer(root, &options)?;
                     if root != prev {
                         writeln!(output, "{}", format!("{name}:\n{root}").replace('\n', "\n   "))?;
                     }
                 }
             }
 
-            // Execute the statement.
-            let result = session.execute(input)?;
+            // Execute the statement. If requested, execute it with an already
+            // cancelled cancellation token, and output its trace.
+            let cancelled = tags.remove("cancel");
+            let mut execute = || match cancelled {
+                true => {
+                    let cancel = CancelToken::new();
+                    cancel.cancel();
+                    session.execute_stream(input, &cancel, |_, rows| {
+                        rows.try_collect::<_, Vec<_>, _>().map(|_| ())
+                    })
+                }
+                false => session.execute(input),
+            };
+            let result = match tags.remove("trace") {
+                true => {
+                    let (result, spans) = trace::capture(execute);
+                    write!(output, "{}", trace::format_tree(&spans))?;
+                    result?
+                }
+                false => execute()?,
+            };
 
             // Output engine ops if requested.
             if tags.remove("ops") {
@@ -242,7 +777,8 @@ mod tests {
                 }
             }
 
-            // Output the result if requested. SELECT results are always output.
+            // Output the result if requested. SELECT and RETURNING rows are
+            // always output.
             match result {
                 StatementResult::Select { columns, rows } => {
                     if tags.remove("header") {
@@ -252,6 +788,22 @@ mod tests {
                         writeln!(output, "{}", row.into_iter().join(", "))?;
                     }
                 }
+                StatementResult::Delete {
+                    returning: Some(Returning { columns, rows }), ..
+                }
+                | StatementResult::Insert {
+                    returning: Some(Returning { columns, rows }), ..
+                }
+                | StatementResult::Update {
+                    returning: Some(Returning { columns, rows }), ..
+                } => {
+                    if tags.remove("header") {
+                        writeln!(output, "{}", columns.into_iter().join(", "))?;
+                    }
+                    for row in rows {
+                        writeln!(output, "{}", row.into_iter().join(", "))?;
+                    }
+                }
                 result if tags.remove("result") => writeln!(output, "{result:?}")?,
                 _ => {}
             }
@@ -275,8 +827,6 @@ mod tests {
     /// optionally emits the expression tree.
     struct ExpressionRunner;
 
-    type Catalog<'a> = <Local<storage::Memory> as Engine<'a>>::Transaction;
-
     impl goldenscript::Runner for ExpressionRunner {
         fn run(&mut self, command: &goldenscript::Command) -> Result<String, Box<dyn Error>> {
             let mut output = String::new();
@@ -294,7 +844,9 @@ mod tests {
             if let Some(next) = parser.lexer.next().transpose()? {
                 return Err(format!("unconsumed token {next}").into());
             }
-            let expr = Planner::<Catalog>::build_expression(ast, &Scope::new())?;
+            let engine = Local::new(storage::Memory::new());
+            let txn = engine.begin_read_only()?;
+            let expr = Planner::new(&txn).build_expression(ast, &Scope::new())?;
 
             // Evaluate the expression.
             let value = expr.evaluate(None)?;
@@ -322,4 +874,30 @@ mod tests {
             Ok(output)
         }
     }
+
+    /// A test runner for the parser. Parses a script of statements in error
+    /// recovery mode, and emits the result of each statement.
+    struct ParserRunner;
+
+    impl goldenscript::Runner for ParserRunner {
+        fn run(&mut self, command: &goldenscript::Command) -> Result<String, Box<dyn Error>> {
+            let mut output = String::new();
+
+            // The entire command is the script to parse. There are no args.
+            if !command.args.is_empty() {
+                return Err("scripts should be given as a command with no args".into());
+            }
+            if let Some(tag) = command.tags.iter().next() {
+                return Err(format!("unknown tag {tag}").into());
+            }
+
+            for result in Parser::new(&command.name).parse_statements() {
+                match result {
+                    Ok(_) => writeln!(output, "ok")?,
+                    Err(error) => writeln!(output, "Error: {error}")?,
+                }
+            }
+            Ok(output)
+        }
+    }
 }
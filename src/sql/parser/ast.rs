@@ -1,63 +1,239 @@
 use std::collections::BTreeMap;
 
-use crate::sql::types::DataType;
+use crate::clock;
+use crate::sql::types::{
+    Collation, Comparison, DataType, FileFormat, IndexType, Interval, Privilege, Timestamp,
+    TriggerEvent, TriggerTiming,
+};
+use crate::storage::mvcc::IsolationLevel;
 
 /// The statement is the root node of the Abstract Syntax Tree, and describes
 /// the syntactic structure of a SQL query. It is built from a raw SQL string by
 /// the parser, and passed on to the planner which validates it and builds an
 /// execution plan from it.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub enum Statement {
     /// Begin a new transaction.
-    Begin { read_only: bool, as_of: Option<u64> },
+    Begin { read_only: bool, as_of: Option<AsOf> },
     /// Commit a transaction.
     Commit,
     /// Roll back a transaction.
     Rollback,
+    /// Create a savepoint in a transaction.
+    Savepoint(String),
+    /// Roll back to a savepoint, undoing writes made since.
+    RollbackToSavepoint(String),
+    /// Release a savepoint, retaining its writes.
+    ReleaseSavepoint(String),
+    /// Forcibly abort a different active transaction by ID.
+    AbortTransaction(u64),
+    /// Set the isolation level of the current transaction.
+    SetTransaction { isolation: IsolationLevel },
+    /// Set a session variable, or reset it to its default if None.
+    Set { name: String, value: Option<String> },
+    /// Show a session variable, or all variables if None.
+    Show { name: Option<String> },
     /// Explain a statement.
     Explain(Box<Statement>),
-    /// Create a new table.
-    CreateTable { name: String, columns: Vec<Column> },
+    /// Create a new table. Temporary tables only exist in the session.
+    CreateTable {
+        name: String,
+        columns: Vec<Column>,
+        temporary: bool,
+        partition_by: Option<PartitionBy>, // PARTITION BY RANGE
+        ttl: Option<Ttl>,                  // WITH (ttl = ..., ttl_column = ...)
+    },
     /// Drop a table.
     DropTable { name: String, if_exists: bool },
+    /// Alter a table.
+    AlterTable { name: String, operation: AlterTable },
+    /// Create a secondary index on a table column, optionally a partial index.
+    CreateIndex {
+        table: String,
+        column: String,
+        index_type: IndexType,
+        r#where: Option<Expression>,
+    },
+    /// Create a row-level trigger on a table. The action is the SQL string of
+    /// a trigger action (see TriggerAction), which has been parsed.
+    CreateTrigger {
+        name: String,
+        table: String,
+        timing: TriggerTiming,
+        event: TriggerEvent,
+        action: String,
+    },
+    /// Drop a trigger from a table.
+    DropTrigger { name: String, table: String, if_exists: bool },
+    /// Create a changefeed for a table.
+    CreateChangefeed { table: String },
+    /// Drop a table's changefeed.
+    DropChangefeed { table: String },
+    /// Create a user account with a password.
+    CreateUser { name: String, password: String },
+    /// Drop a user account.
+    DropUser { name: String, if_exists: bool },
+    /// Grant privileges on a table, or all tables if None, to a user.
+    Grant { privileges: Vec<Privilege>, table: Option<String>, user: String },
+    /// Revoke privileges on a table, or all tables if None, from a user.
+    Revoke { privileges: Vec<Privilege>, table: Option<String>, user: String },
+    /// Delete all rows in a table.
+    TruncateTable { name: String },
     /// Delete matching rows.
-    Delete { table: String, r#where: Option<Expression> },
+    Delete {
+        table: String,
+        r#where: Option<Expression>,
+        returning: Vec<(Expression, Option<String>)>, // RETURNING expressions and aliases
+    },
     /// Insert new rows into a table.
     Insert {
         table: String,
         columns: Option<Vec<String>>, // columns given in values, using default for rest
         values: Vec<Vec<Expression>>, // rows to insert
+        returning: Vec<(Expression, Option<String>)>, // RETURNING expressions and aliases
     },
+    /// Bulk load new rows into a table, bypassing per-row MVCC bookkeeping.
+    Import {
+        table: String,
+        columns: Option<Vec<String>>, // columns given in source, using default for rest
+        source: ImportSource,         // rows to import
+    },
+    /// Export the rows of a SELECT query to a file.
+    Export { query: Box<Statement>, path: String, format: FileFormat, header: bool },
     /// Update rows in a table.
     Update {
         table: String,
         set: BTreeMap<String, Option<Expression>>, // column → value, None for default value
         r#where: Option<Expression>,
+        returning: Vec<(Expression, Option<String>)>, // RETURNING expressions and aliases
     },
+    /// List all tables.
+    ShowTables,
+    /// Show the CREATE TABLE statement of a table.
+    ShowCreateTable { name: String },
+    /// Describe the columns of a table.
+    Describe { name: String },
     /// Select matching rows.
     Select {
+        hints: Vec<Hint>,                          // /*+ */ optimizer hints
         select: Vec<(Expression, Option<String>)>, // optional column aliases
         from: Vec<From>,
+        as_of: Option<AsOf>, // AS OF SYSTEM TIME
         r#where: Option<Expression>,
         group_by: Vec<Expression>,
         having: Option<Expression>,
         order_by: Vec<(Expression, Direction)>,
         offset: Option<Expression>,
         limit: Option<Expression>,
+        for_update: bool, // FOR UPDATE row locks
     },
 }
 
+/// An optimizer hint, given in a /*+ */ comment after SELECT, e.g. SELECT /*+
+/// HASH_JOIN(a b) */ * FROM a JOIN b ON a.id = b.id. Tables are referenced by
+/// their alias, if any. Hints that can't be honored are ignored.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Hint {
+    /// HASH_JOIN(table...): use a hash join to join the given tables.
+    HashJoin(Vec<String>),
+    /// NESTED_LOOP_JOIN(table...): use a nested loop join to join the given
+    /// tables.
+    NestedLoopJoin(Vec<String>),
+    /// INDEX(table [column]): use a primary key or index lookup for the table,
+    /// on the given column if any.
+    Index { table: String, column: Option<String> },
+    /// NO_INDEX(table): use a full table scan for the table.
+    NoIndex(String),
+}
+
+/// An AS OF SYSTEM TIME clause, for time-travel queries.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AsOf {
+    /// An MVCC version, e.g. AS OF SYSTEM TIME 3.
+    Version(u64),
+    /// An HLC timestamp, e.g. AS OF SYSTEM TIME '1700000000000000000.0'.
+    Timestamp(clock::Timestamp),
+}
+
+impl Statement {
+    /// Returns the statement's SQL command name, e.g. "CREATE TABLE". Used to
+    /// label statement metrics.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Begin { .. } => "BEGIN",
+            Self::Commit => "COMMIT",
+            Self::Rollback => "ROLLBACK",
+            Self::Savepoint(_) => "SAVEPOINT",
+            Self::RollbackToSavepoint(_) => "ROLLBACK TO SAVEPOINT",
+            Self::ReleaseSavepoint(_) => "RELEASE SAVEPOINT",
+            Self::AbortTransaction(_) => "ABORT TRANSACTION",
+            Self::SetTransaction { .. } => "SET TRANSACTION",
+            Self::Set { .. } => "SET",
+            Self::Show { .. } => "SHOW",
+            Self::Explain(_) => "EXPLAIN",
+            Self::CreateTable { .. } => "CREATE TABLE",
+            Self::DropTable { .. } => "DROP TABLE",
+            Self::AlterTable { .. } => "ALTER TABLE",
+            Self::CreateIndex { .. } => "CREATE INDEX",
+            Self::CreateTrigger { .. } => "CREATE TRIGGER",
+            Self::DropTrigger { .. } => "DROP TRIGGER",
+            Self::CreateChangefeed { .. } => "CREATE CHANGEFEED",
+            Self::DropChangefeed { .. } => "DROP CHANGEFEED",
+            Self::CreateUser { .. } => "CREATE USER",
+            Self::DropUser { .. } => "DROP USER",
+            Self::Grant { .. } => "GRANT",
+            Self::Revoke { .. } => "REVOKE",
+            Self::TruncateTable { .. } => "TRUNCATE TABLE",
+            Self::Delete { .. } => "DELETE",
+            Self::Insert { .. } => "INSERT",
+            Self::Import { .. } => "IMPORT",
+            Self::Export { .. } => "EXPORT",
+            Self::Update { .. } => "UPDATE",
+            Self::ShowTables => "SHOW TABLES",
+            Self::ShowCreateTable { .. } => "SHOW CREATE TABLE",
+            Self::Describe { .. } => "DESCRIBE",
+            Self::Select { .. } => "SELECT",
+        }
+    }
+
+    /// Returns the optimizer hints of a SELECT query, including EXPLAIN and
+    /// EXPORT queries.
+    pub fn hints(&self) -> &[Hint] {
+        match self {
+            Self::Select { hints, .. } => hints,
+            Self::Explain(statement) | Self::Export { query: statement, .. } => statement.hints(),
+            _ => &[],
+        }
+    }
+}
+
+/// The source of IMPORT rows.
+#[derive(Clone, Debug)]
+pub enum ImportSource {
+    /// VALUES rows.
+    Values(Vec<Vec<Expression>>),
+    /// A file on the server. If header is true, the first CSV record contains
+    /// column names.
+    File { path: String, format: FileFormat, header: bool },
+    /// Data streamed by the client (i.e. COPY FROM STDIN). The parser leaves
+    /// the data empty, it's filled in by Session::execute_copy().
+    Stdin { data: Vec<u8>, format: FileFormat, header: bool },
+}
+
 /// A FROM item.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub enum From {
     /// A table.
     Table { name: String, alias: Option<String> },
     /// A join of two or more tables (may be nested).
     Join { left: Box<From>, right: Box<From>, r#type: JoinType, predicate: Option<Expression> },
+    /// The UNNEST table function, which expands an array into one row per
+    /// element. The expression can reference columns of preceding FROM items.
+    Unnest { expression: Expression, alias: Option<String> },
 }
 
 /// A CREATE TABLE column definition.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Column {
     pub name: String,
     pub datatype: DataType,
@@ -67,10 +243,48 @@ pub struct Column {
     pub unique: bool,
     pub index: bool,
     pub references: Option<String>,
+    pub collation: Collation,
+    pub generated: Option<Expression>,
+}
+
+/// A CREATE TABLE range partitioning clause.
+#[derive(Clone, Debug)]
+pub struct PartitionBy {
+    /// The partitioning column.
+    pub column: String,
+    /// The partitions, as names and exclusive upper bounds. None for MAXVALUE.
+    pub partitions: Vec<(String, Option<Expression>)>,
+}
+
+/// A CREATE TABLE row time-to-live, given as WITH (ttl = '7 days',
+/// ttl_column = created_at).
+#[derive(Clone, Debug)]
+pub struct Ttl {
+    /// The interval after which rows expire, as an interval string.
+    pub interval: String,
+    /// The column containing the row's creation time.
+    pub column: String,
+}
+
+/// An ALTER TABLE operation.
+#[derive(Clone, Debug)]
+pub enum AlterTable {
+    /// Drop a partition and its rows.
+    DropPartition(String),
+}
+
+/// A trigger action.
+#[derive(Clone, Debug)]
+pub enum TriggerAction {
+    /// Sets columns of the new row, i.e. SET column = value, ... Only valid in
+    /// BEFORE INSERT and UPDATE triggers.
+    Set(BTreeMap<String, Option<Expression>>), // column → value, None for default value
+    /// Executes an INSERT, UPDATE, or DELETE statement.
+    Statement(Box<Statement>),
 }
 
 /// JOIN types.
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum JoinType {
     Cross,
     Inner,
@@ -90,7 +304,7 @@ impl JoinType {
 }
 
 /// ORDER BY direction.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub enum Direction {
     Ascending,
     Descending,
@@ -107,8 +321,14 @@ pub enum Expression {
     Literal(Literal),
     /// A function call (name and parameters).
     Function(String, Vec<Expression>),
+    /// An array constructor, i.e. ARRAY[a, b].
+    Array(Vec<Expression>),
     /// An operator.
     Operator(Operator),
+    /// A query parameter, i.e. a placeholder for the literal value at the
+    /// given index. Not produced by the parser, only by the plan cache when
+    /// normalizing statements (see PlanCache).
+    Parameter(usize),
 }
 
 /// Expression literal values.
@@ -119,6 +339,8 @@ pub enum Literal {
     Integer(i64),
     Float(f64),
     String(String),
+    Interval(Interval),
+    Timestamp(Timestamp),
 }
 
 /// To allow using Expressions and Literals in e.g. hashmaps, implement simple
@@ -133,6 +355,8 @@ impl std::cmp::PartialEq for Literal {
             // Implies NaN == NaN but -NaN != NaN. Similarly with +/-0.0.
             (Self::Float(l), Self::Float(r)) => l.to_bits() == r.to_bits(),
             (Self::String(l), Self::String(r)) => l == r,
+            (Self::Interval(l), Self::Interval(r)) => l == r,
+            (Self::Timestamp(l), Self::Timestamp(r)) => l == r,
             (l, r) => core::mem::discriminant(l) == core::mem::discriminant(r),
         }
     }
@@ -149,6 +373,8 @@ impl std::hash::Hash for Literal {
             Self::Integer(v) => v.hash(state),
             Self::Float(v) => v.to_bits().hash(state),
             Self::String(v) => v.hash(state),
+            Self::Interval(v) => v.hash(state),
+            Self::Timestamp(v) => v.hash(state),
         }
     }
 }
@@ -182,7 +408,18 @@ pub enum Operator {
     Remainder(Box<Expression>, Box<Expression>),    // a % b
     Subtract(Box<Expression>, Box<Expression>),     // a - b
 
-    Like(Box<Expression>, Box<Expression>), // a LIKE b
+    Like(Box<Expression>, Box<Expression>),   // a LIKE b
+    Match(Box<Expression>, Box<Expression>),  // a MATCH b or a @@ b
+    Regexp(Box<Expression>, Box<Expression>), // a ~ b or a REGEXP b
+
+    JsonExtract(Box<Expression>, Box<Expression>), // a -> b
+    JsonExtractText(Box<Expression>, Box<Expression>), // a ->> b
+
+    All(Comparison, Box<Expression>, Box<Expression>), // a = ALL(b)
+    Any(Comparison, Box<Expression>, Box<Expression>), // a = ANY(b)
+    Index(Box<Expression>, Box<Expression>),           // a[b]
+
+    Collate(Box<Expression>, Collation), // a COLLATE NOCASE
 }
 
 impl Expression {
@@ -195,30 +432,86 @@ impl Expression {
         }
         match self {
             Self::Operator(Add(lhs, rhs))
+            | Self::Operator(All(_, lhs, rhs))
             | Self::Operator(And(lhs, rhs))
+            | Self::Operator(Any(_, lhs, rhs))
             | Self::Operator(Divide(lhs, rhs))
             | Self::Operator(Equal(lhs, rhs))
             | Self::Operator(Exponentiate(lhs, rhs))
             | Self::Operator(GreaterThan(lhs, rhs))
             | Self::Operator(GreaterThanOrEqual(lhs, rhs))
+            | Self::Operator(Index(lhs, rhs))
+            | Self::Operator(JsonExtract(lhs, rhs))
+            | Self::Operator(JsonExtractText(lhs, rhs))
             | Self::Operator(LessThan(lhs, rhs))
             | Self::Operator(LessThanOrEqual(lhs, rhs))
             | Self::Operator(Like(lhs, rhs))
+            | Self::Operator(Match(lhs, rhs))
             | Self::Operator(Multiply(lhs, rhs))
             | Self::Operator(NotEqual(lhs, rhs))
             | Self::Operator(Or(lhs, rhs))
+            | Self::Operator(Regexp(lhs, rhs))
             | Self::Operator(Remainder(lhs, rhs))
             | Self::Operator(Subtract(lhs, rhs)) => lhs.walk(visitor) && rhs.walk(visitor),
 
-            Self::Operator(Factorial(expr))
+            Self::Operator(Collate(expr, _))
+            | Self::Operator(Factorial(expr))
             | Self::Operator(Identity(expr))
             | Self::Operator(Is(expr, _))
             | Self::Operator(Negate(expr))
             | Self::Operator(Not(expr)) => expr.walk(visitor),
 
             Self::Function(_, exprs) => exprs.iter().any(|expr| expr.walk(visitor)),
+            Self::Array(exprs) => exprs.iter().all(|expr| expr.walk(visitor)),
 
-            Self::All | Self::Column(_, _) | Self::Literal(_) => true,
+            Self::All | Self::Column(_, _) | Self::Literal(_) | Self::Parameter(_) => true,
+        }
+    }
+
+    /// Walks the expression tree depth-first, calling a closure for every node
+    /// before descending into it. The closure can modify the node.
+    pub fn walk_mut(&mut self, visitor: &mut impl FnMut(&mut Expression)) {
+        use Operator::*;
+        visitor(self);
+        match self {
+            Self::Operator(Add(lhs, rhs))
+            | Self::Operator(All(_, lhs, rhs))
+            | Self::Operator(And(lhs, rhs))
+            | Self::Operator(Any(_, lhs, rhs))
+            | Self::Operator(Divide(lhs, rhs))
+            | Self::Operator(Equal(lhs, rhs))
+            | Self::Operator(Exponentiate(lhs, rhs))
+            | Self::Operator(GreaterThan(lhs, rhs))
+            | Self::Operator(GreaterThanOrEqual(lhs, rhs))
+            | Self::Operator(Index(lhs, rhs))
+            | Self::Operator(JsonExtract(lhs, rhs))
+            | Self::Operator(JsonExtractText(lhs, rhs))
+            | Self::Operator(LessThan(lhs, rhs))
+            | Self::Operator(LessThanOrEqual(lhs, rhs))
+            | Self::Operator(Like(lhs, rhs))
+            | Self::Operator(Match(lhs, rhs))
+            | Self::Operator(Multiply(lhs, rhs))
+            | Self::Operator(NotEqual(lhs, rhs))
+            | Self::Operator(Or(lhs, rhs))
+            | Self::Operator(Regexp(lhs, rhs))
+            | Self::Operator(Remainder(lhs, rhs))
+            | Self::Operator(Subtract(lhs, rhs)) => {
+                lhs.walk_mut(visitor);
+                rhs.walk_mut(visitor);
+            }
+
+            Self::Operator(Collate(expr, _))
+            | Self::Operator(Factorial(expr))
+            | Self::Operator(Identity(expr))
+            | Self::Operator(Is(expr, _))
+            | Self::Operator(Negate(expr))
+            | Self::Operator(Not(expr)) => expr.walk_mut(visitor),
+
+            Self::Function(_, exprs) | Self::Array(exprs) => {
+                exprs.iter_mut().for_each(|expr| expr.walk_mut(visitor))
+            }
+
+            Self::All | Self::Column(_, _) | Self::Literal(_) | Self::Parameter(_) => {}
         }
     }
 
@@ -238,33 +531,43 @@ impl Expression {
         use Operator::*;
         match self {
             Self::Operator(Add(lhs, rhs))
+            | Self::Operator(All(_, lhs, rhs))
             | Self::Operator(And(lhs, rhs))
+            | Self::Operator(Any(_, lhs, rhs))
             | Self::Operator(Divide(lhs, rhs))
             | Self::Operator(Equal(lhs, rhs))
             | Self::Operator(Exponentiate(lhs, rhs))
             | Self::Operator(GreaterThan(lhs, rhs))
             | Self::Operator(GreaterThanOrEqual(lhs, rhs))
+            | Self::Operator(Index(lhs, rhs))
+            | Self::Operator(JsonExtract(lhs, rhs))
+            | Self::Operator(JsonExtractText(lhs, rhs))
             | Self::Operator(LessThan(lhs, rhs))
             | Self::Operator(LessThanOrEqual(lhs, rhs))
             | Self::Operator(Like(lhs, rhs))
+            | Self::Operator(Match(lhs, rhs))
             | Self::Operator(Multiply(lhs, rhs))
             | Self::Operator(NotEqual(lhs, rhs))
             | Self::Operator(Or(lhs, rhs))
+            | Self::Operator(Regexp(lhs, rhs))
             | Self::Operator(Remainder(lhs, rhs))
             | Self::Operator(Subtract(lhs, rhs)) => {
                 lhs.collect(visitor, c);
                 rhs.collect(visitor, c);
             }
 
-            Self::Operator(Factorial(expr))
+            Self::Operator(Collate(expr, _))
+            | Self::Operator(Factorial(expr))
             | Self::Operator(Identity(expr))
             | Self::Operator(Is(expr, _))
             | Self::Operator(Negate(expr))
             | Self::Operator(Not(expr)) => expr.collect(visitor, c),
 
-            Self::Function(_, exprs) => exprs.iter().for_each(|expr| expr.collect(visitor, c)),
+            Self::Function(_, exprs) | Self::Array(exprs) => {
+                exprs.iter().for_each(|expr| expr.collect(visitor, c))
+            }
 
-            Self::All | Self::Column(_, _) | Self::Literal(_) => {}
+            Self::All | Self::Column(_, _) | Self::Literal(_) | Self::Parameter(_) => {}
         }
     }
 }
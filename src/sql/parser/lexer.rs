@@ -4,13 +4,41 @@ use std::str::Chars;
 
 use crate::errinput;
 use crate::error::Result;
+use crate::sql::types::format_ident;
 
 /// The lexer (lexical analyzer) preprocesses raw SQL strings into a sequence of
 /// lexical tokens (e.g. keyword, number, string, etc), which are passed on to
 /// the SQL parser. In doing so, it strips away basic syntactic noise such as
 /// whitespace, case, and quotes, and performs initial symbol validation.
+///
+/// The lexer tracks the line and column of each token, for error messages.
+#[derive(Clone)]
 pub struct Lexer<'a> {
     chars: Peekable<Chars<'a>>,
+    /// The position of the next character.
+    next_position: Position,
+    /// The byte offset of the next character.
+    next_offset: usize,
+    /// The position of the last scanned token (including a peeked token), or
+    /// the end of the input if there are no more tokens.
+    position: Position,
+    /// A peeked token, if any. None means there is no peeked token, while
+    /// Some(None) means the end of the input was peeked.
+    peeked: Option<Option<Result<Token>>>,
+}
+
+/// A position in the input string, as 1-based line and column numbers. The
+/// column counts characters, not bytes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Display for Position {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}, column {}", self.line, self.column)
+    }
 }
 
 /// A lexical token.
@@ -39,16 +67,24 @@ pub enum Token {
     LessOrGreaterThan,  // <>
     Plus,               // +
     Minus,              // -
+    Arrow,              // ->
+    LongArrow,          // ->>
     Asterisk,           // *
     Slash,              // /
     Caret,              // ^
     Percent,            // %
+    Tilde,              // ~
+    AtAt,               // @@
     Exclamation,        // !
     Question,           // ?
     Comma,              // ,
     Semicolon,          // ;
     OpenParen,          // (
     CloseParen,         // )
+    OpenBracket,        // [
+    CloseBracket,       // ]
+    OpenHint,           // /*+
+    CloseHint,          // */
 }
 
 impl Display for Token {
@@ -68,16 +104,24 @@ impl Display for Token {
             Self::LessOrGreaterThan => "<>",
             Self::Plus => "+",
             Self::Minus => "-",
+            Self::Arrow => "->",
+            Self::LongArrow => "->>",
             Self::Asterisk => "*",
             Self::Slash => "/",
             Self::Caret => "^",
             Self::Percent => "%",
+            Self::Tilde => "~",
+            Self::AtAt => "@@",
             Self::Exclamation => "!",
             Self::Question => "?",
             Self::Comma => ",",
             Self::Semicolon => ";",
             Self::OpenParen => "(",
             Self::CloseParen => ")",
+            Self::OpenBracket => "[",
+            Self::CloseBracket => "]",
+            Self::OpenHint => "/*+",
+            Self::CloseHint => "*/",
         })
     }
 }
@@ -91,42 +135,65 @@ impl From<Keyword> for Token {
 /// Reserved SQL keywords.
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Keyword {
+    Abort,
+    After,
+    All,
+    Always,
+    Alter,
     And,
+    Any,
+    Array,
     As,
     Asc,
+    Before,
     Begin,
     Bool,
     Boolean,
     By,
+    Changefeed,
+    Collate,
     Commit,
+    Committed,
     Create,
     Cross,
     Default,
     Delete,
     Desc,
+    Describe,
     Double,
     Drop,
     Exists,
     Explain,
     False,
     Float,
+    For,
     From,
+    Generated,
+    Grant,
     Group,
     Having,
     If,
+    Import,
     Index,
     Infinity,
     Inner,
     Insert,
     Int,
     Integer,
+    Interval,
     Into,
     Is,
+    Isolation,
     Join,
+    Json,
     Key,
     Left,
+    Less,
+    Level,
     Like,
     Limit,
+    Match,
+    Maxvalue,
     NaN,
     Not,
     Null,
@@ -137,28 +204,169 @@ pub enum Keyword {
     Or,
     Order,
     Outer,
+    Partition,
+    Password,
     Primary,
+    Privileges,
+    Range,
     Read,
     References,
+    Regexp,
+    Release,
+    Reset,
+    Returning,
+    Revoke,
     Right,
     Rollback,
+    Savepoint,
     Select,
+    Serializable,
     Set,
+    Show,
+    Snapshot,
+    Stored,
     String,
     System,
     Table,
+    Temporary,
     Text,
+    Than,
     Time,
+    Timestamp,
+    To,
     Transaction,
+    Trigger,
     True,
+    Truncate,
     Unique,
     Update,
+    User,
+    Using,
     Values,
     Varchar,
     Where,
+    With,
     Write,
 }
 
+impl Keyword {
+    /// All keywords, in alphabetical order. Used e.g. for tab completion.
+    pub const ALL: &[Keyword] = &[
+        Self::Abort,
+        Self::After,
+        Self::All,
+        Self::Always,
+        Self::Alter,
+        Self::And,
+        Self::Any,
+        Self::Array,
+        Self::As,
+        Self::Asc,
+        Self::Before,
+        Self::Begin,
+        Self::Bool,
+        Self::Boolean,
+        Self::By,
+        Self::Changefeed,
+        Self::Collate,
+        Self::Commit,
+        Self::Committed,
+        Self::Create,
+        Self::Cross,
+        Self::Default,
+        Self::Delete,
+        Self::Desc,
+        Self::Describe,
+        Self::Double,
+        Self::Drop,
+        Self::Exists,
+        Self::Explain,
+        Self::False,
+        Self::Float,
+        Self::For,
+        Self::From,
+        Self::Generated,
+        Self::Grant,
+        Self::Group,
+        Self::Having,
+        Self::If,
+        Self::Import,
+        Self::Index,
+        Self::Infinity,
+        Self::Inner,
+        Self::Insert,
+        Self::Int,
+        Self::Integer,
+        Self::Interval,
+        Self::Into,
+        Self::Is,
+        Self::Isolation,
+        Self::Join,
+        Self::Json,
+        Self::Key,
+        Self::Left,
+        Self::Less,
+        Self::Level,
+        Self::Like,
+        Self::Limit,
+        Self::Match,
+        Self::Maxvalue,
+        Self::NaN,
+        Self::Not,
+        Self::Null,
+        Self::Of,
+        Self::Offset,
+        Self::On,
+        Self::Only,
+        Self::Or,
+        Self::Order,
+        Self::Outer,
+        Self::Partition,
+        Self::Password,
+        Self::Primary,
+        Self::Privileges,
+        Self::Range,
+        Self::Read,
+        Self::References,
+        Self::Regexp,
+        Self::Release,
+        Self::Reset,
+        Self::Returning,
+        Self::Revoke,
+        Self::Right,
+        Self::Rollback,
+        Self::Savepoint,
+        Self::Select,
+        Self::Serializable,
+        Self::Set,
+        Self::Show,
+        Self::Snapshot,
+        Self::Stored,
+        Self::String,
+        Self::System,
+        Self::Table,
+        Self::Temporary,
+        Self::Text,
+        Self::Than,
+        Self::Time,
+        Self::Timestamp,
+        Self::To,
+        Self::Transaction,
+        Self::Trigger,
+        Self::True,
+        Self::Truncate,
+        Self::Unique,
+        Self::Update,
+        Self::User,
+        Self::Using,
+        Self::Values,
+        Self::Varchar,
+        Self::Where,
+        Self::With,
+        Self::Write,
+    ];
+}
+
 impl TryFrom<&str> for Keyword {
     // Use a cheap static string, since this just indicates it's not a keyword.
     type Error = &'static str;
@@ -168,42 +376,65 @@ impl TryFrom<&str> for Keyword {
         // allocating a string to change the case. Assert this.
         debug_assert!(value.chars().all(|c| !c.is_uppercase()), "keyword must be lowercase");
         Ok(match value {
+            "abort" => Self::Abort,
+            "after" => Self::After,
+            "all" => Self::All,
+            "always" => Self::Always,
+            "alter" => Self::Alter,
+            "and" => Self::And,
+            "any" => Self::Any,
+            "array" => Self::Array,
             "as" => Self::As,
             "asc" => Self::Asc,
-            "and" => Self::And,
+            "before" => Self::Before,
             "begin" => Self::Begin,
             "bool" => Self::Bool,
             "boolean" => Self::Boolean,
             "by" => Self::By,
+            "changefeed" => Self::Changefeed,
+            "collate" => Self::Collate,
             "commit" => Self::Commit,
+            "committed" => Self::Committed,
             "create" => Self::Create,
             "cross" => Self::Cross,
             "default" => Self::Default,
             "delete" => Self::Delete,
             "desc" => Self::Desc,
+            "describe" => Self::Describe,
             "double" => Self::Double,
             "drop" => Self::Drop,
             "exists" => Self::Exists,
             "explain" => Self::Explain,
             "false" => Self::False,
             "float" => Self::Float,
+            "for" => Self::For,
             "from" => Self::From,
+            "generated" => Self::Generated,
+            "grant" => Self::Grant,
             "group" => Self::Group,
             "having" => Self::Having,
             "if" => Self::If,
+            "import" => Self::Import,
             "index" => Self::Index,
             "infinity" => Self::Infinity,
             "inner" => Self::Inner,
             "insert" => Self::Insert,
             "int" => Self::Int,
             "integer" => Self::Integer,
+            "interval" => Self::Interval,
             "into" => Self::Into,
             "is" => Self::Is,
+            "isolation" => Self::Isolation,
             "join" => Self::Join,
+            "json" => Self::Json,
             "key" => Self::Key,
             "left" => Self::Left,
+            "less" => Self::Less,
+            "level" => Self::Level,
             "like" => Self::Like,
             "limit" => Self::Limit,
+            "match" => Self::Match,
+            "maxvalue" => Self::Maxvalue,
             "nan" => Self::NaN,
             "not" => Self::Not,
             "null" => Self::Null,
@@ -214,25 +445,48 @@ impl TryFrom<&str> for Keyword {
             "or" => Self::Or,
             "order" => Self::Order,
             "outer" => Self::Outer,
+            "partition" => Self::Partition,
+            "password" => Self::Password,
             "primary" => Self::Primary,
+            "privileges" => Self::Privileges,
+            "range" => Self::Range,
             "read" => Self::Read,
             "references" => Self::References,
+            "regexp" => Self::Regexp,
+            "release" => Self::Release,
+            "reset" => Self::Reset,
+            "returning" => Self::Returning,
+            "revoke" => Self::Revoke,
             "right" => Self::Right,
             "rollback" => Self::Rollback,
+            "savepoint" => Self::Savepoint,
             "select" => Self::Select,
+            "serializable" => Self::Serializable,
             "set" => Self::Set,
+            "show" => Self::Show,
+            "snapshot" => Self::Snapshot,
+            "stored" => Self::Stored,
             "string" => Self::String,
             "system" => Self::System,
             "table" => Self::Table,
+            "temporary" => Self::Temporary,
             "text" => Self::Text,
+            "than" => Self::Than,
             "time" => Self::Time,
+            "timestamp" => Self::Timestamp,
+            "to" => Self::To,
             "transaction" => Self::Transaction,
+            "trigger" => Self::Trigger,
             "true" => Self::True,
+            "truncate" => Self::Truncate,
             "unique" => Self::Unique,
             "update" => Self::Update,
+            "user" => Self::User,
+            "using" => Self::Using,
             "values" => Self::Values,
             "varchar" => Self::Varchar,
             "where" => Self::Where,
+            "with" => Self::With,
             "write" => Self::Write,
             _ => return Err("not a keyword"),
         })
@@ -243,42 +497,65 @@ impl Display for Keyword {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         // Display keywords as uppercase.
         f.write_str(match self {
+            Self::Abort => "ABORT",
+            Self::After => "AFTER",
+            Self::All => "ALL",
+            Self::Always => "ALWAYS",
+            Self::Alter => "ALTER",
+            Self::And => "AND",
+            Self::Any => "ANY",
+            Self::Array => "ARRAY",
             Self::As => "AS",
             Self::Asc => "ASC",
-            Self::And => "AND",
+            Self::Before => "BEFORE",
             Self::Begin => "BEGIN",
             Self::Bool => "BOOL",
             Self::Boolean => "BOOLEAN",
             Self::By => "BY",
+            Self::Changefeed => "CHANGEFEED",
+            Self::Collate => "COLLATE",
             Self::Commit => "COMMIT",
+            Self::Committed => "COMMITTED",
             Self::Create => "CREATE",
             Self::Cross => "CROSS",
             Self::Default => "DEFAULT",
             Self::Delete => "DELETE",
             Self::Desc => "DESC",
+            Self::Describe => "DESCRIBE",
             Self::Double => "DOUBLE",
             Self::Drop => "DROP",
             Self::Exists => "EXISTS",
             Self::Explain => "EXPLAIN",
             Self::False => "FALSE",
             Self::Float => "FLOAT",
+            Self::For => "FOR",
             Self::From => "FROM",
+            Self::Generated => "GENERATED",
+            Self::Grant => "GRANT",
             Self::Group => "GROUP",
             Self::Having => "HAVING",
             Self::If => "IF",
+            Self::Import => "IMPORT",
             Self::Index => "INDEX",
             Self::Infinity => "INFINITY",
             Self::Inner => "INNER",
             Self::Insert => "INSERT",
             Self::Int => "INT",
             Self::Integer => "INTEGER",
+            Self::Interval => "INTERVAL",
             Self::Into => "INTO",
             Self::Is => "IS",
+            Self::Isolation => "ISOLATION",
             Self::Join => "JOIN",
+            Self::Json => "JSON",
             Self::Key => "KEY",
             Self::Left => "LEFT",
+            Self::Less => "LESS",
+            Self::Level => "LEVEL",
             Self::Like => "LIKE",
             Self::Limit => "LIMIT",
+            Self::Match => "MATCH",
+            Self::Maxvalue => "MAXVALUE",
             Self::NaN => "NAN",
             Self::Not => "NOT",
             Self::Null => "NULL",
@@ -287,27 +564,50 @@ impl Display for Keyword {
             Self::On => "ON",
             Self::Only => "ONLY",
             Self::Outer => "OUTER",
+            Self::Partition => "PARTITION",
+            Self::Password => "PASSWORD",
             Self::Or => "OR",
             Self::Order => "ORDER",
             Self::Primary => "PRIMARY",
+            Self::Privileges => "PRIVILEGES",
+            Self::Range => "RANGE",
             Self::Read => "READ",
             Self::References => "REFERENCES",
+            Self::Regexp => "REGEXP",
+            Self::Release => "RELEASE",
+            Self::Reset => "RESET",
+            Self::Returning => "RETURNING",
+            Self::Revoke => "REVOKE",
             Self::Right => "RIGHT",
             Self::Rollback => "ROLLBACK",
+            Self::Savepoint => "SAVEPOINT",
             Self::Select => "SELECT",
+            Self::Serializable => "SERIALIZABLE",
             Self::Set => "SET",
+            Self::Show => "SHOW",
+            Self::Snapshot => "SNAPSHOT",
+            Self::Stored => "STORED",
             Self::String => "STRING",
             Self::System => "SYSTEM",
             Self::Table => "TABLE",
+            Self::Temporary => "TEMPORARY",
             Self::Text => "TEXT",
+            Self::Than => "THAN",
             Self::Time => "TIME",
+            Self::Timestamp => "TIMESTAMP",
+            Self::To => "TO",
             Self::Transaction => "TRANSACTION",
+            Self::Trigger => "TRIGGER",
             Self::True => "TRUE",
+            Self::Truncate => "TRUNCATE",
             Self::Unique => "UNIQUE",
             Self::Update => "UPDATE",
+            Self::User => "USER",
+            Self::Using => "USING",
             Self::Values => "VALUES",
             Self::Varchar => "VARCHAR",
             Self::Where => "WHERE",
+            Self::With => "WITH",
             Self::Write => "WRITE",
         })
     }
@@ -318,11 +618,15 @@ impl Iterator for Lexer<'_> {
     type Item = Result<Token>;
 
     fn next(&mut self) -> Option<Result<Token>> {
+        if let Some(peeked) = self.peeked.take() {
+            return peeked;
+        }
         match self.scan() {
             Ok(Some(token)) => Some(Ok(token)),
             // If there's any remaining chars, the lexer didn't recognize them.
-            // Otherwise, we're done lexing.
-            Ok(None) => self.chars.peek().map(|c| errinput!("unexpected character {c}")),
+            // Otherwise, we're done lexing. The invalid character is skipped,
+            // such that the lexer can continue after errors.
+            Ok(None) => self.next_char().map(|c| errinput!("unexpected character {c}")),
             Err(err) => Some(Err(err)),
         }
     }
@@ -331,20 +635,48 @@ impl Iterator for Lexer<'_> {
 impl<'a> Lexer<'a> {
     /// Creates a new lexer for the given string.
     pub fn new(input: &'a str) -> Lexer<'a> {
-        Lexer { chars: input.chars().peekable() }
+        let position = Position { line: 1, column: 1 };
+        let chars = input.chars().peekable();
+        Lexer { chars, next_position: position, next_offset: 0, position, peeked: None }
+    }
+
+    /// Peeks the next token, if any.
+    pub fn peek(&mut self) -> Option<&Result<Token>> {
+        if self.peeked.is_none() {
+            self.peeked = Some(self.next());
+        }
+        self.peeked.as_ref().and_then(|peeked| peeked.as_ref())
+    }
+
+    /// Returns the position of the last scanned token (including a peeked
+    /// token), or the end of the input if there are no more tokens. For a
+    /// lexer error, this is the position of the invalid token.
+    pub fn position(&self) -> Position {
+        self.position
+    }
+
+    /// Returns the next character, advancing the position.
+    fn next_char(&mut self) -> Option<char> {
+        let c = self.chars.next()?;
+        self.next_offset += c.len_utf8();
+        match c {
+            '\n' => self.next_position = Position { line: self.next_position.line + 1, column: 1 },
+            _ => self.next_position.column += 1,
+        }
+        Some(c)
     }
 
     /// Returns the next character if it satisfies the predicate.
     fn next_if(&mut self, predicate: impl Fn(char) -> bool) -> Option<char> {
         self.chars.peek().filter(|&&c| predicate(c))?;
-        self.chars.next()
+        self.next_char()
     }
 
     /// Applies a function to the next character, returning its result and
     /// consuming the next character if it's Some.
     fn next_if_map<T>(&mut self, map: impl Fn(char) -> Option<T>) -> Option<T> {
         let value = self.chars.peek().and_then(|&c| map(c))?;
-        self.chars.next();
+        self.next_char();
         Some(value)
     }
 
@@ -357,12 +689,14 @@ impl<'a> Lexer<'a> {
     fn scan(&mut self) -> Result<Option<Token>> {
         // Ignore whitespace.
         self.skip_whitespace();
+        self.position = self.next_position;
         // The first character tells us the token type.
         match self.chars.peek() {
             Some('\'') => self.scan_string(),
             Some('"') => self.scan_ident_quoted(),
             Some(c) if c.is_ascii_digit() => Ok(self.scan_number()),
             Some(c) if c.is_alphabetic() => Ok(self.scan_ident_or_keyword()),
+            Some('@') => self.scan_at_at(),
             Some(_) => Ok(self.scan_symbol()),
             None => Ok(None),
         }
@@ -390,7 +724,7 @@ impl<'a> Lexer<'a> {
         }
         let mut ident = String::new();
         loop {
-            match self.chars.next() {
+            match self.next_char() {
                 // "" is the escape sequence for ".
                 Some('"') if self.next_is('"') => ident.push('"'),
                 Some('"') => break,
@@ -435,7 +769,7 @@ impl<'a> Lexer<'a> {
         }
         let mut string = String::new();
         loop {
-            match self.chars.next() {
+            match self.next_char() {
                 // '' is the escape sequence for '.
                 Some('\'') if self.next_is('\'') => string.push('\''),
                 Some('\'') => break,
@@ -446,6 +780,17 @@ impl<'a> Lexer<'a> {
         Ok(Some(Token::String(string)))
     }
 
+    /// Scans the @@ operator. A single @ isn't a valid token.
+    fn scan_at_at(&mut self) -> Result<Option<Token>> {
+        if !self.next_is('@') {
+            return Ok(None);
+        }
+        if !self.next_is('@') {
+            return errinput!("unexpected character @");
+        }
+        Ok(Some(Token::AtAt))
+    }
+
     /// Scans the next symbol token, if any.
     fn scan_symbol(&mut self) -> Option<Token> {
         let mut token = self.next_if_map(|c| {
@@ -460,12 +805,15 @@ impl<'a> Lexer<'a> {
                 '/' => Token::Slash,
                 '^' => Token::Caret,
                 '%' => Token::Percent,
+                '~' => Token::Tilde,
                 '!' => Token::Exclamation,
                 '?' => Token::Question,
                 ',' => Token::Comma,
                 ';' => Token::Semicolon,
                 '(' => Token::OpenParen,
                 ')' => Token::CloseParen,
+                '[' => Token::OpenBracket,
+                ']' => Token::CloseBracket,
                 _ => return None,
             })
         })?;
@@ -475,8 +823,20 @@ impl<'a> Lexer<'a> {
             Token::GreaterThan if self.next_is('=') => Token::GreaterThanOrEqual,
             Token::LessThan if self.next_is('>') => Token::LessOrGreaterThan,
             Token::LessThan if self.next_is('=') => Token::LessThanOrEqual,
+            Token::Minus if self.next_is('>') => Token::Arrow,
+            Token::Asterisk if self.next_is('/') => Token::CloseHint,
             token => token,
         };
+        // Handle three-character tokens, i.e. ->> and /*+. A /* that isn't
+        // followed by + is left as / and *, since comments aren't supported.
+        if token == Token::Arrow && self.next_is('>') {
+            token = Token::LongArrow
+        }
+        if token == Token::Slash && self.chars.clone().take(2).eq(['*', '+']) {
+            self.next_char();
+            self.next_char();
+            token = Token::OpenHint
+        }
         Some(token)
     }
 
@@ -494,3 +854,53 @@ pub fn is_ident(ident: &str) -> bool {
     };
     lexer.next().is_none() // if further tokens, it's not a lone identifier
 }
+
+/// Splits a script into its semicolon-separated statements, as raw strings
+/// without the semicolons. Empty statements are skipped. Errors if the script
+/// can't be tokenized, but the statements themselves aren't parsed.
+pub fn split_statements(script: &str) -> Result<Vec<String>> {
+    let mut lexer = Lexer::new(script);
+    let (mut statements, mut start) = (Vec::new(), 0);
+    while let Some(token) = lexer.next() {
+        if token? == Token::Semicolon {
+            statements.push(&script[start..lexer.next_offset - 1]);
+            start = lexer.next_offset;
+        }
+    }
+    statements.push(&script[start..]);
+    let statements = statements.into_iter().map(str::trim).filter(|s| !s.is_empty());
+    Ok(statements.map(str::to_string).collect())
+}
+
+/// Returns the statement with string and number literals replaced by ?, e.g.
+/// for logging statements without their (possibly sensitive) values. The
+/// tokens are reformatted with normalized whitespace and case. If the
+/// statement can't be tokenized, it's redacted entirely.
+pub fn redact(statement: &str) -> String {
+    let Ok(tokens) = Lexer::new(statement).collect::<Result<Vec<_>>>() else {
+        return "?".to_string();
+    };
+    let mut redacted = String::new();
+    let mut prev: Option<&Token> = None;
+    for token in &tokens {
+        let space = match (prev, token) {
+            (None, _) => false,
+            (Some(Token::Period | Token::OpenParen | Token::OpenBracket), _) => false,
+            (_, Token::Period | Token::Comma | Token::Semicolon) => false,
+            (_, Token::CloseParen | Token::CloseBracket) => false,
+            // Function calls and array indexes.
+            (Some(Token::Ident(_)), Token::OpenParen | Token::OpenBracket) => false,
+            _ => true,
+        };
+        if space {
+            redacted.push(' ');
+        }
+        match token {
+            Token::Number(_) | Token::String(_) => redacted.push('?'),
+            Token::Ident(ident) => redacted.push_str(&format_ident(ident)),
+            token => redacted.push_str(&token.to_string()),
+        }
+        prev = Some(token);
+    }
+    redacted
+}
@@ -4,5 +4,73 @@ pub mod ast;
 mod lexer;
 mod parser;
 
-pub use lexer::{Keyword, Lexer, Token, is_ident};
+pub use lexer::{Keyword, Lexer, Position, Token, is_ident, redact, split_statements};
 pub use parser::Parser;
+
+/// Fuzzing entry point, used by the cargo-fuzz targets under fuzz/. Runs the
+/// lexer and parser in all modes on arbitrary input, which must not panic.
+#[doc(hidden)]
+pub fn fuzz(input: &[u8]) {
+    let Ok(input) = std::str::from_utf8(input) else {
+        return;
+    };
+    _ = Lexer::new(input).count();
+    _ = redact(input);
+    _ = split_statements(input);
+    _ = Parser::new(input).parse();
+    _ = Parser::new(input).parse_statements();
+    _ = Parser::new(input).parse_trigger_action();
+    _ = Parser::new(input).parse_expression();
+}
+
+/// Most parser tests are Goldenscripts under src/sql/testscripts.
+#[cfg(test)]
+mod tests {
+    use itertools::Itertools as _;
+    use rand::rngs::StdRng;
+    use rand::seq::SliceRandom as _;
+    use rand::{Rng as _, SeedableRng as _};
+
+    use super::*;
+
+    /// Fuzzes the parser with random sequences of SQL fragments. This is a
+    /// quick deterministic smoke test, cargo-fuzz does more thorough fuzzing.
+    #[test]
+    fn fuzz_fragments() {
+        let fragments = "
+            SELECT INSERT INTO VALUES UPDATE SET DELETE FROM WHERE CREATE DROP TABLE BEGIN
+            COMMIT EXPLAIN GROUP ORDER BY HAVING LIMIT OFFSET JOIN ON AS AND OR NOT NULL IS IN
+            LIKE CASE WHEN THEN ELSE END ARRAY INTERVAL INT PRIMARY KEY REFERENCES INDEX
+            TRIGGER AFTER OF SYSTEM TIME t a b.c \"q\" 1 3.14 1e9 's' '' * + - / % ^ = != < >=
+            -> ->> @@ ( ) [ ] , . ; ? ' \" @ # é";
+        let fragments = fragments.split_whitespace().chain(["\n"]).collect_vec();
+
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..10_000 {
+            let len = rng.gen_range(0..20);
+            let input = (0..len).map(|_| *fragments.choose(&mut rng).unwrap()).collect_vec();
+            fuzz(input.join(" ").as_bytes());
+        }
+    }
+
+    /// Tests that scripts are split at semicolons outside of literals and
+    /// quoted identifiers.
+    #[test]
+    fn split_statements() {
+        let script = "SELECT ';' AS \"a;b\";\n\n ;INSERT INTO t VALUES ('é');  \nSELECT 1";
+        assert_eq!(
+            super::split_statements(script).unwrap(),
+            vec!["SELECT ';' AS \"a;b\"", "INSERT INTO t VALUES ('é')", "SELECT 1"]
+        );
+        assert_eq!(super::split_statements(" ; ;").unwrap(), Vec::<String>::new());
+        assert!(super::split_statements("SELECT 'a").is_err());
+    }
+
+    /// Tests that positions count lines and characters.
+    #[test]
+    fn position() {
+        let mut parser = Parser::new("SELECT 1,\n  'é', FROM t");
+        let error = parser.parse().unwrap_err().to_string();
+        assert_eq!(error, "invalid input: expected expression atom, found FROM (line 2, column 8)");
+    }
+}
@@ -1,9 +1,16 @@
-use std::iter::Peekable;
+use std::collections::BTreeMap;
 
 use super::{Keyword, Lexer, Token, ast};
 use crate::errinput;
-use crate::error::Result;
-use crate::sql::types::DataType;
+use crate::error::{Error, Result};
+use crate::sql::types::{
+    Collation, Comparison, DataType, FileFormat, IndexType, Interval, Privilege, Timestamp,
+    TriggerEvent, TriggerTiming, format_ident,
+};
+use crate::storage::mvcc::IsolationLevel;
+
+/// The maximum expression nesting depth.
+const MAX_EXPRESSION_DEPTH: usize = 64;
 
 /// The SQL parser takes tokens from the lexer and parses the SQL syntax into an
 /// Abstract Syntax Tree (AST). This nested structure represents the syntactic
@@ -11,25 +18,107 @@ use crate::sql::types::DataType;
 /// arithmetic expressions, etc.). However, it only ensures the syntax is
 /// well-formed, and does not know whether e.g. a given table or column exists
 /// or which kind of join to use -- that is the job of the planner.
+///
+/// Syntax errors are returned as Error::InvalidInput, with the line and column
+/// of the offending token appended to the message.
 pub struct Parser<'a> {
-    pub lexer: Peekable<Lexer<'a>>,
+    pub lexer: Lexer<'a>,
+    /// The current expression nesting depth.
+    depth: usize,
 }
 
 impl Parser<'_> {
     /// Creates a new parser for the given raw SQL string.
     pub fn new(statement: &str) -> Parser {
-        Parser { lexer: Lexer::new(statement).peekable() }
+        Parser { lexer: Lexer::new(statement), depth: 0 }
     }
 
     /// Parses the input string into an AST statement. The whole string must be
     /// parsed as a single statement, ending with an optional semicolon.
     pub fn parse(&mut self) -> Result<ast::Statement> {
-        let statement = self.parse_statement()?;
+        self.with_position(|parser| {
+            let statement = parser.parse_statement()?;
+            parser.parse_end()?;
+            Ok(statement)
+        })
+    }
+
+    /// Parses the input string as a script of semicolon-separated statements,
+    /// in error recovery mode: if a statement has a syntax error, the error is
+    /// recorded and parsing resumes after the statement's semicolon. This
+    /// reports all syntax errors in the script, rather than just the first one.
+    /// Returns a result for each statement, in order. Empty statements are
+    /// skipped.
+    pub fn parse_statements(&mut self) -> Vec<Result<ast::Statement>> {
+        let mut results = Vec::new();
+        loop {
+            while self.next_is(Token::Semicolon) {}
+            if let Ok(None) = self.peek() {
+                return results;
+            }
+            let start = self.lexer.clone();
+            let result = self.with_position(|parser| {
+                let statement = parser.parse_statement()?;
+                if !parser.next_is(Token::Semicolon) {
+                    if let Some(token) = parser.peek()? {
+                        return errinput!("unexpected token {token}, expected ;");
+                    }
+                }
+                Ok(statement)
+            });
+            // On errors, rewind and skip the statement's tokens. Lexer errors
+            // skip the invalid character, so this will always make progress.
+            if result.is_err() {
+                self.lexer = start;
+                _ = self.lexer.by_ref().any(|token| matches!(token, Ok(Token::Semicolon)));
+            }
+            results.push(result);
+        }
+    }
+
+    /// Parses the input string into a trigger action (see CREATE TRIGGER):
+    /// either a SET clause, or an INSERT, UPDATE, or DELETE statement. Like
+    /// parse(), the whole string must be parsed.
+    pub fn parse_trigger_action(&mut self) -> Result<ast::TriggerAction> {
+        self.with_position(Self::parse_trigger_action_inner)
+    }
+
+    /// Parses a trigger action, without annotating errors with the position.
+    fn parse_trigger_action_inner(&mut self) -> Result<ast::TriggerAction> {
+        let action = match self.peek()? {
+            Some(Token::Keyword(Keyword::Set)) => {
+                self.expect(Keyword::Set.into())?;
+                ast::TriggerAction::Set(self.parse_set_clause()?)
+            }
+            Some(Token::Keyword(Keyword::Delete | Keyword::Insert | Keyword::Update)) => {
+                ast::TriggerAction::Statement(Box::new(self.parse_statement()?))
+            }
+            Some(token) => return errinput!("unexpected token {token}, expected trigger action"),
+            None => return errinput!("unexpected end of input"),
+        };
+        self.parse_end()?;
+        Ok(action)
+    }
+
+    /// Runs the given parse function, appending the position of the current
+    /// token to any syntax errors.
+    fn with_position<T>(&mut self, f: impl FnOnce(&mut Self) -> Result<T>) -> Result<T> {
+        let result = f(self);
+        result.map_err(|err| match err {
+            Error::InvalidInput(message) => {
+                Error::InvalidInput(format!("{message} ({})", self.lexer.position()))
+            }
+            err => err,
+        })
+    }
+
+    /// Parses the end of the input, with an optional semicolon.
+    fn parse_end(&mut self) -> Result<()> {
         self.next_is(Token::Semicolon);
         if let Some(token) = self.lexer.next().transpose()? {
-            return errinput!("unexpected token {token}");
+            return errinput!("unexpected token {token}, expected end of input");
         }
-        Ok(statement)
+        Ok(())
     }
 
     /// Fetches the next lexer token, or errors if none is found.
@@ -103,17 +192,31 @@ impl Parser<'_> {
             Token::Keyword(Keyword::Begin) => self.parse_begin(),
             Token::Keyword(Keyword::Commit) => self.parse_commit(),
             Token::Keyword(Keyword::Rollback) => self.parse_rollback(),
+            Token::Keyword(Keyword::Savepoint) => self.parse_savepoint(),
+            Token::Keyword(Keyword::Release) => self.parse_release(),
+            Token::Keyword(Keyword::Abort) => self.parse_abort(),
+            Token::Keyword(Keyword::Set) => self.parse_set(),
+            Token::Keyword(Keyword::Reset) => self.parse_reset(),
             Token::Keyword(Keyword::Explain) => self.parse_explain(),
 
-            Token::Keyword(Keyword::Create) => self.parse_create_table(),
-            Token::Keyword(Keyword::Drop) => self.parse_drop_table(),
+            Token::Keyword(Keyword::Create) => self.parse_create(),
+            Token::Keyword(Keyword::Drop) => self.parse_drop(),
+            Token::Keyword(Keyword::Alter) => self.parse_alter_table(),
+            Token::Keyword(Keyword::Describe) => self.parse_describe(),
+            Token::Keyword(Keyword::Show) => self.parse_show(),
+            Token::Keyword(Keyword::Grant) => self.parse_grant(),
+            Token::Keyword(Keyword::Revoke) => self.parse_revoke(),
 
             Token::Keyword(Keyword::Delete) => self.parse_delete(),
             Token::Keyword(Keyword::Insert) => self.parse_insert(),
+            Token::Keyword(Keyword::Import) => self.parse_import(),
+            Token::Ident(ident) if ident == "copy" => self.parse_copy(),
+            Token::Ident(ident) if ident == "export" => self.parse_export(),
+            Token::Keyword(Keyword::Truncate) => self.parse_truncate(),
             Token::Keyword(Keyword::Select) => self.parse_select(),
             Token::Keyword(Keyword::Update) => self.parse_update(),
 
-            token => errinput!("unexpected token {token}"),
+            token => errinput!("unexpected token {token}, expected statement"),
         }
     }
 
@@ -127,33 +230,128 @@ impl Parser<'_> {
             match self.next()? {
                 Token::Keyword(Keyword::Only) => read_only = true,
                 Token::Keyword(Keyword::Write) => {}
-                token => return errinput!("unexpected token {token}"),
+                token => return errinput!("unexpected token {token}, expected ONLY or WRITE"),
             }
         }
 
-        let mut as_of = None;
-        if self.next_is(Keyword::As.into()) {
-            self.expect(Keyword::Of.into())?;
-            self.expect(Keyword::System.into())?;
-            self.expect(Keyword::Time.into())?;
-            match self.next()? {
-                Token::Number(n) => as_of = Some(n.parse()?),
-                token => return errinput!("unexpected token {token}, wanted number"),
-            }
-        }
+        let as_of = self.parse_as_of_clause()?;
         Ok(ast::Statement::Begin { read_only, as_of })
     }
 
+    /// Parses an AS OF SYSTEM TIME clause, if present.
+    fn parse_as_of_clause(&mut self) -> Result<Option<ast::AsOf>> {
+        if !self.next_is(Keyword::As.into()) {
+            return Ok(None);
+        }
+        self.expect(Keyword::Of.into())?;
+        self.expect(Keyword::System.into())?;
+        self.expect(Keyword::Time.into())?;
+        match self.next()? {
+            Token::Number(n) => Ok(Some(ast::AsOf::Version(n.parse()?))),
+            Token::String(s) => Ok(Some(ast::AsOf::Timestamp(s.parse()?))),
+            token => errinput!("unexpected token {token}, wanted version or timestamp"),
+        }
+    }
+
+    /// Returns true if the next lexer tokens are AS OF, i.e. an AS OF SYSTEM
+    /// TIME clause rather than an alias.
+    fn peek_as_of(&self) -> bool {
+        let mut lexer = self.lexer.clone();
+        matches!(lexer.next(), Some(Ok(Token::Keyword(Keyword::As))))
+            && matches!(lexer.next(), Some(Ok(Token::Keyword(Keyword::Of))))
+    }
+
     /// Parses a COMMIT statement.
     fn parse_commit(&mut self) -> Result<ast::Statement> {
         self.expect(Keyword::Commit.into())?;
         Ok(ast::Statement::Commit)
     }
 
-    /// Parses a ROLLBACK statement.
+    /// Parses a ROLLBACK or ROLLBACK TO SAVEPOINT statement.
     fn parse_rollback(&mut self) -> Result<ast::Statement> {
         self.expect(Keyword::Rollback.into())?;
-        Ok(ast::Statement::Rollback)
+        if !self.next_is(Keyword::To.into()) {
+            return Ok(ast::Statement::Rollback);
+        }
+        self.skip(Keyword::Savepoint.into());
+        Ok(ast::Statement::RollbackToSavepoint(self.next_ident()?))
+    }
+
+    /// Parses a SAVEPOINT statement.
+    fn parse_savepoint(&mut self) -> Result<ast::Statement> {
+        self.expect(Keyword::Savepoint.into())?;
+        Ok(ast::Statement::Savepoint(self.next_ident()?))
+    }
+
+    /// Parses a RELEASE SAVEPOINT statement.
+    fn parse_release(&mut self) -> Result<ast::Statement> {
+        self.expect(Keyword::Release.into())?;
+        self.skip(Keyword::Savepoint.into());
+        Ok(ast::Statement::ReleaseSavepoint(self.next_ident()?))
+    }
+
+    /// Parses an ABORT TRANSACTION statement.
+    fn parse_abort(&mut self) -> Result<ast::Statement> {
+        self.expect(Keyword::Abort.into())?;
+        self.expect(Keyword::Transaction.into())?;
+        match self.next()? {
+            Token::Number(n) => Ok(ast::Statement::AbortTransaction(n.parse()?)),
+            token => errinput!("unexpected token {token}, wanted number"),
+        }
+    }
+
+    /// Parses a SET statement, either SET TRANSACTION ISOLATION LEVEL or SET
+    /// of a session variable.
+    fn parse_set(&mut self) -> Result<ast::Statement> {
+        self.expect(Keyword::Set.into())?;
+        if self.next_is(Keyword::Transaction.into()) {
+            return self.parse_set_transaction();
+        }
+        let name = self.next_ident()?;
+        match self.next()? {
+            Token::Equal | Token::Keyword(Keyword::To) => {}
+            token => return errinput!("unexpected token {token}, expected = or TO"),
+        }
+        // The value can be a string, number, identifier, or keyword (e.g.
+        // SERIALIZABLE), or DEFAULT to reset the variable.
+        let value = match self.next()? {
+            Token::Keyword(Keyword::Default) => None,
+            Token::String(string) => Some(string),
+            Token::Number(number) => Some(number),
+            Token::Minus => match self.next()? {
+                Token::Number(number) => Some(format!("-{number}")),
+                token => return errinput!("unexpected token {token}, wanted number"),
+            },
+            Token::Ident(ident) => Some(ident),
+            Token::Keyword(keyword) => Some(keyword.to_string().to_lowercase()),
+            token => return errinput!("unexpected token {token}, expected value"),
+        };
+        Ok(ast::Statement::Set { name, value })
+    }
+
+    /// Parses a RESET statement, which resets a session variable to its
+    /// default.
+    fn parse_reset(&mut self) -> Result<ast::Statement> {
+        self.expect(Keyword::Reset.into())?;
+        let name = self.next_ident()?;
+        Ok(ast::Statement::Set { name, value: None })
+    }
+
+    /// Parses a SET TRANSACTION ISOLATION LEVEL statement, after SET
+    /// TRANSACTION.
+    fn parse_set_transaction(&mut self) -> Result<ast::Statement> {
+        self.expect(Keyword::Isolation.into())?;
+        self.expect(Keyword::Level.into())?;
+        let isolation = match self.next()? {
+            Token::Keyword(Keyword::Read) => {
+                self.expect(Keyword::Committed.into())?;
+                IsolationLevel::ReadCommitted
+            }
+            Token::Keyword(Keyword::Snapshot) => IsolationLevel::Snapshot,
+            Token::Keyword(Keyword::Serializable) => IsolationLevel::Serializable,
+            token => return errinput!("unexpected token {token}, expected isolation level"),
+        };
+        Ok(ast::Statement::SetTransaction { isolation })
     }
 
     /// Parses an EXPLAIN statement.
@@ -165,10 +363,44 @@ impl Parser<'_> {
         Ok(ast::Statement::Explain(Box::new(self.parse_statement()?)))
     }
 
-    /// Parses a CREATE TABLE statement.
-    fn parse_create_table(&mut self) -> Result<ast::Statement> {
+    /// Parses a CREATE TABLE, CREATE INDEX, or CREATE TRIGGER statement.
+    fn parse_create(&mut self) -> Result<ast::Statement> {
         self.expect(Keyword::Create.into())?;
-        self.expect(Keyword::Table.into())?;
+        match self.next()? {
+            Token::Keyword(Keyword::Table) => self.parse_create_table(false),
+            Token::Keyword(Keyword::Temporary) => {
+                self.expect(Keyword::Table.into())?;
+                self.parse_create_table(true)
+            }
+            Token::Keyword(Keyword::Index) => self.parse_create_index(),
+            Token::Keyword(Keyword::Trigger) => self.parse_create_trigger(),
+            Token::Keyword(Keyword::Changefeed) => {
+                self.expect(Keyword::For.into())?;
+                self.expect(Keyword::Table.into())?;
+                Ok(ast::Statement::CreateChangefeed { table: self.next_ident()? })
+            }
+            Token::Keyword(Keyword::User) => self.parse_create_user(),
+            token => errinput!(
+                "unexpected token {token}, expected TABLE, TEMPORARY, INDEX, TRIGGER, CHANGEFEED, or USER"
+            ),
+        }
+    }
+
+    /// Parses a CREATE USER statement, after CREATE USER.
+    fn parse_create_user(&mut self) -> Result<ast::Statement> {
+        let name = self.next_ident()?;
+        self.skip(Keyword::With.into());
+        self.expect(Keyword::Password.into())?;
+        let password = match self.next()? {
+            Token::String(password) => password,
+            token => return errinput!("expected password string, got {token}"),
+        };
+        Ok(ast::Statement::CreateUser { name, password })
+    }
+
+    /// Parses a CREATE [TEMPORARY] TABLE statement, after CREATE [TEMPORARY]
+    /// TABLE.
+    fn parse_create_table(&mut self, temporary: bool) -> Result<ast::Statement> {
         let name = self.next_ident()?;
         self.expect(Token::OpenParen)?;
         let mut columns = Vec::new();
@@ -179,19 +411,99 @@ impl Parser<'_> {
             }
         }
         self.expect(Token::CloseParen)?;
-        Ok(ast::Statement::CreateTable { name, columns })
+        let partition_by = self.parse_partition_by()?;
+        let ttl = self.parse_create_table_with()?;
+        Ok(ast::Statement::CreateTable { name, columns, temporary, partition_by, ttl })
+    }
+
+    /// Parses a CREATE TABLE WITH clause, if present. The only supported
+    /// options are ttl and ttl_column, which must be given together.
+    fn parse_create_table_with(&mut self) -> Result<Option<ast::Ttl>> {
+        if !self.next_is(Keyword::With.into()) {
+            return Ok(None);
+        }
+        self.expect(Token::OpenParen)?;
+        let (mut interval, mut column) = (None, None);
+        loop {
+            let option = self.next_ident()?;
+            self.expect(Token::Equal)?;
+            match option.as_str() {
+                "ttl" if interval.is_none() => match self.next()? {
+                    Token::String(value) => interval = Some(value),
+                    token => return errinput!("expected interval string, got {token}"),
+                },
+                "ttl_column" if column.is_none() => column = Some(self.next_ident()?),
+                "ttl" | "ttl_column" => return errinput!("option {option} given multiple times"),
+                option => return errinput!("unknown table option {option}"),
+            }
+            if !self.next_is(Token::Comma) {
+                break;
+            }
+        }
+        self.expect(Token::CloseParen)?;
+        match (interval, column) {
+            (Some(interval), Some(column)) => Ok(Some(ast::Ttl { interval, column })),
+            (Some(_), None) => errinput!("ttl requires ttl_column"),
+            (None, _) => errinput!("ttl_column requires ttl"),
+        }
+    }
+
+    /// Parses a CREATE TABLE PARTITION BY RANGE clause, if present.
+    fn parse_partition_by(&mut self) -> Result<Option<ast::PartitionBy>> {
+        if !self.next_is(Keyword::Partition.into()) {
+            return Ok(None);
+        }
+        self.expect(Keyword::By.into())?;
+        self.expect(Keyword::Range.into())?;
+        self.expect(Token::OpenParen)?;
+        let column = self.next_ident()?;
+        self.expect(Token::CloseParen)?;
+        self.expect(Token::OpenParen)?;
+        let mut partitions = Vec::new();
+        loop {
+            self.expect(Keyword::Partition.into())?;
+            let name = self.next_ident()?;
+            self.expect(Keyword::Values.into())?;
+            self.expect(Keyword::Less.into())?;
+            self.expect(Keyword::Than.into())?;
+            let bound = match self.next_is(Keyword::Maxvalue.into()) {
+                true => None,
+                false => {
+                    self.expect(Token::OpenParen)?;
+                    let bound = self.parse_expression()?;
+                    self.expect(Token::CloseParen)?;
+                    Some(bound)
+                }
+            };
+            partitions.push((name, bound));
+            if !self.next_is(Token::Comma) {
+                break;
+            }
+        }
+        self.expect(Token::CloseParen)?;
+        Ok(Some(ast::PartitionBy { column, partitions }))
     }
 
     /// Parses a CREATE TABLE column definition.
     fn parse_create_table_column(&mut self) -> Result<ast::Column> {
         let name = self.next_ident()?;
-        let datatype = match self.next()? {
+        let mut datatype = match self.next()? {
             Token::Keyword(Keyword::Bool | Keyword::Boolean) => DataType::Boolean,
             Token::Keyword(Keyword::Float | Keyword::Double) => DataType::Float,
             Token::Keyword(Keyword::Int | Keyword::Integer) => DataType::Integer,
+            Token::Keyword(Keyword::Interval) => DataType::Interval,
+            Token::Keyword(Keyword::Timestamp) => DataType::Timestamp,
+            Token::Keyword(Keyword::Json) => DataType::Json,
             Token::Keyword(Keyword::String | Keyword::Text | Keyword::Varchar) => DataType::String,
-            token => return errinput!("unexpected token {token}"),
+            token => return errinput!("unexpected token {token}, expected data type"),
         };
+        if self.next_is(Token::OpenBracket) {
+            self.expect(Token::CloseBracket)?;
+            if self.next_is(Token::OpenBracket) {
+                return errinput!("nested arrays are not supported");
+            }
+            datatype = DataType::Array(Box::new(datatype));
+        }
         let mut column = ast::Column {
             name,
             datatype,
@@ -201,6 +513,8 @@ impl Parser<'_> {
             unique: false,
             index: false,
             references: None,
+            collation: Collation::Binary,
+            generated: None,
         };
         while let Some(keyword) = self.next_if_keyword() {
             match keyword {
@@ -225,31 +539,210 @@ impl Parser<'_> {
                 Keyword::Unique => column.unique = true,
                 Keyword::Index => column.index = true,
                 Keyword::References => column.references = Some(self.next_ident()?),
+                Keyword::Collate => column.collation = Collation::parse(&self.next_ident()?)?,
+                Keyword::Generated => {
+                    self.expect(Keyword::Always.into())?;
+                    self.expect(Keyword::As.into())?;
+                    self.expect(Token::OpenParen)?;
+                    column.generated = Some(self.parse_expression()?);
+                    self.expect(Token::CloseParen)?;
+                    self.expect(Keyword::Stored.into())?;
+                }
                 keyword => return errinput!("unexpected keyword {keyword}"),
             }
         }
         Ok(column)
     }
 
-    /// Parses a DROP TABLE statement.
-    fn parse_drop_table(&mut self) -> Result<ast::Statement> {
+    /// Parses a CREATE INDEX statement, after CREATE INDEX. Indexes are
+    /// identified by their column, so they don't have a name.
+    fn parse_create_index(&mut self) -> Result<ast::Statement> {
+        self.expect(Keyword::On.into())?;
+        let table = self.next_ident()?;
+        self.expect(Token::OpenParen)?;
+        let column = self.next_ident()?;
+        self.expect(Token::CloseParen)?;
+        let mut index_type = IndexType::BTree;
+        if self.next_is(Keyword::Using.into()) {
+            index_type = IndexType::parse(&self.next_ident()?)?;
+        }
+        let r#where = self.parse_where_clause()?;
+        Ok(ast::Statement::CreateIndex { table, column, index_type, r#where })
+    }
+
+    /// Parses a CREATE TRIGGER statement, after CREATE TRIGGER. The trigger
+    /// action is the rest of the statement. It's stored as a SQL string, which
+    /// is formatted from its tokens and parsed again to validate it.
+    fn parse_create_trigger(&mut self) -> Result<ast::Statement> {
+        let name = self.next_ident()?;
+        let timing = match self.next()? {
+            Token::Keyword(Keyword::Before) => TriggerTiming::Before,
+            Token::Keyword(Keyword::After) => TriggerTiming::After,
+            token => return errinput!("unexpected token {token}, expected BEFORE or AFTER"),
+        };
+        let event = match self.next()? {
+            Token::Keyword(Keyword::Insert) => TriggerEvent::Insert,
+            Token::Keyword(Keyword::Update) => TriggerEvent::Update,
+            Token::Keyword(Keyword::Delete) => TriggerEvent::Delete,
+            token => {
+                return errinput!("unexpected token {token}, expected INSERT, UPDATE, or DELETE");
+            }
+        };
+        self.expect(Keyword::On.into())?;
+        let table = self.next_ident()?;
+        let mut tokens = Vec::new();
+        while let Some(token) = self.next_if(|t| *t != Token::Semicolon) {
+            tokens.push(token);
+        }
+        let action = format_tokens(&tokens);
+        Parser::new(&action).parse_trigger_action_inner()?;
+        Ok(ast::Statement::CreateTrigger { name, table, timing, event, action })
+    }
+
+    /// Parses a DROP TABLE or DROP TRIGGER statement.
+    fn parse_drop(&mut self) -> Result<ast::Statement> {
         self.expect(Token::Keyword(Keyword::Drop))?;
-        self.expect(Token::Keyword(Keyword::Table))?;
+        let (trigger, user) = match self.next()? {
+            Token::Keyword(Keyword::Table) => (false, false),
+            Token::Keyword(Keyword::Trigger) => (true, false),
+            Token::Keyword(Keyword::User) => (false, true),
+            Token::Keyword(Keyword::Changefeed) => {
+                self.expect(Keyword::For.into())?;
+                self.expect(Keyword::Table.into())?;
+                return Ok(ast::Statement::DropChangefeed { table: self.next_ident()? });
+            }
+            token => {
+                return errinput!(
+                    "unexpected token {token}, expected TABLE, TRIGGER, USER, or CHANGEFEED"
+                );
+            }
+        };
         let mut if_exists = false;
         if self.next_is(Keyword::If.into()) {
             self.expect(Token::Keyword(Keyword::Exists))?;
             if_exists = true;
         }
         let name = self.next_ident()?;
+        if user {
+            return Ok(ast::Statement::DropUser { name, if_exists });
+        }
+        if trigger {
+            self.expect(Keyword::On.into())?;
+            let table = self.next_ident()?;
+            return Ok(ast::Statement::DropTrigger { name, table, if_exists });
+        }
         Ok(ast::Statement::DropTable { name, if_exists })
     }
 
+    /// Parses an ALTER TABLE statement.
+    fn parse_alter_table(&mut self) -> Result<ast::Statement> {
+        self.expect(Keyword::Alter.into())?;
+        self.expect(Keyword::Table.into())?;
+        let name = self.next_ident()?;
+        let operation = match self.next()? {
+            Token::Keyword(Keyword::Drop) => {
+                self.expect(Keyword::Partition.into())?;
+                ast::AlterTable::DropPartition(self.next_ident()?)
+            }
+            token => return errinput!("unexpected token {token}, expected DROP"),
+        };
+        Ok(ast::Statement::AlterTable { name, operation })
+    }
+
+    /// Parses a GRANT statement.
+    fn parse_grant(&mut self) -> Result<ast::Statement> {
+        self.expect(Keyword::Grant.into())?;
+        let (privileges, table) = self.parse_privileges()?;
+        self.expect(Keyword::To.into())?;
+        let user = self.next_ident()?;
+        Ok(ast::Statement::Grant { privileges, table, user })
+    }
+
+    /// Parses a REVOKE statement.
+    fn parse_revoke(&mut self) -> Result<ast::Statement> {
+        self.expect(Keyword::Revoke.into())?;
+        let (privileges, table) = self.parse_privileges()?;
+        self.expect(Keyword::From.into())?;
+        let user = self.next_ident()?;
+        Ok(ast::Statement::Revoke { privileges, table, user })
+    }
+
+    /// Parses the privileges and table of a GRANT or REVOKE statement, i.e.
+    /// privilege [, ...] ON { [TABLE] table | * }. Returns None for all tables.
+    fn parse_privileges(&mut self) -> Result<(Vec<Privilege>, Option<String>)> {
+        let privileges = if self.next_is(Keyword::All.into()) {
+            self.skip(Keyword::Privileges.into());
+            Privilege::ALL.to_vec()
+        } else {
+            let mut privileges = Vec::new();
+            loop {
+                privileges.push(match self.next()? {
+                    Token::Keyword(Keyword::Select) => Privilege::Select,
+                    Token::Keyword(Keyword::Insert) => Privilege::Insert,
+                    Token::Keyword(Keyword::Update) => Privilege::Update,
+                    Token::Keyword(Keyword::Delete) => Privilege::Delete,
+                    Token::Keyword(Keyword::Create) => Privilege::Create,
+                    Token::Keyword(Keyword::Alter) => Privilege::Alter,
+                    Token::Keyword(Keyword::Drop) => Privilege::Drop,
+                    token => return errinput!("expected privilege, got {token}"),
+                });
+                if !self.next_is(Token::Comma) {
+                    break;
+                }
+            }
+            privileges
+        };
+        self.expect(Keyword::On.into())?;
+        if self.next_is(Token::Asterisk) {
+            return Ok((privileges, None));
+        }
+        self.skip(Keyword::Table.into());
+        Ok((privileges, Some(self.next_ident()?)))
+    }
+
+    /// Parses a DESCRIBE statement.
+    fn parse_describe(&mut self) -> Result<ast::Statement> {
+        self.expect(Keyword::Describe.into())?;
+        let name = self.next_ident()?;
+        Ok(ast::Statement::Describe { name })
+    }
+
+    /// Parses a SHOW TABLES, SHOW CREATE TABLE, or SHOW of a session variable
+    /// (or ALL variables). TABLES is not a keyword, to allow it as an
+    /// identifier (e.g. information_schema.tables).
+    fn parse_show(&mut self) -> Result<ast::Statement> {
+        self.expect(Keyword::Show.into())?;
+        match self.next()? {
+            Token::Ident(ident) if ident == "tables" => Ok(ast::Statement::ShowTables),
+            Token::Keyword(Keyword::Create) => {
+                self.expect(Keyword::Table.into())?;
+                let name = self.next_ident()?;
+                Ok(ast::Statement::ShowCreateTable { name })
+            }
+            Token::Keyword(Keyword::All) => Ok(ast::Statement::Show { name: None }),
+            Token::Ident(name) => Ok(ast::Statement::Show { name: Some(name) }),
+            token => {
+                errinput!("unexpected token {token}, expected TABLES, CREATE, ALL, or variable")
+            }
+        }
+    }
+
     /// Parses a DELETE statement.
     fn parse_delete(&mut self) -> Result<ast::Statement> {
         self.expect(Keyword::Delete.into())?;
         self.expect(Keyword::From.into())?;
         let table = self.next_ident()?;
-        Ok(ast::Statement::Delete { table, r#where: self.parse_where_clause()? })
+        let r#where = self.parse_where_clause()?;
+        let returning = self.parse_returning_clause()?;
+        Ok(ast::Statement::Delete { table, r#where, returning })
+    }
+
+    /// Parses a TRUNCATE statement.
+    fn parse_truncate(&mut self) -> Result<ast::Statement> {
+        self.expect(Keyword::Truncate.into())?;
+        self.skip(Keyword::Table.into());
+        let name = self.next_ident()?;
+        Ok(ast::Statement::TruncateTable { name })
     }
 
     /// Parses an INSERT statement.
@@ -257,21 +750,155 @@ impl Parser<'_> {
         self.expect(Keyword::Insert.into())?;
         self.expect(Keyword::Into.into())?;
         let table = self.next_ident()?;
+        let columns = self.parse_column_list()?;
+        let values = self.parse_values()?;
+        let returning = self.parse_returning_clause()?;
+        Ok(ast::Statement::Insert { table, columns, values, returning })
+    }
 
-        let mut columns = None;
-        if self.next_is(Token::OpenParen) {
-            let columns = columns.insert(Vec::new());
-            loop {
-                columns.push(self.next_ident()?);
-                if !self.next_is(Token::Comma) {
-                    break;
-                }
+    /// Parses an IMPORT statement, from either VALUES or a file.
+    fn parse_import(&mut self) -> Result<ast::Statement> {
+        self.expect(Keyword::Import.into())?;
+        self.expect(Keyword::Into.into())?;
+        let table = self.next_ident()?;
+        let columns = self.parse_column_list()?;
+        let source = match self.next_is(Keyword::From.into()) {
+            true => self.parse_import_file()?,
+            false => ast::ImportSource::Values(self.parse_values()?),
+        };
+        Ok(ast::Statement::Import { table, columns, source })
+    }
+
+    /// Parses a COPY FROM or COPY TO statement, the Postgres equivalents of
+    /// IMPORT FROM and EXPORT. COPY TO takes a table (with optional column
+    /// list) or a parenthesized SELECT query. COPY is not a keyword, to allow
+    /// it as an identifier.
+    fn parse_copy(&mut self) -> Result<ast::Statement> {
+        self.expect(Token::Ident("copy".into()))?;
+        if let Some(Token::OpenParen) = self.peek()? {
+            let query = self.parse_export_query()?;
+            self.expect(Keyword::To.into())?;
+            let (path, format, header) = self.parse_export_file()?;
+            return Ok(ast::Statement::Export { query, path, format, header });
+        }
+        let table = self.next_ident()?;
+        let columns = self.parse_column_list()?;
+        if self.next_is(Keyword::To.into()) {
+            let select = match columns {
+                Some(columns) => columns
+                    .into_iter()
+                    .map(|column| (ast::Expression::Column(None, column), None))
+                    .collect(),
+                None => vec![(ast::Expression::All, None)],
+            };
+            let query = Box::new(ast::Statement::Select {
+                hints: Vec::new(),
+                select,
+                from: vec![ast::From::Table { name: table, alias: None }],
+                as_of: None,
+                r#where: None,
+                group_by: Vec::new(),
+                having: None,
+                order_by: Vec::new(),
+                offset: None,
+                limit: None,
+                for_update: false,
+            });
+            let (path, format, header) = self.parse_export_file()?;
+            return Ok(ast::Statement::Export { query, path, format, header });
+        }
+        self.expect(Keyword::From.into())?;
+        let source = self.parse_import_file()?;
+        Ok(ast::Statement::Import { table, columns, source })
+    }
+
+    /// Parses an EXPORT statement. EXPORT is not a keyword, to allow it as an
+    /// identifier.
+    fn parse_export(&mut self) -> Result<ast::Statement> {
+        self.expect(Token::Ident("export".into()))?;
+        self.expect(Keyword::To.into())?;
+        let (path, format, header) = self.parse_export_file()?;
+        let query = self.parse_export_query()?;
+        Ok(ast::Statement::Export { query, path, format, header })
+    }
+
+    /// Parses the parenthesized SELECT query of an EXPORT or COPY statement.
+    fn parse_export_query(&mut self) -> Result<Box<ast::Statement>> {
+        self.expect(Token::OpenParen)?;
+        match self.peek()? {
+            Some(Token::Keyword(Keyword::Select)) => {}
+            Some(token) => return errinput!("unexpected token {token}, expected SELECT query"),
+            None => return errinput!("unexpected end of input"),
+        }
+        let query = self.parse_select()?;
+        self.expect(Token::CloseParen)?;
+        Ok(Box::new(query))
+    }
+
+    /// Parses the file path of an EXPORT or COPY statement following TO, with
+    /// optional FORMAT and HEADER clauses.
+    fn parse_export_file(&mut self) -> Result<(String, FileFormat, bool)> {
+        let path = match self.next()? {
+            Token::String(path) => path,
+            token => return errinput!("unexpected token {token}, expected file path"),
+        };
+        let (format, header) = self.parse_file_options(Some(&path))?;
+        Ok((path, format, header))
+    }
+
+    /// Parses the file path or STDIN of an IMPORT or COPY statement following
+    /// FROM, with optional FORMAT and HEADER clauses. STDIN is not a keyword,
+    /// to allow it as an identifier.
+    fn parse_import_file(&mut self) -> Result<ast::ImportSource> {
+        let path = match self.next()? {
+            Token::String(path) => Some(path),
+            Token::Ident(ident) if ident == "stdin" => None,
+            token => return errinput!("unexpected token {token}, expected file path or STDIN"),
+        };
+        let (format, header) = self.parse_file_options(path.as_deref())?;
+        Ok(match path {
+            Some(path) => ast::ImportSource::File { path, format, header },
+            None => ast::ImportSource::Stdin { data: Vec::new(), format, header },
+        })
+    }
+
+    /// Parses optional FORMAT and HEADER clauses of an IMPORT, EXPORT, or COPY
+    /// file. The format defaults to the file extension (see
+    /// FileFormat::from_path), or CSV for STDIN. FORMAT, CSV, and HEADER are
+    /// not keywords, to allow them as identifiers.
+    fn parse_file_options(&mut self, path: Option<&str>) -> Result<(FileFormat, bool)> {
+        let mut format = path.map_or(FileFormat::Csv, FileFormat::from_path);
+        if self.next_is(Token::Ident("format".into())) {
+            format = match self.next()? {
+                Token::Ident(name) => FileFormat::parse(&name)?,
+                Token::Keyword(Keyword::Json) => FileFormat::Json,
+                token => return errinput!("unexpected token {token}, expected file format"),
+            };
+        }
+        let header = self.next_is(Token::Ident("header".into()));
+        Ok((format, header))
+    }
+
+    /// Parses an optional parenthesized column list of an INSERT or IMPORT
+    /// statement.
+    fn parse_column_list(&mut self) -> Result<Option<Vec<String>>> {
+        if !self.next_is(Token::OpenParen) {
+            return Ok(None);
+        }
+        let mut columns = Vec::new();
+        loop {
+            columns.push(self.next_ident()?);
+            if !self.next_is(Token::Comma) {
+                break;
             }
-            self.expect(Token::CloseParen)?;
         }
+        self.expect(Token::CloseParen)?;
+        Ok(Some(columns))
+    }
 
+    /// Parses the VALUES rows of an INSERT or IMPORT statement.
+    fn parse_values(&mut self) -> Result<Vec<Vec<ast::Expression>>> {
         self.expect(Keyword::Values.into())?;
-
         let mut values = Vec::new();
         loop {
             let mut row = Vec::new();
@@ -288,8 +915,7 @@ impl Parser<'_> {
                 break;
             }
         }
-
-        Ok(ast::Statement::Insert { table, columns, values })
+        Ok(values)
     }
 
     /// Parses an UPDATE statement.
@@ -297,7 +923,25 @@ impl Parser<'_> {
         self.expect(Keyword::Update.into())?;
         let table = self.next_ident()?;
         self.expect(Keyword::Set.into())?;
-        let mut set = std::collections::BTreeMap::new();
+        let set = self.parse_set_clause()?;
+        let r#where = self.parse_where_clause()?;
+        let returning = self.parse_returning_clause()?;
+        Ok(ast::Statement::Update { table, set, r#where, returning })
+    }
+
+    /// Parses a RETURNING clause of an INSERT, UPDATE, or DELETE statement, if
+    /// present. It takes the same expressions and aliases as a SELECT clause.
+    fn parse_returning_clause(&mut self) -> Result<Vec<(ast::Expression, Option<String>)>> {
+        if !self.next_is(Keyword::Returning.into()) {
+            return Ok(Vec::new());
+        }
+        self.parse_select_clause()
+    }
+
+    /// Parses the column assignments of an UPDATE or trigger SET clause, after
+    /// SET.
+    fn parse_set_clause(&mut self) -> Result<BTreeMap<String, Option<ast::Expression>>> {
+        let mut set = BTreeMap::new();
         loop {
             let column = self.next_ident()?;
             self.expect(Token::Equal)?;
@@ -312,14 +956,17 @@ impl Parser<'_> {
                 break;
             }
         }
-        Ok(ast::Statement::Update { table, set, r#where: self.parse_where_clause()? })
+        Ok(set)
     }
 
     /// Parses a SELECT statement.
     fn parse_select(&mut self) -> Result<ast::Statement> {
+        self.expect(Keyword::Select.into())?;
         Ok(ast::Statement::Select {
+            hints: self.parse_hints()?,
             select: self.parse_select_clause()?,
             from: self.parse_from_clause()?,
+            as_of: self.parse_as_of_clause()?,
             r#where: self.parse_where_clause()?,
             group_by: self.parse_group_by_clause()?,
             having: self.parse_having_clause()?,
@@ -332,14 +979,61 @@ impl Parser<'_> {
                 .next_is(Keyword::Offset.into())
                 .then(|| self.parse_expression())
                 .transpose()?,
+            for_update: self.parse_for_update_clause()?,
         })
     }
 
-    /// Parses a SELECT clause, if present.
-    fn parse_select_clause(&mut self) -> Result<Vec<(ast::Expression, Option<String>)>> {
-        if !self.next_is(Keyword::Select.into()) {
-            return Ok(Vec::new());
+    /// Parses a FOR UPDATE clause, if present.
+    fn parse_for_update_clause(&mut self) -> Result<bool> {
+        if !self.next_is(Keyword::For.into()) {
+            return Ok(false);
+        }
+        self.expect(Keyword::Update.into())?;
+        Ok(true)
+    }
+
+    /// Parses optimizer hints following SELECT, if present, e.g. /*+
+    /// HASH_JOIN(a b) INDEX(c id) */. Hint arguments are identifiers separated
+    /// by whitespace or commas.
+    fn parse_hints(&mut self) -> Result<Vec<ast::Hint>> {
+        let mut hints = Vec::new();
+        if !self.next_is(Token::OpenHint) {
+            return Ok(hints);
         }
+        while !self.next_is(Token::CloseHint) {
+            // INDEX is a keyword, the other hint names are identifiers.
+            let name = match self.next()? {
+                Token::Keyword(Keyword::Index) => "index".to_string(),
+                Token::Ident(name) => name,
+                token => return errinput!("expected hint, found {token}"),
+            };
+            self.expect(Token::OpenParen)?;
+            let mut args = Vec::new();
+            while !self.next_is(Token::CloseParen) {
+                args.push(self.next_ident()?);
+                self.skip(Token::Comma);
+            }
+            let hint = match (name.as_str(), args.len()) {
+                ("hash_join", 2..) => ast::Hint::HashJoin(args),
+                ("nested_loop_join", 2..) => ast::Hint::NestedLoopJoin(args),
+                ("index", 1..=2) => {
+                    let mut args = args.into_iter();
+                    let table = args.next().expect("no table");
+                    ast::Hint::Index { table, column: args.next() }
+                }
+                ("no_index", 1) => ast::Hint::NoIndex(args.remove(0)),
+                ("hash_join" | "nested_loop_join" | "index" | "no_index", _) => {
+                    return errinput!("invalid arguments for hint {}", name.to_uppercase());
+                }
+                (name, _) => return errinput!("unknown hint {}", name.to_uppercase()),
+            };
+            hints.push(hint);
+        }
+        Ok(hints)
+    }
+
+    /// Parses the SELECT clause expressions, following SELECT and any hints.
+    fn parse_select_clause(&mut self) -> Result<Vec<(ast::Expression, Option<String>)>> {
         let mut select = Vec::new();
         loop {
             let expr = self.parse_expression()?;
@@ -384,14 +1078,35 @@ impl Parser<'_> {
         Ok(from)
     }
 
-    // Parses a FROM table.
+    // Parses a FROM table, or an UNNEST table function. SYSTEM is a keyword
+    // (for AS OF SYSTEM TIME), but is also allowed as a schema name for the
+    // system virtual tables (e.g. system.table_statistics).
     fn parse_from_table(&mut self) -> Result<ast::From> {
-        let name = self.next_ident()?;
+        let mut name = match self.next_is(Keyword::System.into()) {
+            true if self.peek()? == Some(&Token::Period) => "system".to_string(),
+            true => return errinput!("expected identifier, got SYSTEM"),
+            false => self.next_ident()?,
+        };
+        let mut expression = None;
+        if self.next_is(Token::OpenParen) {
+            if name != "unnest" {
+                return errinput!("unknown table function {name}");
+            }
+            expression = Some(self.parse_expression()?);
+            self.expect(Token::CloseParen)?;
+        } else if self.next_is(Token::Period) {
+            name = format!("{name}.{}", self.next_ident()?);
+        }
         let mut alias = None;
-        if self.next_is(Keyword::As.into()) || matches!(self.peek()?, Some(Token::Ident(_))) {
+        if !self.peek_as_of()
+            && (self.next_is(Keyword::As.into()) || matches!(self.peek()?, Some(Token::Ident(_))))
+        {
             alias = Some(self.next_ident()?)
         };
-        Ok(ast::From::Table { name, alias })
+        match expression {
+            Some(expression) => Ok(ast::From::Unnest { expression, alias }),
+            None => Ok(ast::From::Table { name, alias }),
+        }
     }
 
     // Parses a FROM JOIN type, if present.
@@ -485,7 +1200,22 @@ impl Parser<'_> {
     }
 
     /// Parses an expression at the given minimum precedence.
+    ///
+    /// The nesting depth is limited, to avoid stack overflows on adversarial
+    /// input like deeply nested parentheses.
     fn parse_expression_at(&mut self, min_precedence: Precedence) -> Result<ast::Expression> {
+        if self.depth >= MAX_EXPRESSION_DEPTH {
+            return errinput!("expression exceeds maximum nesting depth {MAX_EXPRESSION_DEPTH}");
+        }
+        self.depth += 1;
+        let result = self.parse_expression_at_depth(min_precedence);
+        self.depth -= 1;
+        result
+    }
+
+    /// Parses an expression at the given minimum precedence, at the current
+    /// nesting depth.
+    fn parse_expression_at_depth(&mut self, min_precedence: Precedence) -> Result<ast::Expression> {
         // If there is a prefix operator, parse it and its right-hand operand.
         // Otherwise, parse the left-hand atom.
         let mut lhs = if let Some(prefix) = self.parse_prefix_operator(min_precedence) {
@@ -500,6 +1230,23 @@ impl Parser<'_> {
         }
         // Apply any binary infix operators, parsing the right-hand operand.
         while let Some(infix) = self.parse_infix_operator(min_precedence) {
+            // Comparisons can be applied to ANY or ALL array elements, e.g.
+            // a = ANY(b). The array must be parenthesized.
+            if let Some(comparison) = infix.comparison() {
+                if let Some(keyword) = self.next_if_map(|token| match token {
+                    Token::Keyword(keyword @ (Keyword::Any | Keyword::All)) => Some(*keyword),
+                    _ => None,
+                }) {
+                    self.expect(Token::OpenParen)?;
+                    let (lhs_, rhs) = (Box::new(lhs), Box::new(self.parse_expression()?));
+                    self.expect(Token::CloseParen)?;
+                    lhs = match keyword {
+                        Keyword::Any => ast::Operator::Any(comparison, lhs_, rhs).into(),
+                        _ => ast::Operator::All(comparison, lhs_, rhs).into(),
+                    };
+                    continue;
+                }
+            }
             let at_precedence = infix.precedence() + infix.associativity();
             let rhs = self.parse_expression_at(at_precedence)?;
             lhs = infix.build(lhs, rhs);
@@ -517,6 +1264,7 @@ impl Parser<'_> {
     /// * A literal value.
     /// * A column name.
     /// * A function call.
+    /// * An array constructor.
     /// * A parenthesized expression.
     fn parse_expression_atom(&mut self) -> Result<ast::Expression> {
         Ok(match self.next()? {
@@ -534,17 +1282,35 @@ impl Parser<'_> {
             Token::Keyword(Keyword::Infinity) => ast::Literal::Float(f64::INFINITY).into(),
             Token::Keyword(Keyword::NaN) => ast::Literal::Float(f64::NAN).into(),
             Token::Keyword(Keyword::Null) => ast::Literal::Null.into(),
-
-            // Function call.
+            Token::Keyword(Keyword::Interval) => match self.next()? {
+                Token::String(s) => ast::Literal::Interval(Interval::parse(&s)?).into(),
+                token => return errinput!("expected interval string, found {token}"),
+            },
+            Token::Keyword(Keyword::Timestamp) => match self.next()? {
+                Token::String(s) => ast::Literal::Timestamp(Timestamp::parse(&s)?).into(),
+                token => return errinput!("expected timestamp string, found {token}"),
+            },
+
+            // Function call. The JSON keyword is also the name of the json()
+            // function, which parses a string as JSON.
             Token::Ident(name) if self.next_is(Token::OpenParen) => {
-                let mut args = Vec::new();
-                while !self.next_is(Token::CloseParen) {
-                    if !args.is_empty() {
+                ast::Expression::Function(name, self.parse_function_args()?)
+            }
+            Token::Keyword(Keyword::Json) if self.next_is(Token::OpenParen) => {
+                ast::Expression::Function("json".to_string(), self.parse_function_args()?)
+            }
+
+            // Array constructor, e.g. ARRAY[1, 2, 3].
+            Token::Keyword(Keyword::Array) => {
+                self.expect(Token::OpenBracket)?;
+                let mut elements = Vec::new();
+                while !self.next_is(Token::CloseBracket) {
+                    if !elements.is_empty() {
                         self.expect(Token::Comma)?;
                     }
-                    args.push(self.parse_expression()?);
+                    elements.push(self.parse_expression()?);
                 }
-                ast::Expression::Function(name, args)
+                ast::Expression::Array(elements)
             }
 
             // Column name, either qualified as table.column or unqualified.
@@ -564,6 +1330,18 @@ impl Parser<'_> {
         })
     }
 
+    /// Parses function call arguments, after the opening parenthesis.
+    fn parse_function_args(&mut self) -> Result<Vec<ast::Expression>> {
+        let mut args = Vec::new();
+        while !self.next_is(Token::CloseParen) {
+            if !args.is_empty() {
+                self.expect(Token::Comma)?;
+            }
+            args.push(self.parse_expression()?);
+        }
+        Ok(args)
+    }
+
     /// Parses a prefix operator, if there is one and its precedence is at least
     /// min_precedence.
     fn parse_prefix_operator(&mut self, min_precedence: Precedence) -> Option<PrefixOperator> {
@@ -583,22 +1361,28 @@ impl Parser<'_> {
     fn parse_infix_operator(&mut self, min_precedence: Precedence) -> Option<InfixOperator> {
         self.next_if_map(|token| {
             let operator = match token {
+                Token::Arrow => InfixOperator::JsonExtract,
                 Token::Asterisk => InfixOperator::Multiply,
                 Token::Caret => InfixOperator::Exponentiate,
                 Token::Equal => InfixOperator::Equal,
                 Token::GreaterThan => InfixOperator::GreaterThan,
                 Token::GreaterThanOrEqual => InfixOperator::GreaterThanOrEqual,
                 Token::Keyword(Keyword::And) => InfixOperator::And,
+                Token::AtAt => InfixOperator::Match,
                 Token::Keyword(Keyword::Like) => InfixOperator::Like,
+                Token::Keyword(Keyword::Match) => InfixOperator::Match,
                 Token::Keyword(Keyword::Or) => InfixOperator::Or,
+                Token::Keyword(Keyword::Regexp) => InfixOperator::Regexp,
                 Token::LessOrGreaterThan => InfixOperator::NotEqual,
                 Token::LessThan => InfixOperator::LessThan,
                 Token::LessThanOrEqual => InfixOperator::LessThanOrEqual,
+                Token::LongArrow => InfixOperator::JsonExtractText,
                 Token::Minus => InfixOperator::Subtract,
                 Token::NotEqual => InfixOperator::NotEqual,
                 Token::Percent => InfixOperator::Remainder,
                 Token::Plus => InfixOperator::Add,
                 Token::Slash => InfixOperator::Divide,
+                Token::Tilde => InfixOperator::Regexp,
                 _ => return None,
             };
             Some(operator).filter(|op| op.precedence() >= min_precedence)
@@ -623,7 +1407,7 @@ impl Parser<'_> {
             let value = match self.next()? {
                 Token::Keyword(Keyword::NaN) => ast::Literal::Float(f64::NAN),
                 Token::Keyword(Keyword::Null) => ast::Literal::Null,
-                token => return errinput!("unexpected token {token}"),
+                token => return errinput!("unexpected token {token}, expected NAN or NULL"),
             };
             let operator = match not {
                 false => PostfixOperator::Is(value),
@@ -632,6 +1416,27 @@ impl Parser<'_> {
             return Ok(Some(operator));
         }
 
+        // Handle COLLATE separately, since it's followed by a collation name.
+        if let Some(Token::Keyword(Keyword::Collate)) = self.peek()? {
+            if PostfixOperator::Collate(Collation::Binary).precedence() < min_precedence {
+                return Ok(None);
+            }
+            self.expect(Keyword::Collate.into())?;
+            let collation = Collation::parse(&self.next_ident()?)?;
+            return Ok(Some(PostfixOperator::Collate(collation)));
+        }
+
+        // Handle array indexing separately, since it contains an expression.
+        if let Some(Token::OpenBracket) = self.peek()? {
+            if PostfixOperator::Index(ast::Literal::Null.into()).precedence() < min_precedence {
+                return Ok(None);
+            }
+            self.expect(Token::OpenBracket)?;
+            let index = self.parse_expression()?;
+            self.expect(Token::CloseBracket)?;
+            return Ok(Some(PostfixOperator::Index(index)));
+        }
+
         Ok(self.next_if_map(|token| {
             let operator = match token {
                 Token::Exclamation => PostfixOperator::Factorial,
@@ -642,6 +1447,32 @@ impl Parser<'_> {
     }
 }
 
+/// Formats tokens as a SQL string, such that it can be lexed into the same
+/// tokens again. Tokens are separated by spaces, except around periods and
+/// inside parentheses and brackets.
+fn format_tokens(tokens: &[Token]) -> String {
+    let mut sql = String::new();
+    let mut prev: Option<&Token> = None;
+    for token in tokens {
+        let glue =
+            matches!(prev, None | Some(Token::Period | Token::OpenParen | Token::OpenBracket))
+                || matches!(
+                    token,
+                    Token::Period | Token::Comma | Token::CloseParen | Token::CloseBracket
+                );
+        if !glue {
+            sql.push(' ');
+        }
+        match token {
+            Token::String(s) => sql.push_str(&format!("'{}'", s.replace('\'', "''"))),
+            Token::Ident(ident) => sql.push_str(&format_ident(ident)),
+            token => sql.push_str(&token.to_string()),
+        }
+        prev = Some(token);
+    }
+    sql
+}
+
 /// Operator precedence.
 type Precedence = u8;
 
@@ -660,7 +1491,7 @@ impl PrefixOperator {
     fn precedence(&self) -> Precedence {
         match self {
             Self::Not => 3,
-            Self::Minus | Self::Plus => 10,
+            Self::Minus | Self::Plus => 11,
         }
     }
 
@@ -690,12 +1521,16 @@ enum InfixOperator {
     Exponentiate,       // a ^ b
     GreaterThan,        // a > b
     GreaterThanOrEqual, // a >= b
+    JsonExtract,        // a -> b
+    JsonExtractText,    // a ->> b
     LessThan,           // a < b
     LessThanOrEqual,    // a <= b
     Like,               // a LIKE b
+    Match,              // a MATCH b or a @@ b
     Multiply,           // a * b
     NotEqual,           // a != b
     Or,                 // a OR b
+    Regexp,             // a ~ b or a REGEXP b
     Remainder,          // a % b
     Subtract,           // a - b
 }
@@ -703,21 +1538,23 @@ enum InfixOperator {
 impl InfixOperator {
     /// The operator precedence.
     ///
-    /// Mostly follows Postgres, except IS and LIKE having same precedence as =.
-    /// This is similar to SQLite and MySQL.
+    /// Mostly follows Postgres, except IS, LIKE and REGEXP having same
+    /// precedence as =. This is similar to SQLite and MySQL. Like Postgres, the JSON operators
+    /// bind tighter than comparisons but looser than arithmetic.
     fn precedence(&self) -> Precedence {
         match self {
             Self::Or => 1,
             Self::And => 2,
             // Self::Not => 3
-            Self::Equal | Self::NotEqual | Self::Like => 4, // and Self::Is
+            Self::Equal | Self::NotEqual | Self::Like | Self::Match | Self::Regexp => 4, // and Self::Is
             Self::GreaterThan
             | Self::GreaterThanOrEqual
             | Self::LessThan
             | Self::LessThanOrEqual => 5,
-            Self::Add | Self::Subtract => 6,
-            Self::Multiply | Self::Divide | Self::Remainder => 7,
-            Self::Exponentiate => 8,
+            Self::JsonExtract | Self::JsonExtractText => 6,
+            Self::Add | Self::Subtract => 7,
+            Self::Multiply | Self::Divide | Self::Remainder => 8,
+            Self::Exponentiate => 9,
         }
     }
 
@@ -729,6 +1566,19 @@ impl InfixOperator {
         }
     }
 
+    /// Returns the comparison for comparison operators, used with ANY or ALL.
+    fn comparison(&self) -> Option<Comparison> {
+        Some(match self {
+            Self::Equal => Comparison::Equal,
+            Self::GreaterThan => Comparison::GreaterThan,
+            Self::GreaterThanOrEqual => Comparison::GreaterThanOrEqual,
+            Self::LessThan => Comparison::LessThan,
+            Self::LessThanOrEqual => Comparison::LessThanOrEqual,
+            Self::NotEqual => Comparison::NotEqual,
+            _ => return None,
+        })
+    }
+
     /// Builds an AST expression for the infix operator.
     fn build(self, lhs: ast::Expression, rhs: ast::Expression) -> ast::Expression {
         let (lhs, rhs) = (Box::new(lhs), Box::new(rhs));
@@ -740,12 +1590,16 @@ impl InfixOperator {
             Self::Exponentiate => ast::Operator::Exponentiate(lhs, rhs).into(),
             Self::GreaterThan => ast::Operator::GreaterThan(lhs, rhs).into(),
             Self::GreaterThanOrEqual => ast::Operator::GreaterThanOrEqual(lhs, rhs).into(),
+            Self::JsonExtract => ast::Operator::JsonExtract(lhs, rhs).into(),
+            Self::JsonExtractText => ast::Operator::JsonExtractText(lhs, rhs).into(),
             Self::LessThan => ast::Operator::LessThan(lhs, rhs).into(),
             Self::LessThanOrEqual => ast::Operator::LessThanOrEqual(lhs, rhs).into(),
             Self::Like => ast::Operator::Like(lhs, rhs).into(),
+            Self::Match => ast::Operator::Match(lhs, rhs).into(),
             Self::Multiply => ast::Operator::Multiply(lhs, rhs).into(),
             Self::NotEqual => ast::Operator::NotEqual(lhs, rhs).into(),
             Self::Or => ast::Operator::Or(lhs, rhs).into(),
+            Self::Regexp => ast::Operator::Regexp(lhs, rhs).into(),
             Self::Remainder => ast::Operator::Remainder(lhs, rhs).into(),
             Self::Subtract => ast::Operator::Subtract(lhs, rhs).into(),
         }
@@ -754,9 +1608,11 @@ impl InfixOperator {
 
 /// Postfix operators.
 enum PostfixOperator {
-    Factorial,           // a!
-    Is(ast::Literal),    // a IS NULL | NAN
-    IsNot(ast::Literal), // a IS NOT NULL | NAN
+    Collate(Collation),     // a COLLATE NOCASE
+    Factorial,              // a!
+    Index(ast::Expression), // a[b]
+    Is(ast::Literal),       // a IS NULL | NAN
+    IsNot(ast::Literal),    // a IS NOT NULL | NAN
 }
 
 impl PostfixOperator {
@@ -764,7 +1620,8 @@ impl PostfixOperator {
     fn precedence(&self) -> Precedence {
         match self {
             Self::Is(_) | Self::IsNot(_) => 4,
-            Self::Factorial => 9,
+            Self::Factorial => 10,
+            Self::Index(_) | Self::Collate(_) => 12,
         }
     }
 
@@ -772,7 +1629,9 @@ impl PostfixOperator {
     fn build(self, lhs: ast::Expression) -> ast::Expression {
         let lhs = Box::new(lhs);
         match self {
+            Self::Collate(collation) => ast::Operator::Collate(lhs, collation).into(),
             Self::Factorial => ast::Operator::Factorial(lhs).into(),
+            Self::Index(index) => ast::Operator::Index(lhs, Box::new(index)).into(),
             Self::Is(v) => ast::Operator::Is(lhs, v).into(),
             Self::IsNot(v) => ast::Operator::Not(ast::Operator::Is(lhs, v).into()).into(),
         }
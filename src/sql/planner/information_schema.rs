@@ -0,0 +1,164 @@
+//! The information schema exposes the schema catalog as a set of virtual,
+//! read-only tables under the information_schema prefix, which can be queried
+//! like any other table, e.g. "SELECT * FROM information_schema.columns". The
+//! rows are generated from the catalog during planning, and thus reflect the
+//! transaction's snapshot of the catalog. The exception is the transactions
+//! table, which lists the currently active read-write transactions.
+
+use crate::error::Result;
+use crate::sql::engine::Catalog;
+use crate::sql::types::{Collation, Column, DataType, IndexState, IndexType, Row, Table, Value};
+
+/// The table name prefix for information schema tables.
+pub const PREFIX: &str = "information_schema.";
+
+/// Returns the virtual table schema for the given information schema table
+/// name (without the prefix), or None if it does not exist.
+pub fn get_table(name: &str) -> Option<Table> {
+    let columns = match name {
+        "tables" => vec![
+            column("table_name", DataType::String, false),
+            column("primary_key", DataType::String, false),
+        ],
+        "columns" => vec![
+            column("table_name", DataType::String, false),
+            column("column_name", DataType::String, false),
+            column("ordinal_position", DataType::Integer, false),
+            column("data_type", DataType::String, false),
+            column("is_nullable", DataType::Boolean, false),
+            column("column_default", DataType::String, true),
+            column("is_primary_key", DataType::Boolean, false),
+            column("is_unique", DataType::Boolean, false),
+            column("referenced_table", DataType::String, true),
+            column("collation_name", DataType::String, true),
+            column("generation_expression", DataType::String, true),
+        ],
+        "indexes" => vec![
+            column("table_name", DataType::String, false),
+            column("column_name", DataType::String, false),
+            column("is_unique", DataType::Boolean, false),
+            column("index_type", DataType::String, false),
+            column("predicate", DataType::String, true),
+            column("state", DataType::String, false),
+        ],
+        "transactions" => vec![
+            column("transaction_id", DataType::Integer, false),
+            column("age", DataType::Integer, false),
+            column("state", DataType::String, false),
+            column("writes", DataType::Integer, false),
+            column("locks", DataType::Integer, false),
+            column("waiting_for", DataType::Integer, true),
+        ],
+        _ => return None,
+    };
+    Some(virtual_table(name, columns))
+}
+
+/// Creates a virtual table schema with the given name and columns.
+pub(super) fn virtual_table(name: &str, columns: Vec<Column>) -> Table {
+    // The virtual tables don't have a primary key, but the schema requires
+    // one. Just use the first column, it isn't used for anything.
+    Table {
+        name: name.to_string(),
+        primary_key: 0,
+        columns,
+        partitions: Vec::new(),
+        triggers: Vec::new(),
+        changefeed: false,
+        ttl: None,
+    }
+}
+
+/// Generates the rows of the given information schema table from the catalog.
+pub fn scan(table: &Table, catalog: &impl Catalog) -> Result<Vec<Row>> {
+    let mut rows = Vec::new();
+    match table.name.as_str() {
+        "tables" => {
+            for table in catalog.list_tables()? {
+                let primary_key = table.columns[table.primary_key].name.clone();
+                rows.push(vec![Value::String(table.name), Value::String(primary_key)]);
+            }
+        }
+        "columns" => {
+            for table in catalog.list_tables()? {
+                for (i, column) in table.columns.iter().enumerate() {
+                    rows.push(vec![
+                        Value::String(table.name.clone()),
+                        Value::String(column.name.clone()),
+                        Value::Integer(i as i64 + 1),
+                        Value::String(column.datatype.to_string()),
+                        Value::Boolean(column.nullable),
+                        column
+                            .default
+                            .as_ref()
+                            .map(|v| v.to_string().into())
+                            .unwrap_or(Value::Null),
+                        Value::Boolean(i == table.primary_key),
+                        Value::Boolean(column.unique),
+                        column.references.clone().map(Value::String).unwrap_or(Value::Null),
+                        match column.datatype {
+                            DataType::String => Value::String(column.collation.to_string()),
+                            _ => Value::Null,
+                        },
+                        column
+                            .generated
+                            .as_ref()
+                            .map(|e| table.format_expression(e).into())
+                            .unwrap_or(Value::Null),
+                    ]);
+                }
+            }
+        }
+        "indexes" => {
+            for table in catalog.list_tables()? {
+                for column in table.columns.iter().filter(|c| c.index) {
+                    rows.push(vec![
+                        Value::String(table.name.clone()),
+                        Value::String(column.name.clone()),
+                        Value::Boolean(column.unique),
+                        Value::String(column.index_type.to_string()),
+                        column
+                            .index_predicate
+                            .as_ref()
+                            .map(|e| table.format_expression(e).into())
+                            .unwrap_or(Value::Null),
+                        Value::String(column.index_state.to_string()),
+                    ]);
+                }
+            }
+        }
+        "transactions" => {
+            for txn in catalog.list_transactions()? {
+                let state = if txn.waiting_for.is_some() { "waiting" } else { "active" };
+                rows.push(vec![
+                    Value::Integer(txn.version as i64),
+                    Value::Integer(txn.age as i64),
+                    Value::String(state.to_string()),
+                    Value::Integer(txn.writes as i64),
+                    Value::Integer(txn.locks as i64),
+                    txn.waiting_for.map(|v| Value::Integer(v as i64)).unwrap_or(Value::Null),
+                ]);
+            }
+        }
+        name => panic!("unknown information schema table {name}"),
+    }
+    Ok(rows)
+}
+
+/// Creates a virtual table column.
+pub(super) fn column(name: &str, datatype: DataType, nullable: bool) -> Column {
+    Column {
+        name: name.to_string(),
+        datatype,
+        nullable,
+        default: nullable.then_some(Value::Null),
+        unique: false,
+        index: false,
+        index_type: IndexType::BTree,
+        index_predicate: None,
+        index_state: IndexState::Public,
+        references: None,
+        collation: Collation::Binary,
+        generated: None,
+    }
+}
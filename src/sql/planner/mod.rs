@@ -1,11 +1,14 @@
 //! The planner builds and optimizes an execution plan based on a SQL
 //! statement's Abstract Syntax Tree (AST) generated by the parser.
 
+mod information_schema;
 mod optimizer;
 mod plan;
 mod planner;
+mod system;
 
 #[cfg(test)]
 pub use optimizer::OPTIMIZERS;
-pub use plan::{Aggregate, Direction, Node, Plan};
-pub use planner::{Planner, Scope};
+pub use optimizer::Options as OptimizerOptions;
+pub use plan::{Aggregate, Direction, ImportInput, Node, Plan};
+pub use planner::{Planner, Scope, TriggerRows};
@@ -1,25 +1,90 @@
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
+use std::ops::Bound;
+use std::sync::Arc;
 
-use super::Node;
+use itertools::Itertools as _;
+
+use super::{Aggregate, Node};
 use crate::error::Result;
-use crate::sql::types::{Expression, Label, Value};
+use crate::sql::engine::{Statistics, TableEstimates};
+use crate::sql::parser::ast::Hint;
+use crate::sql::types::{Expression, IndexState, IndexType, Label, Table, Value, tokenize};
 
 /// A plan optimizer, which recursively transforms a plan node to make plan
 /// execution more efficient where possible.
-pub type Optimizer = fn(Node) -> Result<Node>;
+pub type Optimizer = fn(Node, &Options) -> Result<Node>;
+
+/// Optimizer options, from session settings and query hints. These allow users
+/// to work around bad plans, by disabling or forcing specific plan nodes. They
+/// also carry the table statistics used for row estimates.
+#[derive(Clone, Debug)]
+pub struct Options {
+    /// Use hash joins for equijoins (the enable_hash_join session setting).
+    pub hash_join: bool,
+    /// Use primary key and index lookups (the enable_index_lookup session
+    /// setting).
+    pub index_lookup: bool,
+    /// Query hints, which take precedence over the settings.
+    pub hints: Vec<Hint>,
+    /// Table statistics, used for row estimates if available.
+    pub statistics: Option<Arc<Statistics>>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self { hash_join: true, index_lookup: true, hints: Vec::new(), statistics: None }
+    }
+}
+
+impl Options {
+    /// Returns true if a hash join should be used to join the given sources,
+    /// if possible. The first join hint naming exactly the joined tables
+    /// applies.
+    fn use_hash_join(&self, left: &Node, right: &Node) -> bool {
+        let mut tables = BTreeSet::new();
+        tables.extend(left.tables());
+        tables.extend(right.tables());
+        let matches =
+            |hint: &[String]| hint.iter().map(String::as_str).collect::<BTreeSet<_>>() == tables;
+        self.hints
+            .iter()
+            .find_map(|hint| match hint {
+                Hint::HashJoin(hint) if matches(hint) => Some(true),
+                Hint::NestedLoopJoin(hint) if matches(hint) => Some(false),
+                _ => None,
+            })
+            .unwrap_or(self.hash_join)
+    }
+
+    /// Returns whether a lookup should be used for the given table, if
+    /// possible, and the column to prefer if any. The first index hint for the
+    /// table applies.
+    fn use_index_lookup(&self, table: &str) -> (bool, Option<&str>) {
+        self.hints
+            .iter()
+            .find_map(|hint| match hint {
+                Hint::Index { table: t, column } if t == table => Some((true, column.as_deref())),
+                Hint::NoIndex(t) if t == table => Some((false, None)),
+                _ => None,
+            })
+            .unwrap_or((self.index_lookup, None))
+    }
+}
 
 /// The set of optimizers, and the order in which they are applied.
 pub static OPTIMIZERS: &[(&str, Optimizer)] = &[
     ("Constant folding", fold_constants),
     ("Filter pushdown", push_filters),
     ("Index lookup", index_lookup),
+    ("Partition pruning", prune_partitions),
+    ("Join order", join_order),
     ("Join type", join_type),
     ("Short circuit", short_circuit),
 ];
 
 /// Folds constant (sub)expressions by pre-evaluating them, instead of
 /// re-evaluating then for every row during execution.
-pub fn fold_constants(node: Node) -> Result<Node> {
+pub fn fold_constants(node: Node, _: &Options) -> Result<Node> {
     use Expression::*;
     use Value::*;
 
@@ -70,7 +135,7 @@ pub fn fold_constants(node: Node) -> Result<Node> {
 /// Pushes filter predicates down into child nodes where possible. In
 /// particular, this can allow filtering during storage scans (below Raft),
 /// instead of reading and transmitting all rows then filtering.
-pub fn push_filters(node: Node) -> Result<Node> {
+pub fn push_filters(node: Node, _: &Options) -> Result<Node> {
     /// Pushes an expression into a node if possible. Otherwise, returns the the
     /// unpushed expression.
     fn push_into(expr: Expression, target: &mut Node) -> Option<Expression> {
@@ -161,12 +226,12 @@ pub fn push_filters(node: Node) -> Result<Node> {
         let left_lookups: HashMap<usize, usize> = push_left // column → push_left index
             .iter()
             .enumerate()
-            .filter_map(|(i, expr)| expr.is_column_lookup().map(|column| (column, i)))
+            .filter_map(|(i, expr)| expr.is_column_lookup().map(|(column, _)| (column, i)))
             .collect();
         let right_lookups: HashMap<usize, usize> = push_right // column → push_right index
             .iter()
             .enumerate()
-            .filter_map(|(i, expr)| expr.is_column_lookup().map(|column| (column, i)))
+            .filter_map(|(i, expr)| expr.is_column_lookup().map(|(column, _)| (column, i)))
             .collect();
 
         for expr in &predicate {
@@ -221,30 +286,96 @@ pub fn push_filters(node: Node) -> Result<Node> {
     node.transform(&|node| Ok(xform(node)), &Ok)
 }
 
-/// Uses an index or primary key lookup for a filter when possible.
-pub fn index_lookup(node: Node) -> Result<Node> {
+/// Uses an index or primary key lookup for a filter when possible, unless
+/// disabled by the options. An INDEX hint can prefer a specific column.
+pub fn index_lookup(node: Node, options: &Options) -> Result<Node> {
     let transform = |mut node| {
         // Only handle scan filters. filter_pushdown() must have pushed filters
         // into scan nodes first.
-        let Node::Scan { table, alias, filter: Some(filter) } = node else {
+        let Node::Scan { table, alias, filter: Some(filter), partitions } = node else {
             return node;
         };
+        let (enabled, hint) = options.use_index_lookup(alias.as_ref().unwrap_or(&table.name));
+        if !enabled {
+            return Node::Scan { table, alias, filter: Some(filter), partitions };
+        }
 
         // Convert the filter into conjunctive normal form (a list of ANDs).
         let mut cnf = filter.clone().into_cnf_vec();
 
         // Find the first expression that's either a primary key or secondary
-        // index lookup. We could be more clever here, but this is fine.
-        let Some((i, column)) = cnf.iter().enumerate().find_map(|(i, expr)| {
-            expr.is_column_lookup()
-                .filter(|c| *c == table.primary_key || table.columns[*c].index)
-                .map(|column| (i, column))
-        }) else {
-            return Node::Scan { table, alias, filter: Some(filter) };
+        // index lookup, preferring the hinted column if any. We could be more
+        // clever here, but this is fine. The lookup must use the column's
+        // collation, which the index is keyed by. Partial indexes can only be
+        // used if the filter implies the index predicate, i.e. if all matching
+        // rows are guaranteed to be indexed.
+        let implies = |predicate: &Expression| {
+            predicate.clone().into_cnf_vec().iter().all(|p| cnf.iter().any(|e| e.implies(p)))
+        };
+        let is_indexed = |c: usize, index_type: IndexType| {
+            let column = &table.columns[c];
+            column.index
+                && column.index_state == IndexState::Public
+                && column.index_type == index_type
+                && column.index_predicate.as_ref().is_none_or(implies)
+        };
+        let is_hinted = |c: usize| hint.is_none_or(|name| table.columns[c].name == name);
+        let find_lookup = |hinted: bool| {
+            cnf.iter().enumerate().find_map(|(i, expr)| {
+                expr.is_column_lookup()
+                    .filter(|(c, collation)| *collation == table.columns[*c].collation)
+                    .filter(|(c, _)| {
+                        *c == table.primary_key
+                            || is_indexed(*c, IndexType::BTree)
+                            || is_indexed(*c, IndexType::Hash)
+                    })
+                    .filter(|(c, _)| !hinted || is_hinted(*c))
+                    .map(|(column, _)| (i, column))
+            })
+        };
+        // Otherwise, look for a MATCH query on a full-text index. The index
+        // lookup returns rows containing one of the query words, so the MATCH
+        // is kept as a filter. The longest word is used, since it's likely the
+        // most selective. An empty query matches all rows.
+        let find_match = |hinted: bool| {
+            cnf.iter().find_map(|expr| {
+                let Expression::Match(lhs, rhs) = expr else { return None };
+                let (Expression::Column(c), Expression::Constant(Value::String(query))) =
+                    (lhs.as_ref(), rhs.as_ref())
+                else {
+                    return None;
+                };
+                if hinted && !is_hinted(*c) {
+                    return None;
+                }
+                let word = tokenize(query).into_iter().rev().max_by_key(|w| w.chars().count())?;
+                is_indexed(*c, IndexType::FullText).then_some((*c, word))
+            })
+        };
+
+        // If the hinted column can't be used, ignore the hint.
+        let (lookup, fulltext) = match (find_lookup(true), find_match(true)) {
+            (None, None) => (find_lookup(false), find_match(false)),
+            found => found,
+        };
+        let Some((i, column)) = lookup else {
+            let Some((column, word)) = fulltext else {
+                return Node::Scan { table, alias, filter: Some(filter), partitions };
+            };
+            let values = vec![Value::String(word)];
+            node = Node::IndexLookup { table, column, values, alias };
+            return Node::Filter { source: Box::new(node), predicate: filter };
         };
 
-        // Extract the lookup values and expression from the cnf vector.
-        let values = cnf.remove(i).into_column_values(column);
+        // Extract the lookup values and expression from the cnf vector. Hash
+        // index lookups may return rows whose values have the same hash, so
+        // the expression is kept as a filter.
+        let values = match column != table.primary_key
+            && table.columns[column].index_type == IndexType::Hash
+        {
+            true => cnf[i].clone().into_column_values(column),
+            false => cnf.remove(i).into_column_values(column),
+        };
 
         // Build the primary key or secondary index lookup node.
         if column == table.primary_key {
@@ -263,15 +394,312 @@ pub fn index_lookup(node: Node) -> Result<Node> {
     node.transform(&Ok, &|n| Ok(transform(n)))
 }
 
-/// Uses a hash join instead of a nested loop join for single-column equijoins.
-pub fn join_type(node: Node) -> Result<Node> {
+/// Prunes the partitions of partitioned table scans, such that only partitions
+/// that may contain rows matching the scan filter are scanned. Only primary key
+/// comparisons with constants are used, combined via AND and OR. Must run after
+/// filter pushdown, and after index lookups which may remove the scan.
+pub fn prune_partitions(node: Node, _: &Options) -> Result<Node> {
+    /// Returns the partitions that may contain rows matching the expression,
+    /// or None if all partitions may.
+    fn matching(table: &Table, expr: &Expression) -> Option<BTreeSet<usize>> {
+        use Expression::*;
+        let range = match expr {
+            And(lhs, rhs) => {
+                return match (matching(table, lhs), matching(table, rhs)) {
+                    (Some(lhs), Some(rhs)) => Some(lhs.intersection(&rhs).copied().collect()),
+                    (Some(partitions), None) | (None, Some(partitions)) => Some(partitions),
+                    (None, None) => None,
+                };
+            }
+            Or(lhs, rhs) => {
+                let mut partitions = matching(table, lhs)?;
+                partitions.extend(matching(table, rhs)?);
+                return Some(partitions);
+            }
+            // Normalize comparisons to column/constant order.
+            Equal(lhs, rhs) | GreaterThan(lhs, rhs) | LessThan(lhs, rhs) => {
+                let (column, value, flipped) = match (lhs.as_ref(), rhs.as_ref()) {
+                    (Column(column), Constant(value)) => (*column, value, false),
+                    (Constant(value), Column(column)) => (*column, value, true),
+                    _ => return None,
+                };
+                let pk = &table.columns[table.primary_key];
+                if column != table.primary_key
+                    || value.is_undefined()
+                    || value.datatype().as_ref() != Some(&pk.datatype)
+                {
+                    return None;
+                }
+                let value = value.normalize_ref().into_owned();
+                match (expr, flipped) {
+                    (Equal(..), _) => (Bound::Included(value.clone()), Bound::Included(value)),
+                    (GreaterThan(..), false) | (LessThan(..), true) => {
+                        (Bound::Excluded(value), Bound::Unbounded)
+                    }
+                    (LessThan(..), false) | (GreaterThan(..), true) => {
+                        (Bound::Unbounded, Bound::Excluded(value))
+                    }
+                    _ => return None,
+                }
+            }
+            _ => return None,
+        };
+
+        // A partition may match if the ranges overlap. This is conservative
+        // for excluded bounds of discrete types, e.g. id > 9 AND id < 10.
+        let overlaps = |(start, end): (Bound<Value>, Bound<Value>)| {
+            let start_ok = match (&range.0, &end) {
+                (Bound::Unbounded, _) | (_, Bound::Unbounded) => true,
+                (Bound::Included(s) | Bound::Excluded(s), Bound::Excluded(e)) => s < e,
+                (_, Bound::Included(_)) => unreachable!("partition end bound is excluded"),
+            };
+            let end_ok = match (&start, &range.1) {
+                (Bound::Unbounded, _) | (_, Bound::Unbounded) => true,
+                (Bound::Included(s), Bound::Included(e)) => s <= e,
+                (Bound::Included(s), Bound::Excluded(e)) => s < e,
+                (Bound::Excluded(_), _) => unreachable!("partition start bound is included"),
+            };
+            start_ok && end_ok
+        };
+        Some((0..table.partitions.len()).filter(|i| overlaps(table.partition_range(*i))).collect())
+    }
+
+    let xform = |node| {
+        let Node::Scan { table, alias, filter: Some(filter), partitions: None } = node else {
+            return node;
+        };
+        let partitions = match table.partitions.is_empty() {
+            true => None,
+            false => matching(&table, &filter)
+                .filter(|partitions| partitions.len() < table.partitions.len())
+                .map(|partitions| partitions.into_iter().collect()),
+        };
+        Node::Scan { table, alias, filter: Some(filter), partitions }
+    };
+    node.transform(&Ok, &|node| Ok(xform(node)))
+}
+
+/// Reorders multi-way inner joins, such that small sources are joined first to
+/// reduce the size of intermediate results. Joins are otherwise executed in
+/// the order they're written in the query, which can be very expensive.
+///
+/// Uses a greedy algorithm: start with the source with the fewest (estimated)
+/// rows, then repeatedly join the smallest remaining source that shares a join
+/// predicate with the sources joined so far, falling back to the smallest
+/// remaining source if none do (i.e. a cross join). Each predicate is placed in
+/// the lowest join where all of its columns are available, and a projection
+/// restores the original column order.
+///
+/// Only chains of 3 or more inner joins are reordered. Outer joins are kept
+/// in place, since their order matters. Must run after filter pushdown and
+/// index lookups (which affect row estimates) and before the join type
+/// optimizer, which converts nested loop joins into hash joins.
+pub fn join_order(node: Node, options: &Options) -> Result<Node> {
+    /// Flattens an inner join tree into its sources and the conjunctive parts
+    /// of its join predicates, with column indexes in the joined row.
+    fn flatten(
+        node: Node,
+        offset: usize,
+        sources: &mut Vec<Node>,
+        predicates: &mut Vec<Expression>,
+    ) {
+        match node {
+            Node::NestedLoopJoin { left, right, predicate, outer: false } => {
+                let left_columns = left.columns();
+                flatten(*left, offset, sources, predicates);
+                flatten(*right, offset + left_columns, sources, predicates);
+                if let Some(predicate) = predicate {
+                    let cnf = predicate.into_cnf_vec().into_iter();
+                    predicates.extend(cnf.map(|expr| expr.shift_column(offset as isize)));
+                }
+            }
+            node => sources.push(node),
+        }
+    }
+
+    let xform = |node: Node| -> Result<Node> {
+        let Node::NestedLoopJoin { outer: false, .. } = node else {
+            return Ok(node);
+        };
+        let (mut sources, mut predicates) = (Vec::new(), Vec::new());
+        flatten(node.clone(), 0, &mut sources, &mut predicates);
+        if sources.len() < 3 {
+            return Ok(node);
+        }
+
+        // Find the first column of each source in the joined row, and the
+        // sources referenced by each predicate.
+        let starts = sources
+            .iter()
+            .scan(0, |start, source| {
+                let current = *start;
+                *start += source.columns();
+                Some(current)
+            })
+            .collect_vec();
+        // Sources without columns share a start with the next source. Use the
+        // last matching source, since it must be the one with columns.
+        let source_of = |column: usize| starts.iter().rposition(|start| *start <= column).unwrap();
+        let references = predicates
+            .iter()
+            .map(|expr| {
+                let mut sources = BTreeSet::new();
+                expr.walk(&mut |expr| {
+                    if let Expression::Column(index) = expr {
+                        sources.insert(source_of(*index));
+                    }
+                    true
+                });
+                sources
+            })
+            .collect_vec();
+
+        // Pick the join order greedily. Ties retain the original order.
+        let estimates = sources.iter().map(|source| estimate_rows(source, options)).collect_vec();
+        let mut order: Vec<usize> = Vec::with_capacity(sources.len());
+        while order.len() < sources.len() {
+            let remaining = (0..sources.len()).filter(|i| !order.contains(i)).collect_vec();
+            let connected = remaining.iter().copied().filter(|i| {
+                references.iter().any(|r| r.contains(i) && r.iter().any(|j| order.contains(j)))
+            });
+            let smallest = |a: &usize, b: &usize| estimates[*a].total_cmp(&estimates[*b]);
+            let next = connected.min_by(smallest).or(remaining.into_iter().min_by(smallest));
+            order.push(next.unwrap());
+        }
+        if order.iter().enumerate().all(|(i, source)| i == *source) {
+            return Ok(node);
+        }
+
+        // Map the original column indexes to the new joined row.
+        let mut new_starts = vec![0; sources.len()];
+        let mut start = 0;
+        for &source in &order {
+            new_starts[source] = start;
+            start += sources[source].columns();
+        }
+        let map_column = |column: usize| {
+            let source = source_of(column);
+            new_starts[source] + column - starts[source]
+        };
+        let remap = |expr: Expression| {
+            expr.transform(
+                &|expr| match expr {
+                    Expression::Column(index) => Ok(Expression::Column(map_column(index))),
+                    expr => Ok(expr),
+                },
+                &Ok,
+            )
+        };
+
+        // Build a left-deep join tree in the new order, placing predicates in
+        // the lowest join that has all of their columns.
+        let columns = start;
+        let mut sources = sources.into_iter().map(Some).collect_vec();
+        let mut predicates = predicates.into_iter().map(Some).collect_vec();
+        let mut node = sources[order[0]].take().unwrap();
+        for (i, &source) in order.iter().enumerate().skip(1) {
+            let joined = &order[..=i];
+            let mut predicate = Vec::new();
+            for (j, references) in references.iter().enumerate() {
+                if !references.iter().all(|source| joined.contains(source)) {
+                    continue;
+                }
+                if let Some(expr) = predicates[j].take() {
+                    predicate.push(remap(expr)?);
+                }
+            }
+            let right = sources[source].take().unwrap();
+            let predicate = Expression::and_vec(predicate);
+            node = Node::NestedLoopJoin {
+                left: node.into(),
+                right: right.into(),
+                predicate,
+                outer: false,
+            };
+        }
+
+        // Restore the original column order.
+        let expressions = (0..columns).map(|i| Expression::Column(map_column(i))).collect();
+        let aliases = vec![Label::None; columns];
+        Ok(Node::Projection { source: node.into(), expressions, aliases })
+    };
+
+    // Reorder before descending. The greedy order of a join chain's prefix is
+    // the same as for the full chain, so the lower joins are left unchanged.
+    node.transform(&xform, &Ok)
+}
+
+/// Estimates the number of rows emitted by a node, for join ordering. Table
+/// and index lookup sizes are estimated from table statistics where available
+/// (see TableEstimates), otherwise this uses simple heuristics.
+fn estimate_rows(node: &Node, options: &Options) -> f64 {
+    /// The estimated number of rows in a table without statistics.
+    const TABLE_ROWS: f64 = 1000.0;
+    /// The estimated number of rows per secondary index value without
+    /// statistics.
+    const INDEX_ROWS: f64 = 10.0;
+    /// The estimated fraction of rows that match a predicate.
+    const SELECTIVITY: f64 = 0.1;
+    /// The estimated number of elements per unnested array.
+    const UNNEST_ROWS: f64 = 10.0;
+
+    let estimates = |table: &Table| -> Option<TableEstimates> {
+        options.statistics.as_ref()?.table_estimates(&table.name).ok().flatten()
+    };
+
+    match node {
+        Node::Aggregate { group_by, .. } if group_by.is_empty() => 1.0,
+        Node::Aggregate { source, .. } | Node::Filter { source, .. } => {
+            estimate_rows(source, options) * SELECTIVITY
+        }
+        Node::HashJoin { left, right, .. }
+        | Node::NestedLoopJoin { left, right, predicate: Some(_), .. } => {
+            estimate_rows(left, options) * estimate_rows(right, options) * SELECTIVITY
+        }
+        Node::NestedLoopJoin { left, right, predicate: None, .. } => {
+            estimate_rows(left, options) * estimate_rows(right, options)
+        }
+        Node::IndexLookup { table, column, values, .. } => match estimates(table) {
+            Some(estimates) => {
+                let distinct = estimates.distinct.get(*column).copied().unwrap_or(1).max(1);
+                values.len() as f64 * estimates.rows as f64 / distinct as f64
+            }
+            None => values.len() as f64 * INDEX_ROWS,
+        },
+        Node::KeyLookup { keys, .. } => keys.len() as f64,
+        Node::Limit { source, limit } => estimate_rows(source, options).min(*limit as f64),
+        Node::Nothing { .. } => 0.0,
+        Node::Offset { source, offset } => {
+            (estimate_rows(source, options) - *offset as f64).max(0.0)
+        }
+        Node::Exchange { source, .. }
+        | Node::Lock { source, .. }
+        | Node::Order { source, .. }
+        | Node::Projection { source, .. }
+        | Node::Remap { source, .. } => estimate_rows(source, options),
+        Node::Scan { table, filter, .. } => {
+            let rows = estimates(table).map_or(TABLE_ROWS, |estimates| estimates.rows as f64);
+            match filter {
+                Some(_) => rows * SELECTIVITY,
+                None => rows,
+            }
+        }
+        Node::SchemaScan { rows, .. } => rows.len() as f64,
+        Node::Unnest { source, .. } => estimate_rows(source, options) * UNNEST_ROWS,
+        Node::Values { rows } => rows.len() as f64,
+    }
+}
+
+/// Uses a hash join instead of a nested loop join for single-column equijoins,
+/// unless disabled by the options.
+pub fn join_type(node: Node, options: &Options) -> Result<Node> {
     let xform = |node| match node {
         Node::NestedLoopJoin {
             left,
             right,
             predicate: Some(Expression::Equal(lhs, rhs)),
             outer,
-        } => match (*lhs, *rhs) {
+        } if options.use_hash_join(&left, &right) => match (*lhs, *rhs) {
             (Expression::Column(mut left_column), Expression::Column(mut right_column)) => {
                 // The LHS column may be a column in the right table; swap them.
                 if right_column < left_column {
@@ -295,7 +723,7 @@ pub fn join_type(node: Node) -> Result<Node> {
 
 /// Short-circuits useless nodes and expressions, by removing them and/or
 /// replacing them with Nothing nodes that yield no rows.
-pub fn short_circuit(node: Node) -> Result<Node> {
+pub fn short_circuit(node: Node, _: &Options) -> Result<Node> {
     use Expression::*;
     use Value::*;
 
@@ -310,8 +738,8 @@ pub fn short_circuit(node: Node) -> Result<Node> {
         Node::Filter { source, predicate: Constant(Boolean(true)) } => *source,
 
         // Predicates that always yield true are unnecessary: remove them.
-        Node::Scan { table, filter: Some(Constant(Boolean(true))), alias } => {
-            Node::Scan { table, filter: None, alias }
+        Node::Scan { table, filter: Some(Constant(Boolean(true))), alias, partitions } => {
+            Node::Scan { table, filter: None, alias, partitions }
         }
         Node::NestedLoopJoin { left, right, predicate: Some(Constant(Boolean(true))), outer } => {
             Node::NestedLoopJoin { left, right, predicate: None, outer }
@@ -329,6 +757,9 @@ pub fn short_circuit(node: Node) -> Result<Node> {
         ref node @ Node::Scan { filter: Some(Constant(Boolean(false) | Null)), .. } => {
             nothing(node)
         }
+        ref node @ Node::Scan { partitions: Some(ref partitions), .. } if partitions.is_empty() => {
+            nothing(node)
+        }
         ref node @ Node::Values { ref rows } if rows.is_empty() => nothing(node),
 
         // Short-circuit nodes that pull from a Nothing node.
@@ -342,12 +773,27 @@ pub fn short_circuit(node: Node) -> Result<Node> {
         | Node::NestedLoopJoin { right: ref source, .. }
         | Node::Offset { ref source, .. }
         | Node::Order { ref source, .. }
-        | Node::Projection { ref source, .. })
+        | Node::Projection { ref source, .. }
+        | Node::Unnest { ref source, .. })
             if matches!(**source, Node::Nothing { .. }) =>
         {
             nothing(node)
         }
 
+        // Offsets of 0 are unnecessary: remove them.
+        Node::Offset { source, offset: 0 } => *source,
+
+        // Constant sort keys don't affect the order: remove them, and the
+        // Order node if there are no keys left.
+        Node::Order { source, key } if key.iter().any(|(expr, _)| matches!(expr, Constant(_))) => {
+            let key =
+                key.into_iter().filter(|(expr, _)| !matches!(expr, Constant(_))).collect_vec();
+            match key.is_empty() {
+                true => *source,
+                false => Node::Order { source, key },
+            }
+        }
+
         // Remove noop projections that simply pass through the source columns.
         Node::Projection { source, expressions, aliases }
             if source.columns() == expressions.len()
@@ -360,9 +806,141 @@ pub fn short_circuit(node: Node) -> Result<Node> {
             *source
         }
 
+        // Remove noop remaps that don't move or drop any source columns.
+        Node::Remap { source, targets }
+            if source.columns() == targets.len()
+                && targets.iter().enumerate().all(|(i, target)| *target == Some(i)) =>
+        {
+            *source
+        }
+
         node => node,
     };
 
     // Transform after descending, to pull Nothing nodes upwards.
     node.transform(&Ok, &|node| Ok(xform(node)))
 }
+
+/// Parallelizes table scans and aggregations across the given number of
+/// partitions. This isn't one of the OPTIMIZERS, since it depends on the
+/// session's parallelism setting, and it is applied after them.
+///
+/// Table scans are wrapped in an Exchange node, which executes them in
+/// parallel across primary key ranges. Filters and projections are pulled into
+/// the Exchange, such that they're evaluated by each partition. Aggregates
+/// above an Exchange are split into a partial aggregate in each partition and
+/// a final aggregate that merges the partial results, e.g.:
+///
+/// Projection: movies.genre_id, #1 / #2
+/// └─ Aggregate: #0, sum(#1), sum(#2)
+///    └─ Exchange: 4 partitions
+///       └─ Aggregate: movies.genre_id, sum(movies.rating), count(movies.rating)
+///          └─ Scan: movies
+pub fn parallelize(node: Node, partitions: usize) -> Result<Node> {
+    if partitions <= 1 {
+        return Ok(node);
+    }
+
+    /// Splits an aggregate into partial aggregates for each partition, and a
+    /// final aggregate across the partial results. Averages are split into
+    /// sums and counts that are divided after merging.
+    fn split_aggregate(
+        source: Box<Node>,
+        partitions: usize,
+        group_by: Vec<Expression>,
+        aggregates: Vec<Aggregate>,
+    ) -> Node {
+        use Expression::*;
+
+        // The partial and merge aggregates have the same columns: the group_by
+        // columns followed by the aggregates.
+        let groups = group_by.len();
+        let mut partial = Vec::with_capacity(aggregates.len());
+        let mut merge = Vec::with_capacity(aggregates.len());
+        let mut expressions: Vec<_> = (0..groups).map(Column).collect();
+        for aggregate in aggregates {
+            let column = groups + partial.len();
+            match aggregate {
+                Aggregate::Average(expr) => {
+                    partial.extend([Aggregate::Sum(expr.clone()), Aggregate::Count(expr)]);
+                    merge.extend([
+                        Aggregate::Sum(Column(column)),
+                        Aggregate::Sum(Column(column + 1)),
+                    ]);
+                    expressions.push(Divide(Column(column).into(), Column(column + 1).into()));
+                    continue;
+                }
+                Aggregate::Count(expr) => {
+                    partial.push(Aggregate::Count(expr));
+                    merge.push(Aggregate::Sum(Column(column)));
+                }
+                Aggregate::Max(expr) => {
+                    partial.push(Aggregate::Max(expr));
+                    merge.push(Aggregate::Max(Column(column)));
+                }
+                Aggregate::Min(expr) => {
+                    partial.push(Aggregate::Min(expr));
+                    merge.push(Aggregate::Min(Column(column)));
+                }
+                Aggregate::Sum(expr) => {
+                    partial.push(Aggregate::Sum(expr));
+                    merge.push(Aggregate::Sum(Column(column)));
+                }
+            }
+            expressions.push(Column(column));
+        }
+
+        let source = Node::Aggregate { source, group_by, aggregates: partial };
+        let source = Node::Exchange { source: source.into(), partitions };
+        let group_by = (0..groups).map(Column).collect();
+        let node = Node::Aggregate { source: source.into(), group_by, aggregates: merge };
+
+        // Only project the merged columns if there were any averages.
+        if node.columns() == expressions.len() {
+            return node;
+        }
+        let aliases = vec![Label::None; expressions.len()];
+        Node::Projection { source: node.into(), expressions, aliases }
+    }
+
+    let xform = |node| match node {
+        // Partition table scans.
+        node @ Node::Scan { .. } => Node::Exchange { source: node.into(), partitions },
+
+        // Pull filters and projections into the exchange.
+        Node::Filter { source, predicate } => match *source {
+            Node::Exchange { source, partitions } => {
+                let source = Node::Filter { source, predicate };
+                Node::Exchange { source: source.into(), partitions }
+            }
+            source => Node::Filter { source: source.into(), predicate },
+        },
+        Node::Projection { source, expressions, aliases } => match *source {
+            Node::Exchange { source, partitions } => {
+                let source = Node::Projection { source, expressions, aliases };
+                Node::Exchange { source: source.into(), partitions }
+            }
+            source => Node::Projection { source: source.into(), expressions, aliases },
+        },
+
+        // Split aggregates into partial and merge aggregates.
+        Node::Aggregate { source, group_by, aggregates } => match *source {
+            Node::Exchange { source, partitions } => {
+                split_aggregate(source, partitions, group_by, aggregates)
+            }
+            source => Node::Aggregate { source: source.into(), group_by, aggregates },
+        },
+
+        // Limits typically only need a few rows. Don't scan the entire table
+        // in parallel, since partitions scan ahead of the consumer.
+        Node::Limit { source, limit } => match *source {
+            Node::Exchange { source, .. } => Node::Limit { source, limit },
+            source => Node::Limit { source: source.into(), limit },
+        },
+
+        node => node,
+    };
+
+    // Transform after descending, to pull Exchange nodes upwards.
+    node.transform(&Ok, &|node| Ok(xform(node)))
+}
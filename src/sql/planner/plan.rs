@@ -4,13 +4,16 @@ use std::fmt::Display;
 use itertools::Itertools as _;
 use serde::{Deserialize, Serialize};
 
-use super::optimizer::OPTIMIZERS;
+use super::optimizer::{self, OPTIMIZERS, Options};
 use super::planner::Planner;
+use crate::errdata;
 use crate::error::Result;
 use crate::sql::engine::{Catalog, Transaction};
-use crate::sql::execution::{self, ExecutionResult};
+use crate::sql::execution::{self, CancelToken, ExecutionResult, MemoryAccountant};
 use crate::sql::parser::ast;
-use crate::sql::types::{Expression, Label, Table, Value};
+use crate::sql::types::{
+    Expression, FileFormat, IndexType, Label, Privilege, Row, Table, Trigger, User, Value,
+};
 
 /// A statement execution plan. The root nodes can perform data modifications or
 /// schema changes, in addition to SELECT queries. Beyond the root, the plan is
@@ -36,28 +39,103 @@ use crate::sql::types::{Expression, Label, Table, Value};
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum Plan {
     /// A CREATE TABLE plan. Creates a new table with the given schema. Errors
-    /// if the table already exists or the schema is invalid.
-    CreateTable { schema: Table },
+    /// if the table already exists or the schema is invalid. Temporary tables
+    /// are stored in the session, and dropped when the session ends.
+    CreateTable { schema: Table, temporary: bool },
     /// A DROP TABLE plan. Drops the given table. Errors if the table does not
     /// exist, unless if_exists is true.
     DropTable { table: String, if_exists: bool },
+    /// A CREATE INDEX plan. Creates a secondary index on the given table column,
+    /// optionally a partial index that only contains rows matching the
+    /// predicate. Errors if the column already has an index.
+    CreateIndex {
+        table: Table,
+        column: usize,
+        index_type: IndexType,
+        predicate: Option<Expression>,
+    },
+    /// A CREATE TRIGGER plan. Creates a trigger on the given table. Errors if
+    /// the table does not exist or a trigger with the same name does.
+    CreateTrigger { table: String, trigger: Trigger },
+    /// A DROP TRIGGER plan. Drops the given trigger from the table. Errors if
+    /// the trigger does not exist, unless if_exists is true.
+    DropTrigger { table: String, trigger: String, if_exists: bool },
+    /// A CREATE CHANGEFEED plan. Creates a changefeed for the given table.
+    /// Errors if the table does not exist or already has a changefeed.
+    CreateChangefeed { table: String },
+    /// A DROP CHANGEFEED plan. Drops the given table's changefeed. Errors if
+    /// the table does not exist or has no changefeed.
+    DropChangefeed { table: String },
+    /// An ALTER TABLE DROP PARTITION plan. Drops the given table partition and
+    /// all rows in it. Errors if the table or partition does not exist.
+    DropPartition { table: String, partition: String },
+    /// A TRUNCATE plan. Deletes all rows in the given table without decoding
+    /// them or firing triggers. Errors if the table does not exist or is
+    /// referenced by other tables.
+    TruncateTable { table: String },
+    /// A CREATE USER plan. Creates the given user, with its hashed password.
+    /// Errors if the user already exists.
+    CreateUser { user: User },
+    /// A DROP USER plan. Drops the given user. Errors if the user does not
+    /// exist, unless if_exists is true.
+    DropUser { name: String, if_exists: bool },
+    /// A GRANT plan. Grants privileges on a table, or all tables if None, to
+    /// a user. Errors if the user or table does not exist.
+    Grant { user: String, table: Option<String>, privileges: Vec<Privilege> },
+    /// A REVOKE plan. Revokes privileges on a table, or all tables if None,
+    /// from a user. Errors if the user does not exist.
+    Revoke { user: String, table: Option<String>, privileges: Vec<Privilege> },
     /// A DELETE plan. Deletes rows in table that match the rows from source.
     /// primary_key specifies the primary key column index in the source rows.
-    Delete { table: String, primary_key: usize, source: Node },
+    /// The RETURNING expressions are evaluated against each deleted row, and
+    /// are empty if there is no RETURNING clause (likewise for Insert/Update).
+    Delete { table: String, primary_key: usize, source: Node, returning: Vec<(Expression, Label)> },
     /// An INSERT plan. Inserts rows from source (typically a Values node) into
     /// table. If column_map is given, it maps table → source column indexes and
     /// must have one entry for every column in source. Table columns not
     /// present in source will get the column's default value if set, or error.
-    Insert { table: Table, column_map: Option<HashMap<usize, usize>>, source: Node },
+    /// The RETURNING expressions are evaluated against each inserted row.
+    Insert {
+        table: Table,
+        column_map: Option<HashMap<usize, usize>>,
+        source: Node,
+        returning: Vec<(Expression, Label)>,
+    },
+    /// An IMPORT plan. Like Insert, but bulk loads the rows via
+    /// Transaction::bulk_insert().
+    Import { table: Table, column_map: Option<HashMap<usize, usize>>, source: Node },
+    /// An IMPORT plan from a CSV or JSON file or client data. Decodes the
+    /// rows, converting fields to the column datatypes, and bulk loads them
+    /// like Import. CSV fields are written to the given table column indexes,
+    /// or the header's columns, or all non-generated columns in order. JSON
+    /// objects are keyed by column name.
+    ImportFile {
+        table: Table,
+        columns: Option<Vec<usize>>,
+        input: ImportInput,
+        format: FileFormat,
+        header: bool,
+    },
     /// An UPDATE plan. Updates rows in table that match the rows from source,
     /// where primary_key specifies the primary key column index in the source
     /// rows. The given column/expression pairs specify the row updates to make,
     /// evaluated using the existing source row, which must be a complete row
-    /// from the update table.
-    Update { table: Table, primary_key: usize, source: Node, expressions: Vec<(usize, Expression)> },
+    /// from the update table. The RETURNING expressions are evaluated against
+    /// each updated row.
+    Update {
+        table: Table,
+        primary_key: usize,
+        source: Node,
+        expressions: Vec<(usize, Expression)>,
+        returning: Vec<(Expression, Label)>,
+    },
     /// A SELECT plan. Recursively executes the query plan tree and returns the
     /// resulting rows.
     Select(Node),
+    /// An EXPORT plan. Executes the query plan tree like Select, and streams
+    /// the resulting rows to the given file as CSV (optionally with a header)
+    /// or JSON lines, replacing it if it exists.
+    Export { root: Node, path: String, format: FileFormat, header: bool },
 }
 
 impl Plan {
@@ -66,29 +144,167 @@ impl Plan {
         Planner::new(catalog).build(statement)
     }
 
-    /// Executes the plan, consuming it.
-    pub fn execute(self, txn: &(impl Transaction + Catalog)) -> Result<ExecutionResult> {
-        execution::execute_plan(self, txn, txn)
+    /// Builds a plan from an AST statement at the given wall-clock time, in
+    /// nanoseconds since the Unix epoch. This determines which rows have
+    /// expired in tables with a TTL.
+    pub fn build_at(
+        statement: ast::Statement,
+        catalog: &impl Catalog,
+        wall_time: u64,
+    ) -> Result<Self> {
+        Planner::new(catalog).at_time(wall_time).build(statement)
+    }
+
+    /// Executes the plan, consuming it. Execution errors with Error::Cancelled
+    /// if the cancellation token is cancelled, Error::Timeout if its deadline
+    /// passes, or if it exceeds the memory accountant's limit.
+    pub fn execute(
+        self,
+        txn: &(impl Transaction + Catalog),
+        cancel: &CancelToken,
+        memory: &MemoryAccountant,
+    ) -> Result<ExecutionResult> {
+        execution::execute_plan(self, txn, txn, cancel, memory)
     }
 
-    /// Optimizes the plan, consuming it. See OPTIMIZERS for the list of
-    /// optimizers.
-    pub fn optimize(self) -> Result<Self> {
-        let optimize = |node| OPTIMIZERS.iter().try_fold(node, |node, (_, opt)| opt(node));
+    /// Optimizes the plan with the given options, consuming it. See OPTIMIZERS
+    /// for the list of optimizers.
+    pub fn optimize(self, options: &Options) -> Result<Self> {
+        let optimize = |node| OPTIMIZERS.iter().try_fold(node, |node, (_, opt)| opt(node, options));
         Ok(match self {
-            Self::CreateTable { .. } | Self::DropTable { .. } => self,
-            Self::Delete { table, primary_key, source } => {
-                Self::Delete { table, primary_key, source: optimize(source)? }
+            Self::CreateTable { .. }
+            | Self::DropTable { .. }
+            | Self::CreateIndex { .. }
+            | Self::CreateTrigger { .. }
+            | Self::DropTrigger { .. }
+            | Self::CreateChangefeed { .. }
+            | Self::DropChangefeed { .. }
+            | Self::DropPartition { .. }
+            | Self::TruncateTable { .. }
+            | Self::CreateUser { .. }
+            | Self::DropUser { .. }
+            | Self::Grant { .. }
+            | Self::Revoke { .. }
+            | Self::ImportFile { .. } => self,
+            Self::Delete { table, primary_key, source, returning } => {
+                Self::Delete { table, primary_key, source: optimize(source)?, returning }
+            }
+            Self::Insert { table, column_map, source, returning } => {
+                Self::Insert { table, column_map, source: optimize(source)?, returning }
             }
-            Self::Insert { table, column_map, source } => {
-                Self::Insert { table, column_map, source: optimize(source)? }
+            Self::Import { table, column_map, source } => {
+                Self::Import { table, column_map, source: optimize(source)? }
             }
-            Self::Update { table, primary_key, source, expressions } => {
-                Self::Update { table, primary_key, source: optimize(source)?, expressions }
+            Self::Update { table, primary_key, source, expressions, returning } => {
+                let source = optimize(source)?;
+                Self::Update { table, primary_key, source, expressions, returning }
             }
             Self::Select(root) => Self::Select(optimize(root)?),
+            Self::Export { root, path, format, header } => {
+                Self::Export { root: optimize(root)?, path, format, header }
+            }
+        })
+    }
+
+    /// Parallelizes SELECT table scans and aggregations across the given
+    /// number of partitions, consuming the plan. Should be applied after
+    /// optimization. A parallelism of 0 or 1 leaves the plan unchanged. Write
+    /// plans are not parallelized.
+    pub fn parallelize(self, partitions: usize) -> Result<Self> {
+        Ok(match self {
+            Self::Select(root) => Self::Select(optimizer::parallelize(root, partitions)?),
+            Self::Export { root, path, format, header } => {
+                let root = optimizer::parallelize(root, partitions)?;
+                Self::Export { root, path, format, header }
+            }
+            plan => plan,
+        })
+    }
+
+    /// Binds the query parameters of a cached plan (see PlanCache) to the given
+    /// values, consuming the plan. Should be applied before optimization.
+    /// Regular expression patterns are compiled, such that invalid patterns
+    /// error like they do during planning.
+    pub fn bind(self, params: &[Value]) -> Result<Self> {
+        let bind = |expr| match expr {
+            Expression::Parameter(index) => match params.get(index) {
+                Some(value) => Ok(Expression::Constant(value.clone())),
+                None => errdata!("unknown parameter ${}", index + 1),
+            },
+            expr => Ok(expr),
+        };
+        let compile = |expr: Expression| {
+            if let Expression::Regexp(_, pattern) | Expression::RegexpMatch(_, pattern) = &expr {
+                if let Expression::Constant(Value::String(pattern)) = pattern.as_ref() {
+                    Expression::compile_regex(pattern)?;
+                }
+            }
+            Ok(expr)
+        };
+        let bind_node =
+            |node: Node| node.transform(&|node| node.transform_expressions(&bind, &compile), &Ok);
+        let bind_returning = |returning: Vec<(Expression, Label)>| {
+            returning
+                .into_iter()
+                .map(|(expr, label)| Ok((expr.transform(&bind, &compile)?, label)))
+                .collect::<Result<Vec<_>>>()
+        };
+        Ok(match self {
+            Self::Delete { table, primary_key, source, returning } => {
+                let returning = bind_returning(returning)?;
+                Self::Delete { table, primary_key, source: bind_node(source)?, returning }
+            }
+            Self::Insert { table, column_map, source, returning } => {
+                let returning = bind_returning(returning)?;
+                Self::Insert { table, column_map, source: bind_node(source)?, returning }
+            }
+            Self::Update { table, primary_key, source, expressions, returning } => {
+                let expressions = expressions
+                    .into_iter()
+                    .map(|(column, expr)| Ok((column, expr.transform(&bind, &compile)?)))
+                    .collect::<Result<_>>()?;
+                let returning = bind_returning(returning)?;
+                let source = bind_node(source)?;
+                Self::Update { table, primary_key, source, expressions, returning }
+            }
+            Self::Select(root) => Self::Select(bind_node(root)?),
+            plan => plan,
         })
     }
+
+    /// Returns a one-line summary of the plan, with the node types and tables
+    /// but not expressions or values, e.g. "Projection(Filter(Scan test))". Used
+    /// e.g. in the slow query log, where values must not be logged.
+    pub fn summary(&self) -> String {
+        match self {
+            Self::CreateTable { .. } => "CreateTable".to_string(),
+            Self::DropTable { .. } => "DropTable".to_string(),
+            Self::CreateIndex { .. } => "CreateIndex".to_string(),
+            Self::CreateTrigger { .. } => "CreateTrigger".to_string(),
+            Self::DropTrigger { .. } => "DropTrigger".to_string(),
+            Self::CreateChangefeed { .. } => "CreateChangefeed".to_string(),
+            Self::DropChangefeed { .. } => "DropChangefeed".to_string(),
+            Self::DropPartition { .. } => "DropPartition".to_string(),
+            Self::TruncateTable { .. } => "TruncateTable".to_string(),
+            Self::CreateUser { .. } => "CreateUser".to_string(),
+            Self::DropUser { .. } => "DropUser".to_string(),
+            Self::Grant { .. } => "Grant".to_string(),
+            Self::Revoke { .. } => "Revoke".to_string(),
+            Self::Delete { table, source, .. } => format!("Delete {table}({})", source.summary()),
+            Self::Insert { table, source, .. } => {
+                format!("Insert {}({})", table.name, source.summary())
+            }
+            Self::Import { table, source, .. } => {
+                format!("Import {}({})", table.name, source.summary())
+            }
+            Self::ImportFile { table, .. } => format!("ImportFile {}", table.name),
+            Self::Update { table, source, .. } => {
+                format!("Update {}({})", table.name, source.summary())
+            }
+            Self::Select(root) => root.summary(),
+            Self::Export { root, .. } => format!("Export({})", root.summary()),
+        }
+    }
 }
 
 /// A query plan node. Returns a row iterator, and can be nested.
@@ -98,6 +314,12 @@ pub enum Node {
     /// across all rows in the source node. The group_by columns are emitted
     /// first, followed by the aggregate columns, in the given order.
     Aggregate { source: Box<Node>, group_by: Vec<Expression>, aggregates: Vec<Aggregate> },
+    /// Executes the source plan fragment in parallel on the given number of
+    /// partitions, by splitting its table scan into primary key ranges, and
+    /// merges the partitions' rows in primary key range order. The fragment
+    /// can only contain a single Scan, and Filter, Projection and Aggregate
+    /// nodes. The partition results are buffered in memory.
+    Exchange { source: Box<Node>, partitions: usize },
     /// Filters source rows, by discarding rows for which the predicate
     /// evaluates to false.
     Filter { source: Box<Node>, predicate: Expression },
@@ -120,6 +342,12 @@ pub enum Node {
     KeyLookup { table: Table, keys: Vec<Value>, alias: Option<String> },
     /// Only emits the first limit rows from the source, discards the rest.
     Limit { source: Box<Node>, limit: usize },
+    /// Locks the source table rows for SELECT FOR UPDATE, then re-reads them
+    /// at their latest committed version and emits the ones that still match
+    /// the predicate. Errors with Error::Locked if a row lock is held by a
+    /// different transaction, in which case the session retries the statement
+    /// once the holder completes.
+    Lock { source: Box<Node>, table: Table, predicate: Option<Expression> },
     /// Joins the left and right sources on the given predicate by buffering the
     /// right source and iterating over it for every row in the left source.
     /// When outer is true (e.g. LEFT JOIN), a left row without a right match is
@@ -144,19 +372,67 @@ pub enum Node {
     Remap { source: Box<Node>, targets: Vec<Option<usize>> },
     /// A full table scan, with an optional pushed-down filter. The schema is
     /// used during plan optimization. The alias is only used for formatting.
-    Scan { table: Table, filter: Option<Expression>, alias: Option<String> },
+    /// For partitioned tables, partitions can limit the scan to the given
+    /// partition indexes (i.e. partition pruning), otherwise all are scanned.
+    Scan {
+        table: Table,
+        filter: Option<Expression>,
+        alias: Option<String>,
+        partitions: Option<Vec<usize>>,
+    },
+    /// A scan of a virtual information schema or system table, e.g.
+    /// information_schema.tables, in the given schema. The rows are generated
+    /// from the catalog during planning. The alias is only used for formatting.
+    SchemaScan { schema: String, table: Table, rows: Vec<Row>, alias: Option<String> },
+    /// Expands an array into one row per element. The expression is evaluated
+    /// for each source row, and each element is emitted as a new column after
+    /// the source columns. NULL arrays don't emit any rows. This is a lateral
+    /// table function, since the expression can reference the source columns.
+    /// The alias is used as both table and column name, or unnest if None.
+    Unnest { source: Box<Node>, expression: Expression, alias: Option<String> },
     /// A constant set of values.
     Values { rows: Vec<Vec<Expression>> },
 }
 
 impl Node {
+    /// Returns a one-line summary of the node tree (see Plan::summary).
+    pub fn summary(&self) -> String {
+        match self {
+            Self::Aggregate { source, .. } => format!("Aggregate({})", source.summary()),
+            Self::Exchange { source, .. } => format!("Exchange({})", source.summary()),
+            Self::Filter { source, .. } => format!("Filter({})", source.summary()),
+            Self::HashJoin { left, right, .. } => {
+                format!("HashJoin({}, {})", left.summary(), right.summary())
+            }
+            Self::IndexLookup { table, column, .. } => {
+                format!("IndexLookup {}.{}", table.name, table.columns[*column].name)
+            }
+            Self::KeyLookup { table, .. } => format!("KeyLookup {}", table.name),
+            Self::Limit { source, .. } => format!("Limit({})", source.summary()),
+            Self::Lock { source, .. } => format!("Lock({})", source.summary()),
+            Self::NestedLoopJoin { left, right, .. } => {
+                format!("NestedLoopJoin({}, {})", left.summary(), right.summary())
+            }
+            Self::Nothing { .. } => "Nothing".to_string(),
+            Self::Offset { source, .. } => format!("Offset({})", source.summary()),
+            Self::Order { source, .. } => format!("Order({})", source.summary()),
+            Self::Projection { source, .. } => format!("Projection({})", source.summary()),
+            Self::Remap { source, .. } => format!("Remap({})", source.summary()),
+            Self::Scan { table, .. } => format!("Scan {}", table.name),
+            Self::SchemaScan { table, .. } => format!("SchemaScan {}", table.name),
+            Self::Unnest { source, .. } => format!("Unnest({})", source.summary()),
+            Self::Values { .. } => "Values".to_string(),
+        }
+    }
+
     /// Returns the number of columns emitted by the node.
     pub fn columns(&self) -> usize {
         match self {
             // Source nodes emit all table columns.
             Self::IndexLookup { table, .. }
             | Self::KeyLookup { table, .. }
-            | Self::Scan { table, .. } => table.columns.len(),
+            | Self::Scan { table, .. }
+            | Self::SchemaScan { table, .. } => table.columns.len(),
 
             // Some nodes modify the column set.
             Self::Aggregate { aggregates, group_by, .. } => aggregates.len() + group_by.len(),
@@ -171,17 +447,54 @@ impl Node {
             }
 
             // Simple nodes just pass through the source columns.
-            Self::Filter { source, .. }
+            Self::Exchange { source, .. }
+            | Self::Filter { source, .. }
             | Self::Limit { source, .. }
+            | Self::Lock { source, .. }
             | Self::Offset { source, .. }
             | Self::Order { source, .. } => source.columns(),
 
+            // Unnest adds a column.
+            Self::Unnest { source, .. } => source.columns() + 1,
+
             // And some are trivial.
             Self::Nothing { columns } => columns.len(),
             Self::Values { rows } => rows.first().map(|row| row.len()).unwrap_or(0),
         }
     }
 
+    /// Returns the tables read by the node tree, by alias if any, otherwise by
+    /// name. Used to match optimizer hints.
+    pub fn tables(&self) -> Vec<&str> {
+        match self {
+            Self::IndexLookup { table, alias, .. }
+            | Self::KeyLookup { table, alias, .. }
+            | Self::Scan { table, alias, .. }
+            | Self::SchemaScan { table, alias, .. } => {
+                vec![alias.as_deref().unwrap_or(&table.name)]
+            }
+
+            Self::HashJoin { left, right, .. } | Self::NestedLoopJoin { left, right, .. } => {
+                let mut tables = left.tables();
+                tables.extend(right.tables());
+                tables
+            }
+
+            Self::Aggregate { source, .. }
+            | Self::Exchange { source, .. }
+            | Self::Filter { source, .. }
+            | Self::Limit { source, .. }
+            | Self::Lock { source, .. }
+            | Self::Offset { source, .. }
+            | Self::Order { source, .. }
+            | Self::Projection { source, .. }
+            | Self::Remap { source, .. }
+            | Self::Unnest { source, .. } => source.tables(),
+
+            Self::Nothing { .. } | Self::Values { .. } => Vec::new(),
+        }
+    }
+
     /// Returns a label for a column, if any, by tracing the column through the
     /// plan tree. Only used for query result headers and plan display purposes,
     /// not to look up expression columns (see Scope).
@@ -190,7 +503,8 @@ impl Node {
             // Source nodes use the table/column name.
             Self::IndexLookup { table, alias, .. }
             | Self::KeyLookup { table, alias, .. }
-            | Self::Scan { table, alias, .. } => Label::Qualified(
+            | Self::Scan { table, alias, .. }
+            | Self::SchemaScan { table, alias, .. } => Label::Qualified(
                 alias.as_ref().unwrap_or(&table.name).clone(),
                 table.columns[index].name.clone(),
             ),
@@ -227,11 +541,22 @@ impl Node {
             }
 
             // Simple nodes just dispatch to the source.
-            Self::Filter { source, .. }
+            Self::Exchange { source, .. }
+            | Self::Filter { source, .. }
             | Self::Limit { source, .. }
+            | Self::Lock { source, .. }
             | Self::Offset { source, .. }
             | Self::Order { source, .. } => source.column_label(index),
 
+            // Unnest uses the alias for the element column.
+            Self::Unnest { source, alias, .. } => {
+                if index < source.columns() {
+                    return source.column_label(index);
+                }
+                let name = alias.as_deref().unwrap_or("unnest");
+                Label::Qualified(name.to_string(), name.to_string())
+            }
+
             // Nothing nodes contain the original columns of replaced nodes.
             Self::Nothing { columns } => columns.get(index).cloned().unwrap_or(Label::None),
 
@@ -258,6 +583,9 @@ impl Node {
             Self::Aggregate { source, group_by, aggregates } => {
                 Self::Aggregate { source: xform(source)?, group_by, aggregates }
             }
+            Self::Exchange { source, partitions } => {
+                Self::Exchange { source: xform(source)?, partitions }
+            }
             Self::Filter { source, predicate } => {
                 Self::Filter { source: xform(source)?, predicate }
             }
@@ -269,6 +597,9 @@ impl Node {
                 outer,
             },
             Self::Limit { source, limit } => Self::Limit { source: xform(source)?, limit },
+            Self::Lock { source, table, predicate } => {
+                Self::Lock { source: xform(source)?, table, predicate }
+            }
             Self::NestedLoopJoin { left, right, predicate, outer } => {
                 Self::NestedLoopJoin { left: xform(left)?, right: xform(right)?, predicate, outer }
             }
@@ -278,11 +609,15 @@ impl Node {
                 Self::Projection { source: xform(source)?, expressions, aliases }
             }
             Self::Remap { source, targets } => Self::Remap { source: xform(source)?, targets },
+            Self::Unnest { source, expression, alias } => {
+                Self::Unnest { source: xform(source)?, expression, alias }
+            }
 
             Self::IndexLookup { .. }
             | Self::KeyLookup { .. }
             | Self::Nothing { .. }
             | Self::Scan { .. }
+            | Self::SchemaScan { .. }
             | Self::Values { .. } => self,
         };
         self = after(self)?;
@@ -297,10 +632,23 @@ impl Node {
         after: &impl Fn(Expression) -> Result<Expression>,
     ) -> Result<Self> {
         Ok(match self {
+            Self::Aggregate { source, mut group_by, mut aggregates } => {
+                group_by =
+                    group_by.into_iter().map(|expr| expr.transform(before, after)).try_collect()?;
+                aggregates = aggregates
+                    .into_iter()
+                    .map(|agg| agg.transform_expression(before, after))
+                    .try_collect()?;
+                Self::Aggregate { source, group_by, aggregates }
+            }
             Self::Filter { source, mut predicate } => {
                 predicate = predicate.transform(before, after)?;
                 Self::Filter { source, predicate }
             }
+            Self::Lock { source, table, predicate: Some(predicate) } => {
+                let predicate = Some(predicate.transform(before, after)?);
+                Self::Lock { source, table, predicate }
+            }
             Self::NestedLoopJoin { left, right, predicate: Some(predicate), outer } => {
                 let predicate = Some(predicate.transform(before, after)?);
                 Self::NestedLoopJoin { left, right, predicate, outer }
@@ -319,9 +667,13 @@ impl Node {
                     .try_collect()?;
                 Self::Projection { source, expressions, aliases }
             }
-            Self::Scan { table, alias, filter: Some(filter) } => {
+            Self::Scan { table, alias, filter: Some(filter), partitions } => {
                 let filter = Some(filter.transform(before, after)?);
-                Self::Scan { table, alias, filter }
+                Self::Scan { table, alias, filter, partitions }
+            }
+            Self::Unnest { source, mut expression, alias } => {
+                expression = expression.transform(before, after)?;
+                Self::Unnest { source, expression, alias }
             }
             Self::Values { mut rows } => {
                 rows = rows
@@ -331,16 +683,18 @@ impl Node {
                 Self::Values { rows }
             }
 
-            Self::Aggregate { .. }
+            Self::Exchange { .. }
             | Self::HashJoin { .. }
             | Self::IndexLookup { .. }
             | Self::KeyLookup { .. }
             | Self::Limit { .. }
+            | Self::Lock { predicate: None, .. }
             | Self::NestedLoopJoin { predicate: None, .. }
             | Self::Nothing { .. }
             | Self::Offset { .. }
             | Self::Remap { .. }
-            | Self::Scan { filter: None, .. } => self,
+            | Self::Scan { filter: None, .. }
+            | Self::SchemaScan { .. } => self,
         })
     }
 }
@@ -365,6 +719,22 @@ impl Aggregate {
             Self::Sum(expr) => format!("sum({})", expr.format(node)),
         }
     }
+
+    /// Transforms the aggregate's input expression by calling the given
+    /// closures on it before and after descending.
+    fn transform_expression(
+        self,
+        before: &impl Fn(Expression) -> Result<Expression>,
+        after: &impl Fn(Expression) -> Result<Expression>,
+    ) -> Result<Self> {
+        Ok(match self {
+            Self::Average(expr) => Self::Average(expr.transform(before, after)?),
+            Self::Count(expr) => Self::Count(expr.transform(before, after)?),
+            Self::Max(expr) => Self::Max(expr.transform(before, after)?),
+            Self::Min(expr) => Self::Min(expr.transform(before, after)?),
+            Self::Sum(expr) => Self::Sum(expr.transform(before, after)?),
+        })
+    }
 }
 
 /// A sort order direction.
@@ -396,25 +766,104 @@ impl From<ast::Direction> for Direction {
 impl Display for Plan {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Self::CreateTable { schema } => write!(f, "CreateTable: {}", schema.name),
+            Self::CreateTable { schema, temporary: false } => {
+                write!(f, "CreateTable: {}", schema.name)
+            }
+            Self::CreateTable { schema, temporary: true } => {
+                write!(f, "CreateTable: {} (temporary)", schema.name)
+            }
             Self::DropTable { table, .. } => write!(f, "DropTable: {table}"),
-            Self::Delete { table, source, .. } => {
-                write!(f, "Delete: {table}")?;
+            Self::CreateIndex { table, column, index_type, predicate } => {
+                write!(f, "CreateIndex: {}.{}", table.name, table.columns[*column].name)?;
+                if *index_type != IndexType::BTree {
+                    write!(f, " USING {index_type}")?;
+                }
+                if let Some(predicate) = predicate {
+                    write!(f, " ({})", table.format_expression(predicate))?;
+                }
+                Ok(())
+            }
+            Self::CreateTrigger { table, trigger } => {
+                write!(
+                    f,
+                    "CreateTrigger: {table}.{} ({} {})",
+                    trigger.name, trigger.timing, trigger.event
+                )
+            }
+            Self::DropTrigger { table, trigger, .. } => write!(f, "DropTrigger: {table}.{trigger}"),
+            Self::CreateChangefeed { table } => write!(f, "CreateChangefeed: {table}"),
+            Self::DropChangefeed { table } => write!(f, "DropChangefeed: {table}"),
+            Self::DropPartition { table, partition } => {
+                write!(f, "DropPartition: {table}.{partition}")
+            }
+            Self::TruncateTable { table } => write!(f, "TruncateTable: {table}"),
+            Self::CreateUser { user } => write!(f, "CreateUser: {}", user.name),
+            Self::DropUser { name, .. } => write!(f, "DropUser: {name}"),
+            Self::Grant { user, table, privileges } => {
+                let table = table.as_deref().unwrap_or("*");
+                write!(f, "Grant: {} ON {table} TO {user}", privileges.iter().join(", "))
+            }
+            Self::Revoke { user, table, privileges } => {
+                let table = table.as_deref().unwrap_or("*");
+                write!(f, "Revoke: {} ON {table} FROM {user}", privileges.iter().join(", "))
+            }
+            Self::Delete { table, source, returning, .. } => {
+                write!(f, "Delete: {table}{}", format_returning(returning, source))?;
                 source.format(f, "", false, true)
             }
-            Self::Insert { table, source, .. } => {
-                write!(f, "Insert: {}", table.name)?;
+            Self::Insert { table, source, returning, .. } => {
+                // The RETURNING expressions reference table columns, not the
+                // source columns, so format them against the table.
+                let columns = table
+                    .columns
+                    .iter()
+                    .map(|c| Label::Qualified(table.name.clone(), c.name.clone()))
+                    .collect();
+                let node = Node::Nothing { columns };
+                write!(f, "Insert: {}{}", table.name, format_returning(returning, &node))?;
+                source.format(f, "", false, true)
+            }
+            Self::Import { table, source, .. } => {
+                write!(f, "Import: {}", table.name)?;
                 source.format(f, "", false, true)
             }
-            Self::Update { table, source, expressions, .. } => {
+            Self::ImportFile { table, input, format, header, .. } => {
+                let header = if *header { " with header" } else { "" };
+                write!(f, "Import: {} ({format}{header} from {input})", table.name)
+            }
+            Self::Update { table, source, expressions, returning, .. } => {
                 let expressions = expressions
                     .iter()
                     .map(|(i, expr)| format!("{}={}", table.columns[*i].name, expr.format(source)))
                     .join(", ");
-                write!(f, "Update: {} ({expressions})", table.name)?;
+                let returning = format_returning(returning, source);
+                write!(f, "Update: {} ({expressions}){returning}", table.name)?;
                 source.format(f, "", false, true)
             }
             Self::Select(root) => root.format(f, "", true, true),
+            Self::Export { root, path, format, header } => {
+                let header = if *header { " with header" } else { "" };
+                write!(f, "Export: '{}' ({format}{header})", path.escape_debug())?;
+                root.format(f, "", false, true)
+            }
+        }
+    }
+}
+
+/// The input of a Plan::ImportFile.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ImportInput {
+    /// A file path on the server.
+    File(String),
+    /// Data streamed by the client.
+    Data(Vec<u8>),
+}
+
+impl Display for ImportInput {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::File(path) => write!(f, "'{}'", path.escape_debug()),
+            Self::Data(_) => f.write_str("STDIN"),
         }
     }
 }
@@ -466,6 +915,10 @@ impl Node {
                 write!(f, "Aggregate: {aggregates}")?;
                 source.format(f, &prefix, false, true)?;
             }
+            Self::Exchange { source, partitions } => {
+                write!(f, "Exchange: {partitions} partitions")?;
+                source.format(f, &prefix, false, true)?;
+            }
             Self::Filter { source, predicate } => {
                 write!(f, "Filter: {}", predicate.format(source))?;
                 source.format(f, &prefix, false, true)?;
@@ -511,6 +964,13 @@ impl Node {
                 write!(f, "Limit: {limit}")?;
                 source.format(f, &prefix, false, true)?;
             }
+            Self::Lock { source, table, predicate } => {
+                write!(f, "Lock: {}", table.name)?;
+                if let Some(predicate) = predicate {
+                    write!(f, " ({})", predicate.format(source))?;
+                }
+                source.format(f, &prefix, false, true)?;
+            }
             Self::NestedLoopJoin { left, right, predicate, outer, .. } => {
                 let kind = if *outer { "outer" } else { "inner" };
                 write!(f, "NestedLoopJoin: {kind}")?;
@@ -572,7 +1032,7 @@ impl Node {
                 }
                 source.format(f, &prefix, false, true)?;
             }
-            Self::Scan { table, alias, filter } => {
+            Self::Scan { table, alias, filter, partitions } => {
                 write!(f, "Scan: {}", table.name)?;
                 if let Some(alias) = alias {
                     write!(f, " as {alias}")?;
@@ -580,6 +1040,23 @@ impl Node {
                 if let Some(filter) = filter {
                     write!(f, " ({})", filter.format(self))?;
                 }
+                if let Some(partitions) = partitions {
+                    let names = partitions.iter().map(|i| &table.partitions[*i].name).join(", ");
+                    write!(f, " [partitions {names}]")?;
+                }
+            }
+            Self::SchemaScan { schema, table, alias, .. } => {
+                write!(f, "SchemaScan: {schema}.{}", table.name)?;
+                if let Some(alias) = alias {
+                    write!(f, " as {alias}")?;
+                }
+            }
+            Self::Unnest { source, expression, alias } => {
+                write!(f, "Unnest: {}", expression.format(source))?;
+                if let Some(alias) = alias {
+                    write!(f, " as {alias}")?;
+                }
+                source.format(f, &prefix, false, true)?;
             }
             Self::Values { rows, .. } => {
                 write!(f, "Values: ")?;
@@ -606,3 +1083,20 @@ pub fn remap_sources(targets: &[Option<usize>]) -> Vec<Option<usize>> {
     }
     sources
 }
+
+/// Formats the RETURNING expressions of a write plan for display, resolving
+/// column references via the given node, or an empty string if there are none.
+/// Only aliases are shown, since column references are labeled by the column.
+fn format_returning(returning: &[(Expression, Label)], node: &Node) -> String {
+    if returning.is_empty() {
+        return String::new();
+    }
+    let expressions = returning
+        .iter()
+        .map(|(expr, label)| match label {
+            Label::Unqualified(alias) => format!("{} as {alias}", expr.format(node)),
+            _ => expr.format(node),
+        })
+        .join(", ");
+    format!(" returning {expressions}")
+}
@@ -2,47 +2,158 @@ use std::collections::{BTreeMap, HashMap, HashSet};
 
 use itertools::{Either, Itertools as _};
 
-use super::plan::{Aggregate, Node, Plan, remap_sources};
+use super::plan::{Aggregate, ImportInput, Node, Plan, remap_sources};
+use super::{information_schema, system};
+use crate::clock::{Clock as _, SystemClock};
 use crate::errinput;
-use crate::error::Result;
+use crate::error::{Error, Result};
 use crate::sql::engine::Catalog;
-use crate::sql::parser::ast;
-use crate::sql::types::{Column, Expression, Label, Table, Value};
+use crate::sql::parser::{Parser, ast};
+use crate::sql::types::{
+    Collation, Column, Expression, FileFormat, IndexState, IndexType, Interval, Label, Partition,
+    Privilege, Row, Table, Timestamp, Trigger, TriggerEvent, TriggerTiming, Ttl, User, Value,
+};
 
 /// The planner builds an execution plan from a parsed Abstract Syntax Tree,
 /// using the catalog for schema information.
 pub struct Planner<'a, C: Catalog> {
     catalog: &'a C,
+    /// The old and new rows of a firing trigger, if planning a trigger action.
+    trigger_rows: Option<TriggerRows<'a>>,
+    /// The wall-clock time to plan at, in nanoseconds since the Unix epoch.
+    /// Scans of tables with a TTL omit rows that have expired by then.
+    wall_time: u64,
+}
+
+/// The old and new rows of a firing trigger, which can be referenced in the
+/// trigger action as old.column and new.column. They're planned as constants.
+pub struct TriggerRows<'a> {
+    /// The trigger's table.
+    pub table: &'a Table,
+    /// The trigger event.
+    pub event: TriggerEvent,
+    /// The old row, for UPDATE and DELETE.
+    pub old: Option<&'a Row>,
+    /// The new row, for INSERT and UPDATE.
+    pub new: Option<&'a Row>,
 }
 
 impl<'a, C: Catalog> Planner<'a, C> {
     /// Creates a new planner.
     pub fn new(catalog: &'a C) -> Self {
-        Self { catalog }
+        Self { catalog, trigger_rows: None, wall_time: SystemClock.wall_time() }
+    }
+
+    /// Creates a new planner for a trigger action, with the given trigger rows.
+    pub fn for_trigger(catalog: &'a C, trigger_rows: TriggerRows<'a>) -> Self {
+        Self { catalog, trigger_rows: Some(trigger_rows), wall_time: SystemClock.wall_time() }
+    }
+
+    /// Plans at the given wall-clock time, in nanoseconds since the Unix
+    /// epoch, instead of the system time.
+    pub fn at_time(mut self, wall_time: u64) -> Self {
+        self.wall_time = wall_time;
+        self
     }
 
     /// Builds a plan for an AST statement.
     pub fn build(&mut self, statement: ast::Statement) -> Result<Plan> {
         use ast::Statement::*;
         match statement {
-            CreateTable { name, columns } => self.build_create_table(name, columns),
+            CreateTable { name, columns, temporary, partition_by, ttl } => {
+                self.build_create_table(name, columns, temporary, partition_by, ttl)
+            }
             DropTable { name, if_exists } => Ok(Plan::DropTable { table: name, if_exists }),
-            Delete { table, r#where } => self.build_delete(table, r#where),
-            Insert { table, columns, values } => self.build_insert(table, columns, values),
-            Update { table, set, r#where } => self.build_update(table, set, r#where),
-            Select { select, from, r#where, group_by, having, order_by, offset, limit } => {
-                self.build_select(select, from, r#where, group_by, having, order_by, offset, limit)
+            CreateTrigger { name, table, timing, event, action } => {
+                self.build_create_trigger(name, table, timing, event, action)
             }
-
-            // Transaction and explain statements are handled by Session.
-            Begin { .. } | Commit | Rollback | Explain(_) => {
+            DropTrigger { name, table, if_exists } => {
+                Ok(Plan::DropTrigger { table, trigger: name, if_exists })
+            }
+            CreateChangefeed { table } => Ok(Plan::CreateChangefeed { table }),
+            DropChangefeed { table } => Ok(Plan::DropChangefeed { table }),
+            AlterTable { name, operation } => self.build_alter_table(name, operation),
+            CreateIndex { table, column, index_type, r#where } => {
+                self.build_create_index(table, column, index_type, r#where)
+            }
+            TruncateTable { name } => Ok(Plan::TruncateTable { table: name }),
+            CreateUser { name, password } => self.build_create_user(name, password),
+            DropUser { name, if_exists } => Ok(Plan::DropUser { name, if_exists }),
+            Grant { privileges, table, user } => {
+                let privileges = self.build_privileges(privileges, &table)?;
+                Ok(Plan::Grant { user, table, privileges })
+            }
+            Revoke { privileges, table, user } => {
+                let privileges = self.build_privileges(privileges, &table)?;
+                Ok(Plan::Revoke { user, table, privileges })
+            }
+            Delete { table, r#where, returning } => self.build_delete(table, r#where, returning),
+            Insert { table, columns, values, returning } => {
+                self.build_insert(table, columns, values, returning, false)
+            }
+            Import { table, columns, source: ast::ImportSource::Values(values) } => {
+                self.build_insert(table, columns, values, Vec::new(), true)
+            }
+            Import { table, columns, source } => self.build_import_file(table, columns, source),
+            Export { query, path, format, header } => {
+                self.build_export(*query, path, format, header)
+            }
+            Update { table, set, r#where, returning } => {
+                self.build_update(table, set, r#where, returning)
+            }
+            // AS OF is handled by Session, which runs the query at the version.
+            // Hints are applied by the optimizer, see optimizer::Options.
+            Select {
+                hints: _,
+                select,
+                from,
+                as_of,
+                r#where,
+                group_by,
+                having,
+                order_by,
+                offset,
+                limit,
+                for_update,
+            } => {
+                if for_update && as_of.is_some() {
+                    return errinput!("FOR UPDATE can't be used with AS OF SYSTEM TIME");
+                }
+                self.build_select(
+                    select, from, r#where, group_by, having, order_by, offset, limit, for_update,
+                )
+            }
+            ShowTables => self.build_show_tables(),
+            ShowCreateTable { name } => self.build_show_create_table(name),
+            Describe { name } => self.build_describe(name),
+
+            // Transaction, session variable, and explain statements are
+            // handled by Session.
+            Begin { .. }
+            | Commit
+            | Rollback
+            | Savepoint(_)
+            | RollbackToSavepoint(_)
+            | ReleaseSavepoint(_)
+            | AbortTransaction(_)
+            | SetTransaction { .. }
+            | Set { .. }
+            | Show { .. }
+            | Explain(_) => {
                 panic!("unexpected statement {statement:?}")
             }
         }
     }
 
     /// Builds a CREATE TABLE plan.
-    fn build_create_table(&self, name: String, columns: Vec<ast::Column>) -> Result<Plan> {
+    fn build_create_table(
+        &self,
+        name: String,
+        columns: Vec<ast::Column>,
+        temporary: bool,
+        partition_by: Option<ast::PartitionBy>,
+        ttl: Option<ast::Ttl>,
+    ) -> Result<Plan> {
         // Most schema validation happens during execution via Table.validate().
         // However, the AST specifies the primary key as a column field, while
         // the schema stores it as a column index, so we have to map that here.
@@ -52,68 +163,368 @@ impl<'a, C: Catalog> Planner<'a, C> {
         if columns.iter().filter(|c| c.primary_key).count() > 1 {
             return errinput!("multiple primary keys for table {name}");
         }
+        let mut generated = Vec::new();
         let columns = columns
             .into_iter()
             .map(|c| {
                 let nullable = c.nullable.unwrap_or(!c.primary_key);
+                let is_generated = c.generated.is_some();
+                generated.push(c.generated);
                 Ok(Column {
                     name: c.name,
                     datatype: c.datatype,
                     nullable,
                     default: match c.default {
-                        Some(expr) => Some(Self::evaluate_constant(expr)?),
-                        None if nullable => Some(Value::Null),
+                        Some(expr) => Some(self.evaluate_constant(expr)?),
+                        None if nullable && !is_generated => Some(Value::Null),
                         None => None,
                     },
                     unique: c.unique || c.primary_key,
                     index: (c.index || c.unique || c.references.is_some()) && !c.primary_key,
+                    index_type: IndexType::BTree,
+                    index_predicate: None,
+                    index_state: IndexState::Public,
                     references: c.references,
+                    collation: c.collation,
+                    generated: None,
                 })
             })
             .collect::<Result<_>>()?;
-        Ok(Plan::CreateTable { schema: Table { name, primary_key, columns } })
+        let mut schema = Table {
+            name,
+            primary_key,
+            columns,
+            partitions: Vec::new(),
+            triggers: Vec::new(),
+            changefeed: false,
+            ttl: None,
+        };
+
+        // Tables can only be partitioned by primary key, since rows are stored
+        // in primary key order and each partition must be a key range.
+        if let Some(partition_by) = partition_by {
+            let pkname = &schema.columns[primary_key].name;
+            if &partition_by.column != pkname {
+                return errinput!(
+                    "table {} can only be partitioned by primary key {pkname}",
+                    schema.name
+                );
+            }
+            for (name, bound) in partition_by.partitions {
+                let less_than = bound
+                    .map(|expr| {
+                        self.evaluate_constant(expr).map(|v| v.normalize_ref().into_owned())
+                    })
+                    .transpose()?;
+                schema.partitions.push(Partition { name, less_than });
+            }
+        }
+
+        // The TTL column is stored as a column index, and validated along with
+        // the rest of the schema.
+        if let Some(ttl) = ttl {
+            let Some(column) = schema.columns.iter().position(|c| c.name == ttl.column) else {
+                return errinput!("unknown TTL column {} in table {}", ttl.column, schema.name);
+            };
+            schema.ttl = Some(Ttl { interval: Interval::parse(&ttl.interval)?, column });
+        }
+
+        // Generated column expressions are built against the table's own
+        // columns, so they can only be built once all columns are known.
+        let scope = Scope::from_table(&schema)?;
+        for (i, expr) in generated.into_iter().enumerate() {
+            if let Some(expr) = expr {
+                schema.columns[i].generated = Some(self.build_expression(expr, &scope)?);
+            }
+        }
+        Ok(Plan::CreateTable { schema, temporary })
+    }
+
+    /// Builds a CREATE INDEX plan. The predicate is validated by the catalog.
+    fn build_create_index(
+        &self,
+        table: String,
+        column: String,
+        index_type: IndexType,
+        r#where: Option<ast::Expression>,
+    ) -> Result<Plan> {
+        let table = self.catalog.must_get_table(&table)?;
+        let scope = Scope::from_table(&table)?;
+        let column = scope.lookup_column(None, &column)?;
+        let predicate = r#where.map(|expr| self.build_expression(expr, &scope)).transpose()?;
+        Ok(Plan::CreateIndex { table, column, index_type, predicate })
+    }
+
+    /// Builds a CREATE TRIGGER plan. The action is validated by planning it
+    /// with NULL old and new rows, but it's stored as SQL text and planned
+    /// again with the actual rows whenever the trigger fires.
+    fn build_create_trigger(
+        &self,
+        name: String,
+        table: String,
+        timing: TriggerTiming,
+        event: TriggerEvent,
+        action: String,
+    ) -> Result<Plan> {
+        let table = self.catalog.must_get_table(&table)?;
+        let nulls = vec![Value::Null; table.columns.len()];
+        let mut planner = Planner::for_trigger(
+            self.catalog,
+            TriggerRows {
+                table: &table,
+                event,
+                old: event.has_old().then_some(&nulls),
+                new: event.has_new().then_some(&nulls),
+            },
+        );
+        match Parser::new(&action).parse_trigger_action()? {
+            ast::TriggerAction::Set(set) => {
+                if timing != TriggerTiming::Before || !event.has_new() {
+                    return errinput!("SET is only allowed in BEFORE INSERT and UPDATE triggers");
+                }
+                planner.build_trigger_set(set)?;
+            }
+            ast::TriggerAction::Statement(statement) => {
+                planner.build(*statement)?;
+            }
+        }
+        let trigger = Trigger { name, timing, event, action };
+        Ok(Plan::CreateTrigger { table: table.name, trigger })
+    }
+
+    /// Builds the column/expression pairs of a trigger SET action, which
+    /// modifies the new row. The expressions can only reference the trigger
+    /// rows, as constants.
+    pub fn build_trigger_set(
+        &self,
+        set: BTreeMap<String, Option<ast::Expression>>,
+    ) -> Result<Vec<(usize, Expression)>> {
+        let Some(rows) = &self.trigger_rows else {
+            return errinput!("SET actions are only allowed in triggers");
+        };
+        let table = rows.table;
+        let scope = Scope::new();
+        let mut expressions = Vec::with_capacity(set.len());
+        for (column, expr) in set {
+            let Some(index) = table.columns.iter().position(|c| c.name == column) else {
+                return errinput!("unknown column {column} in table {}", table.name);
+            };
+            if table.columns[index].generated.is_some() {
+                return errinput!("can't set generated column {column}");
+            }
+            let expr = match expr {
+                Some(expr) => self.build_expression(expr, &scope)?,
+                None => match &table.columns[index].default {
+                    Some(default) => Expression::Constant(default.clone()),
+                    None => return errinput!("column {column} has no default value"),
+                },
+            };
+            expressions.push((index, expr));
+        }
+        Ok(expressions)
+    }
+
+    /// Looks up an old.column or new.column reference to a trigger row, if
+    /// planning a trigger action and the name isn't shadowed by a table in the
+    /// scope.
+    fn lookup_trigger_row(
+        &self,
+        table: Option<&str>,
+        name: &str,
+        scope: &Scope,
+    ) -> Result<Option<Value>> {
+        let (Some(rows), Some(table @ ("old" | "new"))) = (&self.trigger_rows, table) else {
+            return Ok(None);
+        };
+        if scope.tables.contains(table) {
+            return Ok(None);
+        }
+        let Some(index) = rows.table.columns.iter().position(|c| c.name == name) else {
+            return errinput!("unknown column {table}.{name}");
+        };
+        let row = match table {
+            "old" => rows.old,
+            _ => rows.new,
+        };
+        let Some(row) = row else {
+            return errinput!("no {table} row in {} triggers", rows.event);
+        };
+        Ok(Some(row[index].clone()))
+    }
+
+    /// Builds an ALTER TABLE plan.
+    fn build_alter_table(&self, name: String, operation: ast::AlterTable) -> Result<Plan> {
+        match operation {
+            ast::AlterTable::DropPartition(partition) => {
+                Ok(Plan::DropPartition { table: name, partition })
+            }
+        }
+    }
+
+    /// Builds a CREATE USER plan. The password is hashed here, rather than
+    /// when the plan is executed, such that the random salt is the same on all
+    /// Raft nodes.
+    fn build_create_user(&self, name: String, password: String) -> Result<Plan> {
+        if name == User::ADMIN {
+            return errinput!("user {name} is reserved");
+        }
+        if password.is_empty() {
+            return errinput!("password can't be empty");
+        }
+        Ok(Plan::CreateUser { user: User::new(&name, &password) })
+    }
+
+    /// Builds the privileges of a GRANT or REVOKE plan, removing duplicates.
+    /// CREATE only applies to all tables, so ALL on a table omits it.
+    fn build_privileges(
+        &self,
+        mut privileges: Vec<Privilege>,
+        table: &Option<String>,
+    ) -> Result<Vec<Privilege>> {
+        if let Some(table) = table {
+            if privileges == Privilege::ALL {
+                privileges = Privilege::TABLE.to_vec();
+            } else if privileges.contains(&Privilege::Create) {
+                return errinput!("CREATE can only be granted on all tables, not {table}");
+            }
+        }
+        Ok(privileges.into_iter().sorted().dedup().collect())
+    }
+
+    /// Adds a filter that omits expired rows to a scan filter, if the table
+    /// has a TTL.
+    fn ttl_filter(&self, table: &Table, filter: Option<Expression>) -> Option<Expression> {
+        let Some(ttl) = &table.ttl else {
+            return filter;
+        };
+        let live = ttl.live_filter(&table.columns[ttl.column].datatype, self.wall_time);
+        match filter {
+            Some(filter) => Some(Expression::And(filter.into(), live.into())),
+            None => Some(live),
+        }
     }
 
     /// Builds a DELETE plan.
-    fn build_delete(&self, table: String, r#where: Option<ast::Expression>) -> Result<Plan> {
+    fn build_delete(
+        &self,
+        table: String,
+        r#where: Option<ast::Expression>,
+        returning: Vec<(ast::Expression, Option<String>)>,
+    ) -> Result<Plan> {
         let table = self.catalog.must_get_table(&table)?;
         let scope = Scope::from_table(&table)?;
-        let filter = r#where.map(|expr| Self::build_expression(expr, &scope)).transpose()?;
+        let filter = r#where.map(|expr| self.build_expression(expr, &scope)).transpose()?;
+        let filter = self.ttl_filter(&table, filter);
+        let returning = self.build_returning(returning, &scope)?;
         Ok(Plan::Delete {
             table: table.name.clone(),
             primary_key: table.primary_key,
-            source: Node::Scan { table, alias: None, filter },
+            source: Node::Scan { table, alias: None, filter, partitions: None },
+            returning,
         })
     }
 
-    /// Builds an INSERT plan.
+    /// Builds the RETURNING expressions of a write plan, along with their
+    /// column labels. They're evaluated against the written table rows, given
+    /// by the scope. * expands to all table columns.
+    fn build_returning(
+        &self,
+        returning: Vec<(ast::Expression, Option<String>)>,
+        scope: &Scope,
+    ) -> Result<Vec<(Expression, Label)>> {
+        let returning: Vec<_> = returning
+            .into_iter()
+            .flat_map(|(expr, alias)| match expr {
+                ast::Expression::All => {
+                    Either::Left(scope.columns.iter().map(|label| (label.clone().into(), None)))
+                }
+                expr => Either::Right(std::iter::once((expr, alias))),
+            })
+            .collect();
+        let labels = scope.project(&returning).columns;
+        returning
+            .into_iter()
+            .zip(labels)
+            .map(|((expr, _), label)| Ok((self.build_expression(expr, scope)?, label)))
+            .collect()
+    }
+
+    /// Builds an INSERT plan, or an IMPORT plan if bulk is true.
     fn build_insert(
         &self,
         table: String,
         columns: Option<Vec<String>>,
         values: Vec<Vec<ast::Expression>>,
+        returning: Vec<(ast::Expression, Option<String>)>,
+        bulk: bool,
     ) -> Result<Plan> {
         let table = self.catalog.must_get_table(&table)?;
-        let mut column_map = None;
-        if let Some(columns) = columns {
-            let column_map = column_map.insert(HashMap::new());
-            for (vidx, name) in columns.into_iter().enumerate() {
-                let Some(cidx) = table.columns.iter().position(|c| c.name == name) else {
-                    return errinput!("unknown column {name} in table {}", table.name);
-                };
-                if column_map.insert(cidx, vidx).is_some() {
-                    return errinput!("column {name} given multiple times");
-                }
-            }
-        }
+        let column_map = columns
+            .map(|columns| -> Result<HashMap<usize, usize>> {
+                let indexes = table.write_columns(&columns)?;
+                Ok(indexes.into_iter().enumerate().map(|(vidx, cidx)| (cidx, vidx)).collect())
+            })
+            .transpose()?;
         let scope = Scope::new();
         let rows = values
             .into_iter()
             .map(|exprs| {
-                exprs.into_iter().map(|expr| Self::build_expression(expr, &scope)).collect()
+                exprs.into_iter().map(|expr| self.build_expression(expr, &scope)).collect()
             })
             .try_collect()?;
-        Ok(Plan::Insert { table, column_map, source: Node::Values { rows } })
+        let source = Node::Values { rows };
+        match bulk {
+            true => Ok(Plan::Import { table, column_map, source }),
+            false => {
+                let returning = self.build_returning(returning, &Scope::from_table(&table)?)?;
+                Ok(Plan::Insert { table, column_map, source, returning })
+            }
+        }
+    }
+
+    /// Builds an IMPORT plan from a file or client data. JSON objects are
+    /// keyed by column name, so they can't be given a column list or header.
+    fn build_import_file(
+        &self,
+        table: String,
+        columns: Option<Vec<String>>,
+        source: ast::ImportSource,
+    ) -> Result<Plan> {
+        let table = self.catalog.must_get_table(&table)?;
+        let columns = columns.map(|columns| table.write_columns(&columns)).transpose()?;
+        let (input, format, header) = match source {
+            ast::ImportSource::File { path, format, header } => {
+                (ImportInput::File(path), format, header)
+            }
+            ast::ImportSource::Stdin { data, format, header } => {
+                (ImportInput::Data(data), format, header)
+            }
+            ast::ImportSource::Values(_) => unreachable!("VALUES planned as insert"),
+        };
+        if format == FileFormat::Json && (columns.is_some() || header) {
+            return errinput!("JSON imports can't have a column list or header");
+        }
+        Ok(Plan::ImportFile { table, columns, input, format, header })
+    }
+
+    /// Builds an EXPORT plan from a SELECT query.
+    fn build_export(
+        &mut self,
+        query: ast::Statement,
+        path: String,
+        format: FileFormat,
+        header: bool,
+    ) -> Result<Plan> {
+        if let ast::Statement::Select { for_update: true, .. } = query {
+            return errinput!("EXPORT can't use FOR UPDATE");
+        }
+        if format == FileFormat::Json && header {
+            return errinput!("JSON exports can't have a header");
+        }
+        match self.build(query)? {
+            Plan::Select(root) => Ok(Plan::Export { root, path, format, header }),
+            plan => unreachable!("SELECT planned as {plan:?}"),
+        }
     }
 
     /// Builds an UPDATE plan.
@@ -122,15 +533,21 @@ impl<'a, C: Catalog> Planner<'a, C> {
         table: String,
         set: BTreeMap<String, Option<ast::Expression>>,
         r#where: Option<ast::Expression>,
+        returning: Vec<(ast::Expression, Option<String>)>,
     ) -> Result<Plan> {
         let table = self.catalog.must_get_table(&table)?;
         let scope = Scope::from_table(&table)?;
-        let filter = r#where.map(|expr| Self::build_expression(expr, &scope)).transpose()?;
+        let filter = r#where.map(|expr| self.build_expression(expr, &scope)).transpose()?;
+        let filter = self.ttl_filter(&table, filter);
+        let returning = self.build_returning(returning, &scope)?;
         let mut expressions = Vec::with_capacity(set.len());
         for (column, expr) in set {
             let index = scope.lookup_column(None, &column)?;
+            if table.columns[index].generated.is_some() {
+                return errinput!("can't update generated column {column}");
+            }
             let expr = match expr {
-                Some(expr) => Self::build_expression(expr, &scope)?,
+                Some(expr) => self.build_expression(expr, &scope)?,
                 None => match &table.columns[index].default {
                     Some(default) => Expression::Constant(default.clone()),
                     None => return errinput!("column {column} has no default value"),
@@ -138,14 +555,74 @@ impl<'a, C: Catalog> Planner<'a, C> {
             };
             expressions.push((index, expr));
         }
+
+        // Recompute generated columns that depend on the updated columns. The
+        // SET expressions are evaluated against the old row, so substitute
+        // them for the column references in the generated expressions.
+        let set: HashMap<usize, Expression> = expressions.iter().cloned().collect();
+        for (index, column) in table.columns.iter().enumerate() {
+            let Some(generated) = &column.generated else { continue };
+            if !generated.contains(&|e| matches!(e, Expression::Column(i) if set.contains_key(i))) {
+                continue;
+            }
+            let expr = generated.clone().transform(&Ok, &|e| match e {
+                Expression::Column(i) if set.contains_key(&i) => Ok(set[&i].clone()),
+                e => Ok(e),
+            })?;
+            expressions.push((index, expr));
+        }
         Ok(Plan::Update {
             table: table.clone(),
             primary_key: table.primary_key,
-            source: Node::Scan { table, alias: None, filter },
+            source: Node::Scan { table, alias: None, filter, partitions: None },
             expressions,
+            returning,
         })
     }
 
+    /// Builds a SHOW TABLES plan. This is a SELECT query against the
+    /// information_schema.tables virtual table.
+    fn build_show_tables(&self) -> Result<Plan> {
+        let column = ast::Expression::Column(None, "table_name".into());
+        let select = vec![(column, Some("table_name".into()))];
+        let from = vec![ast::From::Table { name: "information_schema.tables".into(), alias: None }];
+        self.build_select(select, from, None, vec![], None, vec![], None, None, false)
+    }
+
+    /// Builds a SHOW CREATE TABLE plan. This is a constant SELECT query that
+    /// returns the table's CREATE TABLE statement.
+    fn build_show_create_table(&self, name: String) -> Result<Plan> {
+        let table = self.catalog.must_get_table(&name)?;
+        let literal = ast::Expression::Literal(ast::Literal::String(table.to_string()));
+        let select = vec![(literal, Some("create_table".into()))];
+        self.build_select(select, vec![], None, vec![], None, vec![], None, None, false)
+    }
+
+    /// Builds a DESCRIBE plan. This is a SELECT query against the
+    /// information_schema.columns virtual table.
+    fn build_describe(&self, name: String) -> Result<Plan> {
+        self.catalog.must_get_table(&name)?;
+        let column = |name: &str, alias: &str| {
+            (ast::Expression::Column(None, name.into()), Some(alias.into()))
+        };
+        let select = vec![
+            column("column_name", "column"),
+            column("data_type", "type"),
+            column("is_nullable", "nullable"),
+            column("column_default", "default"),
+            column("is_primary_key", "primary_key"),
+            column("is_unique", "unique"),
+            column("referenced_table", "references"),
+        ];
+        let from =
+            vec![ast::From::Table { name: "information_schema.columns".into(), alias: None }];
+        let r#where = ast::Expression::Operator(ast::Operator::Equal(
+            ast::Expression::Column(None, "table_name".into()).into(),
+            ast::Expression::Literal(ast::Literal::String(name)).into(),
+        ));
+        self.build_select(select, from, Some(r#where), vec![], None, vec![], None, None, false)
+    }
+
     /// Builds a SELECT plan.
     #[allow(clippy::too_many_arguments)]
     fn build_select(
@@ -158,6 +635,7 @@ impl<'a, C: Catalog> Planner<'a, C> {
         order_by: Vec<(ast::Expression, ast::Direction)>,
         offset: Option<ast::Expression>,
         limit: Option<ast::Expression>,
+        for_update: bool,
     ) -> Result<Plan> {
         let mut scope = Scope::new();
 
@@ -191,14 +669,29 @@ impl<'a, C: Catalog> Planner<'a, C> {
             }
         }
 
+        // For FOR UPDATE, the rows must be table rows, so they can be locked.
+        let lock_table = match &node {
+            Node::Scan { table, .. } if for_update => Some(table.clone()),
+            _ if for_update => return errinput!("FOR UPDATE requires a single table"),
+            _ => None,
+        };
+
         // Build WHERE clause.
+        let mut predicate = None;
         if let Some(r#where) = r#where {
-            let predicate = Self::build_expression(r#where, &scope)?;
-            node = Node::Filter { source: Box::new(node), predicate };
+            let expr = self.build_expression(r#where, &scope)?;
+            predicate = Some(expr.clone());
+            node = Node::Filter { source: Box::new(node), predicate: expr };
         }
 
         // Build aggregate functions and GROUP BY clause.
         let aggregates = Self::collect_aggregates(&select, &having, &order_by);
+        if let Some(table) = lock_table {
+            if !group_by.is_empty() || !aggregates.is_empty() {
+                return errinput!("FOR UPDATE can't be used with GROUP BY or aggregates");
+            }
+            node = Node::Lock { source: Box::new(node), table, predicate };
+        }
         if !group_by.is_empty() || !aggregates.is_empty() {
             node = self.build_aggregate(node, group_by, aggregates, &mut scope)?;
         }
@@ -212,7 +705,7 @@ impl<'a, C: Catalog> Planner<'a, C> {
             let mut expressions = Vec::with_capacity(select.len());
             let mut aliases = Vec::with_capacity(select.len());
             for (expr, alias) in select {
-                expressions.push(Self::build_expression(expr, &scope)?);
+                expressions.push(self.build_expression(expr, &scope)?);
                 aliases.push(Label::from(alias));
             }
 
@@ -230,7 +723,7 @@ impl<'a, C: Catalog> Planner<'a, C> {
             if scope.aggregates.is_empty() {
                 return errinput!("HAVING requires GROUP BY or aggregate function");
             }
-            let predicate = Self::build_expression(having, &scope)?;
+            let predicate = self.build_expression(having, &scope)?;
             node = Node::Filter { source: Box::new(node), predicate };
         }
 
@@ -238,14 +731,14 @@ impl<'a, C: Catalog> Planner<'a, C> {
         if !order_by.is_empty() {
             let key = order_by
                 .into_iter()
-                .map(|(expr, dir)| Ok((Self::build_expression(expr, &scope)?, dir.into())))
+                .map(|(expr, dir)| Ok((self.build_collated(expr, &scope)?, dir.into())))
                 .collect::<Result<_>>()?;
             node = Node::Order { source: Box::new(node), key };
         }
 
         // Build OFFSET clause.
         if let Some(offset) = offset {
-            let offset = match Self::evaluate_constant(offset)? {
+            let offset = match self.evaluate_constant(offset)? {
                 Value::Integer(offset) if offset >= 0 => offset as usize,
                 offset => return errinput!("invalid offset {offset}"),
             };
@@ -254,7 +747,7 @@ impl<'a, C: Catalog> Planner<'a, C> {
 
         // Build LIMIT clause.
         if let Some(limit) = limit {
-            let limit = match Self::evaluate_constant(limit)? {
+            let limit = match self.evaluate_constant(limit)? {
                 Value::Integer(limit) if limit >= 0 => limit as usize,
                 limit => return errinput!("invalid limit {limit}"),
             };
@@ -272,6 +765,9 @@ impl<'a, C: Catalog> Planner<'a, C> {
     /// Builds a FROM clause consisting of one or more items. Each item is
     /// either a table or a join of two or more tables. All items are implicitly
     /// joined, e.g. "SELECT * FROM a, b" is an implicit full join of a and b.
+    ///
+    /// UNNEST items are lateral: they're applied to the preceding items, and
+    /// can reference their columns, e.g. "SELECT * FROM a, UNNEST(a.tags)".
     fn build_from_clause(&self, from: Vec<ast::From>, scope: &mut Scope) -> Result<Node> {
         // Build the first FROM item. A FROM clause must have at least one.
         let mut items = from.into_iter();
@@ -282,6 +778,10 @@ impl<'a, C: Catalog> Planner<'a, C> {
 
         // Build and implicitly join additional items.
         for from in items {
+            if let ast::From::Unnest { expression, alias } = from {
+                node = self.build_unnest(node, expression, alias, scope)?;
+                continue;
+            }
             let right = self.build_from(from, scope)?;
             node = Node::NestedLoopJoin {
                 left: Box::new(node),
@@ -302,10 +802,55 @@ impl<'a, C: Catalog> Planner<'a, C> {
 
         let node = match from {
             // A full table scan.
+            ast::From::Table { name, alias } if name.starts_with(information_schema::PREFIX) => {
+                let name = &name[information_schema::PREFIX.len()..];
+                let Some(table) = information_schema::get_table(name) else {
+                    return errinput!("table information_schema.{name} does not exist");
+                };
+                let rows = information_schema::scan(&table, self.catalog)?;
+                scope.add_table(&table, alias.as_deref())?;
+                Node::SchemaScan { schema: "information_schema".to_string(), table, rows, alias }
+            }
+            ast::From::Table { name, alias } if name.starts_with(system::PREFIX) => {
+                let name = &name[system::PREFIX.len()..];
+                let Some(table) = system::get_table(name) else {
+                    return errinput!("table system.{name} does not exist");
+                };
+                let rows = system::scan(&table, self.catalog)?;
+                scope.add_table(&table, alias.as_deref())?;
+                Node::SchemaScan { schema: "system".to_string(), table, rows, alias }
+            }
             ast::From::Table { name, alias } => {
                 let table = self.catalog.must_get_table(&name)?;
                 scope.add_table(&table, alias.as_deref())?;
-                Node::Scan { table, alias, filter: None }
+                let filter = self.ttl_filter(&table, None);
+                Node::Scan { table, alias, filter, partitions: None }
+            }
+
+            // A standalone UNNEST, with a blank source row. It can only use
+            // constant expressions.
+            ast::From::Unnest { expression, alias } => {
+                let source = Node::Values { rows: vec![vec![]] };
+                self.build_unnest(source, expression, alias, &mut scope)?
+            }
+
+            // A lateral UNNEST join, which applies the UNNEST to the left rows.
+            // An inner join predicate is applied as a filter. Outer joins are
+            // not supported.
+            ast::From::Join { left, right, r#type, predicate }
+                if matches!(*right, ast::From::Unnest { .. }) =>
+            {
+                if r#type.is_outer() {
+                    return errinput!("UNNEST can't be used in outer joins");
+                }
+                let ast::From::Unnest { expression, alias } = *right else { unreachable!() };
+                let left = self.build_from(*left, &mut scope)?;
+                let mut node = self.build_unnest(left, expression, alias, &mut scope)?;
+                if let Some(predicate) = predicate {
+                    let predicate = self.build_expression(predicate, &scope)?;
+                    node = Node::Filter { source: Box::new(node), predicate };
+                }
+                node
             }
 
             // A two-way join. The left or right nodes may be chained joins.
@@ -321,7 +866,7 @@ impl<'a, C: Catalog> Planner<'a, C> {
                 let (left_size, right_size) = (left.columns(), right.columns());
 
                 // Build the join node.
-                let predicate = predicate.map(|e| Self::build_expression(e, &scope)).transpose()?;
+                let predicate = predicate.map(|e| self.build_expression(e, &scope)).transpose()?;
                 let outer = r#type.is_outer();
                 let mut node = Node::NestedLoopJoin { left, right, predicate, outer };
 
@@ -340,6 +885,26 @@ impl<'a, C: Catalog> Planner<'a, C> {
         Ok(node)
     }
 
+    /// Builds an UNNEST node over the given source, whose columns are in the
+    /// scope. The element column is added to the scope, using the alias (or
+    /// unnest) as both table and column name.
+    fn build_unnest(
+        &self,
+        source: Node,
+        expression: ast::Expression,
+        alias: Option<String>,
+        scope: &mut Scope,
+    ) -> Result<Node> {
+        let expression = self.build_expression(expression, scope)?;
+        let name = alias.clone().unwrap_or("unnest".to_string());
+        if scope.tables.contains(&name) {
+            return errinput!("duplicate table name {name}");
+        }
+        scope.add_column(Label::Qualified(name.clone(), name.clone()));
+        scope.tables.insert(name);
+        Ok(Node::Unnest { source: Box::new(source), expression, alias })
+    }
+
     /// Builds an aggregate node, which computes aggregates for a set of GROUP
     /// BY buckets. The aggregate functions have been collected from the SELECT,
     /// HAVING, and ORDER BY clauses.
@@ -367,12 +932,13 @@ impl<'a, C: Catalog> Planner<'a, C> {
         group_by.retain(|expr| child_scope.add_aggregate(expr, scope).is_some());
         aggregates.retain(|expr| child_scope.add_aggregate(expr, scope).is_some());
 
-        // Build the node from the remaining unique expressions.
+        // Build the node from the remaining unique expressions. GROUP BY
+        // expressions are grouped by their collation, if any.
         let group_by =
-            group_by.into_iter().map(|expr| Self::build_expression(expr, scope)).try_collect()?;
+            group_by.into_iter().map(|expr| self.build_collated(expr, scope)).try_collect()?;
         let aggregates = aggregates
             .into_iter()
-            .map(|expr| Self::build_aggregate_function(expr, scope))
+            .map(|expr| self.build_aggregate_function(expr, scope))
             .try_collect()?;
 
         *scope = child_scope;
@@ -380,7 +946,7 @@ impl<'a, C: Catalog> Planner<'a, C> {
     }
 
     /// Builds an aggregate function from an AST expression.
-    fn build_aggregate_function(expr: ast::Expression, scope: &Scope) -> Result<Aggregate> {
+    fn build_aggregate_function(&self, expr: ast::Expression, scope: &Scope) -> Result<Aggregate> {
         let ast::Expression::Function(name, mut args) = expr else {
             panic!("aggregate expression must be function");
         };
@@ -393,7 +959,7 @@ impl<'a, C: Catalog> Planner<'a, C> {
         // Special-case COUNT(*) since expressions don't support tuples.
         let expr = match (name.as_str(), args.remove(0)) {
             ("count", ast::Expression::All) => Expression::Constant(Value::Boolean(true)),
-            (_, arg) => Self::build_expression(arg, scope)?,
+            (_, arg) => self.build_expression(arg, scope)?,
         };
         Ok(match name.as_str() {
             "avg" => Aggregate::Average(expr),
@@ -484,7 +1050,7 @@ impl<'a, C: Catalog> Planner<'a, C> {
 
     /// Builds an expression from an AST expression, looking up columns and
     /// aggregate expressions in the scope.
-    pub fn build_expression(expr: ast::Expression, scope: &Scope) -> Result<Expression> {
+    pub fn build_expression(&self, expr: ast::Expression, scope: &Scope) -> Result<Expression> {
         use Expression::*;
 
         // Look up aggregate functions or GROUP BY expressions. These were added
@@ -495,7 +1061,29 @@ impl<'a, C: Catalog> Planner<'a, C> {
 
         // Helper for building a boxed expression.
         let build = |expr: Box<ast::Expression>| -> Result<Box<Expression>> {
-            Ok(Box::new(Self::build_expression(*expr, scope)?))
+            Ok(Box::new(self.build_expression(*expr, scope)?))
+        };
+
+        // Helper for building the operands of a comparison, folding them with
+        // the collation of either operand if any (see Scope.collation()).
+        let build_compare = |lhs: Box<ast::Expression>, rhs: Box<ast::Expression>| {
+            let collation = scope.collation(&[&lhs, &rhs]);
+            let (lhs, rhs) = (build(lhs)?, build(rhs)?);
+            Ok::<_, Error>(match collation {
+                Collation::Binary => (lhs, rhs),
+                collation => (Box::new(Collate(lhs, collation)), Box::new(Collate(rhs, collation))),
+            })
+        };
+
+        // Helper for building a regular expression pattern. Constant patterns
+        // are compiled during planning, such that invalid patterns error even
+        // if there are no rows to match.
+        let build_pattern = |expr: Box<ast::Expression>| -> Result<Box<Expression>> {
+            let expr = build(expr)?;
+            if let Constant(Value::String(pattern)) = expr.as_ref() {
+                Expression::compile_regex(pattern)?;
+            }
+            Ok(expr)
         };
 
         Ok(match expr {
@@ -509,26 +1097,72 @@ impl<'a, C: Catalog> Planner<'a, C> {
                 ast::Literal::Integer(i) => Value::Integer(i),
                 ast::Literal::Float(f) => Value::Float(f),
                 ast::Literal::String(s) => Value::String(s),
+                ast::Literal::Interval(i) => Value::Interval(i),
+                ast::Literal::Timestamp(t) => Value::Timestamp(t),
             }),
+            ast::Expression::Parameter(index) => Parameter(index),
+            ast::Expression::Array(exprs) => {
+                Array(exprs.into_iter().map(|e| self.build_expression(e, scope)).try_collect()?)
+            }
             ast::Expression::Column(table, name) => {
-                Column(scope.lookup_column(table.as_deref(), &name)?)
+                match self.lookup_trigger_row(table.as_deref(), &name, scope)? {
+                    Some(value) => Constant(value),
+                    None => Column(scope.lookup_column(table.as_deref(), &name)?),
+                }
             }
             ast::Expression::Function(name, mut args) => match (name.as_str(), args.len()) {
                 // NB: aggregate functions are processed above.
                 ("sqrt", 1) => SquareRoot(build(Box::new(args.remove(0)))?),
-                (name, n) => return errinput!("unknown function {name} with {n} arguments"),
+                ("age", 2) => {
+                    let (rhs, lhs) = (args.remove(1), args.remove(0));
+                    Age(build(Box::new(lhs))?, build(Box::new(rhs))?)
+                }
+                // age(timestamp) is the age since midnight today, like Postgres.
+                ("age", 1) => {
+                    let today = Timestamp::from_wall_time(self.wall_time)?.date();
+                    Age(Constant(Value::Timestamp(today)).into(), build(Box::new(args.remove(0)))?)
+                }
+                ("json", 1) => JsonParse(build(Box::new(args.remove(0)))?),
+                ("json_extract", 2) => {
+                    let (path, json) = (args.remove(1), args.remove(0));
+                    JsonExtractPath(build(Box::new(json))?, build(Box::new(path))?)
+                }
+                ("json_typeof", 1) => JsonTypeof(build(Box::new(args.remove(0)))?),
+                ("regexp_match", 2) => {
+                    let (pattern, string) = (args.remove(1), args.remove(0));
+                    RegexpMatch(build(Box::new(string))?, build_pattern(Box::new(pattern))?)
+                }
+                (name, n) => match self.catalog.get_function(name)? {
+                    Some(function) if function.args.len() == n => {
+                        let args = args.into_iter().map(|arg| self.build_expression(arg, scope));
+                        Function(function, args.try_collect()?)
+                    }
+                    Some(_) | None => {
+                        return errinput!("unknown function {name} with {n} arguments");
+                    }
+                },
             },
             ast::Expression::Operator(op) => match op {
                 ast::Operator::And(lhs, rhs) => And(build(lhs)?, build(rhs)?),
                 ast::Operator::Not(expr) => Not(build(expr)?),
                 ast::Operator::Or(lhs, rhs) => Or(build(lhs)?, build(rhs)?),
 
-                ast::Operator::Equal(lhs, rhs) => Equal(build(lhs)?, build(rhs)?),
-                ast::Operator::GreaterThan(lhs, rhs) => GreaterThan(build(lhs)?, build(rhs)?),
-                ast::Operator::GreaterThanOrEqual(lhs, rhs) => Or(
-                    GreaterThan(build(lhs.clone())?, build(rhs.clone())?).into(),
-                    Equal(build(lhs)?, build(rhs)?).into(),
-                ),
+                ast::Operator::Equal(lhs, rhs) => {
+                    let (lhs, rhs) = build_compare(lhs, rhs)?;
+                    Equal(lhs, rhs)
+                }
+                ast::Operator::GreaterThan(lhs, rhs) => {
+                    let (lhs, rhs) = build_compare(lhs, rhs)?;
+                    GreaterThan(lhs, rhs)
+                }
+                ast::Operator::GreaterThanOrEqual(lhs, rhs) => {
+                    let (lhs, rhs) = build_compare(lhs, rhs)?;
+                    Or(GreaterThan(lhs.clone(), rhs.clone()).into(), Equal(lhs, rhs).into())
+                }
+                ast::Operator::JsonExtract(lhs, rhs) => JsonExtract(build(lhs)?, build(rhs)?),
+                ast::Operator::JsonExtractText(lhs, rhs) => {
+                    JsonExtractText(build(lhs)?, build(rhs)?)
+                }
                 ast::Operator::Is(expr, literal) => {
                     let expr = build(expr)?;
                     let value = match literal {
@@ -538,13 +1172,24 @@ impl<'a, C: Catalog> Planner<'a, C> {
                     };
                     Is(expr, value)
                 }
-                ast::Operator::LessThan(lhs, rhs) => LessThan(build(lhs)?, build(rhs)?),
-                ast::Operator::LessThanOrEqual(lhs, rhs) => Or(
-                    LessThan(build(lhs.clone())?, build(rhs.clone())?).into(),
-                    Equal(build(lhs)?, build(rhs)?).into(),
-                ),
-                ast::Operator::Like(lhs, rhs) => Like(build(lhs)?, build(rhs)?),
-                ast::Operator::NotEqual(lhs, rhs) => Not(Equal(build(lhs)?, build(rhs)?).into()),
+                ast::Operator::LessThan(lhs, rhs) => {
+                    let (lhs, rhs) = build_compare(lhs, rhs)?;
+                    LessThan(lhs, rhs)
+                }
+                ast::Operator::LessThanOrEqual(lhs, rhs) => {
+                    let (lhs, rhs) = build_compare(lhs, rhs)?;
+                    Or(LessThan(lhs.clone(), rhs.clone()).into(), Equal(lhs, rhs).into())
+                }
+                ast::Operator::Like(lhs, rhs) => {
+                    let (lhs, rhs) = build_compare(lhs, rhs)?;
+                    Like(lhs, rhs)
+                }
+                ast::Operator::Match(lhs, rhs) => Match(build(lhs)?, build(rhs)?),
+                ast::Operator::Regexp(lhs, rhs) => Regexp(build(lhs)?, build_pattern(rhs)?),
+                ast::Operator::NotEqual(lhs, rhs) => {
+                    let (lhs, rhs) = build_compare(lhs, rhs)?;
+                    Not(Equal(lhs, rhs).into())
+                }
 
                 ast::Operator::Add(lhs, rhs) => Add(build(lhs)?, build(rhs)?),
                 ast::Operator::Divide(lhs, rhs) => Divide(build(lhs)?, build(rhs)?),
@@ -555,13 +1200,38 @@ impl<'a, C: Catalog> Planner<'a, C> {
                 ast::Operator::Multiply(lhs, rhs) => Multiply(build(lhs)?, build(rhs)?),
                 ast::Operator::Negate(expr) => Negate(build(expr)?),
                 ast::Operator::Subtract(lhs, rhs) => Subtract(build(lhs)?, build(rhs)?),
+
+                ast::Operator::All(comparison, lhs, rhs) => {
+                    let (lhs, rhs) = build_compare(lhs, rhs)?;
+                    All(comparison, lhs, rhs)
+                }
+                ast::Operator::Any(comparison, lhs, rhs) => {
+                    let (lhs, rhs) = build_compare(lhs, rhs)?;
+                    Any(comparison, lhs, rhs)
+                }
+                ast::Operator::Index(lhs, rhs) => Index(build(lhs)?, build(rhs)?),
+
+                // COLLATE only affects comparisons, sorting, and grouping,
+                // which apply it via Scope.collation(). Otherwise, it's a noop.
+                ast::Operator::Collate(expr, _) => *build(expr)?,
             },
         })
     }
 
+    /// Builds an expression used for sorting or grouping, folding it with its
+    /// collation if any (see Scope.collation()).
+    fn build_collated(&self, expr: ast::Expression, scope: &Scope) -> Result<Expression> {
+        let collation = scope.collation(&[&expr]);
+        let expr = self.build_expression(expr, scope)?;
+        Ok(match collation {
+            Collation::Binary => expr,
+            collation => Expression::Collate(Box::new(expr), collation),
+        })
+    }
+
     /// Builds and evaluates a constant AST expression. Errors on column refs.
-    fn evaluate_constant(expr: ast::Expression) -> Result<Value> {
-        Self::build_expression(expr, &Scope::new())?.evaluate(None)
+    fn evaluate_constant(&self, expr: ast::Expression) -> Result<Value> {
+        self.build_expression(expr, &Scope::new())?.evaluate(None)
     }
 }
 
@@ -597,6 +1267,9 @@ pub struct Scope {
     /// expressions through SELECT projection nodes if the expressions aren't
     /// already projected. They should be removed before emitting results.
     hidden: HashSet<usize>,
+    /// Column collations, by column index. Only contains non-binary
+    /// collations, i.e. case-insensitive STRING columns.
+    collations: HashMap<usize, Collation>,
 }
 
 impl Scope {
@@ -627,7 +1300,10 @@ impl Scope {
             return errinput!("duplicate table name {name}");
         }
         for column in &table.columns {
-            self.add_column(Label::Qualified(name.to_string(), column.name.clone()));
+            let index = self.add_column(Label::Qualified(name.to_string(), column.name.clone()));
+            if column.collation != Collation::Binary {
+                self.collations.insert(index, column.collation);
+            }
         }
         self.tables.insert(name.to_string());
         Ok(())
@@ -675,6 +1351,26 @@ impl Scope {
         errinput!("unknown column {}", fmtname())
     }
 
+    /// Determines the collation to use when comparing the given expressions.
+    /// An explicit COLLATE takes precedence, otherwise the collation of any
+    /// column references is used, falling back to the binary collation.
+    fn collation(&self, exprs: &[&ast::Expression]) -> Collation {
+        let explicit = exprs.iter().find_map(|expr| match expr {
+            ast::Expression::Operator(ast::Operator::Collate(_, collation)) => Some(*collation),
+            _ => None,
+        });
+        let column = || {
+            exprs.iter().find_map(|expr| match expr {
+                ast::Expression::Column(table, name) => self
+                    .lookup_column(table.as_deref(), name)
+                    .ok()
+                    .and_then(|index| self.collations.get(&index).copied()),
+                _ => None,
+            })
+        };
+        explicit.or_else(column).unwrap_or_default()
+    }
+
     /// Adds an aggregate expression to the scope, returning the new column
     /// index or None if the expression already exists. This is either an
     /// aggregate function or a GROUP BY expression, used to look up the
@@ -684,16 +1380,22 @@ impl Scope {
             return None;
         }
         // If this is a simple column reference (i.e. GROUP BY foo), pass
-        // through the column label from the parent scope for lookups.
+        // through the column label and collation from the parent scope for
+        // lookups.
         let mut label = Label::None;
+        let mut collation = None;
         if let ast::Expression::Column(table, column) = expr {
             // Ignore errors, they will be emitted when building the expression.
             if let Ok(index) = parent.lookup_column(table.as_deref(), column.as_str()) {
                 label = parent.columns[index].clone();
+                collation = parent.collations.get(&index).copied();
             }
         }
         let index = self.add_column(label);
         self.aggregates.insert(expr.clone(), index);
+        if let Some(collation) = collation {
+            self.collations.insert(index, collation);
+        }
         Some(index)
     }
 
@@ -715,6 +1417,9 @@ impl Scope {
         if hide || parent.hidden.contains(&parent_index) {
             self.hidden.insert(index);
         }
+        if let Some(collation) = parent.collations.get(&parent_index) {
+            self.collations.insert(index, *collation);
+        }
         index
     }
 
@@ -734,6 +1439,7 @@ impl Scope {
             self.aggregates.entry(expr).or_insert(index + offset);
         }
         self.hidden.extend(scope.hidden.into_iter().map(|index| index + offset));
+        self.collations.extend(scope.collations.into_iter().map(|(i, c)| (i + offset, c)));
         Ok(())
     }
 
@@ -757,6 +1463,11 @@ impl Scope {
                 }
             }
             let index = child.add_column(label);
+            // Column references and COLLATE expressions retain their collation.
+            match self.collation(&[expr]) {
+                Collation::Binary => {}
+                collation => _ = child.collations.insert(index, collation),
+            }
             // If this is an aggregate query, then all projected expressions
             // must also be aggregates by definition (an aggregate node can only
             // emit aggregate functions or GROUP BY expressions).
@@ -793,6 +1504,7 @@ impl Scope {
         self.unqualified.iter_mut().for_each(|(_, vec)| vec.retain(|i| !hidden.contains(i)));
         self.unqualified.retain(|_, vec| !vec.is_empty());
         self.aggregates.retain(|_, index| !hidden.contains(index));
+        self.collations.retain(|index, _| !hidden.contains(index));
         Some(hidden)
     }
 
@@ -0,0 +1,75 @@
+//! The system schema exposes the node's in-memory statement and table
+//! statistics (see engine::Statistics) as virtual, read-only tables under the
+//! system prefix, e.g. "SELECT * FROM system.statement_statistics". Like
+//! information schema tables, the rows are generated during planning. They're
+//! empty if statistics aren't enabled.
+
+use super::information_schema::{column, virtual_table};
+use crate::error::Result;
+use crate::sql::engine::Catalog;
+use crate::sql::types::{DataType, Row, Table, Value};
+
+/// The table name prefix for system tables.
+pub const PREFIX: &str = "system.";
+
+/// Returns the virtual table schema for the given system table name (without
+/// the prefix), or None if it does not exist.
+pub fn get_table(name: &str) -> Option<Table> {
+    let columns = match name {
+        "statement_statistics" => vec![
+            column("fingerprint", DataType::String, false),
+            column("count", DataType::Integer, false),
+            column("errors", DataType::Integer, false),
+            column("rows", DataType::Integer, false),
+            column("total_ms", DataType::Float, false),
+            column("mean_ms", DataType::Float, false),
+            column("max_ms", DataType::Float, false),
+        ],
+        "table_statistics" => vec![
+            column("table_name", DataType::String, false),
+            column("rows_read", DataType::Integer, false),
+            column("rows_inserted", DataType::Integer, false),
+            column("rows_updated", DataType::Integer, false),
+            column("rows_deleted", DataType::Integer, false),
+        ],
+        _ => return None,
+    };
+    Some(virtual_table(name, columns))
+}
+
+/// Generates the rows of the given system table from the catalog's statistics.
+pub fn scan(table: &Table, catalog: &impl Catalog) -> Result<Vec<Row>> {
+    let Some(statistics) = catalog.statistics() else {
+        return Ok(Vec::new());
+    };
+    let mut rows = Vec::new();
+    match table.name.as_str() {
+        "statement_statistics" => {
+            for stats in statistics.statements()? {
+                let total_ms = stats.total_duration.as_secs_f64() * 1000.0;
+                rows.push(vec![
+                    Value::String(stats.fingerprint),
+                    Value::Integer(stats.count as i64),
+                    Value::Integer(stats.errors as i64),
+                    Value::Integer(stats.rows as i64),
+                    Value::Float(total_ms),
+                    Value::Float(total_ms / stats.count as f64),
+                    Value::Float(stats.max_duration.as_secs_f64() * 1000.0),
+                ]);
+            }
+        }
+        "table_statistics" => {
+            for stats in statistics.tables()? {
+                rows.push(vec![
+                    Value::String(stats.table),
+                    Value::Integer(stats.rows_read as i64),
+                    Value::Integer(stats.rows_inserted as i64),
+                    Value::Integer(stats.rows_updated as i64),
+                    Value::Integer(stats.rows_deleted as i64),
+                ]);
+            }
+        }
+        name => panic!("unknown system table {name}"),
+    }
+    Ok(rows)
+}
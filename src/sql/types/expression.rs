@@ -1,7 +1,7 @@
 use regex::Regex;
 use serde::{Deserialize, Serialize};
 
-use super::{Label, Row, Value};
+use super::{Collation, Function, Label, Row, Value, json, tokenize};
 use crate::errinput;
 use crate::error::Result;
 use crate::sql::planner::Node;
@@ -59,6 +59,97 @@ pub enum Expression {
 
     // Checks if a string matches a pattern: a LIKE b.
     Like(Box<Expression>, Box<Expression>),
+    /// Checks if a string contains all words in a full-text query: a MATCH b.
+    Match(Box<Expression>, Box<Expression>),
+    /// Checks if a string matches a regular expression: a ~ b.
+    Regexp(Box<Expression>, Box<Expression>),
+    /// Returns the first regular expression match in a string:
+    /// regexp_match(a, b).
+    RegexpMatch(Box<Expression>, Box<Expression>),
+
+    /// Extracts a JSON object field or array element as JSON: a -> b.
+    JsonExtract(Box<Expression>, Box<Expression>),
+    /// Extracts a JSON path expression as a SQL value: json_extract(a, b).
+    JsonExtractPath(Box<Expression>, Box<Expression>),
+    /// Extracts a JSON object field or array element as a string: a ->> b.
+    JsonExtractText(Box<Expression>, Box<Expression>),
+    /// Parses a string as JSON: json(a).
+    JsonParse(Box<Expression>),
+    /// Returns the type name of a JSON value: json_typeof(a).
+    JsonTypeof(Box<Expression>),
+
+    /// Subtracts two timestamps, yielding a symbolic interval of years,
+    /// months, and days: age(a, b).
+    Age(Box<Expression>, Box<Expression>),
+
+    /// Calls a user-defined function with the given arguments: f(a, b).
+    Function(Function, Vec<Expression>),
+
+    /// Builds an array from the given elements: ARRAY[a, b].
+    Array(Vec<Expression>),
+    /// Looks up an array element by 1-based index: a\[b\].
+    Index(Box<Expression>, Box<Expression>),
+    /// Compares a value with all array elements: a = ALL(b).
+    All(Comparison, Box<Expression>, Box<Expression>),
+    /// Compares a value with any array element: a = ANY(b).
+    Any(Comparison, Box<Expression>, Box<Expression>),
+
+    /// Folds a value into its comparison key under the given collation, e.g.
+    /// lowercases strings for NOCASE: a COLLATE NOCASE. Only used for
+    /// comparisons, sorting, and grouping.
+    Collate(Box<Expression>, Collation),
+
+    /// A query parameter, i.e. a placeholder for a constant value, in plans
+    /// cached by the plan cache. Must be bound before evaluation (see
+    /// Plan::bind).
+    Parameter(usize),
+}
+
+/// A comparison operator, used to compare a value with array elements via ANY
+/// and ALL.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, Serialize, Deserialize)]
+pub enum Comparison {
+    Equal,
+    GreaterThan,
+    GreaterThanOrEqual,
+    LessThan,
+    LessThanOrEqual,
+    NotEqual,
+}
+
+impl Comparison {
+    /// Compares two values, with the same semantics as the corresponding
+    /// comparison expression (e.g. NULLs yield NULL).
+    fn compare(&self, lhs: Value, rhs: Value) -> Result<Value> {
+        use Expression::*;
+        let (lhs, rhs) = (Box::new(Constant(lhs)), Box::new(Constant(rhs)));
+        match self {
+            Self::Equal => Equal(lhs, rhs),
+            Self::GreaterThan => GreaterThan(lhs, rhs),
+            Self::GreaterThanOrEqual => {
+                Or(GreaterThan(lhs.clone(), rhs.clone()).into(), Equal(lhs, rhs).into())
+            }
+            Self::LessThan => LessThan(lhs, rhs),
+            Self::LessThanOrEqual => {
+                Or(LessThan(lhs.clone(), rhs.clone()).into(), Equal(lhs, rhs).into())
+            }
+            Self::NotEqual => Not(Equal(lhs, rhs).into()),
+        }
+        .evaluate(None)
+    }
+}
+
+impl std::fmt::Display for Comparison {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Equal => "=",
+            Self::GreaterThan => ">",
+            Self::GreaterThanOrEqual => ">=",
+            Self::LessThan => "<",
+            Self::LessThanOrEqual => "<=",
+            Self::NotEqual => "!=",
+        })
+    }
 }
 
 impl Expression {
@@ -70,14 +161,21 @@ impl Expression {
         // Precedence levels, for grouping. Matches the parser precedence.
         fn precedence(expr: &Expression) -> u8 {
             match expr {
-                Column(_) | Constant(_) | SquareRoot(_) => 11,
-                Identity(_) | Negate(_) => 10,
-                Factorial(_) => 9,
-                Exponentiate(_, _) => 8,
-                Multiply(_, _) | Divide(_, _) | Remainder(_, _) => 7,
-                Add(_, _) | Subtract(_, _) => 6,
+                Column(_) | Constant(_) | Parameter(_) | RegexpMatch(_, _) | SquareRoot(_) => 12,
+                JsonExtractPath(_, _) | JsonParse(_) | JsonTypeof(_) | Function(_, _) => 12,
+                Age(_, _) | Array(_) | Index(_, _) | Collate(_, _) => 12,
+                Identity(_) | Negate(_) => 11,
+                Factorial(_) => 10,
+                Exponentiate(_, _) => 9,
+                Multiply(_, _) | Divide(_, _) | Remainder(_, _) => 8,
+                Add(_, _) | Subtract(_, _) => 7,
+                JsonExtract(_, _) | JsonExtractText(_, _) => 6,
                 GreaterThan(_, _) | LessThan(_, _) => 5,
-                Equal(_, _) | Like(_, _) | Is(_, _) => 4,
+                All(comparison, _, _) | Any(comparison, _, _) => match comparison {
+                    Comparison::Equal | Comparison::NotEqual => 4,
+                    _ => 5,
+                },
+                Equal(_, _) | Like(_, _) | Match(_, _) | Regexp(_, _) | Is(_, _) => 4,
                 Not(_) => 3,
                 And(_, _) => 2,
                 Or(_, _) => 1,
@@ -123,6 +221,38 @@ impl Expression {
             Subtract(lhs, rhs) => format!("{} - {}", format(lhs), format(rhs)),
 
             Like(lhs, rhs) => format!("{} LIKE {}", format(lhs), format(rhs)),
+            Match(lhs, rhs) => format!("{} MATCH {}", format(lhs), format(rhs)),
+            Regexp(lhs, rhs) => format!("{} ~ {}", format(lhs), format(rhs)),
+            RegexpMatch(lhs, rhs) => format!("regexp_match({}, {})", format(lhs), format(rhs)),
+
+            JsonExtract(lhs, rhs) => format!("{} -> {}", format(lhs), format(rhs)),
+            JsonExtractPath(lhs, rhs) => {
+                format!("json_extract({}, {})", format(lhs), format(rhs))
+            }
+            JsonExtractText(lhs, rhs) => format!("{} ->> {}", format(lhs), format(rhs)),
+            JsonParse(expr) => format!("json({})", format(expr)),
+            JsonTypeof(expr) => format!("json_typeof({})", format(expr)),
+
+            Age(lhs, rhs) => format!("age({}, {})", format(lhs), format(rhs)),
+
+            Function(function, args) => {
+                let args = args.iter().map(format).collect::<Vec<_>>().join(", ");
+                format!("{}({args})", function.name)
+            }
+
+            Array(exprs) => {
+                let exprs = exprs.iter().map(|expr| expr.format(node)).collect::<Vec<_>>();
+                format!("ARRAY[{}]", exprs.join(", "))
+            }
+            Index(lhs, rhs) => format!("{}[{}]", format(lhs), rhs.format(node)),
+            All(comparison, lhs, rhs) => {
+                format!("{} {comparison} ALL({})", format(lhs), rhs.format(node))
+            }
+            Any(comparison, lhs, rhs) => {
+                format!("{} {comparison} ANY({})", format(lhs), rhs.format(node))
+            }
+            Collate(expr, collation) => format!("{} COLLATE {collation}", format(expr)),
+            Parameter(index) => format!("${}", index + 1),
         }
     }
 
@@ -173,6 +303,8 @@ impl Expression {
 
             // Comparisons. Must be of same type, except floats and integers
             // which are interchangeable. NULLs yield NULL, NaNs yield NaN.
+            // Intervals are compared by their normalized length, such that
+            // '1 month' = '30 days'.
             //
             // Does not dispatch to Value.cmp() because sorting and comparisons
             // are different for f64 NaN and -0.0 values.
@@ -184,6 +316,10 @@ impl Expression {
                 (Float(lhs), Integer(rhs)) => Boolean(lhs == rhs as f64),
                 (Float(lhs), Float(rhs)) => Boolean(lhs == rhs),
                 (String(lhs), String(rhs)) => Boolean(lhs == rhs),
+                (Json(lhs), Json(rhs)) => Boolean(lhs == rhs),
+                (Interval(lhs), Interval(rhs)) => Boolean(lhs.normalized() == rhs.normalized()),
+                (Timestamp(lhs), Timestamp(rhs)) => Boolean(lhs == rhs),
+                (Array(lhs), Array(rhs)) => Boolean(lhs == rhs),
                 (Null, _) | (_, Null) => Null,
                 (lhs, rhs) => return errinput!("can't compare {lhs} and {rhs}"),
             },
@@ -196,6 +332,8 @@ impl Expression {
                 (Float(lhs), Integer(rhs)) => Boolean(lhs > rhs as f64),
                 (Float(lhs), Float(rhs)) => Boolean(lhs > rhs),
                 (String(lhs), String(rhs)) => Boolean(lhs > rhs),
+                (Interval(lhs), Interval(rhs)) => Boolean(lhs.normalized() > rhs.normalized()),
+                (Timestamp(lhs), Timestamp(rhs)) => Boolean(lhs > rhs),
                 (Null, _) | (_, Null) => Null,
                 (lhs, rhs) => return errinput!("can't compare {lhs} and {rhs}"),
             },
@@ -208,6 +346,8 @@ impl Expression {
                 (Float(lhs), Integer(rhs)) => Boolean(lhs < rhs as f64),
                 (Float(lhs), Float(rhs)) => Boolean(lhs < rhs),
                 (String(lhs), String(rhs)) => Boolean(lhs < rhs),
+                (Interval(lhs), Interval(rhs)) => Boolean(lhs.normalized() < rhs.normalized()),
+                (Timestamp(lhs), Timestamp(rhs)) => Boolean(lhs < rhs),
                 (Null, _) | (_, Null) => Null,
                 (lhs, rhs) => return errinput!("can't compare {lhs} and {rhs}"),
             },
@@ -216,13 +356,18 @@ impl Expression {
             Self::Is(expr, Float(f)) if f.is_nan() => match expr.evaluate(row)? {
                 Float(f) => Boolean(f.is_nan()),
                 Null => Null,
-                v => return errinput!("IS NAN can't be used with {}", v.datatype().unwrap()),
+                v => match v.datatype() {
+                    Some(datatype) => return errinput!("IS NAN can't be used with {datatype}"),
+                    None => return errinput!("IS NAN can't be used with {v}"),
+                },
             },
             Self::Is(_, v) => panic!("invalid IS value {v}"), // enforced by parser
 
             // Mathematical operations. Inputs must be numbers, but integers and
             // floats are interchangeable (float when mixed). NULLs yield NULL.
             // Errors on integer overflow, while floats yield infinity or NaN.
+            // Intervals can be added and subtracted, and multiplied or divided
+            // by numbers.
             Self::Add(lhs, rhs) => lhs.evaluate(row)?.checked_add(&rhs.evaluate(row)?)?,
             Self::Divide(lhs, rhs) => lhs.evaluate(row)?.checked_div(&rhs.evaluate(row)?)?,
             Self::Exponentiate(lhs, rhs) => lhs.evaluate(row)?.checked_pow(&rhs.evaluate(row)?)?,
@@ -240,6 +385,7 @@ impl Expression {
             Self::Negate(expr) => match expr.evaluate(row)? {
                 Integer(i) => Integer(-i),
                 Float(f) => Float(-f),
+                Interval(i) => Interval(i.checked_neg()?),
                 Null => Null,
                 value => return errinput!("can't negate {value}"),
             },
@@ -267,6 +413,189 @@ impl Expression {
                 (String(_), Null) | (Null, String(_)) | (Null, Null) => Null,
                 (lhs, rhs) => return errinput!("can't LIKE {lhs} and {rhs}"),
             },
+
+            // Full-text matching: the string must contain all words in the
+            // query, in any order (see tokenize). Inputs must be strings. NULLs
+            // yield NULL. An empty query matches any string.
+            Self::Match(lhs, rhs) => match (lhs.evaluate(row)?, rhs.evaluate(row)?) {
+                (String(lhs), String(rhs)) => Boolean(tokenize(&rhs).is_subset(&tokenize(&lhs))),
+                (String(_), Null) | (Null, String(_)) | (Null, Null) => Null,
+                (lhs, rhs) => return errinput!("can't MATCH {lhs} and {rhs}"),
+            },
+
+            // Regular expression matching. Inputs must be strings. NULLs yield
+            // NULL. The pattern matches anywhere in the string, unless anchored
+            // with ^ and $. Like LIKE, the pattern is compiled for every row.
+            Self::Regexp(lhs, rhs) => match (lhs.evaluate(row)?, rhs.evaluate(row)?) {
+                (String(lhs), String(rhs)) => Boolean(Self::compile_regex(&rhs)?.is_match(&lhs)),
+                (String(_), Null) | (Null, String(_)) | (Null, Null) => Null,
+                (lhs, rhs) => return errinput!("can't match {lhs} and {rhs}"),
+            },
+
+            // Returns the first match of the pattern in the string. If the
+            // pattern has capture groups, the first group is returned instead.
+            // Yields NULL if there is no match.
+            Self::RegexpMatch(lhs, rhs) => match (lhs.evaluate(row)?, rhs.evaluate(row)?) {
+                (String(lhs), String(rhs)) => match Self::compile_regex(&rhs)?.captures(&lhs) {
+                    Some(captures) => match captures.get(1).or(captures.get(0)) {
+                        Some(m) => String(m.as_str().to_string()),
+                        None => Null, // the first group didn't participate in the match
+                    },
+                    None => Null,
+                },
+                (String(_), Null) | (Null, String(_)) | (Null, Null) => Null,
+                (lhs, rhs) => return errinput!("can't match {lhs} and {rhs}"),
+            },
+
+            // JSON field/element extraction. Objects take string keys, arrays
+            // take integer indexes (negative from the end). Missing elements
+            // yield NULL, as do NULL inputs. -> returns JSON, while ->> returns
+            // the element as a string (or NULL for JSON null).
+            Self::JsonExtract(lhs, rhs) => match (lhs.evaluate(row)?, rhs.evaluate(row)?) {
+                (Json(doc), key @ (String(_) | Integer(_))) => {
+                    doc.get(&key).cloned().map(Json).unwrap_or(Null)
+                }
+                (Json(_) | Null, Null) | (Null, String(_) | Integer(_)) => Null,
+                (lhs, rhs) => return errinput!("can't extract {rhs} from {lhs}"),
+            },
+            Self::JsonExtractText(lhs, rhs) => match (lhs.evaluate(row)?, rhs.evaluate(row)?) {
+                (Json(doc), key @ (String(_) | Integer(_))) => match doc.get(&key) {
+                    Some(json::Json::Null) | None => Null,
+                    Some(json::Json::String(s)) => String(s.clone()),
+                    Some(doc) => String(doc.to_string()),
+                },
+                (Json(_) | Null, Null) | (Null, String(_) | Integer(_)) => Null,
+                (lhs, rhs) => return errinput!("can't extract {rhs} from {lhs}"),
+            },
+
+            // JSON path extraction, e.g. json_extract(doc, '$.items[0]').
+            // Scalars are returned as SQL values, and arrays/objects as JSON.
+            Self::JsonExtractPath(lhs, rhs) => match (lhs.evaluate(row)?, rhs.evaluate(row)?) {
+                (Json(doc), String(path)) => {
+                    doc.get_path(&path)?.cloned().map(json::Json::into_value).unwrap_or(Null)
+                }
+                (Json(_) | Null, Null) | (Null, String(_)) => Null,
+                (lhs, rhs) => return errinput!("can't extract {rhs} from {lhs}"),
+            },
+
+            // Parses a string as JSON. JSON values are passed through.
+            Self::JsonParse(expr) => match expr.evaluate(row)? {
+                String(s) => Json(json::Json::parse(&s)?),
+                v @ (Json(_) | Null) => v,
+                value => return errinput!("can't parse {value} as JSON"),
+            },
+
+            Self::JsonTypeof(expr) => match expr.evaluate(row)? {
+                Json(doc) => String(doc.type_name().to_string()),
+                Null => Null,
+                value => return errinput!("can't take JSON type of {value}"),
+            },
+
+            // The symbolic difference between two timestamps, in whole years,
+            // months, and days plus time, like Postgres' age(). NULLs yield
+            // NULL.
+            Self::Age(lhs, rhs) => match (lhs.evaluate(row)?, rhs.evaluate(row)?) {
+                (Timestamp(lhs), Timestamp(rhs)) => Interval(lhs.age(&rhs)),
+                (Timestamp(_), Null) | (Null, Timestamp(_)) | (Null, Null) => Null,
+                (lhs, rhs) => return errinput!("can't take age of {lhs} and {rhs}"),
+            },
+
+            // User-defined functions check the argument and return types.
+            Self::Function(function, args) => {
+                let args = args.iter().map(|arg| arg.evaluate(row)).collect::<Result<_>>()?;
+                function.call(args)?
+            }
+
+            // Array constructors. Elements must have the same datatype, or be
+            // NULL. Nested arrays aren't supported.
+            Self::Array(exprs) => {
+                let values = exprs.iter().map(|e| e.evaluate(row)).collect::<Result<Vec<_>>>()?;
+                let mut datatype = None;
+                for value in &values {
+                    if let Array(_) = value {
+                        return errinput!("can't nest arrays");
+                    }
+                    match (value.datatype(), &datatype) {
+                        (Some(vtype), Some(dtype)) if &vtype != dtype => {
+                            return errinput!("array can't contain both {dtype} and {vtype}");
+                        }
+                        (Some(vtype), None) => datatype = Some(vtype),
+                        (Some(_), Some(_)) | (None, _) => {}
+                    }
+                }
+                Array(values)
+            }
+
+            // Array element lookup, using 1-based indexes like Postgres.
+            // Out-of-bounds indexes and NULL inputs yield NULL.
+            Self::Index(lhs, rhs) => match (lhs.evaluate(row)?, rhs.evaluate(row)?) {
+                (Array(values), Integer(i)) => match usize::try_from(i) {
+                    Ok(i) if i >= 1 => values.into_iter().nth(i - 1).unwrap_or(Null),
+                    Ok(_) | Err(_) => Null,
+                },
+                (Array(_) | Null, Null) | (Null, Integer(_)) => Null,
+                (lhs, rhs) => return errinput!("can't index {lhs} with {rhs}"),
+            },
+
+            // Array comparisons. ANY is true if the comparison is true for any
+            // element, and ALL if it's true for all elements. Otherwise, NULL
+            // comparisons yield NULL, following SQL's three-valued logic. An
+            // empty array yields false for ANY and true for ALL.
+            Self::All(comparison, lhs, rhs) => match (lhs.evaluate(row)?, rhs.evaluate(row)?) {
+                (lhs, Array(values)) => {
+                    let mut result = Boolean(true);
+                    for value in values {
+                        match comparison.compare(lhs.clone(), value)? {
+                            Boolean(true) => {}
+                            Boolean(false) => return Ok(Boolean(false)),
+                            _ => result = Null,
+                        }
+                    }
+                    result
+                }
+                (_, Null) => Null,
+                (_, rhs) => return errinput!("can't compare with ALL elements of {rhs}"),
+            },
+            Self::Any(comparison, lhs, rhs) => match (lhs.evaluate(row)?, rhs.evaluate(row)?) {
+                (lhs, Array(values)) => {
+                    let mut result = Boolean(false);
+                    for value in values {
+                        match comparison.compare(lhs.clone(), value)? {
+                            Boolean(true) => return Ok(Boolean(true)),
+                            Boolean(false) => {}
+                            _ => result = Null,
+                        }
+                    }
+                    result
+                }
+                (_, Null) => Null,
+                (_, rhs) => return errinput!("can't compare with ANY element of {rhs}"),
+            },
+
+            Self::Collate(expr, collation) => collation.fold(expr.evaluate(row)?).into_owned(),
+
+            Self::Parameter(index) => return errinput!("unbound parameter ${}", index + 1),
+        })
+    }
+
+    /// Compiles a regular expression pattern, as used by ~ and regexp_match().
+    /// Uses the regex crate, which guarantees linear-time matching in the size
+    /// of the pattern and input, so user-given patterns can't cause runaway
+    /// backtracking. In return, it doesn't support backreferences or
+    /// lookaround assertions.
+    pub fn compile_regex(pattern: &str) -> Result<Regex> {
+        Regex::new(pattern).or_else(|err| {
+            // Syntax errors come with a multi-line diagram. Only keep the
+            // message on the last line.
+            let message = match err {
+                regex::Error::Syntax(message) => message
+                    .lines()
+                    .last()
+                    .map(|line| line.trim_start_matches("error: ").to_string())
+                    .unwrap_or(message),
+                err => err.to_string(),
+            };
+            errinput!("invalid regular expression {pattern}: {message}")
         })
     }
 
@@ -278,26 +607,41 @@ impl Expression {
         }
         match self {
             Self::Add(lhs, rhs)
+            | Self::Age(lhs, rhs)
+            | Self::All(_, lhs, rhs)
             | Self::And(lhs, rhs)
+            | Self::Any(_, lhs, rhs)
             | Self::Divide(lhs, rhs)
             | Self::Equal(lhs, rhs)
             | Self::Exponentiate(lhs, rhs)
             | Self::GreaterThan(lhs, rhs)
+            | Self::Index(lhs, rhs)
+            | Self::JsonExtract(lhs, rhs)
+            | Self::JsonExtractPath(lhs, rhs)
+            | Self::JsonExtractText(lhs, rhs)
             | Self::LessThan(lhs, rhs)
             | Self::Like(lhs, rhs)
             | Self::Multiply(lhs, rhs)
             | Self::Or(lhs, rhs)
+            | Self::Match(lhs, rhs)
+            | Self::Regexp(lhs, rhs)
+            | Self::RegexpMatch(lhs, rhs)
             | Self::Remainder(lhs, rhs)
             | Self::Subtract(lhs, rhs) => lhs.walk(visitor) && rhs.walk(visitor),
 
-            Self::Factorial(expr)
+            Self::Collate(expr, _)
+            | Self::Factorial(expr)
             | Self::Identity(expr)
             | Self::Is(expr, _)
+            | Self::JsonParse(expr)
+            | Self::JsonTypeof(expr)
             | Self::Negate(expr)
             | Self::Not(expr)
             | Self::SquareRoot(expr) => expr.walk(visitor),
 
-            Self::Constant(_) | Self::Column(_) => true,
+            Self::Function(_, args) | Self::Array(args) => args.iter().all(|arg| arg.walk(visitor)),
+
+            Self::Constant(_) | Self::Column(_) | Self::Parameter(_) => true,
         }
     }
 
@@ -324,26 +668,47 @@ impl Expression {
         self = before(self)?;
         self = match self {
             Self::Add(lhs, rhs) => Self::Add(xform(lhs)?, xform(rhs)?),
+            Self::Age(lhs, rhs) => Self::Age(xform(lhs)?, xform(rhs)?),
+            Self::All(c, lhs, rhs) => Self::All(c, xform(lhs)?, xform(rhs)?),
             Self::And(lhs, rhs) => Self::And(xform(lhs)?, xform(rhs)?),
+            Self::Any(c, lhs, rhs) => Self::Any(c, xform(lhs)?, xform(rhs)?),
             Self::Divide(lhs, rhs) => Self::Divide(xform(lhs)?, xform(rhs)?),
             Self::Equal(lhs, rhs) => Self::Equal(xform(lhs)?, xform(rhs)?),
             Self::Exponentiate(lhs, rhs) => Self::Exponentiate(xform(lhs)?, xform(rhs)?),
             Self::GreaterThan(lhs, rhs) => Self::GreaterThan(xform(lhs)?, xform(rhs)?),
+            Self::Index(lhs, rhs) => Self::Index(xform(lhs)?, xform(rhs)?),
+            Self::JsonExtract(lhs, rhs) => Self::JsonExtract(xform(lhs)?, xform(rhs)?),
+            Self::JsonExtractPath(lhs, rhs) => Self::JsonExtractPath(xform(lhs)?, xform(rhs)?),
+            Self::JsonExtractText(lhs, rhs) => Self::JsonExtractText(xform(lhs)?, xform(rhs)?),
             Self::LessThan(lhs, rhs) => Self::LessThan(xform(lhs)?, xform(rhs)?),
             Self::Like(lhs, rhs) => Self::Like(xform(lhs)?, xform(rhs)?),
             Self::Multiply(lhs, rhs) => Self::Multiply(xform(lhs)?, xform(rhs)?),
             Self::Or(lhs, rhs) => Self::Or(xform(lhs)?, xform(rhs)?),
+            Self::Match(lhs, rhs) => Self::Match(xform(lhs)?, xform(rhs)?),
+            Self::Regexp(lhs, rhs) => Self::Regexp(xform(lhs)?, xform(rhs)?),
+            Self::RegexpMatch(lhs, rhs) => Self::RegexpMatch(xform(lhs)?, xform(rhs)?),
             Self::Remainder(lhs, rhs) => Self::Remainder(xform(lhs)?, xform(rhs)?),
             Self::SquareRoot(expr) => Self::SquareRoot(xform(expr)?),
             Self::Subtract(lhs, rhs) => Self::Subtract(xform(lhs)?, xform(rhs)?),
 
+            Self::Collate(expr, collation) => Self::Collate(xform(expr)?, collation),
             Self::Factorial(expr) => Self::Factorial(xform(expr)?),
             Self::Identity(expr) => Self::Identity(xform(expr)?),
             Self::Is(expr, value) => Self::Is(xform(expr)?, value),
+            Self::JsonParse(expr) => Self::JsonParse(xform(expr)?),
+            Self::JsonTypeof(expr) => Self::JsonTypeof(xform(expr)?),
             Self::Negate(expr) => Self::Negate(xform(expr)?),
             Self::Not(expr) => Self::Not(xform(expr)?),
 
-            expr @ (Self::Constant(_) | Self::Column(_)) => expr,
+            Self::Function(function, args) => Self::Function(
+                function,
+                args.into_iter().map(|arg| arg.transform(before, after)).collect::<Result<_>>()?,
+            ),
+            Self::Array(exprs) => Self::Array(
+                exprs.into_iter().map(|e| e.transform(before, after)).collect::<Result<_>>()?,
+            ),
+
+            expr @ (Self::Constant(_) | Self::Column(_) | Self::Parameter(_)) => expr,
         };
         self = after(self)?;
         Ok(self)
@@ -421,19 +786,29 @@ impl Expression {
     }
 
     /// Checks if an expression is a single column lookup (i.e. a disjunction of
-    /// = or IS NULL/NAN for a single column), returning the column index.
-    pub fn is_column_lookup(&self) -> Option<usize> {
+    /// = or IS NULL/NAN for a single column), returning the column index and
+    /// the collation used for the comparison. The lookup can only use an index
+    /// with the same collation.
+    pub fn is_column_lookup(&self) -> Option<(usize, Collation)> {
         use Expression::*;
         match &self {
             // Column/constant equality can use index lookups. NULL and NaN are
-            // handled in into_column_values().
+            // handled in into_column_values(). Collated comparisons can use
+            // a collated index, since the constant has already been folded.
             Equal(lhs, rhs) => match (lhs.as_ref(), rhs.as_ref()) {
-                (Column(c), Constant(_)) | (Constant(_), Column(c)) => Some(*c),
+                (Column(c), Constant(_)) | (Constant(_), Column(c)) => {
+                    Some((*c, Collation::Binary))
+                }
+                (Collate(expr, collation), Constant(_))
+                | (Constant(_), Collate(expr, collation)) => match expr.as_ref() {
+                    Column(c) => Some((*c, *collation)),
+                    _ => None,
+                },
                 _ => None,
             },
             // IS NULL and IS NAN can use index lookups.
             Is(expr, _) => match expr.as_ref() {
-                Column(c) => Some(*c),
+                Column(c) => Some((*c, Collation::Binary)),
                 _ => None,
             },
             // All OR branches must be lookups on the same column:
@@ -452,7 +827,8 @@ impl Expression {
     pub fn into_column_values(self, index: usize) -> Vec<Value> {
         use Expression::*;
         match self {
-            Equal(lhs, rhs) => match (*lhs, *rhs) {
+            // Strip collations, the constant has already been folded.
+            Equal(lhs, rhs) => match ((*lhs).strip_collate(), (*rhs).strip_collate()) {
                 (Column(column), Constant(value)) | (Constant(value), Column(column)) => {
                     assert_eq!(column, index, "unexpected column");
                     // NULL and NAN index lookups are for IS NULL and IS NAN.
@@ -478,6 +854,86 @@ impl Expression {
         }
     }
 
+    /// Returns true if this expression implies the other one, i.e. if the
+    /// other expression is guaranteed to be true for any row where this one
+    /// is true. Used to check whether a query filter implies a partial index
+    /// predicate. The check is conservative: it only handles identical
+    /// expressions, ORs, IS NOT NULL, and column/constant comparisons, and
+    /// may return false for expressions that do imply the other.
+    pub fn implies(&self, other: &Expression) -> bool {
+        use Expression::*;
+        use std::cmp::Ordering;
+
+        // Decomposes a column/constant comparison into the column, ordering,
+        // and value, e.g. 1 < a as (a, Greater, 1). Only handles values that
+        // compare the same as their Ord implementation.
+        fn comparison(expr: &Expression) -> Option<(usize, Ordering, &Value)> {
+            let (lhs, rhs, ordering) = match expr {
+                Equal(lhs, rhs) => (lhs, rhs, Ordering::Equal),
+                GreaterThan(lhs, rhs) => (lhs, rhs, Ordering::Greater),
+                LessThan(lhs, rhs) => (lhs, rhs, Ordering::Less),
+                _ => return None,
+            };
+            let (column, ordering, value) = match (lhs.as_ref(), rhs.as_ref()) {
+                (Column(c), Constant(v)) => (*c, ordering, v),
+                (Constant(v), Column(c)) => (*c, ordering.reverse(), v),
+                _ => return None,
+            };
+            match value {
+                Value::Boolean(_) | Value::Integer(_) | Value::String(_) => {}
+                Value::Float(f) if !f.is_nan() => {}
+                _ => return None,
+            }
+            Some((column, ordering, value))
+        }
+
+        if self == other {
+            return true;
+        }
+        match (self, other) {
+            // a implies a OR b.
+            (_, Or(lhs, rhs)) if self.implies(lhs) || self.implies(rhs) => true,
+            // a OR b implies c if both a and b imply c.
+            (Or(lhs, rhs), _) => lhs.implies(other) && rhs.implies(other),
+            // A column comparison implies the column is not NULL, since
+            // comparisons with NULL yield NULL.
+            (_, Not(expr)) => match (comparison(self), expr.as_ref()) {
+                (Some((column, _, _)), Is(expr, Value::Null)) => **expr == Column(column),
+                _ => false,
+            },
+            // A column comparison can imply another comparison of the same
+            // column, e.g. a = 3 implies a > 1, and a > 3 implies a > 1.
+            _ => {
+                let (Some((lc, lord, lv)), Some((rc, rord, rv))) =
+                    (comparison(self), comparison(other))
+                else {
+                    return false;
+                };
+                let comparable = std::mem::discriminant(lv) == std::mem::discriminant(rv)
+                    || matches!((lv, rv), (Value::Integer(_), Value::Float(_)))
+                    || matches!((lv, rv), (Value::Float(_), Value::Integer(_)));
+                if lc != rc || !comparable {
+                    return false;
+                }
+                // Normalize -0.0 to 0.0, since they're equal in comparisons.
+                let cmp = lv.normalize_ref().cmp(&rv.normalize_ref());
+                match (lord, rord) {
+                    (Ordering::Equal, rord) => cmp == rord,
+                    (lord, rord) if lord == rord => cmp != rord.reverse(),
+                    _ => false,
+                }
+            }
+        }
+    }
+
+    /// Strips any top-level COLLATE from the expression.
+    fn strip_collate(self) -> Self {
+        match self {
+            Self::Collate(expr, _) => *expr,
+            expr => expr,
+        }
+    }
+
     /// Replaces column references with the given column.
     pub fn replace_column(self, from: usize, to: usize) -> Self {
         let xform = |expr| match expr {
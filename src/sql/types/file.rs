@@ -0,0 +1,46 @@
+use std::fmt::Display;
+
+use serde::{Deserialize, Serialize};
+
+use crate::errinput;
+use crate::error::Result;
+
+/// A file format for importing and exporting rows.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub enum FileFormat {
+    /// CSV records, one per row (see encoding::csv). The first record can be
+    /// a header with the column names.
+    Csv,
+    /// JSON lines, with one object per row keyed by column name.
+    Json,
+}
+
+impl FileFormat {
+    /// Parses a file format name, case-insensitively.
+    pub fn parse(name: &str) -> Result<Self> {
+        match name.to_lowercase().as_str() {
+            "csv" => Ok(Self::Csv),
+            "json" => Ok(Self::Json),
+            _ => errinput!("unknown file format {name}"),
+        }
+    }
+
+    /// Returns the file format for a path based on its extension: JSON for
+    /// .json, .jsonl, and .ndjson files, otherwise CSV.
+    pub fn from_path(path: &str) -> Self {
+        let extension = path.rsplit_once('.').map(|(_, ext)| ext.to_lowercase());
+        match extension.as_deref() {
+            Some("json" | "jsonl" | "ndjson") => Self::Json,
+            _ => Self::Csv,
+        }
+    }
+}
+
+impl Display for FileFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Csv => f.write_str("CSV"),
+            Self::Json => f.write_str("JSON"),
+        }
+    }
+}
@@ -0,0 +1,140 @@
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::sync::{Arc, RwLock};
+
+use serde::{Deserialize, Serialize};
+
+use super::{DataType, Value};
+use crate::error::Result;
+use crate::{errdata, errinput};
+
+/// The closure type of a user-defined scalar function. It's given the argument
+/// values, which have already been checked against the argument types.
+pub type ScalarFn = dyn Fn(&[Value]) -> Result<Value> + Send + Sync;
+
+/// A user-defined scalar SQL function, implemented by a Rust closure. These are
+/// registered with the SQL engine (see Functions), and can be called in SQL
+/// expressions like built-in functions, e.g. SELECT twice(2).
+///
+/// The closure must be deterministic, since constant folding may evaluate it
+/// during planning rather than for every row. NULL arguments are passed through
+/// to the closure, which can return NULL in addition to the return type.
+///
+/// Functions are part of query plans, and are serialized without the closure
+/// (e.g. for EXPLAIN results sent to clients). A deserialized function can't be
+/// called.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Function {
+    /// The function name. Always lowercase.
+    pub name: String,
+    /// The argument types.
+    pub args: Vec<DataType>,
+    /// The return type.
+    pub returns: DataType,
+    /// The function closure. None if deserialized.
+    #[serde(skip)]
+    closure: Option<Arc<ScalarFn>>,
+}
+
+impl Function {
+    /// Creates a new function with the given name, argument types, return type,
+    /// and implementation.
+    pub fn new(
+        name: &str,
+        args: Vec<DataType>,
+        returns: DataType,
+        closure: impl Fn(&[Value]) -> Result<Value> + Send + Sync + 'static,
+    ) -> Self {
+        Self { name: name.to_lowercase(), args, returns, closure: Some(Arc::new(closure)) }
+    }
+
+    /// Calls the function with the given arguments. Errors if the arguments
+    /// or return value don't match the function's types. Integers are accepted
+    /// for float arguments, and converted.
+    pub fn call(&self, mut args: Vec<Value>) -> Result<Value> {
+        let Some(closure) = &self.closure else {
+            return errinput!("function {} is not available", self.name);
+        };
+        if args.len() != self.args.len() {
+            return errinput!("function {} takes {} arguments", self.name, self.args.len());
+        }
+        for (arg, datatype) in args.iter_mut().zip(&self.args) {
+            match (&arg, datatype) {
+                (Value::Integer(i), DataType::Float) => *arg = Value::Float(*i as f64),
+                (arg, datatype) if datatype.accepts(arg) => {}
+                (arg, datatype) => {
+                    return errinput!("function {} expects {datatype}, got {arg}", self.name);
+                }
+            }
+        }
+        let value = closure(&args)?;
+        if !self.returns.accepts(&value) {
+            return errdata!("function {} returned {value}, expected {}", self.name, self.returns);
+        }
+        Ok(value)
+    }
+}
+
+impl Debug for Function {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Function")
+            .field("name", &self.name)
+            .field("args", &self.args)
+            .field("returns", &self.returns)
+            .finish()
+    }
+}
+
+/// Functions are compared by signature, since closures can't be compared.
+impl PartialEq for Function {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name && self.args == other.args && self.returns == other.returns
+    }
+}
+
+/// A registry of user-defined functions, shared by a SQL engine and its
+/// transactions. Functions are registered at runtime, and are looked up by
+/// name during planning.
+#[derive(Default)]
+pub struct Functions(RwLock<HashMap<String, Function>>);
+
+impl Functions {
+    /// Names that can't be used for user-defined functions, since they're
+    /// taken by built-in functions.
+    const BUILTINS: &[&str] = &[
+        "avg",
+        "count",
+        "json",
+        "json_extract",
+        "json_typeof",
+        "max",
+        "min",
+        "regexp_match",
+        "sqrt",
+        "sum",
+    ];
+
+    /// Creates a new, empty function registry.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a function. Errors if a function with the same name already
+    /// exists.
+    pub fn register(&self, function: Function) -> Result<()> {
+        if Self::BUILTINS.contains(&function.name.as_str()) {
+            return errinput!("function {} is a built-in function", function.name);
+        }
+        let mut functions = self.0.write()?;
+        if functions.contains_key(&function.name) {
+            return errinput!("function {} already exists", function.name);
+        }
+        functions.insert(function.name.clone(), function);
+        Ok(())
+    }
+
+    /// Fetches a function by name, if it exists.
+    pub fn get(&self, name: &str) -> Result<Option<Function>> {
+        Ok(self.0.read()?.get(&name.to_lowercase()).cloned())
+    }
+}
@@ -0,0 +1,428 @@
+use std::cmp::Ordering;
+use std::fmt::Display;
+
+use serde::{Deserialize, Serialize};
+
+use crate::errinput;
+use crate::error::Result;
+
+/// Microseconds per second, minute, hour, and day.
+pub(super) const SECOND: i64 = 1_000_000;
+pub(super) const MINUTE: i64 = 60 * SECOND;
+pub(super) const HOUR: i64 = 60 * MINUTE;
+pub(super) const DAY: i64 = 24 * HOUR;
+
+/// The number of days per month, used when comparing intervals and cascading
+/// fractional months into days. Like Postgres.
+const DAYS_PER_MONTH: i64 = 30;
+
+/// A time interval, as stored in INTERVAL columns.
+///
+/// Like Postgres, intervals have separate month, day, and microsecond
+/// components, since months and days don't have a fixed length when applied to
+/// calendar dates (e.g. a month has 28-31 days). The components are kept as
+/// given, i.e. '36 hours' is not converted to '1 day 12:00:00'. Like Postgres,
+/// the months and days must fit in 32 bits, and arithmetic errors if they don't.
+///
+/// When comparing intervals in SQL expressions, a month is considered 30 days
+/// and a day 24 hours, such that '1 month' = '30 days'. In code, equality is
+/// structural (they are different values), and ordering uses the normalized
+/// length with the components as a tiebreaker to keep it consistent with
+/// equality. This is similar to how Value handles floats.
+///
+/// Intervals are serialized as a tuple of their components, which allows the
+/// Keycode encoding to use them in keys.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(from = "(i64, i64, i64)", into = "(i64, i64, i64)")]
+pub struct Interval {
+    /// The number of months.
+    pub months: i64,
+    /// The number of days.
+    pub days: i64,
+    /// The number of microseconds.
+    pub micros: i64,
+}
+
+impl Interval {
+    /// Creates a new interval from its components.
+    pub fn new(months: i64, days: i64, micros: i64) -> Self {
+        Self { months, days, micros }
+    }
+
+    /// Parses an interval string. Supports the Postgres verbose format, e.g.
+    /// '1 year 2 months 3 days 04:05:06.5' or '2 hours ago', and ISO 8601
+    /// durations, e.g. 'P1Y2M3DT4H5M6.5S'. Amounts can be fractional, in
+    /// which case the fraction cascades into the smaller components, and a
+    /// bare number is taken as seconds.
+    pub fn parse(input: &str) -> Result<Self> {
+        let trimmed = input.trim();
+        let result = match trimmed.strip_prefix(['P', 'p']) {
+            Some(iso) => Self::parse_iso8601(iso),
+            None => Self::parse_verbose(trimmed),
+        };
+        result.or_else(|_| errinput!("invalid interval '{}'", input.escape_debug()))
+    }
+
+    /// Parses the Postgres verbose format.
+    fn parse_verbose(input: &str) -> Result<Self> {
+        let mut interval = Self::default();
+        let mut words = input.split_whitespace().peekable();
+        let mut parsed = false;
+        let mut ago = false;
+        words.next_if_eq(&"@");
+        while let Some(word) = words.next() {
+            if ago {
+                return errinput!("unexpected {word} after ago");
+            }
+            if word.eq_ignore_ascii_case("ago") && parsed {
+                ago = true;
+                continue;
+            }
+            // A time of day, e.g. 04:05:06.5.
+            if word.contains(':') {
+                interval = interval.checked_add(&Self::parse_time(word)?)?;
+                parsed = true;
+                continue;
+            }
+            // A number, optionally followed by a unit in the same or the next
+            // word, e.g. '1 day' or '1d'.
+            let split = word
+                .find(|c: char| !c.is_ascii_digit() && !matches!(c, '.' | '-' | '+'))
+                .unwrap_or(word.len());
+            let (amount, mut unit) = word.split_at(split);
+            let amount: f64 = amount.parse().or_else(|_| errinput!("invalid amount {amount}"))?;
+            if unit.is_empty() {
+                unit = match words.peek() {
+                    Some(next) if !next.eq_ignore_ascii_case("ago") => words.next().unwrap(),
+                    Some(_) | None => "second",
+                }
+            }
+            interval = interval.checked_add(&Self::from_unit(amount, unit)?)?;
+            parsed = true;
+        }
+        if !parsed {
+            return errinput!("empty interval");
+        }
+        if ago {
+            interval = interval.checked_neg()?;
+        }
+        Ok(interval)
+    }
+
+    /// Parses an ISO 8601 duration, without the leading P.
+    fn parse_iso8601(input: &str) -> Result<Self> {
+        let mut interval = Self::default();
+        let mut time = false;
+        let mut rest = input;
+        if rest.is_empty() {
+            return errinput!("empty interval");
+        }
+        while !rest.is_empty() {
+            if let Some(r) = rest.strip_prefix(['T', 't']) {
+                if time {
+                    return errinput!("duplicate T");
+                }
+                (time, rest) = (true, r);
+                continue;
+            }
+            let split = rest
+                .find(|c: char| !c.is_ascii_digit() && !matches!(c, '.' | '-' | '+'))
+                .unwrap_or(rest.len());
+            let (amount, r) = rest.split_at(split);
+            let amount: f64 = amount.parse().or_else(|_| errinput!("invalid amount {amount}"))?;
+            let Some(designator) = r.chars().next() else {
+                return errinput!("missing designator");
+            };
+            let unit = match (designator.to_ascii_uppercase(), time) {
+                ('Y', false) => "year",
+                ('M', false) => "month",
+                ('W', false) => "week",
+                ('D', false) => "day",
+                ('H', true) => "hour",
+                ('M', true) => "minute",
+                ('S', true) => "second",
+                (designator, _) => return errinput!("invalid designator {designator}"),
+            };
+            interval = interval.checked_add(&Self::from_unit(amount, unit)?)?;
+            rest = &r[designator.len_utf8()..];
+        }
+        Ok(interval)
+    }
+
+    /// Parses a time of day as [-]HH:MM[:SS[.ffffff]].
+    fn parse_time(input: &str) -> Result<Self> {
+        let (negative, time) = match input.strip_prefix('-') {
+            Some(time) => (true, time),
+            None => (false, input.strip_prefix('+').unwrap_or(input)),
+        };
+        let mut parts = time.split(':');
+        let hours: i64 = parts.next().unwrap_or_default().parse()?;
+        let minutes: i64 = parts.next().unwrap_or_default().parse()?;
+        let seconds: f64 = parts.next().map(|s| s.parse()).transpose()?.unwrap_or(0.0);
+        if parts.next().is_some() || !(0..60).contains(&minutes) || !(0.0..60.0).contains(&seconds)
+        {
+            return errinput!("invalid time {input}");
+        }
+        let micros = hours
+            .checked_mul(HOUR)
+            .and_then(|h| h.checked_add(minutes * MINUTE))
+            .and_then(|m| m.checked_add((seconds * SECOND as f64).round() as i64));
+        Self::checked(Some(0), Some(0), micros.map(|m| if negative { -m } else { m }))
+    }
+
+    /// Creates an interval from an amount of the given unit.
+    fn from_unit(amount: f64, unit: &str) -> Result<Self> {
+        Ok(match unit.to_lowercase().as_str() {
+            "microsecond" | "microseconds" | "us" | "usec" | "usecs" => Self::from_micros(amount)?,
+            "millisecond" | "milliseconds" | "ms" | "msec" | "msecs" => {
+                Self::from_micros(amount * 1000.0)?
+            }
+            "second" | "seconds" | "s" | "sec" | "secs" => {
+                Self::from_micros(amount * SECOND as f64)?
+            }
+            "minute" | "minutes" | "m" | "min" | "mins" => {
+                Self::from_micros(amount * MINUTE as f64)?
+            }
+            "hour" | "hours" | "h" | "hr" | "hrs" => Self::from_micros(amount * HOUR as f64)?,
+            "day" | "days" | "d" => Self::from_days(amount)?,
+            "week" | "weeks" | "w" => Self::from_days(amount * 7.0)?,
+            "month" | "months" | "mon" | "mons" => Self::from_months(amount)?,
+            "year" | "years" | "y" | "yr" | "yrs" => Self::from_months(amount * 12.0)?,
+            "decade" | "decades" => Self::from_months(amount * 120.0)?,
+            "century" | "centuries" => Self::from_months(amount * 1200.0)?,
+            "millennium" | "millennia" => Self::from_months(amount * 12000.0)?,
+            _ => return errinput!("invalid unit {unit}"),
+        })
+    }
+
+    /// Creates an interval from a fractional number of months, cascading the
+    /// fraction into days and microseconds.
+    fn from_months(months: f64) -> Result<Self> {
+        let days = Self::from_days(months.fract() * DAYS_PER_MONTH as f64)?;
+        Self::checked(to_i64(months.trunc()), Some(days.days), Some(days.micros))
+    }
+
+    /// Creates an interval from a fractional number of days, cascading the
+    /// fraction into microseconds.
+    fn from_days(days: f64) -> Result<Self> {
+        Self::checked(Some(0), to_i64(days.trunc()), to_i64((days.fract() * DAY as f64).round()))
+    }
+
+    /// Creates an interval from a number of microseconds, rounded.
+    fn from_micros(micros: f64) -> Result<Self> {
+        Self::checked(Some(0), Some(0), to_i64(micros.round()))
+    }
+
+    /// Returns the total length of the interval in microseconds, considering a
+    /// month 30 days and a day 24 hours. Used for comparisons.
+    pub fn normalized(&self) -> i128 {
+        (self.months as i128 * DAYS_PER_MONTH as i128 + self.days as i128) * DAY as i128
+            + self.micros as i128
+    }
+
+    /// Creates an interval from components computed with checked arithmetic,
+    /// erroring if any of them overflowed or the months or days don't fit in
+    /// 32 bits.
+    fn checked(months: Option<i64>, days: Option<i64>, micros: Option<i64>) -> Result<Self> {
+        let in_range = |n: i64| i32::try_from(n).is_ok();
+        match (months, days, micros) {
+            (Some(months), Some(days), Some(micros)) if in_range(months) && in_range(days) => {
+                Ok(Self::new(months, days, micros))
+            }
+            _ => errinput!("interval out of range"),
+        }
+    }
+
+    /// Adds two intervals, componentwise. Errors if out of range.
+    pub fn checked_add(&self, other: &Self) -> Result<Self> {
+        Self::checked(
+            self.months.checked_add(other.months),
+            self.days.checked_add(other.days),
+            self.micros.checked_add(other.micros),
+        )
+    }
+
+    /// Subtracts two intervals, componentwise. Errors if out of range.
+    pub fn checked_sub(&self, other: &Self) -> Result<Self> {
+        self.checked_add(&other.checked_neg()?)
+    }
+
+    /// Negates the interval. Errors if out of range.
+    pub fn checked_neg(&self) -> Result<Self> {
+        Self::checked(self.months.checked_neg(), self.days.checked_neg(), self.micros.checked_neg())
+    }
+
+    /// Multiplies the interval by an integer, componentwise. Errors if out of
+    /// range.
+    pub fn checked_mul(&self, factor: i64) -> Result<Self> {
+        Self::checked(
+            self.months.checked_mul(factor),
+            self.days.checked_mul(factor),
+            self.micros.checked_mul(factor),
+        )
+    }
+
+    /// Multiplies the interval by a float. Fractional months and days cascade
+    /// into the smaller components, like Postgres. Errors if out of range or
+    /// not finite.
+    pub fn checked_mul_float(&self, factor: f64) -> Result<Self> {
+        self.scale(|v| v * factor)
+    }
+
+    /// Divides the interval by a number, cascading fractional months and days
+    /// into the smaller components. Errors on division by zero.
+    pub fn checked_div_float(&self, divisor: f64) -> Result<Self> {
+        if divisor == 0.0 {
+            return errinput!("can't divide by zero");
+        }
+        self.scale(|v| v / divisor)
+    }
+
+    /// Scales each component with the given function, cascading fractions.
+    fn scale(&self, f: impl Fn(f64) -> f64) -> Result<Self> {
+        let months = f(self.months as f64);
+        let days = f(self.days as f64) + months.fract() * DAYS_PER_MONTH as f64;
+        let micros = f(self.micros as f64) + days.fract() * DAY as f64;
+        Self::checked(to_i64(months.trunc()), to_i64(days.trunc()), to_i64(micros.round()))
+    }
+
+    /// Formats the interval in the ISO 8601 format, e.g. P1Y2M3DT4H5M6.5S, as
+    /// with Postgres' iso_8601 intervalstyle. Each component has its own sign.
+    pub fn to_iso8601(&self) -> String {
+        let mut output = "P".to_string();
+        let (years, months) = (self.months / 12, self.months % 12);
+        for (n, unit) in [(years, 'Y'), (months, 'M'), (self.days, 'D')] {
+            if n != 0 {
+                output.push_str(&format!("{n}{unit}"));
+            }
+        }
+        let (hours, rest) = (self.micros / HOUR, self.micros % HOUR);
+        let (minutes, rest) = (rest / MINUTE, rest % MINUTE);
+        if rest == 0 && hours == 0 && minutes == 0 && output.len() > 1 {
+            return output;
+        }
+        output.push('T');
+        for (n, unit) in [(hours, 'H'), (minutes, 'M')] {
+            if n != 0 {
+                output.push_str(&format!("{n}{unit}"));
+            }
+        }
+        if rest != 0 || output.ends_with('T') {
+            let sign = if rest < 0 { "-" } else { "" };
+            let rest = rest.unsigned_abs();
+            let (seconds, fraction) = (rest / SECOND as u64, rest % SECOND as u64);
+            output.push_str(&format!("{sign}{seconds}"));
+            if fraction != 0 {
+                output.push_str(format!(".{fraction:06}").trim_end_matches('0'));
+            }
+            output.push('S');
+        }
+        output
+    }
+}
+
+/// Converts a float to an i64, or None if it's out of range or not finite.
+fn to_i64(f: f64) -> Option<i64> {
+    if !f.is_finite() || f < i64::MIN as f64 || f >= i64::MAX as f64 {
+        return None;
+    }
+    Some(f as i64)
+}
+
+/// Formats the interval like Postgres, e.g. '1 year 2 mons 3 days 04:05:06'.
+impl Display for Interval {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut parts = Vec::new();
+        let plural = |n: i64| if n == 1 { "" } else { "s" };
+        let (years, months) = (self.months / 12, self.months % 12);
+        if years != 0 {
+            parts.push(format!("{years} year{}", plural(years)));
+        }
+        if months != 0 {
+            parts.push(format!("{months} mon{}", plural(months)));
+        }
+        if self.days != 0 {
+            parts.push(format!("{} day{}", self.days, plural(self.days)));
+        }
+        if self.micros != 0 || parts.is_empty() {
+            let sign = if self.micros < 0 { "-" } else { "" };
+            let micros = self.micros.unsigned_abs();
+            let (hours, rest) = (micros / HOUR as u64, micros % HOUR as u64);
+            let (minutes, rest) = (rest / MINUTE as u64, rest % MINUTE as u64);
+            let (seconds, fraction) = (rest / SECOND as u64, rest % SECOND as u64);
+            let mut time = format!("{sign}{hours:02}:{minutes:02}:{seconds:02}");
+            if fraction != 0 {
+                time.push_str(format!(".{fraction:06}").trim_end_matches('0'));
+            }
+            parts.push(time);
+        }
+        f.write_str(&parts.join(" "))
+    }
+}
+
+// Orders intervals by their normalized length, using the components as a
+// tiebreaker such that the ordering is consistent with equality.
+impl Ord for Interval {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.normalized()
+            .cmp(&other.normalized())
+            .then_with(|| (self.months, self.days).cmp(&(other.months, other.days)))
+    }
+}
+
+impl PartialOrd for Interval {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl From<(i64, i64, i64)> for Interval {
+    fn from((months, days, micros): (i64, i64, i64)) -> Self {
+        Self::new(months, days, micros)
+    }
+}
+
+impl From<Interval> for (i64, i64, i64) {
+    fn from(interval: Interval) -> Self {
+        (interval.months, interval.days, interval.micros)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Tests that intervals roundtrip through their display format.
+    #[test]
+    fn display_parse() -> Result<()> {
+        for interval in [
+            Interval::default(),
+            Interval::new(14, 3, 4 * HOUR + 5 * MINUTE + 6 * SECOND + 500_000),
+            Interval::new(-1, -1, -HOUR),
+            Interval::new(0, 1, -2 * HOUR),
+            Interval::new(0, 0, 1),
+            Interval::new(0, 0, 100 * HOUR),
+        ] {
+            assert_eq!(Interval::parse(&interval.to_string())?, interval, "{interval}");
+        }
+        Ok(())
+    }
+
+    /// Tests the ISO 8601 format.
+    #[test]
+    fn iso8601() {
+        for (interval, expect) in [
+            (Interval::default(), "PT0S"),
+            (
+                Interval::new(14, 3, 4 * HOUR + 5 * MINUTE + 6 * SECOND + 500_000),
+                "P1Y2M3DT4H5M6.5S",
+            ),
+            (Interval::new(-1, -1, -HOUR), "P-1M-1DT-1H"),
+            (Interval::new(0, 1, 0), "P1D"),
+            (Interval::new(0, 0, -1), "PT-0.000001S"),
+            (Interval::new(0, 0, 100 * HOUR + 30 * SECOND), "PT100H30S"),
+        ] {
+            assert_eq!(interval.to_iso8601(), expect, "{interval}");
+        }
+    }
+}
@@ -0,0 +1,588 @@
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::fmt::{Display, Write as _};
+use std::hash::{Hash, Hasher};
+use std::iter::Peekable;
+use std::str::Chars;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use super::Value;
+use crate::error::Result;
+use crate::{errdata, errinput};
+
+/// A JSON document, as stored in JSON columns.
+///
+/// Documents are parsed once when converted from strings, and stored using a
+/// compact binary encoding (see encode()) rather than as JSON text, such that
+/// they don't have to be re-parsed when read. Object keys are kept sorted and
+/// deduplicated (the last value wins), like Postgres' jsonb, so two documents
+/// are equal regardless of key order and whitespace.
+///
+/// Numbers without a fraction or exponent that fit in an i64 are stored as
+/// integers, others as floats. Unlike SQL values, JSON can't represent
+/// infinity or NaN, so documents have a simple total order and equality.
+#[derive(Clone, Debug)]
+pub enum Json {
+    Null,
+    Boolean(bool),
+    Integer(i64),
+    Float(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(BTreeMap<String, Json>),
+}
+
+// Binary encoding type tags.
+const TAG_NULL: u8 = 0x00;
+const TAG_FALSE: u8 = 0x01;
+const TAG_TRUE: u8 = 0x02;
+const TAG_INTEGER: u8 = 0x03;
+const TAG_FLOAT: u8 = 0x04;
+const TAG_STRING: u8 = 0x05;
+const TAG_ARRAY: u8 = 0x06;
+const TAG_OBJECT: u8 = 0x07;
+
+impl Json {
+    /// Parses a JSON document from a string.
+    pub fn parse(input: &str) -> Result<Self> {
+        let mut parser = Parser { chars: input.chars().peekable() };
+        let json = parser.parse_value()?;
+        parser.skip_whitespace();
+        if let Some(c) = parser.chars.next() {
+            return errinput!("invalid JSON: unexpected character {c}");
+        }
+        Ok(json)
+    }
+
+    /// Encodes the document using a compact binary encoding. Each value is
+    /// prefixed with a type tag byte. Integers and floats use 8 big-endian
+    /// bytes, while strings, arrays and objects are prefixed by their length
+    /// as an unsigned LEB128 varint. Object entries are stored as key/value
+    /// pairs in key order.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        self.encode_into(&mut buf);
+        buf
+    }
+
+    fn encode_into(&self, buf: &mut Vec<u8>) {
+        match self {
+            Self::Null => buf.push(TAG_NULL),
+            Self::Boolean(false) => buf.push(TAG_FALSE),
+            Self::Boolean(true) => buf.push(TAG_TRUE),
+            Self::Integer(i) => {
+                buf.push(TAG_INTEGER);
+                buf.extend(i.to_be_bytes());
+            }
+            Self::Float(f) => {
+                buf.push(TAG_FLOAT);
+                buf.extend(f.to_be_bytes());
+            }
+            Self::String(s) => {
+                buf.push(TAG_STRING);
+                encode_str(s, buf);
+            }
+            Self::Array(array) => {
+                buf.push(TAG_ARRAY);
+                encode_len(array.len(), buf);
+                array.iter().for_each(|v| v.encode_into(buf));
+            }
+            Self::Object(object) => {
+                buf.push(TAG_OBJECT);
+                encode_len(object.len(), buf);
+                for (key, value) in object {
+                    encode_str(key, buf);
+                    value.encode_into(buf);
+                }
+            }
+        }
+    }
+
+    /// Decodes a document from its binary encoding.
+    pub fn decode(mut bytes: &[u8]) -> Result<Self> {
+        let json = Self::decode_from(&mut bytes)?;
+        if !bytes.is_empty() {
+            return errdata!("unexpected trailing bytes {bytes:x?}");
+        }
+        Ok(json)
+    }
+
+    fn decode_from(bytes: &mut &[u8]) -> Result<Self> {
+        Ok(match take(bytes, 1)?[0] {
+            TAG_NULL => Self::Null,
+            TAG_FALSE => Self::Boolean(false),
+            TAG_TRUE => Self::Boolean(true),
+            TAG_INTEGER => Self::Integer(i64::from_be_bytes(take(bytes, 8)?.try_into()?)),
+            TAG_FLOAT => Self::Float(f64::from_be_bytes(take(bytes, 8)?.try_into()?)),
+            TAG_STRING => Self::String(decode_str(bytes)?),
+            TAG_ARRAY => {
+                let len = decode_len(bytes)?;
+                Self::Array((0..len).map(|_| Self::decode_from(bytes)).collect::<Result<_>>()?)
+            }
+            TAG_OBJECT => {
+                let len = decode_len(bytes)?;
+                let mut object = BTreeMap::new();
+                for _ in 0..len {
+                    object.insert(decode_str(bytes)?, Self::decode_from(bytes)?);
+                }
+                Self::Object(object)
+            }
+            tag => return errdata!("invalid JSON type tag {tag:x}"),
+        })
+    }
+
+    /// Looks up an object field by string key, or an array element by integer
+    /// index (negative indexes count from the end). Returns None if the
+    /// element doesn't exist, or the key type doesn't match the document type.
+    pub fn get(&self, key: &Value) -> Option<&Json> {
+        match (self, key) {
+            (Self::Object(object), Value::String(key)) => object.get(key),
+            (Self::Array(array), Value::Integer(index)) => {
+                let index = match *index {
+                    i if i < 0 => (array.len() as i64).checked_add(i)?,
+                    i => i,
+                };
+                array.get(usize::try_from(index).ok()?)
+            }
+            _ => None,
+        }
+    }
+
+    /// Looks up an element via a path expression, e.g. `$.items[0].name`. The
+    /// path must start with $ (the root document), followed by any number of
+    /// `.key` object field or `[n]` array index accessors. Returns None if the
+    /// element doesn't exist, and errors if the path is invalid.
+    pub fn get_path(&self, path: &str) -> Result<Option<&Json>> {
+        let Some(mut path) = path.strip_prefix('$') else {
+            return errinput!("invalid JSON path {path}: must start with $");
+        };
+        let mut json = Some(self);
+        while !path.is_empty() {
+            let key = if let Some(rest) = path.strip_prefix('.') {
+                let end = rest.find(['.', '[']).unwrap_or(rest.len());
+                if end == 0 {
+                    return errinput!("invalid JSON path: missing key after .");
+                }
+                path = &rest[end..];
+                Value::String(rest[..end].to_string())
+            } else if let Some(rest) = path.strip_prefix('[') {
+                let Some((index, rest)) = rest.split_once(']') else {
+                    return errinput!("invalid JSON path: missing ]");
+                };
+                let Ok(index) = index.parse() else {
+                    return errinput!("invalid JSON path index {index}");
+                };
+                path = rest;
+                Value::Integer(index)
+            } else {
+                return errinput!("invalid JSON path at {path}");
+            };
+            json = json.and_then(|json| json.get(&key));
+        }
+        Ok(json)
+    }
+
+    /// Converts the document into a SQL value. JSON scalars are converted to
+    /// the corresponding SQL value, while arrays and objects remain JSON.
+    pub fn into_value(self) -> Value {
+        match self {
+            Self::Null => Value::Null,
+            Self::Boolean(b) => Value::Boolean(b),
+            Self::Integer(i) => Value::Integer(i),
+            Self::Float(f) => Value::Float(f),
+            Self::String(s) => Value::String(s),
+            json @ (Self::Array(_) | Self::Object(_)) => Value::Json(json),
+        }
+    }
+
+    /// Returns the name of the JSON type, as returned by json_typeof().
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Self::Null => "null",
+            Self::Boolean(_) => "boolean",
+            Self::Integer(_) | Self::Float(_) => "number",
+            Self::String(_) => "string",
+            Self::Array(_) => "array",
+            Self::Object(_) => "object",
+        }
+    }
+
+    /// Returns the approximate memory size of the document in bytes, including
+    /// heap allocations.
+    pub fn size(&self) -> usize {
+        size_of::<Self>()
+            + match self {
+                Self::Null | Self::Boolean(_) | Self::Integer(_) | Self::Float(_) => 0,
+                Self::String(s) => s.len(),
+                Self::Array(array) => array.iter().map(|v| v.size()).sum(),
+                Self::Object(object) => object.iter().map(|(k, v)| k.len() + v.size()).sum(),
+            }
+    }
+
+    /// Returns a rank of the type, used for ordering mixed types.
+    fn type_rank(&self) -> u8 {
+        match self {
+            Self::Null => 0,
+            Self::Boolean(_) => 1,
+            Self::Integer(_) => 2,
+            Self::Float(_) => 3,
+            Self::String(_) => 4,
+            Self::Array(_) => 5,
+            Self::Object(_) => 6,
+        }
+    }
+}
+
+/// Encodes a length as an unsigned LEB128 varint.
+fn encode_len(mut len: usize, buf: &mut Vec<u8>) {
+    while len >= 0x80 {
+        buf.push(len as u8 | 0x80);
+        len >>= 7;
+    }
+    buf.push(len as u8);
+}
+
+/// Encodes a length-prefixed string.
+fn encode_str(s: &str, buf: &mut Vec<u8>) {
+    encode_len(s.len(), buf);
+    buf.extend(s.as_bytes());
+}
+
+/// Decodes an unsigned LEB128 varint length.
+fn decode_len(bytes: &mut &[u8]) -> Result<usize> {
+    let mut len: usize = 0;
+    for shift in (0..usize::BITS).step_by(7) {
+        let byte = take(bytes, 1)?[0];
+        len |= ((byte & 0x7f) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(len);
+        }
+    }
+    errdata!("invalid JSON length varint")
+}
+
+/// Decodes a length-prefixed string.
+fn decode_str(bytes: &mut &[u8]) -> Result<String> {
+    let len = decode_len(bytes)?;
+    Ok(String::from_utf8(take(bytes, len)?.to_vec())?)
+}
+
+/// Takes the given number of bytes from the start of a byte slice.
+fn take<'a>(bytes: &mut &'a [u8], len: usize) -> Result<&'a [u8]> {
+    if bytes.len() < len {
+        return errdata!("unexpected end of JSON encoding");
+    }
+    let (head, tail) = bytes.split_at(len);
+    *bytes = tail;
+    Ok(head)
+}
+
+/// Formats the document as compact JSON text.
+impl Display for Json {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Null => f.write_str("null"),
+            Self::Boolean(b) => b.fmt(f),
+            Self::Integer(i) => i.fmt(f),
+            Self::Float(float) => write!(f, "{float:?}"),
+            Self::String(s) => format_str(s, f),
+            Self::Array(array) => {
+                f.write_char('[')?;
+                for (i, value) in array.iter().enumerate() {
+                    if i > 0 {
+                        f.write_char(',')?;
+                    }
+                    value.fmt(f)?;
+                }
+                f.write_char(']')
+            }
+            Self::Object(object) => {
+                f.write_char('{')?;
+                for (i, (key, value)) in object.iter().enumerate() {
+                    if i > 0 {
+                        f.write_char(',')?;
+                    }
+                    format_str(key, f)?;
+                    f.write_char(':')?;
+                    value.fmt(f)?;
+                }
+                f.write_char('}')
+            }
+        }
+    }
+}
+
+/// Formats a quoted and escaped JSON string.
+fn format_str(s: &str, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    f.write_char('"')?;
+    for c in s.chars() {
+        match c {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            '\n' => f.write_str("\\n")?,
+            '\r' => f.write_str("\\r")?,
+            '\t' => f.write_str("\\t")?,
+            c if c.is_control() => write!(f, "\\u{:04x}", c as u32)?,
+            c => f.write_char(c)?,
+        }
+    }
+    f.write_char('"')
+}
+
+impl PartialEq for Json {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Json {}
+
+impl Hash for Json {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.type_rank().hash(state);
+        match self {
+            Self::Null => {}
+            Self::Boolean(b) => b.hash(state),
+            Self::Integer(i) => i.hash(state),
+            Self::Float(f) => f.to_bits().hash(state),
+            Self::String(s) => s.hash(state),
+            Self::Array(array) => array.hash(state),
+            Self::Object(object) => object.hash(state),
+        }
+    }
+}
+
+// Orders documents first by type, then by value. Integers and floats are
+// considered different types, which keeps the ordering consistent with
+// equality and hashing.
+impl Ord for Json {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (Self::Null, Self::Null) => Ordering::Equal,
+            (Self::Boolean(a), Self::Boolean(b)) => a.cmp(b),
+            (Self::Integer(a), Self::Integer(b)) => a.cmp(b),
+            (Self::Float(a), Self::Float(b)) => a.total_cmp(b),
+            (Self::String(a), Self::String(b)) => a.cmp(b),
+            (Self::Array(a), Self::Array(b)) => a.cmp(b),
+            (Self::Object(a), Self::Object(b)) => a.cmp(b),
+            (a, b) => a.type_rank().cmp(&b.type_rank()),
+        }
+    }
+}
+
+impl PartialOrd for Json {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Serializes documents as bytes using the binary encoding.
+impl Serialize for Json {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(&self.encode())
+    }
+}
+
+impl<'de> Deserialize<'de> for Json {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        let bytes = serde_bytes::ByteBuf::deserialize(deserializer)?;
+        Self::decode(&bytes).map_err(serde::de::Error::custom)
+    }
+}
+
+/// A simple recursive descent JSON text parser, following RFC 8259.
+struct Parser<'a> {
+    chars: Peekable<Chars<'a>>,
+}
+
+impl Parser<'_> {
+    /// Parses the next JSON value.
+    fn parse_value(&mut self) -> Result<Json> {
+        self.skip_whitespace();
+        Ok(match self.chars.peek() {
+            Some('n') => self.parse_keyword("null", Json::Null)?,
+            Some('t') => self.parse_keyword("true", Json::Boolean(true))?,
+            Some('f') => self.parse_keyword("false", Json::Boolean(false))?,
+            Some('"') => Json::String(self.parse_string()?),
+            Some('[') => self.parse_array()?,
+            Some('{') => self.parse_object()?,
+            Some(c) if *c == '-' || c.is_ascii_digit() => self.parse_number()?,
+            Some(c) => return errinput!("invalid JSON: unexpected character {c}"),
+            None => return errinput!("invalid JSON: unexpected end of input"),
+        })
+    }
+
+    /// Parses the given keyword, returning the given value.
+    fn parse_keyword(&mut self, keyword: &str, value: Json) -> Result<Json> {
+        for expect in keyword.chars() {
+            if self.chars.next() != Some(expect) {
+                return errinput!("invalid JSON: expected {keyword}");
+            }
+        }
+        Ok(value)
+    }
+
+    /// Parses a number.
+    fn parse_number(&mut self) -> Result<Json> {
+        let mut number = String::new();
+        let mut is_float = false;
+        while let Some(c) =
+            self.chars.next_if(|c| matches!(c, '-' | '+' | '.' | 'e' | 'E' | '0'..='9'))
+        {
+            is_float |= matches!(c, '.' | 'e' | 'E');
+            number.push(c);
+        }
+        if !is_float {
+            if let Ok(i) = number.parse() {
+                return Ok(Json::Integer(i));
+            }
+        }
+        match number.parse::<f64>() {
+            // Normalize -0.0 to 0.0, as for SQL values.
+            Ok(f) if f.is_finite() => Ok(Json::Float(if f == 0.0 { 0.0 } else { f })),
+            _ => errinput!("invalid JSON number {number}"),
+        }
+    }
+
+    /// Parses a quoted string, resolving escape sequences.
+    fn parse_string(&mut self) -> Result<String> {
+        self.expect('"')?;
+        let mut string = String::new();
+        loop {
+            match self.chars.next() {
+                Some('"') => break,
+                Some('\\') => match self.chars.next() {
+                    Some('"') => string.push('"'),
+                    Some('\\') => string.push('\\'),
+                    Some('/') => string.push('/'),
+                    Some('b') => string.push('\x08'),
+                    Some('f') => string.push('\x0c'),
+                    Some('n') => string.push('\n'),
+                    Some('r') => string.push('\r'),
+                    Some('t') => string.push('\t'),
+                    Some('u') => string.push(self.parse_unicode_escape()?),
+                    _ => return errinput!("invalid JSON string escape sequence"),
+                },
+                Some(c) if c.is_control() => {
+                    return errinput!("invalid JSON: unescaped control character in string");
+                }
+                Some(c) => string.push(c),
+                None => return errinput!("invalid JSON: unterminated string"),
+            }
+        }
+        Ok(string)
+    }
+
+    /// Parses the hex digits of a \u escape sequence, including a following
+    /// low surrogate escape if the first is a high surrogate.
+    fn parse_unicode_escape(&mut self) -> Result<char> {
+        let mut code = self.parse_hex4()?;
+        if (0xd800..0xdc00).contains(&code) {
+            if self.chars.next() != Some('\\') || self.chars.next() != Some('u') {
+                return errinput!("invalid JSON: unpaired surrogate in string");
+            }
+            let low = self.parse_hex4()?;
+            if !(0xdc00..0xe000).contains(&low) {
+                return errinput!("invalid JSON: unpaired surrogate in string");
+            }
+            code = 0x10000 + ((code - 0xd800) << 10) + (low - 0xdc00);
+        }
+        match char::from_u32(code) {
+            Some(c) => Ok(c),
+            None => errinput!("invalid JSON: invalid unicode escape in string"),
+        }
+    }
+
+    /// Parses 4 hex digits.
+    fn parse_hex4(&mut self) -> Result<u32> {
+        let hex: String = self.chars.by_ref().take(4).collect();
+        match u32::from_str_radix(&hex, 16) {
+            Ok(code) if hex.len() == 4 => Ok(code),
+            _ => errinput!("invalid JSON: invalid unicode escape {hex}"),
+        }
+    }
+
+    /// Parses an array.
+    fn parse_array(&mut self) -> Result<Json> {
+        self.expect('[')?;
+        let mut array = Vec::new();
+        self.skip_whitespace();
+        if self.chars.next_if_eq(&']').is_some() {
+            return Ok(Json::Array(array));
+        }
+        loop {
+            array.push(self.parse_value()?);
+            self.skip_whitespace();
+            match self.chars.next() {
+                Some(',') => {}
+                Some(']') => break,
+                _ => return errinput!("invalid JSON: expected , or ] in array"),
+            }
+        }
+        Ok(Json::Array(array))
+    }
+
+    /// Parses an object.
+    fn parse_object(&mut self) -> Result<Json> {
+        self.expect('{')?;
+        let mut object = BTreeMap::new();
+        self.skip_whitespace();
+        if self.chars.next_if_eq(&'}').is_some() {
+            return Ok(Json::Object(object));
+        }
+        loop {
+            self.skip_whitespace();
+            let key = self.parse_string()?;
+            self.skip_whitespace();
+            self.expect(':')?;
+            object.insert(key, self.parse_value()?);
+            self.skip_whitespace();
+            match self.chars.next() {
+                Some(',') => {}
+                Some('}') => break,
+                _ => return errinput!("invalid JSON: expected , or }} in object"),
+            }
+        }
+        Ok(Json::Object(object))
+    }
+
+    /// Consumes the given character, or errors.
+    fn expect(&mut self, expect: char) -> Result<()> {
+        match self.chars.next() {
+            Some(c) if c == expect => Ok(()),
+            Some(c) => errinput!("invalid JSON: expected {expect}, found {c}"),
+            None => errinput!("invalid JSON: expected {expect}, found end of input"),
+        }
+    }
+
+    /// Skips any whitespace.
+    fn skip_whitespace(&mut self) {
+        while self.chars.next_if(|c| matches!(c, ' ' | '\t' | '\n' | '\r')).is_some() {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Tests that documents roundtrip through the binary encoding.
+    #[test]
+    fn encode_decode() -> Result<()> {
+        for input in [
+            "null",
+            "true",
+            "-7",
+            "3.14",
+            r#""foo\né""#,
+            "[]",
+            "{}",
+            r#"{"a":[1,2.5,{"b":null}],"c":"x"}"#,
+            &format!("[{}]", vec!["\"long string value\""; 100].join(",")),
+        ] {
+            let json = Json::parse(input)?;
+            assert_eq!(Json::decode(&json.encode())?, json, "{input}");
+        }
+        Ok(())
+    }
+}
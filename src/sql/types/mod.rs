@@ -1,9 +1,27 @@
 //! The SQL data model, including data types, expressions, and schema objects.
 
 mod expression;
+mod file;
+mod function;
+mod interval;
+mod json;
 mod schema;
+mod timestamp;
+mod trigger;
+mod user;
 mod value;
 
-pub use expression::Expression;
-pub use schema::{Column, Table};
+pub use expression::{Comparison, Expression};
+pub use file::FileFormat;
+pub use function::{Function, Functions, ScalarFn};
+pub use interval::Interval;
+pub use json::Json;
+pub use schema::{
+    Collation, Column, IndexState, IndexType, Partition, Table, Ttl, format_ident, tokenize,
+};
+pub use timestamp::Timestamp;
+pub use trigger::{
+    Trigger, TriggerCallback, TriggerCallbacks, TriggerEvent, TriggerFn, TriggerTiming,
+};
+pub use user::{Privilege, User};
 pub use value::{DataType, Label, Row, Rows, Value};
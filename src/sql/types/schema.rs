@@ -1,19 +1,26 @@
 use std::borrow::Cow;
+use std::collections::BTreeSet;
 use std::fmt::Display;
+use std::ops::Bound;
 
 use serde::{Deserialize, Serialize};
 
-use super::{DataType, Value};
+use super::{DataType, Expression, Interval, Label, Timestamp, Trigger, Value};
 use crate::encoding;
-use crate::errinput;
 use crate::error::Result;
 use crate::sql::engine::{Catalog, Transaction};
 use crate::sql::parser::is_ident;
+use crate::sql::planner::Node;
+use crate::storage::mvcc;
+use crate::{errconstraint, errinput};
 
 /// A table schema, which specifies its data structure and constraints.
 ///
-/// Tables can't change after they are created. There is no ALTER TABLE nor
-/// CREATE/DROP INDEX -- only CREATE TABLE and DROP TABLE.
+/// Tables can't change after they are created, except for adding secondary
+/// indexes via CREATE INDEX, creating and dropping triggers via CREATE/DROP
+/// TRIGGER, creating and dropping changefeeds via CREATE/DROP CHANGEFEED, and
+/// dropping partitions via ALTER TABLE ... DROP PARTITION. There is no other
+/// ALTER TABLE nor DROP INDEX.
 #[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
 pub struct Table {
     /// The table name. Can't be empty.
@@ -23,10 +30,97 @@ pub struct Table {
     pub primary_key: usize,
     /// The table's columns. Must have at least one.
     pub columns: Vec<Column>,
+    /// The table's range partitions, in key order, or empty if the table isn't
+    /// partitioned (i.e. PARTITION BY RANGE). Tables are partitioned by their
+    /// primary key. Since rows are stored in primary key order, each partition
+    /// is a contiguous key range that can be scanned or deleted separately.
+    pub partitions: Vec<Partition>,
+    /// The table's SQL triggers, in creation order (which is also their firing
+    /// order).
+    pub triggers: Vec<Trigger>,
+    /// Whether the table has a changefeed, i.e. whether committed row changes
+    /// are recorded in the MVCC commit log (see Transaction::changes).
+    pub changefeed: bool,
+    /// The table's row time-to-live, if any. Expired rows are invisible to
+    /// queries, and are eventually purged (see Ttl).
+    pub ttl: Option<Ttl>,
 }
 
 impl encoding::Value for Table {}
 
+/// A table row time-to-live (i.e. WITH (ttl = ..., ttl_column = ...)). A row
+/// expires once the TTL interval has passed since the time in its TTL column,
+/// given as a TIMESTAMP or an INTEGER number of seconds since the Unix epoch.
+/// Rows with a NULL time never expire.
+///
+/// The planner hides expired rows by filtering table scans on the current
+/// time, so rows expire at the same time for all transactions regardless of
+/// their snapshot. Expired rows are removed by a background purge (see
+/// Session::purge_expired), and until then they still count towards primary
+/// key and unique constraints.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Ttl {
+    /// The interval after which rows expire. Must be positive.
+    pub interval: Interval,
+    /// The index of the column containing the row time.
+    pub column: usize,
+}
+
+impl Ttl {
+    /// Returns the latest row time that has expired at the given wall-clock
+    /// time, in nanoseconds since the Unix epoch, for a TTL column of the given
+    /// type. Rows expire when their time is at or before this. Timestamps
+    /// subtract the interval in calendar months and days, like TIMESTAMP
+    /// arithmetic. Returns None if no row can have expired, i.e. if the cutoff
+    /// is before the earliest timestamp.
+    pub fn cutoff(&self, datatype: &DataType, wall_time: u64) -> Option<Value> {
+        if *datatype == DataType::Timestamp {
+            let now = Timestamp::from_wall_time(wall_time).ok()?;
+            return now.checked_sub_interval(&self.interval).ok().map(Value::Timestamp);
+        }
+        let now = (wall_time / 1_000_000_000) as i128;
+        let interval = self.interval.normalized().div_euclid(1_000_000);
+        Some(Value::Integer((now - interval).clamp(i64::MIN as i128, i64::MAX as i128) as i64))
+    }
+
+    /// Returns a filter expression over the table's columns that matches rows
+    /// that haven't expired at the given wall-clock time. The datatype is the
+    /// TTL column's.
+    pub fn live_filter(&self, datatype: &DataType, wall_time: u64) -> Expression {
+        let Some(cutoff) = self.cutoff(datatype, wall_time) else {
+            return Expression::Constant(Value::Boolean(true));
+        };
+        let column = Box::new(Expression::Column(self.column));
+        Expression::Or(
+            Expression::GreaterThan(column.clone(), Expression::Constant(cutoff).into()).into(),
+            Expression::Is(column, Value::Null).into(),
+        )
+    }
+
+    /// Returns a filter expression over the table's columns that matches rows
+    /// that have expired at the given wall-clock time. The datatype is the TTL
+    /// column's.
+    pub fn expired_filter(&self, datatype: &DataType, wall_time: u64) -> Expression {
+        let Some(cutoff) = self.cutoff(datatype, wall_time) else {
+            return Expression::Constant(Value::Boolean(false));
+        };
+        let column = Box::new(Expression::Column(self.column));
+        Expression::Not(Expression::GreaterThan(column, Expression::Constant(cutoff).into()).into())
+    }
+}
+
+/// A table range partition. It contains the primary keys from the previous
+/// partition's bound (inclusive) up to this partition's bound (exclusive).
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Partition {
+    /// The partition name. Unique within the table.
+    pub name: String,
+    /// The exclusive upper bound of the partition's primary keys, i.e. VALUES
+    /// LESS THAN. None if unbounded (i.e. MAXVALUE), which is only valid for
+    /// the last partition.
+    pub less_than: Option<Value>,
+}
+
 /// A table column.
 #[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
 pub struct Column {
@@ -47,9 +141,208 @@ pub struct Column {
     /// primary keys, which are the implicit primary index. Must be true for
     /// unique or reference columns.
     pub index: bool,
+    /// The secondary index type, if the column has an index. Full-text indexes
+    /// are only valid for STRING columns. Full-text and hash indexes can't be
+    /// used for unique or reference columns.
+    pub index_type: IndexType,
+    /// If set, the secondary index is a partial index that only contains rows
+    /// where the predicate evaluates to true (i.e. CREATE INDEX ... WHERE).
+    /// Column references are table column indexes. Requires an index, and
+    /// can't be used for unique or reference columns, whose constraint checks
+    /// use the index.
+    pub index_predicate: Option<Expression>,
+    /// The secondary index state, if the column has an index. Only public
+    /// indexes are used by queries, others are being built online.
+    pub index_state: IndexState,
     /// If set, this column is a foreign key reference to the given table's
     /// primary key. Must be of the same type as the target primary key.
     pub references: Option<String>,
+    /// The column's collation, used when comparing and sorting values. Only
+    /// STRING columns can have a non-binary collation.
+    pub collation: Collation,
+    /// If set, this is a stored generated column, whose value is computed from
+    /// the given expression over the row's other columns on every write (i.e.
+    /// GENERATED ALWAYS AS). Column references are table column indexes, and
+    /// can't refer to other generated columns. Generated columns can't have a
+    /// default value, and can't be primary keys.
+    pub generated: Option<Expression>,
+}
+
+/// The state of a secondary index. Online index builds (see Session) step the
+/// index through these states in order, each in a separate transaction, and
+/// wait for concurrent transactions using the previous state to finish before
+/// moving on. This keeps the index consistent with writers that use either of
+/// two adjacent states, without blocking writes during the build.
+///
+/// The in-progress states contain the version of the transaction that moved
+/// the index into the state, i.e. older transactions may use the previous one.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub enum IndexState {
+    /// Writers remove deleted and updated rows from the index, but don't add
+    /// rows to it.
+    DeleteOnly(mvcc::Version),
+    /// Writers maintain the index, but queries don't use it. Existing rows are
+    /// backfilled in this state.
+    WriteOnly(mvcc::Version),
+    /// The index is complete, and queries use it. The default.
+    #[default]
+    Public,
+}
+
+impl IndexState {
+    /// Returns the next state of an online index build, if any, when moved
+    /// into by a transaction at the given version.
+    pub fn next(&self, version: mvcc::Version) -> Option<Self> {
+        match self {
+            Self::DeleteOnly(_) => Some(Self::WriteOnly(version)),
+            Self::WriteOnly(_) => Some(Self::Public),
+            Self::Public => None,
+        }
+    }
+
+    /// Returns the version that moved the index into an in-progress state, or
+    /// None if the index is public.
+    pub fn version(&self) -> Option<mvcc::Version> {
+        match self {
+            Self::DeleteOnly(version) | Self::WriteOnly(version) => Some(*version),
+            Self::Public => None,
+        }
+    }
+}
+
+impl Display for IndexState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::DeleteOnly(_) => "DELETE_ONLY",
+            Self::WriteOnly(_) => "WRITE_ONLY",
+            Self::Public => "PUBLIC",
+        })
+    }
+}
+
+/// A string collation, which determines how strings are compared, sorted,
+/// grouped, and encoded in index keys.
+///
+/// Collations are implemented by folding strings into a comparison key (see
+/// Collation::fold), which is then compared bytewise. This is used both for
+/// expression evaluation and secondary index keys, such that index lookups
+/// match comparisons.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub enum Collation {
+    /// Compares the strings' UTF-8 bytes, i.e. case-sensitive.
+    #[default]
+    Binary,
+    /// Compares strings case-insensitively, by lowercasing them.
+    NoCase,
+}
+
+impl Collation {
+    /// Parses a collation name, case-insensitively.
+    pub fn parse(name: &str) -> Result<Self> {
+        match name.to_lowercase().as_str() {
+            "binary" => Ok(Self::Binary),
+            "nocase" => Ok(Self::NoCase),
+            _ => errinput!("unknown collation {name}"),
+        }
+    }
+
+    /// Folds a value into its comparison key under this collation. Strings and
+    /// string array elements are folded, other values are returned as is.
+    pub fn fold<'a>(&self, value: impl Into<Cow<'a, Value>>) -> Cow<'a, Value> {
+        let value = value.into();
+        match (self, value.as_ref()) {
+            (Self::Binary, _) => value,
+            (Self::NoCase, Value::String(s)) if s.chars().any(|c| c.is_uppercase()) => {
+                Cow::Owned(Value::String(s.to_lowercase()))
+            }
+            (Self::NoCase, Value::Array(values)) => {
+                Cow::Owned(Value::Array(values.iter().map(|v| self.fold(v).into_owned()).collect()))
+            }
+            (Self::NoCase, _) => value,
+        }
+    }
+}
+
+/// A secondary index type.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub enum IndexType {
+    /// An index of the column values, in order. The default.
+    #[default]
+    BTree,
+    /// A full-text inverted index, which maps each word in a string (see
+    /// tokenize) to the rows containing it. Used for MATCH lookups.
+    FullText,
+    /// An index of the column value hashes. Keys have a small, fixed size
+    /// regardless of the value, but they're unordered and may collide, so
+    /// it's only used for equality lookups, which recheck the values.
+    Hash,
+}
+
+impl IndexType {
+    /// Parses an index type name, case-insensitively.
+    pub fn parse(name: &str) -> Result<Self> {
+        match name.to_lowercase().as_str() {
+            "btree" => Ok(Self::BTree),
+            "fulltext" => Ok(Self::FullText),
+            "hash" => Ok(Self::Hash),
+            _ => errinput!("unknown index type {name}"),
+        }
+    }
+
+    /// Returns the index keys for a column value, which must already be
+    /// folded using the column's collation. A B-tree index is keyed by the
+    /// value itself, a hash index by its hash, and a full-text index by each
+    /// word in it.
+    pub fn keys<'a>(&self, value: Cow<'a, Value>) -> Vec<Cow<'a, Value>> {
+        match (self, value.as_ref()) {
+            (Self::BTree, _) => vec![value],
+            (Self::Hash, value) => vec![Cow::Owned(Self::hash(value))],
+            (Self::FullText, Value::String(text)) => {
+                tokenize(text).into_iter().map(|word| Cow::Owned(Value::String(word))).collect()
+            }
+            (Self::FullText, _) => Vec::new(),
+        }
+    }
+
+    /// Returns the hash index key for a value, as the 64-bit FNV-1a hash of
+    /// its Keycode encoding. The hash is persisted, so it must be stable
+    /// across nodes and versions.
+    pub fn hash(value: &Value) -> Value {
+        let hash = encoding::keycode::serialize(value)
+            .into_iter()
+            .fold(0xcbf29ce484222325_u64, |hash, byte| {
+                (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+            });
+        Value::Integer(hash as i64)
+    }
+}
+
+impl Display for IndexType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::BTree => "BTREE",
+            Self::FullText => "FULLTEXT",
+            Self::Hash => "HASH",
+        })
+    }
+}
+
+/// Splits a string into words for full-text search, i.e. lowercase runs of
+/// alphanumeric characters. There's no stemming or stop words.
+pub fn tokenize(text: &str) -> BTreeSet<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| word.to_lowercase())
+        .collect()
+}
+
+impl Display for Collation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Binary => "BINARY",
+            Self::NoCase => "NOCASE",
+        })
+    }
 }
 
 impl Display for Table {
@@ -65,27 +358,134 @@ impl Display for Table {
             if let Some(default) = &column.default {
                 write!(f, " DEFAULT {default}")?;
             }
+            if let Some(generated) = &column.generated {
+                write!(f, " GENERATED ALWAYS AS ({}) STORED", self.format_expression(generated))?;
+            }
             if i != self.primary_key {
                 if column.unique {
                     write!(f, " UNIQUE")?;
                 }
-                if column.index {
+                if column.index
+                    && column.index_state == IndexState::Public
+                    && column.index_predicate.is_none()
+                    && column.index_type == IndexType::BTree
+                {
                     write!(f, " INDEX")?;
                 }
             }
             if let Some(reference) = &column.references {
                 write!(f, " REFERENCES {reference}")?;
             }
+            if column.collation != Collation::Binary {
+                write!(f, " COLLATE {}", column.collation)?;
+            }
             if i < self.columns.len() - 1 {
                 write!(f, ",")?;
             }
             writeln!(f)?;
         }
-        write!(f, ")")
+        write!(f, ")")?;
+        if !self.partitions.is_empty() {
+            let primary_key = format_ident(&self.columns[self.primary_key].name);
+            writeln!(f, " PARTITION BY RANGE ({primary_key}) (")?;
+            for (i, partition) in self.partitions.iter().enumerate() {
+                write!(f, "  PARTITION {} VALUES LESS THAN ", format_ident(&partition.name))?;
+                match &partition.less_than {
+                    Some(value) => write!(f, "({value})")?,
+                    None => write!(f, "MAXVALUE")?,
+                }
+                if i < self.partitions.len() - 1 {
+                    write!(f, ",")?;
+                }
+                writeln!(f)?;
+            }
+            write!(f, ")")?;
+        }
+        if let Some(ttl) = &self.ttl {
+            let column = format_ident(&self.columns[ttl.column].name);
+            write!(f, " WITH (ttl = '{}', ttl_column = {column})", ttl.interval)?;
+        }
+        // Partial, full-text, and hash indexes can't be given inline, so emit
+        // CREATE INDEX. Indexes that are still being built are omitted.
+        for column in &self.columns {
+            if !column.index
+                || column.index_state != IndexState::Public
+                || column.index_predicate.is_none() && column.index_type == IndexType::BTree
+            {
+                continue;
+            }
+            let (table, name) = (format_ident(&self.name), format_ident(&column.name));
+            write!(f, ";\nCREATE INDEX ON {table} ({name})")?;
+            if column.index_type != IndexType::BTree {
+                write!(f, " USING {}", column.index_type)?;
+            }
+            if let Some(predicate) = &column.index_predicate {
+                write!(f, " WHERE {}", self.format_expression(predicate))?;
+            }
+        }
+        for trigger in &self.triggers {
+            let (name, table) = (format_ident(&trigger.name), format_ident(&self.name));
+            let (timing, event, action) = (trigger.timing, trigger.event, &trigger.action);
+            write!(f, ";\nCREATE TRIGGER {name} {timing} {event} ON {table} {action}")?;
+        }
+        if self.changefeed {
+            write!(f, ";\nCREATE CHANGEFEED FOR TABLE {}", format_ident(&self.name))?;
+        }
+        Ok(())
     }
 }
 
 impl Table {
+    /// Formats an expression over the table's columns, e.g. a generated column
+    /// expression, using the column names as labels.
+    pub fn format_expression(&self, expr: &Expression) -> String {
+        let columns = self
+            .columns
+            .iter()
+            .map(|c| Label::Unqualified(format_ident(&c.name).into_owned()))
+            .collect();
+        expr.format(&Node::Nothing { columns })
+    }
+
+    /// Returns the column indexes of the given column names that values are
+    /// written to, e.g. by INSERT. Errors on unknown, generated, or duplicate
+    /// columns.
+    pub fn write_columns(&self, names: &[String]) -> Result<Vec<usize>> {
+        let mut indexes: Vec<usize> = Vec::with_capacity(names.len());
+        for name in names {
+            let Some(index) = self.columns.iter().position(|c| &c.name == name) else {
+                return errinput!("unknown column {name} in table {}", self.name);
+            };
+            if self.columns[index].generated.is_some() {
+                return errinput!("can't insert into generated column {name}");
+            }
+            if indexes.contains(&index) {
+                return errinput!("column {name} given multiple times");
+            }
+            indexes.push(index);
+        }
+        Ok(indexes)
+    }
+
+    /// Returns the primary key range of the given partition index.
+    pub fn partition_range(&self, index: usize) -> (Bound<Value>, Bound<Value>) {
+        let start = match index.checked_sub(1).map(|i| &self.partitions[i].less_than) {
+            Some(Some(value)) => Bound::Included(value.clone()),
+            Some(None) | None => Bound::Unbounded,
+        };
+        let end = match &self.partitions[index].less_than {
+            Some(value) => Bound::Excluded(value.clone()),
+            None => Bound::Unbounded,
+        };
+        (start, end)
+    }
+
+    /// Returns the index of the partition containing the given primary key, or
+    /// None if no partition contains it. Only valid for partitioned tables.
+    pub fn partition_of(&self, id: &Value) -> Option<usize> {
+        self.partitions.iter().position(|p| p.less_than.as_ref().is_none_or(|v| id < v))
+    }
+
     /// Validates the table schema, using the catalog to validate foreign key
     /// references.
     pub fn validate(&self, catalog: &impl Catalog) -> Result<()> {
@@ -119,20 +519,133 @@ impl Table {
                 }
             }
 
+            // Arrays can't be encoded as keys, so they can't be used as primary
+            // keys or in secondary indexes.
+            if let DataType::Array(_) = ctype {
+                if is_primary_key {
+                    return errinput!("primary key {cname} can't be an array");
+                }
+                if column.index {
+                    return errinput!("array column {cname} can't have an index");
+                }
+            }
+
+            // Intervals that compare equal in SQL can have different
+            // components (e.g. '1 month' and '30 days'), so key lookups
+            // wouldn't match SQL equality. Disallow them as keys.
+            if *ctype == DataType::Interval {
+                if is_primary_key {
+                    return errinput!("primary key {cname} can't be an interval");
+                }
+                if column.index {
+                    return errinput!("interval column {cname} can't have an index");
+                }
+            }
+
+            // Only strings can have a collation. Primary keys are also used for
+            // row storage keys, so they can't have one either.
+            if column.collation != Collation::Binary {
+                if *ctype != DataType::String {
+                    return errinput!("{ctype} column {cname} can't have a collation");
+                }
+                if is_primary_key {
+                    return errinput!("primary key {cname} can't have a collation");
+                }
+            }
+
+            // Validate generated columns. These are computed on every write, so
+            // they can't have a default, and generated primary keys could
+            // change the row's identity on updates.
+            if let Some(generated) = &column.generated {
+                if is_primary_key {
+                    return errinput!("primary key {cname} can't be a generated column");
+                }
+                if column.default.is_some() {
+                    return errinput!("generated column {cname} can't have a default value");
+                }
+                if generated.contains(&|e| match e {
+                    Expression::Column(i) => {
+                        self.columns.get(*i).is_some_and(|c| c.generated.is_some())
+                    }
+                    _ => false,
+                }) {
+                    return errinput!("generated column {cname} can't reference generated columns");
+                }
+            }
+
             // Validate default value.
-            match column.default.as_ref().map(|v| v.datatype()) {
+            match &column.default {
+                None if column.generated.is_some() => {}
                 None if column.nullable => {
                     return errinput!("nullable column {cname} must have a default value");
                 }
-                Some(None) if !column.nullable => {
+                Some(Value::Null) if !column.nullable => {
                     return errinput!("invalid NULL default for non-nullable column {cname}");
                 }
-                Some(Some(vtype)) if vtype != column.datatype => {
-                    return errinput!("invalid default type {vtype} for {ctype} column {cname}");
-                }
+                Some(value) if !ctype.accepts(value) => match value.datatype() {
+                    Some(vtype) => {
+                        return errinput!(
+                            "invalid default type {vtype} for {ctype} column {cname}"
+                        );
+                    }
+                    None => return errinput!("invalid default {value} for {ctype} column {cname}"),
+                },
                 Some(_) | None => {}
             }
 
+            // Validate partial index. Unique and reference constraint checks
+            // look up values in the index, so it must contain all rows.
+            if column.index_predicate.is_some() {
+                if !column.index {
+                    return errinput!("column {cname} has an index predicate but no index");
+                }
+                if column.unique || column.references.is_some() {
+                    return errinput!(
+                        "unique or reference column {cname} can't have a partial index"
+                    );
+                }
+            }
+
+            // Validate full-text index. Unique and reference constraint checks
+            // look up the entire value in the index.
+            if column.index_type == IndexType::FullText {
+                if !column.index {
+                    return errinput!("column {cname} has an index type but no index");
+                }
+                if *ctype != DataType::String {
+                    return errinput!("{ctype} column {cname} can't have a full-text index");
+                }
+                if column.unique || column.references.is_some() {
+                    return errinput!(
+                        "unique or reference column {cname} can't have a full-text index"
+                    );
+                }
+            }
+
+            // Validate hash index. Values may have the same hash, so unique and
+            // reference constraint checks can't use it.
+            if column.index_type == IndexType::Hash {
+                if !column.index {
+                    return errinput!("column {cname} has an index type but no index");
+                }
+                if column.unique || column.references.is_some() {
+                    return errinput!("unique or reference column {cname} can't have a hash index");
+                }
+            }
+
+            // Validate index builds. Unique and reference constraint checks use
+            // the index, so it must be public.
+            if column.index_state != IndexState::Public {
+                if !column.index {
+                    return errinput!("column {cname} has an index state but no index");
+                }
+                if column.unique || column.references.is_some() {
+                    return errinput!(
+                        "unique or reference column {cname} must have a public index"
+                    );
+                }
+            }
+
             // Validate unique index.
             if column.unique && !column.index && !is_primary_key {
                 return errinput!("unique column {cname} must have a secondary index");
@@ -144,9 +657,9 @@ impl Table {
                     return errinput!("reference column {cname} must have a secondary index");
                 }
                 let reftype = if reference == &self.name {
-                    self.columns[self.primary_key].datatype
+                    self.columns[self.primary_key].datatype.clone()
                 } else if let Some(target) = catalog.get_table(reference)? {
-                    target.columns[target.primary_key].datatype
+                    target.columns[target.primary_key].datatype.clone()
                 } else {
                     return errinput!("unknown table {reference} referenced by column {cname}");
                 };
@@ -157,6 +670,69 @@ impl Table {
                 }
             }
         }
+
+        // Validate partitions. The bounds must be increasing primary key
+        // values, and only the last partition can be unbounded.
+        let pktype = &self.columns[self.primary_key].datatype;
+        for (i, partition) in self.partitions.iter().enumerate() {
+            let pname = &partition.name;
+            if pname.is_empty() {
+                return errinput!("partition name can't be empty");
+            }
+            if self.partitions[..i].iter().any(|p| &p.name == pname) {
+                return errinput!("duplicate partition {pname}");
+            }
+            match &partition.less_than {
+                Some(value) if value.is_undefined() => {
+                    return errinput!("invalid bound {value} for partition {pname}");
+                }
+                Some(value) if !pktype.accepts(value) => {
+                    return errinput!("invalid bound {value} for {pktype} partition {pname}");
+                }
+                Some(_) => {}
+                None if i < self.partitions.len() - 1 => {
+                    return errinput!("only the last partition can be unbounded");
+                }
+                None => {}
+            }
+            if let (Some(Some(prev)), Some(value)) =
+                (i.checked_sub(1).map(|i| &self.partitions[i].less_than), &partition.less_than)
+            {
+                if value <= prev {
+                    return errinput!(
+                        "partition bounds must be increasing, found {value} after {prev}"
+                    );
+                }
+            }
+        }
+
+        // Validate the TTL. The column must contain timestamps or Unix times
+        // in seconds.
+        if let Some(ttl) = &self.ttl {
+            let Some(column) = self.columns.get(ttl.column) else {
+                return errinput!("invalid TTL column index {}", ttl.column);
+            };
+            if !matches!(column.datatype, DataType::Integer | DataType::Timestamp) {
+                return errinput!(
+                    "TTL column {} must be INTEGER or TIMESTAMP, not {}",
+                    column.name,
+                    column.datatype
+                );
+            }
+            if ttl.interval.normalized() <= 0 {
+                return errinput!("TTL interval must be positive, got '{}'", ttl.interval);
+            }
+        }
+
+        // Validate triggers. Their actions are validated when they're created.
+        for (i, trigger) in self.triggers.iter().enumerate() {
+            if trigger.name.is_empty() {
+                return errinput!("trigger name can't be empty");
+            }
+            if self.triggers[..i].iter().any(|t| t.name == trigger.name) {
+                return errinput!("duplicate trigger {}", trigger.name);
+            }
+        }
         Ok(())
     }
 
@@ -181,7 +757,10 @@ impl Table {
             return errinput!("invalid primary key {id}");
         }
         if !update && !txn.get(&self.name, idslice)?.is_empty() {
-            return errinput!("primary key {id} already exists");
+            return errconstraint!("primary key {id} already exists");
+        }
+        if !self.partitions.is_empty() && self.partition_of(id).is_none() {
+            return errinput!("no partition for primary key {id} in table {}", self.name);
         }
 
         for (i, (column, value)) in self.columns.iter().zip(row).enumerate() {
@@ -189,13 +768,14 @@ impl Table {
             let valueslice = &row[i..=i];
 
             // Validate datatype.
-            if let Some(ref vtype) = value.datatype() {
-                if vtype != ctype {
-                    return errinput!("invalid datatype {vtype} for {ctype} column {cname}");
-                }
+            if !ctype.accepts(value) {
+                return match value.datatype() {
+                    Some(vtype) => errinput!("invalid datatype {vtype} for {ctype} column {cname}"),
+                    None => errinput!("invalid value {value} for {ctype} column {cname}"),
+                };
             }
             if value == &Value::Null && !column.nullable {
-                return errinput!("NULL value not allowed for column {cname}");
+                return errconstraint!("NULL value not allowed for column {cname}");
             }
 
             // Validate outgoing references.
@@ -206,7 +786,7 @@ impl Table {
                     Value::Null => {}
                     v if target == &self.name && v == id => {}
                     v if txn.get(target, valueslice)?.is_empty() => {
-                        return errinput!("reference {v} not in table {target}");
+                        return errconstraint!("reference {v} not in table {target}");
                     }
                     _ => {}
                 }
@@ -219,7 +799,7 @@ impl Table {
                     index.remove(id); // ignore existing version of this row
                 }
                 if !index.is_empty() {
-                    return errinput!("value {value} already in unique column {cname}");
+                    return errconstraint!("value {value} already in unique column {cname}");
                 }
             }
         }
@@ -228,7 +808,7 @@ impl Table {
 }
 
 /// Formats an identifier as valid SQL, quoting it if necessary.
-fn format_ident(ident: &str) -> Cow<str> {
+pub fn format_ident(ident: &str) -> Cow<str> {
     if is_ident(ident) {
         return ident.into();
     }
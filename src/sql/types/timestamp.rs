@@ -0,0 +1,292 @@
+use std::fmt::Display;
+
+use serde::{Deserialize, Serialize};
+
+use super::Interval;
+use super::interval::{DAY, HOUR, MINUTE, SECOND};
+use crate::errinput;
+use crate::error::Result;
+
+/// A date and time of day, as stored in TIMESTAMP columns. Like Postgres'
+/// TIMESTAMP (WITHOUT TIME ZONE), it has microsecond precision and no time
+/// zone, and uses the proleptic Gregorian calendar. It's stored as the number
+/// of microseconds since 1970-01-01 00:00:00, and only years 1 to 9999 are
+/// supported.
+///
+/// Timestamps are serialized as their microseconds, such that the Keycode
+/// encoding orders them chronologically and they can be used in keys.
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(transparent)]
+pub struct Timestamp(i64);
+
+impl Timestamp {
+    /// The earliest supported timestamp, 0001-01-01 00:00:00.
+    const MIN: i64 = -62_135_596_800 * SECOND;
+    /// The latest supported timestamp, 9999-12-31 23:59:59.999999.
+    const MAX: i64 = 253_402_300_800 * SECOND - 1;
+
+    /// Creates a timestamp from microseconds since the Unix epoch. Errors if
+    /// it's outside of the supported range.
+    pub fn from_micros(micros: i64) -> Result<Self> {
+        if !(Self::MIN..=Self::MAX).contains(&micros) {
+            return errinput!("timestamp out of range");
+        }
+        Ok(Self(micros))
+    }
+
+    /// Creates a timestamp from a wall-clock time in nanoseconds since the Unix
+    /// epoch (see clock::Clock), truncated to microseconds.
+    pub fn from_wall_time(wall_time: u64) -> Result<Self> {
+        Self::from_micros(i64::try_from(wall_time / 1000).unwrap_or(i64::MAX))
+    }
+
+    /// Returns the timestamp at midnight of its date.
+    pub fn date(&self) -> Self {
+        Self(self.0 - self.0.rem_euclid(DAY))
+    }
+
+    /// Returns the number of microseconds since the Unix epoch.
+    pub fn micros(&self) -> i64 {
+        self.0
+    }
+
+    /// Creates a timestamp from a calendar date and time of day in
+    /// microseconds. The day must be valid for the month.
+    fn from_parts(year: i64, month: i64, day: i64, time: i64) -> Result<Self> {
+        if !(1..=12).contains(&month) || !(1..=days_in_month(year, month)).contains(&day) {
+            return errinput!("invalid date {year:04}-{month:02}-{day:02}");
+        }
+        let days = days_from_civil(year, month, day);
+        match days.checked_mul(DAY).and_then(|micros| micros.checked_add(time)) {
+            Some(micros) => Self::from_micros(micros),
+            None => errinput!("timestamp out of range"),
+        }
+    }
+
+    /// Returns the calendar date and time of day in microseconds.
+    fn to_parts(self) -> (i64, i64, i64, i64) {
+        let (days, time) = (self.0.div_euclid(DAY), self.0.rem_euclid(DAY));
+        let (year, month, day) = civil_from_days(days);
+        (year, month, day, time)
+    }
+
+    /// Parses a timestamp string, given as 'YYYY-MM-DD' optionally followed by
+    /// a time of day 'HH:MM[:SS[.ffffff]]', separated by a space or T.
+    pub fn parse(input: &str) -> Result<Self> {
+        let invalid = || errinput!("invalid timestamp '{}'", input.escape_debug());
+        let trimmed = input.trim();
+        let (date, time) = match trimmed.split_once([' ', 'T', 't']) {
+            Some((date, time)) => (date, Some(time.trim())),
+            None => (trimmed, None),
+        };
+
+        let mut parts = date.split('-');
+        let (Some(year), Some(month), Some(day), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return invalid();
+        };
+        if year.len() != 4 || month.len() != 2 || day.len() != 2 {
+            return invalid();
+        }
+        let (Ok(year), Ok(month), Ok(day)) = (year.parse(), month.parse(), day.parse()) else {
+            return invalid();
+        };
+
+        let time = match time {
+            Some(time) => match Self::parse_time(time) {
+                Some(time) => time,
+                None => return invalid(),
+            },
+            None => 0,
+        };
+        Self::from_parts(year, month, day, time).or_else(|_| invalid())
+    }
+
+    /// Parses a time of day as HH:MM[:SS[.ffffff]], returning microseconds.
+    fn parse_time(input: &str) -> Option<i64> {
+        let mut parts = input.split(':');
+        let (hours, minutes, seconds) = (parts.next()?, parts.next()?, parts.next());
+        if parts.next().is_some() || hours.len() != 2 || minutes.len() != 2 {
+            return None;
+        }
+        let (hours, minutes): (i64, i64) = (hours.parse().ok()?, minutes.parse().ok()?);
+        let (seconds, fraction) = match seconds {
+            Some(seconds) => seconds.split_once('.').unwrap_or((seconds, "")),
+            None => ("00", ""),
+        };
+        if seconds.len() != 2 || fraction.len() > 6 || !fraction.chars().all(|c| c.is_ascii_digit())
+        {
+            return None;
+        }
+        let seconds: i64 = seconds.parse().ok()?;
+        let fraction: i64 = format!("{fraction:0<6}").parse().ok()?;
+        if hours >= 24 || minutes >= 60 || seconds >= 60 {
+            return None;
+        }
+        Some(hours * HOUR + minutes * MINUTE + seconds * SECOND + fraction)
+    }
+
+    /// Adds an interval to the timestamp, like Postgres. The months are added
+    /// first, clamping the day to the end of the resulting month (e.g.
+    /// 2024-01-31 + 1 month is 2024-02-29), then the days and microseconds.
+    pub fn checked_add_interval(&self, interval: &Interval) -> Result<Self> {
+        let (year, month, day, time) = self.to_parts();
+        let Some(months) = (year * 12 + month - 1).checked_add(interval.months) else {
+            return errinput!("timestamp out of range");
+        };
+        let (year, month) = (months.div_euclid(12), months.rem_euclid(12) + 1);
+        if !(1..=9999).contains(&year) {
+            return errinput!("timestamp out of range");
+        }
+        let day = day.min(days_in_month(year, month));
+        let micros = Self::from_parts(year, month, day, time)?
+            .0
+            .checked_add(interval.days.saturating_mul(DAY))
+            .and_then(|micros| micros.checked_add(interval.micros));
+        match micros {
+            Some(micros) => Self::from_micros(micros),
+            None => errinput!("timestamp out of range"),
+        }
+    }
+
+    /// Subtracts an interval from the timestamp (see checked_add_interval).
+    pub fn checked_sub_interval(&self, interval: &Interval) -> Result<Self> {
+        self.checked_add_interval(&interval.checked_neg()?)
+    }
+
+    /// Returns the interval between two timestamps, self - other, as days and
+    /// microseconds (less than a day), like Postgres.
+    pub fn checked_sub(&self, other: &Self) -> Result<Interval> {
+        let micros = self.0 - other.0; // can't overflow within the supported range
+        Ok(Interval::new(0, micros / DAY, micros % DAY))
+    }
+
+    /// Returns the interval between two timestamps, self - other, in calendar
+    /// years, months, and days, like Postgres' age(). For example, the age of
+    /// 2024-03-01 since 2023-01-31 is 1 year 1 mon 1 day. Each field is
+    /// subtracted separately, borrowing from the next larger field when
+    /// negative, where a month borrows the length of the earlier timestamp's
+    /// month.
+    pub fn age(&self, other: &Self) -> Interval {
+        if self < other {
+            let (months, days, micros) = other.age(self).into();
+            return Interval::new(-months, -days, -micros);
+        }
+        let (year1, month1, day1, time1) = self.to_parts();
+        let (year2, month2, day2, time2) = other.to_parts();
+        let (mut months, mut days, mut micros) =
+            ((year1 - year2) * 12 + month1 - month2, day1 - day2, time1 - time2);
+        if micros < 0 {
+            micros += DAY;
+            days -= 1;
+        }
+        if days < 0 {
+            days += days_in_month(year2, month2);
+            months -= 1;
+        }
+        Interval::new(months, days, micros)
+    }
+}
+
+/// Formats the timestamp like Postgres, e.g. '2024-01-02 03:04:05.5'.
+impl Display for Timestamp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (year, month, day, time) = self.to_parts();
+        let (hours, minutes) = (time / HOUR, time % HOUR / MINUTE);
+        let (seconds, fraction) = (time % MINUTE / SECOND, time % SECOND);
+        write!(f, "{year:04}-{month:02}-{day:02} {hours:02}:{minutes:02}:{seconds:02}")?;
+        if fraction != 0 {
+            f.write_str(format!(".{fraction:06}").trim_end_matches('0'))?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns true if the year is a leap year.
+fn is_leap_year(year: i64) -> bool {
+    year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+/// Returns the number of days in the given month (1-12).
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Returns the number of days since 1970-01-01 for the given date, using
+/// Howard Hinnant's days_from_civil algorithm.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// Returns the date for the given number of days since 1970-01-01, the
+/// inverse of days_from_civil().
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Tests that timestamps roundtrip through their display format, and
+    /// convert to and from calendar dates.
+    #[test]
+    fn display_parse() -> Result<()> {
+        for (input, micros) in [
+            ("1970-01-01 00:00:00", 0),
+            ("1969-12-31 23:59:59.999999", -1),
+            ("2000-02-29 12:30:00.5", 951_827_400_500_000),
+            ("0001-01-01 00:00:00", Timestamp::MIN),
+            ("9999-12-31 23:59:59.999999", Timestamp::MAX),
+        ] {
+            let timestamp = Timestamp::parse(input)?;
+            assert_eq!(timestamp.micros(), micros, "{input}");
+            assert_eq!(timestamp.to_string(), input);
+        }
+        for days in -800_000..800_000 {
+            let (year, month, day) = civil_from_days(days);
+            assert_eq!(days_from_civil(year, month, day), days);
+        }
+        Ok(())
+    }
+
+    /// Tests age(), against results from Postgres.
+    #[test]
+    fn age() -> Result<()> {
+        for (a, b, expect) in [
+            ("2024-03-01", "2023-01-31", "1 year 1 mon 1 day"),
+            ("2024-03-01", "2024-01-31", "1 mon 1 day"),
+            ("2024-03-31", "2024-02-29", "1 mon 2 days"),
+            ("2024-01-01 00:00:00", "2023-12-31 23:00:00", "01:00:00"),
+            ("2023-01-31", "2024-03-01", "-1 years -1 mons -1 days"),
+            ("2024-05-10 10:00:00", "2024-05-10 10:00:00", "00:00:00"),
+        ] {
+            let (a, b) = (Timestamp::parse(a)?, Timestamp::parse(b)?);
+            assert_eq!(a.age(&b).to_string(), expect, "age({a}, {b})");
+        }
+        Ok(())
+    }
+}
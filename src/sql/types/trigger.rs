@@ -0,0 +1,172 @@
+use std::fmt::{Debug, Display};
+use std::sync::{Arc, RwLock};
+
+use serde::{Deserialize, Serialize};
+
+use super::Row;
+use crate::errinput;
+use crate::error::Result;
+use crate::sql::engine::Transaction;
+
+/// When a trigger fires, relative to the row write.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum TriggerTiming {
+    /// Fires before the row is written, and can modify the new row.
+    Before,
+    /// Fires after the row is written.
+    After,
+}
+
+impl Display for TriggerTiming {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Before => "BEFORE",
+            Self::After => "AFTER",
+        })
+    }
+}
+
+/// The row write that fires a trigger.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum TriggerEvent {
+    Insert,
+    Update,
+    Delete,
+}
+
+impl TriggerEvent {
+    /// Returns true if the event has an old row, i.e. UPDATE and DELETE.
+    pub fn has_old(&self) -> bool {
+        matches!(self, Self::Update | Self::Delete)
+    }
+
+    /// Returns true if the event has a new row, i.e. INSERT and UPDATE.
+    pub fn has_new(&self) -> bool {
+        matches!(self, Self::Insert | Self::Update)
+    }
+}
+
+impl Display for TriggerEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Insert => "INSERT",
+            Self::Update => "UPDATE",
+            Self::Delete => "DELETE",
+        })
+    }
+}
+
+/// A row-level SQL trigger, created via CREATE TRIGGER and stored in the table
+/// schema. It fires for every row written by an INSERT, UPDATE, or DELETE
+/// statement on the table, within the writing transaction.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Trigger {
+    /// The trigger name. Unique within the table.
+    pub name: String,
+    /// When the trigger fires.
+    pub timing: TriggerTiming,
+    /// The row write that fires the trigger.
+    pub event: TriggerEvent,
+    /// The trigger action as a SQL string: either a SET clause that modifies
+    /// the new row (BEFORE INSERT and UPDATE only), or an INSERT, UPDATE, or
+    /// DELETE statement. The old and new rows can be referenced in the action
+    /// as old.column and new.column. It's parsed and planned when fired.
+    pub action: String,
+}
+
+/// The closure type of a trigger callback. It's given the writing transaction,
+/// the old row (for UPDATE and DELETE), and the new row (for INSERT and
+/// UPDATE), which BEFORE callbacks can modify.
+pub type TriggerFn =
+    dyn Fn(&dyn Transaction, Option<&Row>, Option<&mut Row>) -> Result<()> + Send + Sync;
+
+/// A row-level trigger implemented by a Rust closure. These are registered
+/// with the SQL engine by embedders (see TriggerCallbacks), and fire like SQL
+/// triggers, after any SQL triggers on the table.
+///
+/// The callback writes rows directly via the transaction, so its writes don't
+/// fire triggers and aren't checked against the table schema.
+#[derive(Clone)]
+pub struct TriggerCallback {
+    /// The callback name. Unique within the table.
+    pub name: String,
+    /// The table name.
+    pub table: String,
+    /// When the callback fires.
+    pub timing: TriggerTiming,
+    /// The row write that fires the callback.
+    pub event: TriggerEvent,
+    /// The callback closure.
+    closure: Arc<TriggerFn>,
+}
+
+impl TriggerCallback {
+    /// Creates a new trigger callback on the given table.
+    pub fn new(
+        name: &str,
+        table: &str,
+        timing: TriggerTiming,
+        event: TriggerEvent,
+        closure: impl Fn(&dyn Transaction, Option<&Row>, Option<&mut Row>) -> Result<()>
+        + Send
+        + Sync
+        + 'static,
+    ) -> Self {
+        let (name, table) = (name.to_string(), table.to_string());
+        Self { name, table, timing, event, closure: Arc::new(closure) }
+    }
+
+    /// Calls the callback.
+    pub fn call(
+        &self,
+        txn: &dyn Transaction,
+        old: Option<&Row>,
+        new: Option<&mut Row>,
+    ) -> Result<()> {
+        (self.closure)(txn, old, new)
+    }
+}
+
+impl Debug for TriggerCallback {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TriggerCallback")
+            .field("name", &self.name)
+            .field("table", &self.table)
+            .field("timing", &self.timing)
+            .field("event", &self.event)
+            .finish()
+    }
+}
+
+/// A registry of trigger callbacks, shared by a SQL engine and its
+/// transactions. Callbacks are registered at runtime, and fire in registration
+/// order.
+#[derive(Default)]
+pub struct TriggerCallbacks(RwLock<Vec<TriggerCallback>>);
+
+impl TriggerCallbacks {
+    /// Creates a new, empty callback registry.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a callback. Errors if a callback with the same name already
+    /// exists on the table.
+    pub fn register(&self, callback: TriggerCallback) -> Result<()> {
+        let mut callbacks = self.0.write()?;
+        if callbacks.iter().any(|c| c.table == callback.table && c.name == callback.name) {
+            return errinput!(
+                "trigger callback {} already exists on table {}",
+                callback.name,
+                callback.table
+            );
+        }
+        callbacks.push(callback);
+        Ok(())
+    }
+
+    /// Fetches the callbacks on the given table.
+    pub fn get(&self, table: &str) -> Result<Vec<TriggerCallback>> {
+        Ok(self.0.read()?.iter().filter(|c| c.table == table).cloned().collect())
+    }
+}
@@ -0,0 +1,137 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Display;
+use std::num::NonZeroU32;
+
+use serde::{Deserialize, Serialize};
+
+use crate::encoding;
+
+/// The number of PBKDF2 iterations used to hash passwords. This is the same as
+/// Postgres' default for SCRAM-SHA-256.
+const PASSWORD_ITERATIONS: NonZeroU32 = NonZeroU32::new(4096).unwrap();
+
+/// A table privilege, granted to users via GRANT and revoked via REVOKE.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+pub enum Privilege {
+    /// Read table rows, via SELECT (or the WHERE clause of UPDATE and DELETE).
+    Select,
+    /// Insert table rows, via INSERT and IMPORT.
+    Insert,
+    /// Update table rows, via UPDATE.
+    Update,
+    /// Delete table rows, via DELETE and TRUNCATE.
+    Delete,
+    /// Create new tables. Only valid for all tables, i.e. ON *.
+    Create,
+    /// Create indexes, triggers, and changefeeds on a table, and alter it.
+    Alter,
+    /// Drop a table, along with its triggers and changefeeds.
+    Drop,
+}
+
+impl Privilege {
+    /// All privileges, i.e. ALL PRIVILEGES.
+    pub const ALL: [Self; 7] = [
+        Self::Select,
+        Self::Insert,
+        Self::Update,
+        Self::Delete,
+        Self::Create,
+        Self::Alter,
+        Self::Drop,
+    ];
+
+    /// All privileges that apply to individual tables, i.e. ALL PRIVILEGES
+    /// on a table. These are granted to users on the tables they create.
+    pub const TABLE: [Self; 6] =
+        [Self::Select, Self::Insert, Self::Update, Self::Delete, Self::Alter, Self::Drop];
+}
+
+impl Display for Privilege {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Select => "SELECT",
+            Self::Insert => "INSERT",
+            Self::Update => "UPDATE",
+            Self::Delete => "DELETE",
+            Self::Create => "CREATE",
+            Self::Alter => "ALTER",
+            Self::Drop => "DROP",
+        })
+    }
+}
+
+/// A user account, created via CREATE USER and stored in the catalog. Users
+/// authenticate with a password when connecting, and can only access tables
+/// they've been granted privileges on. The built-in admin superuser isn't
+/// stored in the catalog, and has all privileges.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct User {
+    /// The user name.
+    pub name: String,
+    /// The random password salt.
+    salt: Vec<u8>,
+    /// The PBKDF2-HMAC-SHA256 password hash.
+    hash: Vec<u8>,
+    /// The user's privileges, by table name, or None for all tables.
+    pub privileges: BTreeMap<Option<String>, BTreeSet<Privilege>>,
+}
+
+impl encoding::Value for User {}
+
+impl User {
+    /// The name of the built-in superuser.
+    pub const ADMIN: &'static str = "admin";
+
+    /// Creates a new user with the given password and no privileges. The
+    /// password is hashed with a random salt.
+    pub fn new(name: &str, password: &str) -> Self {
+        let salt = rand::random::<[u8; 16]>().to_vec();
+        let mut hash = vec![0; ring::digest::SHA256_OUTPUT_LEN];
+        ring::pbkdf2::derive(
+            ring::pbkdf2::PBKDF2_HMAC_SHA256,
+            PASSWORD_ITERATIONS,
+            &salt,
+            password.as_bytes(),
+            &mut hash,
+        );
+        Self { name: name.to_string(), salt, hash, privileges: BTreeMap::new() }
+    }
+
+    /// Returns true if the given password is the user's password.
+    pub fn verify_password(&self, password: &str) -> bool {
+        ring::pbkdf2::verify(
+            ring::pbkdf2::PBKDF2_HMAC_SHA256,
+            PASSWORD_ITERATIONS,
+            &self.salt,
+            password.as_bytes(),
+            &self.hash,
+        )
+        .is_ok()
+    }
+
+    /// Returns true if the user has the given privilege on a table, either
+    /// directly or via a grant on all tables, or on all tables if None.
+    pub fn has_privilege(&self, table: Option<&str>, privilege: Privilege) -> bool {
+        let has = |table| self.privileges.get(&table).is_some_and(|p| p.contains(&privilege));
+        table.is_some_and(|table| has(Some(table.to_string()))) || has(None)
+    }
+
+    /// Grants privileges on a table, or all tables if None.
+    pub fn grant(&mut self, table: Option<&str>, privileges: &[Privilege]) {
+        let granted = self.privileges.entry(table.map(|t| t.to_string())).or_default();
+        granted.extend(privileges);
+    }
+
+    /// Revokes privileges on a table, or all tables if None. Privileges
+    /// granted on all tables are not revoked by a revoke on a single table.
+    pub fn revoke(&mut self, table: Option<&str>, privileges: &[Privilege]) {
+        let table = table.map(|t| t.to_string());
+        if let Some(granted) = self.privileges.get_mut(&table) {
+            granted.retain(|p| !privileges.contains(p));
+            if granted.is_empty() {
+                self.privileges.remove(&table);
+            }
+        }
+    }
+}
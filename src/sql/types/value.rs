@@ -6,15 +6,18 @@ use std::hash::{Hash, Hasher};
 use dyn_clone::DynClone;
 use serde::{Deserialize, Serialize};
 
+use super::{Interval, Json, Timestamp};
 use crate::encoding;
 use crate::error::{Error, Result};
 use crate::sql::parser::ast;
 use crate::{errdata, errinput};
 
-/// A primitive SQL value.
+/// A SQL value.
 ///
 /// For simplicity, only a handful of representative scalar types are supported,
-/// no compound types or more compact variants.
+/// no compound types or more compact variants. The exceptions are JSON, which
+/// can hold semi-structured documents, timestamps and time intervals, and
+/// one-dimensional arrays of scalars.
 ///
 /// In SQL, neither Null nor floating point NaN are considered equal to
 /// themselves (they are unknown values). However, in code, we consider them
@@ -37,6 +40,15 @@ pub enum Value {
     Float(f64),
     /// A UTF-8 encoded string.
     String(String),
+    /// A JSON document.
+    Json(Json),
+    /// An array of values. Elements have the same datatype or are NULL, and
+    /// can't be arrays themselves.
+    Array(Vec<Value>),
+    /// A time interval.
+    Interval(Interval),
+    /// A date and time of day.
+    Timestamp(Timestamp),
 }
 
 impl encoding::Value for Value {}
@@ -51,6 +63,10 @@ impl PartialEq for Value {
             (Self::Integer(l), Self::Integer(r)) => l == r,
             (Self::Float(l), Self::Float(r)) => l == r || l.is_nan() && r.is_nan(),
             (Self::String(l), Self::String(r)) => l == r,
+            (Self::Json(l), Self::Json(r)) => l == r,
+            (Self::Array(l), Self::Array(r)) => l == r,
+            (Self::Interval(l), Self::Interval(r)) => l == r,
+            (Self::Timestamp(l), Self::Timestamp(r)) => l == r,
             (l, r) => core::mem::discriminant(l) == core::mem::discriminant(r),
         }
     }
@@ -68,6 +84,10 @@ impl Hash for Value {
             Self::Integer(v) => v.hash(state),
             Self::Float(v) => v.to_bits().hash(state),
             Self::String(v) => v.hash(state),
+            Self::Json(v) => v.hash(state),
+            Self::Array(v) => v.hash(state),
+            Self::Interval(v) => v.hash(state),
+            Self::Timestamp(v) => v.hash(state),
         }
     }
 }
@@ -86,6 +106,10 @@ impl Ord for Value {
             (Float(a), Integer(b)) => a.total_cmp(&(*b as f64)),
             (Float(a), Float(b)) => a.total_cmp(b),
             (String(a), String(b)) => a.cmp(b),
+            (Json(a), Json(b)) => a.cmp(b),
+            (Array(a), Array(b)) => a.cmp(b),
+            (Interval(a), Interval(b)) => a.cmp(b),
+            (Timestamp(a), Timestamp(b)) => a.cmp(b),
 
             (Null, _) => Less,
             (_, Null) => Greater,
@@ -95,7 +119,15 @@ impl Ord for Value {
             (_, Float(_)) => Greater,
             (Integer(_), _) => Less,
             (_, Integer(_)) => Greater,
-            // String is ordered last.
+            (String(_), _) => Less,
+            (_, String(_)) => Greater,
+            (Timestamp(_), _) => Less,
+            (_, Timestamp(_)) => Greater,
+            (Interval(_), _) => Less,
+            (_, Interval(_)) => Greater,
+            (Json(_), _) => Less,
+            (_, Json(_)) => Greater,
+            // Arrays are ordered last.
         }
     }
 }
@@ -118,8 +150,12 @@ impl Value {
             (Integer(lhs), Float(rhs)) => Float(*lhs as f64 + rhs),
             (Float(lhs), Integer(rhs)) => Float(lhs + *rhs as f64),
             (Float(lhs), Float(rhs)) => Float(lhs + rhs),
-            (Null, Integer(_) | Float(_) | Null) => Null,
-            (Integer(_) | Float(_), Null) => Null,
+            (Interval(lhs), Interval(rhs)) => Interval(lhs.checked_add(rhs)?),
+            (Timestamp(lhs), Interval(rhs)) | (Interval(rhs), Timestamp(lhs)) => {
+                Timestamp(lhs.checked_add_interval(rhs)?)
+            }
+            (Null, Integer(_) | Float(_) | Interval(_) | Timestamp(_) | Null) => Null,
+            (Integer(_) | Float(_) | Interval(_) | Timestamp(_), Null) => Null,
             (lhs, rhs) => return errinput!("can't add {lhs} and {rhs}"),
         })
     }
@@ -133,8 +169,10 @@ impl Value {
             (Integer(lhs), Float(rhs)) => Float(*lhs as f64 / rhs),
             (Float(lhs), Integer(rhs)) => Float(lhs / *rhs as f64),
             (Float(lhs), Float(rhs)) => Float(lhs / rhs),
+            (Interval(lhs), Integer(rhs)) => Interval(lhs.checked_div_float(*rhs as f64)?),
+            (Interval(lhs), Float(rhs)) => Interval(lhs.checked_div_float(*rhs)?),
             (Null, Integer(_) | Float(_) | Null) => Null,
-            (Integer(_) | Float(_), Null) => Null,
+            (Integer(_) | Float(_) | Interval(_), Null) => Null,
             (lhs, rhs) => return errinput!("can't divide {lhs} and {rhs}"),
         })
     }
@@ -150,8 +188,14 @@ impl Value {
             (Integer(lhs), Float(rhs)) => Float(*lhs as f64 * rhs),
             (Float(lhs), Integer(rhs)) => Float(lhs * *rhs as f64),
             (Float(lhs), Float(rhs)) => Float(lhs * rhs),
-            (Null, Integer(_) | Float(_) | Null) => Null,
-            (Integer(_) | Float(_), Null) => Null,
+            (Interval(lhs), Integer(rhs)) | (Integer(rhs), Interval(lhs)) => {
+                Interval(lhs.checked_mul(*rhs)?)
+            }
+            (Interval(lhs), Float(rhs)) | (Float(rhs), Interval(lhs)) => {
+                Interval(lhs.checked_mul_float(*rhs)?)
+            }
+            (Null, Integer(_) | Float(_) | Interval(_) | Null) => Null,
+            (Integer(_) | Float(_) | Interval(_), Null) => Null,
             (lhs, rhs) => return errinput!("can't multiply {lhs} and {rhs}"),
         })
     }
@@ -207,13 +251,18 @@ impl Value {
             (Integer(lhs), Float(rhs)) => Float(*lhs as f64 - rhs),
             (Float(lhs), Integer(rhs)) => Float(lhs - *rhs as f64),
             (Float(lhs), Float(rhs)) => Float(lhs - rhs),
-            (Null, Integer(_) | Float(_) | Null) => Null,
-            (Integer(_) | Float(_), Null) => Null,
+            (Interval(lhs), Interval(rhs)) => Interval(lhs.checked_sub(rhs)?),
+            (Timestamp(lhs), Interval(rhs)) => Timestamp(lhs.checked_sub_interval(rhs)?),
+            (Timestamp(lhs), Timestamp(rhs)) => Interval(lhs.checked_sub(rhs)?),
+            (Null, Integer(_) | Float(_) | Interval(_) | Timestamp(_) | Null) => Null,
+            (Integer(_) | Float(_) | Interval(_) | Timestamp(_), Null) => Null,
             (lhs, rhs) => return errinput!("can't subtract {lhs} and {rhs}"),
         })
     }
 
-    /// Returns the value's datatype, or None for null values.
+    /// Returns the value's datatype, or None for null values. For arrays, the
+    /// element type is given by the first non-NULL element, or None if there
+    /// isn't one (the array type is then unknown, as for NULL).
     pub fn datatype(&self) -> Option<DataType> {
         match self {
             Self::Null => None,
@@ -221,9 +270,33 @@ impl Value {
             Self::Integer(_) => Some(DataType::Integer),
             Self::Float(_) => Some(DataType::Float),
             Self::String(_) => Some(DataType::String),
+            Self::Json(_) => Some(DataType::Json),
+            Self::Interval(_) => Some(DataType::Interval),
+            Self::Timestamp(_) => Some(DataType::Timestamp),
+            Self::Array(values) => values
+                .iter()
+                .find_map(|v| v.datatype())
+                .map(|datatype| DataType::Array(Box::new(datatype))),
         }
     }
 
+    /// Returns the approximate memory size of the value in bytes, including
+    /// heap allocations. Used for memory accounting during execution.
+    pub fn size(&self) -> usize {
+        size_of::<Self>()
+            + match self {
+                Self::Null
+                | Self::Boolean(_)
+                | Self::Integer(_)
+                | Self::Float(_)
+                | Self::Interval(_)
+                | Self::Timestamp(_) => 0,
+                Self::String(s) => s.len(),
+                Self::Json(json) => json.size(),
+                Self::Array(values) => values.iter().map(|v| v.size()).sum(),
+            }
+    }
+
     /// Returns true if the value is undefined (NULL or NaN).
     pub fn is_undefined(&self) -> bool {
         *self == Self::Null || matches!(self, Self::Float(f) if f.is_nan())
@@ -243,18 +316,96 @@ impl Value {
     /// key and index lookups. Returns a Cow::Owned when changed, to avoid
     /// allocating in the common case where the value doesn't change.
     pub fn normalize_ref(&self) -> Cow<'_, Self> {
-        if let Self::Float(f) = self {
-            if (f.is_nan() || *f == -0.0) && f.is_sign_negative() {
-                return Cow::Owned(Self::Float(-f));
+        match self {
+            Self::Float(f) if (f.is_nan() || *f == -0.0) && f.is_sign_negative() => {
+                Cow::Owned(Self::Float(-f))
             }
+            Self::Array(values) if !values.iter().all(|v| v.is_normalized()) => Cow::Owned(
+                Self::Array(values.iter().map(|v| v.normalize_ref().into_owned()).collect()),
+            ),
+            _ => Cow::Borrowed(self),
         }
-        Cow::Borrowed(self)
     }
 
     // Returns true if the value is already normalized.
     pub fn is_normalized(&self) -> bool {
         matches!(self.normalize_ref(), Cow::Borrowed(_))
     }
+
+    /// Parses a value of the given datatype from its text representation, e.g.
+    /// a CSV field. Booleans can be given as TRUE/FALSE, T/F, or 1/0 (in any
+    /// case), floats as NaN or inf, and arrays as JSON arrays.
+    pub fn from_text(text: &str, datatype: &DataType) -> Result<Self> {
+        let invalid =
+            || -> Error { errinput!("invalid {datatype} value '{}'", text.escape_debug()) };
+        Ok(match datatype {
+            DataType::Boolean => match text.to_lowercase().as_str() {
+                "true" | "t" | "1" => Self::Boolean(true),
+                "false" | "f" | "0" => Self::Boolean(false),
+                _ => return Err(invalid()),
+            },
+            DataType::Integer => Self::Integer(text.trim().parse().map_err(|_| invalid())?),
+            DataType::Float => Self::Float(text.trim().parse().map_err(|_| invalid())?),
+            DataType::String => Self::String(text.to_string()),
+            DataType::Json => Self::Json(Json::parse(text)?),
+            DataType::Interval => Self::Interval(Interval::parse(text)?),
+            DataType::Timestamp => Self::Timestamp(Timestamp::parse(text)?),
+            DataType::Array(_) => match serde_json::from_str(text) {
+                Ok(json) => Self::from_json(json, datatype)?,
+                Err(_) => return Err(invalid()),
+            },
+        })
+    }
+
+    /// Converts a JSON value to a value of the given datatype, the inverse of
+    /// to_json(). JSON null is NULL, and floats can also be given as strings,
+    /// e.g. "NaN" or "Infinity".
+    pub fn from_json(json: serde_json::Value, datatype: &DataType) -> Result<Self> {
+        use serde_json::Value as JSON;
+        Ok(match (datatype, json) {
+            (_, JSON::Null) => Self::Null,
+            (DataType::Boolean, JSON::Bool(boolean)) => Self::Boolean(boolean),
+            (DataType::Integer, JSON::Number(number)) if number.is_i64() => {
+                Self::Integer(number.as_i64().expect("not an i64"))
+            }
+            (DataType::Float, JSON::Number(number)) => {
+                Self::Float(number.as_f64().expect("not an f64"))
+            }
+            (DataType::Float, JSON::String(string)) => Self::from_text(&string, datatype)?,
+            (DataType::String, JSON::String(string)) => Self::String(string),
+            (DataType::Json, json) => Self::Json(Json::parse(&json.to_string())?),
+            (DataType::Interval, JSON::String(string)) => Self::Interval(Interval::parse(&string)?),
+            (DataType::Timestamp, JSON::String(string)) => {
+                Self::Timestamp(Timestamp::parse(&string)?)
+            }
+            (DataType::Array(element), JSON::Array(values)) => Self::Array(
+                values.into_iter().map(|v| Self::from_json(v, element)).collect::<Result<_>>()?,
+            ),
+            (datatype, json) => return errinput!("invalid {datatype} value {json}"),
+        })
+    }
+
+    /// Converts the value to JSON. JSON can't represent infinite floats and
+    /// NaN, so they're given as strings like in Postgres, as are intervals and
+    /// timestamps.
+    pub fn to_json(self) -> Result<serde_json::Value> {
+        Ok(match self {
+            Self::Null => serde_json::Value::Null,
+            Self::Boolean(boolean) => boolean.into(),
+            Self::Integer(integer) => integer.into(),
+            Self::Float(float) if float.is_nan() => "NaN".into(),
+            Self::Float(f64::INFINITY) => "Infinity".into(),
+            Self::Float(f64::NEG_INFINITY) => "-Infinity".into(),
+            Self::Float(float) => float.into(),
+            Self::String(string) => string.into(),
+            Self::Json(json) => serde_json::from_str(&json.to_string())?,
+            Self::Interval(interval) => interval.to_string().into(),
+            Self::Timestamp(timestamp) => timestamp.to_string().into(),
+            Self::Array(values) => serde_json::Value::Array(
+                values.into_iter().map(Self::to_json).collect::<Result<_>>()?,
+            ),
+        })
+    }
 }
 
 impl Display for Value {
@@ -266,6 +417,19 @@ impl Display for Value {
             Self::Integer(integer) => integer.fmt(f),
             Self::Float(float) => write!(f, "{float:?}"),
             Self::String(string) => write!(f, "'{}'", string.escape_debug()),
+            Self::Json(json) => json.fmt(f),
+            Self::Interval(interval) => write!(f, "INTERVAL '{interval}'"),
+            Self::Timestamp(timestamp) => write!(f, "TIMESTAMP '{timestamp}'"),
+            Self::Array(values) => {
+                f.write_str("ARRAY[")?;
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(", ")?;
+                    }
+                    value.fmt(f)?;
+                }
+                f.write_str("]")
+            }
         }
     }
 }
@@ -344,14 +508,71 @@ impl TryFrom<Value> for String {
     }
 }
 
+impl From<Json> for Value {
+    fn from(v: Json) -> Self {
+        Value::Json(v)
+    }
+}
+
+impl TryFrom<Value> for Json {
+    type Error = Error;
+
+    fn try_from(value: Value) -> Result<Self> {
+        let Value::Json(j) = value else {
+            return errdata!("not JSON: {value}");
+        };
+        Ok(j)
+    }
+}
+
+impl From<Interval> for Value {
+    fn from(v: Interval) -> Self {
+        Value::Interval(v)
+    }
+}
+
+impl TryFrom<Value> for Interval {
+    type Error = Error;
+
+    fn try_from(value: Value) -> Result<Self> {
+        let Value::Interval(i) = value else {
+            return errdata!("not an interval: {value}");
+        };
+        Ok(i)
+    }
+}
+
+impl From<Timestamp> for Value {
+    fn from(v: Timestamp) -> Self {
+        Value::Timestamp(v)
+    }
+}
+
+impl TryFrom<Value> for Timestamp {
+    type Error = Error;
+
+    fn try_from(value: Value) -> Result<Self> {
+        let Value::Timestamp(t) = value else {
+            return errdata!("not a timestamp: {value}");
+        };
+        Ok(t)
+    }
+}
+
+impl From<Value> for Cow<'_, Value> {
+    fn from(v: Value) -> Self {
+        Cow::Owned(v)
+    }
+}
+
 impl<'a> From<&'a Value> for Cow<'a, Value> {
     fn from(v: &'a Value) -> Self {
         Cow::Borrowed(v)
     }
 }
 
-/// A primitive data type.
-#[derive(Clone, Copy, Debug, Hash, PartialEq, Serialize, Deserialize)]
+/// A data type.
+#[derive(Clone, Debug, Hash, PartialEq, Serialize, Deserialize)]
 pub enum DataType {
     /// A boolean: true or false.
     Boolean,
@@ -361,6 +582,29 @@ pub enum DataType {
     Float,
     /// A UTF-8 encoded string.
     String,
+    /// A JSON document.
+    Json,
+    /// A time interval.
+    Interval,
+    /// An array of the given element type, which can't be an array.
+    Array(Box<DataType>),
+    /// A date and time of day.
+    Timestamp,
+}
+
+impl DataType {
+    /// Returns true if the value can be stored as this datatype. NULL is
+    /// accepted by all datatypes (nullability is checked separately), and
+    /// arrays accept NULL elements.
+    pub fn accepts(&self, value: &Value) -> bool {
+        match (self, value) {
+            (_, Value::Null) => true,
+            (Self::Array(datatype), Value::Array(values)) => {
+                values.iter().all(|v| datatype.accepts(v))
+            }
+            (datatype, value) => value.datatype().as_ref() == Some(datatype),
+        }
+    }
 }
 
 impl Display for DataType {
@@ -370,6 +614,10 @@ impl Display for DataType {
             Self::Integer => write!(f, "INTEGER"),
             Self::Float => write!(f, "FLOAT"),
             Self::String => write!(f, "STRING"),
+            Self::Json => write!(f, "JSON"),
+            Self::Interval => write!(f, "INTERVAL"),
+            Self::Timestamp => write!(f, "TIMESTAMP"),
+            Self::Array(datatype) => write!(f, "{datatype}[]"),
         }
     }
 }
@@ -384,8 +632,8 @@ pub type Rows = Box<dyn RowIterator>;
 /// object-safe. Cloning is needed to be able to reset an iterator back to an
 /// initial state, e.g. during nested loop joins. It has a blanket
 /// implementation for all matching iterators.
-pub trait RowIterator: Iterator<Item = Result<Row>> + DynClone {}
-impl<I: Iterator<Item = Result<Row>> + DynClone> RowIterator for I {}
+pub trait RowIterator: Iterator<Item = Result<Row>> + DynClone + Send {}
+impl<I: Iterator<Item = Result<Row>> + DynClone + Send> RowIterator for I {}
 dyn_clone::clone_trait_object!(RowIterator);
 
 /// A column label, used in query results and plans.
@@ -59,13 +59,13 @@ type KeyDir = BTreeMap<Vec<u8>, ValueLocation>;
 
 /// The location of a value in the log file.
 #[derive(Clone, Copy)]
-struct ValueLocation {
-    offset: u64,
-    length: usize,
+pub(super) struct ValueLocation {
+    pub(super) offset: u64,
+    pub(super) length: usize,
 }
 
 impl ValueLocation {
-    fn end(&self) -> u64 {
+    pub(super) fn end(&self) -> u64 {
         self.offset + self.length as u64
     }
 }
@@ -237,18 +237,18 @@ impl DoubleEndedIterator for ScanIterator<'_> {
 /// 2. Value length as big-endian i32, or -1 for tombstones [4 bytes].
 /// 3. Key as raw bytes [<= 2 GB].
 /// 4. Value as raw bytes [<= 2 GB].
-struct Log {
+pub(super) struct Log {
     /// The open log file.
-    file: File,
+    pub(super) file: File,
     /// Path to the log file.
-    path: PathBuf,
+    pub(super) path: PathBuf,
 }
 
 impl Log {
     /// Opens a log file, or creates one if it does not exist. Takes out an
     /// exclusive lock on the file until it is closed, or errors if the lock is
     /// already held.
-    fn new(path: PathBuf) -> Result<Self> {
+    pub(super) fn new(path: PathBuf) -> Result<Self> {
         if let Some(dir) = path.parent() {
             std::fs::create_dir_all(dir)?
         }
@@ -264,12 +264,25 @@ impl Log {
         Ok(Self { file, path })
     }
 
-    /// Builds a keydir by scanning the log file. If an incomplete entry is
+    /// Builds a keydir by scanning the log file.
+    fn build_keydir(&mut self) -> Result<KeyDir> {
+        let mut keydir = KeyDir::new();
+        self.replay(|key, value_loc| match value_loc {
+            Some(value_loc) => keydir.insert(key, value_loc),
+            None => keydir.remove(&key),
+        })?;
+        Ok(keydir)
+    }
+
+    /// Scans the log file, calling the given closure with each entry's key and
+    /// value location, or None for tombstones. If an incomplete entry is
     /// encountered, it is assumed to be caused by an incomplete write operation
     /// and the remainder of the file is truncated.
-    fn build_keydir(&mut self) -> Result<KeyDir> {
+    pub(super) fn replay<T>(
+        &mut self,
+        mut apply: impl FnMut(Vec<u8>, Option<ValueLocation>) -> T,
+    ) -> Result<()> {
         let mut len_buf = [0u8; 4];
-        let mut keydir = KeyDir::new();
         let file_len = self.file.metadata()?.len();
         let mut r = BufReader::new(&mut self.file);
         let mut offset = r.seek(SeekFrom::Start(0))?;
@@ -313,10 +326,9 @@ impl Log {
                 Ok((key, value_loc))
             }();
 
-            // Update the keydir with the entry.
+            // Apply the entry.
             match result {
-                Ok((key, Some(value_loc))) => keydir.insert(key, value_loc),
-                Ok((key, None)) => keydir.remove(&key),
+                Ok((key, value_loc)) => apply(key, value_loc),
                 // If an incomplete entry was found at the end of the file, assume an
                 // incomplete write and truncate the file.
                 Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => {
@@ -328,11 +340,11 @@ impl Log {
             };
         }
 
-        Ok(keydir)
+        Ok(())
     }
 
     /// Reads a value from the log file at the given location.
-    fn read_value(&mut self, location: ValueLocation) -> Result<Vec<u8>> {
+    pub(super) fn read_value(&mut self, location: ValueLocation) -> Result<Vec<u8>> {
         let mut value = vec![0; location.length];
         self.file.seek(SeekFrom::Start(location.offset))?;
         self.file.read_exact(&mut value)?;
@@ -342,7 +354,11 @@ impl Log {
     /// Appends a key/value entry to the log file, using a None value for
     /// tombstones. It returns the location of the entry's value in the log, for
     /// use with the [`KeyDir`].
-    fn write_entry(&mut self, key: &[u8], value: Option<&[u8]>) -> Result<ValueLocation> {
+    pub(super) fn write_entry(
+        &mut self,
+        key: &[u8],
+        value: Option<&[u8]>,
+    ) -> Result<ValueLocation> {
         let length = 8 + key.len() + value.map_or(0, |v| v.len());
         let offset = self.file.seek(SeekFrom::End(0))?;
         let mut w = BufWriter::with_capacity(length, &mut self.file);
@@ -55,6 +55,16 @@ pub trait Engine: Send {
 
     /// Returns the engine status.
     fn status(&mut self) -> Result<Status>;
+
+    /// Removes keys before the given key, to the extent the engine can do so
+    /// cheaply by deleting whole storage segments, returning the number of
+    /// removed keys. Keys before it may be retained, so this is only useful
+    /// for reclaiming space, e.g. for a prefix of the Raft log. Engines without
+    /// segments (see [`super::Segmented`]) don't remove anything.
+    fn truncate(&mut self, key: &[u8]) -> Result<u64> {
+        _ = key;
+        Ok(0)
+    }
 }
 
 /// A scan iterator over key/value pairs, returned by [`Engine::scan()`].
@@ -296,6 +306,10 @@ pub mod test {
         fn status(&mut self) -> Result<Status> {
             self.inner.status()
         }
+
+        fn truncate(&mut self, key: &[u8]) -> Result<u64> {
+            self.inner.truncate(key)
+        }
     }
 
     /// An engine that wraps two others and mirrors operations across them,
@@ -0,0 +1,166 @@
+use std::ops::{Bound, RangeBounds};
+
+use super::{Engine, Status};
+use crate::error::{Error, Result};
+use crate::fault::FaultInjector;
+
+/// Wraps a storage engine and injects faults into it via a FaultInjector, for
+/// chaos testing: flushes (i.e. fsyncs) can fail with an IO error, and read
+/// values can be corrupted by flipping the bits of their last byte. Writes
+/// are passed through, so corrupt reads don't corrupt the stored data.
+pub struct Faulty<E: Engine> {
+    /// The wrapped engine.
+    inner: E,
+    /// The injected faults.
+    faults: FaultInjector,
+}
+
+impl<E: Engine> Faulty<E> {
+    /// Wraps an engine, injecting the given faults.
+    pub fn new(inner: E, faults: FaultInjector) -> Self {
+        Self { inner, faults }
+    }
+
+    /// Corrupts a read value, if the injected faults say so.
+    fn maybe_corrupt(faults: &FaultInjector, mut value: Vec<u8>) -> Vec<u8> {
+        if faults.corrupt_read() {
+            match value.last_mut() {
+                Some(last) => *last = !*last,
+                None => value.push(0xff),
+            }
+        }
+        value
+    }
+}
+
+impl<E: Engine> Engine for Faulty<E> {
+    type ScanIterator<'a>
+        = ScanIterator<'a, E>
+    where
+        E: 'a;
+
+    fn delete(&mut self, key: &[u8]) -> Result<()> {
+        self.inner.delete(key)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        if self.faults.fail_flush() {
+            return Err(Error::IO("injected flush failure".to_string()));
+        }
+        self.inner.flush()
+    }
+
+    fn get(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        let value = self.inner.get(key)?;
+        Ok(value.map(|value| Self::maybe_corrupt(&self.faults, value)))
+    }
+
+    fn scan(&mut self, range: impl RangeBounds<Vec<u8>>) -> Self::ScanIterator<'_> {
+        ScanIterator { inner: self.inner.scan(range), faults: &self.faults }
+    }
+
+    fn scan_dyn(
+        &mut self,
+        range: (Bound<Vec<u8>>, Bound<Vec<u8>>),
+    ) -> Box<dyn super::ScanIterator + '_> {
+        Box::new(self.scan(range))
+    }
+
+    fn set(&mut self, key: &[u8], value: Vec<u8>) -> Result<()> {
+        self.inner.set(key, value)
+    }
+
+    fn status(&mut self) -> Result<Status> {
+        self.inner.status()
+    }
+
+    fn truncate(&mut self, key: &[u8]) -> Result<u64> {
+        self.inner.truncate(key)
+    }
+}
+
+pub struct ScanIterator<'a, E: Engine + 'a> {
+    inner: E::ScanIterator<'a>,
+    faults: &'a FaultInjector,
+}
+
+impl<E: Engine> ScanIterator<'_, E> {
+    fn corrupt(&self, item: Result<(Vec<u8>, Vec<u8>)>) -> Result<(Vec<u8>, Vec<u8>)> {
+        let (key, value) = item?;
+        Ok((key, Faulty::<E>::maybe_corrupt(self.faults, value)))
+    }
+}
+
+impl<E: Engine> Iterator for ScanIterator<'_, E> {
+    type Item = Result<(Vec<u8>, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|item| self.corrupt(item))
+    }
+}
+
+impl<E: Engine> DoubleEndedIterator for ScanIterator<'_, E> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.inner.next_back().map(|item| self.corrupt(item))
+    }
+}
+
+/// Most storage tests are Goldenscripts under src/storage/testscripts.
+#[cfg(test)]
+mod tests {
+    use std::error::Error as StdError;
+    use std::path::Path;
+    use std::result::Result as StdResult;
+
+    use test_each_file::test_each_path;
+
+    use super::super::Memory;
+    use super::super::engine::test::Runner;
+    use super::*;
+    use crate::fault::Faults;
+
+    // Run common goldenscript tests in src/storage/testscripts/engine, without
+    // any injected faults.
+    test_each_path! { in "src/storage/testscripts/engine" as engine => test_goldenscript }
+
+    // Also run fault injection tests in src/storage/testscripts/faulty.
+    test_each_path! { in "src/storage/testscripts/faulty" as scripts => test_goldenscript }
+
+    fn test_goldenscript(path: &Path) {
+        let faults = FaultInjector::new();
+        let engine = Faulty::new(Memory::new(), faults.clone());
+        goldenscript::run(&mut FaultyRunner { runner: Runner::new(engine), faults }, path)
+            .expect("goldenscript failed")
+    }
+
+    /// Extends the common engine runner with fault injection commands.
+    struct FaultyRunner {
+        runner: Runner<Faulty<Memory>>,
+        faults: FaultInjector,
+    }
+
+    impl goldenscript::Runner for FaultyRunner {
+        fn run(&mut self, command: &goldenscript::Command) -> StdResult<String, Box<dyn StdError>> {
+            match command.name.as_str() {
+                // faults [fail_flush=BOOL] [corrupt_read=FLOAT]
+                "faults" => {
+                    let mut args = command.consume_args();
+                    let fail_flush = args.lookup_parse("fail_flush")?.unwrap_or(false);
+                    let corrupt_read = args.lookup_parse("corrupt_read")?.unwrap_or(0.0);
+                    args.reject_rest()?;
+                    self.faults.set(Faults { fail_flush, corrupt_read, ..Faults::default() })?;
+                    Ok(String::new())
+                }
+
+                // flush
+                "flush" => {
+                    command.consume_args().reject_rest()?;
+                    self.runner.engine.flush()?;
+                    Ok(String::new())
+                }
+
+                _ => self.runner.run(command),
+            }
+        }
+    }
+}
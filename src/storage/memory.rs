@@ -1,27 +1,82 @@
-use std::collections::BTreeMap;
-use std::collections::btree_map::Range;
+use std::collections::{BTreeMap, VecDeque};
 use std::ops::{Bound, RangeBounds};
+use std::sync::{Arc, RwLock};
 
 use super::{Engine, Status};
 use crate::error::Result;
 
 /// An in-memory key-value storage engine using the Rust standard library's
 /// B-tree implementation. Data is not persisted. Primarily for testing.
-#[derive(Default)]
-pub struct Memory(BTreeMap<Vec<u8>, Vec<u8>>);
+///
+/// Clones share the same data, and can be used concurrently from different
+/// threads. The key space is range-partitioned into shards, each a B-tree
+/// behind its own read/write lock, so a writer only blocks readers and writers
+/// of the same shard. A shard is split in half once it reaches SHARD_SIZE
+/// keys, which briefly locks the shard list exclusively. Range sharding (as
+/// opposed to hash sharding) keeps scans cheap, since they only visit the
+/// shards overlapping the range, in order, without merging them.
+///
+/// Scans read batches of key/value pairs, only holding locks while reading a
+/// batch, so long scans don't block writers. A scan isn't isolated from
+/// concurrent writes via other clones, but callers that need this (e.g. MVCC)
+/// serialize access anyway.
+#[derive(Clone)]
+pub struct Memory(Arc<RwLock<Vec<Shard>>>);
+
+/// A shard of a Memory engine. It contains the keys from its start key up to
+/// the next shard's start key. The first shard starts at the empty key.
+struct Shard {
+    start: Vec<u8>,
+    data: RwLock<BTreeMap<Vec<u8>, Vec<u8>>>,
+}
 
 impl Memory {
+    /// The maximum number of key/value pairs scans read at a time. Scans start
+    /// with a batch size of 1 and double it on each read, since they often
+    /// only read a few keys.
+    const SCAN_BATCH: usize = 128;
+
+    /// The number of keys at which a shard is split in half.
+    const SHARD_SIZE: usize = 4096;
+
     /// Creates a new Memory key-value storage engine.
     pub fn new() -> Self {
         Self::default()
     }
+
+    /// Returns the index of the shard containing the given key.
+    fn shard_index(shards: &[Shard], key: &[u8]) -> usize {
+        shards.partition_point(|shard| shard.start.as_slice() <= key) - 1
+    }
+
+    /// Splits the shard containing the given key in half, if it's still full.
+    fn split(&self, key: &[u8]) -> Result<()> {
+        let mut shards = self.0.write()?;
+        let index = Self::shard_index(&shards, key);
+        let data = shards[index].data.get_mut()?;
+        if data.len() < Self::SHARD_SIZE {
+            return Ok(()); // split by a different writer
+        }
+        let start = data.keys().nth(data.len() / 2).expect("no median key").clone();
+        let upper = data.split_off(&start);
+        shards.insert(index + 1, Shard { start, data: RwLock::new(upper) });
+        Ok(())
+    }
+}
+
+impl Default for Memory {
+    fn default() -> Self {
+        let shard = Shard { start: Vec::new(), data: RwLock::default() };
+        Self(Arc::new(RwLock::new(vec![shard])))
+    }
 }
 
 impl Engine for Memory {
     type ScanIterator<'a> = ScanIterator<'a>;
 
     fn delete(&mut self, key: &[u8]) -> Result<()> {
-        self.0.remove(key);
+        let shards = self.0.read()?;
+        shards[Self::shard_index(&shards, key)].data.write()?.remove(key);
         Ok(())
     }
 
@@ -30,11 +85,12 @@ impl Engine for Memory {
     }
 
     fn get(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
-        Ok(self.0.get(key).cloned())
+        let shards = self.0.read()?;
+        Ok(shards[Self::shard_index(&shards, key)].data.read()?.get(key).cloned())
     }
 
     fn scan(&mut self, range: impl RangeBounds<Vec<u8>>) -> Self::ScanIterator<'_> {
-        ScanIterator(self.0.range(range))
+        ScanIterator::new(self, (range.start_bound().cloned(), range.end_bound().cloned()))
     }
 
     fn scan_dyn(
@@ -45,34 +101,157 @@ impl Engine for Memory {
     }
 
     fn set(&mut self, key: &[u8], value: Vec<u8>) -> Result<()> {
-        self.0.insert(key.to_vec(), value);
+        let full = {
+            let shards = self.0.read()?;
+            let mut data = shards[Self::shard_index(&shards, key)].data.write()?;
+            data.insert(key.to_vec(), value);
+            data.len() >= Self::SHARD_SIZE
+        };
+        if full {
+            self.split(key)?;
+        }
         Ok(())
     }
 
     fn status(&mut self) -> Result<Status> {
-        Ok(Status {
-            name: "memory".to_string(),
-            keys: self.0.len() as u64,
-            size: self.0.iter().map(|(k, v)| (k.len() + v.len()) as u64).sum(),
-            disk_size: 0,
-            live_disk_size: 0,
-        })
+        let (mut keys, mut size) = (0, 0);
+        for shard in self.0.read()?.iter() {
+            let data = shard.data.read()?;
+            keys += data.len() as u64;
+            size += data.iter().map(|(k, v)| (k.len() + v.len()) as u64).sum::<u64>();
+        }
+        Ok(Status { name: "memory".to_string(), keys, size, disk_size: 0, live_disk_size: 0 })
     }
 }
 
-pub struct ScanIterator<'a>(Range<'a, Vec<u8>, Vec<u8>>);
+/// A key range, as start and end bounds.
+type Range = (Bound<Vec<u8>>, Bound<Vec<u8>>);
+
+/// A key/value pair.
+type KeyValue = (Vec<u8>, Vec<u8>);
+
+/// A scan over a key range, reading it in batches. Each key/value pair in the
+/// range is either buffered at the front, still unread in the range, or
+/// buffered at the back.
+pub struct ScanIterator<'a> {
+    memory: &'a Memory,
+    /// Key/value pairs read from the start of the range, in key order.
+    front: VecDeque<KeyValue>,
+    /// The unread range, or None if it's exhausted.
+    range: Option<Range>,
+    /// Key/value pairs read from the end of the range, in key order.
+    back: VecDeque<KeyValue>,
+    /// The number of key/value pairs to read in the next batch.
+    batch: usize,
+}
+
+impl<'a> ScanIterator<'a> {
+    /// Creates a new scan over the given range.
+    fn new(memory: &'a Memory, range: Range) -> Self {
+        let range = Self::is_valid(&range).then_some(range);
+        Self { memory, front: VecDeque::new(), range, back: VecDeque::new(), batch: 1 }
+    }
+
+    /// Returns true if the range is valid, i.e. BTreeMap::range won't panic.
+    fn is_valid(range: &Range) -> bool {
+        match range {
+            (Bound::Excluded(start), Bound::Excluded(end)) => start < end,
+            (Bound::Included(start) | Bound::Excluded(start), Bound::Included(end))
+            | (Bound::Included(start), Bound::Excluded(end)) => start <= end,
+            _ => true,
+        }
+    }
+
+    /// Reads the next batch from the start of the range into the front buffer,
+    /// visiting shards from the range start until the batch is full.
+    fn fill_front(&mut self) -> Result<()> {
+        let Some(range) = self.range.take() else { return Ok(()) };
+        let shards = self.memory.0.read()?;
+        let mut index = match &range.0 {
+            Bound::Included(key) | Bound::Excluded(key) => Memory::shard_index(&shards, key),
+            Bound::Unbounded => 0,
+        };
+        while let Some(shard) = shards.get(index) {
+            let past_end = match &range.1 {
+                Bound::Included(end) => shard.start > *end,
+                Bound::Excluded(end) => shard.start >= *end,
+                Bound::Unbounded => false,
+            };
+            if past_end {
+                break;
+            }
+            let data = shard.data.read()?;
+            let batch = data.range(range.clone()).take(self.batch - self.front.len());
+            self.front.extend(batch.map(|(k, v)| (k.clone(), v.clone())));
+            if self.front.len() == self.batch {
+                break;
+            }
+            index += 1;
+        }
+        if self.front.len() == self.batch {
+            let (key, _) = self.front.back().expect("empty batch");
+            let range = (Bound::Excluded(key.clone()), range.1);
+            self.range = Self::is_valid(&range).then_some(range);
+            self.batch = (self.batch * 2).min(Memory::SCAN_BATCH);
+        }
+        Ok(())
+    }
+
+    /// Reads the next batch from the end of the range into the back buffer,
+    /// visiting shards from the range end until the batch is full.
+    fn fill_back(&mut self) -> Result<()> {
+        let Some(range) = self.range.take() else { return Ok(()) };
+        let shards = self.memory.0.read()?;
+        let mut index = match &range.1 {
+            Bound::Included(key) | Bound::Excluded(key) => Memory::shard_index(&shards, key),
+            Bound::Unbounded => shards.len() - 1,
+        };
+        loop {
+            let shard = &shards[index];
+            let data = shard.data.read()?;
+            for (key, value) in data.range(range.clone()).rev().take(self.batch - self.back.len()) {
+                self.back.push_front((key.clone(), value.clone()));
+            }
+            let before_start = match &range.0 {
+                Bound::Included(start) | Bound::Excluded(start) => shard.start <= *start,
+                Bound::Unbounded => false,
+            };
+            if self.back.len() == self.batch || before_start || index == 0 {
+                break;
+            }
+            index -= 1;
+        }
+        if self.back.len() == self.batch {
+            let (key, _) = self.back.front().expect("empty batch");
+            let range = (range.0, Bound::Excluded(key.clone()));
+            self.range = Self::is_valid(&range).then_some(range);
+            self.batch = (self.batch * 2).min(Memory::SCAN_BATCH);
+        }
+        Ok(())
+    }
+}
 
 impl Iterator for ScanIterator<'_> {
-    type Item = Result<(Vec<u8>, Vec<u8>)>;
+    type Item = Result<KeyValue>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.0.next().map(|(k, v)| Ok((k.clone(), v.clone())))
+        if self.front.is_empty() {
+            if let Err(error) = self.fill_front() {
+                return Some(Err(error));
+            }
+        }
+        self.front.pop_front().or_else(|| self.back.pop_front()).map(Ok)
     }
 }
 
 impl DoubleEndedIterator for ScanIterator<'_> {
     fn next_back(&mut self) -> Option<Self::Item> {
-        self.0.next_back().map(|(k, v)| Ok((k.clone(), v.clone())))
+        if self.back.is_empty() {
+            if let Err(error) = self.fill_back() {
+                return Some(Err(error));
+            }
+        }
+        self.back.pop_back().or_else(|| self.front.pop_back()).map(Ok)
     }
 }
 
@@ -81,6 +260,8 @@ impl DoubleEndedIterator for ScanIterator<'_> {
 mod tests {
     use std::path::Path;
 
+    use rand::rngs::StdRng;
+    use rand::{Rng as _, SeedableRng as _};
     use test_each_file::test_each_path;
 
     use super::super::engine::test::Runner;
@@ -95,4 +276,132 @@ mod tests {
     fn test_goldenscript(path: &Path) {
         goldenscript::run(&mut Runner::new(Memory::new()), path).expect("goldenscript failed")
     }
+
+    /// Scans return keys in order across multiple batches and shards, when
+    /// iterating from both ends.
+    #[test]
+    fn scan_batches() -> Result<()> {
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut memory = Memory::new();
+        let mut expect = BTreeMap::new();
+        for _ in 0..40000 {
+            let key = rng.gen_range(0..20000u32).to_be_bytes().to_vec();
+            memory.set(&key, key.clone())?;
+            expect.insert(key.clone(), key);
+        }
+        assert!(memory.0.read()?.len() > 2, "expected multiple shards");
+
+        let ranges = [
+            (Bound::Unbounded, Bound::Unbounded),
+            (Bound::Included(100), Bound::Excluded(900)),
+            (Bound::Excluded(3000), Bound::Included(15000)),
+        ];
+        for (start, end) in ranges {
+            let range = (
+                start.map(|k: u32| k.to_be_bytes().to_vec()),
+                end.map(|k: u32| k.to_be_bytes().to_vec()),
+            );
+            let mut scan = memory.scan(range.clone());
+            let mut expect = expect.range(range);
+            loop {
+                let (next, expected) = match rng.gen_bool(0.5) {
+                    true => (scan.next().transpose()?, expect.next()),
+                    false => (scan.next_back().transpose()?, expect.next_back()),
+                };
+                assert_eq!(next.as_ref(), expected.map(|(k, v)| (k.clone(), v.clone())).as_ref());
+                if next.is_none() {
+                    break;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Clones share the same data, and can be used concurrently.
+    #[test]
+    fn concurrent_clones() -> Result<()> {
+        let memory = Memory::new();
+        std::thread::scope(|s| {
+            for thread in 0..4u8 {
+                let mut memory = memory.clone();
+                s.spawn(move || {
+                    for i in 0..100u8 {
+                        memory.set(&[thread, i], vec![i]).unwrap();
+                    }
+                });
+            }
+        });
+        let mut memory = memory;
+        assert_eq!(memory.status()?.keys, 400);
+        assert_eq!(memory.get(&[3, 99])?, Some(vec![99]));
+        assert_eq!(memory.scan_prefix(&[2]).count(), 100);
+        Ok(())
+    }
+
+    /// Readers see consistent data while writers concurrently insert, update,
+    /// and delete keys, and split shards.
+    #[test]
+    fn concurrent_readers_writers() -> Result<()> {
+        const KEYS: u32 = 20000;
+        let memory = Memory::new();
+        let done = std::sync::atomic::AtomicBool::new(false);
+        std::thread::scope(|s| {
+            // Each writer owns the keys with key % 4 == thread. It inserts them
+            // with their key as value, and deletes every third one.
+            let writers: Vec<_> = (0..4u32)
+                .map(|thread| {
+                    let mut memory = memory.clone();
+                    s.spawn(move || {
+                        for key in (thread..KEYS).step_by(4) {
+                            let key = key.to_be_bytes();
+                            memory.set(&key, key.to_vec()).unwrap();
+                        }
+                        for key in (thread..KEYS).step_by(12) {
+                            memory.delete(&key.to_be_bytes()).unwrap();
+                        }
+                    })
+                })
+                .collect();
+
+            // Readers scan in both directions and read single keys, checking
+            // that keys are ordered and values match their keys.
+            for reverse in [false, true] {
+                let mut memory = memory.clone();
+                let done = &done;
+                s.spawn(move || {
+                    while !done.load(std::sync::atomic::Ordering::Relaxed) {
+                        let scan = memory.scan(..);
+                        let kvs: Vec<_> = match reverse {
+                            false => scan.collect::<Result<_>>().unwrap(),
+                            true => scan.rev().collect::<Result<_>>().unwrap(),
+                        };
+                        for (key, value) in &kvs {
+                            assert_eq!(key, value);
+                        }
+                        assert!(kvs.is_sorted_by(|a, b| (a.0 < b.0) != reverse));
+                        let key = 7u32.to_be_bytes();
+                        if let Some(value) = memory.get(&key).unwrap() {
+                            assert_eq!(value, key);
+                        }
+                    }
+                });
+            }
+
+            for writer in writers {
+                writer.join().unwrap();
+            }
+            done.store(true, std::sync::atomic::Ordering::Relaxed);
+        });
+
+        let mut memory = memory;
+        let expect: Vec<_> = (0..KEYS).filter(|key| key % 12 >= 4).collect();
+        let keys: Vec<_> = memory
+            .scan(..)
+            .map(|r| r.map(|(key, _)| u32::from_be_bytes(key.try_into().unwrap())))
+            .collect::<Result<_>>()?;
+        assert_eq!(keys, expect);
+        assert_eq!(memory.status()?.keys, expect.len() as u64);
+        assert!(memory.0.read()?.len() > 2, "expected multiple shards");
+        Ok(())
+    }
 }
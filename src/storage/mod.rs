@@ -1,11 +1,16 @@
 //! Key/value storage engines, including an MVCC transaction layer. For details,
-//! see the [`engine`], [`bitcask`], and [`mvcc`] module documentation.
+//! see the [`engine`], [`bitcask`], [`segmented`], and [`mvcc`] module
+//! documentation.
 
 pub mod bitcask;
 pub mod engine;
+pub mod faulty;
 pub mod memory;
 pub mod mvcc;
+pub mod segmented;
 
 pub use bitcask::BitCask;
 pub use engine::{Engine, ScanIterator, Status};
+pub use faulty::Faulty;
 pub use memory::Memory;
+pub use segmented::Segmented;
@@ -88,6 +88,79 @@
 //! not see any of t2's writes, because it's still in its local snapshot of the
 //! active set at the time it began.
 //!
+//! Read-write transactions can instead use the weaker read committed isolation
+//! level, where each statement takes a fresh snapshot via
+//! Transaction::refresh_snapshot(). The snapshot records the next version at
+//! the time it's taken along with the current active set, and sees all
+//! versions below it that aren't in the active set. The transaction's own
+//! version doesn't change, so it still conflicts with writes by transactions
+//! that began after it, even if they've committed and are visible, since its
+//! writes must be the latest version of the key.
+//!
+//! Snapshot isolation allows write skew: two concurrent transactions can each
+//! read a key that the other writes, and both commit, which isn't equivalent
+//! to any serial order. Serializable isolation prevents this by validating
+//! the transaction's reads before commit via Transaction::check_read(). If
+//! any key read by the transaction has a version written by a concurrent
+//! transaction, there's a read-write antidependency from the reader to the
+//! writer, and the reader must retry. This is a conservative form of
+//! serializable snapshot isolation (SSI): rather than tracking dependency
+//! chains and only aborting on "dangerous structures" of two consecutive
+//! antidependencies, any antidependency that's observable at commit aborts.
+//! Every remaining dependency then points from a transaction that validated
+//! earlier to one that validated later, so the dependency graph can't have
+//! cycles. The read set itself is tracked by the caller (i.e. the SQL
+//! session), and only serializable transactions are validated.
+//!
+//! SAVEPOINTS
+//! ==========
+//!
+//! A read-write transaction can create named savepoints, and later roll back
+//! to a savepoint to undo the writes made since, without abandoning the whole
+//! transaction. Since a transaction only has a single version per key at its
+//! own timestamp, later writes replace earlier ones, so the previous value must
+//! be retained. Savepoints are stored as Key::TxnSavepoint(version, seq), and
+//! the first write to a key after the latest savepoint records the key's
+//! previous value in the transaction (if any) as
+//! Key::TxnSavepointWrite(version, seq, key). Rolling back to a savepoint
+//! restores these values (or removes the version if there was none) for it
+//! and all later savepoints. Releasing a savepoint keeps its writes, merging
+//! its records into the preceding savepoint.
+//!
+//! LOCKS
+//! =====
+//!
+//! Snapshot isolation reports write conflicts at write time, which makes
+//! read-modify-write patterns fail under contention. Instead, a transaction
+//! can acquire an exclusive lock on a key (e.g. for SELECT FOR UPDATE), stored
+//! as Key::Lock(key) with the holder's version. Other transactions can't lock
+//! or write the key until the holder completes. A transaction that can't
+//! acquire a lock is queued as Key::LockWaiter(key, seq), and retries when
+//! the blocking transaction completes, which wakes it up via LockWaits.
+//! Waiters acquire the lock in arrival order. Locked keys are read and written
+//! at their latest version, such that a lock holder sees (and can overwrite)
+//! versions committed after its snapshot was taken. Key::TxnLock keeps track
+//! of a transaction's locks and waits, which are removed when it commits or
+//! rolls back.
+//!
+//! A waiter records the transaction it's waiting for as Key::TxnWaitsFor,
+//! forming a waits-for graph. If a transaction would wait for a transaction
+//! that's (transitively) waiting for it, this would deadlock, so it errors
+//! with Error::Deadlock instead, and should be rolled back.
+//!
+//! PREFIX DELETES
+//! ==============
+//!
+//! Deleting all keys under a prefix, e.g. to truncate a table, would take a
+//! tombstone per key. Instead, Transaction::delete_prefix() writes a single
+//! Key::PrefixTombstone(version, prefix), which deletes all versions of keys
+//! under the prefix below its version. It's visible like any other version,
+//! so older snapshots still see the keys, and it counts as a version of each
+//! key for write conflicts. The transaction's own writes under the prefix are
+//! at its version, so they're deleted with regular tombstones, and its later
+//! writes remain visible. GC removes prefix tombstones below the horizon along
+//! with the versions they delete.
+//!
 //! READ-ONLY AND TIME TRAVEL QUERIES
 //! =================================
 //!
@@ -133,22 +206,104 @@
 //! GARBAGE COLLECTION
 //! ==================
 //!
-//! Normally, old versions would be garbage collected regularly, when they are
-//! no longer needed by active transactions or time-travel queries. However,
-//! toyDB does not implement garbage collection, instead keeping all history
-//! forever, both out of laziness and also because it allows unlimited time
-//! travel queries (it's a feature, not a bug!).
+//! Old versions are kept until they're garbage collected via MVCC::gc(), which
+//! removes versions that are no longer visible to any transaction at or above
+//! a GC horizon, stored as Key::GCHorizon. For each key, only the latest
+//! version below the horizon is visible to such transactions (if it's not a
+//! tombstone), so older versions are removed, along with the active set
+//! snapshots below the horizon.
+//!
+//! The horizon is chosen such that active read-write transactions, and
+//! time-travel queries within a retention window of recent versions, aren't
+//! affected. It's the lower of the oldest active transaction and the start of
+//! the retention window. However, transactions above the horizon may have
+//! older versions in their active set, which must remain invisible to them.
+//! The horizon is therefore lowered to the oldest version in any active set
+//! snapshot at or above it.
+//!
+//! In the following example, with the horizon at T=4, a1 is removed since a3
+//! is visible in its place, and d1 is removed along with the tombstone at d3.
+//! a3, a4, b3, and c1 are retained.
+//!
+//! Time
+//! 5
+//! 4  a4                    <- horizon
+//! 3  a3  b3      x
+//! 2
+//! 1  a1      c1  d1
+//!    a   b   c   d   Keys
+//!
+//! Read-only transactions aren't in the active set, so GC can remove versions
+//! that are visible to them, typically time-travel queries older than the
+//! retention window or long-running read-only transactions. Reads in such
+//! transactions error once the horizon passes them.
+//!
+//! COMMIT LOG
+//! ==========
+//!
+//! Versions are ordered by transaction start, not commit, so they can't be
+//! used to stream committed writes in order, e.g. for change data capture. A
+//! transaction can instead ask for its writes under a key prefix to be
+//! recorded in the commit log via Transaction::capture(), stored as
+//! Key::TxnCapture(version, prefix). When it commits, the written values are
+//! copied into a Commit record stored as Key::CommitLog(seq), where seq is a
+//! sequence number taken from Key::NextCommitSeq, and are thus independent of
+//! version garbage collection. Readers scan the log via
+//! Transaction::scan_commits(), resuming after the last sequence number
+//! they've seen. GC trims log entries from the start of the log as long as
+//! their versions are below the horizon, such that the log is retained for the
+//! same window as time-travel queries.
+//!
+//! TIMESTAMPS
+//! ==========
+//!
+//! Versions are logical, and say nothing about when a transaction ran. Callers
+//! with a hybrid logical clock (see clock::HybridClock) can instead begin and
+//! commit transactions at HLC timestamps via MVCC::begin_at() and
+//! Transaction::commit_at(), which are recorded as Key::VersionTimestamp and
+//! Key::CommitTimestamp. The latest assigned timestamp is stored as
+//! Key::Timestamp, and a given timestamp is bumped past it if necessary, such
+//! that timestamps are strictly increasing in begin/commit order even if the
+//! callers' clocks disagree. This is deterministic, so it's safe to apply via
+//! Raft.
+//!
+//! A read-only transaction can then be started as of a timestamp via
+//! MVCC::begin_as_of_timestamp(). It sees exactly the transactions that
+//! committed at or before the timestamp: its version is the first version that
+//! began after the timestamp, and its active set contains the earlier versions
+//! that hadn't committed by then. The latest timestamp is bumped to the given
+//! timestamp, such that later transactions can't commit at or before it,
+//! keeping the snapshot stable. Versions without timestamps are considered
+//! committed unless active, and GC removes the timestamps of versions that
+//! committed before the horizon began.
+//!
+//! INTENT RESOLUTION
+//! =================
+//!
+//! Uncommitted versions and locks (i.e. write intents) block conflicting
+//! transactions until their transaction commits or rolls back. If the
+//! transaction's coordinator dies, e.g. a client or server crashes, they would
+//! linger forever. To avoid this, a coordinator can periodically heartbeat a
+//! transaction via Transaction::heartbeat(), which records an expiry timestamp
+//! as Key::TxnExpires. Once the latest assigned timestamp (Key::Timestamp)
+//! passes the expiry, the transaction is considered abandoned. It's then
+//! rolled back lazily when another transaction conflicts with its writes or
+//! locks, which then proceeds as if the transaction never ran, or in the
+//! background via MVCC::resolve_expired(). Transactions that never heartbeat
+//! never expire.
 
 use std::borrow::Cow;
-use std::collections::{BTreeSet, VecDeque};
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
 use std::ops::{Bound, RangeBounds};
-use std::sync::{Arc, Mutex, MutexGuard};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
 
 use itertools::Itertools as _;
 use serde::{Deserialize, Serialize};
 
 use super::engine::{self, Engine};
-use crate::encoding::{self, Key as _, Value as _, bincode, keycode};
+use crate::clock::Timestamp;
+use crate::encoding::{self, Key as _, Value as _, bincode, format, keycode};
 use crate::error::{Error, Result};
 use crate::{errdata, errinput};
 
@@ -195,6 +350,94 @@ pub enum Key<'a> {
         #[serde(borrow)]
         Cow<'a, [u8]>,
     ),
+    /// The GC horizon. Versions below it have been garbage collected, except
+    /// the latest live version of each key.
+    GCHorizon,
+    /// A savepoint name in an active transaction, by transaction version and
+    /// savepoint sequence number (in creation order).
+    TxnSavepoint(Version, u64),
+    /// The previous value of a key written by an active transaction since a
+    /// savepoint, by transaction version and savepoint sequence number, in
+    /// case it rolls back to the savepoint. The value is the raw versioned
+    /// value the transaction wrote before the savepoint, or None if it hadn't
+    /// written the key.
+    TxnSavepointWrite(
+        Version,
+        u64,
+        #[serde(with = "serde_bytes")]
+        #[serde(borrow)]
+        Cow<'a, [u8]>,
+    ),
+    /// An exclusive lock on a key, held by the transaction with the given
+    /// version (the value).
+    Lock(
+        #[serde(with = "serde_bytes")]
+        #[serde(borrow)]
+        Cow<'a, [u8]>,
+    ),
+    /// A transaction waiting for a lock on a key, by key and queue sequence
+    /// number, with the waiter's version as the value. Waiters are queued in
+    /// arrival order.
+    LockWaiter(
+        #[serde(with = "serde_bytes")]
+        #[serde(borrow)]
+        Cow<'a, [u8]>,
+        u64,
+    ),
+    /// Keeps track of all keys locked or waited for by an active transaction,
+    /// in order to release them when it completes. The value is empty for
+    /// held locks, otherwise the LockWaiter sequence number.
+    TxnLock(
+        Version,
+        #[serde(with = "serde_bytes")]
+        #[serde(borrow)]
+        Cow<'a, [u8]>,
+    ),
+    /// The transaction that a lock waiter is waiting for (the value). These
+    /// edges form a waits-for graph, used to detect deadlocks.
+    TxnWaitsFor(Version),
+    /// Keeps track of key ranges bulk written by an active transaction (see
+    /// Transaction::set_bulk), by version and start key, in case it needs to
+    /// roll back. The value is the inclusive end key.
+    TxnBulkWrite(
+        Version,
+        #[serde(with = "serde_bytes")]
+        #[serde(borrow)]
+        Cow<'a, [u8]>,
+    ),
+    /// A key prefix whose writes an active transaction records in the commit
+    /// log when it commits (see Transaction::capture), by version and prefix.
+    TxnCapture(
+        Version,
+        #[serde(with = "serde_bytes")]
+        #[serde(borrow)]
+        Cow<'a, [u8]>,
+    ),
+    /// The next available commit log sequence number.
+    NextCommitSeq,
+    /// The commit log, by commit sequence number, in commit order. The value
+    /// is a Commit.
+    CommitLog(u64),
+    /// The latest assigned HLC timestamp (see MVCC::begin_at).
+    Timestamp,
+    /// The HLC timestamp a version began at, if any.
+    VersionTimestamp(Version),
+    /// The HLC timestamp a version committed at, if any.
+    CommitTimestamp(Version),
+    /// The HLC timestamp at which an active transaction expires, unless its
+    /// coordinator heartbeats it again (see Transaction::heartbeat). This is
+    /// the transaction's status record, used to resolve abandoned intents.
+    TxnExpires(Version),
+    /// A tombstone for all keys under a prefix (see Transaction::delete_prefix),
+    /// by version and prefix. It deletes all versions of the keys below its
+    /// version. The value is the transaction's latest savepoint sequence number
+    /// when it was written, if any, in case it rolls back to the savepoint.
+    PrefixTombstone(
+        Version,
+        #[serde(with = "serde_bytes")]
+        #[serde(borrow)]
+        Cow<'a, [u8]>,
+    ),
 }
 
 impl<'a> encoding::Key<'a> for Key<'a> {}
@@ -213,6 +456,26 @@ enum KeyPrefix<'a> {
         Cow<'a, [u8]>,
     ),
     Unversioned,
+    GCHorizon,
+    TxnSavepoint(Version),
+    TxnSavepointWrite(Version, u64),
+    Lock,
+    LockWaiter(
+        #[serde(with = "serde_bytes")]
+        #[serde(borrow)]
+        Cow<'a, [u8]>,
+    ),
+    TxnLock(Version),
+    TxnWaitsFor,
+    TxnBulkWrite(Version),
+    TxnCapture(Version),
+    NextCommitSeq,
+    CommitLog,
+    Timestamp,
+    VersionTimestamp,
+    CommitTimestamp,
+    TxnExpires,
+    PrefixTombstone,
 }
 
 impl<'a> encoding::Key<'a> for KeyPrefix<'a> {}
@@ -228,32 +491,54 @@ impl<'a> encoding::Key<'a> for KeyPrefix<'a> {}
 /// serialize them anyway.
 pub struct MVCC<E: Engine> {
     pub engine: Arc<Mutex<E>>,
+    /// Wakes up lock waiters when transactions complete.
+    lock_waits: Arc<LockWaits>,
 }
 
 impl<E: Engine> MVCC<E> {
     /// Creates a new MVCC engine with the given storage engine.
     pub fn new(engine: E) -> Self {
-        Self { engine: Arc::new(Mutex::new(engine)) }
+        Self { engine: Arc::new(Mutex::new(engine)), lock_waits: Arc::new(LockWaits::new()) }
+    }
+
+    /// Returns the lock waiter wakeups, which can be used to wait for a lock
+    /// blocker to complete (see Transaction::lock).
+    pub fn lock_waits(&self) -> Arc<LockWaits> {
+        self.lock_waits.clone()
     }
 
     /// Begins a new read-write transaction.
     pub fn begin(&self) -> Result<Transaction<E>> {
-        Transaction::begin(self.engine.clone())
+        Transaction::begin(self.engine.clone(), self.lock_waits.clone(), None)
+    }
+
+    /// Begins a new read-write transaction at the given HLC timestamp, or
+    /// after the latest assigned timestamp if that's later.
+    pub fn begin_at(&self, timestamp: Timestamp) -> Result<Transaction<E>> {
+        Transaction::begin(self.engine.clone(), self.lock_waits.clone(), Some(timestamp))
     }
 
     /// Begins a new read-only transaction at the latest version.
     pub fn begin_read_only(&self) -> Result<Transaction<E>> {
-        Transaction::begin_read_only(self.engine.clone(), None)
+        Transaction::begin_read_only(self.engine.clone(), self.lock_waits.clone(), None)
     }
 
     /// Begins a new read-only transaction as of the given version.
     pub fn begin_as_of(&self, version: Version) -> Result<Transaction<E>> {
-        Transaction::begin_read_only(self.engine.clone(), Some(version))
+        Transaction::begin_read_only(self.engine.clone(), self.lock_waits.clone(), Some(version))
+    }
+
+    /// Begins a new read-only transaction as of the given HLC timestamp,
+    /// seeing the transactions that committed at or before it. The caller must
+    /// make sure the timestamp isn't in the future, since later transactions
+    /// are then assigned timestamps after it.
+    pub fn begin_as_of_timestamp(&self, timestamp: Timestamp) -> Result<Transaction<E>> {
+        Transaction::begin_as_of_timestamp(self.engine.clone(), self.lock_waits.clone(), timestamp)
     }
 
     /// Resumes a transaction from the given transaction state.
     pub fn resume(&self, state: TransactionState) -> Result<Transaction<E>> {
-        Transaction::resume(self.engine.clone(), state)
+        Transaction::resume(self.engine.clone(), self.lock_waits.clone(), state)
     }
 
     /// Fetches the value of an unversioned key.
@@ -274,10 +559,302 @@ impl<E: Engine> MVCC<E> {
             None => 0,
         };
         let active_txns = engine.scan_prefix(&KeyPrefix::TxnActive.encode()).count() as u64;
-        Ok(Status { versions, active_txns, storage: engine.status()? })
+        let gc_horizon = match engine.get(&Key::GCHorizon.encode())? {
+            Some(ref v) => Version::decode(v)?,
+            None => 0,
+        };
+        Ok(Status { versions, active_txns, gc_horizon, storage: engine.status()? })
+    }
+
+    /// Garbage collects old versions that are no longer visible to active
+    /// transactions or time-travel queries within the given number of recent
+    /// versions, and moves the GC horizon up accordingly. See the module
+    /// documentation for details. The horizon never moves down, e.g. if the
+    /// retention increases.
+    pub fn gc(&self, retention: u64) -> Result<GarbageCollection> {
+        let mut engine = self.engine.lock()?;
+        let next = match engine.get(&Key::NextVersion.encode())? {
+            Some(ref v) => Version::decode(v)?,
+            None => 1,
+        };
+        let old_horizon = match engine.get(&Key::GCHorizon.encode())? {
+            Some(ref v) => Version::decode(v)?,
+            None => 0,
+        };
+
+        // Find the horizon, bounded by the retention window, the oldest active
+        // transaction, and the active set snapshots at or above the horizon.
+        // Lower snapshots are checked after higher ones, since their versions
+        // may be above the horizon once it's lowered by a higher one.
+        let active = Transaction::scan_active(&mut engine)?;
+        let mut horizon =
+            next.saturating_sub(retention).min(active.first().copied().unwrap_or(next));
+        let mut snapshots = Vec::new();
+        let mut scan = engine.scan_prefix(&KeyPrefix::TxnActiveSnapshot.encode());
+        while let Some((key, value)) = scan.next().transpose()? {
+            let Key::TxnActiveSnapshot(version) = Key::decode(&key)? else {
+                return errdata!("expected TxnActiveSnapshot key, got {key:?}");
+            };
+            let oldest = BTreeSet::<Version>::decode(&value)?.first().copied().unwrap_or(version);
+            snapshots.push((version, oldest, key));
+        }
+        drop(scan);
+        for (version, oldest, _) in snapshots.iter().rev() {
+            if *version >= horizon {
+                horizon = horizon.min(*oldest);
+            }
+        }
+        if horizon <= old_horizon {
+            return Ok(GarbageCollection { horizon: old_horizon, versions: 0 });
+        }
+
+        // Find versions below the horizon to remove. Versions of a key are
+        // ordered by version, so a version is removed if the next version of
+        // the same key is also below the horizon, or if it's the latest version
+        // below the horizon and a tombstone. Versions deleted by a prefix
+        // tombstone below the horizon are removed too, along with the prefix
+        // tombstone itself. The engine doesn't support writing while scanning,
+        // so buffer the keys.
+        let mut tombstones = Transaction::scan_prefix_tombstones(&mut engine)?;
+        tombstones.retain(|tombstone| tombstone.version < horizon);
+        let tombstone = bincode::serialize(&Option::<Vec<u8>>::None);
+        let mut remove = Vec::new();
+        let mut last: Option<(Vec<u8>, Vec<u8>, bool)> = None; // user key, key, tombstone
+        let range = (
+            Bound::Included(Key::Version(vec![].into(), 0).encode()),
+            Bound::Excluded(KeyPrefix::Unversioned.encode()),
+        );
+        let mut scan = engine.scan(range);
+        while let Some((key, value)) = scan.next().transpose()? {
+            let Key::Version(userkey, version) = Key::decode(&key)? else {
+                return errdata!("expected Key::Version got {key:?}");
+            };
+            if version >= horizon {
+                continue;
+            }
+            if tombstones.iter().any(|t| version < t.version && userkey.starts_with(&t.prefix)) {
+                remove.push(key);
+                continue;
+            }
+            if let Some((lastkey, key, deleted)) = last.take() {
+                if deleted || lastkey == userkey.as_ref() {
+                    remove.push(key);
+                }
+            }
+            last = Some((userkey.into_owned(), key, value == tombstone));
+        }
+        drop(scan);
+        if let Some((_, key, true)) = last {
+            remove.push(key);
+        }
+
+        // Remove the versions and older active set snapshots, and record the
+        // new horizon.
+        let versions = remove.len() as u64;
+        for key in remove {
+            engine.delete(&key)?;
+        }
+        for tombstone in tombstones {
+            engine.delete(&tombstone.key())?;
+        }
+        for (_, _, key) in snapshots.into_iter().filter(|(version, _, _)| *version < horizon) {
+            engine.delete(&key)?;
+        }
+
+        // Trim the start of the commit log, up to the first commit at or above
+        // the horizon.
+        let mut trim = Vec::new();
+        let mut scan = engine.scan_prefix(&KeyPrefix::CommitLog.encode());
+        while let Some((key, value)) = scan.next().transpose()? {
+            if Commit::decode(&value)?.version >= horizon {
+                break;
+            }
+            trim.push(key);
+        }
+        drop(scan);
+        for key in trim {
+            engine.delete(&key)?;
+        }
+
+        // Remove the timestamps of versions below the horizon that committed
+        // (or rolled back) before the horizon version began. Time-travel
+        // queries before then are rejected, and later ones see them as
+        // committed.
+        if let Some(begin) = engine.get(&Key::VersionTimestamp(horizon).encode())? {
+            let begin = Timestamp::decode(&begin)?;
+            let mut remove = Vec::new();
+            let to = Key::VersionTimestamp(horizon).encode();
+            let mut scan = engine.scan(KeyPrefix::VersionTimestamp.encode()..to);
+            while let Some((key, _)) = scan.next().transpose()? {
+                let Key::VersionTimestamp(version) = Key::decode(&key)? else {
+                    return errdata!("expected VersionTimestamp key, got {key:?}");
+                };
+                remove.push(version);
+            }
+            drop(scan);
+            for version in remove {
+                let commit_key = Key::CommitTimestamp(version).encode();
+                if let Some(commit) = engine.get(&commit_key)? {
+                    if Timestamp::decode(&commit)? > begin {
+                        continue;
+                    }
+                    engine.delete(&commit_key)?;
+                }
+                engine.delete(&Key::VersionTimestamp(version).encode())?;
+            }
+        }
+        engine.set(&Key::GCHorizon.encode(), horizon.encode())?;
+        Ok(GarbageCollection { horizon, versions })
+    }
+
+    /// Rolls back all active read-write transactions, returning their
+    /// versions. This is used when restoring a database to a point in time, at
+    /// which point any in-flight transactions can never complete.
+    pub fn rollback_active(&self) -> Result<Vec<Version>> {
+        let active = Transaction::scan_active(&mut self.engine.lock()?)?;
+        for &version in &active {
+            let state = TransactionState {
+                version,
+                read_only: false,
+                active: BTreeSet::new(),
+                snapshot: None,
+            };
+            Transaction { engine: self.engine.clone(), lock_waits: self.lock_waits.clone(), state }
+                .rollback()?;
+        }
+        Ok(active.into_iter().collect())
+    }
+
+    /// Rolls back all expired transactions (see Transaction::heartbeat) as of
+    /// the given HLC timestamp, returning their versions. Conflicting
+    /// transactions resolve expired transactions lazily, but this also cleans
+    /// up abandoned transactions that nobody conflicts with, which would
+    /// otherwise hold back garbage collection.
+    pub fn resolve_expired(&self, timestamp: Timestamp) -> Result<Vec<Version>> {
+        let mut engine = self.engine.lock()?;
+        Transaction::assign_timestamp(&mut engine, timestamp)?;
+        let mut resolved = Vec::new();
+        for version in Transaction::scan_active(&mut engine)? {
+            if Transaction::resolve_expired(&mut engine, &self.lock_waits, version)? {
+                resolved.push(version);
+            }
+        }
+        Ok(resolved)
+    }
+
+    /// Checks the MVCC invariants of all stored keys, returning descriptions
+    /// of any inconsistencies found. If repair is true, the inconsistent keys
+    /// are removed. Checks that:
+    ///
+    /// * All keys and versioned values can be decoded. The storage engines
+    ///   don't checksum data, so this is the only corruption check.
+    /// * Versions are below the next version.
+    /// * Transaction bookkeeping (writes, locks, savepoints, etc.) belongs to
+    ///   active transactions, i.e. there are no orphaned intents left behind
+    ///   by incomplete commits or rollbacks.
+    pub fn scrub(&self, repair: bool) -> Result<Vec<String>> {
+        let mut engine = self.engine.lock()?;
+        let next = match engine.get(&Key::NextVersion.encode())? {
+            Some(ref v) => Version::decode(v)?,
+            None => 1,
+        };
+        let active = Transaction::scan_active(&mut engine)?;
+
+        // The engine doesn't support writing while scanning, so buffer the
+        // inconsistent keys.
+        let mut issues = Vec::new();
+        let mut remove = Vec::new();
+        let mut scan = engine.scan(..);
+        while let Some((key, value)) = scan.next().transpose()? {
+            let issue = match Key::decode(&key) {
+                Err(error) => Some(format!("invalid key {}: {error}", format::Raw::bytes(&key))),
+                Ok(Key::Version(userkey, version)) if version >= next => Some(format!(
+                    "key {} has version {version} at or above next version {next}",
+                    format::Raw::bytes(&userkey)
+                )),
+                Ok(Key::Version(userkey, version)) => {
+                    bincode::deserialize::<Option<Vec<u8>>>(&value).err().map(|error| {
+                        let userkey = format::Raw::bytes(&userkey);
+                        format!("key {userkey} has invalid value at version {version}: {error}")
+                    })
+                }
+                Ok(
+                    Key::TxnWrite(version, _)
+                    | Key::TxnSavepoint(version, _)
+                    | Key::TxnSavepointWrite(version, _, _)
+                    | Key::TxnLock(version, _)
+                    | Key::TxnWaitsFor(version)
+                    | Key::TxnBulkWrite(version, _)
+                    | Key::TxnCapture(version, _)
+                    | Key::TxnExpires(version),
+                ) if !active.contains(&version) => Some(format!(
+                    "orphaned intent {} of inactive transaction {version}",
+                    format::Raw::bytes(&key)
+                )),
+                Ok(Key::LockWaiter(_, _)) => match Version::decode(&value) {
+                    Ok(version) if active.contains(&version) => None,
+                    Ok(version) => Some(format!(
+                        "orphaned intent {} of inactive transaction {version}",
+                        format::Raw::bytes(&key)
+                    )),
+                    Err(error) => Some(format!(
+                        "lock waiter {} is invalid: {error}",
+                        format::Raw::bytes(&key)
+                    )),
+                },
+                Ok(Key::Lock(userkey)) => match Version::decode(&value) {
+                    Ok(version) if active.contains(&version) => None,
+                    Ok(version) => Some(format!(
+                        "key {} is locked by inactive transaction {version}",
+                        format::Raw::bytes(&userkey)
+                    )),
+                    Err(error) => Some(format!(
+                        "key {} has invalid lock: {error}",
+                        format::Raw::bytes(&userkey)
+                    )),
+                },
+                Ok(_) => None,
+            };
+            if let Some(issue) = issue {
+                issues.push(issue);
+                remove.push(key);
+            }
+        }
+        drop(scan);
+
+        if repair {
+            for key in remove {
+                engine.delete(&key)?;
+            }
+        }
+        Ok(issues)
     }
 }
 
+/// The result of an MVCC garbage collection run.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct GarbageCollection {
+    /// The GC horizon after the run.
+    pub horizon: Version,
+    /// The number of removed versions.
+    pub versions: u64,
+}
+
+impl encoding::Value for GarbageCollection {}
+
+/// A commit log entry (see Transaction::capture).
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Commit {
+    /// The commit sequence number, in commit order.
+    pub seq: u64,
+    /// The version of the committed transaction.
+    pub version: Version,
+    /// The written keys and values, in key order. None for deletes.
+    pub writes: Vec<(Vec<u8>, Option<Vec<u8>>)>,
+}
+
+impl encoding::Value for Commit {}
+
 /// MVCC engine status.
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Status {
@@ -285,16 +862,92 @@ pub struct Status {
     pub versions: u64,
     /// Number of currently active transactions.
     pub active_txns: u64,
+    /// The GC horizon, below which old versions have been garbage collected.
+    pub gc_horizon: Version,
     /// The storage engine.
     pub storage: super::engine::Status,
 }
 
 impl encoding::Value for Status {}
 
+/// Wakes up lock waiters when the transaction they're waiting for completes,
+/// such that they can retry immediately rather than polling. Transactions
+/// notify when they commit or roll back, and when they leave lock wait queues,
+/// which may unblock later waiters. This only lives in memory, so waiters must
+/// retry Transaction::lock() to acquire the lock, and should bound their waits
+/// in case a notification is missed (e.g. when a different node's Raft state
+/// machine hasn't applied the completion yet).
+pub struct LockWaits {
+    /// The latest notification sequence number, and the most recent
+    /// notifications as sequence number and version pairs.
+    notifications: Mutex<(u64, VecDeque<(u64, Version)>)>,
+    /// Signalled on every notification.
+    notified: Condvar,
+}
+
+impl LockWaits {
+    /// The number of recent notifications to retain.
+    const HISTORY: usize = 1024;
+
+    /// Creates a new lock waiter notifier.
+    pub fn new() -> Self {
+        Self { notifications: Mutex::new((0, VecDeque::new())), notified: Condvar::new() }
+    }
+
+    /// Returns the latest notification sequence number. Waiters should fetch
+    /// this before attempting to acquire a lock and pass it to wait(), to
+    /// avoid missing notifications in between.
+    pub fn seq(&self) -> Result<u64> {
+        Ok(self.notifications.lock()?.0)
+    }
+
+    /// Notifies waiters that the given transaction has completed or stopped
+    /// waiting for locks.
+    pub fn notify(&self, version: Version) -> Result<()> {
+        let mut notifications = self.notifications.lock()?;
+        let (seq, recent) = &mut *notifications;
+        *seq += 1;
+        if recent.len() >= Self::HISTORY {
+            recent.pop_front();
+        }
+        recent.push_back((*seq, version));
+        self.notified.notify_all();
+        Ok(())
+    }
+
+    /// Waits until the given transaction notifies after the given sequence
+    /// number (see seq), or the timeout elapses. Returns true if notified, or
+    /// if the notification may have been dropped from the history.
+    pub fn wait(&self, version: Version, since: u64, timeout: Duration) -> Result<bool> {
+        let deadline = Instant::now() + timeout;
+        let mut notifications = self.notifications.lock()?;
+        loop {
+            let (seq, recent) = &*notifications;
+            let oldest = recent.front().map_or(seq + 1, |(seq, _)| *seq);
+            if oldest > since + 1 || recent.iter().rev().any(|&(s, v)| s > since && v == version) {
+                return Ok(true);
+            }
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            if timeout.is_zero() {
+                return Ok(false);
+            }
+            notifications = self.notified.wait_timeout(notifications, timeout)?.0;
+        }
+    }
+}
+
+impl Default for LockWaits {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 /// An MVCC transaction.
 pub struct Transaction<E: Engine> {
     /// The underlying engine, shared by all transactions.
     engine: Arc<Mutex<E>>,
+    /// Wakes up lock waiters, shared by all transactions.
+    lock_waits: Arc<LockWaits>,
     /// The transaction state.
     state: TransactionState,
 }
@@ -324,11 +977,42 @@ pub struct TransactionState {
     /// transaction even if they're writing at a lower version, since they're
     /// not committed yet. Uses a BTreeSet for test determinism.
     pub active: BTreeSet<Version>,
+    /// For read committed transactions, the next version as of the current
+    /// statement's snapshot. Versions below it are visible, except for those
+    /// in the active set, which is taken along with the snapshot. None for
+    /// snapshot isolation.
+    pub snapshot: Option<Version>,
 }
 
 impl encoding::Value for TransactionState {}
 
 impl TransactionState {
+    /// Returns the transaction's isolation level.
+    pub fn isolation(&self) -> IsolationLevel {
+        match self.snapshot {
+            Some(_) => IsolationLevel::ReadCommitted,
+            None => IsolationLevel::Snapshot,
+        }
+    }
+
+    /// Errors if versions visible to this transaction may have been garbage
+    /// collected, i.e. if it or a version in its active set is below the GC
+    /// horizon. The horizon never passes active read-write transactions, so
+    /// only read-only transactions are checked.
+    fn check_gc<E: Engine>(&self, engine: &mut E) -> Result<()> {
+        if !self.read_only {
+            return Ok(());
+        }
+        let Some(horizon) = engine.get(&Key::GCHorizon.encode())? else {
+            return Ok(());
+        };
+        let oldest = self.active.first().map_or(self.version, |v| self.version.min(*v));
+        if oldest < Version::decode(&horizon)? {
+            return errinput!("version {} has been garbage collected", self.version);
+        }
+        Ok(())
+    }
+
     /// Checks whether the given version is visible to this transaction.
     ///
     /// Future versions, and versions belonging to active transactions as of
@@ -341,15 +1025,101 @@ impl TransactionState {
     /// a consistent version both before and after any active transaction at
     /// that version commits its writes. See the module documentation for
     /// details.
+    ///
+    /// Read committed transactions see versions below their snapshot, which
+    /// includes their own version.
     fn is_visible(&self, version: Version) -> bool {
         if self.active.contains(&version) {
             false
         } else if self.read_only {
             version < self.version
+        } else if let Some(snapshot) = self.snapshot {
+            version < snapshot
         } else {
             version <= self.version
         }
     }
+
+    /// Checks whether a visible version of a key is deleted by a visible prefix
+    /// tombstone, i.e. one at a later version.
+    fn is_deleted(&self, tombstones: &[PrefixTombstone], key: &[u8], version: Version) -> bool {
+        tombstones.iter().any(|tombstone| {
+            version < tombstone.version
+                && self.is_visible(tombstone.version)
+                && key.starts_with(&tombstone.prefix)
+        })
+    }
+}
+
+/// A tombstone for all keys under a prefix, see Key::PrefixTombstone.
+struct PrefixTombstone {
+    /// The deleted key prefix.
+    prefix: Vec<u8>,
+    /// The version that wrote the tombstone.
+    version: Version,
+    /// The writer's latest savepoint when the tombstone was written, if any.
+    savepoint: Option<u64>,
+}
+
+impl PrefixTombstone {
+    /// Checks whether the tombstone overlaps the given key range.
+    fn overlaps(&self, range: &impl RangeBounds<Vec<u8>>) -> bool {
+        let below_end = match (range.start_bound(), keycode::prefix_range(&self.prefix).1) {
+            (Bound::Included(start) | Bound::Excluded(start), Bound::Excluded(end)) => *start < end,
+            (_, _) => true,
+        };
+        let above_start = match range.end_bound() {
+            Bound::Included(end) => *end >= self.prefix,
+            Bound::Excluded(end) => *end > self.prefix,
+            Bound::Unbounded => true,
+        };
+        below_end && above_start
+    }
+
+    /// Returns the Key::PrefixTombstone engine key.
+    fn key(&self) -> Vec<u8> {
+        Key::PrefixTombstone(self.version, self.prefix.as_slice().into()).encode()
+    }
+}
+
+/// Information about an active read-write transaction, as returned by
+/// Transaction::scan_transactions().
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TransactionInfo {
+    /// The transaction's version, which identifies it.
+    pub version: Version,
+    /// The number of versions allocated since the transaction began.
+    pub age: u64,
+    /// The number of keys written by the transaction.
+    pub writes: u64,
+    /// The number of locks held by the transaction.
+    pub locks: u64,
+    /// The transaction whose lock it's waiting for, if any.
+    pub waiting_for: Option<Version>,
+}
+
+impl encoding::Value for TransactionInfo {}
+
+/// A transaction isolation level.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum IsolationLevel {
+    /// Each statement sees a fresh snapshot of committed data.
+    ReadCommitted,
+    /// The transaction sees a consistent snapshot as of its start.
+    Snapshot,
+    /// Like Snapshot, but reads are validated before commit to prevent write
+    /// skew (see Transaction::check_read). Not tracked by TransactionState.
+    Serializable,
+}
+
+impl std::fmt::Display for IsolationLevel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::ReadCommitted => "READ COMMITTED",
+            Self::Snapshot => "SNAPSHOT",
+            Self::Serializable => "SERIALIZABLE",
+        })
+    }
 }
 
 impl From<TransactionState> for Cow<'_, TransactionState> {
@@ -367,8 +1137,13 @@ impl<'a> From<&'a TransactionState> for Cow<'a, TransactionState> {
 impl<E: Engine> Transaction<E> {
     /// Begins a new transaction in read-write mode. This will allocate a new
     /// version that the transaction can write at, add it to the active set, and
-    /// record its active snapshot for time-travel queries.
-    fn begin(engine: Arc<Mutex<E>>) -> Result<Self> {
+    /// record its active snapshot for time-travel queries. If a timestamp is
+    /// given, the version's begin timestamp is recorded too.
+    fn begin(
+        engine: Arc<Mutex<E>>,
+        lock_waits: Arc<LockWaits>,
+        timestamp: Option<Timestamp>,
+    ) -> Result<Self> {
         let mut session = engine.lock()?;
 
         // Allocate a new version to write at.
@@ -377,6 +1152,10 @@ impl<E: Engine> Transaction<E> {
             None => 1,
         };
         session.set(&Key::NextVersion.encode(), (version + 1).encode())?;
+        if let Some(timestamp) = timestamp {
+            let timestamp = Self::assign_timestamp(&mut session, timestamp)?;
+            session.set(&Key::VersionTimestamp(version).encode(), timestamp.encode())?;
+        }
 
         // Fetch the current set of active transactions, persist it for
         // time-travel queries if non-empty, then add this txn to it.
@@ -387,14 +1166,19 @@ impl<E: Engine> Transaction<E> {
         session.set(&Key::TxnActive(version).encode(), vec![])?;
         drop(session);
 
-        Ok(Self { engine, state: TransactionState { version, read_only: false, active } })
+        let state = TransactionState { version, read_only: false, active, snapshot: None };
+        Ok(Self { engine, lock_waits, state })
     }
 
     /// Begins a new read-only transaction. If version is given it will see the
     /// state as of the beginning of that version (ignoring writes at that
     /// version). In other words, it sees the same state as the read-write
     /// transaction at that version saw when it began.
-    fn begin_read_only(engine: Arc<Mutex<E>>, as_of: Option<Version>) -> Result<Self> {
+    fn begin_read_only(
+        engine: Arc<Mutex<E>>,
+        lock_waits: Arc<LockWaits>,
+        as_of: Option<Version>,
+    ) -> Result<Self> {
         let mut session = engine.lock()?;
 
         // Fetch the latest version.
@@ -418,102 +1202,1023 @@ impl<E: Engine> Transaction<E> {
         } else {
             active = Self::scan_active(&mut session)?;
         }
+        let state = TransactionState { version, read_only: true, active, snapshot: None };
+        state.check_gc(&mut *session)?;
+
+        drop(session);
+
+        Ok(Self { engine, lock_waits, state })
+    }
+
+    /// Begins a new read-only transaction as of the given HLC timestamp. Its
+    /// version is the first version that began after the timestamp, and the
+    /// earlier versions that hadn't committed at the timestamp are considered
+    /// active, such that it sees exactly the transactions that committed at or
+    /// before the timestamp. Bumps the latest timestamp to the given one, such
+    /// that the snapshot is stable.
+    fn begin_as_of_timestamp(
+        engine: Arc<Mutex<E>>,
+        lock_waits: Arc<LockWaits>,
+        timestamp: Timestamp,
+    ) -> Result<Self> {
+        let mut session = engine.lock()?;
+
+        // Versions below the GC horizon may have had their timestamps removed
+        // if they committed before the horizon began, so timestamps before
+        // then can't be reconstructed.
+        if let Some(horizon) = session.get(&Key::GCHorizon.encode())? {
+            let horizon = Version::decode(&horizon)?;
+            if let Some(begin) = session.get(&Key::VersionTimestamp(horizon).encode())? {
+                if timestamp < Timestamp::decode(&begin)? {
+                    return errinput!("timestamp {timestamp} has been garbage collected");
+                }
+            }
+        }
+        Self::assign_timestamp(&mut session, timestamp)?;
+
+        let mut version = match session.get(&Key::NextVersion.encode())? {
+            Some(ref v) => Version::decode(v)?,
+            None => 1,
+        };
+        let mut began = Vec::new();
+        let mut scan = session.scan_prefix(&KeyPrefix::VersionTimestamp.encode());
+        while let Some((key, value)) = scan.next().transpose()? {
+            let Key::VersionTimestamp(v) = Key::decode(&key)? else {
+                return errdata!("expected VersionTimestamp key, got {key:?}");
+            };
+            if Timestamp::decode(&value)? > timestamp {
+                version = v;
+                break;
+            }
+            began.push(v);
+        }
+        drop(scan);
+
+        let mut active: BTreeSet<Version> =
+            Self::scan_active(&mut session)?.into_iter().filter(|v| *v < version).collect();
+        for v in began {
+            match session.get(&Key::CommitTimestamp(v).encode())? {
+                Some(commit) if Timestamp::decode(&commit)? <= timestamp => {}
+                _ => {
+                    active.insert(v);
+                }
+            }
+        }
+        let state = TransactionState { version, read_only: true, active, snapshot: None };
+        state.check_gc(&mut *session)?;
+        drop(session);
+
+        Ok(Self { engine, lock_waits, state })
+    }
+
+    /// Assigns an HLC timestamp, bumping the given timestamp past the latest
+    /// assigned timestamp if necessary, and records it as the latest.
+    fn assign_timestamp(session: &mut MutexGuard<E>, timestamp: Timestamp) -> Result<Timestamp> {
+        let timestamp = match session.get(&Key::Timestamp.encode())? {
+            Some(ref last) => timestamp.max(Timestamp::decode(last)?.next()),
+            None => timestamp,
+        };
+        session.set(&Key::Timestamp.encode(), timestamp.encode())?;
+        Ok(timestamp)
+    }
 
+    /// Takes a new snapshot for a read committed transaction, which sees all
+    /// versions committed before now (as well as its own writes). Subsequent
+    /// calls take new snapshots. Errors for read-only transactions.
+    pub fn refresh_snapshot(&mut self) -> Result<()> {
+        if self.state.read_only {
+            return Err(Error::ReadOnly);
+        }
+        let mut session = self.engine.lock()?;
+        let snapshot = match session.get(&Key::NextVersion.encode())? {
+            Some(ref v) => Version::decode(v)?,
+            None => 1,
+        };
+        let mut active = Self::scan_active(&mut session)?;
+        active.remove(&self.state.version);
         drop(session);
 
-        Ok(Self { engine, state: TransactionState { version, read_only: true, active } })
+        self.state.snapshot = Some(snapshot);
+        self.state.active = active;
+        Ok(())
     }
 
     /// Resumes a transaction from the given state.
-    fn resume(engine: Arc<Mutex<E>>, s: TransactionState) -> Result<Self> {
+    fn resume(
+        engine: Arc<Mutex<E>>,
+        lock_waits: Arc<LockWaits>,
+        s: TransactionState,
+    ) -> Result<Self> {
         // For read-write transactions, verify that the transaction is still
         // active before making further writes.
         if !s.read_only && engine.lock()?.get(&Key::TxnActive(s.version).encode())?.is_none() {
             return errinput!("no active transaction at version {}", s.version);
         }
-        Ok(Self { engine, state: s })
-    }
+        Ok(Self { engine, lock_waits, state: s })
+    }
+
+    /// Errors if the read-write transaction is no longer active, e.g. because
+    /// it was aborted by a different transaction (see abort_transaction).
+    fn check_active(&self, session: &mut MutexGuard<E>) -> Result<()> {
+        if session.get(&Key::TxnActive(self.state.version).encode())?.is_none() {
+            return errinput!("no active transaction at version {}", self.state.version);
+        }
+        Ok(())
+    }
+
+    /// Fetches the set of currently active transactions.
+    fn scan_active(session: &mut MutexGuard<E>) -> Result<BTreeSet<Version>> {
+        let mut active = BTreeSet::new();
+        let mut scan = session.scan_prefix(&KeyPrefix::TxnActive.encode());
+        while let Some((key, _)) = scan.next().transpose()? {
+            match Key::decode(&key)? {
+                Key::TxnActive(version) => active.insert(version),
+                key => return errdata!("expected TxnActive key, got {key:?}"),
+            };
+        }
+        Ok(active)
+    }
+
+    /// Returns the version the transaction is running at.
+    pub fn version(&self) -> Version {
+        self.state.version
+    }
+
+    /// Returns whether the transaction is read-only.
+    pub fn read_only(&self) -> bool {
+        self.state.read_only
+    }
+
+    /// Returns the lock waiter wakeups, which can be used to wait for the
+    /// transaction blocking a lock to complete (see lock).
+    pub fn lock_waits(&self) -> Arc<LockWaits> {
+        self.lock_waits.clone()
+    }
+
+    /// Returns the transaction's state. This can be used to instantiate a
+    /// functionally equivalent transaction via resume().
+    pub fn state(&self) -> &TransactionState {
+        &self.state
+    }
+
+    /// Commits the transaction, by removing it from the active set. This will
+    /// immediately make its writes visible to subsequent transactions. Also
+    /// removes its TxnWrite records, which are no longer needed.
+    ///
+    /// NB: commit does not flush writes to durable storage, since we rely on
+    /// the Raft log for persistence.
+    pub fn commit(self) -> Result<()> {
+        self.commit_with(None)
+    }
+
+    /// Commits the transaction at the given HLC timestamp, or after the latest
+    /// assigned timestamp if that's later (see MVCC::begin_at).
+    pub fn commit_at(self, timestamp: Timestamp) -> Result<()> {
+        self.commit_with(Some(timestamp))
+    }
+
+    /// Commits the transaction, recording the commit timestamp if given.
+    fn commit_with(self, timestamp: Option<Timestamp>) -> Result<()> {
+        if self.state.read_only {
+            return Ok(());
+        }
+        let mut engine = self.engine.lock()?;
+        self.check_active(&mut engine)?;
+        self.log_commit(&mut engine)?;
+        if let Some(timestamp) = timestamp {
+            let timestamp = Self::assign_timestamp(&mut engine, timestamp)?;
+            let key = Key::CommitTimestamp(self.state.version).encode();
+            engine.set(&key, timestamp.encode())?;
+        }
+        let remove: Vec<_> = engine
+            .scan_prefix(&KeyPrefix::TxnWrite(self.state.version).encode())
+            .map_ok(|(k, _)| k)
+            .try_collect()?;
+        for key in remove {
+            engine.delete(&key)?
+        }
+        let remove: Vec<_> = engine
+            .scan_prefix(&KeyPrefix::TxnBulkWrite(self.state.version).encode())
+            .map_ok(|(k, _)| k)
+            .try_collect()?;
+        for key in remove {
+            engine.delete(&key)?
+        }
+        Self::remove_savepoints(&mut engine, self.state.version)?;
+        Self::release_locks(&mut engine, self.state.version)?;
+        Self::remove_expiry(&mut engine, self.state.version)?;
+        engine.delete(&Key::TxnActive(self.state.version).encode())?;
+        self.lock_waits.notify(self.state.version)
+    }
+
+    /// Rolls back the transaction, by undoing all written versions and removing
+    /// it from the active set. The active set snapshot is left behind, since
+    /// this is needed for time travel queries at this version.
+    pub fn rollback(self) -> Result<()> {
+        if self.state.read_only {
+            return Ok(());
+        }
+        Self::rollback_version(&mut self.engine.lock()?, self.state.version)?;
+        self.lock_waits.notify(self.state.version)
+    }
+
+    /// Rolls back the read-write transaction at the given version.
+    fn rollback_version(engine: &mut MutexGuard<E>, version: Version) -> Result<()> {
+        let mut rollback = Vec::new();
+        let mut scan = engine.scan_prefix(&KeyPrefix::TxnWrite(version).encode());
+        while let Some((key, _)) = scan.next().transpose()? {
+            match Key::decode(&key)? {
+                Key::TxnWrite(_, key) => {
+                    rollback.push(Key::Version(key, version).encode()) // the version
+                }
+                key => return errdata!("expected TxnWrite, got {key:?}"),
+            };
+            rollback.push(key); // the TxnWrite record
+        }
+        drop(scan);
+        let mut scan = engine.scan_prefix(&KeyPrefix::TxnBulkWrite(version).encode());
+        let mut ranges = Vec::new();
+        while let Some((key, to)) = scan.next().transpose()? {
+            match Key::decode(&key)? {
+                Key::TxnBulkWrite(_, from) => ranges.push((from.into_owned(), to)),
+                key => return errdata!("expected TxnBulkWrite, got {key:?}"),
+            };
+            rollback.push(key); // the TxnBulkWrite record
+        }
+        drop(scan);
+        for range in ranges {
+            let mut scan = engine.scan(Self::version_range(range.0..=range.1));
+            while let Some((key, _)) = scan.next().transpose()? {
+                match Key::decode(&key)? {
+                    Key::Version(_, v) if v == version => rollback.push(key),
+                    Key::Version(..) => {}
+                    key => return errdata!("expected Key::Version got {key:?}"),
+                }
+            }
+        }
+        let mut scan = engine.scan_prefix(&KeyPrefix::TxnCapture(version).encode());
+        while let Some((key, _)) = scan.next().transpose()? {
+            rollback.push(key);
+        }
+        drop(scan);
+        for tombstone in Self::scan_prefix_tombstones(engine)? {
+            if tombstone.version == version {
+                rollback.push(tombstone.key());
+            }
+        }
+        for key in rollback.into_iter() {
+            engine.delete(&key)?;
+        }
+        Self::remove_savepoints(engine, version)?;
+        Self::release_locks(engine, version)?;
+        Self::remove_expiry(engine, version)?;
+        engine.delete(&Key::TxnActive(version).encode()) // remove from active set
+    }
+
+    /// Removes the transaction's expiry record, if any.
+    fn remove_expiry(engine: &mut MutexGuard<E>, version: Version) -> Result<()> {
+        let key = Key::TxnExpires(version).encode();
+        if engine.get(&key)?.is_some() {
+            engine.delete(&key)?;
+        }
+        Ok(())
+    }
+
+    /// Records a heartbeat from the transaction's coordinator at the given HLC
+    /// timestamp, which expires the transaction after the given timeout unless
+    /// it heartbeats again. An expired transaction is considered abandoned,
+    /// e.g. because its coordinator died, and is rolled back when another
+    /// transaction conflicts with its writes or locks, or by
+    /// MVCC::resolve_expired(). Transactions that never heartbeat don't expire.
+    pub fn heartbeat(&self, timestamp: Timestamp, timeout: Duration) -> Result<()> {
+        if self.state.read_only {
+            return Err(Error::ReadOnly);
+        }
+        let mut engine = self.engine.lock()?;
+        self.check_active(&mut engine)?;
+        let timestamp = Self::assign_timestamp(&mut engine, timestamp)?;
+        let wall = timestamp.wall.saturating_add(timeout.as_nanos().try_into().unwrap_or(u64::MAX));
+        let expires = Timestamp::new(wall, timestamp.logical);
+        engine.set(&Key::TxnExpires(self.state.version).encode(), expires.encode())
+    }
+
+    /// Rolls back the given transaction if it has expired, i.e. if its expiry
+    /// is before the latest assigned HLC timestamp, and wakes up its lock
+    /// waiters. Returns true if it did.
+    fn resolve_expired(
+        session: &mut MutexGuard<E>,
+        lock_waits: &LockWaits,
+        version: Version,
+    ) -> Result<bool> {
+        let Some(expires) = session.get(&Key::TxnExpires(version).encode())? else {
+            return Ok(false);
+        };
+        let Some(now) = session.get(&Key::Timestamp.encode())? else {
+            return Ok(false);
+        };
+        if Timestamp::decode(&expires)? >= Timestamp::decode(&now)? {
+            return Ok(false);
+        }
+        Self::rollback_version(session, version)?;
+        lock_waits.notify(version)?;
+        Ok(true)
+    }
+
+    /// Attempts to acquire an exclusive lock on a key, e.g. for SELECT FOR
+    /// UPDATE. Returns None if the lock was acquired (or was already held),
+    /// otherwise the version of a transaction that must complete first, in
+    /// which case this transaction is added to the back of the key's wait
+    /// queue and should retry when the blocker completes (see LockWaits).
+    /// Retries keep their position in the queue. Locks are released when the
+    /// transaction completes.
+    ///
+    /// The lock can't be acquired while the key is locked by a different
+    /// active transaction, has an uncommitted version written by one, or while
+    /// other transactions are ahead of it in the wait queue. If the key has a
+    /// version written by a newer transaction, this errors with a
+    /// serialization failure, since the lock holder could never write the key
+    /// anyway.
+    pub fn lock(&self, key: &[u8]) -> Result<Option<Version>> {
+        if self.state.read_only {
+            return Err(Error::ReadOnly);
+        }
+        let version = self.state.version;
+        let mut engine = self.engine.lock()?;
+        self.check_active(&mut engine)?;
+        let holder = Self::lock_holder(&mut engine, key)?;
+        if holder == Some(version) {
+            return Ok(None);
+        }
+        let mut blocker = holder;
+        if blocker.is_none() {
+            let latest = engine
+                .scan(
+                    Key::Version(key.into(), 0).encode()
+                        ..=Key::Version(key.into(), u64::MAX).encode(),
+                )
+                .next_back()
+                .transpose()?;
+            let latest = match latest {
+                Some((latest, _)) => match Key::decode(&latest)? {
+                    Key::Version(_, latest) => Some(latest),
+                    key => return errdata!("expected Key::Version got {key:?}"),
+                },
+                None => None,
+            };
+            // Prefix tombstones delete the key as of their version.
+            let latest = latest.max(Self::latest_prefix_tombstone(&mut engine, key)?);
+            if let Some(latest) = latest {
+                if latest > version {
+                    if Self::resolve_expired(&mut engine, &self.lock_waits, latest)? {
+                        drop(engine);
+                        return self.lock(key);
+                    }
+                    return Err(Error::Serialization);
+                } else if latest < version
+                    && engine.get(&Key::TxnActive(latest).encode())?.is_some()
+                {
+                    blocker = Some(latest);
+                }
+            }
+        }
+        if blocker.is_none() {
+            let waiter = engine
+                .scan_prefix(&KeyPrefix::LockWaiter(key.into()).encode())
+                .next()
+                .transpose()?;
+            if let Some((_, waiter)) = waiter {
+                blocker = Some(Version::decode(&waiter)?).filter(|w| *w != version);
+            }
+        }
+
+        // If we have to wait, record the waits-for edge. If the blocker is
+        // (transitively) waiting for us, waiting would deadlock, so abort. If
+        // the blocker has expired, roll it back and retry instead. Otherwise,
+        // join the back of the wait queue unless we're already in it.
+        let position = Self::lock_position(&mut engine, version, key)?;
+        if let Some(blocker) = blocker {
+            if Self::resolve_expired(&mut engine, &self.lock_waits, blocker)? {
+                drop(engine);
+                return self.lock(key);
+            }
+            if Self::waits_for(&mut engine, blocker, version)? {
+                Self::remove_lock_waits(&mut engine, version)?;
+                self.lock_waits.notify(version)?;
+                return Err(Error::Deadlock);
+            }
+            if position.is_none() {
+                let last = engine
+                    .scan_prefix(&KeyPrefix::LockWaiter(key.into()).encode())
+                    .next_back()
+                    .transpose()?;
+                let seq = match last {
+                    Some((last, _)) => match Key::decode(&last)? {
+                        Key::LockWaiter(_, seq) => seq + 1,
+                        key => return errdata!("expected Key::LockWaiter got {key:?}"),
+                    },
+                    None => 0,
+                };
+                engine.set(&Key::TxnLock(version, key.into()).encode(), seq.encode())?;
+                engine.set(&Key::LockWaiter(key.into(), seq).encode(), version.encode())?;
+            }
+            engine.set(&Key::TxnWaitsFor(version).encode(), blocker.encode())?;
+            return Ok(Some(blocker));
+        }
+        if let Some(seq) = position {
+            engine.delete(&Key::LockWaiter(key.into(), seq).encode())?;
+        }
+        engine.set(&Key::TxnLock(version, key.into()).encode(), vec![])?;
+        engine.delete(&Key::TxnWaitsFor(version).encode())?;
+        engine.set(&Key::Lock(key.into()).encode(), version.encode())?;
+        Ok(None)
+    }
+
+    /// Removes the transaction from all lock wait queues, e.g. when it gives
+    /// up waiting, and wakes up waiters that may be unblocked by it. Held
+    /// locks are retained.
+    pub fn cancel_lock_waits(&self) -> Result<()> {
+        if self.state.read_only {
+            return Ok(());
+        }
+        let mut engine = self.engine.lock()?;
+        Self::remove_lock_waits(&mut engine, self.state.version)?;
+        self.lock_waits.notify(self.state.version)
+    }
+
+    /// Removes the given transaction from all lock wait queues, along with its
+    /// waits-for edge.
+    fn remove_lock_waits(session: &mut MutexGuard<E>, version: Version) -> Result<()> {
+        for (key, position) in Self::scan_locks(session, version)? {
+            if let Some(seq) = position {
+                session.delete(&Key::LockWaiter(key.as_slice().into(), seq).encode())?;
+                session.delete(&Key::TxnLock(version, key.into()).encode())?;
+            }
+        }
+        session.delete(&Key::TxnWaitsFor(version).encode())
+    }
+
+    /// Returns true if the given waiter is transitively waiting for the given
+    /// target transaction, by following waits-for edges between active
+    /// transactions. Edges are only updated when a waiter retries, so they
+    /// may be stale, in which case a deadlock may be reported spuriously.
+    fn waits_for(session: &mut MutexGuard<E>, waiter: Version, target: Version) -> Result<bool> {
+        let mut seen = BTreeSet::new();
+        let mut current = waiter;
+        while seen.insert(current) {
+            if current == target {
+                return Ok(true);
+            }
+            if session.get(&Key::TxnActive(current).encode())?.is_none() {
+                break;
+            }
+            match session.get(&Key::TxnWaitsFor(current).encode())? {
+                Some(next) => current = Version::decode(&next)?,
+                None => break,
+            }
+        }
+        Ok(false)
+    }
+
+    /// Returns the transaction holding a lock on the given key, if any. Locks
+    /// are released when the holder completes, so it's always active.
+    fn lock_holder(session: &mut MutexGuard<E>, key: &[u8]) -> Result<Option<Version>> {
+        let Some(holder) = session.get(&Key::Lock(key.into()).encode())? else {
+            return Ok(None);
+        };
+        Ok(Some(Version::decode(&holder)?))
+    }
+
+    /// Returns the given transaction's position in a key's wait queue, if
+    /// it's waiting for the key's lock. The position is stored as the
+    /// transaction's TxnLock value, which is empty for held locks.
+    fn lock_position(
+        session: &mut MutexGuard<E>,
+        version: Version,
+        key: &[u8],
+    ) -> Result<Option<u64>> {
+        match session.get(&Key::TxnLock(version, key.into()).encode())? {
+            Some(seq) if !seq.is_empty() => Ok(Some(u64::decode(&seq)?)),
+            Some(_) | None => Ok(None),
+        }
+    }
+
+    /// Fetches the keys locked or waited for by the given transaction, along
+    /// with its wait queue position for keys it's waiting for.
+    fn scan_locks(
+        session: &mut MutexGuard<E>,
+        version: Version,
+    ) -> Result<Vec<(Vec<u8>, Option<u64>)>> {
+        let mut keys = Vec::new();
+        let mut scan = session.scan_prefix(&KeyPrefix::TxnLock(version).encode());
+        while let Some((key, seq)) = scan.next().transpose()? {
+            let position = if seq.is_empty() { None } else { Some(u64::decode(&seq)?) };
+            match Key::decode(&key)? {
+                Key::TxnLock(_, key) => keys.push((key.into_owned(), position)),
+                key => return errdata!("expected Key::TxnLock got {key:?}"),
+            }
+        }
+        Ok(keys)
+    }
+
+    /// Releases all locks held by the given transaction, and removes it from
+    /// all wait queues.
+    fn release_locks(session: &mut MutexGuard<E>, version: Version) -> Result<()> {
+        // Waits-for edges only exist alongside lock records.
+        let keys = Self::scan_locks(session, version)?;
+        if keys.is_empty() {
+            return Ok(());
+        }
+        for (key, position) in keys {
+            match position {
+                Some(seq) => {
+                    session.delete(&Key::LockWaiter(key.as_slice().into(), seq).encode())?
+                }
+                None => session.delete(&Key::Lock(key.as_slice().into()).encode())?,
+            }
+            session.delete(&Key::TxnLock(version, key.into()).encode())?;
+        }
+        session.delete(&Key::TxnWaitsFor(version).encode())
+    }
+
+    /// Fetches the latest value of a key locked by the transaction, ignoring
+    /// the transaction's snapshot. The lock ensures there are no uncommitted
+    /// or newer versions written by other transactions, but errors with a
+    /// serialization failure if there are anyway.
+    fn get_locked(
+        session: &mut MutexGuard<E>,
+        state: &TransactionState,
+        key: &[u8],
+    ) -> Result<Option<Vec<u8>>> {
+        let from = Key::Version(key.into(), 0).encode();
+        let to = Key::Version(key.into(), u64::MAX).encode();
+        let Some((latest, value)) = session.scan(from..=to).next_back().transpose()? else {
+            return Ok(None);
+        };
+        let Key::Version(_, mut version) = Key::decode(&latest)? else {
+            return errdata!("expected Key::Version got {latest:?}");
+        };
+        let tombstone = Self::latest_prefix_tombstone(session, key)?.filter(|t| *t > version);
+        if let Some(tombstone) = tombstone {
+            version = tombstone;
+        }
+        if !state.is_visible(version)
+            && (version > state.version
+                || session.get(&Key::TxnActive(version).encode())?.is_some())
+        {
+            return Err(Error::Serialization);
+        }
+        if tombstone.is_some() {
+            return Ok(None);
+        }
+        bincode::deserialize(&value)
+    }
+
+    /// Fetches all prefix tombstones, including uncommitted ones. There are
+    /// usually few of them, since GC removes them along with the versions they
+    /// delete once they're below the horizon.
+    fn scan_prefix_tombstones(session: &mut MutexGuard<E>) -> Result<Vec<PrefixTombstone>> {
+        let mut tombstones = Vec::new();
+        let mut scan = session.scan_prefix(&KeyPrefix::PrefixTombstone.encode());
+        while let Some((key, value)) = scan.next().transpose()? {
+            let Key::PrefixTombstone(version, prefix) = Key::decode(&key)? else {
+                return errdata!("expected Key::PrefixTombstone got {key:?}");
+            };
+            let savepoint = bincode::deserialize(&value)?;
+            tombstones.push(PrefixTombstone { prefix: prefix.into_owned(), version, savepoint });
+        }
+        Ok(tombstones)
+    }
+
+    /// Returns the latest version of a prefix tombstone covering the given key,
+    /// if any, whether or not it has committed.
+    fn latest_prefix_tombstone(session: &mut MutexGuard<E>, key: &[u8]) -> Result<Option<Version>> {
+        Ok(Self::scan_prefix_tombstones(session)?
+            .into_iter()
+            .filter(|tombstone| key.starts_with(&tombstone.prefix))
+            .map(|tombstone| tombstone.version)
+            .max())
+    }
+
+    /// Scans the commit log for up to limit commits with sequence numbers
+    /// after the given one, in commit order, only returning writes to keys
+    /// with the given prefix. Commits without such writes are skipped. Errors
+    /// if commits after the given sequence number have been trimmed by GC.
+    /// Unlike reads, this reflects the current state rather than the
+    /// transaction's snapshot.
+    pub fn scan_commits(&self, after: u64, limit: usize, prefix: &[u8]) -> Result<Vec<Commit>> {
+        let mut engine = self.engine.lock()?;
+        let first = engine.scan_prefix(&KeyPrefix::CommitLog.encode()).next().transpose()?;
+        let first = match first {
+            Some((_, value)) => Commit::decode(&value)?.seq,
+            None => match engine.get(&Key::NextCommitSeq.encode())? {
+                Some(ref v) => u64::decode(v)?,
+                None => 1,
+            },
+        };
+        if after.saturating_add(1) < first {
+            return errinput!("commits after {after} have been garbage collected");
+        }
+        let from = Key::CommitLog(after.saturating_add(1)).encode();
+        let range =
+            (Bound::Included(from), keycode::prefix_range(&KeyPrefix::CommitLog.encode()).1);
+        let mut commits = Vec::new();
+        let mut scan = engine.scan(range);
+        while commits.len() < limit {
+            let Some((_, value)) = scan.next().transpose()? else {
+                break;
+            };
+            let mut commit = Commit::decode(&value)?;
+            commit.writes.retain(|(key, _)| key.starts_with(prefix));
+            if !commit.writes.is_empty() {
+                commits.push(commit);
+            }
+        }
+        Ok(commits)
+    }
+
+    /// Lists the currently active read-write transactions, including this one.
+    /// Unlike reads, this reflects the current state rather than the
+    /// transaction's snapshot.
+    pub fn scan_transactions(&self) -> Result<Vec<TransactionInfo>> {
+        let mut engine = self.engine.lock()?;
+        let next = match engine.get(&Key::NextVersion.encode())? {
+            Some(ref v) => Version::decode(v)?,
+            None => 1,
+        };
+        let mut transactions = Vec::new();
+        for version in Self::scan_active(&mut engine)? {
+            let writes = engine
+                .scan_prefix(&KeyPrefix::TxnWrite(version).encode())
+                .try_fold(0, |n, r| r.map(|_| n + 1))?;
+            let locks = Self::scan_locks(&mut engine, version)?
+                .into_iter()
+                .filter(|(_, position)| position.is_none())
+                .count() as u64;
+            let waiting_for = match engine.get(&Key::TxnWaitsFor(version).encode())? {
+                Some(v) => Some(Version::decode(&v)?),
+                None => None,
+            };
+            let age = next - version;
+            transactions.push(TransactionInfo { version, age, writes, locks, waiting_for });
+        }
+        Ok(transactions)
+    }
+
+    /// Forcibly aborts a different active read-write transaction, e.g. one
+    /// that's stuck holding locks, by rolling back its writes. Its subsequent
+    /// writes and commit will error.
+    pub fn abort_transaction(&self, version: Version) -> Result<()> {
+        if self.state.read_only {
+            return Err(Error::ReadOnly);
+        }
+        if version == self.state.version {
+            return errinput!("can't abort the current transaction, use ROLLBACK");
+        }
+        if self.engine.lock()?.get(&Key::TxnActive(version).encode())?.is_none() {
+            return errinput!("no active transaction at version {version}");
+        }
+        let state =
+            TransactionState { version, read_only: false, active: BTreeSet::new(), snapshot: None };
+        Transaction { engine: self.engine.clone(), lock_waits: self.lock_waits.clone(), state }
+            .rollback()
+    }
+
+    /// Creates a savepoint with the given name. Rolling back to it undoes the
+    /// writes made since, while retaining earlier writes. Names don't have to
+    /// be unique: later savepoints shadow earlier ones with the same name.
+    pub fn savepoint(&self, name: &str) -> Result<()> {
+        if self.state.read_only {
+            return Err(Error::ReadOnly);
+        }
+        let mut engine = self.engine.lock()?;
+        self.check_active(&mut engine)?;
+        let seq = match engine
+            .scan_prefix(&KeyPrefix::TxnSavepoint(self.state.version).encode())
+            .next_back()
+            .transpose()?
+        {
+            Some((key, _)) => match Key::decode(&key)? {
+                Key::TxnSavepoint(_, seq) => seq + 1,
+                key => return errdata!("expected TxnSavepoint, got {key:?}"),
+            },
+            None => 0,
+        };
+        engine.set(&Key::TxnSavepoint(self.state.version, seq).encode(), bincode::serialize(&name))
+    }
+
+    /// Rolls back to the latest savepoint with the given name, by restoring the
+    /// previous values of keys written since it was created. The savepoint is
+    /// retained, but later savepoints are removed.
+    pub fn rollback_to_savepoint(&self, name: &str) -> Result<()> {
+        if self.state.read_only {
+            return Err(Error::ReadOnly);
+        }
+        let version = self.state.version;
+        let mut engine = self.engine.lock()?;
+        let savepoints = Self::scan_savepoints(&mut engine, version)?;
+        let Some(i) = savepoints.iter().rposition(|(_, n)| n == name) else {
+            return errinput!("savepoint {name} does not exist");
+        };
+        // Restore the savepoints' previous values in reverse order, such that
+        // the value as of the given savepoint is restored last.
+        for (seq, _) in savepoints[i..].iter().rev() {
+            for (key, value) in Self::scan_savepoint_writes(&mut engine, version, *seq)? {
+                match value {
+                    Some(value) => {
+                        engine.set(&Key::Version(key.as_slice().into(), version).encode(), value)?
+                    }
+                    None => {
+                        engine.delete(&Key::Version(key.as_slice().into(), version).encode())?;
+                        engine.delete(&Key::TxnWrite(version, key.as_slice().into()).encode())?;
+                    }
+                }
+                engine.delete(&Key::TxnSavepointWrite(version, *seq, key.into()).encode())?;
+            }
+            if *seq != savepoints[i].0 {
+                engine.delete(&Key::TxnSavepoint(version, *seq).encode())?;
+            }
+        }
+        // Remove prefix tombstones written since the savepoint.
+        for tombstone in Self::scan_prefix_tombstones(&mut engine)? {
+            if tombstone.version == version && tombstone.savepoint >= Some(savepoints[i].0) {
+                engine.delete(&tombstone.key())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Releases the latest savepoint with the given name, along with any later
+    /// savepoints. Their writes are retained, and can still be rolled back by
+    /// rolling back to an earlier savepoint.
+    pub fn release_savepoint(&self, name: &str) -> Result<()> {
+        if self.state.read_only {
+            return Err(Error::ReadOnly);
+        }
+        let version = self.state.version;
+        let mut engine = self.engine.lock()?;
+        let savepoints = Self::scan_savepoints(&mut engine, version)?;
+        let Some(i) = savepoints.iter().rposition(|(_, n)| n == name) else {
+            return errinput!("savepoint {name} does not exist");
+        };
+        // Move the previous values to the preceding savepoint, if any, unless
+        // it already has an older value for the key.
+        let preceding = i.checked_sub(1).map(|i| savepoints[i].0);
+        for (seq, _) in &savepoints[i..] {
+            for (key, value) in Self::scan_savepoint_writes(&mut engine, version, *seq)? {
+                if let Some(preceding) = preceding {
+                    let record =
+                        Key::TxnSavepointWrite(version, preceding, key.as_slice().into()).encode();
+                    if engine.get(&record)?.is_none() {
+                        engine.set(&record, bincode::serialize(&value))?;
+                    }
+                }
+                engine.delete(&Key::TxnSavepointWrite(version, *seq, key.into()).encode())?;
+            }
+            engine.delete(&Key::TxnSavepoint(version, *seq).encode())?;
+        }
+        // Move prefix tombstones written since the savepoint to the preceding
+        // savepoint, if any.
+        for tombstone in Self::scan_prefix_tombstones(&mut engine)? {
+            if tombstone.version == version && tombstone.savepoint >= Some(savepoints[i].0) {
+                engine.set(&tombstone.key(), bincode::serialize(&preceding))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Fetches the transaction's savepoints as (sequence number, name) pairs,
+    /// in creation order.
+    fn scan_savepoints(
+        session: &mut MutexGuard<E>,
+        version: Version,
+    ) -> Result<Vec<(u64, String)>> {
+        let mut savepoints = Vec::new();
+        let mut scan = session.scan_prefix(&KeyPrefix::TxnSavepoint(version).encode());
+        while let Some((key, value)) = scan.next().transpose()? {
+            match Key::decode(&key)? {
+                Key::TxnSavepoint(_, seq) => savepoints.push((seq, bincode::deserialize(&value)?)),
+                key => return errdata!("expected TxnSavepoint, got {key:?}"),
+            }
+        }
+        Ok(savepoints)
+    }
+
+    /// Fetches the keys written since a savepoint, along with their previous
+    /// values, if any.
+    fn scan_savepoint_writes(
+        session: &mut MutexGuard<E>,
+        version: Version,
+        seq: u64,
+    ) -> Result<Vec<(Vec<u8>, Option<Vec<u8>>)>> {
+        let mut writes = Vec::new();
+        let mut scan = session.scan_prefix(&KeyPrefix::TxnSavepointWrite(version, seq).encode());
+        while let Some((key, value)) = scan.next().transpose()? {
+            match Key::decode(&key)? {
+                Key::TxnSavepointWrite(_, _, key) => {
+                    writes.push((key.into_owned(), bincode::deserialize(&value)?))
+                }
+                key => return errdata!("expected TxnSavepointWrite, got {key:?}"),
+            }
+        }
+        Ok(writes)
+    }
+
+    /// Records the transaction's writes under its captured key prefixes (see
+    /// capture()) in the commit log, if any, and removes the TxnCapture
+    /// records. Called when committing. Unlike writes, bulk writes are only
+    /// recorded as key ranges, so these are scanned for the transaction's
+    /// versions.
+    fn log_commit(&self, session: &mut MutexGuard<E>) -> Result<()> {
+        let version = self.state.version;
+        let mut captures = Vec::new();
+        let mut prefixes = Vec::new();
+        let mut scan = session.scan_prefix(&KeyPrefix::TxnCapture(version).encode());
+        while let Some((key, _)) = scan.next().transpose()? {
+            match Key::decode(&key)? {
+                Key::TxnCapture(_, prefix) => prefixes.push(prefix.into_owned()),
+                key => return errdata!("expected TxnCapture, got {key:?}"),
+            }
+            captures.push(key);
+        }
+        drop(scan);
+        if captures.is_empty() {
+            return Ok(());
+        }
+        let captured = |key: &[u8]| prefixes.iter().any(|prefix| key.starts_with(prefix));
+
+        // Find the captured versions written by the transaction. Writes may
+        // have been undone by savepoint rollbacks, in which case the write
+        // record remains but the version doesn't.
+        let mut keys = Vec::new();
+        let mut ranges = Vec::new();
+        let mut scan = session.scan_prefix(&KeyPrefix::TxnWrite(version).encode());
+        while let Some((key, _)) = scan.next().transpose()? {
+            match Key::decode(&key)? {
+                Key::TxnWrite(_, key) if captured(&key) => keys.push(key.into_owned()),
+                Key::TxnWrite(..) => {}
+                key => return errdata!("expected TxnWrite, got {key:?}"),
+            }
+        }
+        drop(scan);
+        let mut scan = session.scan_prefix(&KeyPrefix::TxnBulkWrite(version).encode());
+        while let Some((key, to)) = scan.next().transpose()? {
+            match Key::decode(&key)? {
+                Key::TxnBulkWrite(_, from) => ranges.push((from.into_owned(), to)),
+                key => return errdata!("expected TxnBulkWrite, got {key:?}"),
+            }
+        }
+        drop(scan);
+        let mut writes = BTreeMap::new();
+        for key in keys {
+            if let Some(value) = session.get(&Key::Version((&key).into(), version).encode())? {
+                writes.insert(key, bincode::deserialize(&value)?);
+            }
+        }
+        for range in ranges {
+            let mut scan = session.scan(Self::version_range(range.0..=range.1));
+            while let Some((key, value)) = scan.next().transpose()? {
+                match Key::decode(&key)? {
+                    Key::Version(key, v) if v == version && captured(&key) => {
+                        writes.insert(key.into_owned(), bincode::deserialize(&value)?);
+                    }
+                    Key::Version(..) => {}
+                    key => return errdata!("expected Key::Version got {key:?}"),
+                }
+            }
+        }
 
-    /// Fetches the set of currently active transactions.
-    fn scan_active(session: &mut MutexGuard<E>) -> Result<BTreeSet<Version>> {
-        let mut active = BTreeSet::new();
-        let mut scan = session.scan_prefix(&KeyPrefix::TxnActive.encode());
-        while let Some((key, _)) = scan.next().transpose()? {
-            match Key::decode(&key)? {
-                Key::TxnActive(version) => active.insert(version),
-                key => return errdata!("expected TxnActive key, got {key:?}"),
+        if !writes.is_empty() {
+            let seq = match session.get(&Key::NextCommitSeq.encode())? {
+                Some(ref v) => u64::decode(v)?,
+                None => 1,
             };
+            session.set(&Key::NextCommitSeq.encode(), (seq + 1).encode())?;
+            let commit = Commit { seq, version, writes: writes.into_iter().collect() };
+            session.set(&Key::CommitLog(seq).encode(), commit.encode())?;
         }
-        Ok(active)
-    }
-
-    /// Returns the version the transaction is running at.
-    pub fn version(&self) -> Version {
-        self.state.version
+        for key in captures {
+            session.delete(&key)?;
+        }
+        Ok(())
     }
 
-    /// Returns whether the transaction is read-only.
-    pub fn read_only(&self) -> bool {
-        self.state.read_only
+    /// Removes all of the transaction's savepoints and their write records.
+    fn remove_savepoints(session: &mut MutexGuard<E>, version: Version) -> Result<()> {
+        for (seq, _) in Self::scan_savepoints(session, version)? {
+            for (key, _) in Self::scan_savepoint_writes(session, version, seq)? {
+                session.delete(&Key::TxnSavepointWrite(version, seq, key.into()).encode())?;
+            }
+            session.delete(&Key::TxnSavepoint(version, seq).encode())?;
+        }
+        Ok(())
     }
 
-    /// Returns the transaction's state. This can be used to instantiate a
-    /// functionally equivalent transaction via resume().
-    pub fn state(&self) -> &TransactionState {
-        &self.state
+    /// Deletes a key.
+    pub fn delete(&self, key: &[u8]) -> Result<()> {
+        self.write_version(key, None)
     }
 
-    /// Commits the transaction, by removing it from the active set. This will
-    /// immediately make its writes visible to subsequent transactions. Also
-    /// removes its TxnWrite records, which are no longer needed.
-    ///
-    /// NB: commit does not flush writes to durable storage, since we rely on
-    /// the Raft log for persistence.
-    pub fn commit(self) -> Result<()> {
+    /// Deletes all keys under the given prefix, returning the number of live
+    /// keys deleted. Rather than writing a tombstone for each key, this writes
+    /// a single Key::PrefixTombstone, e.g. to truncate a table, which deletes
+    /// all versions of the keys below the transaction's version. Older
+    /// snapshots still see the keys, and GC removes them once the tombstone is
+    /// below the horizon. The keys are still scanned to count them and check
+    /// for write conflicts. Errors if the prefix is captured in the commit log
+    /// (see capture()), since only individual writes are recorded there.
+    pub fn delete_prefix(&self, prefix: &[u8]) -> Result<u64> {
         if self.state.read_only {
-            return Ok(());
-        }
-        let mut engine = self.engine.lock()?;
-        let remove: Vec<_> = engine
-            .scan_prefix(&KeyPrefix::TxnWrite(self.state.version).encode())
-            .map_ok(|(k, _)| k)
-            .try_collect()?;
-        for key in remove {
-            engine.delete(&key)?
+            return Err(Error::ReadOnly);
         }
-        engine.delete(&Key::TxnActive(self.state.version).encode())
-    }
+        let count = self.scan_prefix(prefix).try_fold(0, |n, r| r.map(|_| n + 1))?;
 
-    /// Rolls back the transaction, by undoing all written versions and removing
-    /// it from the active set. The active set snapshot is left behind, since
-    /// this is needed for time travel queries at this version.
-    pub fn rollback(self) -> Result<()> {
-        if self.state.read_only {
-            return Ok(());
-        }
         let mut engine = self.engine.lock()?;
-        let mut rollback = Vec::new();
-        let mut scan = engine.scan_prefix(&KeyPrefix::TxnWrite(self.state.version).encode());
+        self.check_active(&mut engine)?;
+        let overlaps = |other: &[u8]| other.starts_with(prefix) || prefix.starts_with(other);
+        let mut scan = engine.scan_prefix(&KeyPrefix::TxnCapture(self.state.version).encode());
         while let Some((key, _)) = scan.next().transpose()? {
             match Key::decode(&key)? {
-                Key::TxnWrite(_, key) => {
-                    rollback.push(Key::Version(key, self.state.version).encode())
-                    // the version
+                Key::TxnCapture(_, captured) if overlaps(&captured) => {
+                    return errinput!("can't delete a prefix captured in the commit log");
                 }
-                key => return errdata!("expected TxnWrite, got {key:?}"),
+                Key::TxnCapture(..) => {}
+                key => return errdata!("expected TxnCapture, got {key:?}"),
+            }
+        }
+        drop(scan);
+
+        // Check for write conflicts like write_version(), i.e. versions,
+        // prefix tombstones, or locks under the prefix that were written by
+        // other transactions and are invisible to us or newer than us. Our own
+        // live versions aren't deleted by the prefix tombstone, so they're
+        // deleted separately. The engine doesn't support writing while
+        // scanning, so buffer them.
+        let version = self.state.version;
+        let conflicts = |v: Version| v != version && (!self.state.is_visible(v) || v > version);
+        let mut conflict = None;
+        let mut own = Vec::new();
+        let mut scan = engine.scan(Self::prefix_version_range(prefix));
+        while let Some((key, value)) = scan.next().transpose()? {
+            let Key::Version(key, v) = Key::decode(&key)? else {
+                return errdata!("expected Key::Version got {key:?}");
             };
-            rollback.push(key); // the TxnWrite record
+            if conflicts(v) {
+                conflict = Some(v);
+                break;
+            } else if v == version && bincode::deserialize::<Option<Vec<u8>>>(&value)?.is_some() {
+                own.push(key.into_owned());
+            }
         }
         drop(scan);
-        for key in rollback.into_iter() {
-            engine.delete(&key)?;
+        let tombstones = Self::scan_prefix_tombstones(&mut engine)?;
+        conflict = conflict.or_else(|| {
+            tombstones
+                .iter()
+                .find(|tombstone| overlaps(&tombstone.prefix) && conflicts(tombstone.version))
+                .map(|tombstone| tombstone.version)
+        });
+        let mut locks = Key::Lock(prefix.into()).encode();
+        locks.truncate(locks.len() - 2);
+        let mut scan = engine.scan(keycode::prefix_range(&locks));
+        while let Some((_, holder)) = scan.next().transpose()? {
+            let holder = Version::decode(&holder)?;
+            if holder != version {
+                conflict = conflict.or(Some(holder));
+                break;
+            }
+        }
+        drop(scan);
+        if let Some(conflict) = conflict {
+            if Self::resolve_expired(&mut engine, &self.lock_waits, conflict)? {
+                drop(engine);
+                return self.delete_prefix(prefix);
+            }
+            return Err(Error::Serialization);
         }
-        engine.delete(&Key::TxnActive(self.state.version).encode()) // remove from active set
-    }
 
-    /// Deletes a key.
-    pub fn delete(&self, key: &[u8]) -> Result<()> {
-        self.write_version(key, None)
+        // Write the prefix tombstone, recording the latest savepoint in case
+        // we roll back to it. If we've already deleted the prefix, keep the
+        // original savepoint.
+        let key = Key::PrefixTombstone(version, prefix.into()).encode();
+        if engine.get(&key)?.is_none() {
+            let savepoint = match engine
+                .scan_prefix(&KeyPrefix::TxnSavepoint(version).encode())
+                .next_back()
+                .transpose()?
+            {
+                Some((key, _)) => match Key::decode(&key)? {
+                    Key::TxnSavepoint(_, seq) => Some(seq),
+                    key => return errdata!("expected TxnSavepoint, got {key:?}"),
+                },
+                None => None,
+            };
+            engine.set(&key, bincode::serialize(&savepoint))?;
+        }
+        drop(engine);
+        for key in own {
+            self.delete(&key)?;
+        }
+        Ok(count)
     }
 
     /// Sets a value for a key.
@@ -530,25 +2235,72 @@ impl<E: Engine> Transaction<E> {
             return Err(Error::ReadOnly);
         }
         let mut engine = self.engine.lock()?;
+        self.check_active(&mut engine)?;
+
+        // Writes to keys locked by other active transactions conflict, unless
+        // the lock holder has expired, in which case it's rolled back and the
+        // write is retried. Likewise for uncommitted versions below.
+        let holder = Self::lock_holder(&mut engine, key)?;
+        if let Some(holder) = holder.filter(|h| *h != self.state.version) {
+            if Self::resolve_expired(&mut engine, &self.lock_waits, holder)? {
+                drop(engine);
+                return self.write_version(key, value);
+            }
+            return Err(Error::Serialization);
+        }
+        let locked = holder == Some(self.state.version);
 
         // Check for write conflicts, i.e. if the latest key is invisible to us
-        // (either a newer version, or an uncommitted version in our past). We
+        // (either a newer version, or an uncommitted version in our past), or
+        // is a newer version that's visible to a read committed snapshot. We
         // can only conflict with the latest key, since all transactions enforce
-        // the same invariant.
-        let from = Key::Version(
-            key.into(),
-            self.state.active.iter().min().copied().unwrap_or(self.state.version + 1),
-        )
-        .encode();
+        // the same invariant. If we hold the key's lock, we've read its latest
+        // version, and can write over versions that were committed since our
+        // snapshot (lock() ensures they're older than us).
+        // Prefix tombstones count as versions of the key.
+        let next = self.state.version + 1;
+        let oldest = self.state.active.first().map_or(next, |v| next.min(*v));
+        let from = Key::Version(key.into(), oldest).encode();
         let to = Key::Version(key.into(), u64::MAX).encode();
-        if let Some((key, _)) = engine.scan(from..=to).last().transpose()? {
-            match Key::decode(&key)? {
-                Key::Version(_, version) => {
-                    if !self.state.is_visible(version) {
-                        return Err(Error::Serialization);
+        let latest = match engine.scan(from..=to).last().transpose()? {
+            Some((latest, _)) => match Key::decode(&latest)? {
+                Key::Version(_, version) => Some(version),
+                key => return errdata!("expected Key::Version got {key:?}"),
+            },
+            None => None,
+        };
+        let tombstone = Self::latest_prefix_tombstone(&mut engine, key)?.filter(|v| *v >= oldest);
+        match latest.max(tombstone) {
+            Some(version) if self.state.is_visible(version) && version <= self.state.version => {}
+            Some(version) => {
+                let committed = version < self.state.version
+                    && engine.get(&Key::TxnActive(version).encode())?.is_none();
+                if !locked || !committed {
+                    if Self::resolve_expired(&mut engine, &self.lock_waits, version)? {
+                        drop(engine);
+                        return self.write_version(key, value);
                     }
+                    return Err(Error::Serialization);
                 }
-                key => return errdata!("expected Key::Version got {key:?}"),
+            }
+            None => {}
+        }
+
+        // If the transaction has a savepoint, record the key's previous value
+        // the first time it's written since the latest savepoint.
+        let savepoint = engine
+            .scan_prefix(&KeyPrefix::TxnSavepoint(self.state.version).encode())
+            .next_back()
+            .transpose()?;
+        if let Some((savepoint, _)) = savepoint {
+            let Key::TxnSavepoint(_, seq) = Key::decode(&savepoint)? else {
+                return errdata!("expected TxnSavepoint, got {savepoint:?}");
+            };
+            let record = Key::TxnSavepointWrite(self.state.version, seq, key.into()).encode();
+            if engine.get(&record)?.is_none() {
+                let previous =
+                    engine.get(&Key::Version(key.into(), self.state.version).encode())?;
+                engine.set(&record, bincode::serialize(&previous))?;
             }
         }
 
@@ -561,16 +2313,90 @@ impl<E: Engine> Transaction<E> {
             .set(&Key::Version(key.into(), self.state.version).encode(), bincode::serialize(&value))
     }
 
+    /// Writes a batch of new keys, e.g. for bulk loads. The keys must be sorted
+    /// and unique, and no versions of any keys in the range they span may
+    /// exist (including deleted and uncommitted versions). Unlike set(), this
+    /// skips per-key conflict checks and write records: the batch is checked
+    /// with a single scan of its key range, and recorded as a single
+    /// TxnBulkWrite range for rollbacks. Later writes by other transactions in
+    /// the range conflict as usual. Errors if the transaction has savepoints,
+    /// which can't roll back bulk writes.
+    pub fn set_bulk(&self, writes: Vec<(Vec<u8>, Vec<u8>)>) -> Result<()> {
+        if self.state.read_only {
+            return Err(Error::ReadOnly);
+        }
+        let (Some((first, _)), Some((last, _))) = (writes.first(), writes.last()) else {
+            return Ok(());
+        };
+        if !writes.windows(2).all(|w| w[0].0 < w[1].0) {
+            return errinput!("bulk write keys must be sorted and unique");
+        }
+        let mut engine = self.engine.lock()?;
+        self.check_active(&mut engine)?;
+        if engine
+            .scan_prefix(&KeyPrefix::TxnSavepoint(self.state.version).encode())
+            .next()
+            .transpose()?
+            .is_some()
+        {
+            return errinput!("can't bulk write in a transaction with savepoints");
+        }
+        let range = first.clone()..=last.clone();
+        if engine.scan(Self::version_range(range.clone())).next().transpose()?.is_some() {
+            return errinput!("bulk write overlaps existing keys");
+        }
+        let locks = Key::Lock(first.into()).encode()..=Key::Lock(last.into()).encode();
+        if engine.scan(locks).next().transpose()?.is_some() {
+            return Err(Error::Serialization);
+        }
+        let conflict = |tombstone: &PrefixTombstone| {
+            !self.state.is_visible(tombstone.version) || tombstone.version > self.state.version
+        };
+        let tombstones = Self::scan_prefix_tombstones(&mut engine)?;
+        if tombstones.iter().any(|tombstone| tombstone.overlaps(&range) && conflict(tombstone)) {
+            return Err(Error::Serialization);
+        }
+
+        let (from, to) = range.into_inner();
+        engine.set(&Key::TxnBulkWrite(self.state.version, from.into()).encode(), to)?;
+        for (key, value) in writes {
+            let value = bincode::serialize(&Some(value));
+            engine.set(&Key::Version(key.into(), self.state.version).encode(), value)?;
+        }
+        Ok(())
+    }
+
+    /// Records the transaction's writes to keys with the given prefix in the
+    /// commit log when it commits, including writes made before this call.
+    /// Used for change data capture. See the module documentation for details.
+    pub fn capture(&self, prefix: &[u8]) -> Result<()> {
+        if self.state.read_only {
+            return Err(Error::ReadOnly);
+        }
+        let mut engine = self.engine.lock()?;
+        self.check_active(&mut engine)?;
+        engine.set(&Key::TxnCapture(self.state.version, prefix.into()).encode(), vec![])
+    }
+
     /// Fetches a key's value, or None if it does not exist.
     pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
         let mut engine = self.engine.lock()?;
+        self.state.check_gc(&mut *engine)?;
+        // Keys locked by the transaction are read at their latest version.
+        if !self.state.read_only && Self::lock_holder(&mut engine, key)? == Some(self.state.version)
+        {
+            return Self::get_locked(&mut engine, &self.state, key);
+        }
+        let tombstones = Self::scan_prefix_tombstones(&mut engine)?;
         let from = Key::Version(key.into(), 0).encode();
-        let to = Key::Version(key.into(), self.state.version).encode();
-        let mut scan = engine.scan(from..=to).rev();
+        let to = Key::Version(key.into(), self.state.snapshot.unwrap_or(self.state.version));
+        let mut scan = engine.scan(from..=to.encode()).rev();
         while let Some((key, value)) = scan.next().transpose()? {
             match Key::decode(&key)? {
-                Key::Version(_, version) => {
-                    if self.state.is_visible(version) {
+                Key::Version(key, version) => {
+                    if self.state.is_deleted(&tombstones, &key, version) {
+                        return Ok(None);
+                    } else if self.state.is_visible(version) {
                         return bincode::deserialize(&value);
                     }
                 }
@@ -583,6 +2409,70 @@ impl<E: Engine> Transaction<E> {
     /// Returns an iterator over the latest visible key/value pairs at the
     /// transaction's version.
     pub fn scan(&self, range: impl RangeBounds<Vec<u8>>) -> ScanIterator<E> {
+        ScanIterator::new(self.engine.clone(), self.state().clone(), Self::version_range(range))
+    }
+
+    /// Returns the last live and visible key/value pair in the given range, if
+    /// any. ScanIterator doesn't support reverse scans, so this is provided
+    /// separately, e.g. to find a table's largest primary key.
+    pub fn scan_last(
+        &self,
+        range: impl RangeBounds<Vec<u8>>,
+    ) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
+        let mut engine = self.engine.lock()?;
+        self.state.check_gc(&mut *engine)?;
+        let tombstones = Self::scan_prefix_tombstones(&mut engine)?;
+        let scan = engine.scan(Self::version_range(range)).rev();
+        // In reverse, the first visible version of each key is the latest one.
+        // If it's a tombstone, skip the key's remaining versions.
+        let mut deleted = None;
+        for result in VersionIterator::new(&self.state, scan) {
+            let (key, version, value) = result?;
+            if deleted.as_ref() == Some(&key) {
+                continue;
+            }
+            match bincode::deserialize(&value)? {
+                Some(_) if self.state.is_deleted(&tombstones, &key, version) => deleted = Some(key),
+                Some(value) => return Ok(Some((key, value))),
+                None => deleted = Some(key),
+            }
+        }
+        Ok(None)
+    }
+
+    /// Validates a read of the given key range for serializable isolation, by
+    /// erroring with Error::Serialization if any key in the range has a version
+    /// written by a concurrent transaction (i.e. one that's invisible to us),
+    /// whether or not it has committed. Such a version is a read-write
+    /// antidependency from this transaction to the writer, since we didn't see
+    /// its write. See the ISOLATION section in the module docs.
+    pub fn check_read(&self, range: impl RangeBounds<Vec<u8>>) -> Result<()> {
+        let mut engine = self.engine.lock()?;
+        for tombstone in Self::scan_prefix_tombstones(&mut engine)? {
+            if tombstone.version != self.state.version
+                && !self.state.is_visible(tombstone.version)
+                && tombstone.overlaps(&range)
+            {
+                return Err(Error::Serialization);
+            }
+        }
+        for result in engine.scan(Self::version_range(range)) {
+            let (key, _) = result?;
+            match Key::decode(&key)? {
+                Key::Version(_, version) if version == self.state.version => {}
+                Key::Version(_, version) if !self.state.is_visible(version) => {
+                    return Err(Error::Serialization);
+                }
+                Key::Version(..) => {}
+                key => return errdata!("expected Key::Version got {key:?}"),
+            }
+        }
+        Ok(())
+    }
+
+    /// Converts a user key range into a Key::Version engine key range spanning
+    /// all versions of the keys.
+    fn version_range(range: impl RangeBounds<Vec<u8>>) -> (Bound<Vec<u8>>, Bound<Vec<u8>>) {
         let start = match range.start_bound() {
             Bound::Excluded(k) => Bound::Excluded(Key::Version(k.into(), u64::MAX).encode()),
             Bound::Included(k) => Bound::Included(Key::Version(k.into(), 0).encode()),
@@ -593,18 +2483,24 @@ impl<E: Engine> Transaction<E> {
             Bound::Included(k) => Bound::Included(Key::Version(k.into(), u64::MAX).encode()),
             Bound::Unbounded => Bound::Excluded(KeyPrefix::Unversioned.encode()),
         };
-        ScanIterator::new(self.engine.clone(), self.state().clone(), (start, end))
+        (start, end)
     }
 
     /// Scans keys under a given prefix.
     pub fn scan_prefix(&self, prefix: &[u8]) -> ScanIterator<E> {
+        let range = Self::prefix_version_range(prefix);
+        ScanIterator::new(self.engine.clone(), self.state().clone(), range)
+    }
+
+    /// Converts a user key prefix into a Key::Version engine key range spanning
+    /// all versions of keys with the prefix.
+    fn prefix_version_range(prefix: &[u8]) -> (Bound<Vec<u8>>, Bound<Vec<u8>>) {
         // Normally, KeyPrefix::Version will only match all versions of the
         // exact given key. We want all keys maching the prefix, so we chop off
         // the Keycode byte slice terminator 0x0000 at the end.
         let mut prefix = KeyPrefix::Version(prefix.into()).encode();
         prefix.truncate(prefix.len() - 2);
-        let range = keycode::prefix_range(&prefix);
-        ScanIterator::new(self.engine.clone(), self.state().clone(), range)
+        keycode::prefix_range(&prefix)
     }
 }
 
@@ -669,8 +2565,10 @@ impl<E: Engine> ScanIterator<E> {
         let range_end = range.1.clone();
 
         let mut engine = self.engine.lock()?;
+        self.txn.check_gc(&mut *engine)?;
+        let tombstones = Transaction::scan_prefix_tombstones(&mut engine)?;
         let mut iter = VersionIterator::new(&self.txn, engine.scan(range)).peekable();
-        while let Some((key, _, value)) = iter.next().transpose()? {
+        while let Some((key, version, value)) = iter.next().transpose()? {
             // If the next key equals this one, we're not at the latest version.
             match iter.peek() {
                 Some(Ok((next, _, _))) if next == &key => continue,
@@ -679,6 +2577,9 @@ impl<E: Engine> ScanIterator<E> {
             }
 
             // Decode the value, and skip deleted keys (tombstones).
+            if self.txn.is_deleted(&tombstones, &key, version) {
+                continue;
+            }
             let Some(value) = bincode::deserialize(&value)? else { continue };
             self.buffer.push_back((key, value));
 
@@ -691,7 +2592,24 @@ impl<E: Engine> ScanIterator<E> {
                     let range_start = Bound::Included(Key::Version(next.into(), version).encode());
                     self.remainder = Some((range_start, range_end));
                 }
-                return Ok(());
+                break;
+            }
+        }
+        drop(iter);
+
+        // Keys locked by the transaction are read at their latest version. The
+        // lock was acquired for a visible key, so we only have to replace or
+        // remove buffered keys.
+        if !self.txn.read_only
+            && engine.scan_prefix(&KeyPrefix::TxnLock(self.txn.version).encode()).next().is_some()
+        {
+            let buffer = std::mem::take(&mut self.buffer);
+            for (key, value) in buffer {
+                if Transaction::lock_holder(&mut engine, &key)? != Some(self.txn.version) {
+                    self.buffer.push_back((key, value));
+                } else if let Some(value) = Transaction::get_locked(&mut engine, &self.txn, &key)? {
+                    self.buffer.push_back((key, value));
+                }
             }
         }
         Ok(())
@@ -702,7 +2620,9 @@ impl<E: Engine> Iterator for ScanIterator<E> {
     type Item = Result<(Vec<u8>, Vec<u8>)>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.buffer.is_empty() {
+        // Locked keys may be removed from the buffer, so keep filling it until
+        // it's non-empty or the range is exhausted.
+        while self.buffer.is_empty() && self.remainder.is_some() {
             if let Err(error) = self.fill_buffer() {
                 return Some(Err(error));
             }
@@ -782,12 +2702,44 @@ pub mod tests {
     #[test_case(KeyPrefix::TxnWrite(1), Key::TxnWrite(1, b"foo".as_slice().into()); "TxnWrite")]
     #[test_case(KeyPrefix::Version(b"foo".as_slice().into()), Key::Version(b"foo".as_slice().into(), 1); "Version")]
     #[test_case(KeyPrefix::Unversioned, Key::Unversioned(b"foo".as_slice().into()); "Unversioned")]
+    #[test_case(KeyPrefix::TxnExpires, Key::TxnExpires(1); "TxnExpires")]
+    #[test_case(KeyPrefix::PrefixTombstone, Key::PrefixTombstone(1, b"foo".as_slice().into()); "PrefixTombstone")]
     fn key_prefix(prefix: KeyPrefix, key: Key) {
         let prefix = prefix.encode();
         let key = key.encode();
         assert_eq!(prefix, key[..prefix.len()])
     }
 
+    /// Tests that lock waiters are woken up when the blocking transaction
+    /// completes, rather than waiting for the timeout.
+    #[test]
+    fn lock_waits_wakeup() -> Result<(), Box<dyn Error>> {
+        let mvcc = MVCC::new(Memory::new());
+        let t1 = mvcc.begin()?;
+        let t2 = mvcc.begin()?;
+        let lock_waits = mvcc.lock_waits();
+
+        assert_eq!(t1.lock(b"key")?, None);
+        let seq = lock_waits.seq()?;
+        assert_eq!(t2.lock(b"key")?, Some(t1.version()));
+
+        // Waiting for a different transaction times out.
+        assert!(!lock_waits.wait(t2.version(), seq, Duration::ZERO)?);
+
+        // Committing t1 wakes up t2, which can then acquire the lock.
+        let version = t1.version();
+        let commit = std::thread::spawn(move || t1.commit());
+        assert!(lock_waits.wait(version, seq, Duration::from_secs(3600))?);
+        commit.join().expect("commit panicked")?;
+        assert_eq!(t2.lock(b"key")?, None);
+
+        // Notifications before the wait aren't missed.
+        let version = t2.version();
+        t2.commit()?;
+        assert!(lock_waits.wait(version, seq, Duration::ZERO)?);
+        Ok(())
+    }
+
     /// Runs MVCC goldenscript tests.
     pub struct MVCCRunner {
         mvcc: MVCC<TestEngine>,
@@ -840,7 +2792,7 @@ pub mod tests {
             let mut tags = command.tags.clone();
 
             match command.name.as_str() {
-                // txn: begin [readonly] [as_of=VERSION]
+                // txn: begin [readonly] [as_of=VERSION] [timestamp=TIMESTAMP]
                 "begin" => {
                     let name = Self::txn_name(&command.prefix)?;
                     if self.txns.contains_key(name) {
@@ -853,22 +2805,85 @@ pub mod tests {
                         Some(v) => return Err(format!("invalid argument {v}").into()),
                     };
                     let as_of = args.lookup_parse("as_of")?;
+                    let timestamp = args.lookup_parse("timestamp")?;
                     args.reject_rest()?;
-                    let txn = match (readonly, as_of) {
-                        (false, None) => self.mvcc.begin()?,
-                        (true, None) => self.mvcc.begin_read_only()?,
-                        (true, Some(v)) => self.mvcc.begin_as_of(v)?,
-                        (false, Some(_)) => return Err("as_of only valid for read-only txn".into()),
+                    let txn = match (readonly, as_of, timestamp) {
+                        (false, None, None) => self.mvcc.begin()?,
+                        (false, None, Some(t)) => self.mvcc.begin_at(t)?,
+                        (true, None, None) => self.mvcc.begin_read_only()?,
+                        (true, Some(v), None) => self.mvcc.begin_as_of(v)?,
+                        (true, None, Some(t)) => self.mvcc.begin_as_of_timestamp(t)?,
+                        (false, Some(_), _) => {
+                            return Err("as_of only valid for read-only txn".into());
+                        }
+                        (true, Some(_), Some(_)) => {
+                            return Err("can't use both as_of and timestamp".into());
+                        }
                     };
                     self.txns.insert(name.to_string(), txn);
                 }
 
-                // txn: commit
+                // txn: abort VERSION
+                "abort" => {
+                    let txn = self.get_txn(&command.prefix)?;
+                    let mut args = command.consume_args();
+                    let version = args.next_pos().ok_or("version not given")?.parse()?;
+                    args.reject_rest()?;
+                    txn.abort_transaction(version)?;
+                }
+
+                // txn: cancel_lock_waits
+                "cancel_lock_waits" => {
+                    let txn = self.get_txn(&command.prefix)?;
+                    command.consume_args().reject_rest()?;
+                    txn.cancel_lock_waits()?;
+                }
+
+                // txn: capture PREFIX
+                "capture" => {
+                    let txn = self.get_txn(&command.prefix)?;
+                    let mut args = command.consume_args();
+                    let prefix = decode_binary(&args.next_pos().ok_or("prefix not given")?.value);
+                    args.reject_rest()?;
+                    txn.capture(&prefix)?;
+                }
+
+                // txn: check_read [RANGE]
+                "check_read" => {
+                    let txn = self.get_txn(&command.prefix)?;
+                    let mut args = command.consume_args();
+                    let range =
+                        parse_key_range(args.next_pos().map(|a| a.value.as_str()).unwrap_or(".."))?;
+                    args.reject_rest()?;
+                    txn.check_read(range)?;
+                }
+
+                // txn: commit [timestamp=TIMESTAMP]
                 "commit" => {
                     let name = Self::txn_name(&command.prefix)?;
                     let txn = self.txns.remove(name).ok_or(format!("unknown txn {name}"))?;
-                    command.consume_args().reject_rest()?;
-                    txn.commit()?;
+                    let mut args = command.consume_args();
+                    let timestamp = args.lookup_parse("timestamp")?;
+                    args.reject_rest()?;
+                    match timestamp {
+                        Some(timestamp) => txn.commit_at(timestamp)?,
+                        None => txn.commit()?,
+                    }
+                }
+
+                // txn: commits [AFTER] [limit=N] [prefix=PREFIX]
+                "commits" => {
+                    let txn = self.get_txn(&command.prefix)?;
+                    let mut args = command.consume_args();
+                    let after = args.next_pos().map(|a| a.parse()).transpose()?.unwrap_or(0);
+                    let limit = args.lookup_parse("limit")?.unwrap_or(usize::MAX);
+                    let prefix =
+                        args.lookup("prefix").map(|a| decode_binary(&a.value)).unwrap_or_default();
+                    args.reject_rest()?;
+                    for commit in txn.scan_commits(after, limit, &prefix)? {
+                        let fmt = format::MVCC::<format::Raw>::commit(&commit);
+                        writeln!(output, "{}: {fmt}", commit.seq)?;
+                    }
                 }
 
                 // txn: delete KEY...
@@ -882,6 +2897,16 @@ pub mod tests {
                     args.reject_rest()?;
                 }
 
+                // txn: delete_prefix PREFIX
+                "delete_prefix" => {
+                    let txn = self.get_txn(&command.prefix)?;
+                    let mut args = command.consume_args();
+                    let prefix = decode_binary(&args.next_pos().ok_or("prefix not given")?.value);
+                    args.reject_rest()?;
+                    let count = txn.delete_prefix(&prefix)?;
+                    writeln!(output, "{count}")?;
+                }
+
                 // dump
                 "dump" => {
                     command.consume_args().reject_rest()?;
@@ -894,6 +2919,16 @@ pub mod tests {
                     }
                 }
 
+                // gc [RETENTION]
+                "gc" => {
+                    Self::no_txn(command)?;
+                    let mut args = command.consume_args();
+                    let retention = args.next_pos().map(|a| a.parse()).transpose()?.unwrap_or(0);
+                    args.reject_rest()?;
+                    let gc = self.mvcc.gc(retention)?;
+                    writeln!(output, "horizon={} versions={}", gc.horizon, gc.versions)?;
+                }
+
                 // txn: get KEY...
                 "get" => {
                     let txn = self.get_txn(&command.prefix)?;
@@ -924,7 +2959,8 @@ pub mod tests {
                 "import" => {
                     Self::no_txn(command)?;
                     let mut args = command.consume_args();
-                    let version = args.next_pos().map(|a| a.parse()).transpose()?;
+                    let version: Option<Version> =
+                        args.next_pos().map(|a| a.parse()).transpose()?;
                     let mut txn = self.mvcc.begin()?;
                     if let Some(version) = version {
                         if txn.version() > version {
@@ -947,6 +2983,48 @@ pub mod tests {
                     txn.commit()?;
                 }
 
+                // txn: heartbeat TIMESTAMP TIMEOUT
+                "heartbeat" => {
+                    let txn = self.get_txn(&command.prefix)?;
+                    let mut args = command.consume_args();
+                    let timestamp = args.next_pos().ok_or("timestamp not given")?.parse()?;
+                    let timeout = args.next_pos().ok_or("timeout not given")?.parse()?;
+                    args.reject_rest()?;
+                    txn.heartbeat(timestamp, Duration::from_nanos(timeout))?;
+                }
+
+                // txn: lock KEY...
+                "lock" => {
+                    let txn = self.get_txn(&command.prefix)?;
+                    let mut args = command.consume_args();
+                    for arg in args.rest_pos() {
+                        let key = decode_binary(&arg.value);
+                        match txn.lock(&key)? {
+                            None => writeln!(output, "{} → locked", format::Raw::key(&key))?,
+                            Some(v) => {
+                                writeln!(output, "{} → waiting for {v}", format::Raw::key(&key))?
+                            }
+                        }
+                    }
+                    args.reject_rest()?;
+                }
+
+                // txn: release NAME
+                "release" => {
+                    let txn = self.get_txn(&command.prefix)?;
+                    let mut args = command.consume_args();
+                    let name = &args.next_pos().ok_or("name not given")?.value;
+                    args.reject_rest()?;
+                    txn.release_savepoint(name)?;
+                }
+
+                // txn: refresh_snapshot
+                "refresh_snapshot" => {
+                    let txn = self.get_txn(&command.prefix)?;
+                    command.consume_args().reject_rest()?;
+                    txn.refresh_snapshot()?;
+                }
+
                 // txn: resume JSON
                 "resume" => {
                     let name = Self::txn_name(&command.prefix)?;
@@ -966,6 +3044,42 @@ pub mod tests {
                     txn.rollback()?;
                 }
 
+                // resolve_expired TIMESTAMP
+                "resolve_expired" => {
+                    Self::no_txn(command)?;
+                    let mut args = command.consume_args();
+                    let timestamp = args.next_pos().ok_or("timestamp not given")?.parse()?;
+                    args.reject_rest()?;
+                    let versions = self.mvcc.resolve_expired(timestamp)?;
+                    writeln!(output, "{}", versions.iter().map(|v| format!("v{v}")).join(" "))?;
+                }
+
+                // rollback_active
+                "rollback_active" => {
+                    Self::no_txn(command)?;
+                    command.consume_args().reject_rest()?;
+                    let versions = self.mvcc.rollback_active()?;
+                    writeln!(output, "{}", versions.iter().map(|v| format!("v{v}")).join(" "))?;
+                }
+
+                // txn: rollback_to NAME
+                "rollback_to" => {
+                    let txn = self.get_txn(&command.prefix)?;
+                    let mut args = command.consume_args();
+                    let name = &args.next_pos().ok_or("name not given")?.value;
+                    args.reject_rest()?;
+                    txn.rollback_to_savepoint(name)?;
+                }
+
+                // txn: savepoint NAME
+                "savepoint" => {
+                    let txn = self.get_txn(&command.prefix)?;
+                    let mut args = command.consume_args();
+                    let name = &args.next_pos().ok_or("name not given")?.value;
+                    args.reject_rest()?;
+                    txn.savepoint(name)?;
+                }
+
                 // txn: scan [RANGE]
                 "scan" => {
                     let txn = self.get_txn(&command.prefix)?;
@@ -980,6 +3094,22 @@ pub mod tests {
                     }
                 }
 
+                // txn: scan_last [RANGE]
+                "scan_last" => {
+                    let txn = self.get_txn(&command.prefix)?;
+                    let mut args = command.consume_args();
+                    let range =
+                        parse_key_range(args.next_pos().map(|a| a.value.as_str()).unwrap_or(".."))?;
+                    args.reject_rest()?;
+
+                    match txn.scan_last(range)? {
+                        Some((key, value)) => {
+                            writeln!(output, "{}", format::Raw::key_value(&key, &value))?
+                        }
+                        None => writeln!(output, "None")?,
+                    }
+                }
+
                 // txn: scan_prefix PREFIX
                 "scan_prefix" => {
                     let txn = self.get_txn(&command.prefix)?;
@@ -1005,6 +3135,21 @@ pub mod tests {
                     args.reject_rest()?;
                 }
 
+                // txn: set_bulk KEY=VALUE...
+                "set_bulk" => {
+                    let txn = self.get_txn(&command.prefix)?;
+                    let mut args = command.consume_args();
+                    let writes = args
+                        .rest_key()
+                        .into_iter()
+                        .map(|kv| {
+                            (decode_binary(kv.key.as_ref().unwrap()), decode_binary(&kv.value))
+                        })
+                        .collect();
+                    args.reject_rest()?;
+                    txn.set_bulk(writes)?;
+                }
+
                 // set_unversioned KEY=VALUE...
                 "set_unversioned" => {
                     Self::no_txn(command)?;
@@ -1029,11 +3174,28 @@ pub mod tests {
                         if state.read_only { "ro" } else { "rw" },
                         state.active.iter().sorted().join(",")
                     )?;
+                    if let Some(snapshot) = state.snapshot {
+                        write!(output, " snapshot={snapshot}")?;
+                    }
                 }
 
                 // status
                 "status" => writeln!(output, "{:#?}", self.mvcc.status()?)?,
 
+                // txn: transactions
+                "transactions" => {
+                    let txn = self.get_txn(&command.prefix)?;
+                    command.consume_args().reject_rest()?;
+                    for info in txn.scan_transactions()? {
+                        let TransactionInfo { version, age, writes, locks, waiting_for } = info;
+                        write!(output, "v{version} age={age} writes={writes} locks={locks}")?;
+                        if let Some(waiting_for) = waiting_for {
+                            write!(output, " waiting_for={waiting_for}")?;
+                        }
+                        writeln!(output)?;
+                    }
+                }
+
                 name => return Err(format!("invalid command {name}").into()),
             }
 
@@ -0,0 +1,390 @@
+use std::collections::BTreeMap;
+use std::collections::btree_map::Range;
+use std::ops::{Bound, RangeBounds};
+use std::path::PathBuf;
+
+use log::{error, info};
+
+use super::bitcask::{Log, ValueLocation};
+use super::{Engine, Status};
+use crate::error::{Error, Result};
+
+/// A BitCask variant that splits the log into fixed-size segment files, like
+/// standard BitCask does (see [`super::BitCask`] for the log format). It's
+/// intended for append-mostly data where old keys are removed in bulk, i.e.
+/// the Raft log: the oldest segments can be removed by simply deleting their
+/// files (see [`Engine::truncate`]), instead of rewriting the entire log.
+///
+/// Segments are stored as files named by their sequence number in the given
+/// directory, e.g. `0000000001.segment`. Writes are appended to the last
+/// (active) segment, and once it reaches the segment size a new segment is
+/// started. Like BitCask, an in-memory keydir maps keys to the segment and
+/// location of their latest value, and is rebuilt on open by scanning the
+/// segments in order.
+///
+/// Garbage (replaced or deleted values) is only removed by truncation, there
+/// is no compaction. Old segments that are kept therefore retain garbage, e.g.
+/// replaced Raft term and commit index values.
+pub struct Segmented {
+    /// The segment directory.
+    dir: PathBuf,
+    /// The segment files by sequence number. The last one is the active
+    /// segment, which writes are appended to.
+    segments: BTreeMap<SegmentID, Log>,
+    /// The size of the active segment, in bytes.
+    active_size: u64,
+    /// Maps keys to a value's segment and location in it.
+    keydir: KeyDir,
+    /// The size at which to start a new segment, in bytes. Segments may
+    /// exceed it by the size of the last entry.
+    segment_size: u64,
+    /// The number of whole segments to retain before a truncation point.
+    retain_segments: usize,
+}
+
+/// A segment sequence number, starting at 1.
+type SegmentID = u64;
+
+/// Maps keys to a value's segment and location in it.
+type KeyDir = BTreeMap<Vec<u8>, (SegmentID, ValueLocation)>;
+
+/// The segment file extension.
+const EXTENSION: &str = "segment";
+
+impl Segmented {
+    /// Opens or creates a segmented database in the given directory, using
+    /// the given segment size in bytes and number of segments to retain before
+    /// a truncation point.
+    pub fn new(dir: PathBuf, segment_size: u64, retain_segments: usize) -> Result<Self> {
+        info!("Opening database {}", dir.display());
+        std::fs::create_dir_all(&dir)?;
+
+        // Find the existing segment files, ignoring any other files.
+        let mut ids = Vec::new();
+        for entry in std::fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == EXTENSION) {
+                let id = path.file_stem().and_then(|s| s.to_str()).and_then(|s| s.parse().ok());
+                let Some(id) = id else {
+                    return Err(Error::InvalidData(format!("invalid segment file {path:?}")));
+                };
+                ids.push(id);
+            }
+        }
+        ids.sort();
+        if ids.is_empty() {
+            ids.push(1);
+        }
+
+        // Open the segments and build the keydir by scanning them in order.
+        let mut segments = BTreeMap::new();
+        let mut keydir = KeyDir::new();
+        for id in ids {
+            let mut log = Log::new(Self::segment_path(&dir, id))?;
+            log.replay(|key, value_loc| match value_loc {
+                Some(value_loc) => keydir.insert(key, (id, value_loc)),
+                None => keydir.remove(&key),
+            })?;
+            segments.insert(id, log);
+        }
+        let (_, active) = segments.last_key_value().expect("no segments");
+        let active_size = active.file.metadata()?.len();
+        info!("Indexed {} live keys in {} segments", keydir.len(), segments.len());
+
+        Ok(Self { dir, segments, active_size, keydir, segment_size, retain_segments })
+    }
+
+    /// Returns the path of a segment file.
+    fn segment_path(dir: &std::path::Path, id: SegmentID) -> PathBuf {
+        dir.join(format!("{id:010}.{EXTENSION}"))
+    }
+
+    /// Returns the active segment's ID.
+    fn active_id(&self) -> SegmentID {
+        *self.segments.last_key_value().expect("no segments").0
+    }
+
+    /// Appends a key/value entry to the active segment, using a None value for
+    /// tombstones. Starts a new segment first if the active segment is full.
+    fn write_entry(
+        &mut self,
+        key: &[u8],
+        value: Option<&[u8]>,
+    ) -> Result<(SegmentID, ValueLocation)> {
+        if self.active_size >= self.segment_size {
+            // Flush the full segment, since flush() only syncs the active one.
+            self.flush()?;
+            let id = self.active_id() + 1;
+            self.segments.insert(id, Log::new(Self::segment_path(&self.dir, id))?);
+            self.active_size = 0;
+        }
+        let mut active = self.segments.last_entry().expect("no segments");
+        let value_loc = active.get_mut().write_entry(key, value)?;
+        self.active_size = value_loc.end();
+        Ok((*active.key(), value_loc))
+    }
+
+    /// Reads a value from a segment.
+    fn read_value(
+        segments: &mut BTreeMap<SegmentID, Log>,
+        (id, value_loc): (SegmentID, ValueLocation),
+    ) -> Result<Vec<u8>> {
+        segments.get_mut(&id).expect("unknown segment").read_value(value_loc)
+    }
+
+    /// Returns the segment IDs and file sizes.
+    pub fn segments(&self) -> Result<Vec<(SegmentID, u64)>> {
+        self.segments.iter().map(|(id, log)| Ok((*id, log.file.metadata()?.len()))).collect()
+    }
+}
+
+impl Engine for Segmented {
+    type ScanIterator<'a> = ScanIterator<'a>;
+
+    fn delete(&mut self, key: &[u8]) -> Result<()> {
+        self.write_entry(key, None)?;
+        self.keydir.remove(key);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        // Don't fsync in tests, to speed them up (see BitCask::flush).
+        #[cfg(not(test))]
+        self.segments.last_key_value().expect("no segments").1.file.sync_all()?;
+        Ok(())
+    }
+
+    fn get(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        let Some(location) = self.keydir.get(key) else {
+            return Ok(None);
+        };
+        Self::read_value(&mut self.segments, *location).map(Some)
+    }
+
+    fn scan(&mut self, range: impl RangeBounds<Vec<u8>>) -> Self::ScanIterator<'_> {
+        ScanIterator { inner: self.keydir.range(range), segments: &mut self.segments }
+    }
+
+    fn scan_dyn(
+        &mut self,
+        range: (Bound<Vec<u8>>, Bound<Vec<u8>>),
+    ) -> Box<dyn super::ScanIterator + '_> {
+        Box::new(self.scan(range))
+    }
+
+    fn set(&mut self, key: &[u8], value: Vec<u8>) -> Result<()> {
+        let location = self.write_entry(key, Some(&value))?;
+        self.keydir.insert(key.to_vec(), location);
+        Ok(())
+    }
+
+    fn status(&mut self) -> Result<Status> {
+        let keys = self.keydir.len() as u64;
+        let size = self.keydir.iter().map(|(key, (_, loc))| (key.len() + loc.length) as u64).sum();
+        let disk_size = self.segments()?.into_iter().map(|(_, size)| size).sum();
+        let live_disk_size = size + 8 * keys; // account for length prefixes
+        Ok(Status { name: "segmented".to_string(), keys, size, disk_size, live_disk_size })
+    }
+
+    /// Deletes the segments before the one containing the first live key at
+    /// or after the given key, except for the last retain_segments of them.
+    /// Live keys at or after the given key in the deleted segments (e.g. Raft
+    /// metadata which sorts after the log entries) are rewritten to the active
+    /// segment first. Keys before the given key in the remaining segments are
+    /// retained.
+    fn truncate(&mut self, key: &[u8]) -> Result<u64> {
+        let active_id = self.active_id();
+        let keep_id = match self.keydir.range(key.to_vec()..).next() {
+            Some((_, (id, _))) => (*id).min(active_id),
+            None => active_id,
+        };
+        let Some(&last_id) =
+            self.segments.range(..keep_id).rev().nth(self.retain_segments).map(|(id, _)| id)
+        else {
+            return Ok(0);
+        };
+
+        // Find the live keys in the deleted segments, and rewrite the ones at
+        // or after the truncation point.
+        let mut removed = Vec::new();
+        let mut rewrite = Vec::new();
+        for (k, (id, _)) in &self.keydir {
+            if *id <= last_id {
+                match k.as_slice() < key {
+                    true => removed.push(k.clone()),
+                    false => rewrite.push(k.clone()),
+                }
+            }
+        }
+        for k in rewrite {
+            let value = Self::read_value(&mut self.segments, self.keydir[&k])?;
+            self.set(&k, value)?;
+        }
+        for k in &removed {
+            self.keydir.remove(k);
+        }
+
+        // Make sure rewritten keys are durable before deleting the segments,
+        // and delete them in order such that a crash leaves a valid suffix.
+        self.flush()?;
+        let ids: Vec<_> = self.segments.range(..=last_id).map(|(id, _)| *id).collect();
+        for id in ids {
+            let log = self.segments.remove(&id).expect("unknown segment");
+            let path = log.path.clone();
+            drop(log); // close the file
+            std::fs::remove_file(&path)?;
+        }
+        info!("Truncated {} keys in segments up to {last_id}", removed.len());
+        Ok(removed.len() as u64)
+    }
+}
+
+/// Attempt to flush the active segment when the database is closed.
+impl Drop for Segmented {
+    fn drop(&mut self) {
+        if let Err(error) = self.flush() {
+            error!("failed to flush file: {}", error)
+        }
+    }
+}
+
+pub struct ScanIterator<'a> {
+    inner: Range<'a, Vec<u8>, (SegmentID, ValueLocation)>,
+    segments: &'a mut BTreeMap<SegmentID, Log>,
+}
+
+impl ScanIterator<'_> {
+    fn map(&mut self, item: (&Vec<u8>, &(SegmentID, ValueLocation))) -> <Self as Iterator>::Item {
+        let (key, location) = item;
+        Ok((key.clone(), Segmented::read_value(self.segments, *location)?))
+    }
+}
+
+impl Iterator for ScanIterator<'_> {
+    type Item = Result<(Vec<u8>, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|item| self.map(item))
+    }
+}
+
+impl DoubleEndedIterator for ScanIterator<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.inner.next_back().map(|item| self.map(item))
+    }
+}
+
+/// Most storage tests are Goldenscripts under src/storage/testscripts.
+#[cfg(test)]
+mod tests {
+    use std::error::Error as StdError;
+    use std::fmt::Write as _;
+    use std::result::Result as StdResult;
+
+    use tempfile::TempDir;
+    use test_each_file::test_each_path;
+
+    use super::super::engine::test::{Runner, decode_binary};
+    use super::*;
+    use crate::encoding::format::{self, Formatter as _};
+
+    // Run common goldenscript tests in src/storage/testscripts/engine, with a
+    // small segment size such that they span many segments.
+    test_each_path! { in "src/storage/testscripts/engine" as engine => test_goldenscript_engine }
+
+    // Also run segment-specific tests in src/storage/testscripts/segmented.
+    test_each_path! { in "src/storage/testscripts/segmented" as scripts => test_goldenscript }
+
+    fn test_goldenscript_engine(path: &std::path::Path) {
+        goldenscript::run(&mut SegmentedRunner::new(32, 0), path).expect("goldenscript failed")
+    }
+
+    fn test_goldenscript(path: &std::path::Path) {
+        goldenscript::run(&mut SegmentedRunner::new(64, 0), path).expect("goldenscript failed")
+    }
+
+    /// Tests that exclusive locks are taken out on segment files, erroring if
+    /// held, and released when the database is closed.
+    #[test]
+    fn lock() -> Result<()> {
+        let dir = TempDir::with_prefix("toydb")?.path().join("segmented");
+        let engine = Segmented::new(dir.clone(), 64, 0)?;
+        assert!(Segmented::new(dir.clone(), 64, 0).is_err());
+        drop(engine);
+        assert!(Segmented::new(dir, 64, 0).is_ok());
+        Ok(())
+    }
+
+    /// A segment-specific goldenscript runner, which dispatches through to
+    /// the standard Engine runner.
+    struct SegmentedRunner {
+        inner: Runner<Segmented>,
+        tempdir: TempDir,
+    }
+
+    impl goldenscript::Runner for SegmentedRunner {
+        fn run(&mut self, command: &goldenscript::Command) -> StdResult<String, Box<dyn StdError>> {
+            let mut output = String::new();
+            match command.name.as_str() {
+                // reopen [segment_size=BYTES] [retain_segments=N]
+                // Closes and reopens the database, optionally with a different
+                // segment size and retention.
+                "reopen" => {
+                    let mut args = command.consume_args();
+                    let engine = &self.inner.engine;
+                    let segment_size =
+                        args.lookup_parse("segment_size")?.unwrap_or(engine.segment_size);
+                    let retain_segments =
+                        args.lookup_parse("retain_segments")?.unwrap_or(engine.retain_segments);
+                    args.reject_rest()?;
+                    // Close the segment files before reopening them, by
+                    // temporarily replacing the engine with an empty one.
+                    let dir = engine.dir.clone();
+                    let empty = self.tempdir.path().join("empty");
+                    self.inner.engine = Segmented::new(empty, segment_size, retain_segments)?;
+                    self.inner.engine = Segmented::new(dir, segment_size, retain_segments)?;
+                }
+
+                // segments
+                // Lists the segment files and the live keys in them.
+                "segments" => {
+                    command.consume_args().reject_rest()?;
+                    let engine = &self.inner.engine;
+                    for (id, size) in engine.segments()? {
+                        let keys = engine
+                            .keydir
+                            .iter()
+                            .filter(|(_, (segment, _))| *segment == id)
+                            .map(|(key, _)| format::Raw::key(key))
+                            .collect::<Vec<_>>();
+                        writeln!(output, "{id}: {size} bytes, keys [{}]", keys.join(", "))?;
+                    }
+                }
+
+                // truncate KEY
+                // Truncates keys before KEY.
+                "truncate" => {
+                    let mut args = command.consume_args();
+                    let key = decode_binary(&args.next_pos().ok_or("key not given")?.value);
+                    args.reject_rest()?;
+                    let removed = self.inner.engine.truncate(&key)?;
+                    writeln!(output, "removed {removed} keys")?;
+                }
+
+                // Pass other commands to the standard engine runner.
+                _ => return self.inner.run(command),
+            }
+            Ok(output)
+        }
+    }
+
+    impl SegmentedRunner {
+        fn new(segment_size: u64, retain_segments: usize) -> Self {
+            let tempdir = TempDir::with_prefix("toydb").expect("tempdir failed");
+            let dir = tempdir.path().join("segmented");
+            let engine =
+                Segmented::new(dir, segment_size, retain_segments).expect("segmented failed");
+            Self { inner: Runner::new(engine), tempdir }
+        }
+    }
+}
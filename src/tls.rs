@@ -0,0 +1,306 @@
+//! TLS encryption for SQL client connections and Raft peer traffic, using
+//! rustls.
+//!
+//! Each node has a certificate and private key, and a set of CA certificates
+//! used to verify the other nodes. SQL clients verify the server certificate
+//! against the CA, and Raft peers verify each other's certificates against it.
+//! Mutual authentication can optionally be required for Raft peers, in which
+//! case nodes present their certificate when connecting to peers and only
+//! accept inbound Raft connections from nodes with a certificate signed by the
+//! CA. SQL clients authenticate with a password instead, if enabled.
+//!
+//! Certificates and keys are given as PEM files. The certificates must be
+//! valid for the host names that clients and peers connect to.
+
+use std::io::{ErrorKind, Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::os::fd::AsRawFd as _;
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use rustls::RootCertStore;
+use rustls::crypto::CryptoProvider;
+use rustls::pki_types::pem::PemObject as _;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use rustls::server::WebPkiClientVerifier;
+
+use crate::errinput;
+use crate::error::Result;
+
+/// A node's TLS configuration.
+#[derive(Clone)]
+pub struct Config {
+    /// Accepts SQL client connections.
+    pub sql: Arc<rustls::ServerConfig>,
+    /// Accepts inbound Raft peer connections. Requires client certificates if
+    /// peer authentication is enabled.
+    pub raft_server: Arc<rustls::ServerConfig>,
+    /// Connects to Raft peers. Presents the node's certificate if peer
+    /// authentication is enabled.
+    pub raft_client: Arc<rustls::ClientConfig>,
+    /// Connects to peers' SQL client addresses, e.g. to collect their status.
+    pub sql_client: Arc<rustls::ClientConfig>,
+}
+
+impl Config {
+    /// Loads a TLS configuration from the given PEM files: the node's
+    /// certificate chain and private key, and the CA certificates used to
+    /// verify peers. If peer_auth is true, Raft peers must authenticate with a
+    /// certificate signed by the CA.
+    pub fn load(cert: &Path, key: &Path, ca: &Path, peer_auth: bool) -> Result<Self> {
+        let certs = load_certs(cert)?;
+        let key = load_key(key)?;
+        let roots = Arc::new(load_roots(ca)?);
+
+        let sql = rustls::ServerConfig::builder_with_provider(provider())
+            .with_safe_default_protocol_versions()?
+            .with_no_client_auth()
+            .with_single_cert(certs.clone(), key.clone_key())?;
+
+        let raft_server = rustls::ServerConfig::builder_with_provider(provider())
+            .with_safe_default_protocol_versions()?;
+        let raft_server = match peer_auth {
+            true => raft_server.with_client_cert_verifier(
+                WebPkiClientVerifier::builder_with_provider(roots.clone(), provider()).build()?,
+            ),
+            false => raft_server.with_no_client_auth(),
+        };
+        let raft_server = raft_server.with_single_cert(certs.clone(), key.clone_key())?;
+
+        let sql_client = rustls::ClientConfig::builder_with_provider(provider())
+            .with_safe_default_protocol_versions()?
+            .with_root_certificates(roots.clone())
+            .with_no_client_auth();
+
+        let raft_client = rustls::ClientConfig::builder_with_provider(provider())
+            .with_safe_default_protocol_versions()?
+            .with_root_certificates(roots);
+        let raft_client = match peer_auth {
+            true => raft_client.with_client_auth_cert(certs, key)?,
+            false => raft_client.with_no_client_auth(),
+        };
+
+        Ok(Self {
+            sql: Arc::new(sql),
+            raft_server: Arc::new(raft_server),
+            raft_client: Arc::new(raft_client),
+            sql_client: Arc::new(sql_client),
+        })
+    }
+}
+
+/// Builds a client TLS configuration that verifies servers against the CA
+/// certificates in the given PEM file.
+pub fn client_config(ca: &Path) -> Result<Arc<rustls::ClientConfig>> {
+    let config = rustls::ClientConfig::builder_with_provider(provider())
+        .with_safe_default_protocol_versions()?
+        .with_root_certificates(load_roots(ca)?)
+        .with_no_client_auth();
+    Ok(Arc::new(config))
+}
+
+/// Returns the crypto provider. It's given explicitly, rather than relying on
+/// the process-wide default provider.
+fn provider() -> Arc<CryptoProvider> {
+    Arc::new(rustls::crypto::ring::default_provider())
+}
+
+/// Loads a certificate chain from a PEM file.
+fn load_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .and_then(|iter| iter.collect::<std::result::Result<Vec<_>, _>>())
+        .or_else(|err| errinput!("invalid certificate file {}: {err}", path.display()))?;
+    if certs.is_empty() {
+        return errinput!("no certificates in {}", path.display());
+    }
+    Ok(certs)
+}
+
+/// Loads a private key from a PEM file.
+fn load_key(path: &Path) -> Result<PrivateKeyDer<'static>> {
+    PrivateKeyDer::from_pem_file(path)
+        .or_else(|err| errinput!("invalid private key file {}: {err}", path.display()))
+}
+
+/// Loads CA certificates from a PEM file.
+fn load_roots(path: &Path) -> Result<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+    for cert in load_certs(path)? {
+        roots.add(cert)?;
+    }
+    Ok(roots)
+}
+
+/// A TCP stream, optionally encrypted with TLS, or a local Unix socket stream.
+/// Like TcpStream, it can be cloned via try_clone() to read and write
+/// concurrently from separate threads.
+pub enum Stream {
+    /// An unencrypted stream.
+    Tcp(TcpStream),
+    /// An unencrypted Unix socket stream.
+    Unix(UnixStream),
+    /// A TLS stream. Clones share the TLS connection state, but each clone
+    /// reads from and writes to the socket without holding the connection lock
+    /// while blocked reading, so a blocked reader doesn't block writers. Writers
+    /// hold the lock while writing, which is fine since each side of a toyDB
+    /// connection mostly writes while the other reads.
+    Tls { socket: TcpStream, conn: Arc<Mutex<rustls::Connection>> },
+}
+
+impl Stream {
+    /// Accepts a server connection, performing a TLS handshake if given a
+    /// config.
+    pub fn accept(socket: TcpStream, config: Option<&Arc<rustls::ServerConfig>>) -> Result<Self> {
+        let Some(config) = config else {
+            return Ok(Self::Tcp(socket));
+        };
+        let conn = rustls::ServerConnection::new(config.clone())?;
+        Self::handshake(socket, conn.into())
+    }
+
+    /// Sets up a client connection, performing a TLS handshake if given a
+    /// config. The server's certificate must be valid for the given host name.
+    pub fn connect(
+        socket: TcpStream,
+        config: Option<&Arc<rustls::ClientConfig>>,
+        host: &str,
+    ) -> Result<Self> {
+        let Some(config) = config else {
+            return Ok(Self::Tcp(socket));
+        };
+        let name = ServerName::try_from(host.to_string())
+            .or_else(|_| errinput!("invalid TLS server name {host}"))?;
+        let conn = rustls::ClientConnection::new(config.clone(), name)?;
+        Self::handshake(socket, conn.into())
+    }
+
+    /// Performs a TLS handshake.
+    fn handshake(mut socket: TcpStream, mut conn: rustls::Connection) -> Result<Self> {
+        while conn.is_handshaking() {
+            conn.complete_io(&mut socket)?;
+        }
+        Ok(Self::Tls { socket, conn: Arc::new(Mutex::new(conn)) })
+    }
+
+    /// Clones the stream. The clone can be used concurrently from a separate
+    /// thread.
+    pub fn try_clone(&self) -> Result<Self> {
+        Ok(match self {
+            Self::Tcp(socket) => Self::Tcp(socket.try_clone()?),
+            Self::Unix(socket) => Self::Unix(socket.try_clone()?),
+            Self::Tls { socket, conn } => {
+                Self::Tls { socket: socket.try_clone()?, conn: conn.clone() }
+            }
+        })
+    }
+
+    /// Shuts down the stream. For TLS streams, the peer is first notified that
+    /// the connection is closing, ignoring errors.
+    pub fn shutdown(&self, how: Shutdown) -> Result<()> {
+        match self {
+            Self::Tcp(socket) => socket.shutdown(how)?,
+            Self::Unix(socket) => socket.shutdown(how)?,
+            Self::Tls { socket, conn } => {
+                let mut conn = lock(conn);
+                conn.send_close_notify();
+                conn.write_tls(&mut &*socket).ok();
+                socket.shutdown(how)?
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns true if the peer has closed the connection or sent data, without
+    /// blocking. Used to detect connections closed by the peer while idle, when
+    /// no data is expected.
+    pub fn peer_closed(&self) -> bool {
+        let fd = match self {
+            Self::Tcp(socket) | Self::Tls { socket, .. } => socket.as_raw_fd(),
+            Self::Unix(socket) => socket.as_raw_fd(),
+        };
+        let mut buf = [0u8];
+        // SAFETY: the socket is open while borrowed, and the buffer is valid
+        // for the given length.
+        let n = unsafe {
+            libc::recv(fd, buf.as_mut_ptr().cast(), 1, libc::MSG_PEEK | libc::MSG_DONTWAIT)
+        };
+        n >= 0 || std::io::Error::last_os_error().kind() != ErrorKind::WouldBlock
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let (mut socket, conn) = match self {
+            Self::Tcp(socket) => return socket.read(buf),
+            Self::Unix(socket) => return socket.read(buf),
+            Self::Tls { socket, conn } => (&*socket, conn),
+        };
+        let mut ciphertext = [0; 16384];
+        loop {
+            // Return any buffered plaintext. The peer may close the socket
+            // without notifying us first, which is treated as a regular EOF;
+            // toyDB's protocol messages are self-delimiting, so truncation is
+            // detected when decoding them.
+            match lock(conn).reader().read(buf) {
+                Err(err) if err.kind() == ErrorKind::WouldBlock => {}
+                Err(err) if err.kind() == ErrorKind::UnexpectedEof => return Ok(0),
+                result => return result,
+            }
+
+            // Read more ciphertext from the socket (without holding the lock),
+            // and process it. An empty read records the EOF.
+            let n = socket.read(&mut ciphertext)?;
+            let mut conn = lock(conn);
+            let mut input = &ciphertext[..n];
+            loop {
+                conn.read_tls(&mut input)?;
+                if let Err(err) = conn.process_new_packets() {
+                    conn.write_tls(&mut socket).ok(); // send alert
+                    return Err(std::io::Error::new(ErrorKind::InvalidData, err));
+                }
+                if input.is_empty() {
+                    break;
+                }
+            }
+            while conn.wants_write() {
+                conn.write_tls(&mut socket)?;
+            }
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let (mut socket, conn) = match self {
+            Self::Tcp(socket) => return socket.write(buf),
+            Self::Unix(socket) => return socket.write(buf),
+            Self::Tls { socket, conn } => (&*socket, conn),
+        };
+        let mut conn = lock(conn);
+        let n = conn.writer().write(buf)?;
+        while conn.wants_write() {
+            conn.write_tls(&mut socket)?;
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        let (mut socket, conn) = match self {
+            Self::Tcp(socket) => return socket.flush(),
+            Self::Unix(socket) => return socket.flush(),
+            Self::Tls { socket, conn } => (&*socket, conn),
+        };
+        let mut conn = lock(conn);
+        conn.writer().flush()?;
+        while conn.wants_write() {
+            conn.write_tls(&mut socket)?;
+        }
+        socket.flush()
+    }
+}
+
+/// Locks a TLS connection.
+fn lock(conn: &Mutex<rustls::Connection>) -> MutexGuard<'_, rustls::Connection> {
+    conn.lock().expect("mutex failed")
+}
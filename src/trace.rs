@@ -0,0 +1,356 @@
+//! Distributed tracing of SQL statements, exported to Jaeger or another
+//! OpenTelemetry collector via OTLP/HTTP with JSON encoding.
+//!
+//! A trace is a tree of spans, each timing an operation: a root span for the
+//! statement with children for parsing, planning, and execution, which in turn
+//! have children for storage reads and the Raft requests they submit, e.g.:
+//!
+//! query
+//! ├─ parse
+//! ├─ plan
+//! └─ execute
+//!    └─ storage.scan
+//!       └─ raft.snapshot_read
+//!
+//! Spans are tracked per thread: root() starts a trace on the current thread
+//! (if sampled), and span() starts a child of the current thread's innermost
+//! open span. Both return a guard which ends the span when dropped, so spans
+//! nest with the scopes they're created in. When the root span ends, the trace
+//! is sent to the exporter. Outside of a trace, span() is a cheap noop.
+//!
+//! Spans only cover work done on the thread that started the trace. Raft
+//! requests are applied on the leader's Raft thread, so their span includes
+//! replication and state machine execution on the leader, but without further
+//! children. Similarly, work done by parallel execution workers is included
+//! in the span of the operator that spawned them.
+
+use std::cell::RefCell;
+use std::io::{BufRead as _, BufReader, Write as _};
+use std::net::TcpStream;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant, SystemTime};
+
+use crossbeam::channel::{Receiver, RecvTimeoutError, Sender};
+use log::{debug, warn};
+use serde_json::json;
+
+use crate::error::Result;
+use crate::raft::NodeID;
+use crate::{errdata, errinput};
+
+/// The installed exporter, if any.
+static EXPORTER: OnceLock<Exporter> = OnceLock::new();
+
+/// The maximum number of traces buffered for export. Further traces are
+/// dropped until the exporter catches up.
+const EXPORT_BUFFER: usize = 1024;
+
+/// How often to export buffered spans.
+const EXPORT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The maximum number of spans per export request.
+const EXPORT_BATCH_SIZE: usize = 1000;
+
+thread_local! {
+    /// The trace active on the current thread, if any.
+    static TRACE: RefCell<Option<ActiveTrace>> = const { RefCell::new(None) };
+    /// Captured traces, if capturing on the current thread (see capture).
+    static CAPTURE: RefCell<Option<Vec<Span>>> = const { RefCell::new(None) };
+}
+
+/// A finished span.
+#[derive(Clone, Debug)]
+pub struct Span {
+    /// The trace ID, shared by all spans in the trace.
+    pub trace_id: u128,
+    /// The span ID.
+    pub span_id: u64,
+    /// The parent span ID, or None for the root span.
+    pub parent_id: Option<u64>,
+    /// The operation name, e.g. "parse".
+    pub name: &'static str,
+    /// The wall-clock start time.
+    pub start: SystemTime,
+    /// The span's duration.
+    pub duration: Duration,
+    /// Attributes, in the order they were set.
+    pub attributes: Vec<(&'static str, String)>,
+    /// The error message, if the operation failed.
+    pub error: Option<String>,
+}
+
+/// A trace in progress on the current thread.
+struct ActiveTrace {
+    trace_id: u128,
+    /// Open spans, innermost last, with their start instants.
+    open: Vec<(Span, Instant)>,
+    /// Finished spans.
+    finished: Vec<Span>,
+}
+
+/// An open span, which ends when dropped. Inert if not in a trace.
+#[must_use = "the span ends when the guard is dropped"]
+pub struct SpanGuard {
+    /// The span ID, or None if inert.
+    span_id: Option<u64>,
+}
+
+/// Starts a new trace with the given root span name, if it's sampled by the
+/// exporter or traces are being captured. If the thread is already in a
+/// trace, it starts a child span instead.
+pub fn root(name: &'static str) -> SpanGuard {
+    let in_trace = TRACE.with_borrow(|trace| trace.is_some());
+    if in_trace {
+        return span(name);
+    }
+    let capturing = CAPTURE.with_borrow(|capture| capture.is_some());
+    let sampled =
+        EXPORTER.get().is_some_and(|exporter| rand::random::<f64>() < exporter.sample_rate);
+    if !capturing && !sampled {
+        return SpanGuard { span_id: None };
+    }
+    let trace_id = rand::random::<u128>().max(1); // 0 is an invalid ID
+    TRACE.set(Some(ActiveTrace { trace_id, open: Vec::new(), finished: Vec::new() }));
+    span(name)
+}
+
+/// Starts a child span of the current thread's innermost open span, if any.
+pub fn span(name: &'static str) -> SpanGuard {
+    TRACE.with_borrow_mut(|trace| {
+        let Some(trace) = trace else {
+            return SpanGuard { span_id: None };
+        };
+        let span_id = rand::random::<u64>().max(1);
+        let span = Span {
+            trace_id: trace.trace_id,
+            span_id,
+            parent_id: trace.open.last().map(|(span, _)| span.span_id),
+            name,
+            start: SystemTime::now(),
+            duration: Duration::ZERO,
+            attributes: Vec::new(),
+            error: None,
+        };
+        trace.open.push((span, Instant::now()));
+        SpanGuard { span_id: Some(span_id) }
+    })
+}
+
+impl SpanGuard {
+    /// Sets an attribute on the span. The value is only formatted if the span
+    /// is recorded.
+    pub fn attr(&self, key: &'static str, value: impl ToString) {
+        self.with_span(|span| span.attributes.push((key, value.to_string())));
+    }
+
+    /// Records the error of a failed operation, if any.
+    pub fn result<T>(&self, result: &Result<T>) {
+        if let Err(error) = result {
+            self.with_span(|span| span.error = Some(error.to_string()));
+        }
+    }
+
+    /// Calls the closure with the open span, if recorded.
+    fn with_span(&self, f: impl FnOnce(&mut Span)) {
+        let Some(span_id) = self.span_id else {
+            return;
+        };
+        TRACE.with_borrow_mut(|trace| {
+            let span = trace
+                .as_mut()
+                .and_then(|trace| trace.open.iter_mut().find(|(span, _)| span.span_id == span_id));
+            if let Some((span, _)) = span {
+                f(span);
+            }
+        });
+    }
+}
+
+impl Drop for SpanGuard {
+    fn drop(&mut self) {
+        let Some(span_id) = self.span_id else {
+            return;
+        };
+        let finished = TRACE.with_borrow_mut(|active| {
+            let trace = active.as_mut()?;
+            let i = trace.open.iter().position(|(span, _)| span.span_id == span_id)?;
+            let (mut span, start) = trace.open.remove(i);
+            span.duration = start.elapsed();
+            let is_root = span.parent_id.is_none();
+            trace.finished.push(span);
+            if !is_root {
+                return None;
+            }
+            active.take().map(|trace| trace.finished)
+        });
+        if let Some(spans) = finished {
+            let captured = CAPTURE.with_borrow_mut(|capture| match capture {
+                Some(capture) => {
+                    capture.extend_from_slice(&spans);
+                    true
+                }
+                None => false,
+            });
+            if !captured {
+                if let Some(exporter) = EXPORTER.get() {
+                    _ = exporter.tx.try_send(spans); // drop if full
+                }
+            }
+        }
+    }
+}
+
+/// Runs the closure in a child span, recording its error if any.
+pub fn in_span<T>(name: &'static str, f: impl FnOnce() -> Result<T>) -> Result<T> {
+    let span = span(name);
+    let result = f();
+    span.result(&result);
+    result
+}
+
+/// Runs the closure, capturing all traces started on the current thread
+/// instead of exporting them. Traces are always sampled. Used in tests.
+pub fn capture<T>(f: impl FnOnce() -> T) -> (T, Vec<Span>) {
+    CAPTURE.set(Some(Vec::new()));
+    let result = f();
+    (result, CAPTURE.take().unwrap_or_default())
+}
+
+/// Formats spans as an indented tree of names, attributes, and errors, without
+/// IDs or timings. Children are ordered by start time.
+pub fn format_tree(spans: &[Span]) -> String {
+    fn format(spans: &[Span], parent_id: Option<u64>, depth: usize, output: &mut String) {
+        let mut children: Vec<_> = spans.iter().filter(|s| s.parent_id == parent_id).collect();
+        children.sort_by_key(|span| span.start);
+        for span in children {
+            output.push_str(&"  ".repeat(depth));
+            output.push_str(span.name);
+            for (key, value) in &span.attributes {
+                output.push_str(&format!(" {key}={value}"));
+            }
+            if let Some(error) = &span.error {
+                output.push_str(&format!(" error={error:?}"));
+            }
+            output.push('\n');
+            format(spans, Some(span.span_id), depth + 1, output);
+        }
+    }
+    let mut output = String::new();
+    format(spans, None, 0, &mut output);
+    output
+}
+
+/// Exports traces to an OTLP/HTTP collector in a background thread.
+struct Exporter {
+    tx: Sender<Vec<Span>>,
+    sample_rate: f64,
+}
+
+/// Installs an exporter that sends traces to the OTLP/HTTP collector at the
+/// given host:port address (e.g. Jaeger at localhost:4318), sampling the given
+/// fraction of traces. Can only be called once.
+pub fn init(endpoint: &str, sample_rate: f64, node_id: NodeID) -> Result<()> {
+    let (tx, rx) = crossbeam::channel::bounded(EXPORT_BUFFER);
+    if EXPORTER.set(Exporter { tx, sample_rate }).is_err() {
+        return errinput!("trace exporter already initialized");
+    }
+    let endpoint = endpoint.to_string();
+    std::thread::spawn(move || export(&endpoint, node_id, rx));
+    Ok(())
+}
+
+/// Exports traces received from the channel in batches.
+fn export(endpoint: &str, node_id: NodeID, rx: Receiver<Vec<Span>>) {
+    let mut batch = Vec::new();
+    let mut deadline = Instant::now() + EXPORT_INTERVAL;
+    let mut failing = false;
+    loop {
+        match rx.recv_deadline(deadline) {
+            Ok(spans) => batch.extend(spans),
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return,
+        }
+        if batch.len() < EXPORT_BATCH_SIZE && Instant::now() < deadline {
+            continue;
+        }
+        deadline = Instant::now() + EXPORT_INTERVAL;
+        if batch.is_empty() {
+            continue;
+        }
+        // Only log the first failure, to avoid spamming the log when the
+        // collector is down.
+        match post(endpoint, &otlp_json(&batch, node_id)) {
+            Ok(()) if failing => {
+                debug!("Exported traces to {endpoint}");
+                failing = false
+            }
+            Ok(()) => {}
+            Err(err) if !failing => {
+                warn!("Failed exporting traces to {endpoint}: {err}");
+                failing = true
+            }
+            Err(_) => {}
+        }
+        batch.clear();
+    }
+}
+
+/// Encodes spans as an OTLP ExportTraceServiceRequest in JSON.
+fn otlp_json(spans: &[Span], node_id: NodeID) -> String {
+    let attributes = |attributes: &[(&str, String)]| {
+        attributes
+            .iter()
+            .map(|(key, value)| json!({"key": key, "value": {"stringValue": value}}))
+            .collect::<Vec<_>>()
+    };
+    let nanos = |time: SystemTime| {
+        time.duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_nanos().to_string()
+    };
+    let spans: Vec<_> = spans
+        .iter()
+        .map(|span| {
+            json!({
+                "traceId": format!("{:032x}", span.trace_id),
+                "spanId": format!("{:016x}", span.span_id),
+                "parentSpanId": span.parent_id.map(|id| format!("{id:016x}")).unwrap_or_default(),
+                "name": span.name,
+                "kind": 1, // SPAN_KIND_INTERNAL
+                "startTimeUnixNano": nanos(span.start),
+                "endTimeUnixNano": nanos(span.start + span.duration),
+                "attributes": attributes(&span.attributes),
+                "status": match &span.error {
+                    Some(error) => json!({"code": 2, "message": error}), // STATUS_CODE_ERROR
+                    None => json!({}),
+                },
+            })
+        })
+        .collect();
+    let resource = [("service.name", "toydb".to_string()), ("toydb.node", node_id.to_string())];
+    json!({
+        "resourceSpans": [{
+            "resource": {"attributes": attributes(&resource)},
+            "scopeSpans": [{"scope": {"name": "toydb"}, "spans": spans}],
+        }]
+    })
+    .to_string()
+}
+
+/// POSTs a JSON body to the collector's /v1/traces endpoint, and errors if it
+/// doesn't respond with a 2xx status.
+fn post(endpoint: &str, body: &str) -> Result<()> {
+    let mut stream = TcpStream::connect(endpoint)?;
+    stream.set_read_timeout(Some(Duration::from_secs(10)))?;
+    write!(
+        stream,
+        "POST /v1/traces HTTP/1.1\r\nHost: {endpoint}\r\nContent-Type: application/json\r\n\
+         Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )?;
+    stream.flush()?;
+    let mut status = String::new();
+    BufReader::new(stream).read_line(&mut status)?;
+    match status.split_whitespace().nth(1) {
+        Some(code) if code.starts_with('2') => Ok(()),
+        _ => errdata!("unexpected collector response {:?}", status.trim_end()),
+    }
+}
@@ -0,0 +1,237 @@
+use std::error::Error;
+use std::path::Path;
+
+use base64::Engine as _;
+use itertools::Itertools as _;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::transport::{Certificate, Channel, ClientTlsConfig};
+use tonic::{Request, Streaming};
+
+use toydb::grpc::proto;
+use toydb::grpc::proto::admin_client::AdminClient;
+use toydb::grpc::proto::sql_client::SqlClient;
+
+/// A gRPC client, for testing the server's gRPC front end via the generated
+/// tonic clients. It runs its own Tokio runtime, and returns the server's
+/// responses as human-readable lines.
+pub struct GrpcClient {
+    runtime: tokio::runtime::Runtime,
+    sql: SqlClient<Channel>,
+    admin: AdminClient<Channel>,
+    /// The default credentials, i.e. the admin superuser if authentication is
+    /// enabled.
+    credentials: Option<(String, String)>,
+    /// An open session stream, if any.
+    session: Option<(mpsc::Sender<proto::SessionRequest>, Streaming<proto::SessionResponse>)>,
+}
+
+impl GrpcClient {
+    /// Connects to the given port. If given a CA certificate file, uses TLS.
+    /// If given an admin password, authenticates as admin by default.
+    pub fn connect(
+        port: u16,
+        tls_ca: Option<&Path>,
+        admin_password: Option<&str>,
+    ) -> Result<Self, Box<dyn Error>> {
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+        let scheme = if tls_ca.is_some() { "https" } else { "http" };
+        let mut endpoint = Channel::from_shared(format!("{scheme}://localhost:{port}"))?;
+        if let Some(tls_ca) = tls_ca {
+            let ca = Certificate::from_pem(std::fs::read(tls_ca)?);
+            endpoint = endpoint
+                .tls_config(ClientTlsConfig::new().ca_certificate(ca).domain_name("localhost"))?;
+        }
+        let channel = runtime.block_on(endpoint.connect())?;
+        Ok(Self {
+            runtime,
+            sql: SqlClient::new(channel.clone()),
+            admin: AdminClient::new(channel),
+            credentials: admin_password.map(|password| ("admin".to_string(), password.to_string())),
+            session: None,
+        })
+    }
+
+    /// Executes a statement via Sql.Execute.
+    pub fn execute(
+        &mut self,
+        query: &str,
+        params: Vec<proto::Value>,
+        credentials: Option<(&str, &str)>,
+    ) -> Result<Vec<String>, Box<dyn Error>> {
+        let request =
+            self.request(proto::ExecuteRequest { query: query.to_string(), params }, credentials);
+        let result = self.runtime.block_on(self.sql.execute(request)).map_err(format_status)?;
+        Ok(format_result(result.into_inner()))
+    }
+
+    /// Sends a request on the session stream, opening it if necessary.
+    pub fn session(
+        &mut self,
+        request: proto::session_request::Request,
+        credentials: Option<(&str, &str)>,
+    ) -> Result<Vec<String>, Box<dyn Error>> {
+        if self.session.is_none() {
+            let (request_tx, request_rx) = mpsc::channel(1);
+            let request = self.request(ReceiverStream::new(request_rx), credentials);
+            let responses =
+                self.runtime.block_on(self.sql.session(request)).map_err(format_status)?;
+            self.session = Some((request_tx, responses.into_inner()));
+        }
+        let (request_tx, responses) = self.session.as_mut().expect("no session");
+        let request = proto::SessionRequest { request: Some(request) };
+        let response = self.runtime.block_on(async {
+            request_tx.send(request).await.map_err(|_| "session closed")?;
+            responses.message().await.map_err(format_status)
+        });
+        let response = match response {
+            Ok(Some(response)) => response,
+            Ok(None) => {
+                self.session = None;
+                return Err("session closed".into());
+            }
+            Err(error) => {
+                self.session = None;
+                return Err(error);
+            }
+        };
+        match response.response.ok_or("empty response")? {
+            proto::session_response::Response::Result(result) => Ok(format_result(result)),
+            proto::session_response::Response::Error(error) => {
+                let code = tonic::Code::from_i32(error.code);
+                Err(format!("{code:?}: {}", error.message).into())
+            }
+        }
+    }
+
+    /// Closes the session stream, if open, and waits for the server to end it
+    /// (i.e. to drop the session).
+    pub fn close_session(&mut self) -> Result<(), Box<dyn Error>> {
+        let Some((request_tx, mut responses)) = self.session.take() else {
+            return Ok(());
+        };
+        drop(request_tx);
+        while self.runtime.block_on(responses.message()).map_err(format_status)?.is_some() {}
+        Ok(())
+    }
+
+    /// Calls Admin.Status.
+    pub fn status(&mut self, credentials: Option<(&str, &str)>) -> Result<String, Box<dyn Error>> {
+        let request = self.request(proto::StatusRequest {}, credentials);
+        let status = self.runtime.block_on(self.admin.status(request)).map_err(format_status)?;
+        Ok(format!("{:?}", status.into_inner()))
+    }
+
+    /// Calls Admin.ListTables.
+    pub fn list_tables(
+        &mut self,
+        credentials: Option<(&str, &str)>,
+    ) -> Result<Vec<String>, Box<dyn Error>> {
+        let request = self.request(proto::ListTablesRequest {}, credentials);
+        let tables =
+            self.runtime.block_on(self.admin.list_tables(request)).map_err(format_status)?;
+        Ok(tables.into_inner().tables)
+    }
+
+    /// Calls Admin.GetTable.
+    pub fn get_table(
+        &mut self,
+        name: &str,
+        credentials: Option<(&str, &str)>,
+    ) -> Result<Vec<String>, Box<dyn Error>> {
+        let request = self.request(proto::GetTableRequest { name: name.to_string() }, credentials);
+        let table = self.runtime.block_on(self.admin.get_table(request)).map_err(format_status)?;
+        Ok(table.into_inner().schema.lines().map(str::to_string).collect())
+    }
+
+    /// Calls Admin.GarbageCollect.
+    pub fn gc(&mut self, credentials: Option<(&str, &str)>) -> Result<String, Box<dyn Error>> {
+        let request = self.request(proto::GarbageCollectRequest {}, credentials);
+        let gc =
+            self.runtime.block_on(self.admin.garbage_collect(request)).map_err(format_status)?;
+        Ok(format!("{:?}", gc.into_inner()))
+    }
+
+    /// Creates a request with basic authentication metadata, using the given
+    /// credentials or the default ones.
+    fn request<T>(&self, message: T, credentials: Option<(&str, &str)>) -> Request<T> {
+        let mut request = Request::new(message);
+        let default = self.credentials.as_ref().map(|(u, p)| (u.as_str(), p.as_str()));
+        if let Some((user, password)) = credentials.or(default) {
+            let credentials =
+                base64::engine::general_purpose::STANDARD.encode(format!("{user}:{password}"));
+            let value = format!("Basic {credentials}").parse().expect("invalid metadata");
+            request.metadata_mut().insert("authorization", value);
+        }
+        request
+    }
+}
+
+/// Parses a parameter value from JSON, e.g. 1, "a", or null.
+pub fn parse_param(json: &str) -> Result<proto::Value, Box<dyn Error>> {
+    fn convert(json: serde_json::Value) -> Result<proto::Value, Box<dyn Error>> {
+        use proto::value::Value as V;
+        let value = match json {
+            serde_json::Value::Null => None,
+            serde_json::Value::Bool(boolean) => Some(V::Boolean(boolean)),
+            serde_json::Value::Number(number) => match number.as_i64() {
+                Some(integer) => Some(V::Integer(integer)),
+                None => Some(V::Float(number.as_f64().ok_or("invalid number")?)),
+            },
+            serde_json::Value::String(string) => Some(V::String(string)),
+            serde_json::Value::Array(values) => Some(V::Array(proto::Array {
+                values: values.into_iter().map(convert).try_collect()?,
+            })),
+            object @ serde_json::Value::Object(_) => Some(V::Json(object.to_string())),
+        };
+        Ok(proto::Value { value })
+    }
+    convert(serde_json::from_str(json)?)
+}
+
+/// Formats a statement result. RETURNING rows follow the write count.
+fn format_result(result: proto::StatementResult) -> Vec<String> {
+    use proto::statement_result::Result as R;
+    let r#type = result.r#type;
+    let mut lines = match result.result {
+        Some(R::Select(select)) => format_select(select),
+        Some(R::Explain(plan)) => plan.lines().map(str::to_string).collect(),
+        Some(R::Begin(begin)) => {
+            vec![format!("{type} version={} read_only={}", begin.version, begin.read_only)]
+        }
+        Some(R::Version(version)) => vec![format!("{type} version={version}")],
+        Some(R::Count(count)) => vec![format!("{type} count={count}")],
+        Some(R::Other(json)) => vec![format!("{type} {json}")],
+        None => vec![r#type],
+    };
+    lines.extend(result.returning.map(format_select).unwrap_or_default());
+    lines
+}
+
+/// Formats a SELECT result, with a column header.
+fn format_select(select: proto::SelectResult) -> Vec<String> {
+    std::iter::once(select.columns.join(", "))
+        .chain(select.rows.iter().map(|row| row.values.iter().map(format_value).join(", ")))
+        .collect()
+}
+
+/// Formats a value.
+fn format_value(value: &proto::Value) -> String {
+    use proto::value::Value as V;
+    match &value.value {
+        None => "NULL".to_string(),
+        Some(V::Boolean(boolean)) => boolean.to_string().to_uppercase(),
+        Some(V::Integer(integer)) => integer.to_string(),
+        Some(V::Float(float)) => format!("{float:?}"),
+        Some(V::String(string)) => format!("{string:?}"),
+        Some(V::Json(json)) => format!("json {json}"),
+        Some(V::Interval(interval)) => format!("interval {interval}"),
+        Some(V::Timestamp(timestamp)) => format!("timestamp {timestamp}"),
+        Some(V::Array(array)) => format!("[{}]", array.values.iter().map(format_value).join(", ")),
+    }
+}
+
+/// Formats a gRPC error status as CODE: message.
+fn format_status(status: tonic::Status) -> Box<dyn Error> {
+    format!("{:?}: {}", status.code(), status.message()).into()
+}
@@ -0,0 +1,75 @@
+use std::error::Error;
+use std::io::{BufRead as _, BufReader, Read as _, Write as _};
+use std::net::TcpStream;
+use std::path::Path;
+
+use base64::Engine as _;
+
+use toydb::tls::{self, Stream};
+
+/// A minimal HTTP/1.1 client, for testing the server's HTTP/JSON front end
+/// without depending on an HTTP library. It keeps the connection alive between
+/// requests, and returns the server's responses as human-readable lines.
+pub struct HttpClient {
+    reader: BufReader<Stream>,
+    writer: Stream,
+}
+
+impl HttpClient {
+    /// Connects to the given port. If given a CA certificate file, uses TLS.
+    pub fn connect(port: u16, tls_ca: Option<&Path>) -> Result<Self, Box<dyn Error>> {
+        let socket = TcpStream::connect(("localhost", port))?;
+        let config = tls_ca.map(tls::client_config).transpose()?;
+        let stream = Stream::connect(socket, config.as_ref(), "localhost")?;
+        Ok(Self { reader: BufReader::new(stream.try_clone()?), writer: stream })
+    }
+
+    /// Sends a request with an optional body and basic authentication
+    /// credentials, and returns the response status and JSON body. Non-JSON
+    /// bodies are returned as separate lines.
+    pub fn request(
+        &mut self,
+        method: &str,
+        path: &str,
+        body: Option<&str>,
+        credentials: Option<(&str, &str)>,
+    ) -> Result<Vec<String>, Box<dyn Error>> {
+        let body = body.unwrap_or_default();
+        let mut request = format!("{method} {path} HTTP/1.1\r\nHost: localhost\r\n");
+        if let Some((user, password)) = credentials {
+            let credentials =
+                base64::engine::general_purpose::STANDARD.encode(format!("{user}:{password}"));
+            request.push_str(&format!("Authorization: Basic {credentials}\r\n"));
+        }
+        request.push_str(&format!("Content-Length: {}\r\n\r\n{body}", body.len()));
+        self.writer.write_all(request.as_bytes())?;
+        self.writer.flush()?;
+
+        let mut line = String::new();
+        self.reader.read_line(&mut line)?;
+        let status = line.trim_end().split_once(' ').ok_or("invalid status line")?.1.to_string();
+        let mut length = 0;
+        let mut json = false;
+        loop {
+            line.clear();
+            self.reader.read_line(&mut line)?;
+            let Some((name, value)) = line.trim_end().split_once(':') else {
+                break;
+            };
+            if name.eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse()?;
+            }
+            if name.eq_ignore_ascii_case("content-type") {
+                json = value.trim().starts_with("application/json");
+            }
+        }
+        let mut body = vec![0; length];
+        self.reader.read_exact(&mut body)?;
+        if !json {
+            let body = String::from_utf8(body)?;
+            return Ok(std::iter::once(status).chain(body.lines().map(String::from)).collect());
+        }
+        let body: serde_json::Value = serde_json::from_slice(&body)?;
+        Ok(vec![status, body.to_string()])
+    }
+}
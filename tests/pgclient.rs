@@ -0,0 +1,244 @@
+use std::error::Error;
+use std::io::{BufReader, Read as _, Write as _};
+use std::net::TcpStream;
+use std::path::Path;
+
+use toydb::tls::{self, Stream};
+
+/// A minimal Postgres wire protocol client, for testing the server's Postgres
+/// front end without depending on a Postgres driver. It returns the server's
+/// responses as human-readable lines.
+pub struct PgClient {
+    reader: BufReader<Stream>,
+    writer: Stream,
+    /// The password to send if the server requests one.
+    password: Option<String>,
+}
+
+impl PgClient {
+    /// Connects to the given port and runs the startup handshake. Like libpq,
+    /// it first requests SSL. If given a CA certificate file, the server must
+    /// accept it, otherwise it must decline it. If given an admin password, it
+    /// authenticates as the admin when asked for a password.
+    pub fn connect(
+        port: u16,
+        tls_ca: Option<&Path>,
+        admin_password: Option<&str>,
+    ) -> Result<Self, Box<dyn Error>> {
+        let mut socket = TcpStream::connect(("localhost", port))?;
+        socket.write_all(&8i32.to_be_bytes())?;
+        socket.write_all(&80877103i32.to_be_bytes())?;
+        let mut response = [0];
+        socket.read_exact(&mut response)?;
+        let stream = match (&response, tls_ca) {
+            (b"S", Some(ca)) => {
+                Stream::connect(socket, Some(&tls::client_config(ca)?), "localhost")?
+            }
+            (b"N", None) => Stream::Tcp(socket),
+            _ => return Err(format!("unexpected SSL response {response:?}").into()),
+        };
+        let mut client = Self {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: stream,
+            password: admin_password.map(|password| password.to_string()),
+        };
+
+        let user = if admin_password.is_some() { "admin" } else { "toydb" };
+        let mut body = 196608i32.to_be_bytes().to_vec();
+        for param in ["user", user, "database", "toydb", ""] {
+            body.extend_from_slice(param.as_bytes());
+            body.push(0);
+        }
+        client.writer.write_all(&(body.len() as i32 + 4).to_be_bytes())?;
+        client.writer.write_all(&body)?;
+        client.receive()?;
+        Ok(client)
+    }
+
+    /// Runs a simple query.
+    pub fn query(&mut self, query: &str) -> Result<Vec<String>, Box<dyn Error>> {
+        let mut body = Vec::new();
+        put_str(&mut body, query);
+        self.send(b'Q', &body)?;
+        self.receive()
+    }
+
+    /// Runs an extended query with the given text parameters, via the unnamed
+    /// statement and portal. Results are fetched max_rows at a time (0 for
+    /// all), in binary format if requested.
+    pub fn execute(
+        &mut self,
+        query: &str,
+        params: &[Option<String>],
+        max_rows: i32,
+        binary: bool,
+    ) -> Result<Vec<String>, Box<dyn Error>> {
+        let mut parse = Vec::new();
+        put_str(&mut parse, "");
+        put_str(&mut parse, query);
+        parse.extend_from_slice(&0i16.to_be_bytes());
+        self.send(b'P', &parse)?;
+
+        let mut bind = Vec::new();
+        put_str(&mut bind, "");
+        put_str(&mut bind, "");
+        bind.extend_from_slice(&0i16.to_be_bytes());
+        bind.extend_from_slice(&(params.len() as i16).to_be_bytes());
+        for param in params {
+            match param {
+                Some(param) => {
+                    bind.extend_from_slice(&(param.len() as i32).to_be_bytes());
+                    bind.extend_from_slice(param.as_bytes());
+                }
+                None => bind.extend_from_slice(&(-1i32).to_be_bytes()),
+            }
+        }
+        bind.extend_from_slice(&1i16.to_be_bytes());
+        bind.extend_from_slice(&(binary as i16).to_be_bytes());
+        self.send(b'B', &bind)?;
+
+        let mut describe = vec![b'P'];
+        put_str(&mut describe, "");
+        self.send(b'D', &describe)?;
+
+        let mut execute = Vec::new();
+        put_str(&mut execute, "");
+        execute.extend_from_slice(&max_rows.to_be_bytes());
+        self.send(b'E', &execute)?;
+        if max_rows > 0 {
+            self.send(b'E', &execute)?;
+        }
+        self.send(b'S', &[])?;
+        self.receive()
+    }
+
+    /// Sends a message.
+    fn send(&mut self, kind: u8, body: &[u8]) -> Result<(), Box<dyn Error>> {
+        self.writer.write_all(&[kind])?;
+        self.writer.write_all(&(body.len() as i32 + 4).to_be_bytes())?;
+        self.writer.write_all(body)?;
+        Ok(())
+    }
+
+    /// Receives messages until ReadyForQuery, formatting them as lines.
+    /// Startup, parse, bind, and close acknowledgements are omitted.
+    fn receive(&mut self) -> Result<Vec<String>, Box<dyn Error>> {
+        let mut lines = Vec::new();
+        loop {
+            let mut header = [0; 5];
+            self.reader.read_exact(&mut header)?;
+            let len = i32::from_be_bytes(header[1..].try_into()?) as usize;
+            let mut body = vec![0; len - 4];
+            self.reader.read_exact(&mut body)?;
+            let mut body = body.as_slice();
+            match header[0] {
+                b'T' => {
+                    let columns = (0..get_i16(&mut body))
+                        .map(|_| {
+                            let name = get_str(&mut body);
+                            body = &body[6..]; // table OID, attribute number
+                            let type_oid = get_i32(&mut body);
+                            body = &body[6..]; // type size, modifier
+                            let format = match get_i16(&mut body) {
+                                0 => "",
+                                _ => " binary",
+                            };
+                            format!("{name} {}{format}", type_name(type_oid))
+                        })
+                        .collect::<Vec<_>>();
+                    lines.push(format!("columns: {}", columns.join(", ")));
+                }
+                b'D' => {
+                    let values = (0..get_i16(&mut body))
+                        .map(|_| match get_i32(&mut body) {
+                            -1 => "NULL".to_string(),
+                            len => {
+                                let (value, rest) = body.split_at(len as usize);
+                                body = rest;
+                                match std::str::from_utf8(value) {
+                                    Ok(value) if !value.contains('\0') => value.to_string(),
+                                    _ => format!("0x{}", hex::encode(value)),
+                                }
+                            }
+                        })
+                        .collect::<Vec<_>>();
+                    lines.push(values.join(", "));
+                }
+                b'C' => lines.push(get_str(&mut body)),
+                b'E' => {
+                    let (mut code, mut message) = (String::new(), String::new());
+                    while body[0] != 0 {
+                        let field = body[0];
+                        body = &body[1..];
+                        match (field, get_str(&mut body)) {
+                            (b'C', value) => code = value,
+                            (b'M', value) => message = value,
+                            _ => {}
+                        }
+                    }
+                    lines.push(format!("Error: {code} {message}"));
+                }
+                b'I' => lines.push("empty query".to_string()),
+                b'S' => {
+                    let name = get_str(&mut body);
+                    lines.push(format!("parameter {name} = {}", get_str(&mut body)));
+                }
+                b'n' => lines.push("no data".to_string()),
+                b's' => lines.push("portal suspended".to_string()),
+                b'Z' => {
+                    if body[0] != b'I' {
+                        lines.push(format!("transaction status {}", body[0] as char));
+                    }
+                    return Ok(lines);
+                }
+                // A cleartext password request.
+                b'R' if get_i32(&mut body) == 3 => {
+                    let mut message = Vec::new();
+                    put_str(&mut message, self.password.as_deref().ok_or("no password")?);
+                    self.send(b'p', &message)?;
+                }
+                b'R' | b'K' | b'1' | b'2' | b'3' => {}
+                kind => return Err(format!("unexpected message {}", kind as char).into()),
+            }
+        }
+    }
+}
+
+/// Returns the name of a Postgres type OID.
+fn type_name(type_oid: i32) -> String {
+    match type_oid {
+        16 => "bool".to_string(),
+        20 => "int8".to_string(),
+        25 => "text".to_string(),
+        114 => "json".to_string(),
+        701 => "float8".to_string(),
+        1016 => "int8[]".to_string(),
+        1009 => "text[]".to_string(),
+        1186 => "interval".to_string(),
+        type_oid => format!("oid{type_oid}"),
+    }
+}
+
+fn put_str(body: &mut Vec<u8>, value: &str) {
+    body.extend_from_slice(value.as_bytes());
+    body.push(0);
+}
+
+fn get_i16(body: &mut &[u8]) -> i16 {
+    let value = i16::from_be_bytes(body[..2].try_into().unwrap());
+    *body = &body[2..];
+    value
+}
+
+fn get_i32(body: &mut &[u8]) -> i32 {
+    let value = i32::from_be_bytes(body[..4].try_into().unwrap());
+    *body = &body[4..];
+    value
+}
+
+fn get_str(body: &mut &[u8]) -> String {
+    let len = body.iter().position(|b| *b == 0).unwrap();
+    let value = String::from_utf8_lossy(&body[..len]).to_string();
+    *body = &body[len + 1..];
+    value
+}
@@ -1,21 +1,43 @@
+use itertools::Itertools as _;
 use rand::Rng;
 use std::collections::BTreeMap;
 use std::error::Error;
 use std::fmt::Write as _;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::time::Duration;
-use toydb::Client;
+use toydb::client::PoolConfig;
 use toydb::raft::NodeID;
+use toydb::storage::{BitCask, Engine as _};
+use toydb::{AsyncClient, Client, Pool};
 
 /// Timeout for node readiness.
 const TIMEOUT: Duration = Duration::from_secs(5);
 
+/// Timeout for nodes to shut down gracefully.
+const STOP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Timeout for toydb subcommands to exit.
+const CLI_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How long to wait for nodes to reload their config on SIGHUP. They poll for
+/// signals every 100 milliseconds.
+const RELOAD_WAIT: Duration = Duration::from_millis(500);
+
 /// The base SQL port (+id).
 const SQL_BASE_PORT: u16 = 19600;
 
 /// The base Raft port (+id).
 const RAFT_BASE_PORT: u16 = 19700;
 
+/// The base Postgres port (+id).
+const PG_BASE_PORT: u16 = 19800;
+
+/// The base HTTP port (+id).
+const HTTP_BASE_PORT: u16 = 19900;
+
+/// The base gRPC port (+id).
+const GRPC_BASE_PORT: u16 = 19950;
+
 /// Runs a toyDB cluster using the built binary in a temporary directory. The
 /// cluster will be killed and removed when dropped.
 ///
@@ -26,33 +48,138 @@ const RAFT_BASE_PORT: u16 = 19700;
 /// server (and eventually the toySQL client) end-to-end.
 pub struct TestCluster {
     servers: BTreeMap<NodeID, TestServer>,
-    #[allow(dead_code)]
     dir: tempfile::TempDir, // deleted when dropped
+    /// The number of times the cluster has been restored from a backup. Each
+    /// restore uses new node data directories.
+    restores: usize,
+    /// If true, the nodes use TLS with mutual peer authentication, using a
+    /// generated CA and node certificate.
+    tls: bool,
+    /// The admin password, if authentication is enabled. Clients connect as
+    /// the admin.
+    admin_password: Option<String>,
+    /// Additional node config settings.
+    config: Vec<(String, String)>,
+    /// The Raft and SQL ports of all nodes, including stopped ones.
+    ports: NodePorts,
 }
 
 type NodePorts = BTreeMap<NodeID, (u16, u16)>; // raft,sql on localhost
 
 impl TestCluster {
-    /// Runs and returns a test cluster. It keeps running until dropped.
-    pub fn run(nodes: u8) -> Result<Self, Box<dyn Error>> {
+    /// Runs and returns a test cluster, optionally using TLS and
+    /// authentication with the given admin password, and additional node
+    /// config settings. It keeps running until dropped.
+    pub fn run(
+        nodes: u8,
+        tls: bool,
+        admin_password: Option<String>,
+        config: Vec<(String, String)>,
+    ) -> Result<Self, Box<dyn Error>> {
         // Create temporary directory.
         let dir = tempfile::TempDir::with_prefix("toydb")?;
 
+        let servers = BTreeMap::new();
+        let ports = NodePorts::new();
+        let mut cluster = Self { servers, dir, restores: 0, tls, admin_password, config, ports };
+        if tls {
+            cluster.generate_certs()?;
+        }
+        cluster.start(nodes)?;
+        Ok(cluster)
+    }
+
+    /// Initializes a test cluster via `toydb init`, optionally with TLS, and
+    /// runs it using the generated config files. Returns the cluster and the
+    /// command's output, with the cluster directory replaced by $DIR.
+    pub fn init(nodes: u8, tls: bool) -> Result<(Self, String), Box<dyn Error>> {
+        let dir = tempfile::TempDir::with_prefix("toydb")?;
+        let build = escargot::CargoBuild::new().bin("toydb").run()?;
+        let mut command = build.command();
+        command.args(["init", "--nodes", &nodes.to_string()]);
+        command.args(["--dir", &dir.path().to_string_lossy()]);
+        command.args(["--sql-port", &(SQL_BASE_PORT + 1).to_string()]);
+        command.args(["--raft-port", &(RAFT_BASE_PORT + 1).to_string()]);
+        if tls {
+            command.arg("--tls");
+        }
+        let output = command.output()?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(stderr.trim().trim_start_matches("Error: ").into());
+        }
+        let path = dir.path().canonicalize()?;
+        let stdout = String::from_utf8(output.stdout)?.replace(&*path.to_string_lossy(), "$DIR");
+
+        let servers = BTreeMap::new();
+        let ports = (1..=nodes)
+            .map(|id| (id, (RAFT_BASE_PORT + id as u16, SQL_BASE_PORT + id as u16)))
+            .collect();
+        let mut cluster =
+            Self { servers, dir, restores: 0, tls, admin_password: None, config: vec![], ports };
+        for id in 1..=nodes {
+            let configfile = cluster.node_dir(id).join("toydb.yaml");
+            let certs = cluster.certs();
+            let server =
+                TestServer::spawn(id, &configfile, &cluster.ports, certs.as_deref(), None)?;
+            cluster.servers.insert(id, server);
+        }
+        cluster.wait_ready()?;
+        Ok((cluster, stdout))
+    }
+
+    /// Generates a CA certificate (ca.pem), and a node certificate for
+    /// localhost signed by it (node.pem, node.key), in the cluster directory.
+    /// All nodes use the same certificate.
+    fn generate_certs(&self) -> Result<(), Box<dyn Error>> {
+        let ca_key = rcgen::KeyPair::generate()?;
+        let mut ca_params = rcgen::CertificateParams::new(Vec::new())?;
+        ca_params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+        let ca = ca_params.self_signed(&ca_key)?;
+
+        let node_key = rcgen::KeyPair::generate()?;
+        let node_params = rcgen::CertificateParams::new(vec!["localhost".to_string()])?;
+        let node = node_params.signed_by(&node_key, &ca, &ca_key)?;
+
+        std::fs::write(self.dir().join("ca.pem"), ca.pem())?;
+        std::fs::write(self.dir().join("node.pem"), node.pem())?;
+        std::fs::write(self.dir().join("node.key"), node_key.serialize_pem())?;
+        Ok(())
+    }
+
+    /// Returns the CA certificate file, if TLS is enabled.
+    pub fn tls_ca(&self) -> Option<PathBuf> {
+        self.tls.then(|| self.dir().join("ca.pem"))
+    }
+
+    /// Returns the admin password, if authentication is enabled.
+    pub fn admin_password(&self) -> Option<&str> {
+        self.admin_password.as_deref()
+    }
+
+    /// Starts the cluster nodes, and waits for them to be ready.
+    fn start(&mut self, nodes: u8) -> Result<(), Box<dyn Error>> {
         // Allocate port numbers for nodes.
-        let ports: NodePorts = (1..=nodes)
+        self.ports = (1..=nodes)
             .map(|id| (id, (RAFT_BASE_PORT + id as u16, SQL_BASE_PORT + id as u16)))
             .collect();
 
         // Start nodes.
-        let mut servers = BTreeMap::new();
         for id in 1..=nodes {
-            let dir = dir.path().join(format!("toydb{id}"));
-            servers.insert(id, TestServer::run(id, &dir, &ports)?);
+            let dir = self.node_dir(id);
+            let password = self.admin_password.as_deref();
+            let certs = self.certs();
+            let server =
+                TestServer::run(id, &dir, &self.ports, certs.as_deref(), password, &self.config)?;
+            self.servers.insert(id, server);
         }
+        self.wait_ready()
+    }
 
-        // Wait for the nodes to be ready, by fetching the server status.
+    /// Waits for the nodes to be ready, by fetching the server status.
+    fn wait_ready(&mut self) -> Result<(), Box<dyn Error>> {
         let started = std::time::Instant::now();
-        for server in servers.values_mut() {
+        for server in self.servers.values_mut() {
             while let Err(error) = server.connect().and_then(|mut c| Ok(c.status()?)) {
                 server.assert_alive();
                 if started.elapsed() >= TIMEOUT {
@@ -61,16 +188,324 @@ impl TestCluster {
                 std::thread::sleep(Duration::from_millis(200));
             }
         }
+        Ok(())
+    }
+
+    /// Returns the certificate directory, if TLS is enabled.
+    fn certs(&self) -> Option<PathBuf> {
+        self.tls.then(|| self.dir().to_path_buf())
+    }
+
+    /// Changes the given node config settings, and reloads them on all running
+    /// nodes via SIGHUP. Waits for the nodes to see the signal, but doesn't
+    /// check whether the reload succeeded.
+    pub fn reload(&mut self, config: Vec<(String, String)>) -> Result<(), Box<dyn Error>> {
+        merge_config(&mut self.config, config);
+        let (certs, password) = (self.certs(), self.admin_password.as_deref());
+        for (id, server) in &self.servers {
+            let dir = self.node_dir(*id);
+            let cfg = TestServer::build_config(
+                *id,
+                &dir,
+                &self.ports,
+                certs.as_deref(),
+                password,
+                &self.config,
+            )?;
+            std::fs::write(dir.join("toydb.yaml"), cfg)?;
+            server.signal(libc::SIGHUP)?;
+        }
+        std::thread::sleep(RELOAD_WAIT);
+        Ok(())
+    }
+
+    /// Validates node 1's config with the given changed settings via `toydb
+    /// check`, without changing the node. Also passes the given environment
+    /// variables and command-line flags.
+    pub fn check_config(
+        &self,
+        changes: Vec<(String, String)>,
+        env: Vec<(String, String)>,
+        flags: Vec<String>,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut config = self.config.clone();
+        merge_config(&mut config, changes);
+        let dir = self.dir().join("check");
+        std::fs::create_dir_all(&dir)?;
+        let (certs, password) = (self.certs(), self.admin_password.as_deref());
+        let cfg =
+            TestServer::build_config(1, &dir, &self.ports, certs.as_deref(), password, &config)?;
+        let file = dir.join("toydb.yaml");
+        std::fs::write(&file, cfg)?;
+
+        let build = escargot::CargoBuild::new().bin("toydb").run()?;
+        let output = build
+            .command()
+            .args(["-c", &file.to_string_lossy()])
+            .args(flags)
+            .arg("check")
+            .envs(env)
+            .output()?;
+        // Only the first line of command-line parsing errors is used.
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let error = stderr.lines().next().unwrap_or_default();
+            return Err(error.trim_start_matches("Error: ").trim_start_matches("error: ").into());
+        }
+        Ok(())
+    }
+
+    /// Runs `toydb status` with the given node's config, returning its
+    /// output.
+    pub fn status_cli(&self, id: NodeID) -> Result<String, Box<dyn Error>> {
+        self.cli(id, &["status"])
+    }
+
+    /// Runs `toydb decommission` for the given node with the given node's
+    /// config, returning its output.
+    pub fn decommission_cli(&self, id: NodeID, node: NodeID) -> Result<String, Box<dyn Error>> {
+        self.cli(id, &["decommission", &node.to_string()])
+    }
+
+    /// Starts the given stopped node via the toydb binary, expecting it to
+    /// fail. Returns its error, or an error if it keeps running.
+    pub fn start_cli(&self, id: NodeID) -> Result<String, Box<dyn Error>> {
+        if self.servers.contains_key(&id) {
+            return Err(format!("node {id} is running").into());
+        }
+        match self.cli(id, &[]) {
+            Ok(_) => Err(format!("node {id} exited without error").into()),
+            Err(error) => Ok(error.to_string()),
+        }
+    }
+
+    /// Runs the toydb binary with the given node's config and arguments,
+    /// returning its output. Errors if it doesn't exit successfully.
+    fn cli(&self, id: NodeID, args: &[&str]) -> Result<String, Box<dyn Error>> {
+        let output = self.cli_output(id, args)?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(stderr.trim().trim_start_matches("Error: ").into());
+        }
+        Ok(String::from_utf8(output.stdout)?)
+    }
+
+    /// Runs the toydb binary with the given node's config and arguments,
+    /// returning its exit status, stdout, and stderr.
+    pub fn cli_output(
+        &self,
+        id: NodeID,
+        args: &[&str],
+    ) -> Result<std::process::Output, Box<dyn Error>> {
+        let file = self.node_dir(id).join("toydb.yaml");
+        Self::run_bin("toydb", &[&["-c", &file.to_string_lossy()], args].concat())
+    }
+
+    /// Runs the toysql binary with the given arguments, returning its exit
+    /// status, stdout, and stderr.
+    pub fn toysql_output(args: &[&str]) -> Result<std::process::Output, Box<dyn Error>> {
+        Self::run_bin("toysql", args)
+    }
+
+    /// Runs the given binary with the given arguments, returning its exit
+    /// status, stdout, and stderr. Errors if it doesn't exit within
+    /// CLI_TIMEOUT.
+    fn run_bin(bin: &str, args: &[&str]) -> Result<std::process::Output, Box<dyn Error>> {
+        let build = escargot::CargoBuild::new().bin(bin).run()?;
+        let mut child = build
+            .command()
+            .args(args)
+            .stdin(std::process::Stdio::null())
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped())
+            .spawn()?;
+        let started = std::time::Instant::now();
+        while child.try_wait()?.is_none() {
+            if started.elapsed() >= CLI_TIMEOUT {
+                child.kill()?;
+                child.wait()?;
+                return Err(format!("{bin} {} didn't exit", args.join(" ")).into());
+            }
+            std::thread::sleep(Duration::from_millis(100));
+        }
+        Ok(child.wait_with_output()?)
+    }
+
+    /// Returns the cluster's temporary directory.
+    pub fn dir(&self) -> &Path {
+        self.dir.path()
+    }
+
+    /// Returns the current data directory of the given node.
+    fn node_dir(&self, id: NodeID) -> PathBuf {
+        match self.restores {
+            0 => self.dir.path().join(format!("toydb{id}")),
+            n => self.dir.path().join(format!("toydb{id}-restore{n}")),
+        }
+    }
+
+    /// Restores the cluster from the given backup and incremental backups as
+    /// of the given version, if any. It stops the cluster, runs `toydb
+    /// restore` against node 1's Raft log (or without it, if log is false),
+    /// copies the restored database to all nodes, and starts them with empty
+    /// Raft logs. If the restore fails, the original nodes are restarted.
+    pub fn restore(
+        &mut self,
+        backup: &Path,
+        incremental: &[PathBuf],
+        as_of: Option<u64>,
+        log: bool,
+    ) -> Result<(), Box<dyn Error>> {
+        let nodes = self.servers.len() as u8;
+        let config = self.node_dir(1).join("toydb.yaml");
+        self.servers.clear(); // kills the nodes
+        self.restores += 1;
+
+        let restored = self.node_dir(1);
+        let build = escargot::CargoBuild::new().bin("toydb").run()?;
+        let mut command = build.command();
+        command.args(["-c", &config.to_string_lossy()]);
+        if !log {
+            // Point the data directory elsewhere, such that there's no log.
+            command.args(["--data-dir", &self.dir.path().join("nolog").to_string_lossy()]);
+        }
+        command.args(["restore", &backup.to_string_lossy()]);
+        for path in incremental {
+            command.args(["--incremental", &path.to_string_lossy()]);
+        }
+        command.args(["--to", &restored.to_string_lossy()]);
+        if let Some(as_of) = as_of {
+            command.args(["--as-of", &as_of.to_string()]);
+        }
+        let output = command.output()?;
+        if !output.status.success() {
+            self.restores -= 1;
+            self.start(nodes)?;
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(stderr.trim().trim_start_matches("Error: ").into());
+        }
+        for id in 2..=nodes {
+            let dir = self.node_dir(id);
+            std::fs::create_dir_all(&dir)?;
+            std::fs::copy(restored.join("sql"), dir.join("sql"))?;
+        }
 
-        Ok(Self { servers, dir })
+        self.start(nodes)
     }
 
     /// Connects to a random cluster node using a Rust client. Testing with
     /// toysql is too annoying, since we have to deal with rustyline, PTYs,
     /// echoing, multiline editing, etc.
     pub fn connect(&self) -> Result<Client, Box<dyn Error>> {
-        let id = rand::thread_rng().gen_range(1..=self.servers.len()) as NodeID;
-        self.servers.get(&id).unwrap().connect()
+        self.servers.get(&self.random_node()).unwrap().connect()
+    }
+
+    /// Connects to the given node using a Rust client.
+    pub fn connect_node(&self, id: NodeID) -> Result<Client, Box<dyn Error>> {
+        self.servers.get(&id).ok_or_else(|| format!("unknown node {id}"))?.connect()
+    }
+
+    /// Connects to a random cluster node via its Unix socket. Authenticates as
+    /// the admin if authentication is enabled.
+    pub fn connect_unix(&self) -> Result<Client, Box<dyn Error>> {
+        let mut client =
+            Client::connect_unix(self.node_dir(self.random_node()).join("toydb.sock"))?;
+        if let Some(password) = self.admin_password() {
+            client.authenticate("admin", password)?;
+        }
+        Ok(client)
+    }
+
+    /// Connects a cluster client via the given nodes, which redirects to the
+    /// leader. Authenticates as the admin if authentication is enabled.
+    pub fn connect_cluster(&self, ids: &[NodeID]) -> Result<Client, Box<dyn Error>> {
+        let addrs = ids.iter().map(|id| format!("localhost:{}", SQL_BASE_PORT + *id as u16));
+        let addrs = addrs.collect_vec();
+        let mut client = match self.tls_ca() {
+            Some(ca) => Client::connect_cluster_tls(&addrs, ca)?,
+            None => Client::connect_cluster(&addrs)?,
+        };
+        if let Some(password) = self.admin_password() {
+            client.authenticate("admin", password)?;
+        }
+        Ok(client)
+    }
+
+    /// Returns the IDs of the running nodes.
+    pub fn nodes(&self) -> Vec<NodeID> {
+        self.servers.keys().copied().collect()
+    }
+
+    /// Kills the given node.
+    pub fn kill(&mut self, id: NodeID) -> Result<(), Box<dyn Error>> {
+        self.servers.remove(&id).map(drop).ok_or_else(|| format!("unknown node {id}").into())
+    }
+
+    /// Shuts down the given node gracefully, and waits for it to exit.
+    pub fn stop(&mut self, id: NodeID) -> Result<(), Box<dyn Error>> {
+        self.servers.get_mut(&id).ok_or_else(|| format!("unknown node {id}"))?.stop()?;
+        self.servers.remove(&id);
+        Ok(())
+    }
+
+    /// Corrupts the given node's SQL storage, by stopping it, writing the
+    /// given raw key and value, and restarting it.
+    pub fn corrupt(
+        &mut self,
+        id: NodeID,
+        key: &[u8],
+        value: Vec<u8>,
+    ) -> Result<(), Box<dyn Error>> {
+        self.stop(id)?;
+        let dir = self.node_dir(id);
+        let mut engine = BitCask::new(dir.join("sql"))?;
+        engine.set(key, value)?;
+        engine.flush()?;
+        drop(engine);
+        let (certs, password) = (self.certs(), self.admin_password.as_deref());
+        let configfile = dir.join("toydb.yaml");
+        let server = TestServer::spawn(id, &configfile, &self.ports, certs.as_deref(), password)?;
+        self.servers.insert(id, server);
+        self.wait_ready()
+    }
+
+    /// Returns the ID of a random running node.
+    fn random_node(&self) -> NodeID {
+        let index = rand::thread_rng().gen_range(0..self.servers.len());
+        *self.servers.keys().nth(index).unwrap()
+    }
+
+    /// Creates a connection pool for a random cluster node.
+    pub fn pool(&self, config: PoolConfig) -> Result<Pool, Box<dyn Error>> {
+        let server = self.servers.get(&self.random_node()).unwrap();
+        let (port, tls_ca) = (server.sql_port, server.tls_ca.clone());
+        let admin_password = server.admin_password.clone();
+        Ok(Pool::new(config, move || connect(port, tls_ca.as_deref(), admin_password.as_deref()))?)
+    }
+
+    /// Connects to a random cluster node using an async client.
+    pub async fn connect_async(&self) -> Result<AsyncClient, Box<dyn Error>> {
+        self.servers.get(&self.random_node()).unwrap().connect_async().await
+    }
+
+    /// Returns the SQL port of a random cluster node.
+    pub fn sql_port(&self) -> u16 {
+        SQL_BASE_PORT + self.random_node() as u16
+    }
+
+    /// Returns the Postgres port of a random cluster node.
+    pub fn pg_port(&self) -> u16 {
+        PG_BASE_PORT + self.random_node() as u16
+    }
+
+    /// Returns the HTTP port of a random cluster node.
+    pub fn http_port(&self) -> u16 {
+        HTTP_BASE_PORT + self.random_node() as u16
+    }
+
+    /// Returns the gRPC port of a random cluster node.
+    pub fn grpc_port(&self) -> u16 {
+        GRPC_BASE_PORT + self.random_node() as u16
     }
 }
 
@@ -79,16 +514,39 @@ pub struct TestServer {
     id: NodeID,
     child: std::process::Child,
     sql_port: u16,
+    /// The CA certificate file, if TLS is enabled.
+    tls_ca: Option<PathBuf>,
+    /// The admin password, if authentication is enabled.
+    admin_password: Option<String>,
 }
 
 impl TestServer {
-    /// Runs a toyDB server.
-    fn run(id: NodeID, dir: &Path, ports: &NodePorts) -> Result<Self, Box<dyn Error>> {
+    /// Runs a toyDB server. If given a certificate directory, it uses TLS. If
+    /// given an admin password, it requires authentication.
+    fn run(
+        id: NodeID,
+        dir: &Path,
+        ports: &NodePorts,
+        certs: Option<&Path>,
+        admin_password: Option<&str>,
+        config: &[(String, String)],
+    ) -> Result<Self, Box<dyn Error>> {
         // Build and write the configuration file.
         let configfile = dir.join("toydb.yaml");
         std::fs::create_dir_all(dir)?;
-        std::fs::write(&configfile, Self::build_config(id, dir, ports)?)?;
+        let config = Self::build_config(id, dir, ports, certs, admin_password, config)?;
+        std::fs::write(&configfile, config)?;
+        Self::spawn(id, &configfile, ports, certs, admin_password)
+    }
 
+    /// Runs a toyDB server with the given config file.
+    fn spawn(
+        id: NodeID,
+        configfile: &Path,
+        ports: &NodePorts,
+        certs: Option<&Path>,
+        admin_password: Option<&str>,
+    ) -> Result<Self, Box<dyn Error>> {
         // Build the binary.
         //
         // TODO: this may contribute to slow tests, consider building once.
@@ -103,17 +561,46 @@ impl TestServer {
             .spawn()?;
 
         let (_, sql_port) = ports.get(&id).copied().expect("node not in ports");
-        Ok(Self { id, child, sql_port })
+        let tls_ca = certs.map(|certs| certs.join("ca.pem"));
+        let admin_password = admin_password.map(|password| password.to_string());
+        Ok(Self { id, child, sql_port, tls_ca, admin_password })
     }
 
     /// Generates a config file for the given node.
-    fn build_config(id: NodeID, dir: &Path, ports: &NodePorts) -> Result<String, Box<dyn Error>> {
+    fn build_config(
+        id: NodeID,
+        dir: &Path,
+        ports: &NodePorts,
+        certs: Option<&Path>,
+        admin_password: Option<&str>,
+        config: &[(String, String)],
+    ) -> Result<String, Box<dyn Error>> {
         let (raft_port, sql_port) = ports.get(&id).expect("node not in ports");
         let mut cfg = String::new();
         writeln!(cfg, "id: {id}")?;
         writeln!(cfg, "data_dir: {}", dir.to_string_lossy())?;
         writeln!(cfg, "listen_raft: localhost:{raft_port}")?;
         writeln!(cfg, "listen_sql: localhost:{sql_port}")?;
+        writeln!(cfg, "listen_pg: localhost:{}", PG_BASE_PORT + id as u16)?;
+        writeln!(cfg, "listen_http: localhost:{}", HTTP_BASE_PORT + id as u16)?;
+        writeln!(cfg, "listen_grpc: localhost:{}", GRPC_BASE_PORT + id as u16)?;
+        writeln!(cfg, "listen_unix: {}", dir.join("toydb.sock").to_string_lossy())?;
+        writeln!(cfg, "gc_retention: 2")?; // garbage collected via the gc command
+        writeln!(cfg, "gc_interval: 0")?;
+        writeln!(cfg, "frame_size: 100")?; // stream SELECT rows in several frames
+        writeln!(cfg, "shutdown_grace: 1")?;
+        if let Some(certs) = certs {
+            writeln!(cfg, "tls_cert: {}", certs.join("node.pem").to_string_lossy())?;
+            writeln!(cfg, "tls_key: {}", certs.join("node.key").to_string_lossy())?;
+            writeln!(cfg, "tls_ca: {}", certs.join("ca.pem").to_string_lossy())?;
+            writeln!(cfg, "tls_peer_auth: true")?;
+        }
+        if let Some(password) = admin_password {
+            writeln!(cfg, "admin_password: {password}")?;
+        }
+        for (key, value) in config {
+            writeln!(cfg, "{key}: {value}")?;
+        }
         write!(cfg, "peers: {{")?;
         if ports.len() > 1 {
             writeln!(cfg)?;
@@ -122,6 +609,14 @@ impl TestServer {
             write!(cfg, "  '{peer_id}': localhost:{peer_raft_port},")?;
         }
         writeln!(cfg, "}}")?;
+        write!(cfg, "peers_sql: {{")?;
+        if ports.len() > 1 {
+            writeln!(cfg)?;
+        }
+        for (peer_id, (_, peer_sql_port)) in ports.iter().filter(|(peer, _)| **peer != id) {
+            write!(cfg, "  '{peer_id}': localhost:{peer_sql_port},")?;
+        }
+        writeln!(cfg, "}}")?;
         Ok(cfg)
     }
 
@@ -132,9 +627,50 @@ impl TestServer {
         }
     }
 
-    /// Connects to the server using a regular client.
+    /// Sends the given signal to the server process.
+    fn signal(&self, signal: libc::c_int) -> Result<(), Box<dyn Error>> {
+        // SAFETY: sends a signal to the child process, which is still running
+        // since it hasn't been waited for.
+        if unsafe { libc::kill(self.child.id() as libc::pid_t, signal) } != 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        Ok(())
+    }
+
+    /// Shuts down the server gracefully via SIGTERM, and waits for it to exit
+    /// successfully.
+    fn stop(&mut self) -> Result<(), Box<dyn Error>> {
+        self.signal(libc::SIGTERM)?;
+        let started = std::time::Instant::now();
+        while started.elapsed() < STOP_TIMEOUT {
+            if let Some(status) = self.child.try_wait()? {
+                return match status.success() {
+                    true => Ok(()),
+                    false => Err(format!("node {} exited with {status}", self.id).into()),
+                };
+            }
+            std::thread::sleep(Duration::from_millis(100));
+        }
+        Err(format!("node {} didn't shut down", self.id).into())
+    }
+
+    /// Connects to the server using a regular client, authenticating as the
+    /// admin if authentication is enabled.
     fn connect(&self) -> Result<Client, Box<dyn Error>> {
-        Ok(Client::connect(("localhost", self.sql_port))?)
+        Ok(connect(self.sql_port, self.tls_ca.as_deref(), self.admin_password.as_deref())?)
+    }
+
+    /// Connects to the server using an async client, authenticating as the
+    /// admin if authentication is enabled.
+    async fn connect_async(&self) -> Result<AsyncClient, Box<dyn Error>> {
+        let mut client = match &self.tls_ca {
+            Some(ca) => AsyncClient::connect_tls("localhost", self.sql_port, ca).await?,
+            None => AsyncClient::connect(("localhost", self.sql_port)).await?,
+        };
+        if let Some(password) = &self.admin_password {
+            client.authenticate("admin", password).await?;
+        }
+        Ok(client)
     }
 }
 
@@ -145,3 +681,31 @@ impl Drop for TestServer {
         self.child.wait().expect("failed to wait for node to terminate");
     }
 }
+
+/// Merges config setting changes into the given settings, replacing existing
+/// values of the same key.
+fn merge_config(config: &mut Vec<(String, String)>, changes: Vec<(String, String)>) {
+    for (key, value) in changes {
+        match config.iter_mut().find(|(k, _)| *k == key) {
+            Some(setting) => setting.1 = value,
+            None => config.push((key, value)),
+        }
+    }
+}
+
+/// Connects a client to the given local port, using TLS with the given CA
+/// certificate if any, and authenticating as the admin if given a password.
+fn connect(
+    port: u16,
+    tls_ca: Option<&Path>,
+    admin_password: Option<&str>,
+) -> toydb::error::Result<Client> {
+    let mut client = match tls_ca {
+        Some(ca) => Client::connect_tls("localhost", port, ca)?,
+        None => Client::connect(("localhost", port))?,
+    };
+    if let Some(password) = admin_password {
+        client.authenticate("admin", password)?;
+    }
+    Ok(client)
+}
@@ -7,19 +7,36 @@
 
 #![warn(clippy::all)]
 
+mod grpcclient;
+mod httpclient;
+mod pgclient;
 mod testcluster;
 
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
 use std::error::Error;
 use std::fmt::Write as _;
 use std::path::Path;
 use std::sync::{LazyLock, Mutex};
+use std::time::Duration;
 
 use itertools::Itertools as _;
+use rand::Rng as _;
 use test_each_file::test_each_path;
 
+use grpcclient::GrpcClient;
+use httpclient::HttpClient;
+use pgclient::PgClient;
 use testcluster::TestCluster;
-use toydb::{Client, StatementResult};
+use toydb::client::{Migration, PoolConfig, PooledClient};
+use toydb::clock::Timestamp;
+use toydb::encoding::{Key as _, Value as _, bincode};
+use toydb::fault::Faults;
+use toydb::history::{History, Key, Op, Outcome, Recorder};
+use toydb::raft::NodeID;
+use toydb::server::{Capabilities, Consistency, Health, Request, Response};
+use toydb::sql::execution::Returning;
+use toydb::storage::mvcc;
+use toydb::{AsyncClient, Client, Pool, StatementResult};
 
 // Run goldenscript tests in tests/scripts.
 test_each_path! { in "tests/scripts" => test_goldenscript }
@@ -40,6 +57,22 @@ fn test_goldenscript(path: &Path) {
 struct Runner {
     cluster: Option<TestCluster>,
     clients: HashMap<String, Client>,
+    pg_clients: HashMap<String, PgClient>,
+    http_clients: HashMap<String, HttpClient>,
+    grpc_clients: HashMap<String, GrpcClient>,
+    async_clients: HashMap<String, AsyncClient>,
+    pool: Option<Pool>,
+    /// Clients checked out from the pool. These are used instead of regular
+    /// clients for their prefix.
+    pooled_clients: HashMap<String, PooledClient>,
+    /// The Tokio runtime for async clients, created on first use.
+    runtime: Option<tokio::runtime::Runtime>,
+    /// Nodes with injected faults. Requests forwarded by them to the leader
+    /// may be lost, so workloads don't connect via them.
+    faulty: BTreeSet<NodeID>,
+    /// The latest clock timestamp returned by the clock command, substituted
+    /// for $clock in SQL statements.
+    timestamp: Option<Timestamp>,
 }
 
 impl Runner {
@@ -50,6 +83,9 @@ impl Runner {
     /// Fetches a client for the given prefix, or creates a new one.
     fn get_client(&mut self, prefix: &Option<String>) -> Result<&mut Client, Box<dyn Error>> {
         let name = Self::client_name(prefix);
+        if self.pooled_clients.contains_key(name) {
+            return Ok(self.pooled_clients.get_mut(name).expect("no client"));
+        }
         if !self.clients.contains_key(name) {
             let Some(cluster) = self.cluster.as_mut() else {
                 return Err("no cluster".into());
@@ -60,6 +96,107 @@ impl Runner {
         Ok(self.clients.get_mut(name).expect("no client"))
     }
 
+    /// Fetches a Postgres client for the given prefix, or creates a new one.
+    fn get_pg_client(&mut self, prefix: &Option<String>) -> Result<&mut PgClient, Box<dyn Error>> {
+        let name = Self::client_name(prefix);
+        if !self.pg_clients.contains_key(name) {
+            let Some(cluster) = self.cluster.as_mut() else {
+                return Err("no cluster".into());
+            };
+            let client = PgClient::connect(
+                cluster.pg_port(),
+                cluster.tls_ca().as_deref(),
+                cluster.admin_password(),
+            )?;
+            self.pg_clients.insert(name.to_string(), client);
+        }
+        Ok(self.pg_clients.get_mut(name).expect("no client"))
+    }
+
+    /// Fetches an HTTP client for the given prefix, or creates a new one.
+    fn get_http_client(
+        &mut self,
+        prefix: &Option<String>,
+    ) -> Result<&mut HttpClient, Box<dyn Error>> {
+        let name = Self::client_name(prefix);
+        if !self.http_clients.contains_key(name) {
+            let Some(cluster) = self.cluster.as_mut() else {
+                return Err("no cluster".into());
+            };
+            let client = HttpClient::connect(cluster.http_port(), cluster.tls_ca().as_deref())?;
+            self.http_clients.insert(name.to_string(), client);
+        }
+        Ok(self.http_clients.get_mut(name).expect("no client"))
+    }
+
+    /// Fetches a gRPC client for the given prefix, or creates a new one.
+    fn get_grpc_client(
+        &mut self,
+        prefix: &Option<String>,
+    ) -> Result<&mut GrpcClient, Box<dyn Error>> {
+        let name = Self::client_name(prefix);
+        if !self.grpc_clients.contains_key(name) {
+            let Some(cluster) = self.cluster.as_mut() else {
+                return Err("no cluster".into());
+            };
+            let client = GrpcClient::connect(
+                cluster.grpc_port(),
+                cluster.tls_ca().as_deref(),
+                cluster.admin_password(),
+            )?;
+            self.grpc_clients.insert(name.to_string(), client);
+        }
+        Ok(self.grpc_clients.get_mut(name).expect("no client"))
+    }
+
+    /// Runs the given statement via the async client for the given prefix,
+    /// creating the client if necessary. Up to limit rows are streamed and
+    /// formatted as they're received, with a column header if requested. The
+    /// remaining rows are dropped.
+    fn execute_async(
+        &mut self,
+        prefix: &Option<String>,
+        statement: &str,
+        header: bool,
+        limit: usize,
+    ) -> Result<String, Box<dyn Error>> {
+        let cluster = self.cluster.as_ref().ok_or("no cluster")?;
+        let runtime = match &mut self.runtime {
+            Some(runtime) => runtime,
+            runtime => {
+                runtime.insert(tokio::runtime::Builder::new_current_thread().enable_all().build()?)
+            }
+        };
+        let name = Self::client_name(prefix);
+        runtime.block_on(async {
+            if !self.async_clients.contains_key(name) {
+                self.async_clients.insert(name.to_string(), cluster.connect_async().await?);
+            }
+            let client = self.async_clients.get_mut(name).expect("no client");
+            let mut output = String::new();
+            let mut rows = client.query(statement).await?;
+            if header {
+                writeln!(output, "{}", rows.columns().iter().join(", "))?;
+            }
+            for _ in 0..limit {
+                let Some(row) = rows.next().await? else { break };
+                writeln!(output, "{}", row.into_iter().join(", "))?;
+            }
+            Ok(output)
+        })
+    }
+
+    /// Drops all clients and the pool, e.g. when nodes are restarted.
+    fn clear_clients(&mut self) {
+        self.clients.clear();
+        self.pg_clients.clear();
+        self.http_clients.clear();
+        self.grpc_clients.clear();
+        self.async_clients.clear();
+        self.pooled_clients.clear();
+        self.pool = None;
+    }
+
     /// Returns a client name for a prefix.
     fn client_name(prefix: &Option<String>) -> &str {
         prefix.as_deref().unwrap_or_default()
@@ -74,6 +211,140 @@ impl goldenscript::Runner for Runner {
 
         // Handle simple, non-SQL commands.
         match command.name.as_str() {
+            // authenticate USER PASSWORD
+            "authenticate" => {
+                let mut args = command.consume_args();
+                let user = args.next_pos().ok_or("user not given")?.value.clone();
+                let password = args.next_pos().ok_or("password not given")?.value.clone();
+                args.reject_rest()?;
+                self.get_client(&command.prefix)?.authenticate(&user, &password)?;
+                return Ok(output);
+            }
+
+            // async_query QUERY rows=N
+            //
+            // Runs a query via an async client, fetching only the first N
+            // rows and dropping the rest.
+            "async_query" => {
+                let mut args = command.consume_args();
+                let query = args.next_pos().ok_or("query not given")?.value.clone();
+                let limit = args.lookup_parse("rows")?.ok_or("rows not given")?;
+                args.reject_rest()?;
+                return self.execute_async(&command.prefix, &query, false, limit);
+            }
+
+            // await_leader
+            //
+            // Waits for the prefix's client to be connected to a leader,
+            // retrying requests to let the client redirect to it.
+            "await_leader" => {
+                command.consume_args().reject_rest()?;
+                let client = self.get_client(&command.prefix)?;
+                let started = std::time::Instant::now();
+                loop {
+                    match client.status() {
+                        Ok(status) if status.server == status.raft.leader => break,
+                        Ok(_) | Err(_) if started.elapsed() < Duration::from_secs(10) => {
+                            std::thread::sleep(Duration::from_millis(100))
+                        }
+                        Ok(status) => return Err(format!("not leader: {status:?}").into()),
+                        Err(error) => return Err(error.into()),
+                    }
+                }
+                writeln!(output, "connected to leader")?;
+                return Ok(output);
+            }
+
+            // backup FILE [since=INDEX]
+            //
+            // Takes a full backup, or an incremental backup since the given
+            // Raft log index.
+            "backup" => {
+                let mut args = command.consume_args();
+                let file = args.next_pos().ok_or("file not given")?.value.clone();
+                let since = args.lookup_parse("since")?;
+                args.reject_rest()?;
+                let path = self.cluster.as_ref().ok_or("no cluster")?.dir().join(file);
+                let client = self.get_client(&command.prefix)?;
+                let index = match since {
+                    Some(since) => client.backup_incremental(path, since)?,
+                    None => client.backup(path)?,
+                };
+                writeln!(output, "index={index}")?;
+                return Ok(output);
+            }
+
+            // batch STATEMENT...
+            //
+            // Executes the statements in a single batch request, and outputs
+            // each result. SELECT and EXPLAIN results are output as rows.
+            "batch" => {
+                let mut args = command.consume_args();
+                let statements = args.rest_pos().iter().map(|arg| arg.value.as_str()).collect_vec();
+                args.reject_rest()?;
+                let results = self.get_client(&command.prefix)?.execute_batch(&statements)?;
+                for result in results {
+                    match result {
+                        Ok(StatementResult::Select { rows, .. }) => {
+                            for row in rows {
+                                writeln!(output, "{}", row.into_iter().join(", "))?;
+                            }
+                        }
+                        Ok(StatementResult::Explain(root)) => writeln!(output, "{root}")?,
+                        Ok(result) => writeln!(output, "{result:?}")?,
+                        Err(error) => writeln!(output, "Error: {error}")?,
+                    }
+                }
+                return Ok(output);
+            }
+
+            // changes TABLE [AFTER] [limit=N]
+            "changes" => {
+                let mut args = command.consume_args();
+                let table = args.next_pos().ok_or("table not given")?.value.clone();
+                let after = args.next_pos().map(|a| a.parse()).transpose()?.unwrap_or(0);
+                let limit = args.lookup_parse("limit")?.unwrap_or(usize::MAX);
+                args.reject_rest()?;
+                for change in self.get_client(&command.prefix)?.changes(&table, after, limit)? {
+                    writeln!(output, "{change:?}")?;
+                }
+                return Ok(output);
+            }
+
+            // changefeed TABLE [AFTER] count=N
+            "changefeed" => {
+                let mut args = command.consume_args();
+                let table = args.next_pos().ok_or("table not given")?.value.clone();
+                let after = args.next_pos().map(|a| a.parse()).transpose()?.unwrap_or(0);
+                let count = args.lookup_parse("count")?.ok_or("count not given")?;
+                args.reject_rest()?;
+                let client = self.get_client(&command.prefix)?;
+                let changefeed = client.changefeed(&table, after, Duration::from_millis(10));
+                for change in changefeed.take(count) {
+                    writeln!(output, "{:?}", change?)?;
+                }
+                return Ok(output);
+            }
+
+            // clock
+            //
+            // Synchronizes the client's clock with its server, after observing
+            // the previous timestamp returned by this command (possibly from a
+            // different node). The returned timestamp is substituted for
+            // $clock in subsequent SQL statements.
+            "clock" => {
+                command.consume_args().reject_rest()?;
+                let previous = self.timestamp.unwrap_or_default();
+                let client = self.get_client(&command.prefix)?;
+                client.observe_timestamp(previous);
+                let timestamp = client.clock()?;
+                if timestamp <= previous {
+                    return Err(format!("timestamp {timestamp} not after {previous}").into());
+                }
+                self.timestamp = Some(timestamp);
+                return Ok(output);
+            }
+
             // close
             "close" => {
                 command.consume_args().reject_rest()?;
@@ -84,15 +355,744 @@ impl goldenscript::Runner for Runner {
                 return Ok(output);
             }
 
-            // cluster nodes=N
+            // check_config KEY=VALUE... [TOYDB_VAR=VALUE...] [--flag=VALUE...]
+            //
+            // Validates a node config with the given changed settings via
+            // `toydb check`. TOYDB_ keys are passed as environment variables,
+            // and -- keys as command-line flags.
+            "check_config" => {
+                let mut args = command.consume_args();
+                let (mut changes, mut env, mut flags) = (Vec::new(), Vec::new(), Vec::new());
+                for arg in args.rest_key() {
+                    let (key, value) = (arg.key.clone().unwrap(), arg.value.clone());
+                    if key.starts_with("TOYDB_") {
+                        env.push((key, value));
+                    } else if key.starts_with("--") {
+                        flags.push(format!("{key}={value}"));
+                    } else {
+                        changes.push((key, value));
+                    }
+                }
+                args.reject_rest()?;
+                self.cluster.as_ref().ok_or("no cluster")?.check_config(changes, env, flags)?;
+                writeln!(output, "ok")?;
+                return Ok(output);
+            }
+
+            // check_consistency [summary=BOOL]
+            //
+            // Checks replica consistency, omitting the index and version. If
+            // summary is true, it only outputs each node's consistency
+            // without errors, sorted, since the node IDs vary.
+            "check_consistency" => {
+                let mut args = command.consume_args();
+                let summary = args.lookup_parse("summary")?.unwrap_or(false);
+                args.reject_rest()?;
+                let check = self.get_client(&command.prefix)?.check_consistency()?;
+                if summary {
+                    let nodes = check.nodes.values().map(|consistency| match consistency {
+                        Consistency::Consistent => "consistent".to_string(),
+                        Consistency::Divergent(ranges) => {
+                            format!("divergent: {}", ranges.join(", "))
+                        }
+                        Consistency::Unavailable(_) => "unavailable".to_string(),
+                    });
+                    for node in nodes.sorted() {
+                        writeln!(output, "{node}")?;
+                    }
+                } else {
+                    for line in check.to_string().lines().skip(1) {
+                        writeln!(output, "{line}")?;
+                    }
+                }
+                writeln!(output, "consistent={}", check.is_consistent())?;
+                return Ok(output);
+            }
+
+            // checkout
+            //
+            // Checks out a client from the pool for the prefix, which is used
+            // for its commands until checked in.
+            "checkout" => {
+                command.consume_args().reject_rest()?;
+                let name = Self::client_name(&command.prefix).to_string();
+                if self.pooled_clients.contains_key(&name) {
+                    return Err(format!("client {name} already checked out").into());
+                }
+                let client = self.pool.as_ref().ok_or("no pool")?.get()?;
+                self.pooled_clients.insert(name, client);
+                return Ok(output);
+            }
+
+            // checkin
+            //
+            // Returns the prefix's checked out client to the pool.
+            "checkin" => {
+                command.consume_args().reject_rest()?;
+                let name = Self::client_name(&command.prefix);
+                if self.pooled_clients.remove(name).is_none() {
+                    return Err(format!("client {name} not checked out").into());
+                }
+                return Ok(output);
+            }
+
+            // cluster nodes=N [tls=BOOL] [admin_password=PASSWORD] [KEY=VALUE...]
+            //
+            // Other key arguments are added to the nodes' config files.
             "cluster" => {
                 let mut args = command.consume_args();
                 let nodes = args.lookup_parse("nodes")?.unwrap_or(0);
+                let tls = args.lookup_parse("tls")?.unwrap_or(false);
+                let admin_password = args.lookup("admin_password").map(|arg| arg.value.clone());
+                let config = args
+                    .rest_key()
+                    .into_iter()
+                    .map(|arg| (arg.key.clone().unwrap(), arg.value.clone()))
+                    .collect();
                 args.reject_rest()?;
                 if self.cluster.is_some() {
                     return Err("cluster already exists".into());
                 }
-                self.cluster = Some(TestCluster::run(nodes)?);
+                self.cluster = Some(TestCluster::run(nodes, tls, admin_password, config)?);
+                return Ok(output);
+            }
+
+            // cluster_status [cli=BOOL] [summary=BOOL]
+            //
+            // Outputs the cluster status, as fetched by the prefix's client.
+            // If cli is true, it's instead fetched by running `toydb status`
+            // against node 1. If summary is true, it only outputs the health
+            // and role of each node, sorted, since the node IDs of the leader
+            // and followers vary. It then first waits for a reachable leader
+            // that all reachable nodes know of.
+            "cluster_status" => {
+                let mut args = command.consume_args();
+                let cli = args.lookup_parse("cli")?.unwrap_or(false);
+                let summary = args.lookup_parse("summary")?.unwrap_or(false);
+                args.reject_rest()?;
+                if cli {
+                    let cluster = self.cluster.as_ref().ok_or("no cluster")?;
+                    return cluster.status_cli(1);
+                }
+                let client = self.get_client(&command.prefix)?;
+                if !summary {
+                    write!(output, "{}", client.cluster_status()?)?;
+                    return Ok(output);
+                }
+                let started = std::time::Instant::now();
+                let status = loop {
+                    let status = client.cluster_status()?;
+                    let leaderless = status.nodes.values().any(|n| n.health == Health::NoLeader);
+                    let leader = status.leader.and_then(|id| status.nodes.get(&id));
+                    let settled = !leaderless && leader.is_some_and(|n| n.status.is_some());
+                    if settled || started.elapsed() >= Duration::from_secs(10) {
+                        break status;
+                    }
+                    std::thread::sleep(Duration::from_millis(100));
+                };
+                let nodes = status.nodes.values().map(|node| {
+                    let role = node.status.as_ref().map(|s| s.raft.role.to_string());
+                    match node.health {
+                        Health::Unreachable(_) => "unreachable".to_string(),
+                        ref health => format!("{health} {}", role.unwrap_or_default()),
+                    }
+                });
+                for node in nodes.sorted() {
+                    writeln!(output, "{node}")?;
+                }
+                writeln!(output, "leader={}", status.leader.is_some())?;
+                return Ok(output);
+            }
+
+            // connect_cluster [via=leader|follower|all]
+            //
+            // Replaces the prefix's client with a cluster client seeded with
+            // the leader, a follower, or all nodes (default), which redirects
+            // to the leader.
+            "connect_cluster" => {
+                let mut args = command.consume_args();
+                let via = args.lookup("via").map(|arg| arg.value.clone());
+                args.reject_rest()?;
+                let cluster = self.cluster.as_ref().ok_or("no cluster")?;
+                let leader = cluster.connect()?.status()?.raft.leader;
+                let nodes = cluster.nodes();
+                let seeds = match via.as_deref().unwrap_or("all") {
+                    "all" => nodes,
+                    "leader" => vec![leader],
+                    "follower" => nodes.into_iter().filter(|id| *id != leader).take(1).collect(),
+                    via => return Err(format!("invalid via {via}").into()),
+                };
+                let client = cluster.connect_cluster(&seeds)?;
+                self.clients.insert(Self::client_name(&command.prefix).to_string(), client);
+                return Ok(output);
+            }
+
+            // connect_node ID|follower
+            //
+            // Replaces the prefix's client with a client connected to the
+            // given node, or to any follower of the current leader.
+            "connect_node" => {
+                let mut args = command.consume_args();
+                let node = args.next_pos().ok_or("node ID not given")?.value.clone();
+                args.reject_rest()?;
+                let cluster = self.cluster.as_ref().ok_or("no cluster")?;
+                let id = match node.as_str() {
+                    "follower" => {
+                        let leader = cluster.connect()?.status()?.raft.leader;
+                        let mut followers = cluster.nodes().into_iter().filter(|id| *id != leader);
+                        followers.next().ok_or("no followers")?
+                    }
+                    id => id.parse()?,
+                };
+                let client = cluster.connect_node(id)?;
+                self.clients.insert(Self::client_name(&command.prefix).to_string(), client);
+                return Ok(output);
+            }
+
+            // connect_unix
+            //
+            // Connects the client via a Unix socket.
+            "connect_unix" => {
+                command.consume_args().reject_rest()?;
+                let client = self.cluster.as_ref().ok_or("no cluster")?.connect_unix()?;
+                self.clients.insert(Self::client_name(&command.prefix).to_string(), client);
+                return Ok(output);
+            }
+
+            // copy STATEMENT [LINE...]
+            //
+            // Executes a COPY FROM STDIN statement, streaming the given lines
+            // as data, and outputs its result.
+            "copy" => {
+                let mut args = command.consume_args();
+                let statement = args.next_pos().ok_or("statement not given")?.value.clone();
+                let data = args.rest_pos().iter().map(|arg| format!("{}\n", arg.value)).join("");
+                args.reject_rest()?;
+                let result =
+                    self.get_client(&command.prefix)?.copy_from(&statement, data.as_bytes())?;
+                writeln!(output, "{result:?}")?;
+                return Ok(output);
+            }
+
+            // corrupt NODE|follower [divergent=BOOL]
+            //
+            // Corrupts the SQL storage of the given node or the first follower
+            // while it's stopped, and restarts it. By default, it writes an undecodable key. If divergent is
+            // true, it instead writes a valid but visible idempotency token
+            // that other replicas don't have.
+            "corrupt" => {
+                let mut args = command.consume_args();
+                let node = args.next_pos().ok_or("node not given")?.value.clone();
+                let divergent = args.lookup_parse("divergent")?.unwrap_or(false);
+                args.reject_rest()?;
+                let (key, value) = match divergent {
+                    // The SQL engine's Key::Idempotency("divergent") at the
+                    // first MVCC version, with an arbitrary result.
+                    true => (
+                        mvcc::Key::Version(b"\x04divergent\x00\x00".as_slice().into(), 1).encode(),
+                        bincode::serialize(&Some(b"result".to_vec())),
+                    ),
+                    false => (b"\xffcorrupt".to_vec(), b"garbage".to_vec()),
+                };
+                let cluster = self.cluster.as_mut().ok_or("no cluster")?;
+                let id = match node.as_str() {
+                    "follower" => {
+                        let leader = cluster.connect()?.status()?.raft.leader;
+                        cluster.nodes().into_iter().find(|id| *id != leader).ok_or("no follower")?
+                    }
+                    id => id.parse()?,
+                };
+                cluster.corrupt(id, &key, value)?;
+                self.clear_clients(); // the node was restarted
+                return Ok(output);
+            }
+
+            // decommission NODE [via=ID] [stop=BOOL]
+            //
+            // Decommissions the node by running `toydb decommission` with the
+            // config of the given node (default the decommissioned one), and
+            // outputs whether its data directory was retired. Since the Raft
+            // leader varies, the command output isn't included. If stop is
+            // true, the node is then shut down.
+            "decommission" => {
+                let mut args = command.consume_args();
+                let node = args.next_pos().ok_or("node not given")?.parse()?;
+                let via = args.lookup_parse("via")?.unwrap_or(node);
+                let stop = args.lookup_parse("stop")?.unwrap_or(false);
+                args.reject_rest()?;
+                let cluster = self.cluster.as_mut().ok_or("no cluster")?;
+                cluster.decommission_cli(via, node)?;
+                let retired = cluster.dir().join(format!("toydb{node}/RETIRED")).exists();
+                writeln!(output, "decommissioned n{node} retired={retired}")?;
+                if stop {
+                    cluster.stop(node)?;
+                    self.clear_clients();
+                }
+                return Ok(output);
+            }
+
+            // faults NODE|follower|all [drop=FLOAT] [duplicate=FLOAT]
+            //   [delay=MS] [fail_flush=BOOL] [corrupt_read=FLOAT]
+            //
+            // Injects faults into the given node, the first follower, or all
+            // nodes, replacing their current faults. Without faults, the nodes
+            // are healed. Requires fault_injection=true.
+            "faults" => {
+                let mut args = command.consume_args();
+                let node = args.next_pos().ok_or("node not given")?.value.clone();
+                let faults = Faults {
+                    drop: args.lookup_parse("drop")?.unwrap_or(0.0),
+                    duplicate: args.lookup_parse("duplicate")?.unwrap_or(0.0),
+                    delay: Duration::from_millis(args.lookup_parse("delay")?.unwrap_or(0)),
+                    fail_flush: args.lookup_parse("fail_flush")?.unwrap_or(false),
+                    corrupt_read: args.lookup_parse("corrupt_read")?.unwrap_or(0.0),
+                    partition: BTreeSet::new(),
+                };
+                args.reject_rest()?;
+                let cluster = self.cluster.as_ref().ok_or("no cluster")?;
+                let nodes = match node.as_str() {
+                    "all" => cluster.nodes(),
+                    "follower" => {
+                        let leader = cluster.connect()?.status()?.raft.leader;
+                        cluster.nodes().into_iter().filter(|id| *id != leader).take(1).collect()
+                    }
+                    id => vec![id.parse()?],
+                };
+                for id in nodes {
+                    cluster.connect_node(id)?.inject_faults(faults.clone())?;
+                    match faults == Faults::default() {
+                        true => self.faulty.remove(&id),
+                        false => self.faulty.insert(id),
+                    };
+                }
+                return Ok(output);
+            }
+
+            // gc
+            "gc" => {
+                command.consume_args().reject_rest()?;
+                let gc = self.get_client(&command.prefix)?.gc()?;
+                write!(output, "{gc:?}")?;
+                return Ok(output);
+            }
+
+            // grpc QUERY [PARAM...] [user=USER password=PASSWORD]
+            //
+            // Executes a statement via the gRPC Sql.Execute call. Params are
+            // given as JSON. Uses the admin password if not given a user.
+            "grpc" => {
+                let mut args = command.consume_args();
+                let user = args.lookup("user").map(|arg| arg.value.clone());
+                let password = args.lookup("password").map(|arg| arg.value.clone());
+                let query = args.next_pos().ok_or("query not given")?.value.clone();
+                let params: Vec<_> = args
+                    .rest_pos()
+                    .iter()
+                    .map(|arg| grpcclient::parse_param(&arg.value))
+                    .try_collect()?;
+                args.reject_rest()?;
+                let credentials = user.as_deref().map(|u| (u, password.as_deref().unwrap_or("")));
+                let client = self.get_grpc_client(&command.prefix)?;
+                for line in client.execute(&query, params, credentials)? {
+                    writeln!(output, "{line}")?;
+                }
+                return Ok(output);
+            }
+
+            // grpc_admin status|tables|gc [user=USER password=PASSWORD]
+            // grpc_admin table NAME [user=USER password=PASSWORD]
+            //
+            // Calls a gRPC Admin service method.
+            "grpc_admin" => {
+                let mut args = command.consume_args();
+                let user = args.lookup("user").map(|arg| arg.value.clone());
+                let password = args.lookup("password").map(|arg| arg.value.clone());
+                let method = args.next_pos().ok_or("method not given")?.value.clone();
+                let table = args.next_pos().map(|arg| arg.value.clone());
+                args.reject_rest()?;
+                let credentials = user.as_deref().map(|u| (u, password.as_deref().unwrap_or("")));
+                let client = self.get_grpc_client(&command.prefix)?;
+                let lines = match (method.as_str(), table) {
+                    ("status", None) => vec![client.status(credentials)?],
+                    ("tables", None) => client.list_tables(credentials)?,
+                    ("table", Some(table)) => client.get_table(&table, credentials)?,
+                    ("gc", None) => vec![client.gc(credentials)?],
+                    _ => return Err(format!("invalid grpc_admin method {method}").into()),
+                };
+                for line in lines {
+                    writeln!(output, "{line}")?;
+                }
+                return Ok(output);
+            }
+
+            // grpc_session execute QUERY [PARAM...]
+            // grpc_session begin [read_only=BOOL] [as_of=VERSION]
+            // grpc_session commit|rollback|close
+            //
+            // Sends a request on the gRPC Sql.Session stream, opening it if
+            // necessary, or closes it. Params are given as JSON.
+            "grpc_session" => {
+                use toydb::grpc::proto;
+                use toydb::grpc::proto::session_request::Request;
+                let mut args = command.consume_args();
+                let user = args.lookup("user").map(|arg| arg.value.clone());
+                let password = args.lookup("password").map(|arg| arg.value.clone());
+                let read_only = args.lookup_parse("read_only")?.unwrap_or(false);
+                let as_of = args.lookup_parse("as_of")?;
+                let kind = args.next_pos().ok_or("request not given")?.value.clone();
+                let request = match kind.as_str() {
+                    "execute" => {
+                        let query = args.next_pos().ok_or("query not given")?.value.clone();
+                        let params = args
+                            .rest_pos()
+                            .iter()
+                            .map(|arg| grpcclient::parse_param(&arg.value))
+                            .try_collect()?;
+                        Some(Request::Execute(proto::ExecuteRequest { query, params }))
+                    }
+                    "begin" => Some(Request::Begin(proto::BeginRequest { read_only, as_of })),
+                    "commit" => Some(Request::Commit(proto::CommitRequest {})),
+                    "rollback" => Some(Request::Rollback(proto::RollbackRequest {})),
+                    "close" => None,
+                    kind => return Err(format!("invalid session request {kind}").into()),
+                };
+                args.reject_rest()?;
+                let credentials = user.as_deref().map(|u| (u, password.as_deref().unwrap_or("")));
+                let client = self.get_grpc_client(&command.prefix)?;
+                let Some(request) = request else {
+                    client.close_session()?;
+                    return Ok(output);
+                };
+                for line in client.session(request, credentials)? {
+                    writeln!(output, "{line}")?;
+                }
+                return Ok(output);
+            }
+
+            // handshake VERSION [capabilities=BITS]
+            //
+            // Sends a raw protocol handshake on a new connection, and outputs
+            // the negotiated version and capabilities. Defaults to all of the
+            // client's capabilities.
+            "handshake" => {
+                let mut args = command.consume_args();
+                let version = args.next_pos().ok_or("version not given")?.parse()?;
+                let capabilities = args
+                    .lookup_parse("capabilities")?
+                    .map(Capabilities)
+                    .unwrap_or(Capabilities::ALL);
+                args.reject_rest()?;
+                let port = self.cluster.as_ref().ok_or("no cluster")?.sql_port();
+                let mut socket = std::net::TcpStream::connect(("localhost", port))?;
+                Request::Handshake { version, capabilities }.encode_into(&mut socket)?;
+                match toydb::error::Result::<Response>::decode_from(&mut socket)?? {
+                    Response::Handshake { version, capabilities } => {
+                        writeln!(output, "version={version} capabilities={capabilities}")?
+                    }
+                    response => return Err(format!("unexpected response {response:?}").into()),
+                }
+                return Ok(output);
+            }
+
+            // http METHOD PATH [BODY] [user=USER password=PASSWORD]
+            //
+            // Sends an HTTP request, with basic authentication if given.
+            "http" => {
+                let mut args = command.consume_args();
+                let method = args.next_pos().ok_or("method not given")?.value.clone();
+                let path = args.next_pos().ok_or("path not given")?.value.clone();
+                let body = args.next_pos().map(|arg| arg.value.clone());
+                let user = args.lookup("user").map(|arg| arg.value.clone());
+                let password = args.lookup("password").map(|arg| arg.value.clone());
+                args.reject_rest()?;
+                let credentials = user.as_deref().map(|u| (u, password.as_deref().unwrap_or("")));
+                let client = self.get_http_client(&command.prefix)?;
+                for line in client.request(&method, &path, body.as_deref(), credentials)? {
+                    writeln!(output, "{line}")?;
+                }
+                return Ok(output);
+            }
+
+            // idempotent TOKEN STATEMENT
+            //
+            // Executes a write statement at most once for the idempotency
+            // token, and outputs its result.
+            "idempotent" => {
+                let mut args = command.consume_args();
+                let token = &args.next_pos().ok_or("token not given")?.value;
+                let statement = &args.next_pos().ok_or("statement not given")?.value;
+                args.reject_rest()?;
+                let result =
+                    self.get_client(&command.prefix)?.execute_idempotent(statement, token)?;
+                writeln!(output, "{result:?}")?;
+                return Ok(output);
+            }
+
+            // init nodes=N [tls=BOOL]
+            //
+            // Initializes a cluster via `toydb init`, optionally with TLS,
+            // and runs it with the generated config files. Outputs the
+            // command's output.
+            "init" => {
+                let mut args = command.consume_args();
+                let nodes = args.lookup_parse("nodes")?.unwrap_or(0);
+                let tls = args.lookup_parse("tls")?.unwrap_or(false);
+                args.reject_rest()?;
+                if self.cluster.is_some() {
+                    return Err("cluster already exists".into());
+                }
+                let (cluster, init) = TestCluster::init(nodes, tls)?;
+                self.cluster = Some(cluster);
+                write!(output, "{init}")?;
+                return Ok(output);
+            }
+
+            // kill_leader
+            //
+            // Kills the current leader node.
+            "kill_leader" => {
+                command.consume_args().reject_rest()?;
+                let cluster = self.cluster.as_mut().ok_or("no cluster")?;
+                let leader = cluster.connect()?.status()?.raft.leader;
+                cluster.kill(leader)?;
+                return Ok(output);
+            }
+
+            // linearizability [workers=N] [ops=N] [registers=N]
+            //
+            // Runs a register workload with concurrent workers connected to
+            // the leader via nodes without injected faults, recording their
+            // operations, and checks that the
+            // history is linearizable. Each worker performs the given number
+            // of random reads, writes, and compare-and-sets on empty
+            // registers.
+            "linearizability" => {
+                let mut args = command.consume_args();
+                let workers: usize = args.lookup_parse("workers")?.unwrap_or(4);
+                let ops: usize = args.lookup_parse("ops")?.unwrap_or(50);
+                let registers: Key = args.lookup_parse("registers")?.unwrap_or(5);
+                args.reject_rest()?;
+                let cluster = self.cluster.as_ref().ok_or("no cluster")?;
+                let nodes = cluster.nodes().into_iter().filter(|id| !self.faulty.contains(id));
+                let nodes = nodes.collect_vec();
+                Recorder::setup(&mut cluster.connect_cluster(&nodes)?, registers)?;
+                let history = History::new();
+                std::thread::scope(|s| -> Result<(), Box<dyn Error>> {
+                    let mut handles = Vec::new();
+                    for process in 0..workers {
+                        let client = cluster.connect_cluster(&nodes)?;
+                        let mut recorder = Recorder::new(client, history.clone(), process);
+                        handles.push(s.spawn(move || {
+                            let mut rng = rand::thread_rng();
+                            let mut last = HashMap::new();
+                            for i in 0..ops {
+                                let key = rng.gen_range(0..registers);
+                                let value = (process * ops + i) as i64;
+                                let op = match rng.gen_range(0..10) {
+                                    0..5 => Op::Read(key),
+                                    5..8 => Op::Write(key, value),
+                                    _ => Op::Cas(key, last.get(&key).copied().flatten(), value),
+                                };
+                                if let Outcome::Read(read) = recorder.perform(op) {
+                                    last.insert(key, read);
+                                }
+                            }
+                        }));
+                    }
+                    for handle in handles {
+                        handle.join().map_err(|_| "worker panicked")?;
+                    }
+                    Ok(())
+                })?;
+                let check = history.check();
+                if !check.is_linearizable() {
+                    return Err(check.to_string().into());
+                }
+                writeln!(output, "{} operations: linearizable", check.operations)?;
+                return Ok(output);
+            }
+
+            // metrics NAME... [user=USER password=PASSWORD]
+            //
+            // Fetches the server metrics via HTTP GET /metrics, and outputs
+            // the samples of the given series, e.g. toydb_txn_commits_total
+            // or toydb_statement_duration_seconds_count.
+            "metrics" => {
+                let mut args = command.consume_args();
+                let user = args.lookup("user").map(|arg| arg.value.clone());
+                let password = args.lookup("password").map(|arg| arg.value.clone());
+                let names: Vec<String> =
+                    args.rest_pos().iter().map(|arg| arg.value.clone()).collect();
+                args.reject_rest()?;
+                let credentials = user.as_deref().map(|u| (u, password.as_deref().unwrap_or("")));
+                let client = self.get_http_client(&command.prefix)?;
+                let mut lines = client.request("GET", "/metrics", None, credentials)?.into_iter();
+                let status = lines.next().ok_or("no response status")?;
+                if status != "200 OK" {
+                    return Err(format!("unexpected status {status}").into());
+                }
+                for line in lines {
+                    let series = line.split(['{', ' ']).next().unwrap_or_default();
+                    if names.iter().any(|name| name == series) {
+                        writeln!(output, "{line}")?;
+                    }
+                }
+                return Ok(output);
+            }
+
+            // migrate [VERSION_NAME=SCRIPT...]
+            //
+            // Writes the given migration scripts to VERSION_NAME.sql files in
+            // a temporary directory, reads them back as migrations, and
+            // applies them. Outputs the applied versions.
+            "migrate" => {
+                let mut args = command.consume_args();
+                let dir = tempfile::TempDir::with_prefix("migrations")?;
+                for arg in args.rest_key() {
+                    let file = format!("{}.sql", arg.key.as_deref().unwrap_or_default());
+                    std::fs::write(dir.path().join(file), &arg.value)?;
+                }
+                args.reject_rest()?;
+                let migrations = Migration::read_dir(dir.path())?;
+                let applied = self.get_client(&command.prefix)?.migrate(&migrations)?;
+                writeln!(output, "applied {applied:?}")?;
+                return Ok(output);
+            }
+
+            // pg_execute QUERY [PARAM...] [max_rows=N] [binary=BOOL]
+            //
+            // Runs an extended query via the Postgres protocol. NULL params
+            // are given as NULL.
+            "pg_execute" => {
+                let mut args = command.consume_args();
+                let max_rows = args.lookup_parse("max_rows")?.unwrap_or(0);
+                let binary = args.lookup_parse("binary")?.unwrap_or(false);
+                let query = args.next_pos().ok_or("query not given")?.value.clone();
+                let params = args
+                    .rest_pos()
+                    .iter()
+                    .map(|arg| Some(arg.value.clone()).filter(|v| v != "NULL"))
+                    .collect_vec();
+                args.reject_rest()?;
+                let client = self.get_pg_client(&command.prefix)?;
+                for line in client.execute(&query, &params, max_rows, binary)? {
+                    writeln!(output, "{line}")?;
+                }
+                return Ok(output);
+            }
+
+            // pool [min=N] [max=N] [timeout=MILLIS]
+            //
+            // Creates a connection pool for a random node. Health checks and
+            // idle timeouts are disabled.
+            "pool" => {
+                let mut args = command.consume_args();
+                let mut config = PoolConfig {
+                    health_check_idle: None,
+                    idle_timeout: None,
+                    ..PoolConfig::default()
+                };
+                config.min_connections = args.lookup_parse("min")?.unwrap_or(0);
+                config.max_connections = args.lookup_parse("max")?.unwrap_or(10);
+                if let Some(timeout) = args.lookup_parse("timeout")? {
+                    config.checkout_timeout = Duration::from_millis(timeout);
+                }
+                args.reject_rest()?;
+                let cluster = self.cluster.as_ref().ok_or("no cluster")?;
+                self.pool = Some(cluster.pool(config)?);
+                return Ok(output);
+            }
+
+            // pool_status
+            "pool_status" => {
+                command.consume_args().reject_rest()?;
+                let status = self.pool.as_ref().ok_or("no pool")?.status()?;
+                writeln!(output, "{status:?}")?;
+                return Ok(output);
+            }
+
+            // protocol
+            //
+            // Outputs the client's negotiated protocol version and capabilities.
+            "protocol" => {
+                command.consume_args().reject_rest()?;
+                let client = self.get_client(&command.prefix)?;
+                let (version, capabilities) = (client.protocol_version(), client.capabilities());
+                writeln!(output, "version={version} capabilities={capabilities}")?;
+                return Ok(output);
+            }
+
+            // query QUERY rows=N
+            //
+            // Runs a query, streaming only the first N rows and dropping the
+            // rest.
+            "query" => {
+                let mut args = command.consume_args();
+                let query = args.next_pos().ok_or("query not given")?.value.clone();
+                let limit = args.lookup_parse("rows")?.ok_or("rows not given")?;
+                args.reject_rest()?;
+                for row in self.get_client(&command.prefix)?.query(&query)?.take(limit) {
+                    writeln!(output, "{}", row?.into_iter().join(", "))?;
+                }
+                return Ok(output);
+            }
+
+            // reload KEY=VALUE...
+            //
+            // Changes the given node config settings, and reloads the config
+            // on all nodes via SIGHUP.
+            "reload" => {
+                let mut args = command.consume_args();
+                let changes = args
+                    .rest_key()
+                    .into_iter()
+                    .map(|arg| (arg.key.clone().unwrap(), arg.value.clone()))
+                    .collect();
+                args.reject_rest()?;
+                self.cluster.as_mut().ok_or("no cluster")?.reload(changes)?;
+                return Ok(output);
+            }
+
+            // restore FILE [INCREMENTAL...] [as_of=VERSION] [log=BOOL]
+            //
+            // Restores the backup file, replaying the incremental backup
+            // files and node 1's Raft log (unless log=false) on top of it.
+            "restore" => {
+                let mut args = command.consume_args();
+                let file = args.next_pos().ok_or("file not given")?.value.clone();
+                let incremental =
+                    args.rest_pos().iter().map(|arg| arg.value.clone()).collect::<Vec<_>>();
+                let as_of = args.lookup_parse("as_of")?;
+                let log = args.lookup_parse("log")?.unwrap_or(true);
+                args.reject_rest()?;
+                self.clear_clients(); // the nodes are restarted
+                let cluster = self.cluster.as_mut().ok_or("no cluster")?;
+                let path = cluster.dir().join(file);
+                let incremental = incremental.iter().map(|f| cluster.dir().join(f)).collect_vec();
+                cluster.restore(&path, &incremental, as_of, log)?;
+                return Ok(output);
+            }
+
+            // scrub [repair=BOOL]
+            "scrub" => {
+                let mut args = command.consume_args();
+                let repair = args.lookup_parse("repair")?.unwrap_or(false);
+                args.reject_rest()?;
+                let scrub = self.get_client(&command.prefix)?.scrub(repair)?;
+                write!(output, "{scrub}")?;
+                return Ok(output);
+            }
+
+            // start_cli ID
+            //
+            // Starts the given stopped node via the toydb binary, and outputs
+            // its startup error.
+            "start_cli" => {
+                let mut args = command.consume_args();
+                let id = args.next_pos().ok_or("node not given")?.parse()?;
+                args.reject_rest()?;
+                let error = self.cluster.as_ref().ok_or("no cluster")?.start_cli(id)?;
+                writeln!(output, "{error}")?;
                 return Ok(output);
             }
 
@@ -104,6 +1104,17 @@ impl goldenscript::Runner for Runner {
                 return Ok(output);
             }
 
+            // stop_leader
+            //
+            // Shuts down the leader gracefully, and waits for it to exit.
+            "stop_leader" => {
+                command.consume_args().reject_rest()?;
+                let cluster = self.cluster.as_mut().ok_or("no cluster")?;
+                let leader = cluster.connect()?.status()?.raft.leader;
+                cluster.stop(leader)?;
+                return Ok(output);
+            }
+
             // table [TABLE]
             "table" => {
                 let mut args = command.consume_args();
@@ -129,6 +1140,41 @@ impl goldenscript::Runner for Runner {
                 return Ok(output);
             }
 
+            // toydb [node=ID] [stdout=BOOL] ARG...
+            // toysql [stdout=BOOL] ARG...
+            //
+            // Runs the toydb binary with the given node's config (node 1 by
+            // default), or the toysql binary, with the given arguments. Outputs
+            // its stdout (unless stdout is false), stderr, and exit code. Other
+            // key arguments are passed as KEY=VALUE arguments.
+            "toydb" | "toysql" => {
+                let mut args = command.consume_args();
+                let id = args.lookup_parse("node")?.unwrap_or(1);
+                let stdout = args.lookup_parse("stdout")?.unwrap_or(true);
+                let cli_args = args
+                    .rest()
+                    .iter()
+                    .map(|arg| match &arg.key {
+                        Some(key) => format!("{key}={}", arg.value),
+                        None => arg.value.clone(),
+                    })
+                    .collect_vec();
+                let cluster = self.cluster.as_ref().ok_or("no cluster")?;
+                let cli_args = cli_args.iter().map(|arg| arg.as_str()).collect_vec();
+                let result = match command.name.as_str() {
+                    "toydb" => cluster.cli_output(id, &cli_args)?,
+                    _ => TestCluster::toysql_output(&cli_args)?,
+                };
+                let dir = cluster.dir().to_string_lossy().to_string();
+                let streams = [stdout.then_some(&result.stdout), Some(&result.stderr)];
+                for stream in streams.into_iter().flatten() {
+                    let text = String::from_utf8_lossy(stream).replace(&dir, "$DIR");
+                    write!(output, "{text}")?;
+                }
+                writeln!(output, "exit code {}", result.status.code().unwrap_or(-1))?;
+                return Ok(output);
+            }
+
             _ => {}
         }
 
@@ -136,12 +1182,49 @@ impl goldenscript::Runner for Runner {
         if !command.args.is_empty() {
             return Err("statements should be given as a command with no args".into());
         }
+        let input = &match self.timestamp {
+            Some(timestamp) => command.name.replace("$clock", &timestamp.to_string()),
+            None => command.name.clone(),
+        };
+
+        // If requested, run it as a simple query via the Postgres protocol.
+        if tags.remove("pg") {
+            if let Some(tag) = tags.iter().next() {
+                return Err(format!("invalid tag {tag}").into());
+            }
+            for line in self.get_pg_client(&command.prefix)?.query(input)? {
+                writeln!(output, "{line}")?;
+            }
+            return Ok(output);
+        }
+
+        // If requested, run it via an async client.
+        if tags.remove("async") {
+            let header = tags.remove("header");
+            if let Some(tag) = tags.iter().next() {
+                return Err(format!("invalid tag {tag}").into());
+            }
+            return self.execute_async(&command.prefix, input, header, usize::MAX);
+        }
+
         let client = self.get_client(&command.prefix)?;
-        let input = &command.name;
 
         // Execute the command and display the result if requested.
-        // SELECT and EXPLAIN results are always output.
-        let result = client.execute(input)?;
+        // SELECT and EXPLAIN results are always output. If requested, cancel
+        // the statement from a separate thread while it's executing.
+        let result = match tags.remove("cancel") {
+            true => {
+                let cancel = client.cancel_handle();
+                std::thread::scope(|s| {
+                    s.spawn(move || {
+                        std::thread::sleep(Duration::from_millis(100));
+                        cancel.cancel()
+                    });
+                    client.execute(input)
+                })?
+            }
+            false => client.execute(input)?,
+        };
 
         match result {
             StatementResult::Select { columns, rows } => {
@@ -152,6 +1235,16 @@ impl goldenscript::Runner for Runner {
                     writeln!(output, "{}", row.into_iter().join(", "))?;
                 }
             }
+            StatementResult::Delete { returning: Some(Returning { columns, rows }), .. }
+            | StatementResult::Insert { returning: Some(Returning { columns, rows }), .. }
+            | StatementResult::Update { returning: Some(Returning { columns, rows }), .. } => {
+                if tags.remove("header") {
+                    writeln!(output, "{}", columns.into_iter().join(", "))?;
+                }
+                for row in rows {
+                    writeln!(output, "{}", row.into_iter().join(", "))?;
+                }
+            }
             StatementResult::Explain(root) => writeln!(output, "{root}")?,
             result if tags.remove("result") => writeln!(output, "{result:?}")?,
             _ => {}